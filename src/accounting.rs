@@ -0,0 +1,264 @@
+//! End-of-run check that every planned byte was received exactly once.
+//!
+//! A planning bug that fetches a range twice or skips one still produces a
+//! plausible MB/s. After `download`, `columnar` and `scrub`, the bytes received
+//! from each object are compared with what the run should have read: the
+//! whole object for full scans, the union of the planned ranges otherwise.
+//! Results carry the comparison under `accounting`, listing each object that
+//! disagrees with the difference as `delta_bytes`. Vanished objects are left
+//! out, and an interrupted run isn't checked, as it never read everything.
+//!
+//! With `--strict-accounting` a disagreement fails the run, after its result
+//! is emitted.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Mutex;
+
+use object_store::{path::Path, ObjectMeta};
+
+use crate::coverage::insert_interval;
+use crate::experiment::{fields, Fields};
+
+/// Most disagreeing objects listed in the result.
+const MAX_LISTED_MISMATCHES: usize = 100;
+
+/// Bytes each object should yield, and the bytes it did.
+#[derive(Debug, Default)]
+pub struct Accounting {
+    expected: HashMap<Path, u64>,
+    received: Mutex<HashMap<Path, u64>>,
+}
+
+impl Accounting {
+    /// Expect every byte of each of `objects` once, for full scans.
+    pub fn whole<'a>(objects: impl IntoIterator<Item = &'a ObjectMeta>) -> Self {
+        Self {
+            expected: objects
+                .into_iter()
+                .map(|meta| (meta.location.clone(), meta.size as u64))
+                .collect(),
+            received: Mutex::default(),
+        }
+    }
+
+    /// Expect each byte the `planned` ranges cover once, however many
+    /// ranges cover it.
+    pub fn planned(planned: impl IntoIterator<Item = (Path, Range<usize>)>) -> Self {
+        let mut intervals: HashMap<Path, BTreeMap<usize, usize>> = HashMap::new();
+        for (location, range) in planned {
+            if !range.is_empty() {
+                insert_interval(intervals.entry(location).or_default(), range);
+            }
+        }
+        Self {
+            expected: intervals
+                .into_iter()
+                .map(|(location, intervals)| {
+                    let unique = intervals.iter().map(|(start, end)| (end - start) as u64);
+                    (location, unique.sum())
+                })
+                .collect(),
+            received: Mutex::default(),
+        }
+    }
+
+    /// Count `bytes` received from `location`.
+    pub fn received(&self, location: &Path, bytes: usize) {
+        *self
+            .received
+            .lock()
+            .unwrap()
+            .entry(location.clone())
+            .or_default() += bytes as u64;
+    }
+
+    /// Compare what each object yielded with what was expected, leaving out
+    /// objects `skipped` says were never meant to be read in full.
+    pub fn check(&self, interrupted: bool, skipped: impl Fn(&Path) -> bool) -> AccountingReport {
+        let received = self.received.lock().unwrap();
+        let mut report = AccountingReport {
+            interrupted,
+            ..Default::default()
+        };
+        if interrupted {
+            return report;
+        }
+        let mut paths = self
+            .expected
+            .keys()
+            .chain(received.keys())
+            .collect::<Vec<_>>();
+        paths.sort();
+        paths.dedup();
+        for path in paths {
+            if skipped(path) {
+                report.skipped_objects += 1;
+                continue;
+            }
+            let expected = self.expected.get(path).copied().unwrap_or(0);
+            let got = received.get(path).copied().unwrap_or(0);
+            report.objects_checked += 1;
+            report.expected_bytes += expected;
+            report.received_bytes += got;
+            if expected != got {
+                report.mismatches.push(Mismatch {
+                    path: path.clone(),
+                    expected_bytes: expected,
+                    received_bytes: got,
+                });
+            }
+        }
+        report
+    }
+}
+
+/// One object whose received bytes disagree with its plan.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub path: Path,
+    pub expected_bytes: u64,
+    pub received_bytes: u64,
+}
+
+impl Mismatch {
+    /// Bytes received beyond the plan, or negative for bytes missed.
+    pub fn delta_bytes(&self) -> i128 {
+        self.received_bytes as i128 - self.expected_bytes as i128
+    }
+}
+
+/// The outcome of [`Accounting::check`].
+#[derive(Debug, Default)]
+pub struct AccountingReport {
+    pub interrupted: bool,
+    pub objects_checked: usize,
+    pub skipped_objects: usize,
+    pub expected_bytes: u64,
+    pub received_bytes: u64,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl AccountingReport {
+    /// The report, as the `accounting` field.
+    pub fn json_field(&self) -> Fields {
+        let report = if self.interrupted {
+            serde_json::json!({"checked": false})
+        } else {
+            serde_json::json!({
+                "checked": true,
+                "objects_checked": self.objects_checked,
+                "skipped_objects": self.skipped_objects,
+                "expected_bytes": self.expected_bytes,
+                "received_bytes": self.received_bytes,
+                "mismatched_objects": self.mismatches.len(),
+                "mismatches": self
+                    .mismatches
+                    .iter()
+                    .take(MAX_LISTED_MISMATCHES)
+                    .map(|mismatch| serde_json::json!({
+                        "path": mismatch.path.to_string(),
+                        "expected_bytes": mismatch.expected_bytes,
+                        "received_bytes": mismatch.received_bytes,
+                        "delta_bytes": mismatch.delta_bytes() as i64,
+                    }))
+                    .collect::<Vec<_>>(),
+            })
+        };
+        fields(serde_json::json!({ "accounting": report }))
+    }
+
+    /// An error if any object disagreed and `strict`, as with
+    /// `--strict-accounting`.
+    pub fn enforce(&self, strict: bool) -> Result<(), crate::Error> {
+        if !strict || self.mismatches.is_empty() {
+            return Ok(());
+        }
+        let first = &self.mismatches[0];
+        Err(crate::Error::Accounting(format!(
+            "received bytes disagree with the plan for {} objects, first {}: expected {}, received {}",
+            self.mismatches.len(),
+            first.path,
+            first.expected_bytes,
+            first.received_bytes
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::BlockPlan;
+
+    fn meta(name: &str, size: usize) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(name),
+            last_modified: chrono::Utc::now(),
+            size,
+            e_tag: None,
+        }
+    }
+
+    #[test]
+    fn scans_catch_skipped_and_refetched_blocks() {
+        let objects = [meta("data/a", 1000), meta("data/b", 1000)];
+        let accounting = Accounting::whole(&objects);
+        let blocks = BlockPlan::new(1000, 300).blocks().collect::<Vec<_>>();
+        // A plan that drops a's last block and fetches b's first twice.
+        for block in &blocks[..blocks.len() - 1] {
+            accounting.received(&objects[0].location, (block.end - block.start) as usize);
+        }
+        for block in blocks.iter().chain(&blocks[..1]) {
+            accounting.received(&objects[1].location, (block.end - block.start) as usize);
+        }
+
+        let report = accounting.check(false, |_| false);
+        assert_eq!(report.objects_checked, 2);
+        let deltas = report
+            .mismatches
+            .iter()
+            .map(|m| (m.path.to_string(), m.delta_bytes()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            deltas,
+            [("data/a".to_string(), -100), ("data/b".to_string(), 300)]
+        );
+        let json = report.json_field();
+        assert_eq!(json["accounting"]["mismatched_objects"], 2);
+        assert_eq!(json["accounting"]["mismatches"][0]["delta_bytes"], -100);
+    }
+
+    #[test]
+    fn overlapping_plans_expect_unique_bytes() {
+        let a = Path::from("data/a");
+        // Pages that overlap by 50 bytes, as from a miscomputed offset.
+        let plan = [
+            (a.clone(), 0..100),
+            (a.clone(), 50..150),
+            (a.clone(), 200..300),
+        ];
+        let accounting = Accounting::planned(plan.clone());
+        for (location, range) in &plan {
+            accounting.received(location, range.len());
+        }
+        let report = accounting.check(false, |_| false);
+        assert_eq!(report.expected_bytes, 250);
+        assert_eq!(report.mismatches[0].delta_bytes(), 50);
+
+        assert!(report.enforce(false).is_ok());
+        assert!(report.enforce(true).is_err());
+        // Nothing to check in a run that was stopped partway.
+        assert!(accounting.check(true, |_| false).enforce(true).is_ok());
+    }
+
+    #[test]
+    fn skipped_objects_are_not_checked() {
+        let objects = [meta("data/a", 10), meta("data/b", 10)];
+        let accounting = Accounting::whole(&objects);
+        accounting.received(&objects[0].location, 10);
+        let report = accounting.check(false, |path| path.as_ref() == "data/b");
+        assert!(report.mismatches.is_empty());
+        assert_eq!(report.skipped_objects, 1);
+        assert!(report.enforce(true).is_ok());
+    }
+}
@@ -0,0 +1,216 @@
+//! Page-size and coalescing recommendations for the columnar benchmark.
+//!
+//! We model each range request as a fixed per-request latency `L` followed by
+//! a transfer at a single-stream bandwidth `B`:
+//!
+//! ```text
+//! time(size) = L + size / B
+//! ```
+//!
+//! The fraction of a request's time spent actually moving bytes is then
+//! `size / (size + L * B)`. `L * B` is the bandwidth-delay product: the number
+//! of bytes that could have been transferred in the time spent waiting.
+//!
+//! * The recommended page size is the smallest size reaching
+//!   [`TARGET_EFFICIENCY`], i.e. `L * B * e / (1 - e)`. Larger pages are always
+//!   at least as efficient in this model, but cost memory and read amplification.
+//! * Two reads separated by a gap of `g` bytes are worth coalescing when
+//!   transferring the gap (`g / B`) is cheaper than a second request (`L`), so
+//!   the recommended coalescing gap is `L * B`.
+//!
+//! `L` and `B` are estimated by a short calibration phase run after the timed
+//! section: a handful of tiny reads give `L`, and a few large reads give `B`
+//! once `L` is subtracted from their duration.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectStore};
+
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+
+/// Per-request efficiency the recommended page size should reach.
+pub const TARGET_EFFICIENCY: f64 = 0.9;
+
+/// Size of each read used to estimate fixed per-request latency.
+const LATENCY_PROBE_SIZE: usize = 1024;
+const LATENCY_PROBES: usize = 16;
+/// Size of each read used to estimate streaming bandwidth.
+const BANDWIDTH_PROBE_SIZE: usize = 16 * 1024 * 1024;
+const BANDWIDTH_PROBES: usize = 4;
+
+/// Fixed latency and streaming bandwidth of a store, as seen by one request.
+#[derive(Debug, Clone, Copy)]
+pub struct StoreModel {
+    pub latency: Duration,
+    /// Bytes per second
+    pub bandwidth: f64,
+}
+
+impl StoreModel {
+    /// Bytes that could be transferred during one request's fixed latency.
+    pub fn bandwidth_delay_bytes(&self) -> f64 {
+        self.latency.as_secs_f64() * self.bandwidth
+    }
+
+    /// Fraction of a request of `size` bytes spent transferring data.
+    pub fn efficiency(&self, size: f64) -> f64 {
+        if size <= 0.0 {
+            return 0.0;
+        }
+        size / (size + self.bandwidth_delay_bytes())
+    }
+
+    /// Smallest page size that achieves `target` efficiency.
+    pub fn page_size_for_efficiency(&self, target: f64) -> f64 {
+        self.bandwidth_delay_bytes() * target / (1.0 - target)
+    }
+
+    /// Largest gap between two reads that is still cheaper to read through
+    /// than to skip with a separate request.
+    pub fn coalesce_gap(&self) -> f64 {
+        self.bandwidth_delay_bytes()
+    }
+}
+
+/// Recommendation for a measured columnar configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct Analysis {
+    pub model: StoreModel,
+    pub optimal_page_size: usize,
+    pub optimal_coalesce_gap: usize,
+    pub measured_mean_page_size: f64,
+    pub measured_efficiency: f64,
+    /// Measured efficiency divided by the efficiency at the optimal page size
+    pub relative_efficiency: f64,
+}
+
+impl Analysis {
+    pub fn new(model: StoreModel, page_sizes: &[usize]) -> Self {
+        let measured_mean_page_size =
+            page_sizes.iter().sum::<usize>() as f64 / page_sizes.len() as f64;
+        let measured_efficiency = model.efficiency(measured_mean_page_size);
+        Self {
+            model,
+            optimal_page_size: model.page_size_for_efficiency(TARGET_EFFICIENCY).round() as usize,
+            optimal_coalesce_gap: model.coalesce_gap().round() as usize,
+            measured_mean_page_size,
+            measured_efficiency,
+            relative_efficiency: measured_efficiency / TARGET_EFFICIENCY,
+        }
+    }
+
+    /// JSON object describing the analysis.
+    pub fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "latency_us": self.model.latency.as_micros() as u64,
+            "bandwidth_mbps": self.model.bandwidth / 1024.0 / 1024.0,
+            "target_efficiency": TARGET_EFFICIENCY,
+            "optimal_page_size": self.optimal_page_size,
+            "optimal_coalesce_gap": self.optimal_coalesce_gap,
+            "measured_mean_page_size": self.measured_mean_page_size,
+            "measured_efficiency": self.measured_efficiency,
+            "relative_efficiency": self.relative_efficiency,
+        })
+    }
+}
+
+/// Estimate a [`StoreModel`] by issuing sequential probe reads against `location`.
+pub async fn calibrate(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    object_size: usize,
+    retry: &RetryPolicy,
+) -> Result<StoreModel, Error> {
+    let mut latencies = Vec::with_capacity(LATENCY_PROBES);
+    let probe_size = std::cmp::min(LATENCY_PROBE_SIZE, object_size);
+    let stride = (object_size - probe_size) / LATENCY_PROBES;
+    for i in 0..LATENCY_PROBES {
+        let offset = i * stride;
+        let start = Instant::now();
+        retry
+            .run(|| object_store.get_range(location, offset..(offset + probe_size)))
+            .await?;
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    let latency = latencies[latencies.len() / 2];
+
+    let probe_size = std::cmp::min(BANDWIDTH_PROBE_SIZE, object_size);
+    let mut transfer = Duration::ZERO;
+    for _ in 0..BANDWIDTH_PROBES {
+        let start = Instant::now();
+        retry
+            .run(|| object_store.get_range(location, 0..probe_size))
+            .await?;
+        transfer += start.elapsed().saturating_sub(latency);
+    }
+    let bandwidth = model_bandwidth(probe_size * BANDWIDTH_PROBES, transfer);
+
+    Ok(StoreModel { latency, bandwidth })
+}
+
+/// Bytes per second, guarding against a zero transfer time on very fast stores.
+fn model_bandwidth(bytes: usize, transfer: Duration) -> f64 {
+    let secs = transfer.as_secs_f64().max(1e-9);
+    bytes as f64 / secs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> StoreModel {
+        // 20ms latency at 100 MiB/s: a bandwidth-delay product of 2 MiB.
+        StoreModel {
+            latency: Duration::from_millis(20),
+            bandwidth: 100.0 * 1024.0 * 1024.0,
+        }
+    }
+
+    #[test]
+    fn bandwidth_delay_product() {
+        let bdp = model().bandwidth_delay_bytes();
+        assert!((bdp - 2.0 * 1024.0 * 1024.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn efficiency_at_bdp_is_half() {
+        let model = model();
+        let efficiency = model.efficiency(model.bandwidth_delay_bytes());
+        assert!((efficiency - 0.5).abs() < 1e-9);
+        assert_eq!(model.efficiency(0.0), 0.0);
+    }
+
+    #[test]
+    fn optimal_page_size_reaches_target() {
+        let model = model();
+        let size = model.page_size_for_efficiency(TARGET_EFFICIENCY);
+        // e / (1 - e) = 9 for a 90% target
+        assert!((size - 18.0 * 1024.0 * 1024.0).abs() < 1.0);
+        assert!((model.efficiency(size) - TARGET_EFFICIENCY).abs() < 1e-9);
+    }
+
+    #[test]
+    fn analysis_of_small_pages() {
+        let analysis = Analysis::new(model(), &[64 * 1024, 64 * 1024, 64 * 1024]);
+        assert_eq!(analysis.optimal_coalesce_gap, 2 * 1024 * 1024);
+        assert_eq!(analysis.optimal_page_size, 18 * 1024 * 1024);
+        // 64 KiB pages against a 2 MiB bandwidth-delay product: 64 / (64 + 2048)
+        let expected = 64.0 / (64.0 + 2048.0);
+        assert!((analysis.measured_efficiency - expected).abs() < 1e-9);
+        assert!((analysis.relative_efficiency - expected / 0.9).abs() < 1e-9);
+    }
+
+    #[test]
+    fn zero_latency_store() {
+        let model = StoreModel {
+            latency: Duration::ZERO,
+            bandwidth: 1e9,
+        };
+        assert_eq!(model.coalesce_gap(), 0.0);
+        assert_eq!(model.page_size_for_efficiency(TARGET_EFFICIENCY), 0.0);
+        assert_eq!(model.efficiency(1.0), 1.0);
+    }
+}
@@ -0,0 +1,373 @@
+//! Backing off when the store throttles, at the level of the whole run.
+//!
+//! Retrying a throttled request only adds to the load that caused it. With
+//! `--adaptive-backoff`, every request attempt is reported to an
+//! [`AdaptiveBackoff`], which counts throttling responses (`SlowDown`, 503,
+//! 429) over consecutive windows. After a window whose throttle rate is above
+//! the threshold, the number of requests allowed in flight is cut
+//! multiplicatively; after a clean one it grows by one, until it no longer
+//! binds: AIMD, as TCP does for congestion. Each attempt, retries included,
+//! waits for a free slot in [`crate::retry::RetryPolicy::run`], so requests
+//! already in flight back off too rather than retrying at full concurrency.
+//!
+//! Results carry the limit after every window and the level it settled at,
+//! the mean over the last few windows, which estimates the concurrency the
+//! store sustains. Open-loop workloads are limited by the same concurrency
+//! cap rather than by lowering their issue rate.
+
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio::sync::Notify;
+
+/// Most windows kept in the reported trajectory.
+const MAX_TRAJECTORY: usize = 1000;
+
+/// Windows the settled level is averaged over.
+const SETTLED_WINDOWS: usize = 10;
+
+/// How to react to throttling, written `threshold=0.05,window-ms=1000,decrease=0.5`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AimdConfig {
+    /// Fraction of attempts in a window that may be throttled without backing off
+    pub threshold: f64,
+    pub window: Duration,
+    /// Factor the limit is multiplied by after a throttled window
+    pub decrease: f64,
+    /// The limit never drops below this
+    pub min: usize,
+}
+
+impl Default for AimdConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.05,
+            window: Duration::from_secs(1),
+            decrease: 0.5,
+            min: 1,
+        }
+    }
+}
+
+impl FromStr for AimdConfig {
+    type Err = String;
+
+    /// Parse `key=value` pairs separated by commas; missing keys keep their
+    /// defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut config = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", pair))?;
+            let invalid = |err: &dyn std::fmt::Display| format!("{}: {}", key, err);
+            match key.trim() {
+                "threshold" => config.threshold = value.parse().map_err(|err| invalid(&err))?,
+                "window-ms" => {
+                    config.window =
+                        Duration::from_millis(value.parse().map_err(|err| invalid(&err))?)
+                }
+                "decrease" => config.decrease = value.parse().map_err(|err| invalid(&err))?,
+                "min" => config.min = value.parse().map_err(|err| invalid(&err))?,
+                _ => {
+                    return Err(format!(
+                        "unknown key {:?}; expected threshold, window-ms, decrease or min",
+                        key
+                    ))
+                }
+            }
+        }
+        if !(0.0..1.0).contains(&config.threshold) {
+            return Err(format!(
+                "threshold must be at least 0 and below 1, got {}",
+                config.threshold
+            ));
+        }
+        if !(config.decrease > 0.0 && config.decrease < 1.0) {
+            return Err(format!(
+                "decrease must be between 0 and 1, got {}",
+                config.decrease
+            ));
+        }
+        if config.window.is_zero() || config.min == 0 {
+            return Err("window-ms and min must be positive".to_string());
+        }
+        Ok(config)
+    }
+}
+
+/// The limit in force after one window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Step {
+    /// End of the window, since the start of the run
+    at: Duration,
+    attempts: u64,
+    throttled: u64,
+    limit: usize,
+}
+
+#[derive(Debug)]
+struct State {
+    window_start: Duration,
+    attempts: u64,
+    throttled: u64,
+    /// Requests allowed in flight; `usize::MAX` until the first back-off
+    limit: usize,
+    trajectory: Vec<Step>,
+    windows: usize,
+    recent: VecDeque<usize>,
+    backoffs: usize,
+}
+
+/// A request's place under the limit, held until it finishes.
+pub struct Slot<'a>(&'a AdaptiveBackoff);
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.freed.notify_waiters();
+    }
+}
+
+/// Run-wide concurrency limit steered by the throttle rate.
+#[derive(Debug)]
+pub struct AdaptiveBackoff {
+    config: AimdConfig,
+    start: Instant,
+    limit: AtomicUsize,
+    in_flight: AtomicUsize,
+    /// Most requests seen in flight at once, which bounds the limit worth growing to
+    peak: AtomicUsize,
+    freed: Notify,
+    state: Mutex<State>,
+}
+
+impl AdaptiveBackoff {
+    pub fn new(config: AimdConfig) -> Self {
+        Self {
+            config,
+            start: Instant::now(),
+            limit: AtomicUsize::new(usize::MAX),
+            in_flight: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            freed: Notify::new(),
+            state: Mutex::new(State {
+                window_start: Duration::ZERO,
+                attempts: 0,
+                throttled: 0,
+                limit: usize::MAX,
+                trajectory: Vec::new(),
+                windows: 0,
+                recent: VecDeque::new(),
+                backoffs: 0,
+            }),
+        }
+    }
+
+    /// Wait until a request may be issued under the current limit, and take
+    /// a slot, given back when the returned guard is dropped.
+    pub async fn acquire(&self) -> Slot<'_> {
+        loop {
+            let freed = self.freed.notified();
+            let taken = self
+                .in_flight
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                    (n < self.limit.load(Ordering::SeqCst)).then_some(n + 1)
+                });
+            if let Ok(previous) = taken {
+                self.peak.fetch_max(previous + 1, Ordering::SeqCst);
+                return Slot(self);
+            }
+            freed.await;
+        }
+    }
+
+    /// Count one request attempt, and whether the store throttled it.
+    pub fn observe(&self, throttled: bool) {
+        self.observe_at(self.start.elapsed(), throttled);
+    }
+
+    fn observe_at(&self, now: Duration, throttled: bool) {
+        let mut state = self.state.lock().unwrap();
+        let window_end = state.window_start + self.config.window;
+        if now >= window_end {
+            self.end_window(&mut state, window_end);
+            let windows_passed =
+                (now - state.window_start).as_micros() / self.config.window.as_micros();
+            state.window_start += self.config.window * windows_passed as u32;
+        }
+        state.attempts += 1;
+        state.throttled += throttled as u64;
+    }
+
+    /// Adjust the limit for the window ending at `at`. A window without
+    /// attempts says nothing about the store, so leaves the limit alone.
+    fn end_window(&self, state: &mut State, at: Duration) {
+        if state.attempts == 0 {
+            return;
+        }
+        let peak = self.peak.load(Ordering::SeqCst).max(1);
+        let rate = state.throttled as f64 / state.attempts as f64;
+        let previous = state.limit;
+        if rate > self.config.threshold {
+            let base = previous.min(peak) as f64;
+            state.limit = ((base * self.config.decrease) as usize).max(self.config.min);
+            state.backoffs += 1;
+        } else if previous < peak {
+            state.limit = previous + 1;
+        }
+        self.limit.store(state.limit, Ordering::SeqCst);
+        if state.limit > previous {
+            self.freed.notify_waiters();
+        }
+
+        let limit = state.limit.min(peak);
+        if state.trajectory.len() < MAX_TRAJECTORY {
+            state.trajectory.push(Step {
+                at,
+                attempts: state.attempts,
+                throttled: state.throttled,
+                limit,
+            });
+        }
+        state.windows += 1;
+        state.recent.push_back(limit);
+        if state.recent.len() > SETTLED_WINDOWS {
+            state.recent.pop_front();
+        }
+        state.attempts = 0;
+        state.throttled = 0;
+    }
+
+    /// Mean limit over the last windows, or `None` before the first ends.
+    fn settled(state: &State) -> Option<f64> {
+        (!state.recent.is_empty())
+            .then(|| state.recent.iter().sum::<usize>() as f64 / state.recent.len() as f64)
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap();
+        let peak = self.peak.load(Ordering::SeqCst);
+        let trajectory = state
+            .trajectory
+            .iter()
+            .map(|step| {
+                serde_json::json!({
+                    "at_s": step.at.as_secs_f64(),
+                    "attempts": step.attempts,
+                    "throttled": step.throttled,
+                    "limit": step.limit,
+                })
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "threshold": self.config.threshold,
+            "window_secs": self.config.window.as_secs_f64(),
+            "decrease": self.config.decrease,
+            "peak_in_flight": peak,
+            "backoffs": state.backoffs,
+            "windows": state.windows,
+            "final_limit": (state.limit != usize::MAX).then(|| state.limit.min(peak)),
+            "settled_limit": Self::settled(&state),
+            "trajectory": trajectory,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn backoff_with_peak(peak: usize) -> AdaptiveBackoff {
+        let backoff = AdaptiveBackoff::new(AimdConfig::default());
+        backoff.peak.store(peak, Ordering::SeqCst);
+        backoff
+    }
+
+    /// Feed one window of `attempts`, `throttled` of them throttled, and
+    /// return the limit once it has ended.
+    fn window(backoff: &AdaptiveBackoff, i: u64, attempts: u64, throttled: u64) -> usize {
+        let start = Duration::from_secs(i);
+        for n in 0..attempts {
+            backoff.observe_at(start + Duration::from_millis(n % 1000), n < throttled);
+        }
+        backoff.observe_at(start + Duration::from_secs(1), false);
+        let limit = backoff.state.lock().unwrap().limit;
+        // Take back the probe, which belongs to the next window.
+        backoff.state.lock().unwrap().attempts -= 1;
+        limit
+    }
+
+    #[test]
+    fn cuts_multiplicatively_and_recovers_additively() {
+        let backoff = backoff_with_peak(64);
+        assert_eq!(window(&backoff, 0, 100, 0), usize::MAX);
+        assert_eq!(window(&backoff, 1, 100, 20), 32);
+        assert_eq!(window(&backoff, 2, 100, 20), 16);
+        // Throttling at or below the threshold counts as clean.
+        assert_eq!(window(&backoff, 3, 100, 5), 17);
+        assert_eq!(window(&backoff, 4, 100, 0), 18);
+        for i in 5..20 {
+            window(&backoff, i, 100, 100);
+        }
+        assert_eq!(backoff.state.lock().unwrap().limit, 1);
+
+        let json = backoff.to_json();
+        assert_eq!(json["final_limit"], 1);
+        assert_eq!(json["settled_limit"], 1.0);
+        assert_eq!(json["trajectory"][1]["limit"], 32);
+        assert_eq!(json["trajectory"][1]["throttled"], 20);
+    }
+
+    #[test]
+    fn stops_growing_once_the_limit_no_longer_binds() {
+        let backoff = backoff_with_peak(4);
+        assert_eq!(window(&backoff, 0, 10, 10), 2);
+        assert_eq!(window(&backoff, 1, 10, 0), 3);
+        assert_eq!(window(&backoff, 2, 10, 0), 4);
+        assert_eq!(window(&backoff, 3, 10, 0), 4);
+        // Idle windows leave the limit alone.
+        backoff.observe_at(Duration::from_secs(10), true);
+        assert_eq!(backoff.state.lock().unwrap().windows, 4);
+    }
+
+    #[tokio::test]
+    async fn acquire_waits_for_a_free_slot() {
+        let backoff = Arc::new(backoff_with_peak(2));
+        backoff.limit.store(1, Ordering::SeqCst);
+        let slot = backoff.acquire().await;
+        let waiting = tokio::spawn({
+            let backoff = backoff.clone();
+            async move {
+                let _slot = backoff.acquire().await;
+                backoff.in_flight.load(Ordering::SeqCst)
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiting.is_finished());
+        drop(slot);
+        let in_flight = tokio::time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(in_flight, 1);
+        assert_eq!(backoff.in_flight.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn parses_configs() {
+        assert_eq!("".parse::<AimdConfig>().unwrap(), AimdConfig::default());
+        let config: AimdConfig = "threshold=0.1,window-ms=500,decrease=0.7,min=2"
+            .parse()
+            .unwrap();
+        assert_eq!(config.window, Duration::from_millis(500));
+        assert_eq!(config.min, 2);
+        assert!("decrease=1".parse::<AimdConfig>().is_err());
+        assert!("threshold=1.5".parse::<AimdConfig>().is_err());
+        assert!("speed=2".parse::<AimdConfig>().is_err());
+    }
+}
@@ -0,0 +1,297 @@
+//! Measuring the tool's own per-request and per-byte overhead.
+//!
+//! `calibrate` runs a scaled-down copy of a workload against an in-memory
+//! store, where the store itself costs almost nothing, so what remains is the
+//! tool's scheduling, planning and accounting. The workload runs twice: once
+//! with its own request size and once with the request size halved, which
+//! roughly doubles the request count for the same bytes. Solving
+//!
+//! ```text
+//! elapsed_us = requests * per_request_us + bytes * per_byte_us
+//! ```
+//!
+//! for the two runs separates the two costs. With `--calibration path`, later
+//! results get an `overhead_adjusted` object holding the calibration, the
+//! overhead it predicts for the run, and the adjusted elapsed time and
+//! throughput next to the raw ones. The raw fields are never changed.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+use object_store::{memory::InMemory, path::Path, ObjectStore};
+
+use crate::columnar::{columnar_read_test, ColumnarOptions};
+use crate::context::RunContext;
+use crate::control::RunControl;
+use crate::download::{parallel_download_bench, DownloadOptions};
+use crate::error::Error;
+use crate::experiment::Fields;
+use crate::inspect_location;
+use crate::retry::RetryPolicy;
+
+/// Largest number of objects mirrored into the in-memory store.
+const MAX_OBJECTS: usize = 16;
+/// Largest size of each mirrored object.
+const MAX_OBJECT_SIZE: usize = 32 * 1024 * 1024;
+
+/// The workloads that can be calibrated, with their parameters.
+#[derive(Debug, Clone)]
+pub enum Workload {
+    Download {
+        parallel_downloads: usize,
+        block_size: Option<usize>,
+    },
+    Columnar(ColumnarOptions),
+}
+
+impl Workload {
+    fn name(&self) -> &'static str {
+        match self {
+            Workload::Download { .. } => "download",
+            Workload::Columnar(_) => "columnar",
+        }
+    }
+
+    /// The same workload with every request half the size.
+    fn halved(&self, object_size: usize) -> Self {
+        match self {
+            Workload::Download {
+                parallel_downloads,
+                block_size,
+            } => Workload::Download {
+                parallel_downloads: *parallel_downloads,
+                block_size: Some(
+                    (block_size.unwrap_or(object_size / parallel_downloads) / 2).max(1),
+                ),
+            },
+            Workload::Columnar(options) => Workload::Columnar(ColumnarOptions {
+                page_sizes: options
+                    .page_sizes
+                    .iter()
+                    .map(|size| (size / 2).max(1))
+                    .collect(),
+                ..options.clone()
+            }),
+        }
+    }
+
+    async fn run(
+        &self,
+        object_store: Arc<dyn ObjectStore>,
+        location: Path,
+    ) -> Result<Sample, Error> {
+        // The benchmarks' own results describe the scaled-down runs, not the
+        // workload, so keep them out of the output and any experiment directory.
+        let control = RunControl::with_context(RunContext {
+            quiet: true,
+            ..RunContext::default()
+        });
+        let retry = RetryPolicy::default();
+        let start = Instant::now();
+        match self {
+            Workload::Download {
+                parallel_downloads,
+                block_size,
+            } => {
+                parallel_download_bench(
+                    object_store,
+                    location,
+                    DownloadOptions {
+                        parallel_downloads: *parallel_downloads,
+                        block_size: *block_size,
+                        ..DownloadOptions::default()
+                    },
+                    retry,
+                    control.clone(),
+                )
+                .await?
+                .into_result()?;
+            }
+            Workload::Columnar(options) => {
+                let options = ColumnarOptions {
+                    analyze: false,
+                    manifest_out: None,
+                    ..options.clone()
+                };
+                columnar_read_test(object_store, location, options, retry, control.clone())
+                    .await?
+                    .into_result()?;
+            }
+        }
+        let elapsed_us = start.elapsed().as_secs_f64() * 1_000_000.0;
+        let snapshot = control.snapshot();
+        Ok(Sample {
+            requests: snapshot.requests,
+            bytes: snapshot.bytes,
+            elapsed_us,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    requests: u64,
+    bytes: u64,
+    elapsed_us: f64,
+}
+
+impl Sample {
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "requests": self.requests,
+            "bytes": self.bytes,
+            "elapsed_us": self.elapsed_us,
+        })
+    }
+}
+
+/// Overhead coefficients from a calibration record.
+#[derive(Debug, Clone)]
+pub struct Calibration {
+    source: String,
+    workload: String,
+    per_request_us: f64,
+    per_byte_us: f64,
+}
+
+/// Solve for per-request and per-byte costs from two samples, falling back to
+/// charging everything per request when the samples can't be told apart.
+fn solve(a: Sample, b: Sample) -> (f64, f64) {
+    let (r1, b1, r2, b2) = (
+        a.requests as f64,
+        a.bytes as f64,
+        b.requests as f64,
+        b.bytes as f64,
+    );
+    let det = r1 * b2 - r2 * b1;
+    if det.abs() < f64::EPSILON || r1 == 0.0 {
+        return (a.elapsed_us / r1.max(1.0), 0.0);
+    }
+    let per_request = (a.elapsed_us * b2 - b.elapsed_us * b1) / det;
+    let per_byte = (r1 * b.elapsed_us - r2 * a.elapsed_us) / det;
+    (per_request.max(0.0), per_byte.max(0.0))
+}
+
+/// Calibrate `workload` on an in-memory copy of the objects under `location`,
+/// scaled down to at most [`MAX_OBJECTS`] of [`MAX_OBJECT_SIZE`] bytes.
+pub async fn calibrate(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    workload: Workload,
+    out: Option<&std::path::Path>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    let num_objects = objects.len().min(MAX_OBJECTS);
+    let object_size = objects[0].size.min(MAX_OBJECT_SIZE);
+
+    let memory: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let prefix = Path::from("calibration");
+    let data = Bytes::from(vec![0u8; object_size]);
+    for i in 0..num_objects {
+        memory
+            .put(&prefix.child(format!("object_{}.bin", i)), data.clone())
+            .await?;
+    }
+
+    let full = workload.run(memory.clone(), prefix.clone()).await?;
+    let halved = workload.halved(object_size);
+    let half = halved.run(memory, prefix).await?;
+
+    let (per_request_us, per_byte_us) = solve(full, half);
+    let record = serde_json::json!({
+        "mode": "calibration",
+        "workload": workload.name(),
+        "num_objects": num_objects,
+        "object_size": object_size,
+        "samples": [full.to_json(), half.to_json()],
+        "per_request_us": per_request_us,
+        "per_byte_us": per_byte_us,
+        "model": "elapsed_us = requests * per_request_us + bytes * per_byte_us",
+    });
+    if let Some(out) = out {
+        std::fs::write(out, format!("{}\n", record))?;
+    }
+    control.emit(record);
+    Ok(())
+}
+
+/// Load the calibration record at `path`, to apply to every result.
+pub fn load(path: &std::path::Path) -> Result<Calibration, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let record: serde_json::Value = serde_json::from_str(contents.trim())
+        .map_err(|err| format!("{}: invalid calibration record: {}", path.display(), err))?;
+    let field = |name: &str| {
+        record
+            .get(name)
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| format!("{}: calibration record is missing {}", path.display(), name))
+    };
+    let calibration = Calibration {
+        source: path.display().to_string(),
+        workload: record
+            .get("workload")
+            .and_then(|v| v.as_str())
+            .unwrap_or("unknown")
+            .to_string(),
+        per_request_us: field("per_request_us")?,
+        per_byte_us: field("per_byte_us")?,
+    };
+    Ok(calibration)
+}
+
+impl Calibration {
+    /// Warn when a calibration taken for one workload is applied to another.
+    pub fn check_workload(&self, command: &str) {
+        if self.workload != command {
+            eprintln!(
+                "warning: calibration {} was measured for {}, not {}",
+                self.source, self.workload, command
+            );
+        }
+    }
+}
+
+/// Append overhead-adjusted figures from `calibration` to the JSON object
+/// `result`, if it reports its requests and bytes.
+pub fn with_adjustment(result: &mut Fields, calibration: &Calibration) {
+    let (Some(requests), Some(bytes), Some(elapsed_us)) = (
+        result.get("num_requests").and_then(|v| v.as_u64()),
+        result.get("bytes").and_then(|v| v.as_u64()),
+        result.get("elapsed_us").and_then(|v| v.as_u64()),
+    ) else {
+        return;
+    };
+    let paused_us = result
+        .get("paused_us")
+        .and_then(|v| v.as_u64())
+        .unwrap_or(0);
+
+    let request_overhead_us = requests as f64 * calibration.per_request_us;
+    let byte_overhead_us = bytes as f64 * calibration.per_byte_us;
+    let overhead_us = request_overhead_us + byte_overhead_us;
+    // Like mbps, the raw time excludes any time spent paused.
+    let raw_us = crate::stats::active_us(elapsed_us.into(), paused_us.into()) as f64;
+    let adjusted_us = (raw_us - overhead_us).max(0.0);
+    let mbps = |us: f64| bytes as f64 / 1024.0 / 1024.0 / (us / 1_000_000.0);
+    let adjustment = serde_json::json!({
+        "calibration": calibration.source,
+        "formula": "adjusted_elapsed_us = raw_elapsed_us - (requests * per_request_us + bytes * per_byte_us)",
+        "per_request_us": calibration.per_request_us,
+        "per_byte_us": calibration.per_byte_us,
+        "requests": requests,
+        "bytes": bytes,
+        "request_overhead_us": request_overhead_us,
+        "byte_overhead_us": byte_overhead_us,
+        "overhead_us": overhead_us,
+        "raw_elapsed_us": raw_us,
+        "adjusted_elapsed_us": adjusted_us,
+        "raw_mbps": mbps(raw_us),
+        "adjusted_mbps": mbps(adjusted_us),
+    });
+    result.insert("overhead_adjusted".to_string(), adjustment);
+}
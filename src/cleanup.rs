@@ -0,0 +1,190 @@
+//! Deleting uploaded test data.
+//!
+//! Without a manifest every object under the location is deleted, which needs
+//! a listing. With `--manifest`, exactly the objects an upload recorded are
+//! deleted and nothing is listed, so other data sharing the prefix survives.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+use serde_json::json;
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::manifest::UploadManifest;
+use crate::retry::RetryPolicy;
+
+/// What a cleanup removed.
+#[derive(Debug, Default, PartialEq)]
+pub struct CleanupSummary {
+    pub deleted: usize,
+    /// Objects in the manifest that were already gone
+    pub missing: usize,
+}
+
+/// Deletes the test data under `location`.
+///
+/// * `parallel`: maximum number of deletes in flight
+/// * `manifest`: manifest of the upload to remove, instead of everything listed
+pub async fn cleanup(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel: usize,
+    manifest: Option<std::path::PathBuf>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<CleanupSummary, Error> {
+    let objects = match &manifest {
+        Some(path) => UploadManifest::load(path)?
+            .objects_under(&location)?
+            .to_vec(),
+        None => inspect_location(object_store.as_ref(), &location, control.listing(), &retry)
+            .await?
+            .into_iter()
+            .map(|meta| meta.location)
+            .collect(),
+    };
+
+    let start = Instant::now();
+    let outcomes = futures::stream::iter(objects.iter())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|object| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                control.record("delete", object, None);
+                match retry.run(|| object_store.delete(object)).await {
+                    Ok(()) => {
+                        control.request_finished(0);
+                        Ok(Some(true))
+                    }
+                    Err(object_store::Error::NotFound { .. }) => {
+                        control.request_finished(0);
+                        Ok(Some(false))
+                    }
+                    Err(err) => {
+                        control.request_failed();
+                        Err(err)
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .try_filter_map(|deleted| futures::future::ready(Ok(deleted)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    let elapsed_us = start.elapsed().as_micros();
+
+    let summary = CleanupSummary {
+        deleted: outcomes.iter().filter(|deleted| **deleted).count(),
+        missing: outcomes.iter().filter(|deleted| !**deleted).count(),
+    };
+    let mut result = fields(json!({
+        "mode": "cleanup",
+        "root": location.to_string(),
+        "from_manifest": manifest.is_some(),
+        "num_objects": objects.len(),
+        "deleted": summary.deleted,
+        "missing": summary.missing,
+        "parallel": parallel,
+        "elapsed_us": elapsed_us as u64,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::Contents;
+    use crate::upload::{upload_multiple, UploadSamples};
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    async fn seeded_upload(
+        object_store: Arc<dyn ObjectStore>,
+        root: &Path,
+        manifest_out: &std::path::Path,
+    ) -> UploadManifest {
+        upload_multiple(
+            object_store,
+            root,
+            3,
+            3 * 1024,
+            crate::store_defaults::PART_SIZE,
+            true,
+            Some(42),
+            Contents::Random { seed: None },
+            Some("seeded"),
+            Some(manifest_out),
+            &RetryPolicy::new(0, None),
+            None,
+            &UploadSamples::start(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn manifest_cleanup_removes_only_the_upload() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let root = Path::from("data");
+        let bystanders = [Path::from("data/keep.bin"), Path::from("other/keep.bin")];
+        for bystander in &bystanders {
+            object_store
+                .put(bystander, Bytes::from_static(b"keep"))
+                .await
+                .unwrap();
+        }
+        let manifest_out = std::env::temp_dir().join(format!(
+            "object-store-bench-cleanup-{}.json",
+            std::process::id()
+        ));
+        let manifest = seeded_upload(object_store.clone(), &root, &manifest_out).await;
+
+        // The same seed draws the same prefixes.
+        let again = seeded_upload(Arc::new(InMemory::new()), &root, &manifest_out).await;
+        assert_eq!(again.objects, manifest.objects);
+        assert_eq!(manifest.prefix_seed, Some(42));
+
+        let summary = cleanup(
+            object_store.clone(),
+            root,
+            2,
+            Some(manifest_out.clone()),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        std::fs::remove_file(&manifest_out).unwrap();
+        assert_eq!(
+            summary,
+            CleanupSummary {
+                deleted: 3,
+                missing: 0
+            }
+        );
+
+        let mut left = object_store
+            .list(None)
+            .await
+            .unwrap()
+            .map_ok(|meta| meta.location)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        left.sort();
+        assert_eq!(left, bystanders);
+    }
+}
@@ -5,100 +5,986 @@
 //!
 //! For example, we might get a parameter `--page-sizes=1024,4096,16384` and
 //! so then we split up the file into pages of those sizes, repeating as necessary.
+//!
+//! With `--footer-size N`, the last N bytes of every object are its footer,
+//! and pages are laid out before it. As a Parquet reader would, the run first
+//! reads each object's footer, then its pages; footer latency is reported
+//! under `footer`, apart from the page reads' throughput.
+//!
+//! `--columns` projects a subset of the columns: their pages are read where
+//! the full layout puts them, and the others are skipped.
+//!
+//! Within a group, pages are issued in column order unless `--column-priority`
+//! lists columns to issue first. Each column's time-to-available (from the
+//! start of its group to its page arriving) is reported either way.
+//!
+//! Each page is its own `get_range` unless `--coalesce-gap` is given. Then a
+//! group's pages at most that many bytes apart are read in one request, as a
+//! real reader would, so 0 merges only adjacent pages. `read_amplification`
+//! compares the bytes fetched, gaps included, with the page bytes needed.
+//!
+//! With `--ranges-api`, each group's reads of an object are instead sent as
+//! one `get_ranges`, which some backends optimize, and every page of the group
+//! is available when it returns. Results name the API used under `api`.
+
+use std::collections::HashSet;
+use std::ops::Range;
 
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use futures::{StreamExt, TryStreamExt};
 use object_store::{path::Path, ObjectStore};
+use serde_json::json;
 
+use crate::accounting::Accounting;
+use crate::analyze::{calibrate, Analysis};
+use crate::control::RunControl;
+use crate::download::to_usize_range;
+use crate::error::Error;
 use crate::inspect_location;
+use crate::missing::MissingTracker;
+use crate::query_sim::{coalesce, read_range};
+use crate::report::{Checked, ColumnReady, ColumnarResult};
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps, LatencySummary};
+use crate::think_time::{Pacer, Pacing};
+
+/// Placement of each column's page within an object, group by group.
+///
+/// Pages may be preceded by padding so they start at a multiple of
+/// `page_align`, and followed by `inter_page_gap` unused bytes. Reads only ever
+/// fetch page bytes, so padding shows up as wasted bytes when reads coalesce.
+/// Offsets are `u64`, like the object sizes download plans with, so a layout
+/// past 4 GiB is exact on any target.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub page_sizes: Vec<usize>,
+    pub page_align: usize,
+    pub inter_page_gap: usize,
+    pub num_groups: usize,
+    /// `page_offsets[column][group]` is the start of that page
+    pub page_offsets: Vec<Vec<u64>>,
+    /// Bytes of page data across all groups
+    pub data_bytes: u64,
+    /// Bytes of alignment padding and gaps between the first and last page
+    pub padding_bytes: u64,
+}
+
+impl Layout {
+    /// Lay out as many whole groups as fit in `object_size`.
+    pub fn plan(
+        page_sizes: &[usize],
+        object_size: u64,
+        page_align: usize,
+        inter_page_gap: usize,
+    ) -> Self {
+        let page_align = page_align.max(1);
+        let num_columns = page_sizes.len();
+        let mut page_offsets = vec![Vec::new(); num_columns];
+        let mut group_offsets = Vec::with_capacity(num_columns);
+        let mut num_groups = 0;
+        let mut offset: u64 = 0;
+        let mut data_bytes = 0;
+        let group_bytes = page_sizes.iter().map(|&size| size as u64).sum::<u64>();
+        'groups: loop {
+            group_offsets.clear();
+            let mut group_end = offset;
+            for &page_size in page_sizes {
+                // Checked, so a group that would run past the end of the
+                // address space ends the layout like one past the object.
+                let page_end = group_end
+                    .checked_next_multiple_of(page_align as u64)
+                    .and_then(|page_start| {
+                        Some((page_start, page_start.checked_add(page_size as u64)?))
+                    });
+                let Some((page_start, page_end)) = page_end.filter(|(_, end)| *end <= object_size)
+                else {
+                    break 'groups;
+                };
+                group_offsets.push(page_start);
+                group_end = page_end.saturating_add(inter_page_gap as u64);
+            }
+            for (column_i, page_start) in group_offsets.iter().enumerate() {
+                page_offsets[column_i].push(*page_start);
+            }
+            data_bytes += group_bytes;
+            num_groups += 1;
+            offset = group_end;
+        }
+        let end_of_data = page_offsets
+            .iter()
+            .zip(page_sizes)
+            .filter_map(|(offsets, &size)| offsets.last().map(|offset| offset + size as u64))
+            .max()
+            .unwrap_or(0);
+
+        Self {
+            page_sizes: page_sizes.to_vec(),
+            page_align,
+            inter_page_gap,
+            num_groups,
+            page_offsets,
+            data_bytes,
+            padding_bytes: end_of_data - data_bytes,
+        }
+    }
+
+    /// The bytes of `column_i`'s page in group `group_i`.
+    pub fn page(&self, column_i: usize, group_i: usize) -> Range<u64> {
+        let start = self.page_offsets[column_i][group_i];
+        start..start + self.page_sizes[column_i] as u64
+    }
+
+    /// Fraction of the laid-out file taken up by padding.
+    pub fn space_overhead(&self) -> f64 {
+        let file_bytes = self.data_bytes + self.padding_bytes;
+        if file_bytes == 0 {
+            return 0.0;
+        }
+        self.padding_bytes as f64 / file_bytes as f64
+    }
+
+    /// The layout as a JSON manifest with the true offset of every page.
+    pub fn manifest(&self) -> serde_json::Value {
+        serde_json::json!({
+            "page_sizes": self.page_sizes,
+            "page_align": self.page_align,
+            "inter_page_gap": self.inter_page_gap,
+            "num_groups": self.num_groups,
+            "data_bytes": self.data_bytes,
+            "padding_bytes": self.padding_bytes,
+            "page_offsets": self.page_offsets,
+        })
+    }
+}
+
+/// Parameters for [`columnar_read_test`].
+#[derive(Debug, Clone)]
+pub struct ColumnarOptions {
+    /// Number of groups to read in parallel
+    pub parallel_downloads: usize,
+    pub page_sizes: Vec<usize>,
+    /// Calibrate the store after the run and report recommendations
+    pub analyze: bool,
+    pub page_align: usize,
+    pub inter_page_gap: usize,
+    /// Where to write the page layout manifest, if anywhere
+    pub manifest_out: Option<std::path::PathBuf>,
+    /// Columns to read; all of them when empty
+    pub columns: Vec<usize>,
+    /// Columns whose pages are issued first within each group, in this order
+    pub column_priority: Vec<usize>,
+    /// Replace `page_sizes` with ones inferred from the first object
+    pub infer_layout: bool,
+    /// Read a group's pages at most this many bytes apart in one request
+    pub coalesce_gap: Option<usize>,
+    /// Read each group with one `get_ranges` rather than a `get_range` per read
+    pub ranges_api: bool,
+    /// Bytes at the end of each object read as its footer before its pages
+    pub footer_size: Option<usize>,
+    /// Think time each slot waits after a group completes
+    pub pacing: Option<Pacing>,
+}
+
+impl Default for ColumnarOptions {
+    /// One group at a time, every column of the command line's three 64 KiB
+    /// pages, packed without padding and each read with its own `get_range`.
+    fn default() -> Self {
+        Self {
+            parallel_downloads: 1,
+            page_sizes: vec![65536; 3],
+            analyze: false,
+            page_align: 1,
+            inter_page_gap: 0,
+            manifest_out: None,
+            columns: Vec::new(),
+            column_priority: Vec::new(),
+            infer_layout: false,
+            coalesce_gap: None,
+            ranges_api: false,
+            footer_size: None,
+            pacing: None,
+        }
+    }
+}
+
+/// The columns a run reads: `columns`, or all `num_columns` when it's empty.
+pub fn projection(num_columns: usize, columns: &[usize]) -> Result<Vec<usize>, String> {
+    if columns.is_empty() {
+        return Ok((0..num_columns).collect());
+    }
+    for (i, &column_i) in columns.iter().enumerate() {
+        if column_i >= num_columns {
+            return Err(format!(
+                "columns lists column {} but there are only {} columns",
+                column_i, num_columns
+            ));
+        }
+        if columns[..i].contains(&column_i) {
+            return Err(format!("columns lists column {} twice", column_i));
+        }
+    }
+    let mut projected = columns.to_vec();
+    projected.sort_unstable();
+    Ok(projected)
+}
+
+/// Order in which a group's pages are issued: the prioritized columns first,
+/// then the rest in their natural order.
+pub fn issue_order(num_columns: usize, priority: &[usize]) -> Result<Vec<usize>, String> {
+    let mut order = Vec::with_capacity(num_columns);
+    for &column_i in priority {
+        if column_i >= num_columns {
+            return Err(format!(
+                "column priority lists column {} but there are only {} columns",
+                column_i, num_columns
+            ));
+        }
+        if order.contains(&column_i) {
+            return Err(format!("column priority lists column {} twice", column_i));
+        }
+        order.push(column_i);
+    }
+    order.extend((0..num_columns).filter(|column_i| !priority.contains(column_i)));
+    Ok(order)
+}
+
+/// The reads issuing `order`'s pages of one group, each with the columns it
+/// covers. Without a `coalesce_gap` every page is its own read, in `order`;
+/// with one, nearby pages share a read, and reads go out in the order of
+/// their earliest column in `order`.
+pub fn group_reads(
+    order: &[usize],
+    pages: impl Fn(usize) -> Range<u64>,
+    coalesce_gap: Option<usize>,
+) -> Vec<(Range<u64>, Vec<usize>)> {
+    let Some(gap) = coalesce_gap else {
+        return order
+            .iter()
+            .map(|&column_i| (pages(column_i), vec![column_i]))
+            .collect();
+    };
+    let mut ranges = order
+        .iter()
+        .map(|&column_i| pages(column_i))
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start);
+    let mut reads = coalesce(ranges, gap as u64)
+        .into_iter()
+        .map(|range| (range, Vec::new()))
+        .collect::<Vec<_>>();
+    for &column_i in order {
+        let start = pages(column_i).start;
+        let read_i = reads.partition_point(|(range, _)| range.start <= start) - 1;
+        reads[read_i].1.push(column_i);
+    }
+    let position = |column_i: &usize| order.iter().position(|c| c == column_i);
+    reads.sort_by_key(|(_, columns)| columns.iter().map(position).min());
+    reads
+}
+
+/// `range` of a layout as the `usize` range object_store takes. The layout
+/// lies within an object, whose size is a `usize`, so it always fits.
+fn request_range(range: Range<u64>) -> Range<usize> {
+    to_usize_range(range).expect("a page lies within its object")
+}
+
+/// Read `ranges` of `location`: the one range with `get_range`, or all of
+/// them with one `get_ranges` when `ranges_api` is set.
+async fn fetch(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    ranges: &[Range<usize>],
+    ranges_api: bool,
+    retry: &RetryPolicy,
+) -> object_store::Result<Vec<usize>> {
+    if !ranges_api {
+        let bytes = retry
+            .run(|| object_store.get_range(location, ranges[0].clone()))
+            .await?;
+        return Ok(vec![bytes.len()]);
+    }
+    let buffers = retry
+        .run(|| object_store.get_ranges(location, ranges))
+        .await?;
+    check_buffers(location, ranges, &buffers)
+}
+
+/// The length of each of `buffers`, or an error unless there is one per
+/// range and each is its range's size.
+fn check_buffers(
+    location: &Path,
+    ranges: &[Range<usize>],
+    buffers: &[bytes::Bytes],
+) -> object_store::Result<Vec<usize>> {
+    let lens = buffers
+        .iter()
+        .map(|buffer| buffer.len())
+        .collect::<Vec<_>>();
+    let expected = ranges.iter().map(Range::len).collect::<Vec<_>>();
+    if lens != expected {
+        return Err(object_store::Error::Generic {
+            store: "columnar",
+            source: format!(
+                "get_ranges of {} returned buffers of {:?} bytes for ranges of {:?}",
+                location, lens, expected
+            )
+            .into(),
+        });
+    }
+    Ok(lens)
+}
+
+/// What the reads of one group returned.
+#[derive(Debug, Default)]
+struct GroupRead {
+    /// Bytes received, including any gaps between coalesced pages
+    fetched: usize,
+    /// Bytes of the pages read
+    needed: usize,
+    requests: usize,
+    /// Requests the run went on without, see `RetryPolicy::absorb`
+    failed_requests: usize,
+    /// When each column's page arrived, from the start of the group
+    ready: Vec<(usize, Duration)>,
+    /// When the group was issued and when its last read completed, from the
+    /// start of the run
+    span: (Duration, Duration),
+}
 
 pub async fn columnar_read_test(
     object_store: Arc<dyn ObjectStore>,
     location: Path,
-    parallel_downloads: usize,
-    page_sizes: Vec<usize>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let objects = inspect_location(object_store.as_ref(), &location).await?;
-    let object_size = objects[0].size;
-    assert!(
-        objects.iter().all(|o| o.size == object_size),
-        "expected all objects to have the same size"
+    options: ColumnarOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<Checked<ColumnarResult>, Error> {
+    let ColumnarOptions {
+        parallel_downloads,
+        page_sizes,
+        analyze,
+        page_align,
+        inter_page_gap,
+        manifest_out,
+        columns,
+        column_priority,
+        infer_layout,
+        coalesce_gap,
+        ranges_api,
+        footer_size,
+        pacing,
+    } = options;
+    // Inferring a Parquet layout can change the number of columns, so the
+    // projection is checked once more below.
+    if !infer_layout {
+        projection(page_sizes.len(), &columns)?;
+    }
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    // Zero-byte markers such as `_SUCCESS` hold no pages; skip them.
+    let (empty, objects): (Vec<_>, Vec<_>) = objects.into_iter().partition(|o| o.size == 0);
+    if objects.is_empty() {
+        return Err(format!("every object under {} is empty", location).into());
+    }
+    let inferred = match infer_layout {
+        true => Some(
+            crate::infer_layout::infer(
+                object_store.as_ref(),
+                &objects[0],
+                page_sizes.len(),
+                &retry,
+            )
+            .await?,
+        ),
+        false => None,
+    };
+    let page_sizes = inferred
+        .as_ref()
+        .map_or(page_sizes, |inferred| inferred.page_sizes.clone());
+    let columns = projection(page_sizes.len(), &columns)?;
+    if let Some(column_i) = column_priority.iter().find(|c| !columns.contains(c)) {
+        return Err(format!(
+            "column priority lists column {}, which isn't projected",
+            column_i
+        )
+        .into());
+    }
+    let order = issue_order(page_sizes.len(), &column_priority)?
+        .into_iter()
+        .filter(|column_i| columns.contains(column_i))
+        .collect::<Vec<_>>();
+    let footer_bytes = footer_size.unwrap_or(0);
+    if let Some(meta) = objects.iter().find(|meta| meta.size < footer_bytes) {
+        return Err(format!(
+            "{} is {} bytes, smaller than the {} byte footer",
+            meta.location, meta.size, footer_bytes
+        )
+        .into());
+    }
+    for meta in &objects {
+        let data_size = (meta.size - footer_bytes) as u64;
+        if Layout::plan(&page_sizes, data_size, page_align, inter_page_gap).num_groups == 0 {
+            return Err(format!(
+                "{} is {} bytes, too small for one group of pages {:?}",
+                meta.location, meta.size, page_sizes
+            )
+            .into());
+        }
+    }
+    // Every object is laid out from offset zero, so a smaller object's groups
+    // are a prefix of the largest one's and only their number differs.
+    let groups_per_object = objects
+        .iter()
+        .map(|meta| {
+            Layout::plan(
+                &page_sizes,
+                (meta.size - footer_bytes) as u64,
+                page_align,
+                inter_page_gap,
+            )
+            .num_groups
+        })
+        .collect::<Vec<_>>();
+    let largest = objects.iter().map(|meta| meta.size).max().unwrap_or(0);
+    let layout = Layout::plan(
+        &page_sizes,
+        (largest - footer_bytes) as u64,
+        page_align,
+        inter_page_gap,
     );
+    let num_groups = layout.num_groups;
+    let total_groups = groups_per_object.iter().sum::<usize>() as u64;
+    if let Some(manifest_out) = &manifest_out {
+        std::fs::write(manifest_out, layout.manifest().to_string())?;
+    }
 
-    let num_columns = page_sizes.len();
-    let group_size = page_sizes.iter().sum::<usize>();
-    let num_groups = object_size / group_size;
-    let mut page_offsets = vec![Vec::with_capacity(num_groups); num_columns];
-
-    let total_size = group_size * num_groups;
-    assert!(total_size <= object_size, "object is too small");
-
-    let mut offset = 0;
-    for _group_i in 0..num_groups {
-        for column_i in 0..num_columns {
-            let page_size = page_sizes[column_i];
-            page_offsets[column_i].push(offset);
-            offset += page_size;
+    let tracker = Arc::new(MissingTracker::new(
+        object_store.clone(),
+        &location,
+        &objects,
+        control.listing(),
+        &retry,
+    ));
+    let mut planned = Vec::new();
+    for (meta, &object_groups) in objects.iter().zip(&groups_per_object) {
+        for &column_i in &columns {
+            for group_i in 0..object_groups {
+                planned.push((
+                    meta.location.clone(),
+                    request_range(layout.page(column_i, group_i)),
+                ));
+            }
         }
     }
+    if footer_size.is_some() {
+        for meta in &objects {
+            planned.push((meta.location.clone(), meta.size - footer_bytes..meta.size));
+        }
+    }
+    let accounting = Arc::new(Accounting::planned(planned));
+    // Objects with a request the run went on without, see `RetryPolicy::absorb`.
+    let failed = Mutex::new(HashSet::new());
+    let failed = &failed;
 
+    // Footers are read first, and timed apart from the pages.
+    let footer = match footer_size {
+        Some(footer_size) => {
+            let footer_start = Instant::now();
+            let footers = futures::stream::iter(&objects)
+                .take_while(|_| futures::future::ready(!control.is_shutdown()))
+                .map(|meta| {
+                    let range = meta.size - footer_size..meta.size;
+                    let (object_store, retry, control) = (&object_store, &retry, &control);
+                    let (tracker, accounting) = (&tracker, &accounting);
+                    async move {
+                        let read = match tracker
+                            .resolve(&meta.location, || {
+                                read_range(
+                                    object_store.as_ref(),
+                                    &meta.location,
+                                    range.clone(),
+                                    retry,
+                                    control,
+                                )
+                            })
+                            .await
+                        {
+                            Ok(read) => read.flatten(),
+                            Err(err) if retry.absorb(&err) => {
+                                failed.lock().unwrap().insert(meta.location.clone());
+                                None
+                            }
+                            Err(err) => return Err(err),
+                        };
+                        if let Some((len, _)) = read {
+                            accounting.received(&meta.location, len);
+                        }
+                        Ok::<_, object_store::Error>(read)
+                    }
+                })
+                .buffer_unordered(parallel_downloads)
+                .take_until(control.abandoned())
+                .try_collect::<Vec<_>>()
+                .await?;
+            let elapsed_us = footer_start.elapsed().as_micros();
+            let mut latencies = footers
+                .iter()
+                .flatten()
+                .map(|(_, latency)| *latency)
+                .collect::<Vec<_>>();
+            Some(json!({
+                "footer_size": footer_size,
+                "requests": latencies.len(),
+                "bytes": footers.iter().flatten().map(|(len, _)| len).sum::<usize>(),
+                "elapsed_us": elapsed_us as u64,
+                "latency": LatencySummary::from_latencies(&mut latencies).to_json(),
+            }))
+        }
+        None => None,
+    };
     let objects_ref = objects.as_slice();
+    let groups_ref = groups_per_object.as_slice();
     let ranges_iter = (0..num_groups).flat_map(move |group_i| {
         objects_ref
             .iter()
-            .map(move |meta| (meta.location.clone(), group_i))
+            .zip(groups_ref)
+            .filter(move |(_, &object_groups)| group_i < object_groups)
+            .map(move |(meta, _)| (meta.location.clone(), group_i))
             .collect::<Vec<_>>()
     });
 
+    let projected_bytes: u64 = columns
+        .iter()
+        .map(|&column_i| page_sizes[column_i] as u64)
+        .sum();
+    control.set_bytes_total(projected_bytes * total_groups);
+
     let start = std::time::Instant::now();
     let page_sizes_ref = page_sizes.as_slice();
-    let page_offsets_ref = page_offsets.as_slice();
-    let _counts = futures::stream::iter(ranges_iter)
+    let layout_ref = &layout;
+    let order_ref = order.as_slice();
+    let pacer = pacing.map(Pacer::new);
+    let pacer = &pacer;
+    let groups = futures::stream::iter(ranges_iter)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
         .map(|(location, group_i)| {
             let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            let tracker = tracker.clone();
+            let accounting = accounting.clone();
             async move {
-                let reads = page_offsets_ref
-                    .iter()
-                    .enumerate()
-                    .map(|(column_i, offsets)| {
-                        let page_size = page_sizes_ref[column_i];
-                        let offset = offsets[group_i];
-                        // We already checked the object size, so this should be safe
-                        let range = offset..(offset + page_size);
+                let group_start = Instant::now();
+                // The object holds this group, as its groups were counted above.
+                let page = |column_i: usize| layout_ref.page(column_i, group_i);
+                let reads = group_reads(order_ref, page, coalesce_gap).into_iter().map(
+                    |(range, columns)| {
+                        let needed: usize = columns
+                            .iter()
+                            .map(|&column_i| page_sizes_ref[column_i])
+                            .sum();
+                        (request_range(range), columns, needed)
+                    },
+                );
+                // With the ranges API a group's reads go out as one request.
+                let batches: Vec<Vec<_>> = match ranges_api {
+                    true => vec![reads.collect()],
+                    false => reads.map(|read| vec![read]).collect(),
+                };
+                let batches = batches
+                    .into_iter()
+                    .map(|batch| {
                         let location = location.clone();
                         let object_store = object_store.clone();
+                        let retry = retry.clone();
+                        let control = control.clone();
+                        let tracker = tracker.clone();
+                        let accounting = accounting.clone();
                         tokio::task::spawn(async move {
-                            object_store
-                                .get_range(&location, range)
-                                .await
-                                .map(|res| res.len())
+                            if tracker.is_vanished(&location) || !control.request_started().await {
+                                return Ok(None);
+                            }
+                            let ranges = batch
+                                .iter()
+                                .map(|(range, _, _)| range.clone())
+                                .collect::<Vec<_>>();
+                            for range in &ranges {
+                                control.record("get_range", &location, Some(range));
+                            }
+                            let result = tracker
+                                .resolve(&location, || {
+                                    fetch(
+                                        object_store.as_ref(),
+                                        &location,
+                                        &ranges,
+                                        ranges_api,
+                                        &retry,
+                                    )
+                                })
+                                .await;
+                            match &result {
+                                Ok(Some(lens)) => {
+                                    control.request_finished(lens.iter().sum());
+                                    for ((range, _, needed), len) in batch.iter().zip(lens) {
+                                        // Gap bytes between coalesced pages weren't planned.
+                                        let gaps = range.len() - needed;
+                                        accounting.received(&location, len.saturating_sub(gaps));
+                                    }
+                                }
+                                Ok(None) | Err(_) => control.request_failed(),
+                            }
+                            let elapsed = group_start.elapsed();
+                            result.map(|lens| lens.map(|lens| (batch, lens, elapsed)))
                         })
                     })
                     .collect::<Vec<_>>();
-                let counts = futures::future::join_all(reads).await;
-                let mut total = 0;
-                for count in counts {
-                    total += match count {
-                        Ok(Ok(count)) => count,
-                        Ok(Err(e)) => return Err(e),
-                        Err(e) => return Err(object_store::Error::JoinError { source: e }),
+                let batches = futures::future::join_all(batches).await;
+                let mut group = GroupRead::default();
+                for batch in batches {
+                    let batch = match batch {
+                        Ok(batch) => batch,
+                        Err(e) => Err(object_store::Error::JoinError { source: e }),
                     };
+                    match batch {
+                        Ok(Some((batch, lens, elapsed))) => {
+                            group.requests += 1;
+                            for ((_, columns, needed), len) in batch.into_iter().zip(lens) {
+                                group.fetched += len;
+                                group.needed += needed;
+                                group.ready.extend(
+                                    columns.into_iter().map(|column_i| (column_i, elapsed)),
+                                );
+                            }
+                        }
+                        Ok(None) => {}
+                        Err(err) if retry.absorb(&err) => {
+                            group.failed_requests += 1;
+                            failed.lock().unwrap().insert(location.clone());
+                        }
+                        Err(err) => return Err(err),
+                    };
+                }
+                group.span = (group_start - start, start.elapsed());
+                // The slot stays busy through the think time.
+                if let Some(pacer) = pacer {
+                    pacer.think().await;
                 }
-                Ok(total)
+                Ok(group)
             }
         })
         .buffered(parallel_downloads)
+        .take_until(control.abandoned())
         .try_collect::<Vec<_>>()
         .await?;
     let end = std::time::Instant::now();
-    let elapsed_us = (end - start).as_micros();
+    // A paced run ends with its last read, not the think time after it.
+    let elapsed = match pacer {
+        Some(_) => groups
+            .iter()
+            .map(|group| group.span.1)
+            .max()
+            .unwrap_or_default(),
+        None => end - start,
+    };
+    // Time spent paused is excluded from throughput.
+    let elapsed_us = elapsed.as_micros();
+    let paused_us = control.paused().as_micros();
+
+    let total_size: u64 = groups.iter().map(|group| group.fetched as u64).sum();
+    let bytes_needed: u64 = groups.iter().map(|group| group.needed as u64).sum();
+    let successful_requests: usize = groups.iter().map(|group| group.requests).sum();
+    let num_requests = successful_requests
+        + groups
+            .iter()
+            .map(|group| group.failed_requests)
+            .sum::<usize>();
+    let mut column_ready = vec![Vec::<Duration>::new(); page_sizes.len()];
+    for group in &groups {
+        for &(column_i, elapsed) in &group.ready {
+            column_ready[column_i].push(elapsed);
+        }
+    }
+    let time_to_available = columns
+        .iter()
+        .map(|&column_i| ColumnReady {
+            column: column_i,
+            page_size: page_sizes[column_i],
+            latency: LatencySummary::from_latencies(&mut column_ready[column_i]),
+        })
+        .collect::<Vec<_>>();
+    let mbps = mbps(total_size, active_us(elapsed_us, paused_us));
+
+    // Calibration runs after the timed section so it can't disturb it.
+    let analysis = if analyze {
+        let model = calibrate(
+            object_store.clone(),
+            &objects[0].location,
+            objects[0].size,
+            &retry,
+        )
+        .await?;
+        Some(Analysis::new(model, &page_sizes).to_json())
+    } else {
+        None
+    };
+    let pacing = pacer.as_ref().map(|pacer| {
+        let requests = groups.iter().map(|group| group.span).collect();
+        pacer.json_field(requests, elapsed)
+    });
+    let inferred = inferred.map(|inferred| inferred.source);
+    let accounting = accounting.check(control.is_shutdown(), |path| {
+        tracker.is_vanished(path) || failed.lock().unwrap().contains(path)
+    });
+    let mut fields = accounting.json_field();
+    fields.extend(tracker.json_field());
+    fields.extend(retry.json_fields());
+    for (name, value) in [
+        ("analysis", analysis),
+        ("inferred_layout", inferred),
+        ("footer", footer),
+    ] {
+        if let Some(value) = value {
+            fields.insert(name.to_string(), value);
+        }
+    }
+    fields.extend(pacing.into_iter().flatten());
+
+    let result = ColumnarResult {
+        mode: "columnar",
+        api: if ranges_api {
+            "get_ranges"
+        } else {
+            "get_range"
+        },
+        num_objects: objects.len(),
+        zero_byte_objects: empty.len(),
+        num_groups,
+        page_sizes,
+        page_align,
+        inter_page_gap,
+        padding_bytes: layout.padding_bytes,
+        space_overhead: layout.space_overhead(),
+        parallel_downloads,
+        columns,
+        column_priority,
+        time_to_available,
+        coalesce_gap,
+        num_requests,
+        successful_requests,
+        bytes: total_size,
+        bytes_needed,
+        read_amplification: total_size as f64 / bytes_needed.max(1) as f64,
+        elapsed_us,
+        paused_us,
+        mbps,
+        interrupted: control.is_shutdown(),
+        fields,
+    };
+    let failure = accounting
+        .enforce(control.context().strict_accounting)
+        .and_then(|()| retry.enforce())
+        .err();
+    Ok(Checked { result, failure })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn nearby_pages_of_a_group_share_a_read() {
+        // Columns 0, 1 and 2 at 0..10, 10..20 and 30..40.
+        let pages = |column_i: usize| [0..10, 10..20, 30..40][column_i].clone();
+        assert_eq!(
+            group_reads(&[2, 0, 1], pages, None),
+            vec![(30..40, vec![2]), (0..10, vec![0]), (10..20, vec![1])]
+        );
+        assert_eq!(
+            group_reads(&[2, 0, 1], pages, Some(0)),
+            vec![(30..40, vec![2]), (0..20, vec![0, 1])]
+        );
+        assert_eq!(
+            group_reads(&[2, 0, 1], pages, Some(10)),
+            vec![(0..40, vec![2, 0, 1])]
+        );
+    }
 
-    let total_size = objects.len() * group_size * num_groups;
-    let mbps = total_size as f64 / 1024.0 / 1024.0 / (elapsed_us as f64 / 1_000_000.0);
+    #[test]
+    fn layouts_past_4gib_stay_exact() {
+        const GIB: u64 = 1024 * 1024 * 1024;
+        // Pages of one byte less than a GiB, aligned to a GiB, so every group
+        // pads by one byte per page and the third group ends past 4 GiB.
+        let page = GIB as usize - 1;
+        let layout = Layout::plan(&[page, page], 6 * GIB, GIB as usize, 0);
+        assert_eq!(layout.num_groups, 3);
+        assert_eq!(
+            layout.page_offsets,
+            [[0, 2 * GIB, 4 * GIB], [GIB, 3 * GIB, 5 * GIB]]
+        );
+        assert_eq!(layout.page(1, 2), 5 * GIB..6 * GIB - 1);
+        assert_eq!(layout.data_bytes, 6 * (GIB - 1));
+        assert_eq!(layout.padding_bytes, 5);
+        assert_eq!(
+            group_reads(&[0, 1], |column_i| layout.page(column_i, 2), Some(1)),
+            vec![(4 * GIB..6 * GIB - 1, vec![0, 1])]
+        );
 
-    println!("{{\"num_objects\": {}, \"num_groups\": {}, \"page_sizes\": {:?}, \"parallel_downloads\": {}, \"elapsed_us\": {}, \"mbps\": {}}}",
-        objects.len(), num_groups, page_sizes, parallel_downloads, elapsed_us, mbps);
+        // One byte short of the last page, the group doesn't fit.
+        let layout = Layout::plan(&[page, page], 6 * GIB - 2, GIB as usize, 0);
+        assert_eq!(layout.num_groups, 2);
+    }
+
+    #[tokio::test]
+    async fn ranges_api_reads_a_group_in_one_request() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 3000]))
+            .await
+            .unwrap();
+        let options = ColumnarOptions {
+            parallel_downloads: 2,
+            page_sizes: vec![100, 200, 300],
+            ranges_api: true,
+            ..ColumnarOptions::default()
+        };
+        let checked = columnar_read_test(
+            object_store,
+            Path::from("data"),
+            options,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        assert_eq!(result["api"], "get_ranges");
+        assert_eq!(result["num_groups"], 5);
+        assert_eq!(result["num_requests"], 5);
+        assert_eq!(result["bytes"], 3000);
+        assert_eq!(result["time_to_available"][2]["latency"]["count"], 5);
+    }
 
-    Ok(())
+    #[tokio::test]
+    async fn smaller_objects_are_read_for_the_groups_they_hold() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for (name, size) in [("data/a", 3000), ("data/b", 1300)] {
+            object_store
+                .put(&Path::from(name), Bytes::from(vec![7; size]))
+                .await
+                .unwrap();
+        }
+        let options = ColumnarOptions {
+            parallel_downloads: 2,
+            page_sizes: vec![100, 200, 300],
+            ranges_api: true,
+            ..ColumnarOptions::default()
+        };
+        let checked = columnar_read_test(
+            object_store,
+            Path::from("data"),
+            options,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        // Five groups of 600 bytes fit in the first object, two in the second.
+        assert_eq!(result["num_groups"], 5);
+        assert_eq!(result["num_requests"], 7);
+        assert_eq!(result["bytes"], 4200);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+    }
+
+    #[test]
+    fn short_get_ranges_responses_are_errors() {
+        let location = Path::from("data/a");
+        let ranges = [0..10, 20..30];
+        let buffers = [Bytes::from(vec![0; 10]), Bytes::from(vec![0; 10])];
+        assert_eq!(
+            check_buffers(&location, &ranges, &buffers).unwrap(),
+            [10, 10]
+        );
+        assert!(check_buffers(&location, &ranges, &buffers[..1]).is_err());
+        let short = [Bytes::from(vec![0; 10]), Bytes::from(vec![0; 4])];
+        assert!(check_buffers(&location, &ranges, &short).is_err());
+    }
+
+    #[tokio::test]
+    async fn projected_columns_are_read_in_place() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 3000]))
+            .await
+            .unwrap();
+        let options = |columns| ColumnarOptions {
+            parallel_downloads: 2,
+            page_sizes: vec![100, 200, 300],
+            columns,
+            ..ColumnarOptions::default()
+        };
+        let run = |options| {
+            columnar_read_test(
+                object_store.clone(),
+                Path::from("data"),
+                options,
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            )
+        };
+        let result = run(options(vec![2, 0])).await.unwrap().into_result();
+        let result = serde_json::to_value(result.unwrap()).unwrap();
+        assert_eq!(result["columns"], serde_json::json!([0, 2]));
+        assert_eq!(result["num_groups"], 5);
+        assert_eq!(result["num_requests"], 10);
+        assert_eq!(result["bytes"], 5 * 400);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+        assert_eq!(result["time_to_available"].as_array().unwrap().len(), 2);
+
+        let err = run(options(vec![3])).await.unwrap_err().to_string();
+        assert!(err.contains("only 3 columns"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn footers_are_read_before_the_pages() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 1100]))
+            .await
+            .unwrap();
+        let options = |footer_size| ColumnarOptions {
+            parallel_downloads: 2,
+            page_sizes: vec![100, 100],
+            footer_size: Some(footer_size),
+            ..ColumnarOptions::default()
+        };
+        let run = |options| {
+            columnar_read_test(
+                object_store.clone(),
+                Path::from("data"),
+                options,
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            )
+        };
+        let result = run(options(300)).await.unwrap().into_result();
+        let result = serde_json::to_value(result.unwrap()).unwrap();
+        // 800 bytes before the footer hold 4 groups.
+        assert_eq!(result["num_groups"], 4);
+        assert_eq!(result["bytes"], 800);
+        assert_eq!(result["footer"]["requests"], 1);
+        assert_eq!(result["footer"]["bytes"], 300);
+        assert_eq!(result["footer"]["latency"]["count"], 1);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+
+        let err = run(options(2000)).await.unwrap_err().to_string();
+        assert!(err.contains("smaller than the 2000 byte footer"), "{}", err);
+    }
 }
@@ -0,0 +1,255 @@
+//! Breaking a cold start down into connection setup and request time.
+//!
+//! The first requests of a run pay for DNS resolution, a TCP connect and a
+//! TLS handshake inside the HTTP client's pool, where they can't be timed
+//! separately. With `--connection-breakdown`, before the benchmark the
+//! endpoint named by the URI is resolved, connected to and handshaken with
+//! directly, each stage timed on its own. Then two `head` requests go through
+//! the store: the first on a cold pool and the second reusing its connection,
+//! so their difference is the setup cost as the client sees it.
+//!
+//! Results record the stages under `connection_breakdown`.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectStore};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls;
+use url::Url;
+
+use crate::experiment::Fields;
+
+/// How long to wait for any one stage before giving up on it.
+const STAGE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The host a store's requests are sent to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Endpoint {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+}
+
+impl Endpoint {
+    /// The endpoint `url` is served from, or `None` for local stores.
+    ///
+    /// S3 buckets are addressed virtual-host style in `AWS_REGION`, or
+    /// through `AWS_ENDPOINT` when it is set, as object_store does.
+    pub fn from_url(url: &Url) -> Option<Self> {
+        match url.scheme() {
+            "s3" | "s3a" => match std::env::var("AWS_ENDPOINT") {
+                Ok(endpoint) => Self::from_http(&Url::parse(&endpoint).ok()?),
+                Err(_) => {
+                    let region = std::env::var("AWS_REGION")
+                        .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+                        .unwrap_or_else(|_| "us-east-1".to_string());
+                    Some(Self {
+                        host: format!("{}.s3.{}.amazonaws.com", url.host_str()?, region),
+                        port: 443,
+                        tls: true,
+                    })
+                }
+            },
+            "gs" => Some(Self {
+                host: "storage.googleapis.com".to_string(),
+                port: 443,
+                tls: true,
+            }),
+            "http" | "https" => Self::from_http(url),
+            _ => None,
+        }
+    }
+
+    fn from_http(url: &Url) -> Option<Self> {
+        Some(Self {
+            host: url.host_str()?.to_string(),
+            port: url.port_or_known_default()?,
+            tls: url.scheme() == "https",
+        })
+    }
+}
+
+/// Time taken by each stage of reaching the endpoint, in microseconds.
+/// Stages after the first failure are not attempted.
+#[derive(Debug, Default)]
+pub struct ConnectionBreakdown {
+    pub endpoint: Option<Endpoint>,
+    pub addresses: usize,
+    pub dns_us: Option<u128>,
+    pub connect_us: Option<u128>,
+    pub tls_us: Option<u128>,
+    /// A `head` through the store on a cold connection pool
+    pub first_request_us: Option<u128>,
+    /// The same request again, reusing the pooled connection
+    pub warm_request_us: Option<u128>,
+    pub error: Option<String>,
+}
+
+impl ConnectionBreakdown {
+    /// Cold request time beyond the warm one: the connection setup the client paid.
+    pub fn setup_overhead_us(&self) -> Option<u128> {
+        Some(self.first_request_us?.saturating_sub(self.warm_request_us?))
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "host": self.endpoint.as_ref().map(|e| &e.host),
+            "port": self.endpoint.as_ref().map(|e| e.port),
+            "tls": self.endpoint.as_ref().map(|e| e.tls),
+            "addresses": self.addresses,
+            "dns_us": self.dns_us,
+            "connect_us": self.connect_us,
+            "tls_us": self.tls_us,
+            "first_request_us": self.first_request_us,
+            "warm_request_us": self.warm_request_us,
+            "setup_overhead_us": self.setup_overhead_us(),
+            "error": self.error,
+        })
+    }
+}
+
+/// Run `stage` with a timeout, returning its output and how long it took.
+async fn timed<T, E: std::fmt::Display>(
+    name: &str,
+    stage: impl std::future::Future<Output = Result<T, E>>,
+) -> Result<(T, u128), String> {
+    let start = Instant::now();
+    match tokio::time::timeout(STAGE_TIMEOUT, stage).await {
+        Ok(Ok(output)) => Ok((output, start.elapsed().as_micros())),
+        Ok(Err(err)) => Err(format!("{} failed: {}", name, err)),
+        Err(_) => Err(format!("{} timed out after {:?}", name, STAGE_TIMEOUT)),
+    }
+}
+
+fn tls_connector() -> tokio_rustls::TlsConnector {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|anchor| {
+        rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+            anchor.subject,
+            anchor.spki,
+            anchor.name_constraints,
+        )
+    }));
+    let config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    tokio_rustls::TlsConnector::from(Arc::new(config))
+}
+
+/// Time resolving, connecting to and handshaking with `endpoint`, filling
+/// in `breakdown` stage by stage.
+async fn probe_endpoint(
+    endpoint: &Endpoint,
+    breakdown: &mut ConnectionBreakdown,
+) -> Result<(), String> {
+    let (addresses, dns_us) = timed(
+        "resolving",
+        tokio::net::lookup_host((endpoint.host.as_str(), endpoint.port)),
+    )
+    .await?;
+    let addresses = addresses.collect::<Vec<_>>();
+    breakdown.addresses = addresses.len();
+    breakdown.dns_us = Some(dns_us);
+
+    let (tcp, connect_us) = timed("connecting", TcpStream::connect(&addresses[..])).await?;
+    breakdown.connect_us = Some(connect_us);
+
+    if endpoint.tls {
+        let name = rustls::ServerName::try_from(endpoint.host.as_str())
+            .map_err(|err| format!("invalid TLS server name: {}", err))?;
+        let (_, tls_us) = timed("TLS handshake", tls_connector().connect(name, tcp)).await?;
+        breakdown.tls_us = Some(tls_us);
+    }
+    Ok(())
+}
+
+/// Probe the endpoint behind `url`, then time a cold and a warm `head` of
+/// `location` through `object_store`. A missing object still completes a
+/// round trip, so it counts.
+pub async fn probe(
+    url: &Url,
+    object_store: &dyn ObjectStore,
+    location: &Path,
+) -> ConnectionBreakdown {
+    let mut breakdown = ConnectionBreakdown {
+        endpoint: Endpoint::from_url(url),
+        ..Default::default()
+    };
+    if let Some(endpoint) = breakdown.endpoint.clone() {
+        if let Err(err) = probe_endpoint(&endpoint, &mut breakdown).await {
+            breakdown.error = Some(err);
+            return breakdown;
+        }
+    }
+    let head = || async {
+        match object_store.head(location).await {
+            Ok(_) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(err),
+        }
+    };
+    match timed("first request", head()).await {
+        Ok(((), us)) => breakdown.first_request_us = Some(us),
+        Err(err) => {
+            breakdown.error = Some(err);
+            return breakdown;
+        }
+    }
+    match timed("warm request", head()).await {
+        Ok(((), us)) => breakdown.warm_request_us = Some(us),
+        Err(err) => breakdown.error = Some(err),
+    }
+    breakdown
+}
+
+/// Append the connection `breakdown` to the JSON object `result`.
+pub fn with_breakdown(result: &mut Fields, breakdown: &ConnectionBreakdown) {
+    result.insert("connection_breakdown".to_string(), breakdown.to_json());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn endpoint(url: &str) -> Option<Endpoint> {
+        Endpoint::from_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn endpoints_come_from_the_uri() {
+        assert_eq!(
+            endpoint("gs://bucket/data"),
+            Some(Endpoint {
+                host: "storage.googleapis.com".to_string(),
+                port: 443,
+                tls: true
+            })
+        );
+        assert_eq!(
+            endpoint("http://gateway.internal:8080/data"),
+            Some(Endpoint {
+                host: "gateway.internal".to_string(),
+                port: 8080,
+                tls: false
+            })
+        );
+        assert_eq!(
+            endpoint("https://example.com/data").map(|e| e.port),
+            Some(443)
+        );
+        assert_eq!(endpoint("file:///tmp/data"), None);
+        assert_eq!(endpoint("memory:///"), None);
+    }
+
+    #[tokio::test]
+    async fn local_stores_only_time_the_requests() {
+        let url = Url::parse("memory:///").unwrap();
+        let breakdown = probe(&url, &InMemory::new(), &Path::from("missing")).await;
+        assert_eq!(breakdown.error, None);
+        assert_eq!(breakdown.dns_us, None);
+        assert!(breakdown.first_request_us.is_some());
+        assert!(breakdown.setup_overhead_us().is_some());
+    }
+}
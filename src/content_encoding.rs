@@ -0,0 +1,556 @@
+//! Measuring transparent compression on HTTP stores.
+//!
+//! With `--accept-encoding`, an `http(s)://` location is read through one of
+//! two clients, chosen by [`EncodingStore`] for the current setting: an
+//! `HttpStore` sending `Accept-Encoding: identity`, or [`GzipClient`], which
+//! sends `Accept-Encoding: gzip` and decodes a body only when the response's
+//! `Content-Encoding` says the server compressed it. An object stored
+//! compressed, such as a `.gz` file, is passed on as it is under either
+//! setting. `compare` runs the command once per setting.
+//!
+//! A compressed body can only be decoded whole, so under gzip only reads of
+//! whole objects are supported; ranged reads fail with `NotSupported`, and
+//! `download --mode sequential` reads each object whole.
+//!
+//! Results record under `content_encoding` the bytes received on the wire and
+//! the bytes after decoding. A server asked for gzip that never compressed a
+//! response is reported as ignoring the header.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::io::Read;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use flate2::read::MultiGzDecoder;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::http::HttpBuilder;
+use object_store::path::Path;
+use object_store::{
+    ClientOptions, GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+use url::Url;
+
+use crate::experiment::Fields;
+
+const STORE: &str = "HTTP";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AcceptEncoding {
+    Gzip,
+    Identity,
+    /// Run once with each of the others
+    Compare,
+}
+
+impl AcceptEncoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            AcceptEncoding::Gzip => "gzip",
+            AcceptEncoding::Identity => "identity",
+            AcceptEncoding::Compare => "compare",
+        }
+    }
+
+    /// The settings a run is made with, in order.
+    pub fn settings(self) -> Vec<AcceptEncoding> {
+        match self {
+            AcceptEncoding::Compare => vec![AcceptEncoding::Identity, AcceptEncoding::Gzip],
+            setting => vec![setting],
+        }
+    }
+}
+
+/// Responses and bytes received under the current setting.
+#[derive(Debug, Default)]
+pub struct EncodingStatus {
+    /// Index into [`SETTINGS`] of the setting reads are made with
+    setting: AtomicUsize,
+    responses: AtomicU64,
+    compressed_responses: AtomicU64,
+    wire_bytes: AtomicU64,
+    logical_bytes: AtomicU64,
+}
+
+/// The settings [`EncodingStatus::setting`] indexes.
+const SETTINGS: [AcceptEncoding; 2] = [AcceptEncoding::Identity, AcceptEncoding::Gzip];
+
+impl EncodingStatus {
+    fn setting(&self) -> AcceptEncoding {
+        SETTINGS[self.setting.load(Ordering::SeqCst)]
+    }
+
+    /// Switch reads to `setting` and start counting afresh.
+    pub fn begin(&self, setting: AcceptEncoding) {
+        let index = SETTINGS
+            .iter()
+            .position(|s| *s == setting)
+            .expect("compare is not a single setting");
+        self.setting.store(index, Ordering::SeqCst);
+        for counter in [
+            &self.responses,
+            &self.compressed_responses,
+            &self.wire_bytes,
+            &self.logical_bytes,
+        ] {
+            counter.store(0, Ordering::SeqCst);
+        }
+    }
+
+    /// Whether the server was asked for gzip and never compressed a response
+    pub fn ignored(&self) -> bool {
+        self.setting() == AcceptEncoding::Gzip
+            && self.responses.load(Ordering::SeqCst) > 0
+            && self.compressed_responses.load(Ordering::SeqCst) == 0
+    }
+
+    /// Warn if the server ignored a request for gzip during this run.
+    pub fn check_ignored(&self) {
+        if self.ignored() {
+            eprintln!("warning: asked for gzip but the server sent every response uncompressed");
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let wire_bytes = self.wire_bytes.load(Ordering::SeqCst);
+        let logical_bytes = self.logical_bytes.load(Ordering::SeqCst);
+        serde_json::json!({
+            "accept_encoding": self.setting().name(),
+            "responses": self.responses.load(Ordering::SeqCst),
+            "compressed_responses": self.compressed_responses.load(Ordering::SeqCst),
+            "wire_bytes": wire_bytes,
+            "logical_bytes": logical_bytes,
+            "compression_ratio": (wire_bytes > 0).then(|| logical_bytes as f64 / wire_bytes as f64),
+            "ignored_by_server": self.ignored(),
+        })
+    }
+
+    /// Count a response of `wire` bytes, `logical` once decoded.
+    fn record(&self, wire: usize, logical: usize, compressed: bool) {
+        self.responses.fetch_add(1, Ordering::SeqCst);
+        if compressed {
+            self.compressed_responses.fetch_add(1, Ordering::SeqCst);
+        }
+        self.wire_bytes.fetch_add(wire as u64, Ordering::SeqCst);
+        self.logical_bytes
+            .fetch_add(logical as u64, Ordering::SeqCst);
+    }
+}
+
+/// Reads whole objects asking for `Accept-Encoding: gzip`, and decodes the
+/// bodies the server says it compressed.
+///
+/// object_store's HTTP client doesn't expose response headers, so this is a
+/// plain reqwest client; reqwest is built without its own gzip support and
+/// hands back the body as it came off the wire.
+#[derive(Debug)]
+pub struct GzipClient {
+    client: reqwest::Client,
+    origin: Url,
+}
+
+impl GzipClient {
+    /// A client for objects under `origin`.
+    pub fn new(origin: Url) -> Result<Self> {
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::ACCEPT_ENCODING,
+            http::HeaderValue::from_static("gzip"),
+        );
+        let client = reqwest::Client::builder()
+            .default_headers(headers)
+            .build()
+            .map_err(|err| object_store::Error::Generic {
+                store: STORE,
+                source: Box::new(err),
+            })?;
+        Ok(Self { client, origin })
+    }
+
+    fn url(&self, location: &Path) -> Url {
+        let mut url = self.origin.clone();
+        url.path_segments_mut()
+            .expect("an http(s) origin")
+            .pop_if_empty()
+            .extend(location.parts());
+        url
+    }
+
+    /// The whole object at `location`, decoded, counted in `status`.
+    async fn get(
+        &self,
+        location: &Path,
+        options: &GetOptions,
+        status: &EncodingStatus,
+    ) -> Result<Bytes> {
+        let generic = |err: reqwest::Error| object_store::Error::Generic {
+            store: STORE,
+            source: Box::new(err),
+        };
+        let mut request = self.client.get(self.url(location));
+        if let Some(etag) = &options.if_match {
+            request = request.header(http::header::IF_MATCH, etag);
+        }
+        if let Some(etag) = &options.if_none_match {
+            request = request.header(http::header::IF_NONE_MATCH, etag);
+        }
+        let http_date = |date: &chrono::DateTime<chrono::Utc>| {
+            date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+        };
+        if let Some(date) = &options.if_modified_since {
+            request = request.header(http::header::IF_MODIFIED_SINCE, http_date(date));
+        }
+        if let Some(date) = &options.if_unmodified_since {
+            request = request.header(http::header::IF_UNMODIFIED_SINCE, http_date(date));
+        }
+        let response = request.send().await.map_err(generic)?;
+        let path = location.to_string();
+        let status_code = response.status();
+        if !status_code.is_success() {
+            let source = format!("{} answered {}", self.url(location), status_code).into();
+            return Err(match status_code {
+                reqwest::StatusCode::NOT_FOUND => object_store::Error::NotFound { path, source },
+                reqwest::StatusCode::NOT_MODIFIED => {
+                    object_store::Error::NotModified { path, source }
+                }
+                reqwest::StatusCode::PRECONDITION_FAILED => {
+                    object_store::Error::Precondition { path, source }
+                }
+                _ => object_store::Error::Generic {
+                    store: STORE,
+                    source,
+                },
+            });
+        }
+        let encoding = response
+            .headers()
+            .get(http::header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.trim().to_ascii_lowercase());
+        let body = response.bytes().await.map_err(generic)?;
+        match encoding.as_deref() {
+            None | Some("identity") => {
+                status.record(body.len(), body.len(), false);
+                Ok(body)
+            }
+            Some("gzip" | "x-gzip") => {
+                let mut decoded = Vec::new();
+                MultiGzDecoder::new(body.as_ref())
+                    .read_to_end(&mut decoded)
+                    .map_err(|err| object_store::Error::Generic {
+                        store: STORE,
+                        source: format!("decoding the gzip body of {}: {}", path, err).into(),
+                    })?;
+                status.record(body.len(), decoded.len(), true);
+                Ok(Bytes::from(decoded))
+            }
+            Some(other) => Err(object_store::Error::Generic {
+                store: STORE,
+                source: format!("{} came with Content-Encoding {:?}", path, other).into(),
+            }),
+        }
+    }
+}
+
+/// The error for a ranged read under gzip.
+fn ranged_gzip(location: &Path) -> object_store::Error {
+    object_store::Error::NotSupported {
+        source: format!(
+            "ranged reads of {} can't be decoded under Accept-Encoding: gzip; read whole objects or use identity",
+            location
+        )
+        .into(),
+    }
+}
+
+/// Append the bytes `status` counted on the wire and after decoding to the
+/// JSON object `result`.
+pub fn with_status(result: &mut Fields, status: &EncodingStatus) {
+    result.insert("content_encoding".to_string(), status.to_json());
+}
+
+/// An [`ObjectStore`] that reads through the client for the current
+/// `Accept-Encoding` setting, decoding compressed bodies.
+pub struct EncodingStore {
+    identity: Arc<dyn ObjectStore>,
+    gzip: GzipClient,
+    status: Arc<EncodingStatus>,
+}
+
+impl EncodingStore {
+    /// HTTP clients for the origin of `url`, one per setting.
+    pub fn http(url: &Url) -> Result<Self> {
+        let origin = &url[..url::Position::BeforePath];
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            http::header::ACCEPT_ENCODING,
+            http::HeaderValue::from_static("identity"),
+        );
+        let identity = HttpBuilder::new()
+            .with_url(origin)
+            .with_client_options(
+                ClientOptions::new()
+                    .with_default_headers(headers)
+                    .with_allow_http(url.scheme() == "http"),
+            )
+            .build()?;
+        let origin = Url::parse(origin).map_err(|err| object_store::Error::Generic {
+            store: STORE,
+            source: Box::new(err),
+        })?;
+        Ok(Self::new(Arc::new(identity), GzipClient::new(origin)?))
+    }
+
+    /// Read through `identity` or `gzip`, the clients for each setting.
+    pub fn new(identity: Arc<dyn ObjectStore>, gzip: GzipClient) -> Self {
+        Self {
+            identity,
+            gzip,
+            status: Default::default(),
+        }
+    }
+
+    fn gzip(&self) -> bool {
+        self.status.setting() == AcceptEncoding::Gzip
+    }
+
+    /// Count an identity response of `body`.
+    fn identity_body(&self, body: Bytes) -> Bytes {
+        self.status.record(body.len(), body.len(), false);
+        body
+    }
+
+    pub fn status(&self) -> Arc<EncodingStatus> {
+        self.status.clone()
+    }
+}
+
+impl Display for EncodingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EncodingStore({})", self.identity)
+    }
+}
+
+impl Debug for EncodingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncodingStore")
+            .field("identity", &self.identity)
+            .field("gzip", &self.gzip.origin)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for EncodingStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.identity.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.identity.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.identity.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        // Bodies are collected before they are handed on, so the bytes after
+        // decoding are known.
+        let body = if !self.gzip() {
+            let body = self
+                .identity
+                .get_opts(location, options)
+                .await?
+                .bytes()
+                .await?;
+            self.identity_body(body)
+        } else if options.range.is_some() {
+            return Err(ranged_gzip(location));
+        } else {
+            self.gzip.get(location, &options, &self.status).await?
+        };
+        Ok(GetResult::Stream(
+            futures::stream::once(async { Ok(body) }).boxed(),
+        ))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        if self.gzip() {
+            return Err(ranged_gzip(location));
+        }
+        let body = self.identity.get_range(location, range).await?;
+        Ok(self.identity_body(body))
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        if self.gzip() {
+            return Err(ranged_gzip(location));
+        }
+        let bodies = self.identity.get_ranges(location, ranges).await?;
+        Ok(bodies
+            .into_iter()
+            .map(|body| self.identity_body(body))
+            .collect())
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.identity.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.identity.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.identity.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.identity.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.identity.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.identity.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use object_store::memory::InMemory;
+    use std::collections::HashMap;
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn gzip(data: &[u8]) -> Bytes {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        Bytes::from(encoder.finish().unwrap())
+    }
+
+    /// Serve each path's body, with a `Content-Encoding: gzip` header where
+    /// the flag is set, over plain HTTP on a local port.
+    async fn serve(objects: HashMap<&'static str, (Bytes, bool)>) -> Url {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let origin = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                while !request.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0; 1];
+                    if socket.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    request.push(byte[0]);
+                }
+                let request = String::from_utf8_lossy(&request).to_ascii_lowercase();
+                assert!(request.contains("accept-encoding: gzip"), "{}", request);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let (head, body) = match objects.get(path.trim_start_matches('/')) {
+                    Some((body, compressed)) => {
+                        let encoding = if *compressed {
+                            "Content-Encoding: gzip\r\n"
+                        } else {
+                            ""
+                        };
+                        (format!("200 OK\r\n{}", encoding), body.clone())
+                    }
+                    None => ("404 Not Found\r\n".to_string(), Bytes::new()),
+                };
+                let response = format!(
+                    "HTTP/1.1 {}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    head,
+                    body.len()
+                );
+                socket.write_all(response.as_bytes()).await.unwrap();
+                socket.write_all(&body).await.unwrap();
+            }
+        });
+        origin
+    }
+
+    #[tokio::test]
+    async fn decodes_only_bodies_the_server_compressed() {
+        let data = Bytes::from(vec![b'a'; 64 * 1024]);
+        let compressed = gzip(&data);
+        // An object stored compressed, served as it is.
+        let stored = gzip(b"already compressed");
+        let origin = serve(HashMap::from([
+            ("data.bin", (compressed.clone(), true)),
+            ("stored.gz", (stored.clone(), false)),
+        ]))
+        .await;
+
+        let identity = Arc::new(InMemory::new());
+        identity
+            .put(&Path::from("stored.gz"), stored.clone())
+            .await
+            .unwrap();
+        let store = EncodingStore::new(identity, GzipClient::new(origin).unwrap());
+        let status = store.status();
+
+        // Under identity nothing is decoded, even with the gzip magic bytes.
+        status.begin(AcceptEncoding::Identity);
+        let location = Path::from("stored.gz");
+        assert_eq!(store.get_range(&location, 0..2).await.unwrap(), stored[..2]);
+        assert_eq!(
+            store.get(&location).await.unwrap().bytes().await.unwrap(),
+            stored
+        );
+        assert_eq!(status.to_json()["compressed_responses"], 0);
+
+        status.begin(AcceptEncoding::Gzip);
+        let location = Path::from("data.bin");
+        assert_eq!(
+            store.get(&location).await.unwrap().bytes().await.unwrap(),
+            data
+        );
+        let json = status.to_json();
+        assert_eq!(json["wire_bytes"], compressed.len());
+        assert_eq!(json["logical_bytes"], data.len());
+        assert_eq!(json["compressed_responses"], 1);
+        assert!(!status.ignored());
+        let err = store.get_range(&location, 10..100).await.unwrap_err();
+        assert!(
+            matches!(err, object_store::Error::NotSupported { .. }),
+            "{}",
+            err
+        );
+
+        // A body without Content-Encoding is left alone under gzip too.
+        status.begin(AcceptEncoding::Gzip);
+        let body = store
+            .get(&Path::from("stored.gz"))
+            .await
+            .unwrap()
+            .bytes()
+            .await
+            .unwrap();
+        assert_eq!(body, stored);
+        assert!(status.ignored());
+
+        let err = store.get(&Path::from("missing.bin")).await.unwrap_err();
+        assert!(
+            matches!(err, object_store::Error::NotFound { .. }),
+            "{}",
+            err
+        );
+    }
+}
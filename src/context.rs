@@ -0,0 +1,147 @@
+//! What every result of a process's runs is reported with, and where to.
+//!
+//! A [`RunContext`] holds the settings and counters a result is decorated
+//! with, such as the request counts, the store's defaults and the run's
+//! metadata, along with its destination: stdout, an `--output` file or an
+//! experiment directory. The binary fills one from its flags and hands it to
+//! a [`RunControl`](crate::control::RunControl), where each benchmark finds it.
+//! The default reports nothing beyond the result itself and prints it to
+//! stdout.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::calibration::Calibration;
+use crate::connection::ConnectionBreakdown;
+use crate::content_encoding::EncodingStatus;
+use crate::cost::PriceModel;
+use crate::counting::RequestCounts;
+use crate::coverage::Coverage;
+use crate::direct_io::DirectIoStatus;
+use crate::experiment::{Experiment, Fields};
+use crate::instrumented::OpStats;
+use crate::iterate::Iteration;
+use crate::partitions::Partitions;
+use crate::pool::ClientConfig;
+use crate::schedule::ScheduledRun;
+use crate::simulate::Simulation;
+use crate::size_buckets::{SizeBuckets, DEFAULT_EDGES};
+use crate::store_defaults::StoreFamily;
+use crate::wire::WireModel;
+use crate::worker::RunInfo;
+use crate::ListingOptions;
+
+/// The settings, counters and destination of one process's results.
+#[derive(Debug, Default)]
+pub struct RunContext {
+    /// Which of the listed objects runs read
+    pub listing: ListingOptions,
+    /// Append results to this file instead of printing them
+    pub output: Option<PathBuf>,
+    /// Also record results in this experiment directory
+    pub experiment: Option<Experiment>,
+    /// Drop results instead of emitting them, for internal runs whose
+    /// results aren't meaningful on their own
+    pub quiet: bool,
+    /// The kind of store, whose defaults results report
+    pub store: Option<StoreFamily>,
+    /// This process's place in a sharded run
+    pub worker: Option<RunInfo>,
+    /// The flags that weren't left to their defaults
+    pub config: Option<serde_json::Value>,
+    pub calibration: Option<Calibration>,
+    pub price_model: Option<PriceModel>,
+    pub wire: Option<WireModel>,
+    pub simulation: Option<Simulation>,
+    pub direct_io: Option<Arc<DirectIoStatus>>,
+    pub encoding: Option<Arc<EncodingStatus>>,
+    pub counts: Option<Arc<RequestCounts>>,
+    pub coverage: Option<Arc<Coverage>>,
+    pub partitions: Option<Arc<Partitions>>,
+    pub ops: Option<Arc<OpStats>>,
+    pub connection: Option<ConnectionBreakdown>,
+    /// Fail runs whose received bytes disagree with their plan
+    pub strict_accounting: bool,
+    /// Size class edges, [`DEFAULT_EDGES`] when `None`
+    pub size_buckets: Option<Vec<u64>>,
+    /// Chart sweeps even when stdout isn't a terminal
+    pub sparkline: bool,
+    /// The settings of the running client, when any were given
+    pub(crate) client: Mutex<Option<ClientConfig>>,
+    /// The scheduled run in progress
+    pub(crate) scheduled: Mutex<Option<ScheduledRun>>,
+    /// The iteration running, while repeating
+    pub(crate) iteration: Mutex<Option<Iteration>>,
+    /// The most recent result, until taken
+    pub(crate) last_result: Mutex<Option<String>>,
+}
+
+impl RunContext {
+    /// Empty size classes at the configured edges.
+    pub fn size_buckets(&self) -> SizeBuckets {
+        SizeBuckets::with_edges(self.size_buckets.as_deref().unwrap_or(&DEFAULT_EDGES))
+    }
+
+    /// Add the fields reported for every result: the schema version, the
+    /// process-wide settings and counters, and the run's metadata.
+    pub(crate) fn decorate(&self, result: &mut Fields) {
+        crate::report::with_schema_version(result);
+        if let Some(cache) = &self.listing.cache {
+            crate::listing_cache::with_status(result, cache);
+        }
+        if let Some(family) = self.store {
+            crate::store_defaults::with_store(result, family);
+        }
+        if let Some(sampling) = &self.listing.sampling {
+            crate::sampling::with_sample(result, sampling);
+        }
+        if let Some(simulation) = &self.simulation {
+            crate::simulate::with_simulation(result, simulation);
+        }
+        if let Some(status) = &self.direct_io {
+            crate::direct_io::with_status(result, status);
+        }
+        if let Some(counts) = &self.counts {
+            crate::counting::with_counts(
+                result,
+                counts,
+                self.price_model.as_ref(),
+                self.wire.as_ref(),
+            );
+        }
+        if let Some(stats) = &self.ops {
+            crate::instrumented::with_ops(result, stats);
+        }
+        if let Some(coverage) = &self.coverage {
+            let map_file = self.experiment.as_ref().map(Experiment::coverage_path);
+            crate::coverage::with_coverage(result, coverage, map_file);
+        }
+        if let Some(partitions) = &self.partitions {
+            crate::partitions::with_partitions(result, partitions);
+        }
+        if let Some(status) = &self.encoding {
+            crate::content_encoding::with_status(result, status);
+        }
+        if let Some(config) = &*self.client.lock().unwrap() {
+            crate::pool::with_client(result, config);
+        }
+        if let Some(breakdown) = &self.connection {
+            crate::connection::with_breakdown(result, breakdown);
+        }
+        if let Some(calibration) = &self.calibration {
+            crate::calibration::with_adjustment(result, calibration);
+        }
+        if let Some(run) = &*self.scheduled.lock().unwrap() {
+            crate::schedule::with_run(result, run);
+        }
+        if let Some(iteration) = &*self.iteration.lock().unwrap() {
+            crate::iterate::with_iteration(result, iteration);
+        }
+        if let Some(config) = &self.config {
+            crate::user_defaults::with_config(result, config);
+        }
+        if let Some(run) = &self.worker {
+            crate::worker::with_metadata(result, run);
+        }
+    }
+}
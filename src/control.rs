@@ -0,0 +1,512 @@
+//! Live run state shared between the benchmark driver and anything observing
+//! or steering it.
+//!
+//! The counters are plain atomics updated on the request path, so observers
+//! (the interactive status line) never take a lock the benchmark waits on.
+
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use object_store::path::Path;
+use tokio::sync::Notify;
+
+use crate::context::RunContext;
+use crate::plan::PlanRecorder;
+use crate::ListingOptions;
+
+#[derive(Debug)]
+struct Inner {
+    start: Instant,
+    bytes: AtomicU64,
+    requests: AtomicU64,
+    in_flight: AtomicU64,
+    /// Most requests in flight at once since [`RunControl::take_peak_in_flight`]
+    peak_in_flight: AtomicU64,
+    errors: AtomicU64,
+    /// Bytes the run plans to transfer, or `u64::MAX` if not known yet
+    bytes_total: AtomicU64,
+    paused: AtomicBool,
+    paused_since: Mutex<Option<Instant>>,
+    paused_us: AtomicU64,
+    shutdown: AtomicBool,
+    stopped: Notify,
+    /// Set once the requests still in flight after a shutdown are given up on
+    abandoned: AtomicBool,
+    abandon: Notify,
+    resumed: Notify,
+    plan: OnceLock<PlanRecorder>,
+    context: RunContext,
+    /// Concurrency at which a run leaves ramp-up, or 0 when not tracking phases
+    target_concurrency: AtomicU64,
+    /// Microseconds from `start` to phase transitions, `u64::MAX` until seen
+    first_issued_us: AtomicU64,
+    ramped_up_us: AtomicU64,
+    /// Microseconds from `start` to the latest request issued
+    last_issued_us: AtomicU64,
+}
+
+/// Counters and pause/shutdown switches for one run.
+#[derive(Debug, Clone)]
+pub struct RunControl {
+    inner: Arc<Inner>,
+}
+
+/// When a run's phases began, relative to the start of the run.
+///
+/// Ramp-up lasts from the first request until `target` requests are first in
+/// flight at once, steady state from then until the last request is issued,
+/// and the drain from then until the run ends. A run that never reaches its
+/// target concurrency has no steady state.
+#[derive(Debug, Clone, Copy)]
+pub struct PhaseBoundaries {
+    pub first_issued: Duration,
+    pub ramped_up: Option<Duration>,
+    pub last_issued: Duration,
+}
+
+/// Point-in-time view of a run's counters.
+#[derive(Debug, Clone, Copy)]
+pub struct Snapshot {
+    pub elapsed: Duration,
+    pub bytes: u64,
+    pub requests: u64,
+    pub in_flight: u64,
+    pub errors: u64,
+    pub paused: Duration,
+}
+
+impl Default for RunControl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RunControl {
+    pub fn new() -> Self {
+        Self::with_context(RunContext::default())
+    }
+
+    /// A run that benchmarks the objects `listing` narrows a location to.
+    pub fn with_listing(listing: ListingOptions) -> Self {
+        Self::with_context(RunContext {
+            listing,
+            ..RunContext::default()
+        })
+    }
+
+    /// A run whose results are reported as `context` says.
+    pub fn with_context(context: RunContext) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                start: Instant::now(),
+                bytes: AtomicU64::new(0),
+                requests: AtomicU64::new(0),
+                in_flight: AtomicU64::new(0),
+                peak_in_flight: AtomicU64::new(0),
+                errors: AtomicU64::new(0),
+                bytes_total: AtomicU64::new(u64::MAX),
+                paused: AtomicBool::new(false),
+                paused_since: Mutex::new(None),
+                paused_us: AtomicU64::new(0),
+                shutdown: AtomicBool::new(false),
+                stopped: Notify::new(),
+                abandoned: AtomicBool::new(false),
+                abandon: Notify::new(),
+                resumed: Notify::new(),
+                plan: OnceLock::new(),
+                context,
+                target_concurrency: AtomicU64::new(0),
+                first_issued_us: AtomicU64::new(u64::MAX),
+                ramped_up_us: AtomicU64::new(u64::MAX),
+                last_issued_us: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Called before issuing a request: waits while paused. Returns false if
+    /// the run is shutting down and the request should not be issued.
+    pub async fn request_started(&self) -> bool {
+        while self.inner.paused.load(Ordering::SeqCst) {
+            let resumed = self.inner.resumed.notified();
+            if !self.inner.paused.load(Ordering::SeqCst) {
+                break;
+            }
+            resumed.await;
+        }
+        if self.is_shutdown() {
+            return false;
+        }
+        let in_flight = self.inner.in_flight.fetch_add(1, Ordering::Relaxed) + 1;
+        self.inner
+            .peak_in_flight
+            .fetch_max(in_flight, Ordering::Relaxed);
+        self.note_issued(in_flight);
+        true
+    }
+
+    /// The most requests in flight at once since the last call.
+    pub fn take_peak_in_flight(&self) -> u64 {
+        self.inner.peak_in_flight.swap(0, Ordering::Relaxed)
+    }
+
+    /// Move the phase markers for a request just issued with `in_flight`
+    /// requests now outstanding.
+    fn note_issued(&self, in_flight: u64) {
+        let target = self.inner.target_concurrency.load(Ordering::Relaxed);
+        if target == 0 {
+            return;
+        }
+        let now = self.inner.start.elapsed().as_micros() as u64;
+        let unset = |marker: &AtomicU64| {
+            let _ = marker.compare_exchange(u64::MAX, now, Ordering::Relaxed, Ordering::Relaxed);
+        };
+        unset(&self.inner.first_issued_us);
+        if in_flight >= target {
+            unset(&self.inner.ramped_up_us);
+        }
+        self.inner.last_issued_us.fetch_max(now, Ordering::Relaxed);
+    }
+
+    /// Start tracking phase boundaries afresh for a run that aims to keep
+    /// `target` requests in flight.
+    pub fn track_phases(&self, target: usize) {
+        self.inner
+            .first_issued_us
+            .store(u64::MAX, Ordering::Relaxed);
+        self.inner.ramped_up_us.store(u64::MAX, Ordering::Relaxed);
+        self.inner.last_issued_us.store(0, Ordering::Relaxed);
+        self.inner
+            .target_concurrency
+            .store(target as u64, Ordering::Relaxed);
+    }
+
+    /// Phase boundaries since [`RunControl::track_phases`], relative to
+    /// `run_start`, or `None` if no request was issued.
+    pub fn phases(&self, run_start: Instant) -> Option<PhaseBoundaries> {
+        let offset = run_start.saturating_duration_since(self.inner.start);
+        let marker = |marker: &AtomicU64| match marker.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            us => Some(Duration::from_micros(us).saturating_sub(offset)),
+        };
+        let last_issued = Duration::from_micros(self.inner.last_issued_us.load(Ordering::Relaxed));
+        Some(PhaseBoundaries {
+            first_issued: marker(&self.inner.first_issued_us)?,
+            ramped_up: marker(&self.inner.ramped_up_us),
+            last_issued: last_issued.saturating_sub(offset),
+        })
+    }
+
+    pub fn request_finished(&self, bytes: usize) {
+        self.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// A transfer made without [`Self::request_started`], such as one part
+    /// of an upload, counted toward progress.
+    pub fn transferred(&self, bytes: usize) {
+        self.inner.requests.fetch_add(1, Ordering::Relaxed);
+        self.inner.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub fn request_failed(&self) {
+        self.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.inner.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A request dropped at its deadline, which is neither a success nor
+    /// an error.
+    pub fn request_expired(&self) {
+        self.inner.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// How this run narrows the listings it benchmarks.
+    pub fn listing(&self) -> &ListingOptions {
+        &self.inner.context.listing
+    }
+
+    /// What this run's results are reported with, and where to.
+    pub fn context(&self) -> &RunContext {
+        &self.inner.context
+    }
+
+    /// Report a benchmark result: print it to stdout or append it to the
+    /// `--output` file, with the fields every result carries, and record it
+    /// in the experiment directory if one is in use. Inside
+    /// [`capture`](crate::experiment::capture) it is only collected.
+    pub fn emit(&self, result: serde_json::Value) {
+        crate::experiment::emit(&self.inner.context, result);
+    }
+
+    /// The result this run most recently emitted, if it has emitted one
+    /// since the last call.
+    pub fn take_last_result(&self) -> Option<String> {
+        crate::experiment::take_last_result(&self.inner.context)
+    }
+
+    /// Record every request issued from now on into `recorder`.
+    pub fn set_plan_recorder(&self, recorder: PlanRecorder) {
+        let _ = self.inner.plan.set(recorder);
+    }
+
+    /// Note an issued request in the plan, if one is being recorded.
+    pub fn record(&self, op: &str, location: &Path, range: Option<&Range<usize>>) {
+        if let Some(plan) = self.inner.plan.get() {
+            plan.record(op, location, range);
+        }
+    }
+
+    pub fn flush_plan(&self) -> std::io::Result<()> {
+        match self.inner.plan.get() {
+            Some(plan) => plan.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Record how many bytes the benchmark plans to transfer.
+    pub fn set_bytes_total(&self, bytes: u64) {
+        self.inner.bytes_total.store(bytes, Ordering::Relaxed);
+    }
+
+    pub fn bytes_total(&self) -> Option<u64> {
+        match self.inner.bytes_total.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            total => Some(total),
+        }
+    }
+
+    /// Stop issuing new requests. In-flight requests are allowed to finish.
+    pub fn pause(&self) {
+        if !self.inner.paused.swap(true, Ordering::SeqCst) {
+            *self.inner.paused_since.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.inner.paused.swap(false, Ordering::SeqCst) {
+            if let Some(since) = self.inner.paused_since.lock().unwrap().take() {
+                self.inner
+                    .paused_us
+                    .fetch_add(since.elapsed().as_micros() as u64, Ordering::SeqCst);
+            }
+            self.inner.resumed.notify_waiters();
+        }
+    }
+
+    /// Ask the benchmark to stop issuing requests and report what it has.
+    pub fn shutdown(&self) {
+        self.inner.shutdown.store(true, Ordering::SeqCst);
+        self.inner.stopped.notify_waiters();
+        self.resume();
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the run is shut down.
+    pub async fn stopped(&self) {
+        wait_for(&self.inner.shutdown, &self.inner.stopped).await;
+    }
+
+    /// Give up on the requests still in flight, so the benchmark reports
+    /// without them.
+    pub fn abandon(&self) {
+        self.inner.abandoned.store(true, Ordering::SeqCst);
+        self.inner.abandon.notify_waiters();
+    }
+
+    /// Resolves once the run's requests in flight are abandoned. Benchmarks
+    /// stop collecting results when it does, dropping those requests.
+    pub async fn abandoned(&self) {
+        wait_for(&self.inner.abandoned, &self.inner.abandon).await;
+    }
+
+    /// Total time spent paused, including a pause that is still ongoing.
+    pub fn paused(&self) -> Duration {
+        let mut paused = Duration::from_micros(self.inner.paused_us.load(Ordering::SeqCst));
+        if let Some(since) = *self.inner.paused_since.lock().unwrap() {
+            paused += since.elapsed();
+        }
+        paused
+    }
+
+    /// Start counting paused time from zero, for a new iteration of a run.
+    pub fn reset_paused(&self) {
+        let mut since = self.inner.paused_since.lock().unwrap();
+        self.inner.paused_us.store(0, Ordering::SeqCst);
+        if since.is_some() {
+            *since = Some(Instant::now());
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            elapsed: self.inner.start.elapsed(),
+            bytes: self.inner.bytes.load(Ordering::Relaxed),
+            requests: self.inner.requests.load(Ordering::Relaxed),
+            in_flight: self.inner.in_flight.load(Ordering::Relaxed),
+            errors: self.inner.errors.load(Ordering::Relaxed),
+            paused: self.paused(),
+        }
+    }
+}
+
+/// The terminal mode from before [`spawn_key_listener`] changed it, until it
+/// is restored.
+#[cfg(unix)]
+static ORIGINAL_TERMINAL: Mutex<Option<libc::termios>> = Mutex::new(None);
+
+/// Put the terminal back the way [`spawn_key_listener`] found it, if it
+/// changed it. Call this before `std::process::exit`, which skips the
+/// [`TerminalGuard`] that would otherwise do it.
+pub fn restore_terminal() {
+    #[cfg(unix)]
+    if let Some(original) = ORIGINAL_TERMINAL.lock().unwrap().take() {
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &original);
+        }
+    }
+}
+
+/// Restores the terminal mode when dropped.
+pub struct TerminalGuard {
+    _restores: (),
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Exit at once on a second Ctrl-C, with the terminal restored.
+pub fn exit_interrupted() -> ! {
+    restore_terminal();
+    std::process::exit(130)
+}
+
+/// Wait until `flag` is set, woken by `notify`.
+async fn wait_for(flag: &AtomicBool, notify: &Notify) {
+    // A `Notified` created before the check is woken by any later notify.
+    let notified = notify.notified();
+    if flag.load(Ordering::SeqCst) {
+        return;
+    }
+    notified.await;
+}
+
+/// On Ctrl-C, shut `control` down so the benchmark stops issuing requests and
+/// reports what it has, marked `interrupted`. Requests still in flight after
+/// `grace` are abandoned. A second Ctrl-C exits at once.
+pub fn spawn_interrupt_handler(control: RunControl, grace: Duration) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        eprintln!(
+            "interrupted: waiting up to {:?} for requests in flight; press Ctrl-C again to exit now",
+            grace
+        );
+        control.shutdown();
+        tokio::select! {
+            _ = tokio::time::sleep(grace) => {
+                if control.snapshot().in_flight > 0 {
+                    eprintln!("interrupted: abandoning the requests still in flight");
+                }
+                control.abandon();
+            }
+            _ = tokio::signal::ctrl_c() => exit_interrupted(),
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            exit_interrupted();
+        }
+    });
+}
+
+/// Listen for single keypresses on stdin and steer `control` with them:
+///
+/// * `s`: print a status snapshot to stderr
+/// * `p`: pause issuing new requests, letting in-flight ones drain
+/// * `r`: resume
+/// * `q`: stop issuing requests and report partial results
+///
+/// Does nothing (returns `None`) unless stdin is a terminal, so it is inert
+/// under CI and when input is piped.
+pub fn spawn_key_listener(control: RunControl) -> Option<TerminalGuard> {
+    #[cfg(unix)]
+    {
+        use std::io::Read;
+
+        if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+            return None;
+        }
+        let guard = unsafe {
+            let mut original = std::mem::zeroed::<libc::termios>();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return None;
+            }
+            let mut raw = original;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            *ORIGINAL_TERMINAL.lock().unwrap() = Some(original);
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw);
+            TerminalGuard { _restores: () }
+        };
+        eprintln!("interactive: [s]tatus [p]ause [r]esume [q]uit");
+
+        std::thread::spawn(move || {
+            let mut stdin = std::io::stdin();
+            let mut key = [0u8; 1];
+            let mut last = control.snapshot();
+            while stdin.read_exact(&mut key).is_ok() {
+                match key[0] {
+                    b's' => {
+                        let now = control.snapshot();
+                        print_status(&now, &last);
+                        last = now;
+                    }
+                    b'p' => {
+                        control.pause();
+                        eprintln!("paused; in-flight requests will drain");
+                    }
+                    b'r' => {
+                        control.resume();
+                        eprintln!("resumed");
+                    }
+                    b'q' => {
+                        eprintln!("stopping; waiting for in-flight requests");
+                        control.shutdown();
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        });
+        Some(guard)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = control;
+        eprintln!("--interactive is only supported on unix terminals; ignoring");
+        None
+    }
+}
+
+fn print_status(now: &Snapshot, last: &Snapshot) {
+    let interval = (now.elapsed - last.elapsed).as_secs_f64();
+    let current_mbps = (now.bytes - last.bytes) as f64 / 1024.0 / 1024.0 / interval.max(1e-9);
+    eprintln!(
+        "elapsed {:.1}s  bytes {}  current {:.1} MB/s  requests {}  in-flight {}  errors {}  paused {:.1}s",
+        now.elapsed.as_secs_f64(),
+        now.bytes,
+        current_mbps,
+        now.requests,
+        now.in_flight,
+        now.errors,
+        now.paused.as_secs_f64(),
+    );
+}
@@ -0,0 +1,392 @@
+//! Estimating what a run cost from the requests it sent.
+//!
+//! `--price-model path.toml` describes a backend's prices:
+//!
+//! ```toml
+//! currency = "USD"
+//!
+//! # Price per 1000 requests of each class; classes left out are free.
+//! [requests]
+//! get = 0.0004
+//! head = 0.0004
+//! put = 0.005
+//! list = 0.005
+//! copy = 0.005
+//!
+//! # Price per GB (2^30 bytes) read. Tiers apply in order, each up to
+//! # `up_to_gb` in total; the last one has no limit. `per_gb = 0.09` is
+//! # shorthand for a single tier, and `same_region = true` makes reads free.
+//! [egress]
+//! tiers = [
+//!     { up_to_gb = 100, per_gb = 0.0 },
+//!     { up_to_gb = 10240, per_gb = 0.09 },
+//!     { per_gb = 0.085 },
+//! ]
+//! ```
+//!
+//! Without an `[egress]` table reads are free. Prices vary between regions
+//! and change over time, so no model is built in.
+
+use std::collections::BTreeMap;
+
+use crate::counting::{Operation, RequestTotals};
+use crate::error::Error;
+
+const GB: f64 = (1u64 << 30) as f64;
+
+/// Prices for each request class and for bytes read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PriceModel {
+    pub currency: String,
+    /// Price per 1000 requests
+    pub per_1k_requests: BTreeMap<Operation, f64>,
+    pub egress: Egress,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Egress {
+    /// Reads from the same region, which aren't billed
+    SameRegion,
+    /// Price per GB within each tier, in order
+    Tiered(Vec<EgressTier>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EgressTier {
+    /// Total GB read at which the next tier starts; `None` for the last tier
+    pub up_to_gb: Option<f64>,
+    pub per_gb: f64,
+}
+
+/// The cost of one run's requests and reads.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CostEstimate {
+    pub currency: String,
+    /// Count and cost of each request class with a price
+    pub requests: Vec<(Operation, u64, f64)>,
+    pub egress_gb: f64,
+    pub egress: f64,
+    pub same_region: bool,
+}
+
+impl CostEstimate {
+    pub fn total(&self) -> f64 {
+        self.requests.iter().map(|(_, _, cost)| cost).sum::<f64>() + self.egress
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let requests = self
+            .requests
+            .iter()
+            .map(|(op, count, cost)| {
+                (
+                    op.name().to_string(),
+                    serde_json::json!({"count": count, "cost": cost}),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::json!({
+            "currency": self.currency,
+            "requests": requests,
+            "egress": {"gb": self.egress_gb, "same_region": self.same_region, "cost": self.egress},
+            "total": self.total(),
+        })
+    }
+}
+
+impl PriceModel {
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        Ok(Self::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))?)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, String> {
+        let table: toml::Table = contents.parse().map_err(|err| format!("{}", err))?;
+        for key in table.keys() {
+            if !["currency", "requests", "egress"].contains(&key.as_str()) {
+                return Err(format!(
+                    "unknown key {:?}; expected currency, requests or egress",
+                    key
+                ));
+            }
+        }
+        let currency = match table.get("currency") {
+            None => "USD".to_string(),
+            Some(toml::Value::String(currency)) => currency.clone(),
+            Some(value) => return Err(format!("currency must be a string, got {}", value)),
+        };
+
+        let mut per_1k_requests = BTreeMap::new();
+        let requests = match table.get("requests") {
+            None => toml::Table::new(),
+            Some(toml::Value::Table(requests)) => requests.clone(),
+            Some(value) => return Err(format!("requests must be a table, got {}", value)),
+        };
+        for (name, price) in &requests {
+            let op = Operation::from_name(name).ok_or_else(|| {
+                format!(
+                    "unknown request class {:?}; expected one of {}",
+                    name,
+                    Operation::ALL.map(Operation::name).join(", ")
+                )
+            })?;
+            per_1k_requests.insert(op, non_negative(&format!("requests.{}", name), price)?);
+        }
+
+        let egress = match table.get("egress") {
+            None => Egress::Tiered(Vec::new()),
+            Some(toml::Value::Table(egress)) => parse_egress(egress)?,
+            Some(value) => return Err(format!("egress must be a table, got {}", value)),
+        };
+        Ok(Self {
+            currency,
+            per_1k_requests,
+            egress,
+        })
+    }
+
+    pub fn estimate(&self, totals: &RequestTotals) -> CostEstimate {
+        let requests = self
+            .per_1k_requests
+            .iter()
+            .map(|(op, price)| {
+                let count = totals.requests(*op);
+                (*op, count, count as f64 / 1000.0 * price)
+            })
+            .collect();
+        let egress_gb = totals.bytes_read as f64 / GB;
+        let egress = match &self.egress {
+            Egress::SameRegion => 0.0,
+            Egress::Tiered(tiers) => {
+                let mut cost = 0.0;
+                let mut tier_start = 0.0;
+                for tier in tiers {
+                    let tier_end = tier.up_to_gb.unwrap_or(f64::INFINITY);
+                    let in_tier = egress_gb.min(tier_end) - tier_start;
+                    if in_tier <= 0.0 {
+                        break;
+                    }
+                    cost += in_tier * tier.per_gb;
+                    tier_start = tier_end;
+                }
+                cost
+            }
+        };
+        CostEstimate {
+            currency: self.currency.clone(),
+            requests,
+            egress_gb,
+            egress,
+            same_region: self.egress == Egress::SameRegion,
+        }
+    }
+}
+
+fn non_negative(name: &str, value: &toml::Value) -> Result<f64, String> {
+    let price = match value {
+        toml::Value::Float(price) => *price,
+        toml::Value::Integer(price) => *price as f64,
+        _ => return Err(format!("{} must be a number, got {}", name, value)),
+    };
+    if price < 0.0 {
+        return Err(format!("{} must not be negative, got {}", name, price));
+    }
+    Ok(price)
+}
+
+fn parse_egress(egress: &toml::Table) -> Result<Egress, String> {
+    for key in egress.keys() {
+        if !["same_region", "per_gb", "tiers"].contains(&key.as_str()) {
+            return Err(format!(
+                "unknown key egress.{}; expected same_region, per_gb or tiers",
+                key
+            ));
+        }
+    }
+    match egress.get("same_region") {
+        None | Some(toml::Value::Boolean(false)) => {}
+        Some(toml::Value::Boolean(true)) => return Ok(Egress::SameRegion),
+        Some(value) => {
+            return Err(format!(
+                "egress.same_region must be a boolean, got {}",
+                value
+            ))
+        }
+    }
+    match (egress.get("per_gb"), egress.get("tiers")) {
+        (Some(_), Some(_)) => Err("give egress.per_gb or egress.tiers, not both".to_string()),
+        (None, None) => Err("egress needs per_gb, tiers or same_region = true".to_string()),
+        (Some(price), None) => Ok(Egress::Tiered(vec![EgressTier {
+            up_to_gb: None,
+            per_gb: non_negative("egress.per_gb", price)?,
+        }])),
+        (None, Some(toml::Value::Array(tiers))) => {
+            let mut parsed = Vec::new();
+            for (i, tier) in tiers.iter().enumerate() {
+                let name = format!("egress.tiers[{}]", i);
+                let tier = tier
+                    .as_table()
+                    .ok_or_else(|| format!("{} must be a table", name))?;
+                let per_gb = tier
+                    .get("per_gb")
+                    .ok_or_else(|| format!("{} is missing per_gb", name))?;
+                let up_to_gb = tier
+                    .get("up_to_gb")
+                    .map(|limit| non_negative(&format!("{}.up_to_gb", name), limit))
+                    .transpose()?;
+                parsed.push(EgressTier {
+                    up_to_gb,
+                    per_gb: non_negative(&format!("{}.per_gb", name), per_gb)?,
+                });
+            }
+            check_tiers(&parsed)?;
+            Ok(Egress::Tiered(parsed))
+        }
+        (None, Some(value)) => Err(format!("egress.tiers must be an array, got {}", value)),
+    }
+}
+
+/// Tiers must grow, and only the last one may be unlimited, so that every
+/// byte read falls in exactly one tier.
+fn check_tiers(tiers: &[EgressTier]) -> Result<(), String> {
+    let Some((last, rest)) = tiers.split_last() else {
+        return Err("egress.tiers is empty".to_string());
+    };
+    if last.up_to_gb.is_some() {
+        return Err("the last egress tier must have no up_to_gb".to_string());
+    }
+    let mut previous = 0.0;
+    for (i, tier) in rest.iter().enumerate() {
+        match tier.up_to_gb {
+            None => {
+                return Err(format!(
+                    "only the last egress tier may omit up_to_gb, not tier {}",
+                    i
+                ))
+            }
+            Some(limit) if limit <= previous => {
+                return Err(format!(
+                    "egress tier {} ends at {} GB, not after the previous tier's {} GB",
+                    i, limit, previous
+                ))
+            }
+            Some(limit) => previous = limit,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIERED: &str = r#"
+        currency = "USD"
+
+        [requests]
+        get = 0.4
+        put = 5
+
+        [egress]
+        tiers = [
+            { up_to_gb = 1, per_gb = 0.0 },
+            { up_to_gb = 3, per_gb = 0.1 },
+            { per_gb = 0.05 },
+        ]
+    "#;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn prices_each_request_class() {
+        let model = PriceModel::parse(TIERED).unwrap();
+        let totals = RequestTotals::new(
+            &[
+                (Operation::Get, 2500),
+                (Operation::Put, 10),
+                (Operation::List, 3),
+            ],
+            0,
+            0,
+        );
+        let estimate = model.estimate(&totals);
+        assert_eq!(
+            estimate.requests,
+            vec![(Operation::Get, 2500, 1.0), (Operation::Put, 10, 0.05)]
+        );
+        assert!(close(estimate.total(), 1.05));
+    }
+
+    #[test]
+    fn egress_is_charged_by_tier() {
+        let model = PriceModel::parse(TIERED).unwrap();
+        let cost = |gb: f64| {
+            let totals = RequestTotals::new(&[], (gb * GB) as u64, 0);
+            model.estimate(&totals).egress
+        };
+        assert!(close(cost(0.5), 0.0));
+        assert!(close(cost(1.0), 0.0));
+        assert!(close(cost(2.0), 0.1));
+        assert!(close(cost(3.0), 0.2));
+        assert!(close(cost(5.0), 0.2 + 2.0 * 0.05));
+    }
+
+    #[test]
+    fn same_region_reads_are_free() {
+        let model = PriceModel::parse(
+            r#"
+            [requests]
+            get = 0.4
+            [egress]
+            same_region = true
+            per_gb = 0.09
+            "#,
+        )
+        .unwrap();
+        let totals = RequestTotals::new(&[(Operation::Get, 1000)], 10 * GB as u64, 0);
+        let estimate = model.estimate(&totals);
+        assert!(estimate.same_region);
+        assert!(close(estimate.egress, 0.0));
+        assert!(close(estimate.total(), 0.4));
+    }
+
+    #[test]
+    fn flat_egress_and_defaults() {
+        let model = PriceModel::parse("[egress]\nper_gb = 0.09\n").unwrap();
+        assert_eq!(model.currency, "USD");
+        assert!(model.per_1k_requests.is_empty());
+        let totals = RequestTotals::new(&[(Operation::Get, 1000)], 2 * GB as u64, 0);
+        assert!(close(model.estimate(&totals).total(), 0.18));
+
+        let free = PriceModel::parse("").unwrap();
+        assert!(close(free.estimate(&totals).total(), 0.0));
+    }
+
+    #[test]
+    fn rejects_invalid_models() {
+        for (contents, expected) in [
+            ("[requests]\ngets = 0.4\n", "unknown request class"),
+            ("[requests]\nget = -1\n", "must not be negative"),
+            ("[requests]\nget = \"cheap\"\n", "must be a number"),
+            ("price = 1\n", "unknown key"),
+            ("[egress]\n", "egress needs"),
+            ("[egress]\nper_gb = 1\ntiers = []\n", "not both"),
+            ("[egress]\ntiers = []\n", "is empty"),
+            (
+                "[egress]\ntiers = [{ up_to_gb = 5, per_gb = 1 }]\n",
+                "last egress tier",
+            ),
+            (
+                "[egress]\ntiers = [{ up_to_gb = 5, per_gb = 1 }, { up_to_gb = 2, per_gb = 1 }, { per_gb = 1 }]\n",
+                "not after the previous",
+            ),
+            ("[requests\n", "TOML parse error"),
+        ] {
+            let err = PriceModel::parse(contents).unwrap_err();
+            assert!(err.contains(expected), "{:?}: {}", contents, err);
+        }
+    }
+}
@@ -0,0 +1,409 @@
+//! Counting the requests a run sends to the backend.
+//!
+//! [`CountingStore`] wraps the store the URI names, beneath every other
+//! wrapper, so it sees the requests the backend would bill for and none of
+//! the injected faults. Every result carries the totals so far under
+//! `request_counts`, with `--price-model` an `estimated_cost` computed from
+//! them by [`crate::cost`], and for remote stores the bytes they are estimated
+//! to have put on the wire by [`crate::wire`].
+//!
+//! Some requests are issued inside object_store rather than by this tool, so
+//! a few counts are estimates: a listing counts one request per page of 1000
+//! objects, a multipart upload counts its parts from the bytes written plus
+//! the requests that start and complete it, and `get_ranges` counts one
+//! request per range before any coalescing.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::cost::PriceModel;
+use crate::experiment::Fields;
+use crate::wire::WireModel;
+
+/// Objects returned by one page of a listing.
+const LIST_PAGE_SIZE: u64 = 1000;
+
+/// Classes of request that backends price separately.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Operation {
+    Get,
+    Head,
+    Put,
+    List,
+    Copy,
+    Delete,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 6] = [
+        Operation::Get,
+        Operation::Head,
+        Operation::Put,
+        Operation::List,
+        Operation::Copy,
+        Operation::Delete,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Get => "get",
+            Operation::Head => "head",
+            Operation::Put => "put",
+            Operation::List => "list",
+            Operation::Copy => "copy",
+            Operation::Delete => "delete",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Operation::ALL.into_iter().find(|op| op.name() == name)
+    }
+}
+
+/// Requests and bytes sent to the backend so far.
+#[derive(Debug, Default)]
+pub struct RequestCounts {
+    requests: [AtomicU64; Operation::ALL.len()],
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// A copy of [`RequestCounts`] at one point in the run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestTotals {
+    requests: [u64; Operation::ALL.len()],
+    pub bytes_read: u64,
+    pub bytes_written: u64,
+}
+
+impl RequestCounts {
+    fn add(&self, op: Operation, requests: u64) {
+        self.requests[op as usize].fetch_add(requests, Ordering::SeqCst);
+    }
+
+    pub fn totals(&self) -> RequestTotals {
+        RequestTotals {
+            requests: Operation::ALL.map(|op| self.requests[op as usize].load(Ordering::SeqCst)),
+            bytes_read: self.bytes_read.load(Ordering::SeqCst),
+            bytes_written: self.bytes_written.load(Ordering::SeqCst),
+        }
+    }
+}
+
+impl RequestTotals {
+    #[cfg(test)]
+    pub fn new(requests: &[(Operation, u64)], bytes_read: u64, bytes_written: u64) -> Self {
+        let mut totals = Self {
+            bytes_read,
+            bytes_written,
+            ..Default::default()
+        };
+        for (op, count) in requests {
+            totals.requests[*op as usize] += count;
+        }
+        totals
+    }
+
+    pub fn requests(&self, op: Operation) -> u64 {
+        self.requests[op as usize]
+    }
+
+    /// JSON object with the count of each request class and the bytes moved.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut totals = Operation::ALL
+            .iter()
+            .map(|op| (op.name().to_string(), self.requests(*op).into()))
+            .collect::<Fields>();
+        totals.insert("bytes_read".to_string(), self.bytes_read.into());
+        totals.insert("bytes_written".to_string(), self.bytes_written.into());
+        totals.into()
+    }
+}
+
+/// Append the requests sent so far, their cost under `price_model` and
+/// their wire bytes under `wire`, to the JSON object `result`.
+pub fn with_counts(
+    result: &mut Fields,
+    counts: &RequestCounts,
+    price_model: Option<&PriceModel>,
+    wire: Option<&WireModel>,
+) {
+    let totals = counts.totals();
+    result.insert("request_counts".to_string(), totals.to_json());
+    if let Some(cost) = price_model.map(|model| model.estimate(&totals)) {
+        result.insert("estimated_cost".to_string(), cost.to_json());
+    }
+    if let Some(wire) = wire.map(|model| model.estimate(&totals)) {
+        result.insert("wire".to_string(), wire.to_json());
+    }
+}
+
+/// The object and byte range of every ranged read, in the order they were sent.
+pub type RangeLog = Arc<Mutex<Vec<(Path, Range<usize>)>>>;
+
+/// An [`ObjectStore`] that counts the requests sent to `inner`.
+pub struct CountingStore {
+    inner: Arc<dyn ObjectStore>,
+    counts: Arc<RequestCounts>,
+    ranges: Option<RangeLog>,
+}
+
+impl CountingStore {
+    pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            inner,
+            counts: Arc::new(RequestCounts::default()),
+            ranges: None,
+        }
+    }
+
+    /// Also log the range of every ranged read, for tests to check. Runs
+    /// don't, as the log grows with every request.
+    pub fn recording_ranges(mut self) -> Self {
+        self.ranges = Some(RangeLog::default());
+        self
+    }
+
+    /// Counters of requests sent, which stay readable after the store is
+    /// handed to a benchmark.
+    pub fn counts(&self) -> Arc<RequestCounts> {
+        self.counts.clone()
+    }
+
+    /// The ranges read so far, if [`Self::recording_ranges`] was asked for.
+    pub fn ranges(&self) -> Option<RangeLog> {
+        self.ranges.clone()
+    }
+
+    fn record(&self, location: &Path, ranges: &[Range<usize>]) {
+        if let Some(log) = &self.ranges {
+            let mut log = log.lock().unwrap();
+            log.extend(ranges.iter().map(|range| (location.clone(), range.clone())));
+        }
+    }
+}
+
+/// Counts the bytes written to a multipart upload and, once it completes,
+/// the requests it took.
+struct CountingWriter {
+    inner: Box<dyn AsyncWrite + Unpin + Send>,
+    counts: Arc<RequestCounts>,
+    written: u64,
+}
+
+impl AsyncWrite for CountingWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.written += written as u64;
+            self.counts
+                .bytes_written
+                .fetch_add(written as u64, Ordering::SeqCst);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let poll = Pin::new(&mut self.inner).poll_shutdown(cx);
+        if let Poll::Ready(Ok(())) = poll {
+            let part_size = crate::store_defaults::PART_SIZE as u64;
+            let parts = self.written.div_ceil(part_size).max(1);
+            // The parts, plus the requests that create and complete the upload.
+            self.counts.add(Operation::Put, parts + 2);
+        }
+        poll
+    }
+}
+
+impl Display for CountingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CountingStore({})", self.inner)
+    }
+}
+
+impl Debug for CountingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CountingStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for CountingStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.counts.add(Operation::Put, 1);
+        self.counts
+            .bytes_written
+            .fetch_add(bytes.len() as u64, Ordering::SeqCst);
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        let (id, inner) = self.inner.put_multipart(location).await?;
+        let writer = CountingWriter {
+            inner,
+            counts: self.counts.clone(),
+            written: 0,
+        };
+        Ok((id, Box::new(writer)))
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.counts.add(Operation::Delete, 1);
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.counts.add(Operation::Get, 1);
+        if let Some(range) = &options.range {
+            self.record(location, std::slice::from_ref(range));
+        }
+        let result = self.inner.get_opts(location, options).await?;
+        let counts = self.counts.clone();
+        let stream = result.into_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                counts
+                    .bytes_read
+                    .fetch_add(chunk.len() as u64, Ordering::SeqCst);
+            }
+        });
+        Ok(GetResult::Stream(stream.boxed()))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.counts.add(Operation::Get, 1);
+        self.record(location, std::slice::from_ref(&range));
+        let bytes = self.inner.get_range(location, range).await?;
+        self.counts
+            .bytes_read
+            .fetch_add(bytes.len() as u64, Ordering::SeqCst);
+        Ok(bytes)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.counts.add(Operation::Get, ranges.len() as u64);
+        self.record(location, ranges);
+        let bytes = self.inner.get_ranges(location, ranges).await?;
+        let read: usize = bytes.iter().map(Bytes::len).sum();
+        self.counts
+            .bytes_read
+            .fetch_add(read as u64, Ordering::SeqCst);
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.counts.add(Operation::Head, 1);
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.counts.add(Operation::Delete, 1);
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        // The first page is requested even when it comes back empty.
+        self.counts.add(Operation::List, 1);
+        let counts = self.counts.clone();
+        let mut listed = 0;
+        let stream = self.inner.list(prefix).await?.inspect(move |meta| {
+            if meta.is_ok() {
+                listed += 1;
+                if listed % LIST_PAGE_SIZE == 1 && listed > 1 {
+                    counts.add(Operation::List, 1);
+                }
+            }
+        });
+        Ok(stream.boxed())
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.counts.add(Operation::List, 1);
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.counts.add(Operation::Copy, 1);
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.counts.add(Operation::Copy, 1);
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn counts_requests_and_bytes() {
+        let store = CountingStore::new(Arc::new(InMemory::new()));
+        let counts = store.counts();
+        for i in 0..1001 {
+            let location = Path::from(format!("data/{}.bin", i));
+            store
+                .put(&location, Bytes::from(vec![0; 10]))
+                .await
+                .unwrap();
+        }
+        let (_, mut writer) = store.put_multipart(&Path::from("big.bin")).await.unwrap();
+        writer.write_all(&[0; 100]).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        let location = Path::from("data/0.bin");
+        store.get(&location).await.unwrap().bytes().await.unwrap();
+        store.get_range(&location, 0..4).await.unwrap();
+        store.get_ranges(&location, &[0..1, 2..3]).await.unwrap();
+        store.head(&location).await.unwrap();
+        let listed = store
+            .list(Some(&Path::from("data")))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1001);
+
+        let totals = counts.totals();
+        assert_eq!(totals.requests(Operation::Put), 1001 + 3);
+        assert_eq!(totals.requests(Operation::Get), 4);
+        assert_eq!(totals.requests(Operation::Head), 1);
+        assert_eq!(totals.requests(Operation::List), 2);
+        assert_eq!(totals.bytes_read, 10 + 4 + 2);
+        assert_eq!(totals.bytes_written, 1001 * 10 + 100);
+    }
+}
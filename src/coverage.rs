@@ -0,0 +1,503 @@
+//! Which bytes of each object a run read.
+//!
+//! With `--coverage`, [`CoverageStore`] records the byte range of every read
+//! and, from `head` and `list`, the size of every object it sees. Every
+//! result then carries a `coverage` field: for each object read, the share of
+//! its bytes fetched at least once, and the bytes fetched again by
+//! overlapping requests as `duplicate_bytes_fetched`.
+//!
+//! Objects up to [`EXACT_MAX_SIZE`] keep an exact set of the intervals read.
+//! Larger ones count the bytes fetched in each of [`CELLS`] equal cells, so
+//! millions of small reads cost one counter update each; a cell is covered up
+//! to its length and bytes beyond that count as duplicates, which is exact
+//! unless two reads hit different parts of one cell.
+//!
+//! A heat map of each object, `--coverage` buckets (1024 by default) of one
+//! hex digit each from `0` (untouched) to `f` (fully read), is written one
+//! line per object to `--coverage-out`, or to `coverage.jsonl` in the
+//! experiment's run directory.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::experiment::Fields;
+
+/// Largest object whose reads are kept as exact intervals.
+pub const EXACT_MAX_SIZE: usize = 16 * 1024 * 1024;
+
+/// Cells a larger object is counted in.
+pub const CELLS: usize = 65536;
+
+/// Most objects listed in the result; the map file has them all.
+const MAX_LISTED_OBJECTS: usize = 100;
+
+/// Bytes read from one object.
+#[derive(Debug, Clone, PartialEq)]
+enum Touched {
+    /// Disjoint intervals read, keyed by start
+    Exact(BTreeMap<usize, usize>),
+    /// Bytes fetched within each `cell_size` cell
+    Cells { cell_size: usize, fetched: Vec<u32> },
+}
+
+/// What one object's reads covered.
+#[derive(Debug, Clone, PartialEq)]
+struct ObjectCoverage {
+    size: Option<usize>,
+    fetched: u64,
+    touched: Touched,
+}
+
+impl Default for ObjectCoverage {
+    fn default() -> Self {
+        Self {
+            size: None,
+            fetched: 0,
+            touched: Touched::Exact(BTreeMap::new()),
+        }
+    }
+}
+
+impl ObjectCoverage {
+    fn set_size(&mut self, size: usize) {
+        self.size = Some(size);
+        if size <= EXACT_MAX_SIZE {
+            return;
+        }
+        if let Touched::Exact(intervals) = &mut self.touched {
+            let intervals = std::mem::take(intervals);
+            self.touched = Touched::Cells {
+                cell_size: size.div_ceil(CELLS).max(1),
+                fetched: vec![0; CELLS],
+            };
+            for (start, end) in intervals {
+                self.add_to_cells(start..end);
+            }
+        }
+    }
+
+    fn read(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+        self.fetched += range.len() as u64;
+        match &mut self.touched {
+            Touched::Exact(intervals) => insert_interval(intervals, range),
+            Touched::Cells { .. } => self.add_to_cells(range),
+        }
+    }
+
+    fn add_to_cells(&mut self, range: Range<usize>) {
+        let Touched::Cells { cell_size, fetched } = &mut self.touched else {
+            return;
+        };
+        let cell_size = *cell_size;
+        let first = range.start / cell_size;
+        let last = (range.end - 1) / cell_size;
+        for cell in first..=last.min(fetched.len() - 1) {
+            let cell_start = cell * cell_size;
+            let overlap = range.end.min(cell_start + cell_size) - range.start.max(cell_start);
+            fetched[cell] = fetched[cell].saturating_add(overlap as u32);
+        }
+    }
+
+    /// Stretches of the object with the bytes covered in each, assumed
+    /// spread evenly across the stretch.
+    fn segments(&self) -> Vec<(Range<usize>, usize)> {
+        match &self.touched {
+            Touched::Exact(intervals) => intervals
+                .iter()
+                .map(|(start, end)| (*start..*end, end - start))
+                .collect(),
+            Touched::Cells { cell_size, fetched } => {
+                let size = self.size.unwrap_or(usize::MAX);
+                fetched
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, fetched)| **fetched > 0)
+                    .map(|(cell, fetched)| {
+                        let start = cell * cell_size;
+                        let end = (start + cell_size).min(size);
+                        (start..end, (*fetched as usize).min(end - start))
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn covered(&self) -> u64 {
+        self.segments()
+            .iter()
+            .map(|(_, covered)| *covered as u64)
+            .sum()
+    }
+
+    /// The size, or for an object never seen listed the end of its last read.
+    fn extent(&self) -> usize {
+        self.size
+            .unwrap_or_else(|| self.segments().last().map_or(0, |(range, _)| range.end))
+    }
+
+    /// One hex digit per bucket, for the share of the bucket read.
+    fn heat_map(&self, buckets: usize) -> String {
+        let extent = self.extent();
+        if extent == 0 {
+            return String::new();
+        }
+        let buckets = buckets.clamp(1, extent);
+        let mut covered = vec![0f64; buckets];
+        let bucket_of =
+            |offset: usize| (offset as u128 * buckets as u128 / extent as u128) as usize;
+        let bucket_start =
+            |bucket: usize| (bucket as u128 * extent as u128 / buckets as u128) as usize;
+        for (range, bytes) in self.segments() {
+            let density = bytes as f64 / range.len() as f64;
+            let first = bucket_of(range.start);
+            let last = bucket_of(range.end - 1).min(buckets - 1);
+            for (bucket, covered) in covered.iter_mut().enumerate().take(last + 1).skip(first) {
+                let overlap =
+                    range.end.min(bucket_start(bucket + 1)) - range.start.max(bucket_start(bucket));
+                *covered += overlap as f64 * density;
+            }
+        }
+        covered
+            .iter()
+            .enumerate()
+            .map(|(bucket, covered)| {
+                let len = bucket_start(bucket + 1) - bucket_start(bucket);
+                let level = (covered / len as f64 * 15.0).round().clamp(0.0, 15.0) as u32;
+                char::from_digit(level, 16).unwrap()
+            })
+            .collect()
+    }
+
+    fn to_json(&self, path: &Path) -> serde_json::Value {
+        let covered = self.covered();
+        serde_json::json!({
+            "path": path.to_string(),
+            "size": self.size,
+            "fetched_bytes": self.fetched,
+            "covered_bytes": covered,
+            "duplicate_bytes_fetched": self.fetched.saturating_sub(covered),
+            "coverage": match self.extent() {
+                0 => 0.0,
+                extent => covered as f64 / extent as f64,
+            },
+            "approximate": matches!(self.touched, Touched::Cells { .. }),
+        })
+    }
+}
+
+/// Add `range` to the disjoint `intervals`, merging what it overlaps or abuts.
+pub fn insert_interval(intervals: &mut BTreeMap<usize, usize>, range: Range<usize>) {
+    let (mut start, mut end) = (range.start, range.end);
+    let touching = intervals
+        .range(..=range.end)
+        .rev()
+        .take_while(|(_, interval_end)| **interval_end >= range.start)
+        .map(|(start, end)| (*start, *end))
+        .collect::<Vec<_>>();
+    for (interval_start, interval_end) in touching {
+        intervals.remove(&interval_start);
+        start = start.min(interval_start);
+        end = end.max(interval_end);
+    }
+    intervals.insert(start, end);
+}
+
+/// Reads recorded across the run.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    buckets: usize,
+    out: Option<PathBuf>,
+    objects: Mutex<HashMap<Path, ObjectCoverage>>,
+}
+
+impl Coverage {
+    pub fn new(buckets: usize, out: Option<PathBuf>) -> Self {
+        Self {
+            buckets,
+            out,
+            objects: Mutex::default(),
+        }
+    }
+
+    fn read(&self, location: &Path, range: Range<usize>) {
+        let mut objects = self.objects.lock().unwrap();
+        objects.entry(location.clone()).or_default().read(range);
+    }
+
+    fn saw(&self, meta: &ObjectMeta) {
+        let mut objects = self.objects.lock().unwrap();
+        // Unread objects are only remembered by size, which is cheap.
+        let object = objects.entry(meta.location.clone()).or_default();
+        if object.size != Some(meta.size) {
+            object.set_size(meta.size);
+        }
+    }
+
+    /// The objects read so far, sorted by path.
+    fn read_objects(&self) -> Vec<(Path, ObjectCoverage)> {
+        let objects = self.objects.lock().unwrap();
+        let mut read = objects
+            .iter()
+            .filter(|(_, object)| object.fetched > 0)
+            .map(|(path, object)| (path.clone(), object.clone()))
+            .collect::<Vec<_>>();
+        read.sort_by(|a, b| a.0.cmp(&b.0));
+        read
+    }
+
+    fn summary(&self, map_file: Option<&std::path::Path>) -> serde_json::Value {
+        let objects = self.read_objects();
+        let fetched = objects.iter().map(|(_, o)| o.fetched).sum::<u64>();
+        let covered = objects.iter().map(|(_, o)| o.covered()).sum::<u64>();
+        let extent = objects.iter().map(|(_, o)| o.extent() as u64).sum::<u64>();
+        serde_json::json!({
+            "objects": objects.len(),
+            "fetched_bytes": fetched,
+            "covered_bytes": covered,
+            "duplicate_bytes_fetched": fetched.saturating_sub(covered),
+            "coverage": match extent {
+                0 => 0.0,
+                extent => covered as f64 / extent as f64,
+            },
+            "per_object": objects
+                .iter()
+                .take(MAX_LISTED_OBJECTS)
+                .map(|(path, object)| object.to_json(path))
+                .collect::<Vec<_>>(),
+            "buckets": self.buckets,
+            "map_file": map_file.map(|path| path.display().to_string()),
+        })
+    }
+
+    /// Rewrite the heat map file with every object read so far.
+    fn write_maps(&self, path: &std::path::Path) -> std::io::Result<()> {
+        let lines = self
+            .read_objects()
+            .iter()
+            .map(|(location, object)| {
+                let mut line = object.to_json(location);
+                line["heat_map"] = object.heat_map(self.buckets).into();
+                format!("{}\n", line)
+            })
+            .collect::<String>();
+        std::fs::write(path, lines)
+    }
+}
+
+/// Append the `coverage` of the reads so far to the JSON object `result`,
+/// and rewrite the heat map file: the `--coverage-out` one, or else
+/// `map_file`.
+pub fn with_coverage(result: &mut Fields, coverage: &Coverage, map_file: Option<PathBuf>) {
+    let map_file = coverage.out.clone().or(map_file);
+    let map_file = map_file.filter(|path| match coverage.write_maps(path) {
+        Ok(()) => true,
+        Err(err) => {
+            eprintln!("failed to write coverage map {}: {}", path.display(), err);
+            false
+        }
+    });
+    result.insert(
+        "coverage".to_string(),
+        coverage.summary(map_file.as_deref()),
+    );
+}
+
+/// An [`ObjectStore`] that records the ranges read from `inner`.
+pub struct CoverageStore {
+    inner: Arc<dyn ObjectStore>,
+    coverage: Arc<Coverage>,
+}
+
+impl CoverageStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, coverage: Arc<Coverage>) -> Self {
+        Self { inner, coverage }
+    }
+}
+
+impl Display for CoverageStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "CoverageStore({})", self.inner)
+    }
+}
+
+impl Debug for CoverageStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CoverageStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for CoverageStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let mut offset = options.range.as_ref().map_or(0, |range| range.start);
+        let result = self.inner.get_opts(location, options).await?;
+        let coverage = self.coverage.clone();
+        let location = location.clone();
+        // Recorded as the body streams, so a read abandoned partway counts
+        // only what arrived.
+        let stream = result.into_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                coverage.read(&location, offset..offset + chunk.len());
+                offset += chunk.len();
+            }
+        });
+        Ok(GetResult::Stream(stream.boxed()))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let bytes = self.inner.get_range(location, range.clone()).await?;
+        self.coverage
+            .read(location, range.start..range.start + bytes.len());
+        Ok(bytes)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let bytes = self.inner.get_ranges(location, ranges).await?;
+        for (range, bytes) in ranges.iter().zip(&bytes) {
+            self.coverage
+                .read(location, range.start..range.start + bytes.len());
+        }
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let meta = self.inner.head(location).await?;
+        self.coverage.saw(&meta);
+        Ok(meta)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        let coverage = self.coverage.clone();
+        let stream = self.inner.list(prefix).await?.inspect(move |meta| {
+            if let Ok(meta) = meta {
+                coverage.saw(meta);
+            }
+        });
+        Ok(stream.boxed())
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        let listed = self.inner.list_with_delimiter(prefix).await?;
+        for meta in &listed.objects {
+            self.coverage.saw(meta);
+        }
+        Ok(listed)
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn overlapping_reads_count_once() {
+        let mut object = ObjectCoverage::default();
+        object.set_size(1000);
+        object.read(0..100);
+        object.read(50..150);
+        object.read(150..200);
+        object.read(500..600);
+        assert_eq!(
+            object.touched,
+            Touched::Exact(BTreeMap::from([(0, 200), (500, 600)]))
+        );
+        assert_eq!(object.fetched, 350);
+        assert_eq!(object.covered(), 300);
+        let json = object.to_json(&Path::from("a"));
+        assert_eq!(json["duplicate_bytes_fetched"], 50);
+        assert_eq!(json["coverage"], 0.3);
+        // Ten buckets of 100 bytes: the first two and the sixth read.
+        assert_eq!(object.heat_map(10), "ff000f0000");
+    }
+
+    #[test]
+    fn large_objects_are_counted_in_cells() {
+        let size = 64 * EXACT_MAX_SIZE;
+        let mut object = ObjectCoverage::default();
+        // Read before the size was known, then moved into cells.
+        object.read(0..1024);
+        object.set_size(size);
+        assert!(matches!(object.touched, Touched::Cells { .. }));
+        for _ in 0..3 {
+            object.read(size / 2..size / 2 + 2 * CELLS * 1024);
+        }
+        assert_eq!(object.covered(), 1024 + 2 * CELLS as u64 * 1024);
+        assert_eq!(object.fetched, 1024 + 6 * CELLS as u64 * 1024);
+        // The first 1 KiB rounds to nothing; the third bucket is half read.
+        assert_eq!(object.heat_map(4), "0080");
+    }
+
+    #[tokio::test]
+    async fn store_records_reads_and_sizes() {
+        let inner = Arc::new(InMemory::new());
+        let location = Path::from("data/a.bin");
+        inner
+            .put(&location, Bytes::from(vec![0; 4096]))
+            .await
+            .unwrap();
+        let coverage = Arc::new(Coverage::new(4, None));
+        let store = CoverageStore::new(inner, coverage.clone());
+        store.head(&location).await.unwrap();
+        store.get_range(&location, 0..1024).await.unwrap();
+        store
+            .get_ranges(&location, &[512..1536, 3072..4096])
+            .await
+            .unwrap();
+
+        let summary = coverage.summary(None);
+        assert_eq!(summary["objects"], 1);
+        assert_eq!(summary["covered_bytes"], 2560);
+        assert_eq!(summary["duplicate_bytes_fetched"], 512);
+        assert_eq!(summary["per_object"][0]["size"], 4096);
+        let objects = coverage.read_objects();
+        assert_eq!(objects[0].1.heat_map(4), "f80f");
+    }
+}
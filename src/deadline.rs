@@ -0,0 +1,278 @@
+//! Abandoning requests that miss a deadline, with `--request-deadline-ms`.
+//!
+//! A query engine that enforces deadlines at the storage layer drops a read
+//! that won't finish in time. With `--request-deadline-ms`, `download`,
+//! `random-read` and fairness' class A reads do the same: a request still
+//! running at the deadline, retries included, is dropped and counted as
+//! expired rather than failed. `--on-expire skip` gives up on its bytes;
+//! `reissue` sends it once more under the same deadline, as a plain
+//! `get_range` (or `get`) rather than the streamed `get_opts` of the first
+//! attempt.
+//!
+//! The first attempt streams its body, so the bytes an expired request had
+//! received count as `wasted_bytes`. A reissued request returns nothing until
+//! it completes, so what an expired reissue transferred can't be measured.
+//! Results report under `deadline` the expiry rate, and goodput: the bytes,
+//! and MB/s, of the requests that met their deadline.
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::StreamExt;
+use object_store::{path::Path, GetOptions, ObjectStore};
+
+use crate::report::DeadlineReport;
+use crate::retry::RetryPolicy;
+use crate::stats::mbps;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnExpire {
+    #[default]
+    Skip,
+    Reissue,
+}
+
+impl OnExpire {
+    pub fn name(self) -> &'static str {
+        match self {
+            OnExpire::Skip => "skip",
+            OnExpire::Reissue => "reissue",
+        }
+    }
+}
+
+/// How long a request may take, and what happens when it takes longer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeadlineSpec {
+    pub deadline: Duration,
+    pub on_expire: OnExpire,
+}
+
+/// What a request under a deadline returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fetched {
+    /// Met its deadline, or its reissue did, with this many bytes
+    Met(usize),
+    /// Expired, and was not reissued or expired again
+    Expired,
+}
+
+/// The requests of one run held to a [`DeadlineSpec`].
+#[derive(Debug)]
+pub struct Deadlines {
+    spec: DeadlineSpec,
+    /// First attempts that met the deadline
+    met: AtomicUsize,
+    /// First attempts that expired
+    expired: AtomicUsize,
+    reissued: AtomicUsize,
+    reissues_met: AtomicUsize,
+    goodput_bytes: AtomicU64,
+    wasted_bytes: AtomicU64,
+    /// Objects with bytes given up on
+    skipped: Mutex<HashSet<Path>>,
+}
+
+impl Deadlines {
+    pub fn new(spec: DeadlineSpec) -> Self {
+        Self {
+            spec,
+            met: AtomicUsize::new(0),
+            expired: AtomicUsize::new(0),
+            reissued: AtomicUsize::new(0),
+            reissues_met: AtomicUsize::new(0),
+            goodput_bytes: AtomicU64::new(0),
+            wasted_bytes: AtomicU64::new(0),
+            skipped: Mutex::default(),
+        }
+    }
+
+    /// Read `range` of `location`, or all of it, under the deadline.
+    pub async fn fetch(
+        &self,
+        object_store: &dyn ObjectStore,
+        location: &Path,
+        range: Option<Range<usize>>,
+        retry: &RetryPolicy,
+    ) -> object_store::Result<Fetched> {
+        let received = AtomicUsize::new(0);
+        let streamed = stream(object_store, location, range.clone(), retry, &received);
+        match tokio::time::timeout(self.spec.deadline, streamed).await {
+            Ok(result) => {
+                let bytes = result?;
+                self.met.fetch_add(1, Ordering::Relaxed);
+                self.goodput_bytes
+                    .fetch_add(bytes as u64, Ordering::Relaxed);
+                return Ok(Fetched::Met(bytes));
+            }
+            Err(_) => {
+                self.expired.fetch_add(1, Ordering::Relaxed);
+                self.wasted_bytes
+                    .fetch_add(received.load(Ordering::Relaxed) as u64, Ordering::Relaxed);
+            }
+        }
+        if self.spec.on_expire == OnExpire::Reissue {
+            self.reissued.fetch_add(1, Ordering::Relaxed);
+            let reissue = async {
+                match &range {
+                    Some(range) => retry
+                        .run(|| object_store.get_range(location, range.clone()))
+                        .await
+                        .map(|bytes| bytes.len()),
+                    None => retry
+                        .run(|| async { object_store.get(location).await?.bytes().await })
+                        .await
+                        .map(|bytes| bytes.len()),
+                }
+            };
+            if let Ok(result) = tokio::time::timeout(self.spec.deadline, reissue).await {
+                let bytes = result?;
+                self.reissues_met.fetch_add(1, Ordering::Relaxed);
+                self.goodput_bytes
+                    .fetch_add(bytes as u64, Ordering::Relaxed);
+                return Ok(Fetched::Met(bytes));
+            }
+        }
+        self.skipped.lock().unwrap().insert(location.clone());
+        Ok(Fetched::Expired)
+    }
+
+    /// Whether bytes of `location` were given up on.
+    pub fn skipped(&self, location: &Path) -> bool {
+        self.skipped.lock().unwrap().contains(location)
+    }
+
+    /// The counts so far, for a run that moved bytes for `elapsed_us`.
+    pub fn report(&self, elapsed_us: u128) -> DeadlineReport {
+        let met = self.met.load(Ordering::Relaxed);
+        let expired = self.expired.load(Ordering::Relaxed);
+        let goodput_bytes = self.goodput_bytes.load(Ordering::Relaxed);
+        DeadlineReport {
+            deadline_ms: self.spec.deadline.as_millis() as u64,
+            on_expire: self.spec.on_expire.name(),
+            requests: met + expired,
+            met,
+            expired,
+            expiry_rate: expired as f64 / (met + expired).max(1) as f64,
+            reissued: self.reissued.load(Ordering::Relaxed),
+            reissues_met: self.reissues_met.load(Ordering::Relaxed),
+            goodput_bytes,
+            goodput_mbps: mbps(goodput_bytes, elapsed_us),
+            wasted_bytes: self.wasted_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Stream `range` of `location` with `get_opts`, counting the bytes into
+/// `received` as they arrive.
+async fn stream(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    range: Option<Range<usize>>,
+    retry: &RetryPolicy,
+    received: &AtomicUsize,
+) -> object_store::Result<usize> {
+    // The local and in-memory stores ignore `GetOptions::range` and return
+    // the whole object, so stop once the range has been read.
+    let expected = range.as_ref().map_or(usize::MAX, Range::len);
+    retry
+        .run(|| async {
+            let options = GetOptions {
+                range: range.clone(),
+                ..Default::default()
+            };
+            let mut body = object_store
+                .get_opts(location, options)
+                .await?
+                .into_stream();
+            let mut bytes = 0;
+            while bytes < expected {
+                let Some(chunk) = body.next().await else {
+                    break;
+                };
+                let taken = chunk?.len().min(expected - bytes);
+                bytes += taken;
+                received.fetch_add(taken, Ordering::Relaxed);
+            }
+            Ok(bytes)
+        })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+    use std::sync::Arc;
+
+    async fn store(faults: &[Fault]) -> (Arc<dyn ObjectStore>, Path) {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let location = Path::from("data/a");
+        inner
+            .put(&location, Bytes::from(vec![7; 1000]))
+            .await
+            .unwrap();
+        let store = FaultStore::new(inner, FaultConfig::new(faults), 0);
+        (Arc::new(store), location)
+    }
+
+    fn deadlines(ms: u64, on_expire: OnExpire) -> Deadlines {
+        Deadlines::new(DeadlineSpec {
+            deadline: Duration::from_millis(ms),
+            on_expire,
+        })
+    }
+
+    #[tokio::test]
+    async fn slow_requests_expire_instead_of_failing() {
+        let (store, location) = store(&[Fault::LatencyMs(200)]).await;
+        let retry = RetryPolicy::new(0, None);
+
+        let skipping = deadlines(20, OnExpire::Skip);
+        let fetched = skipping
+            .fetch(store.as_ref(), &location, Some(0..100), &retry)
+            .await
+            .unwrap();
+        assert_eq!(fetched, Fetched::Expired);
+        assert!(skipping.skipped(&location));
+        let report = skipping.report(1);
+        assert_eq!((report.requests, report.expired), (1, 1));
+        assert_eq!(report.expiry_rate, 1.0);
+        assert_eq!(report.goodput_bytes, 0);
+        assert_eq!(report.reissued, 0);
+
+        // A generous deadline is met.
+        let generous = deadlines(5_000, OnExpire::Skip);
+        let fetched = generous
+            .fetch(store.as_ref(), &location, Some(0..100), &retry)
+            .await
+            .unwrap();
+        assert_eq!(fetched, Fetched::Met(100));
+        assert_eq!(generous.report(1).goodput_bytes, 100);
+        assert!(!generous.skipped(&location));
+    }
+
+    #[tokio::test]
+    async fn reissues_get_one_more_try() {
+        let (store, location) = store(&[Fault::LatencyMs(200)]).await;
+        let retry = RetryPolicy::new(0, None);
+        let reissuing = deadlines(20, OnExpire::Reissue);
+        let fetched = reissuing
+            .fetch(store.as_ref(), &location, None, &retry)
+            .await
+            .unwrap();
+        // Every request is slow, so the reissue expires too.
+        assert_eq!(fetched, Fetched::Expired);
+        let report = reissuing.report(1);
+        assert_eq!(
+            (report.expired, report.reissued, report.reissues_met),
+            (1, 1, 0)
+        );
+        assert!(reissuing.skipped(&location));
+    }
+}
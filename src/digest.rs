@@ -0,0 +1,189 @@
+//! Content digests for audit: computed while uploading, verified by scrub.
+//!
+//! With `--digest sha256`, every uploaded object's SHA-256 is computed over the
+//! data as it is generated and, with `--digest-out`, written to an audit file
+//! with one JSON line per object: `{"path", "sha256", "size", "timestamp"}`.
+
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use bytes::Bytes;
+use object_store::path::Path;
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum DigestAlgorithm {
+    Sha256,
+}
+
+/// Digest settings for an upload, plus the time spent hashing.
+#[derive(Debug)]
+pub struct DigestConfig {
+    /// Hash on the blocking thread pool, overlapping with the upload
+    pub blocking: bool,
+    log: Option<Mutex<File>>,
+    hash_ns: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl DigestConfig {
+    pub fn new(blocking: bool, out: Option<&std::path::Path>) -> std::io::Result<Self> {
+        let log = match out {
+            Some(path) => Some(Mutex::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            blocking,
+            log,
+            hash_ns: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        })
+    }
+
+    /// Start hashing a new object.
+    pub fn hasher(&self) -> ObjectHasher<'_> {
+        ObjectHasher {
+            config: self,
+            hasher: Some(Sha256::new()),
+            pending: None,
+        }
+    }
+
+    /// Append a finished object's digest to the audit file.
+    pub fn record(&self, location: &Path, digest: &str, size: usize) -> std::io::Result<()> {
+        if let Some(log) = &self.log {
+            let line = serde_json::json!({
+                "path": location.to_string(),
+                "sha256": digest,
+                "size": size,
+                "timestamp": chrono::Utc::now().to_rfc3339(),
+            });
+            log.lock()
+                .unwrap()
+                .write_all(format!("{}\n", line).as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Time spent hashing, as fields for the upload result.
+    pub fn json_fields(&self) -> Fields {
+        let hash_us = self.hash_ns.load(Ordering::Relaxed) / 1000;
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let mbps = bytes as f64 / 1024.0 / 1024.0 / (hash_us as f64 / 1_000_000.0);
+        fields(serde_json::json!({
+            "digest": "sha256",
+            "digest_blocking": self.blocking,
+            "digest_bytes": bytes,
+            "digest_us": hash_us,
+            "digest_mbps": mbps,
+        }))
+    }
+
+    fn add_time(&self, start: Instant, bytes: usize) {
+        self.hash_ns
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+}
+
+/// Streaming SHA-256 of one object.
+///
+/// In blocking mode each chunk is hashed on the blocking pool while the caller
+/// goes on to upload it; the next `update` (or `finish`) waits for it.
+pub struct ObjectHasher<'a> {
+    config: &'a DigestConfig,
+    hasher: Option<Sha256>,
+    pending: Option<tokio::task::JoinHandle<(Sha256, u64)>>,
+}
+
+impl ObjectHasher<'_> {
+    pub async fn update(&mut self, chunk: &[u8]) {
+        let mut hasher = self.take().await;
+        if self.config.blocking {
+            let chunk = Bytes::copy_from_slice(chunk);
+            self.config
+                .bytes
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+            self.pending = Some(tokio::task::spawn_blocking(move || {
+                let start = Instant::now();
+                hasher.update(&chunk);
+                (hasher, start.elapsed().as_nanos() as u64)
+            }));
+        } else {
+            let start = Instant::now();
+            hasher.update(chunk);
+            self.config.add_time(start, chunk.len());
+            self.hasher = Some(hasher);
+        }
+    }
+
+    /// Hex-encoded digest of everything passed to `update`.
+    pub async fn finish(mut self) -> String {
+        let hasher = self.take().await;
+        hex(&hasher.finalize())
+    }
+
+    async fn take(&mut self) -> Sha256 {
+        if let Some(pending) = self.pending.take() {
+            let (hasher, ns) = pending.await.expect("digest task panicked");
+            self.config.hash_ns.fetch_add(ns, Ordering::Relaxed);
+            return hasher;
+        }
+        self.hasher.take().expect("hasher already finished")
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Expected digest and size of an object, from an audit file.
+#[derive(Debug, Clone)]
+pub struct ExpectedDigest {
+    pub sha256: String,
+    pub size: usize,
+}
+
+/// Load an audit file written with `--digest-out`, keyed by object path. If an
+/// object appears more than once the latest entry wins.
+pub fn load_digests(path: &std::path::Path) -> Result<HashMap<String, ExpectedDigest>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut digests = HashMap::new();
+    for (i, line) in contents.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: serde_json::Value = serde_json::from_str(line)
+            .map_err(|err| format!("{}:{}: {}", path.display(), i + 1, err))?;
+        let (Some(object), Some(sha256), Some(size)) = (
+            entry.get("path").and_then(|v| v.as_str()),
+            entry.get("sha256").and_then(|v| v.as_str()),
+            entry.get("size").and_then(|v| v.as_u64()),
+        ) else {
+            return Err(format!(
+                "{}:{}: expected path, sha256 and size",
+                path.display(),
+                i + 1
+            )
+            .into());
+        };
+        digests.insert(
+            object.to_string(),
+            ExpectedDigest {
+                sha256: sha256.to_string(),
+                size: size as usize,
+            },
+        );
+    }
+    Ok(digests)
+}
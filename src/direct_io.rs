@@ -0,0 +1,334 @@
+//! Reading local files past the page cache.
+//!
+//! Against a `file://` store, every rerun after the first reads from RAM. With
+//! `--direct-io`, [`DirectIoStore`] serves reads itself: it opens files with
+//! `O_DIRECT` and reads whole aligned blocks into an aligned buffer, so each
+//! read reaches the device. Some filesystems reject `O_DIRECT`; reads then
+//! go through the page cache as usual and are evicted again with
+//! `posix_fadvise(POSIX_FADV_DONTNEED)`, so the next pass misses the cache.
+//! Off Linux neither is available and reads are plain buffered reads.
+//!
+//! Results record under `direct_io` how many reads took each path, and why
+//! direct I/O wasn't used if it wasn't.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::experiment::Fields;
+
+const STORE: &str = "direct_io";
+
+/// Offset, length and buffer alignment for `O_DIRECT` reads. Devices with
+/// 512-byte sectors accept any multiple of it too.
+const ALIGN: usize = 4096;
+
+/// How many reads bypassed the page cache, and why the others didn't.
+#[derive(Debug, Default)]
+pub struct DirectIoStatus {
+    direct_reads: AtomicU64,
+    buffered_reads: AtomicU64,
+    /// Why a read fell back to buffered I/O, the first time one did
+    fallback_reason: Mutex<Option<String>>,
+}
+
+impl DirectIoStatus {
+    fn fell_back(&self, reason: String) {
+        self.buffered_reads.fetch_add(1, Ordering::Relaxed);
+        let mut fallback_reason = self.fallback_reason.lock().unwrap();
+        if fallback_reason.is_none() {
+            eprintln!(
+                "warning: direct I/O unavailable, dropping cached pages instead: {}",
+                reason
+            );
+            *fallback_reason = Some(reason);
+        }
+    }
+}
+
+/// The status of a run that asked for `--direct-io` on a store it can't
+/// apply to.
+pub fn unsupported(reason: &str) -> Arc<DirectIoStatus> {
+    eprintln!("warning: ignoring --direct-io: {}", reason);
+    let status = DirectIoStatus::default();
+    *status.fallback_reason.lock().unwrap() = Some(reason.to_string());
+    Arc::new(status)
+}
+
+/// Append whether reads bypassed the page cache, as `status` counted them,
+/// to the JSON object `result`.
+pub fn with_status(result: &mut Fields, status: &DirectIoStatus) {
+    let direct_reads = status.direct_reads.load(Ordering::Relaxed);
+    let buffered_reads = status.buffered_reads.load(Ordering::Relaxed);
+    let direct_io = serde_json::json!({
+        "in_effect": direct_reads > 0 && buffered_reads == 0,
+        "direct_reads": direct_reads,
+        "buffered_reads": buffered_reads,
+        "fallback_reason": *status.fallback_reason.lock().unwrap(),
+    });
+    result.insert("direct_io".to_string(), direct_io);
+}
+
+#[cfg(target_os = "linux")]
+fn open_direct(path: &std::path::Path) -> io::Result<File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_DIRECT)
+        .open(path)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_direct(_path: &std::path::Path) -> io::Result<File> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "O_DIRECT is only available on Linux",
+    ))
+}
+
+/// Ask the kernel to evict the cached pages of `range` in `file`.
+#[cfg(target_os = "linux")]
+fn drop_cached(file: &File, range: &Range<usize>) {
+    use std::os::unix::io::AsRawFd;
+    // SAFETY: the descriptor is open for the duration of the call, and
+    // posix_fadvise only reads its arguments.
+    unsafe {
+        libc::posix_fadvise(
+            file.as_raw_fd(),
+            range.start as libc::off_t,
+            range.len() as libc::off_t,
+            libc::POSIX_FADV_DONTNEED,
+        );
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached(_file: &File, _range: &Range<usize>) {}
+
+/// Read into `buf` from `offset` until it is full or the file ends.
+fn read_at(file: &mut File, offset: usize, buf: &mut [u8]) -> io::Result<usize> {
+    file.seek(SeekFrom::Start(offset as u64))?;
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(filled)
+}
+
+/// Read `range` of `file`, opened with `O_DIRECT`, widening it to whole
+/// aligned blocks.
+fn read_direct(mut file: File, range: &Range<usize>) -> io::Result<Bytes> {
+    let start = range.start / ALIGN * ALIGN;
+    let end = range.end.div_ceil(ALIGN) * ALIGN;
+    // Over-allocate so an aligned window of the needed length fits inside.
+    let mut buffer = vec![0u8; end - start + ALIGN];
+    let offset = buffer.as_ptr().align_offset(ALIGN);
+    let window = &mut buffer[offset..offset + end - start];
+    let read = read_at(&mut file, start, window)?;
+    let skip = range.start - start;
+    let available = read.saturating_sub(skip).min(range.len());
+    Ok(Bytes::copy_from_slice(&window[skip..skip + available]))
+}
+
+/// Read `range` through the page cache, then evict what was read.
+fn read_buffered(mut file: File, range: &Range<usize>) -> io::Result<Bytes> {
+    let mut buffer = vec![0u8; range.len()];
+    let read = read_at(&mut file, range.start, &mut buffer)?;
+    drop_cached(&file, range);
+    buffer.truncate(read);
+    Ok(buffer.into())
+}
+
+fn read_range(
+    path: &std::path::Path,
+    range: &Range<usize>,
+    status: &DirectIoStatus,
+) -> io::Result<Bytes> {
+    let direct = open_direct(path).and_then(|file| read_direct(file, range));
+    match direct {
+        Ok(bytes) => {
+            status.direct_reads.fetch_add(1, Ordering::Relaxed);
+            Ok(bytes)
+        }
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Err(err),
+        Err(err) => {
+            status.fell_back(err.to_string());
+            read_buffered(File::open(path)?, range)
+        }
+    }
+}
+
+/// An [`ObjectStore`] that serves reads of local files without the page
+/// cache, and passes everything else to `inner`.
+pub struct DirectIoStore {
+    inner: Arc<dyn ObjectStore>,
+    status: Arc<DirectIoStatus>,
+}
+
+impl DirectIoStore {
+    /// Wrap `inner`, a `LocalFileSystem` rooted at `/`.
+    pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            inner,
+            status: Arc::default(),
+        }
+    }
+
+    /// What the reads through this store have done so far.
+    pub fn status(&self) -> Arc<DirectIoStatus> {
+        self.status.clone()
+    }
+
+    /// The file holding `location`, found the way `LocalFileSystem` does.
+    fn filesystem_path(location: &Path) -> Result<PathBuf> {
+        let mut url = url::Url::parse("file:///").unwrap();
+        url.path_segments_mut()
+            .unwrap()
+            .pop_if_empty()
+            .extend(location.parts());
+        url.to_file_path()
+            .map_err(|_| object_store::Error::Generic {
+                store: STORE,
+                source: format!("{} is not a local path", location).into(),
+            })
+    }
+
+    async fn read(&self, location: &Path, range: Option<Range<usize>>) -> Result<Bytes> {
+        let path = Self::filesystem_path(location)?;
+        let status = self.status.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            let range = match range {
+                Some(range) => range,
+                None => 0..std::fs::metadata(&path)?.len() as usize,
+            };
+            let bytes = read_range(&path, &range, &status)?;
+            if bytes.len() < range.len() {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!(
+                        "range {:?} extends past the end of {}",
+                        range,
+                        path.display()
+                    ),
+                ));
+            }
+            Ok(bytes)
+        })
+        .await
+        .map_err(|err| object_store::Error::JoinError { source: err })?;
+        result.map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => object_store::Error::NotFound {
+                path: location.to_string(),
+                source: err.into(),
+            },
+            _ => object_store::Error::Generic {
+                store: STORE,
+                source: err.into(),
+            },
+        })
+    }
+}
+
+impl Display for DirectIoStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DirectIoStore({})", self.inner)
+    }
+}
+
+impl Debug for DirectIoStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DirectIoStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for DirectIoStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        // Conditional reads need the metadata checks the inner store does.
+        let conditional = options.if_match.is_some()
+            || options.if_none_match.is_some()
+            || options.if_modified_since.is_some()
+            || options.if_unmodified_since.is_some();
+        if conditional {
+            return self.inner.get_opts(location, options).await;
+        }
+        let bytes = self.read(location, options.range).await?;
+        Ok(GetResult::Stream(
+            futures::stream::once(async { Ok(bytes) }).boxed(),
+        ))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.read(location, Some(range)).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let mut bytes = Vec::with_capacity(ranges.len());
+        for range in ranges {
+            bytes.push(self.read(location, Some(range.clone())).await?);
+        }
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
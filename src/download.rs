@@ -1,74 +1,1171 @@
 //! Parallel download implementation
+//!
+//! Block boundaries and byte totals are worked out in `u64`, so a single
+//! object of many terabytes plans the same way on every target. `--huge-object`
+//! checks that planning against a synthetic object without issuing requests.
+//!
+//! `--mode sequential` skips block planning and streams each object whole
+//! through one `get`, draining the body chunk by chunk as simple copy tools
+//! do, `--parallel-downloads` objects at a time. A body that ends short of,
+//! or runs past, the object's listed size fails the run.
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use bytes::Bytes;
 use futures::{StreamExt, TryStreamExt};
-use object_store::{path::Path, ObjectStore};
+use object_store::{path::Path, GetOptions, ObjectStore};
+use serde_json::{json, Value};
 use tracing::instrument;
 
+use crate::accounting::Accounting;
+use crate::control::{PhaseBoundaries, RunControl};
+use crate::deadline::{DeadlineSpec, Deadlines, Fetched};
+use crate::duration::MeasurementWindow;
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
 use crate::inspect_location;
+use crate::missing::MissingTracker;
+use crate::pattern::Verification;
+use crate::report::{Checked, DownloadResult, Streaming};
+use crate::retry::{is_timeout, RetryPolicy};
+use crate::size_buckets::SizeBuckets;
+use crate::stats::{
+    self, active_us, mbps, phases, windowed, Histogram, LatencySummary, TimedSample,
+};
+use crate::think_time::{Pacer, Pacing};
+
+/// How `download` reads each object.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ReadMode {
+    /// Ranged requests of one block each, interleaved across objects
+    #[default]
+    Ranged,
+    /// One streaming get per object
+    Sequential,
+}
+
+impl ReadMode {
+    pub fn name(self) -> &'static str {
+        match self {
+            ReadMode::Ranged => "ranged",
+            ReadMode::Sequential => "sequential",
+        }
+    }
+}
+
+/// One object split into fixed-size blocks, the last of which may be short.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockPlan {
+    pub object_size: u64,
+    pub block_size: u64,
+}
+
+impl BlockPlan {
+    pub fn new(object_size: u64, block_size: u64) -> Self {
+        Self {
+            object_size,
+            block_size: block_size.max(1),
+        }
+    }
+
+    pub fn num_blocks(&self) -> u64 {
+        self.object_size.div_ceil(self.block_size)
+    }
+
+    /// Byte range of block `i`. Block starts are below the object size, so
+    /// neither bound can overflow.
+    pub fn block(&self, i: u64) -> Range<u64> {
+        let start = i * self.block_size;
+        start..start.saturating_add(self.block_size).min(self.object_size)
+    }
+
+    pub fn blocks(self) -> impl Iterator<Item = Range<u64>> {
+        (0..self.num_blocks()).map(move |i| self.block(i))
+    }
+}
+
+/// Blocks aren't split smaller than this unless a block size is given, so
+/// objects below it are fetched whole.
+pub const MIN_BLOCK_SIZE: usize = 64 << 10;
+
+/// The block size for objects of which the largest is `largest` bytes:
+/// `block_size` if given, else the largest object split evenly across the
+/// `parallel_downloads`, but no smaller than [`MIN_BLOCK_SIZE`].
+pub fn block_size_for(
+    largest: usize,
+    parallel_downloads: usize,
+    block_size: Option<usize>,
+) -> usize {
+    block_size
+        .unwrap_or_else(|| (largest / parallel_downloads.max(1)).max(MIN_BLOCK_SIZE))
+        .max(1)
+}
+
+/// `range` as the `usize` range object_store takes, if this target can
+/// address it.
+pub fn to_usize_range(range: Range<u64>) -> Option<Range<usize>> {
+    Some(usize::try_from(range.start).ok()?..usize::try_from(range.end).ok()?)
+}
+
+/// Plans downloading one synthetic object of `object_size` bytes and checks
+/// that the blocks are contiguous and account for every byte, without
+/// issuing any requests. Blocks are checked one at a time as they are
+/// planned, so nothing grows with the object.
+pub fn check_huge_object(
+    object_size: u64,
+    block_size: Option<usize>,
+    parallel_downloads: usize,
+    control: &RunControl,
+) -> Result<(), Error> {
+    let block_size = block_size.map_or(
+        (object_size / parallel_downloads.max(1) as u64).max(MIN_BLOCK_SIZE as u64),
+        |b| b as u64,
+    );
+    let plan = BlockPlan::new(object_size, block_size);
+    let mut next = 0;
+    let mut planned_bytes: u64 = 0;
+    let mut num_blocks: u64 = 0;
+    let mut unaddressable = 0;
+    let mut last_block = None;
+    for range in plan.blocks() {
+        if range.start != next || range.is_empty() || range.end - range.start > plan.block_size {
+            return Err(format!(
+                "block {} is {:?}, expected a non-empty range of at most {} bytes from {}",
+                num_blocks, range, plan.block_size, next
+            )
+            .into());
+        }
+        if to_usize_range(range.clone()).is_none() {
+            unaddressable += 1;
+        }
+        planned_bytes += range.end - range.start;
+        next = range.end;
+        num_blocks += 1;
+        last_block = Some(range);
+    }
+    if planned_bytes != object_size || num_blocks != plan.num_blocks() {
+        return Err(format!(
+            "planned {} bytes in {} blocks for a {} byte object, expected {} blocks",
+            planned_bytes,
+            num_blocks,
+            object_size,
+            plan.num_blocks()
+        )
+        .into());
+    }
+    control.emit(json!({
+        "mode": "huge_object_plan",
+        "object_size": object_size,
+        "block_size": plan.block_size,
+        "num_blocks": num_blocks,
+        "planned_bytes": planned_bytes,
+        "last_block": last_block.map(|r| [r.start, r.end]),
+        "unaddressable_blocks": unaddressable,
+        "mbps_if_read_in_an_hour": mbps(planned_bytes, 3_600_000_000),
+    }));
+    if unaddressable > 0 {
+        return Err(format!(
+            "{} blocks lie beyond the {} bytes this target can address",
+            unaddressable,
+            usize::MAX
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Parameters for [`parallel_download_bench`].
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Maximum number of requests to make in parallel
+    pub parallel_downloads: usize,
+    /// Size of each block to download; picked from the largest object if unset
+    pub block_size: Option<usize>,
+    /// Whether to read in ranged blocks or stream each object whole
+    pub read_mode: ReadMode,
+    /// Cycle through the blocks until this long has passed rather than
+    /// reading each once, see [`crate::duration`]
+    pub duration: Option<Duration>,
+    /// Stream and drain each block no faster than this rate, simulating a
+    /// slow consumer
+    pub consume_mbps: Option<f64>,
+    /// Drop requests still running at the deadline and count them as
+    /// expired, see [`crate::deadline`]
+    pub deadline: Option<DeadlineSpec>,
+    /// Think time each slot waits after every completed block before issuing
+    /// its next request, see [`crate::think_time`]
+    pub pacing: Option<Pacing>,
+    /// Check every block against the offset pattern, see [`crate::pattern`];
+    /// the run fails after reporting if any mismatch
+    pub verify: bool,
+    /// Length of the intervals latency and throughput are also reported over
+    pub window: Duration,
+    /// Also report the bytes and requests completed in each second of the
+    /// run, see [`crate::stats::timeline`]
+    pub timeline: bool,
+}
+
+impl Default for DownloadOptions {
+    /// One request at a time, each object read once in ranged blocks, with
+    /// the command line's ten-second windows.
+    fn default() -> Self {
+        Self {
+            parallel_downloads: 1,
+            block_size: None,
+            read_mode: ReadMode::Ranged,
+            duration: None,
+            consume_mbps: None,
+            deadline: None,
+            pacing: None,
+            verify: false,
+            window: Duration::from_secs(10),
+            timeline: false,
+        }
+    }
+}
 
 /// Benchmarks the approach of downloading an object in parallel
 ///
 /// * `location`: where the test object should be made
-/// * `parallel_downloads`: maximum number of requests to make in parallel
-/// * `block_size`: size of each block to download
+/// * `options`: how to read it, see [`DownloadOptions`]
+/// * `retry`: retry policy applied to each range request; a request that
+///   fails for good either ends the run or, where [`RetryPolicy::absorb`]
+///   allows, is left out and fails the run after reporting
+/// * `control`: live counters, and the pause/shutdown switches for the run
+///
+/// Returns the result to report, with any check made on it that failed.
 pub async fn parallel_download_bench(
     object_store: Arc<dyn ObjectStore>,
     location: Path,
-    parallel_downloads: usize,
-    block_size: Option<usize>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let objects = inspect_location(object_store.as_ref(), &location).await?;
-    let object_size = objects[0].size;
-    assert!(
-        objects.iter().all(|o| o.size == object_size),
-        "expected all objects to have the same size"
+    options: DownloadOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<Checked<DownloadResult>, Error> {
+    let DownloadOptions {
+        parallel_downloads,
+        block_size,
+        read_mode,
+        duration,
+        consume_mbps,
+        deadline,
+        pacing,
+        verify,
+        window,
+        timeline,
+    } = options;
+    if deadline.is_some() && consume_mbps.is_some() {
+        return Err("a request deadline can't be combined with --consume-mbps".into());
+    }
+    if verify && (deadline.is_some() || consume_mbps.is_some()) {
+        return Err("--verify can't be combined with a request deadline or --consume-mbps".into());
+    }
+    let sequential = read_mode == ReadMode::Sequential;
+    if sequential && (deadline.is_some() || consume_mbps.is_some() || verify) {
+        return Err(
+            "--mode sequential can't be combined with a request deadline, --consume-mbps or --verify"
+                .into(),
+        );
+    }
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    // Zero-byte markers such as `_SUCCESS` have nothing to download.
+    let (empty, objects): (Vec<_>, Vec<_>) = objects.into_iter().partition(|o| o.size == 0);
+    let Some(largest) = objects.iter().map(|o| o.size).max() else {
+        return Err(format!("every object under {} is empty", location).into());
+    };
+    let tracker = MissingTracker::new(
+        object_store.clone(),
+        &location,
+        &objects,
+        control.listing(),
+        &retry,
     );
+    let accounting = Accounting::whole(&objects);
+    let block_size = block_size_for(largest, parallel_downloads, block_size);
+    // Objects smaller than one block are fetched whole with a single get, as
+    // are all objects when reading sequentially.
+    let (small, objects): (Vec<_>, Vec<_>) = objects
+        .into_iter()
+        .partition(|o| sequential || o.size < block_size);
+    // Each object is split into blocks of its own; the last may be short.
+    let plans = objects
+        .iter()
+        .map(|meta| BlockPlan::new(meta.size as u64, block_size as u64))
+        .collect::<Vec<_>>();
+    let num_blocks = plans.iter().map(BlockPlan::num_blocks).max().unwrap_or(0);
+    let total_blocks: u64 = plans.iter().map(BlockPlan::num_blocks).sum();
+    let measurement = duration.map(|requested| MeasurementWindow { requested });
 
-    let block_size = block_size.unwrap_or(object_size / parallel_downloads);
-    let num_blocks = (object_size + block_size - 1) / block_size;
-
-    // Make requests interleaving across objects.
-    let objects_ref = &objects;
-    let ranges_iter = (0..num_blocks).flat_map(move |block_i| {
-        let start = block_i * block_size;
-        let end = std::cmp::min((block_i + 1) * block_size, object_size);
-        objects_ref
-            .iter()
-            .map(move |meta| (meta.location.clone(), start..end))
-            .collect::<Vec<_>>()
+    // Make requests interleaving across objects, after the whole-object gets:
+    // block i of every object that has one before any block i + 1.
+    let (objects_ref, plans_ref) = (&objects, &plans);
+    let ranges_iter = small
+        .iter()
+        .map(|meta| (meta.location.clone(), None, meta.size))
+        .chain((0..num_blocks).flat_map(move |block_i| {
+            objects_ref
+                .iter()
+                .zip(plans_ref)
+                .filter(move |(_, plan)| block_i < plan.num_blocks())
+                .map(move |(meta, plan)| {
+                    // Blocks lie within an object whose size is a usize.
+                    let range = to_usize_range(plan.block(block_i)).unwrap();
+                    (meta.location.clone(), Some(range), meta.size)
+                })
+                .collect::<Vec<_>>()
+        }));
+    // A duration-bounded run goes round the blocks until its window closes.
+    let ranges_iter = ranges_iter.cycle().take(match measurement {
+        Some(_) => usize::MAX,
+        None => small.len() + total_blocks as usize,
     });
 
-    // TODO: add tracing
+    if measurement.is_none() {
+        control.set_bytes_total(objects.iter().chain(&small).map(|o| o.size as u64).sum());
+    }
+
+    tracing::info!(
+        location = %location,
+        objects = objects.len() + small.len(),
+        num_blocks,
+        block_size,
+        parallel_downloads,
+        "download started"
+    );
+    control.track_phases(parallel_downloads);
     let start = std::time::Instant::now();
-    let _counts = futures::stream::iter(ranges_iter)
-        .map(|(location, range)| fetch_range_len(object_store.clone(), location, range))
+    let run_start = start;
+    let tracker = &tracker;
+    let mut by_size = control.context().size_buckets();
+    for meta in small.iter().chain(&objects) {
+        by_size.add_object(meta.size);
+    }
+    let deadlines = deadline.map(Deadlines::new);
+    let deadlines = &deadlines;
+    let pacer = pacing.map(Pacer::new);
+    let pacer = &pacer;
+    let verification = verify.then(Verification::default);
+    let verification = &verification;
+    // Requests the run went on without, by object, see `RetryPolicy::absorb`.
+    let failed = Mutex::new(HashMap::<Path, usize>::new());
+    let failed = &failed;
+    let aggregate = futures::stream::iter(ranges_iter)
+        .take_while(|_| {
+            let open = measurement
+                .is_none_or(|measurement| measurement.is_open(start.elapsed(), control.paused()));
+            futures::future::ready(open && !control.is_shutdown())
+        })
+        .map(|(location, range, object_size)| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            async move {
+                // The rest of a vanished object's blocks are never requested.
+                if tracker.is_vanished(&location) || !control.request_started().await {
+                    return Ok(None);
+                }
+                match &range {
+                    Some(range) => control.record("get_range", &location, Some(range)),
+                    None => control.record("get", &location, None),
+                }
+                let start = std::time::Instant::now();
+                let issued = start - run_start;
+                let whole = range.is_none();
+                let (object_store, request_location, retry, range) =
+                    (&object_store, &location, &retry, &range);
+                let outcome = tracker
+                    .resolve(&location, move || async move {
+                        if let Some(deadlines) = deadlines {
+                            return deadlines
+                                .fetch(
+                                    object_store.as_ref(),
+                                    request_location,
+                                    range.clone(),
+                                    retry,
+                                )
+                                .await
+                                .map(|fetched| match fetched {
+                                    Fetched::Met(bytes) => StreamOutcome::complete(bytes),
+                                    Fetched::Expired => StreamOutcome::expired(),
+                                })
+                                .map_err(Into::into);
+                        }
+                        match (range.clone(), consume_mbps) {
+                            (None, _) if sequential => stream_len(
+                                object_store.clone(),
+                                request_location.clone(),
+                                object_size,
+                                retry.clone(),
+                            )
+                            .await
+                            .map(StreamOutcome::complete),
+                            (None, _) => fetch(
+                                object_store.clone(),
+                                request_location.clone(),
+                                retry.clone(),
+                            )
+                            .await
+                            .map(|data| StreamOutcome::fetched(data, verify)),
+                            (Some(range), Some(consume_mbps)) => {
+                                stream_range_len(
+                                    object_store.clone(),
+                                    request_location.clone(),
+                                    range,
+                                    retry.clone(),
+                                    consume_mbps,
+                                )
+                                .await
+                            }
+                            (Some(range), None) => fetch_range(
+                                object_store.clone(),
+                                request_location.clone(),
+                                range,
+                                retry.clone(),
+                            )
+                            .await
+                            .map(|data| StreamOutcome::fetched(data, verify)),
+                        }
+                    })
+                    .await;
+                let latency = start.elapsed();
+                let mut outcome = outcome;
+                if let (Some(verification), Ok(Some(outcome))) = (verification, &mut outcome) {
+                    if let Some(data) = outcome.data.take() {
+                        let range = range.clone().unwrap_or(0..object_size);
+                        verification.check(&location, range.start as u64..range.end as u64, &data);
+                    }
+                }
+                match &outcome {
+                    Ok(Some(outcome)) if outcome.expired => control.request_expired(),
+                    Ok(Some(outcome)) => control.request_finished(outcome.bytes),
+                    Ok(None) | Err(_) => control.request_failed(),
+                }
+                let outcome = match outcome {
+                    Err(err) if retry.absorb(&err) => {
+                        *failed.lock().unwrap().entry(location.clone()).or_default() += 1;
+                        Ok(None)
+                    }
+                    outcome => outcome,
+                };
+                // The slot stays busy through the think time.
+                if let Some(pacer) = pacer {
+                    pacer.think().await;
+                }
+                outcome.map(|outcome| {
+                    outcome.map(|outcome| BlockSample {
+                        location,
+                        outcome,
+                        whole,
+                        object_size,
+                        issued,
+                        latency,
+                    })
+                })
+            }
+        })
         .buffer_unordered(parallel_downloads)
-        .try_collect::<Vec<_>>()
+        .take_until(control.abandoned())
+        // Expired requests moved no bytes the run can use.
+        .try_filter_map(|outcome| {
+            futures::future::ready(Ok(outcome.filter(|sample| !sample.outcome.expired)))
+        })
+        .try_fold(RunAggregate::new(by_size), |mut aggregate, sample| {
+            if measurement.is_some_and(|measurement| {
+                measurement.is_late(sample.issued + sample.latency, control.paused())
+            }) {
+                aggregate.add_late(sample);
+                return futures::future::ready(Ok(aggregate));
+            }
+            accounting.received(&sample.location, sample.outcome.bytes);
+            aggregate.add(sample);
+            futures::future::ready(Ok(aggregate))
+        })
         .await?;
-    let end = std::time::Instant::now();
+    // The run ends when its last request completes, not when the stream
+    // has been drained.
+    let elapsed = aggregate.last_completed.unwrap_or_else(|| start.elapsed());
+    // A duration-bounded run is measured over its window.
+    let elapsed = measurement.map_or(elapsed, |measurement| {
+        measurement.measured(elapsed, control.paused(), control.is_shutdown())
+    });
 
-    let elapsed_us = (end - start).as_micros();
-    let total_size = object_size * objects.len();
-    let mbps = total_size as f64 / 1024.0 / 1024.0 / (elapsed_us as f64 / 1_000_000.0);
+    // Time spent paused is excluded from throughput.
+    let aggregation_start = std::time::Instant::now();
+    let elapsed_us = elapsed.as_micros();
+    let paused_us = control.paused().as_micros();
+    let mbps = mbps(aggregate.bytes, active_us(elapsed_us, paused_us));
+    let streaming = consume_mbps.map(|consume_mbps| Streaming {
+        consume_mbps,
+        bytes_received: aggregate.bytes,
+        stream_errors: aggregate.stream_errors,
+        stream_timeouts: aggregate.stream_timeouts,
+    });
+    let successful_requests = aggregate.requests;
+    let num_requests = successful_requests + failed.lock().unwrap().values().sum::<usize>();
+    let total_size = aggregate.bytes;
+    let pacing = pacer.as_ref().map(|pacer| {
+        let requests = aggregate
+            .timed
+            .iter()
+            .map(|sample| (sample.completed - sample.latency, sample.completed))
+            .collect();
+        pacer.json_field(requests, elapsed)
+    });
+    let verify = verification.as_ref().map(Verification::json_field);
+    let duration = measurement.map(|measurement| {
+        measurement.report(elapsed, aggregate.late_requests, aggregate.late_bytes)
+    });
+    let timeline = timeline.then(|| stats::timeline(&aggregate.timed, elapsed));
+    let mut fields = aggregate.summarize(window, elapsed, control.phases(start));
+    let aggregation_us = aggregation_start.elapsed().as_micros();
+    // Cycling reads blocks more than once, so there is nothing to check.
+    let accounting = accounting.check(control.is_shutdown() || measurement.is_some(), |path| {
+        tracker.is_vanished(path)
+            || deadlines.as_ref().is_some_and(|d| d.skipped(path))
+            || failed.lock().unwrap().contains_key(path)
+    });
+    tracing::info!(
+        requests = num_requests,
+        bytes = total_size,
+        elapsed_us = elapsed_us as u64,
+        mbps,
+        "download finished"
+    );
+    fields.extend(timeline.into_iter().flatten());
+    fields.extend(accounting.json_field());
+    fields.extend(tracker.json_field());
+    fields.extend(retry.json_fields());
+    fields.extend(pacing.into_iter().flatten());
+    fields.extend(verify.into_iter().flatten());
 
-    println!("{{\"num_objects\": {}, \"num_blocks\": {}, \"block_size\": {}, \"parallel_downloads\": {}, \"elapsed_us\": {}, \"mbps\": {}}}",
-    objects.len(), num_blocks, block_size, parallel_downloads, elapsed_us, mbps);
-    Ok(())
+    let result = DownloadResult {
+        mode: "download",
+        num_objects: objects.len() + small.len(),
+        zero_byte_objects: empty.len(),
+        num_blocks,
+        block_size,
+        parallel_downloads,
+        num_requests,
+        successful_requests,
+        bytes: total_size,
+        elapsed_us,
+        paused_us,
+        aggregation_us,
+        mbps,
+        read_mode: read_mode.name(),
+        interrupted: control.is_shutdown(),
+        streaming,
+        deadline: deadlines
+            .as_ref()
+            .map(|deadlines| deadlines.report(active_us(elapsed_us, paused_us))),
+        duration,
+        fields,
+    };
+    let failure = accounting
+        .enforce(control.context().strict_accounting)
+        .and_then(|()| retry.enforce())
+        .and_then(|()| verification.as_ref().map_or(Ok(()), Verification::enforce))
+        .err();
+    Ok(Checked { result, failure })
+}
+
+/// One completed block request.
+struct BlockSample {
+    location: Path,
+    outcome: StreamOutcome,
+    /// Whether this was a whole-object get of a small object
+    whole: bool,
+    /// Size of the object the block belongs to
+    object_size: usize,
+    /// When the request was issued, relative to the start of the run
+    issued: std::time::Duration,
+    latency: std::time::Duration,
 }
 
-#[instrument(skip(object_store))]
-async fn fetch_range_len(
+/// Requests to one object: the span from its first being issued to its
+/// last completing, and what they moved.
+#[derive(Debug, Clone, Copy, Default)]
+struct ObjectSpan {
+    first_issued: Duration,
+    last_completed: Duration,
+    requests: usize,
+    bytes: u64,
+    latency_us: u128,
+}
+
+impl ObjectSpan {
+    fn add(&mut self, other: &ObjectSpan) {
+        self.requests += other.requests;
+        self.bytes += other.bytes;
+        self.latency_us += other.latency_us;
+    }
+
+    fn busy_us(&self) -> u128 {
+        (self.last_completed - self.first_issued).as_micros()
+    }
+}
+
+/// Everything reported about a download, folded in as each request
+/// completes so that little is left to do after the last byte arrives.
+///
+/// Counters, the histogram and the per-object spans are final as soon as the
+/// run is; only the percentiles and windows are worked out by
+/// [`RunAggregate::summarize`], and that is timed as `aggregation_us`.
+#[derive(Default)]
+struct RunAggregate {
+    requests: usize,
+    bytes: u64,
+    stream_errors: usize,
+    stream_timeouts: usize,
+    /// When the last request completed, relative to the start of the run
+    last_completed: Option<Duration>,
+    /// Requests of a duration-bounded run that completed after its window
+    late_requests: usize,
+    late_bytes: u64,
+    timed: Vec<TimedSample>,
+    ranged_latencies: Vec<Duration>,
+    histogram: Histogram,
+    /// Whole-object gets of small objects are summarized separately, since
+    /// they behave differently from ranged reads.
+    whole_latencies: Vec<Duration>,
+    whole_bytes: u64,
+    /// The object owning the first completed ranged request
+    first_object: Option<Path>,
+    objects: HashMap<Path, ObjectSpan>,
+    by_size: SizeBuckets,
+}
+
+impl RunAggregate {
+    /// An empty aggregate, with the run's objects already counted in
+    /// `by_size`.
+    fn new(by_size: SizeBuckets) -> Self {
+        Self {
+            by_size,
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, sample: BlockSample) {
+        let completed = sample.issued + sample.latency;
+        let bytes = sample.outcome.bytes as u64;
+        self.requests += 1;
+        self.bytes += bytes;
+        self.stream_errors += sample.outcome.error as usize;
+        self.stream_timeouts += sample.outcome.timed_out as usize;
+        self.last_completed = self.last_completed.max(Some(completed));
+        self.timed.push(TimedSample {
+            completed,
+            latency: sample.latency,
+            bytes: sample.outcome.bytes,
+            error: sample.outcome.error,
+        });
+        self.by_size
+            .record(sample.object_size, sample.latency, sample.outcome.bytes);
+        if sample.whole {
+            self.whole_latencies.push(sample.latency);
+            self.whole_bytes += bytes;
+            return;
+        }
+        self.ranged_latencies.push(sample.latency);
+        self.histogram.record(sample.latency);
+        if self.first_object.is_none() {
+            self.first_object = Some(sample.location.clone());
+        }
+        // Per-object spans, so idle time between objects doesn't count.
+        let span = self.objects.entry(sample.location).or_insert(ObjectSpan {
+            first_issued: sample.issued,
+            last_completed: completed,
+            ..Default::default()
+        });
+        span.first_issued = span.first_issued.min(sample.issued);
+        span.last_completed = span.last_completed.max(completed);
+        span.requests += 1;
+        span.bytes += bytes;
+        span.latency_us += sample.latency.as_micros();
+    }
+
+    /// Count a request that completed after the measurement window: in the
+    /// latencies, but not in the bytes or anything derived from them.
+    fn add_late(&mut self, sample: BlockSample) {
+        self.late_requests += 1;
+        self.late_bytes += sample.outcome.bytes as u64;
+        if sample.whole {
+            self.whole_latencies.push(sample.latency);
+        } else {
+            self.ranged_latencies.push(sample.latency);
+            self.histogram.record(sample.latency);
+        }
+    }
+
+    /// The latency, window, phase, per-object and size class fields of the
+    /// result, for a run lasting `elapsed`.
+    fn summarize(
+        mut self,
+        window: Duration,
+        elapsed: Duration,
+        phase_boundaries: Option<PhaseBoundaries>,
+    ) -> Fields {
+        let mut summary = fields(json!({
+            "small_objects": {
+                "count": self.whole_latencies.len(),
+                "bytes": self.whole_bytes,
+                "latency": LatencySummary::from_latencies(&mut self.whole_latencies).to_json(),
+            },
+            "latency": LatencySummary::from_latencies(&mut self.ranged_latencies).to_json(),
+            "latency_histogram": self.histogram.to_json(),
+        }));
+        summary.extend(windowed(&self.timed, window, elapsed));
+        summary.extend(phases(&self.timed, phase_boundaries, elapsed));
+        if self.objects.len() > 1 {
+            summary.insert("first_object".to_string(), self.first_object_penalty());
+        }
+        summary.extend(self.by_size.json_field());
+        summary
+    }
+
+    /// Latency and throughput of the first object touched against every
+    /// other object.
+    ///
+    /// Requests interleave across objects, so the "first object" is the one
+    /// that owns the first completed request. An object's throughput is its
+    /// bytes over the span from its first request being issued to its last
+    /// completing. The startup cost is the first object's mean request
+    /// latency minus that of the other objects.
+    fn first_object_penalty(&self) -> Value {
+        let Some(first) = &self.first_object else {
+            return Value::Null;
+        };
+        let first_span = self.objects[first];
+        let mut rest = ObjectSpan::default();
+        let mut rest_busy_us = 0;
+        for (_, span) in self.objects.iter().filter(|(path, _)| *path != first) {
+            rest.add(span);
+            rest_busy_us += span.busy_us();
+        }
+        let mean_latency_us =
+            |span: &ObjectSpan| span.latency_us as f64 / span.requests.max(1) as f64;
+        let first_latency_us = mean_latency_us(&first_span);
+        let rest_latency_us = mean_latency_us(&rest);
+        json!({
+            "path": first.to_string(),
+            "requests": first_span.requests,
+            "mean_latency_us": first_latency_us,
+            "mbps": mbps(first_span.bytes, first_span.busy_us()),
+            "rest": {
+                "num_objects": self.objects.len() - 1,
+                "requests": rest.requests,
+                "mean_latency_us": rest_latency_us,
+                "mbps": mbps(rest.bytes, rest_busy_us),
+            },
+            "startup_cost_us": first_latency_us - rest_latency_us,
+        })
+    }
+}
+
+/// Bytes received for one block, and whether its body stream failed part way
+/// or the request missed its deadline.
+struct StreamOutcome {
+    bytes: usize,
+    error: bool,
+    timed_out: bool,
+    expired: bool,
+    /// The body, kept only until it has been verified
+    data: Option<Bytes>,
+}
+
+impl StreamOutcome {
+    fn complete(bytes: usize) -> Self {
+        Self {
+            bytes,
+            error: false,
+            timed_out: false,
+            expired: false,
+            data: None,
+        }
+    }
+
+    /// A fetched body, kept when it is to be verified.
+    fn fetched(data: Bytes, keep: bool) -> Self {
+        Self {
+            data: keep.then(|| data.clone()),
+            ..Self::complete(data.len())
+        }
+    }
+
+    fn expired() -> Self {
+        Self {
+            expired: true,
+            ..Self::complete(0)
+        }
+    }
+}
+
+/// Fetches a range via `get_opts` and drains the body stream chunk by chunk,
+/// sleeping between chunks so the overall rate stays at or below `consume_mbps`.
+///
+/// Errors while draining the body are recorded in the outcome rather than
+/// failing the run, since they are exactly what this mode is meant to surface.
+#[instrument(skip(object_store, retry))]
+async fn stream_range_len(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    range: std::ops::Range<usize>,
+    retry: RetryPolicy,
+    consume_mbps: f64,
+) -> Result<StreamOutcome, Error> {
+    Ok(tokio::task::spawn(async move {
+        let result = retry
+            .run(|| {
+                let options = GetOptions {
+                    range: Some(range.clone()),
+                    ..Default::default()
+                };
+                object_store.get_opts(&location, options)
+            })
+            .await?;
+
+        let bytes_per_sec = consume_mbps * 1024.0 * 1024.0;
+        let start = std::time::Instant::now();
+        let mut outcome = StreamOutcome::complete(0);
+        // The local and in-memory stores ignore `GetOptions::range` in this
+        // version of object_store and return the whole object, so stop once
+        // the requested length has been drained.
+        let expected = range.len();
+        let mut stream = result.into_stream();
+        while outcome.bytes < expected {
+            let Some(chunk) = stream.next().await else {
+                // The body ended before the requested range was delivered.
+                outcome.error = true;
+                break;
+            };
+            match chunk {
+                Ok(chunk) => {
+                    outcome.bytes += std::cmp::min(chunk.len(), expected - outcome.bytes);
+                    let target =
+                        std::time::Duration::from_secs_f64(outcome.bytes as f64 / bytes_per_sec);
+                    if let Some(remaining) = target.checked_sub(start.elapsed()) {
+                        tokio::time::sleep(remaining).await;
+                    }
+                }
+                Err(err) => {
+                    outcome.error = true;
+                    outcome.timed_out = is_timeout(&err);
+                    break;
+                }
+            }
+        }
+        Ok::<_, object_store::Error>(outcome)
+    })
+    .await??)
+}
+
+/// Streams all of `location` with one get, counting the bytes of each chunk,
+/// and fails if the body doesn't hold exactly `size` bytes.
+#[instrument(skip(object_store, retry))]
+async fn stream_len(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    size: usize,
+    retry: RetryPolicy,
+) -> Result<usize, Error> {
+    Ok(tokio::task::spawn(async move {
+        retry
+            .run(|| async {
+                let mut stream = object_store.get(&location).await?.into_stream();
+                let mut received = 0;
+                while let Some(chunk) = stream.next().await {
+                    received += chunk?.len();
+                }
+                if received != size {
+                    return Err(object_store::Error::Generic {
+                        store: "sequential",
+                        source: format!(
+                            "{} streamed {} bytes but was listed with {}",
+                            location, received, size
+                        )
+                        .into(),
+                    });
+                }
+                Ok(received)
+            })
+            .await
+    })
+    .await??)
+}
+
+#[instrument(skip(object_store, retry))]
+async fn fetch(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    retry: RetryPolicy,
+) -> Result<Bytes, Error> {
+    Ok(tokio::task::spawn(async move {
+        retry
+            .run(|| async { object_store.get(&location).await?.bytes().await })
+            .await
+    })
+    .await??)
+}
+
+#[instrument(skip(object_store, retry))]
+async fn fetch_range(
     object_store: Arc<dyn ObjectStore>,
     location: Path,
     range: std::ops::Range<usize>,
-) -> Result<usize, Box<dyn std::error::Error>> {
+    retry: RetryPolicy,
+) -> Result<Bytes, Error> {
     Ok(tokio::task::spawn(async move {
-        object_store
-            .get_range(&location, range)
+        retry
+            .run(|| object_store.get_range(&location, range.clone()))
             .await
-            .map(|res| res.len())
     })
     .await??)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    #[test]
+    fn blocks_straddle_the_4gib_boundary() {
+        let plan = BlockPlan::new(4 * GIB + 1, GIB);
+        assert_eq!(plan.num_blocks(), 5);
+        assert_eq!(plan.block(3), 3 * GIB..4 * GIB);
+        assert_eq!(plan.block(4), 4 * GIB..4 * GIB + 1);
+        assert_eq!(
+            to_usize_range(plan.block(4)),
+            Some(4 * GIB as usize..4 * GIB as usize + 1)
+        );
+
+        // A block size that doesn't divide the object still covers it exactly.
+        let plan = BlockPlan::new(4 * GIB, 3 * GIB);
+        assert_eq!(
+            plan.blocks().collect::<Vec<_>>(),
+            [0..3 * GIB, 3 * GIB..4 * GIB]
+        );
+    }
+
+    #[test]
+    fn blocks_past_2_pow_53_stay_exact() {
+        let size = (1 << 53) + 3;
+        let plan = BlockPlan::new(size, 1 << 20);
+        assert_eq!(plan.num_blocks(), (1 << 33) + 1);
+        assert_eq!(plan.block(1 << 33), 1 << 53..size);
+        assert_eq!(plan.block((1 << 33) - 1).end, 1 << 53);
+
+        // No product overflows when the last block starts near u64::MAX.
+        let plan = BlockPlan::new(u64::MAX, u64::MAX / 2);
+        assert_eq!(plan.block(2), u64::MAX - 1..u64::MAX);
+    }
+
+    #[test]
+    fn throughput_keeps_its_precision_past_2_pow_53_bytes() {
+        let bytes = (1u64 << 53) + 12345;
+        let expected = (1u64 << 33) as f64 + 12345.0 / (1 << 20) as f64;
+        assert!((mbps(bytes, 1_000_000) - expected).abs() < 1e-5);
+        assert_eq!(mbps(4 * GIB, 2_000_000), 2048.0);
+    }
+
+    #[test]
+    fn checks_a_20_tib_object_without_requests() {
+        let control = RunControl::new();
+        check_huge_object(20 * 1024 * GIB + 7, Some(64 << 20), 10, &control).unwrap();
+        check_huge_object(20 * 1024 * GIB, None, 64, &control).unwrap();
+    }
+
+    #[test]
+    fn aggregates_hundreds_of_thousands_of_samples_quickly() {
+        let objects = (0..1000)
+            .map(|i| Path::from(format!("data/object_{}.bin", i)))
+            .collect::<Vec<_>>();
+        let mut aggregate = RunAggregate::default();
+        // 300k blocks completing over 5 minutes, the first object's slower.
+        let num_samples = 300_000;
+        for i in 0..num_samples {
+            let object = i % objects.len();
+            let latency = Duration::from_micros(if object == 0 { 9_000 } else { 1_000 });
+            aggregate.add(BlockSample {
+                location: objects[object].clone(),
+                outcome: StreamOutcome::complete(1024),
+                whole: false,
+                object_size: 1 << 20,
+                issued: Duration::from_micros(i as u64 * 1_000),
+                latency,
+            });
+        }
+        let elapsed = aggregate.last_completed.unwrap();
+        assert_eq!(elapsed, Duration::from_micros(299_999_000 + 1_000));
+        assert_eq!(aggregate.requests, num_samples);
+        assert_eq!(aggregate.bytes, num_samples as u64 * 1024);
+
+        let start = std::time::Instant::now();
+        let summary = aggregate.summarize(Duration::from_secs(1), elapsed, None);
+        let aggregation = start.elapsed();
+        assert!(
+            aggregation < Duration::from_secs(5),
+            "aggregation took {:?}",
+            aggregation
+        );
+
+        assert_eq!(summary["latency"]["count"], num_samples);
+        assert_eq!(summary["windows"].as_array().unwrap().len(), 300);
+        // Windows end at the last completion, not when summarizing finished.
+        let last_window = summary["windows"].as_array().unwrap().last().unwrap();
+        assert_eq!(last_window["end_s"], 300.0);
+        assert_eq!(summary["first_object"]["rest"]["num_objects"], 999);
+        assert_eq!(summary["first_object"]["startup_cost_us"], 8_000.0);
+        assert_eq!(summary["by_size_bucket"][1]["requests"], num_samples);
+    }
+
+    #[test]
+    fn results_serialize_as_valid_json() {
+        let result = DownloadResult {
+            mode: "download",
+            num_objects: 1,
+            zero_byte_objects: 0,
+            num_blocks: 1,
+            block_size: 10,
+            parallel_downloads: 1,
+            num_requests: 1,
+            successful_requests: 1,
+            bytes: 10,
+            elapsed_us: 0,
+            paused_us: 0,
+            aggregation_us: 0,
+            // A run too short to time.
+            mbps: f64::INFINITY,
+            read_mode: "ranged",
+            interrupted: false,
+            streaming: None,
+            deadline: None,
+            duration: None,
+            fields: fields(json!({"retries": 0})),
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&result).unwrap()).unwrap();
+        assert_eq!(json["mbps"], serde_json::Value::Null);
+        assert_eq!(json["retries"], 0);
+        assert!(json.get("consume_mbps").is_none());
+        assert!(json.get("deadline").is_none());
+    }
+
+    #[tokio::test]
+    async fn objects_of_different_sizes_are_each_read_in_full() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(object_store::memory::InMemory::new());
+        for (name, size) in [("a", 3000), ("b", 10_000), ("c", 20_000)] {
+            object_store
+                .put(
+                    &Path::from(format!("data/{}", name)),
+                    Bytes::from(vec![1; size]),
+                )
+                .await
+                .unwrap();
+        }
+        let checked = parallel_download_bench(
+            object_store,
+            Path::from("data"),
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(4096),
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        // One whole get of the small object, then 3 and 5 blocks.
+        assert_eq!(result["num_requests"], 9);
+        assert_eq!(result["num_blocks"], 5);
+        assert_eq!(result["bytes"], 33_000);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+        // The `head` telling the prefix from an object is no error.
+        assert_eq!(result["error_kinds"], json!({}));
+        assert_eq!(result["real_errors"], 0);
+    }
+
+    #[tokio::test]
+    async fn tiny_objects_are_fetched_whole() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(object_store::memory::InMemory::new());
+        for i in 0..4 {
+            object_store
+                .put(
+                    &Path::from(format!("data/{}", i)),
+                    Bytes::from_static(b"abc"),
+                )
+                .await
+                .unwrap();
+        }
+        let checked = parallel_download_bench(
+            object_store,
+            Path::from("data"),
+            DownloadOptions {
+                parallel_downloads: 8,
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        assert_eq!(result["block_size"], MIN_BLOCK_SIZE);
+        assert_eq!(result["num_requests"], 4);
+        assert_eq!(result["num_blocks"], 0);
+        assert_eq!(result["bytes"], 12);
+        assert_eq!(result["small_objects"]["count"], 4);
+        assert_eq!(result["small_objects"]["bytes"], 12);
+    }
+
+    #[tokio::test]
+    async fn abandoned_requests_leave_a_partial_result() {
+        let memory: Arc<dyn ObjectStore> = Arc::new(object_store::memory::InMemory::new());
+        memory
+            .put(&Path::from("data/a"), Bytes::from(vec![1; 64 << 10]))
+            .await
+            .unwrap();
+        // The head answers after a second, and the first read a second later.
+        let simulation = crate::simulate::Simulation {
+            latency: Some("1000".parse().unwrap()),
+            bandwidth_mbps: None,
+        };
+        let object_store = Arc::new(crate::simulate::SimulatedStore::new(memory, simulation));
+        let control = RunControl::new();
+        let interrupt = {
+            let control = control.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(1300)).await;
+                control.shutdown();
+                control.abandon();
+            }
+        };
+        let start = std::time::Instant::now();
+        let (outcome, _) = tokio::join!(
+            parallel_download_bench(
+                object_store,
+                Path::from("data"),
+                DownloadOptions {
+                    block_size: Some(4096),
+                    ..DownloadOptions::default()
+                },
+                RetryPolicy::new(0, None),
+                control
+            ),
+            interrupt,
+        );
+        assert!(start.elapsed() < Duration::from_millis(1800));
+        let result = outcome.and_then(Checked::into_result).unwrap();
+        assert!(result.interrupted);
+        assert_eq!(result.bytes, 0);
+    }
+}
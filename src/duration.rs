@@ -0,0 +1,113 @@
+//! Duration-bounded runs, with `--duration`.
+//!
+//! A fixed set of blocks finishes as soon as the store is done with it, so a
+//! fast store is measured over a few seconds of warm-up. With `--duration 60s`,
+//! `download` cycles through its blocks and `random-read` keeps drawing new
+//! offsets until the time is up, not counting time spent paused. No request is
+//! issued after that; those still in flight are drained. Throughput covers
+//! only the measurement window: a request completing after it is counted in
+//! the latency statistics but not in the bytes, and reported under `duration`
+//! as late. Results record the requested duration and the window actually
+//! measured, which is shorter when the run was interrupted.
+
+use std::time::Duration;
+
+use crate::report::DurationReport;
+
+/// Parse `90`, `90s`, `500ms`, `2m` or `1h`; a bare number is in seconds.
+pub fn parse(arg: &str) -> Result<Duration, String> {
+    let arg = arg.trim();
+    let (number, unit_secs) = [("ms", 0.001), ("s", 1.0), ("m", 60.0), ("h", 3600.0)]
+        .iter()
+        .find_map(|(suffix, secs)| arg.strip_suffix(suffix).map(|number| (number, *secs)))
+        .unwrap_or((arg, 1.0));
+    let number = number
+        .trim()
+        .parse::<f64>()
+        .map_err(|err| format!("{:?}: {}", arg, err))?;
+    if !number.is_finite() || number <= 0.0 {
+        return Err(format!("{:?} is not a positive duration", arg));
+    }
+    Ok(Duration::from_secs_f64(number * unit_secs))
+}
+
+/// The measurement window of a `--duration` run, timed from its start.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeasurementWindow {
+    pub requested: Duration,
+}
+
+impl MeasurementWindow {
+    /// Whether a request may still be issued `elapsed` into the run, of which
+    /// `paused` was spent paused.
+    pub fn is_open(&self, elapsed: Duration, paused: Duration) -> bool {
+        elapsed.saturating_sub(paused) < self.requested
+    }
+
+    /// Whether a request completing `completed` into the run did so after the
+    /// window closed.
+    pub fn is_late(&self, completed: Duration, paused: Duration) -> bool {
+        completed.saturating_sub(paused) > self.requested
+    }
+
+    /// The window measured, pauses included: all of it, or up to the last
+    /// completion of a run interrupted early.
+    pub fn measured(
+        &self,
+        last_completed: Duration,
+        paused: Duration,
+        interrupted: bool,
+    ) -> Duration {
+        let window = self.requested + paused;
+        match interrupted {
+            true => last_completed.min(window),
+            false => window,
+        }
+    }
+
+    pub fn report(
+        &self,
+        measured: Duration,
+        late_requests: usize,
+        late_bytes: u64,
+    ) -> DurationReport {
+        DurationReport {
+            requested_secs: self.requested.as_secs_f64(),
+            measured_us: measured.as_micros(),
+            late_requests,
+            late_bytes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations_with_units() {
+        assert_eq!(parse("60s"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse("2m"), Ok(Duration::from_secs(120)));
+        assert_eq!(parse("1.5h"), Ok(Duration::from_secs(5400)));
+        assert!(parse("0s").is_err());
+        assert!(parse("soon").is_err());
+    }
+
+    #[test]
+    fn late_requests_complete_after_the_window() {
+        let window = MeasurementWindow {
+            requested: Duration::from_secs(10),
+        };
+        let secs = Duration::from_secs;
+        assert!(window.is_open(secs(9), secs(0)));
+        assert!(!window.is_open(secs(10), secs(0)));
+        // Time spent paused extends the window.
+        assert!(window.is_open(secs(12), secs(3)));
+        assert!(window.is_late(secs(11), secs(0)));
+        assert!(!window.is_late(secs(11), secs(2)));
+        assert_eq!(window.measured(secs(9), secs(1), false), secs(11));
+        assert_eq!(window.measured(secs(4), secs(1), true), secs(4));
+    }
+}
@@ -0,0 +1,103 @@
+//! Why a benchmark failed, for callers of the library to match on.
+//!
+//! Every public function of the library returns [`Error`], sorting its
+//! failures into the modes worth telling apart, so a harness calling the
+//! benchmarks can match on them; the command line only prints them.
+
+/// A failed benchmark.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// A request to the store failed, after any retries
+    #[error(transparent)]
+    Store(#[from] object_store::Error),
+    /// A local file, such as a manifest or audit log, couldn't be written
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    /// The settings can't be combined, or don't suit the objects
+    #[error("{0}")]
+    Invalid(String),
+    /// The bytes received disagree with the plan, under `--strict-accounting`
+    #[error("{0}")]
+    Accounting(String),
+    /// Downloaded data didn't match what was uploaded
+    #[error("{0}")]
+    Verification(String),
+    /// Requests failed and the run went on without them, under
+    /// `--continue-on-error` or once their retries ran out
+    #[error("{failed} requests failed; the results count them in failed_request_kinds")]
+    FailedRequests { failed: usize },
+    /// Anything else, by its message
+    #[error("{0}")]
+    Other(String),
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error::Invalid(message)
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        Error::Invalid(message.to_string())
+    }
+}
+
+impl From<object_store::path::Error> for Error {
+    fn from(err: object_store::path::Error) -> Self {
+        Error::Invalid(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(err: serde_json::Error) -> Self {
+        Error::Other(err.to_string())
+    }
+}
+
+impl From<tokio::task::JoinError> for Error {
+    fn from(source: tokio::task::JoinError) -> Self {
+        Error::Store(object_store::Error::JoinError { source })
+    }
+}
+
+impl From<Box<dyn std::error::Error>> for Error {
+    fn from(err: Box<dyn std::error::Error>) -> Self {
+        let err = match err.downcast::<Error>() {
+            Ok(err) => return *err,
+            Err(err) => err,
+        };
+        let err = match err.downcast::<object_store::Error>() {
+            Ok(err) => return Error::Store(*err),
+            Err(err) => err,
+        };
+        match err.downcast::<std::io::Error>() {
+            Ok(err) => Error::Io(*err),
+            Err(err) => Error::Other(err.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxed_errors_keep_their_kind() {
+        let boxed: Box<dyn std::error::Error> = Box::new(object_store::Error::NotFound {
+            path: "data/a".to_string(),
+            source: "gone".into(),
+        });
+        assert!(matches!(
+            Error::from(boxed),
+            Error::Store(object_store::Error::NotFound { .. })
+        ));
+        let boxed: Box<dyn std::error::Error> = Box::new(Error::FailedRequests { failed: 2 });
+        assert!(matches!(
+            Error::from(boxed),
+            Error::FailedRequests { failed: 2 }
+        ));
+        let boxed: Box<dyn std::error::Error> = "no luck".into();
+        assert_eq!(Error::from(boxed).to_string(), "no luck");
+    }
+}
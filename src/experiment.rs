@@ -0,0 +1,192 @@
+//! Experiment directories: one subdirectory per run plus a shared index.
+//!
+//! With `--experiment-dir path`, each run gets its own timestamped subdirectory
+//! holding its result and trace files, and appends one line to
+//! `path/index.jsonl` describing the run. Index lines are written with a single
+//! `write` call on a file opened with `O_APPEND`, so concurrent runs sharing an
+//! experiment directory never interleave partial rows.
+
+use std::cell::RefCell;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rand::{thread_rng, Rng};
+use serde_json::{Map, Value};
+
+use crate::context::RunContext;
+
+pub const INDEX_FILE: &str = "index.jsonl";
+const RESULT_FILE: &str = "result.jsonl";
+const TRACE_FILE: &str = "trace.json";
+const COVERAGE_FILE: &str = "coverage.jsonl";
+
+/// Metrics copied from each result into the index.
+const HEADLINE_METRICS: &[&str] = &["elapsed_us", "mbps", "steady_mbps"];
+
+tokio::task_local! {
+    /// Results emitted inside [`capture`], kept instead of printed.
+    static CAPTURED: RefCell<Captured>;
+}
+
+#[derive(Default)]
+struct Captured {
+    results: Vec<String>,
+    /// How many of `results` [`take_last_result`] has seen
+    taken: usize,
+}
+
+#[derive(Debug)]
+pub struct Experiment {
+    root: PathBuf,
+    run_id: String,
+    run_dir: PathBuf,
+    command: String,
+    args: Vec<String>,
+}
+
+impl Experiment {
+    /// Create the run directory under `root`, to record the results of a
+    /// [`RunContext`] in.
+    pub fn init(root: &Path, command: &str) -> std::io::Result<Experiment> {
+        let suffix = thread_rng()
+            .sample_iter(rand::distributions::Alphanumeric)
+            .take(6)
+            .map(char::from)
+            .collect::<String>()
+            .to_lowercase();
+        let run_id = format!("{}-{}", chrono::Utc::now().format("%Y%m%dT%H%M%SZ"), suffix);
+        let run_dir = root.join(&run_id);
+        std::fs::create_dir_all(&run_dir)?;
+
+        Ok(Experiment {
+            root: root.to_path_buf(),
+            run_id,
+            run_dir,
+            command: command.to_string(),
+            args: std::env::args().skip(1).collect(),
+        })
+    }
+
+    pub fn trace_path(&self) -> PathBuf {
+        self.run_dir.join(TRACE_FILE)
+    }
+
+    /// Where the run's coverage heat maps go.
+    pub fn coverage_path(&self) -> PathBuf {
+        self.run_dir.join(COVERAGE_FILE)
+    }
+
+    /// Write a result line into the run directory and add it to the index.
+    fn record(&self, result: &str) -> std::io::Result<()> {
+        append_line(&self.run_dir.join(RESULT_FILE), result)?;
+
+        let parsed: serde_json::Value = serde_json::from_str(result).unwrap_or_default();
+        let mut row = serde_json::json!({
+            "run_id": self.run_id,
+            "command": self.command,
+            "args": self.args,
+            "result_dir": self.run_id,
+        });
+        for key in HEADLINE_METRICS {
+            row[*key] = parsed.get(*key).cloned().unwrap_or_default();
+        }
+        append_line(&self.root.join(INDEX_FILE), &row.to_string())
+    }
+}
+
+/// Append `line` plus a newline in a single write.
+fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(format!("{}\n", line).as_bytes())
+}
+
+/// Named fields of a result, in the order they are reported. The parts of a
+/// result each module reports are built as these and merged with `extend`.
+pub type Fields = Map<String, Value>;
+
+/// The fields of the JSON object `value`, such as one built with
+/// `serde_json::json!`; anything but an object has none.
+pub fn fields(value: Value) -> Fields {
+    match value {
+        Value::Object(fields) => fields,
+        _ => Fields::new(),
+    }
+}
+
+/// Put `name` first among the fields of `result`.
+pub fn prepend(result: &mut Fields, name: &str, value: Value) {
+    let mut fields = Fields::new();
+    fields.insert(name.to_string(), value);
+    fields.extend(std::mem::take(result));
+    *result = fields;
+}
+
+/// Print a benchmark result to stdout, or append it to the `context`'s
+/// output file, and record it in its experiment directory if it has one.
+/// Inside [`capture`] it is only collected.
+pub(crate) fn emit(context: &RunContext, result: Value) {
+    if context.quiet {
+        return;
+    }
+    let result = match result {
+        Value::Object(mut result) => {
+            context.decorate(&mut result);
+            Value::Object(result)
+        }
+        result => result,
+    };
+    let result = &result.to_string();
+    if CAPTURED
+        .try_with(|captured| captured.borrow_mut().results.push(result.clone()))
+        .is_ok()
+    {
+        return;
+    }
+    crate::progress::above_bar(|| match &context.output {
+        Some(path) => {
+            if let Err(err) = append_line(path, result) {
+                eprintln!("failed to write result to {}: {}", path.display(), err);
+                println!("{}", result);
+            }
+        }
+        None => println!("{}", result),
+    });
+    *context.last_result.lock().unwrap() = Some(result.clone());
+    if let Some(experiment) = &context.experiment {
+        if let Err(err) = experiment.record(result) {
+            eprintln!(
+                "failed to record result in {}: {}",
+                experiment.root.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Run `future`, collecting the results it emits instead of printing them.
+pub async fn capture<F: std::future::Future>(future: F) -> (F::Output, Vec<String>) {
+    CAPTURED
+        .scope(RefCell::default(), async move {
+            let output = future.await;
+            (output, CAPTURED.with(|captured| captured.take().results))
+        })
+        .await
+}
+
+/// The result `context` most recently emitted, if it has emitted one since
+/// the last call.
+pub(crate) fn take_last_result(context: &RunContext) -> Option<String> {
+    let captured = CAPTURED.try_with(|captured| {
+        let mut captured = captured.borrow_mut();
+        let last = (captured.results.len() > captured.taken)
+            .then(|| captured.results.last().cloned())
+            .flatten();
+        captured.taken = captured.results.len();
+        last
+    });
+    match captured {
+        Ok(last) => last,
+        Err(_) => context.last_result.lock().unwrap().take(),
+    }
+}
@@ -0,0 +1,259 @@
+//! Interference between an interactive and a batch tenant sharing a prefix.
+//!
+//! Class A models an interactive tenant: small random reads issued open-loop at
+//! a target rate, so a slow store shows up as latency rather than as a lower
+//! request rate. Class B models a batch tenant: `parallel` workers scanning the
+//! objects in large blocks as fast as they can. Class A first runs alone for
+//! the phase duration, then again with class B running alongside it, so its
+//! latency percentiles can be compared with and without the batch load.
+//! `--selection` picks which objects class A reads; both phases draw the same
+//! sequence. With `--request-deadline-ms`, class A reads that miss the
+//! deadline are dropped, and each phase reports how many expired.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use serde_json::{json, Value};
+
+use crate::control::RunControl;
+use crate::deadline::{DeadlineSpec, Deadlines, Fetched};
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::report::DeadlineReport;
+use crate::retry::RetryPolicy;
+use crate::selection::SelectionSpec;
+use crate::stats::LatencySummary;
+
+/// Parameters for [`fairness_bench`].
+#[derive(Debug, Clone)]
+pub struct FairnessOptions {
+    /// Size of each class A read
+    pub read_size: usize,
+    /// Class A requests issued per second
+    pub target_qps: f64,
+    /// Size of each class B block read
+    pub block_size: usize,
+    /// Number of concurrent class B scanners
+    pub parallel: usize,
+    /// Length of each phase
+    pub duration: Duration,
+    /// How class A picks the object and offset of each read
+    pub selection: SelectionSpec,
+    /// When set, class A reads still running at the deadline are dropped
+    pub deadline: Option<DeadlineSpec>,
+}
+
+/// Class A's results for one phase.
+struct InteractivePhase {
+    latency: LatencySummary,
+    errors: usize,
+    elapsed: Duration,
+    deadline: Option<DeadlineReport>,
+}
+
+impl InteractivePhase {
+    fn to_json(&self) -> Value {
+        let mut phase = fields(json!({
+            "requests": self.latency.count,
+            "errors": self.errors,
+            "achieved_qps": self.latency.count as f64 / self.elapsed.as_secs_f64(),
+            "latency": self.latency.to_json(),
+        }));
+        if let Some(deadline) = &self.deadline {
+            phase.insert("deadline".to_string(), json!(deadline));
+        }
+        phase.into()
+    }
+}
+
+/// Benchmarks how much a batch scan slows interactive reads on `location`.
+pub async fn fairness_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: FairnessOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects = Arc::new(
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?,
+    );
+
+    let baseline = interactive(&object_store, &objects, &options, &retry, &control).await?;
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let scanned = Arc::new(AtomicUsize::new(0));
+    let scan_start = Instant::now();
+    let scanners = (0..options.parallel)
+        .map(|worker| {
+            tokio::task::spawn(scan(
+                object_store.clone(),
+                objects.clone(),
+                worker,
+                options.clone(),
+                retry.clone(),
+                control.clone(),
+                stop.clone(),
+                scanned.clone(),
+            ))
+        })
+        .collect::<Vec<_>>();
+    let contended = interactive(&object_store, &objects, &options, &retry, &control).await?;
+    stop.store(true, Ordering::SeqCst);
+    let mut scan_errors = 0;
+    for scanner in scanners {
+        scan_errors += scanner.await?;
+    }
+    let scan_elapsed = scan_start.elapsed();
+    let scanned = scanned.load(Ordering::SeqCst);
+
+    let mut result = fields(json!({
+        "mode": "fairness",
+        "num_objects": objects.len(),
+        "phase_duration_ms": options.duration.as_millis() as u64,
+        "class_a": {
+            "read_size": options.read_size,
+            "target_qps": options.target_qps,
+            "selection": options.selection.to_json(),
+            "baseline": baseline.to_json(),
+            "contended": contended.to_json(),
+            "p99_slowdown": contended.latency.p99_us as f64 / baseline.latency.p99_us.max(1) as f64,
+        },
+        "class_b": {
+            "block_size": options.block_size,
+            "parallel": options.parallel,
+            "bytes": scanned,
+            "errors": scan_errors,
+            "mbps": scanned as f64 / 1024.0 / 1024.0 / scan_elapsed.as_secs_f64(),
+        },
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+/// Run class A for one phase: random reads issued at the target rate.
+async fn interactive(
+    object_store: &Arc<dyn ObjectStore>,
+    objects: &Arc<Vec<ObjectMeta>>,
+    options: &FairnessOptions,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> Result<InteractivePhase, Error> {
+    let mut selector = options.selection.selector(objects)?;
+    let start = Instant::now();
+    let mut ticks = tokio::time::interval(Duration::from_secs_f64(1.0 / options.target_qps));
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Burst);
+    let deadlines = options.deadline.map(|spec| Arc::new(Deadlines::new(spec)));
+    let mut requests = Vec::new();
+    while start.elapsed() < options.duration && !control.is_shutdown() {
+        ticks.tick().await;
+        let (location, range) = {
+            let meta = &objects[selector.next_object()];
+            let read_size = options.read_size.min(meta.size);
+            let offset = selector.next_offset(meta.size, read_size);
+            (meta.location.clone(), offset..offset + read_size)
+        };
+        let object_store = object_store.clone();
+        let retry = retry.clone();
+        let control = control.clone();
+        let deadlines = deadlines.clone();
+        requests.push(tokio::task::spawn(async move {
+            if !control.request_started().await {
+                return None;
+            }
+            control.record("get_range", &location, Some(&range));
+            let start = Instant::now();
+            let result = match &deadlines {
+                Some(deadlines) => {
+                    deadlines
+                        .fetch(object_store.as_ref(), &location, Some(range), &retry)
+                        .await
+                }
+                None => retry
+                    .run(|| object_store.get_range(&location, range.clone()))
+                    .await
+                    .map(|bytes| Fetched::Met(bytes.len())),
+            };
+            match &result {
+                Ok(Fetched::Met(bytes)) => control.request_finished(*bytes),
+                Ok(Fetched::Expired) => control.request_expired(),
+                Err(_) => control.request_failed(),
+            }
+            // Expired reads have no latency to report.
+            Some(result.map(|fetched| (fetched != Fetched::Expired).then(|| start.elapsed())))
+        }));
+    }
+
+    // The rate is over the time spent issuing, not waiting for stragglers.
+    let elapsed = start.elapsed();
+    let mut latencies = Vec::with_capacity(requests.len());
+    let mut errors = 0;
+    for request in requests {
+        match request.await {
+            Ok(Some(Ok(Some(latency)))) => latencies.push(latency),
+            Ok(Some(Err(_))) | Err(_) => errors += 1,
+            Ok(Some(Ok(None))) | Ok(None) => {}
+        }
+    }
+    Ok(InteractivePhase {
+        latency: LatencySummary::from_latencies(&mut latencies),
+        errors,
+        elapsed,
+        deadline: deadlines.map(|deadlines| deadlines.report(elapsed.as_micros())),
+    })
+}
+
+/// Run one class B scanner until `stop` is set, returning its error count.
+///
+/// Scanner `worker` starts at a different object from the others and moves
+/// through every object block by block, wrapping around at the end.
+#[allow(clippy::too_many_arguments)]
+async fn scan(
+    object_store: Arc<dyn ObjectStore>,
+    objects: Arc<Vec<ObjectMeta>>,
+    worker: usize,
+    options: FairnessOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+    stop: Arc<AtomicBool>,
+    scanned: Arc<AtomicUsize>,
+) -> usize {
+    let mut errors = 0;
+    let mut object_i = worker % objects.len();
+    let mut offset = 0;
+    while !stop.load(Ordering::SeqCst) && !control.is_shutdown() {
+        let meta = &objects[object_i];
+        if offset >= meta.size {
+            object_i = (object_i + 1) % objects.len();
+            offset = 0;
+            continue;
+        }
+        let range = offset..(offset + options.block_size).min(meta.size);
+        offset = range.end;
+        if !control.request_started().await {
+            break;
+        }
+        control.record("get_range", &meta.location, Some(&range));
+        match retry
+            .run(|| object_store.get_range(&meta.location, range.clone()))
+            .await
+        {
+            Ok(bytes) => {
+                control.request_finished(bytes.len());
+                scanned.fetch_add(bytes.len(), Ordering::SeqCst);
+            }
+            Err(_) => {
+                control.request_failed();
+                errors += 1;
+            }
+        }
+        // A store that answers at once, such as `memory://`, never suspends
+        // the scan, which would starve class A on the same thread.
+        tokio::task::yield_now().await;
+    }
+    errors
+}
@@ -0,0 +1,1095 @@
+//! Failure injection, for exercising retry and partial-failure handling.
+//!
+//! [`FaultStore`] wraps another store and, on reads, injects the faults given
+//! with `--fault`: random `Generic` errors, added latency, bodies truncated part
+//! way through, `NotFound` for specific keys, and throttling of reads beyond a
+//! concurrency. `--inject-failures P` is short for `--fault range-error-rate=P`,
+//! failing only `get_range` calls. Faults are drawn from an RNG seeded with
+//! `--fault-seed` (or `--inject-failures-seed`), so a run with the same seed
+//! and a concurrency of one sees the same faults every time.
+//!
+//! Only reads (`get`, `get_range`, `get_ranges` and `head`) are faulted; writes,
+//! listings and deletes pass straight through so the dataset can be set up.
+//! Results count the failed attempts that were injected apart from those the
+//! store really returned, so a fault run also shows whether the store itself
+//! misbehaved: every injected error carries an [`InjectedError`] source.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::AsyncWrite;
+
+const STORE: &str = "fault";
+
+/// One `--fault` flag, written as `kind=value`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    /// `error-rate=P`: fail each read with a `Generic` error with probability P
+    ErrorRate(f64),
+    /// `range-error-rate=P`: fail each `get_range` with a `Generic` error with
+    /// probability P
+    RangeErrorRate(f64),
+    /// `latency-ms=N`: delay each read by N milliseconds
+    LatencyMs(u64),
+    /// `truncate-rate=P`: cut each read's body short with probability P
+    TruncateRate(f64),
+    /// `not-found=KEY`: report the object at this path as missing
+    NotFound(String),
+    /// `auth-error-rate=P`: fail each read with an expired-credential error
+    /// with probability P
+    AuthErrorRate(f64),
+    /// `throttle-above=N`: fail reads with a `503 Slow Down` while more than N
+    /// are in flight
+    ThrottleAbove(usize),
+}
+
+impl FromStr for Fault {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (kind, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected kind=value, got {:?}", s))?;
+        let probability = |value: &str| {
+            let p: f64 = value.parse().map_err(|err| format!("{}: {}", kind, err))?;
+            if !(0.0..=1.0).contains(&p) {
+                return Err(format!("{} must be between 0 and 1, got {}", kind, p));
+            }
+            Ok(p)
+        };
+        match kind {
+            "error-rate" => Ok(Fault::ErrorRate(probability(value)?)),
+            "range-error-rate" => Ok(Fault::RangeErrorRate(probability(value)?)),
+            "truncate-rate" => Ok(Fault::TruncateRate(probability(value)?)),
+            "latency-ms" => Ok(Fault::LatencyMs(
+                value.parse().map_err(|err| format!("{}: {}", kind, err))?,
+            )),
+            "not-found" => Ok(Fault::NotFound(value.to_string())),
+            "auth-error-rate" => Ok(Fault::AuthErrorRate(probability(value)?)),
+            "throttle-above" => Ok(Fault::ThrottleAbove(
+                value.parse().map_err(|err| format!("{}: {}", kind, err))?,
+            )),
+            _ => Err(format!(
+                "unknown fault {:?}; expected error-rate, range-error-rate, latency-ms, truncate-rate, not-found, auth-error-rate or throttle-above",
+                kind
+            )),
+        }
+    }
+}
+
+/// Parse `--inject-failures P`, the same fault as `--fault range-error-rate=P`.
+pub fn parse_failure_rate(arg: &str) -> std::result::Result<Fault, String> {
+    format!("range-error-rate={}", arg).parse()
+}
+
+/// Combined settings from every `--fault` flag.
+#[derive(Debug, Clone, Default)]
+pub struct FaultConfig {
+    pub error_rate: f64,
+    pub range_error_rate: f64,
+    pub latency: Duration,
+    pub truncate_rate: f64,
+    pub not_found: Vec<String>,
+    pub auth_error_rate: f64,
+    pub throttle_above: Option<usize>,
+}
+
+impl FaultConfig {
+    pub fn new(faults: &[Fault]) -> Self {
+        let mut config = Self::default();
+        for fault in faults {
+            match fault {
+                Fault::ErrorRate(p) => config.error_rate = *p,
+                Fault::RangeErrorRate(p) => config.range_error_rate = *p,
+                Fault::LatencyMs(ms) => config.latency = Duration::from_millis(*ms),
+                Fault::TruncateRate(p) => config.truncate_rate = *p,
+                Fault::NotFound(key) => config.not_found.push(key.clone()),
+                Fault::AuthErrorRate(p) => config.auth_error_rate = *p,
+                Fault::ThrottleAbove(n) => config.throttle_above = Some(*n),
+            }
+        }
+        config
+    }
+}
+
+/// Number of faults of each kind injected so far.
+#[derive(Debug, Default)]
+pub struct FaultCounts {
+    pub errors: AtomicUsize,
+    pub truncations: AtomicUsize,
+    pub not_found: AtomicUsize,
+    pub delayed: AtomicUsize,
+    pub auth_errors: AtomicUsize,
+    pub throttled: AtomicUsize,
+}
+
+impl FaultCounts {
+    /// JSON object with the count of each fault kind.
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "errors": self.errors.load(Ordering::SeqCst),
+            "truncations": self.truncations.load(Ordering::SeqCst),
+            "not_found": self.not_found.load(Ordering::SeqCst),
+            "delayed": self.delayed.load(Ordering::SeqCst),
+            "auth_errors": self.auth_errors.load(Ordering::SeqCst),
+            "throttled": self.throttled.load(Ordering::SeqCst),
+        })
+    }
+}
+
+/// The source of every error a [`FaultStore`] injects, so [`is_injected`] can
+/// tell them from errors the store beneath returned.
+#[derive(Debug)]
+pub struct InjectedError(String);
+
+impl Display for InjectedError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InjectedError {}
+
+/// A `Generic` error from the fault store, with an [`InjectedError`] source.
+fn injected(message: String) -> object_store::Error {
+    object_store::Error::Generic {
+        store: STORE,
+        source: Box::new(InjectedError(message)),
+    }
+}
+
+/// What to do to one read, decided before it is passed to the inner store.
+enum Injected {
+    None,
+    Truncate,
+}
+
+/// An [`ObjectStore`] that injects faults into reads from `inner`.
+pub struct FaultStore {
+    inner: Arc<dyn ObjectStore>,
+    config: FaultConfig,
+    rng: Mutex<StdRng>,
+    counts: Arc<FaultCounts>,
+    /// Reads in progress, for `throttle-above`
+    active: AtomicUsize,
+}
+
+/// Counts a read as in progress until dropped.
+struct ActiveRead<'a>(&'a AtomicUsize);
+
+impl Drop for ActiveRead<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl FaultStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, config: FaultConfig, seed: u64) -> Self {
+        Self {
+            inner,
+            config,
+            rng: Mutex::new(StdRng::seed_from_u64(seed)),
+            counts: Arc::new(FaultCounts::default()),
+            active: AtomicUsize::new(0),
+        }
+    }
+
+    /// Counters of injected faults, which stay readable after the store is
+    /// handed to a benchmark.
+    pub fn counts(&self) -> Arc<FaultCounts> {
+        self.counts.clone()
+    }
+
+    /// Count a read as in progress for as long as the guard is held.
+    fn enter(&self) -> ActiveRead<'_> {
+        self.active.fetch_add(1, Ordering::SeqCst);
+        ActiveRead(&self.active)
+    }
+
+    /// Delay, then decide whether this read fails, is truncated or proceeds.
+    /// Only reads that return a `body` can be truncated.
+    async fn inject(&self, location: &Path, body: bool) -> Result<Injected> {
+        if !self.config.latency.is_zero() {
+            self.counts.delayed.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(self.config.latency).await;
+        }
+        if let Some(limit) = self.config.throttle_above {
+            if self.active.load(Ordering::SeqCst) > limit {
+                self.counts.throttled.fetch_add(1, Ordering::SeqCst);
+                return Err(injected(format!(
+                    "injected 503 Slow Down reading {}: Please reduce your request rate",
+                    location
+                )));
+            }
+        }
+        if self
+            .config
+            .not_found
+            .iter()
+            .any(|key| key == location.as_ref())
+        {
+            self.counts.not_found.fetch_add(1, Ordering::SeqCst);
+            return Err(object_store::Error::NotFound {
+                path: location.to_string(),
+                source: Box::new(InjectedError("injected not found".to_string())),
+            });
+        }
+        let (error, auth_error, truncate) = {
+            let mut rng = self.rng.lock().unwrap();
+            (
+                rng.gen_bool(self.config.error_rate),
+                rng.gen_bool(self.config.auth_error_rate),
+                body && rng.gen_bool(self.config.truncate_rate),
+            )
+        };
+        if error {
+            self.counts.errors.fetch_add(1, Ordering::SeqCst);
+            return Err(injected(format!("injected error reading {}", location)));
+        }
+        if auth_error {
+            self.counts.auth_errors.fetch_add(1, Ordering::SeqCst);
+            return Err(injected(format!(
+                "injected 403 Forbidden reading {}: ExpiredToken: the provided token has expired",
+                location
+            )));
+        }
+        if truncate {
+            self.counts.truncations.fetch_add(1, Ordering::SeqCst);
+            return Ok(Injected::Truncate);
+        }
+        Ok(Injected::None)
+    }
+
+    /// Decide whether this `get_range` fails under `range-error-rate`.
+    fn inject_range_error(&self, location: &Path) -> Result<()> {
+        if self.config.range_error_rate == 0.0 {
+            return Ok(());
+        }
+        if self
+            .rng
+            .lock()
+            .unwrap()
+            .gen_bool(self.config.range_error_rate)
+        {
+            self.counts.errors.fetch_add(1, Ordering::SeqCst);
+            return Err(injected(format!(
+                "injected error reading a range of {}",
+                location
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Whether `err` was injected by a [`FaultStore`] rather than returned by the
+/// store beneath it.
+pub fn is_injected(err: &object_store::Error) -> bool {
+    match err {
+        object_store::Error::Generic { source, .. }
+        | object_store::Error::NotFound { source, .. } => {
+            source.downcast_ref::<InjectedError>().is_some()
+        }
+        _ => false,
+    }
+}
+
+fn truncated(location: &Path) -> object_store::Error {
+    injected(format!(
+        "injected truncation of {}: connection closed mid-body",
+        location
+    ))
+}
+
+/// Yield the first `limit` bytes of `stream`, then fail as a dropped
+/// connection would.
+fn truncate_stream(
+    stream: BoxStream<'static, Result<Bytes>>,
+    location: Path,
+    limit: usize,
+) -> BoxStream<'static, Result<Bytes>> {
+    futures::stream::unfold(
+        (stream, location, limit, false),
+        |(mut stream, location, remaining, failed)| async move {
+            if failed {
+                return None;
+            }
+            match stream.next().await {
+                Some(Ok(chunk)) if chunk.len() < remaining => {
+                    let remaining = remaining - chunk.len();
+                    Some((Ok(chunk), (stream, location, remaining, false)))
+                }
+                Some(Ok(chunk)) if remaining > 0 => {
+                    let chunk = chunk.slice(..remaining);
+                    Some((Ok(chunk), (stream, location, 0, false)))
+                }
+                Some(Err(err)) => Some((Err(err), (stream, location, 0, true))),
+                _ => {
+                    let err = truncated(&location);
+                    Some((Err(err), (stream, location, 0, true)))
+                }
+            }
+        },
+    )
+    .boxed()
+}
+
+impl Display for FaultStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FaultStore({})", self.inner)
+    }
+}
+
+impl Debug for FaultStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FaultStore")
+            .field("inner", &self.inner)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FaultStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let _active = self.enter();
+        let injected = self.inject(location, true).await?;
+        // Cut the body off half way through the requested range. Without a
+        // range the size isn't known up front, so fail after the first byte.
+        let limit = options.range.as_ref().map(|range| range.len() / 2);
+        let result = self.inner.get_opts(location, options).await?;
+        Ok(match injected {
+            Injected::None => result,
+            Injected::Truncate => GetResult::Stream(truncate_stream(
+                result.into_stream(),
+                location.clone(),
+                limit.unwrap_or(1),
+            )),
+        })
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let _active = self.enter();
+        let injected = self.inject(location, true).await?;
+        self.inject_range_error(location)?;
+        match injected {
+            Injected::None => self.inner.get_range(location, range).await,
+            // A collected body that was cut short surfaces as an error.
+            Injected::Truncate => Err(truncated(location)),
+        }
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let _active = self.enter();
+        match self.inject(location, true).await? {
+            Injected::None => self.inner.get_ranges(location, ranges).await,
+            Injected::Truncate => Err(truncated(location)),
+        }
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let _active = self.enter();
+        self.inject(location, false).await?;
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backoff::{AdaptiveBackoff, AimdConfig};
+    use crate::columnar::{columnar_read_test, ColumnarOptions};
+    use crate::control::RunControl;
+    use crate::deadline::{DeadlineSpec, OnExpire};
+    use crate::download::{parallel_download_bench, DownloadOptions, ReadMode};
+    use crate::missing::MissingObjects;
+    use crate::report::Checked;
+    use crate::retry::RetryPolicy;
+    use crate::simulate::{SimulatedStore, Simulation};
+    use object_store::memory::InMemory;
+
+    const OBJECT_SIZE: usize = 1 << 20;
+    const WINDOW: Duration = Duration::from_secs(10);
+
+    /// A store holding `data/a.bin` and `data/b.bin`, and the `data` prefix.
+    async fn faulty_store(faults: &[Fault]) -> (Arc<dyn ObjectStore>, Arc<FaultCounts>, Path) {
+        let inner = Arc::new(InMemory::new());
+        for name in ["data/a.bin", "data/b.bin"] {
+            inner
+                .put(&Path::from(name), Bytes::from(vec![7; OBJECT_SIZE]))
+                .await
+                .unwrap();
+        }
+        let store = FaultStore::new(inner, FaultConfig::new(faults), 42);
+        let counts = store.counts();
+        (Arc::new(store), counts, Path::from("data"))
+    }
+
+    fn columnar_options() -> ColumnarOptions {
+        ColumnarOptions {
+            page_sizes: vec![4096, 16384],
+            ..ColumnarOptions::default()
+        }
+    }
+
+    fn store_error(err: crate::Error) -> object_store::Error {
+        match err {
+            crate::Error::Store(err) => err,
+            err => panic!("expected an object_store error, got {}", err),
+        }
+    }
+
+    #[test]
+    fn parses_fault_flags() {
+        assert_eq!("error-rate=0.25".parse(), Ok(Fault::ErrorRate(0.25)));
+        assert_eq!(parse_failure_rate("0.5"), Ok(Fault::RangeErrorRate(0.5)));
+        assert!(parse_failure_rate("1.5").is_err());
+        assert_eq!("latency-ms=30".parse(), Ok(Fault::LatencyMs(30)));
+        assert_eq!(
+            "not-found=a/b.bin".parse(),
+            Ok(Fault::NotFound("a/b.bin".to_string()))
+        );
+        assert_eq!("auth-error-rate=0.1".parse(), Ok(Fault::AuthErrorRate(0.1)));
+        assert_eq!("throttle-above=8".parse(), Ok(Fault::ThrottleAbove(8)));
+        assert!("error-rate=2".parse::<Fault>().is_err());
+        assert!("throttle=1".parse::<Fault>().is_err());
+        assert!("error-rate".parse::<Fault>().is_err());
+    }
+
+    #[tokio::test]
+    async fn download_recovers_from_transient_errors() {
+        let (store, counts, location) = faulty_store(&[Fault::ErrorRate(0.3)]).await;
+        let retry = RetryPolicy::new(20, None);
+        let control = RunControl::new();
+        parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                block_size: Some(OBJECT_SIZE / 16),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+
+        let injected = counts.errors.load(Ordering::SeqCst);
+        assert!(injected > 0);
+        assert_eq!(retry.retries(), injected);
+        let snapshot = control.snapshot();
+        assert_eq!(snapshot.bytes, 2 * OBJECT_SIZE as u64);
+        assert_eq!(snapshot.errors, 0);
+    }
+
+    #[tokio::test]
+    async fn failure_injection_only_fails_ranged_reads() {
+        let (store, counts, _) = faulty_store(&[Fault::RangeErrorRate(1.0)]).await;
+        let location = Path::from("data/a.bin");
+        let err = store.get_range(&location, 0..10).await.unwrap_err();
+        assert!(is_injected(&err), "{}", err);
+        store.get(&location).await.unwrap().bytes().await.unwrap();
+        store.head(&location).await.unwrap();
+        assert_eq!(counts.errors.load(Ordering::SeqCst), 1);
+
+        // Only the source type marks an error as injected, not its message.
+        let real = object_store::Error::NotFound {
+            path: location.to_string(),
+            source: "injected by a proxy".into(),
+        };
+        assert!(!is_injected(&real));
+        let real = object_store::Error::Generic {
+            store: STORE,
+            source: "injected by a proxy".into(),
+        };
+        assert!(!is_injected(&real));
+    }
+
+    #[tokio::test]
+    async fn injected_errors_are_told_apart_over_simulated_latency() {
+        let inner = Arc::new(InMemory::new());
+        inner
+            .put(&Path::from("data/a.bin"), Bytes::from(vec![7; 64 << 10]))
+            .await
+            .unwrap();
+        let simulation = Simulation {
+            latency: Some("5".parse().unwrap()),
+            bandwidth_mbps: None,
+        };
+        let simulated = Arc::new(SimulatedStore::new(inner, simulation));
+        let store = FaultStore::new(simulated, FaultConfig::new(&[Fault::ErrorRate(0.3)]), 7);
+        let counts = store.counts();
+        let retry = RetryPolicy::new(20, None);
+        let result = parallel_download_bench(
+            Arc::new(store),
+            Path::from("data"),
+            DownloadOptions {
+                block_size: Some(4 << 10),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            RunControl::new(),
+        )
+        .await
+        .and_then(Checked::into_result)
+        .unwrap();
+
+        let injected = counts.errors.load(Ordering::SeqCst);
+        assert!(injected > 0);
+        let fields = retry.json_fields();
+        assert_eq!(fields["injected_errors"], injected);
+        // Finding no object at `data` before listing the prefix is no error.
+        assert_eq!(fields["real_errors"], 0, "{:?}", fields);
+        assert!(fields["error_kinds"].get("not_found").is_none());
+        // Injected failures return at once; the 16 reads that reach the
+        // simulated store each wait out its latency.
+        assert!(result.elapsed_us >= 16 * 5_000, "{}", result.elapsed_us);
+        assert!(!is_injected(&object_store::Error::NotFound {
+            path: "data/a.bin".to_string(),
+            source: "gone".into(),
+        }));
+    }
+
+    #[tokio::test]
+    async fn auth_errors_are_classified_and_timed() {
+        let (store, counts, location) = faulty_store(&[Fault::AuthErrorRate(0.3)]).await;
+        let retry = RetryPolicy::new(20, None);
+        parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                block_size: Some(OBJECT_SIZE / 16),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+
+        let injected = counts.auth_errors.load(Ordering::SeqCst);
+        assert!(injected > 0);
+        let fields = retry.json_fields();
+        assert_eq!(fields["error_kinds"]["auth"], injected);
+        assert_eq!(fields["auth_errors"], injected);
+        let timeline = fields["auth_error_timeline"].as_array().unwrap();
+        assert_eq!(timeline.len(), injected.min(100));
+        assert!(timeline.iter().all(|event| event["retried"] == true));
+        assert!(timeline
+            .windows(2)
+            .all(|pair| pair[0]["at_us"].as_u64() <= pair[1]["at_us"].as_u64()));
+    }
+
+    #[tokio::test]
+    async fn adaptive_backoff_cuts_concurrency_when_throttled() {
+        let (store, counts, location) =
+            faulty_store(&[Fault::ThrottleAbove(2), Fault::LatencyMs(5)]).await;
+        let config: AimdConfig = "window-ms=20".parse().unwrap();
+        let backoff = Arc::new(AdaptiveBackoff::new(config));
+        let retry = RetryPolicy::new(100, None).with_backoff(Some(backoff.clone()));
+        let control = RunControl::new();
+        parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                parallel_downloads: 8,
+                block_size: Some(OBJECT_SIZE / 64),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+
+        let throttled = counts.throttled.load(Ordering::SeqCst);
+        assert!(throttled > 0);
+        let fields = retry.json_fields();
+        assert_eq!(fields["error_kinds"]["throttle"], throttled);
+        let backoff = &fields["adaptive_backoff"];
+        assert!(backoff["backoffs"].as_u64().unwrap() > 0);
+        assert!(backoff["final_limit"].as_u64().unwrap() < 8);
+        assert_eq!(control.snapshot().bytes, 2 * OBJECT_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn download_finishes_once_retries_run_out() {
+        let (store, counts, location) = faulty_store(&[Fault::TruncateRate(1.0)]).await;
+        let retry = RetryPolicy::new(3, None);
+        let control = RunControl::new();
+        let checked = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        assert!(outcome
+            .unwrap_err()
+            .to_string()
+            .starts_with("2 requests failed"));
+
+        // Both objects' requests were given up on, and the run still reported.
+        assert_eq!(result["failed_requests"], 2);
+        assert_eq!(result["retries"], 6);
+        assert_eq!(result["bytes"], 0);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+        assert_eq!(counts.truncations.load(Ordering::SeqCst), 8);
+        assert_eq!(control.snapshot().errors, 2);
+    }
+
+    #[tokio::test]
+    async fn sequential_reads_stream_whole_objects() {
+        let (store, _, location) = faulty_store(&[]).await;
+        let run = |store| {
+            parallel_download_bench(
+                store,
+                location.clone(),
+                DownloadOptions {
+                    parallel_downloads: 2,
+                    block_size: Some(1 << 16),
+                    read_mode: ReadMode::Sequential,
+                    window: WINDOW,
+                    ..DownloadOptions::default()
+                },
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            )
+        };
+        let result = run(store).await.and_then(Checked::into_result).unwrap();
+        let result = serde_json::to_value(result).unwrap();
+        assert_eq!(result["read_mode"], "sequential");
+        assert_eq!(result["bytes"], 2 * OBJECT_SIZE);
+        assert_eq!(result["num_requests"], 2);
+
+        // A body cut short fails the run rather than counting fewer bytes.
+        let (store, _, _) = faulty_store(&[Fault::TruncateRate(1.0)]).await;
+        assert!(run(store).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn duration_bounded_downloads_cycle_until_the_window_closes() {
+        let (store, _, location) = faulty_store(&[Fault::LatencyMs(20)]).await;
+        let checked = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(OBJECT_SIZE / 4),
+                duration: Some(Duration::from_millis(200)),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        // About ten rounds of two 20ms requests, well past the eight blocks.
+        let requests = result["num_requests"].as_u64().unwrap();
+        assert!(requests > 8, "{}", requests);
+        assert_eq!(result["elapsed_us"], 200_000);
+        assert_eq!(result["duration"]["requested_secs"], 0.2);
+        assert_eq!(result["duration"]["measured_us"], 200_000);
+        // Requests still in flight at the end are timed but not counted.
+        let late = result["duration"]["late_requests"].as_u64().unwrap();
+        assert!(late >= 1);
+        assert_eq!(result["latency"]["count"], requests + late);
+        assert_eq!(result["bytes"], requests * OBJECT_SIZE as u64 / 4);
+        assert_eq!(result["accounting"]["checked"], false);
+    }
+
+    #[tokio::test]
+    async fn timelines_bucket_completions_by_second() {
+        let (store, _, location) = faulty_store(&[]).await;
+        let checked = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(OBJECT_SIZE / 4),
+                window: WINDOW,
+                timeline: true,
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        let timeline = result["timeline"].as_array().unwrap();
+        assert_eq!(timeline[0]["start_s"], 0);
+        let total = |field: &str| {
+            timeline
+                .iter()
+                .map(|bucket| bucket[field].as_u64().unwrap())
+                .sum::<u64>()
+        };
+        assert_eq!(total("requests"), 8);
+        assert_eq!(total("bytes"), 2 * OBJECT_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn download_fails_on_the_first_error_without_retries() {
+        let (store, _, location) = faulty_store(&[Fault::TruncateRate(1.0)]).await;
+        let retry = RetryPolicy::new(0, None);
+        let err = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            RunControl::new(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            store_error(err),
+            object_store::Error::Generic { .. }
+        ));
+        assert_eq!(retry.failed_requests(), 0);
+    }
+
+    #[tokio::test]
+    async fn retry_budget_bounds_retries_across_requests() {
+        let (store, _, location) = faulty_store(&[Fault::TruncateRate(1.0)]).await;
+        let retry = RetryPolicy::new(10, Some(2));
+        let checked = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        assert!(outcome.is_err());
+        assert_eq!(result["failed_requests"], 2);
+        assert_eq!(retry.retries(), 2);
+        assert!(retry.budget.as_ref().unwrap().exhausted_at_us().is_some());
+    }
+
+    #[tokio::test]
+    async fn truncated_streams_are_reported_as_partial() {
+        let (store, _, location) = faulty_store(&[Fault::TruncateRate(1.0)]).await;
+        let retry = RetryPolicy::new(5, None);
+        let control = RunControl::new();
+        parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(OBJECT_SIZE / 4),
+                consume_mbps: Some(1024.0),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+
+        // Body errors after the response started are recorded, not retried.
+        assert_eq!(retry.retries(), 0);
+        assert_eq!(control.snapshot().bytes, OBJECT_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn columnar_does_not_retry_missing_objects() {
+        let (store, counts, location) =
+            faulty_store(&[Fault::NotFound("data/a.bin".to_string())]).await;
+        let retry = RetryPolicy::new(5, None);
+        let control = RunControl::new();
+        let err = columnar_read_test(
+            store,
+            location,
+            columnar_options(),
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(
+            store_error(err),
+            object_store::Error::NotFound { .. }
+        ));
+        assert_eq!(retry.retries(), 0);
+        // Both pages of the first group were issued, then the run stopped.
+        assert_eq!(counts.not_found.load(Ordering::SeqCst), 2);
+        assert_eq!(control.snapshot().errors, 2);
+        assert_eq!(control.snapshot().bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn skipped_missing_objects_drop_out_of_the_run() {
+        let (store, counts, location) =
+            faulty_store(&[Fault::NotFound("data/a.bin".to_string())]).await;
+        let retry = RetryPolicy::new(5, None).with_missing_objects(MissingObjects::Skip);
+        let control = RunControl::new();
+        columnar_read_test(
+            store.clone(),
+            location.clone(),
+            columnar_options(),
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+        // At most the first group's pages of the missing object were
+        // requested, depending on whether the second was issued before the
+        // first came back.
+        let not_found = counts.not_found.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&not_found));
+        assert_eq!(control.snapshot().bytes, 51 * (4096 + 16384));
+
+        let control = RunControl::new();
+        parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                block_size: Some(OBJECT_SIZE / 16),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry,
+            control.clone(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(counts.not_found.load(Ordering::SeqCst), not_found + 1);
+        assert_eq!(control.snapshot().bytes, OBJECT_SIZE as u64);
+    }
+
+    #[tokio::test]
+    async fn continue_on_error_reports_the_failed_requests() {
+        let (store, _, location) = faulty_store(&[Fault::NotFound("data/a.bin".to_string())]).await;
+        let retry = RetryPolicy::new(0, None).with_continue_on_error(true);
+        let checked = parallel_download_bench(
+            store.clone(),
+            location.clone(),
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(OBJECT_SIZE / 4),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            retry.clone(),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        // The run fails, but only after reporting what did arrive.
+        assert!(outcome.is_err());
+        assert_eq!(result["num_requests"], 8);
+        assert_eq!(result["successful_requests"], 4);
+        assert_eq!(result["failed_requests"], 4);
+        assert_eq!(result["failed_request_kinds"]["not_found"], 4);
+        assert_eq!(result["bytes"], OBJECT_SIZE);
+        // Throughput is over the bytes that arrived, in the measured time.
+        let measured_us =
+            result["elapsed_us"].as_u64().unwrap() - result["paused_us"].as_u64().unwrap();
+        assert_eq!(
+            result["mbps"],
+            crate::stats::mbps(OBJECT_SIZE as u64, measured_us.into())
+        );
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+
+        let retry = RetryPolicy::new(0, None).with_continue_on_error(true);
+        let checked = columnar_read_test(
+            store,
+            location,
+            columnar_options(),
+            retry,
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        assert!(outcome.is_err());
+        assert_eq!(result["num_requests"], 4 * 51);
+        assert_eq!(result["successful_requests"], 2 * 51);
+        assert_eq!(result["failed_requests"], 2 * 51);
+        assert_eq!(result["failed_request_kinds"]["not_found"], 2 * 51);
+        assert_eq!(result["bytes"], 51 * (4096 + 16384));
+    }
+
+    #[tokio::test]
+    async fn columnar_recovers_from_truncated_pages() {
+        let (store, counts, location) = faulty_store(&[Fault::TruncateRate(0.3)]).await;
+        let retry = RetryPolicy::new(20, None);
+        let control = RunControl::new();
+        let checked = columnar_read_test(
+            store,
+            location,
+            columnar_options(),
+            retry.clone(),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+
+        let injected = counts.truncations.load(Ordering::SeqCst);
+        assert!(injected > 0);
+        assert_eq!(retry.retries(), injected);
+        // 51 whole groups of 4096 + 16384 bytes fit in each object.
+        assert_eq!(control.snapshot().bytes, 2 * 51 * (4096 + 16384));
+        // Every planned page arrived exactly once despite the retries.
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+        assert_eq!(result["accounting"]["objects_checked"], 2);
+        assert_eq!(
+            result["accounting"]["received_bytes"],
+            2 * 51 * (4096 + 16384)
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_location_is_not_found() {
+        let (store, _, _) = faulty_store(&[]).await;
+        let err = parallel_download_bench(
+            store,
+            Path::from("missing"),
+            DownloadOptions {
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(3, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(
+            store_error(err),
+            object_store::Error::NotFound { .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn slow_blocks_expire_at_the_deadline() {
+        let (store, _, location) = faulty_store(&[Fault::LatencyMs(100)]).await;
+        let control = RunControl::new();
+        let checked = parallel_download_bench(
+            store,
+            location,
+            DownloadOptions {
+                parallel_downloads: 16,
+                block_size: Some(OBJECT_SIZE / 16),
+                deadline: Some(DeadlineSpec {
+                    deadline: Duration::from_millis(10),
+                    on_expire: OnExpire::Skip,
+                }),
+                window: WINDOW,
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            control.clone(),
+        )
+        .await
+        .unwrap();
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let outcome = checked.into_result();
+        outcome.unwrap();
+        assert_eq!(result["deadline"]["requests"], 32);
+        assert_eq!(result["deadline"]["expired"], 32);
+        assert_eq!(result["deadline"]["goodput_bytes"], 0);
+        // Expiring is neither a success nor an error.
+        assert_eq!(result["num_requests"], 0);
+        assert_eq!(control.snapshot().errors, 0);
+        // Objects with skipped blocks aren't held to the plan.
+        assert_eq!(result["accounting"]["skipped_objects"], 2);
+        assert_eq!(result["accounting"]["mismatched_objects"], 0);
+    }
+
+    #[tokio::test]
+    async fn latency_is_added_to_every_read() {
+        let (store, counts, location) = faulty_store(&[Fault::LatencyMs(20)]).await;
+        let start = std::time::Instant::now();
+        let object = location.child("a.bin");
+        store.get_range(&object, 0..10).await.unwrap();
+        store.head(&object).await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(counts.delayed.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,111 @@
+//! Narrowing a listing to the data files, with `--filter`.
+//!
+//! A table directory holds manifests, `_SUCCESS` markers and checkpoints
+//! beside its data files. `--filter '*.parquet'` keeps only the listed objects
+//! whose path below the location matches the glob, before any command sees
+//! them, so `download`, `columnar`, `cleanup` and the rest all work on the
+//! same set. Patterns follow `mirror --include`; with `--filter` repeated an
+//! object is kept if it matches any of them. The number of objects kept and
+//! dropped is printed to stderr.
+
+use object_store::{path::Path, ObjectMeta};
+
+use crate::mirror::Glob;
+
+/// Keep the objects under `location` that match any of `globs`, or all of
+/// them if there are none.
+pub fn apply(
+    objects: Vec<ObjectMeta>,
+    location: &Path,
+    globs: &[Glob],
+) -> Result<Vec<ObjectMeta>, String> {
+    if globs.is_empty() {
+        return Ok(objects);
+    }
+    let total = objects.len();
+    let kept = keep(objects, location, globs);
+    eprintln!(
+        "--filter kept {} of {} objects, dropping {}",
+        kept.len(),
+        total,
+        total - kept.len()
+    );
+    if kept.is_empty() {
+        return Err(format!(
+            "none of the {} objects under {} match --filter",
+            total, location
+        ));
+    }
+    Ok(kept)
+}
+
+fn keep(objects: Vec<ObjectMeta>, location: &Path, globs: &[Glob]) -> Vec<ObjectMeta> {
+    objects
+        .into_iter()
+        .filter(|meta| {
+            let path = meta.location.as_ref();
+            // An object listed as the location itself is matched by its name.
+            let relative = path
+                .strip_prefix(location.as_ref())
+                .map(|relative| relative.trim_start_matches('/'))
+                .filter(|relative| !relative.is_empty())
+                .unwrap_or(path);
+            globs.iter().any(|glob| glob.matches(relative))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(location: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(location),
+            last_modified: chrono::Utc::now(),
+            size: 1,
+            e_tag: None,
+        }
+    }
+
+    #[test]
+    fn keeps_objects_matching_any_glob_below_the_location() {
+        let objects = vec![
+            meta("table/part-0.parquet"),
+            meta("table/_SUCCESS"),
+            meta("table/_delta_log/00000.json"),
+            meta("table/_delta_log/00000.checkpoint.parquet"),
+            meta("table/year=2023/part-1.parquet"),
+        ];
+        let location = Path::from("table");
+        let names = |globs: &[&str]| {
+            let globs = globs
+                .iter()
+                .map(|glob| glob.parse().unwrap())
+                .collect::<Vec<Glob>>();
+            keep(objects.clone(), &location, &globs)
+                .into_iter()
+                .map(|meta| meta.location.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(&["*.parquet"]),
+            [
+                "table/part-0.parquet",
+                "table/_delta_log/00000.checkpoint.parquet",
+                "table/year=2023/part-1.parquet"
+            ]
+        );
+        // Patterns with a `/` match the path below the location.
+        assert_eq!(
+            names(&["year=*/*.parquet", "_SUCCESS"]),
+            ["table/_SUCCESS", "table/year=2023/part-1.parquet"]
+        );
+        let single = keep(
+            vec![meta("table/part-0.parquet")],
+            &Path::from("table/part-0.parquet"),
+            &["*.parquet".parse().unwrap()],
+        );
+        assert_eq!(single.len(), 1);
+    }
+}
@@ -0,0 +1,151 @@
+//! Whole-object fetches through each of object_store's read calls.
+//!
+//! The same bytes can be fetched with `get`, with `get_opts` and a range
+//! covering the whole object, or with `get_range(0..size)`, and backends
+//! don't always issue the same request for each. This benchmark fetches every
+//! object under the location through each call in turn, at the same
+//! concurrency, so a reader can pick the cheapest one for small objects.
+
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, GetOptions, ObjectMeta, ObjectStore};
+use serde_json::json;
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::retry::RetryPolicy;
+use crate::stats::LatencySummary;
+
+/// One of the calls that can fetch a whole object.
+#[derive(Debug, Clone, Copy)]
+enum GetApi {
+    Get,
+    GetOpts,
+    GetRange,
+}
+
+impl GetApi {
+    const ALL: [GetApi; 3] = [GetApi::Get, GetApi::GetOpts, GetApi::GetRange];
+
+    fn name(self) -> &'static str {
+        match self {
+            GetApi::Get => "get",
+            GetApi::GetOpts => "get_opts",
+            GetApi::GetRange => "get_range",
+        }
+    }
+
+    /// Fetch all of `meta` through this call, returning the bytes received.
+    async fn fetch(
+        self,
+        object_store: &dyn ObjectStore,
+        meta: &ObjectMeta,
+    ) -> object_store::Result<usize> {
+        let bytes = match self {
+            GetApi::Get => object_store.get(&meta.location).await?.bytes().await?,
+            GetApi::GetOpts => {
+                let options = GetOptions {
+                    range: Some(0..meta.size),
+                    ..Default::default()
+                };
+                object_store
+                    .get_opts(&meta.location, options)
+                    .await?
+                    .bytes()
+                    .await?
+            }
+            GetApi::GetRange => object_store.get_range(&meta.location, 0..meta.size).await?,
+        };
+        Ok(bytes.len())
+    }
+}
+
+/// Benchmarks fetching every object under `location` whole, once per call
+///
+/// * `parallel_downloads`: maximum number of objects fetched concurrently
+pub async fn compare_get_apis(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel_downloads: usize,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+
+    let mut result = fields(json!({
+        "mode": "get_apis",
+        "num_objects": objects.len(),
+        "parallel_downloads": parallel_downloads,
+    }));
+    let mut fastest: Option<(GetApi, u128)> = None;
+    for api in GetApi::ALL {
+        let start = Instant::now();
+        let samples = futures::stream::iter(objects.iter())
+            .take_while(|_| futures::future::ready(!control.is_shutdown()))
+            .map(|meta| {
+                let object_store = object_store.clone();
+                let retry = retry.clone();
+                let control = control.clone();
+                async move {
+                    if !control.request_started().await {
+                        return Ok(None);
+                    }
+                    let range = 0..meta.size;
+                    match api {
+                        GetApi::Get => control.record("get", &meta.location, None),
+                        _ => control.record(api.name(), &meta.location, Some(&range)),
+                    }
+                    let start = Instant::now();
+                    match retry.run(|| api.fetch(object_store.as_ref(), meta)).await {
+                        Ok(bytes) => {
+                            control.request_finished(bytes);
+                            Ok(Some((bytes, start.elapsed())))
+                        }
+                        Err(err) => {
+                            control.request_failed();
+                            Err(err)
+                        }
+                    }
+                }
+            })
+            .buffer_unordered(parallel_downloads)
+            .try_filter_map(|sample| futures::future::ready(Ok(sample)))
+            .try_collect::<Vec<_>>()
+            .await?;
+        let elapsed_us = start.elapsed().as_micros();
+
+        let bytes: usize = samples.iter().map(|(bytes, _)| bytes).sum();
+        let mut latencies = samples
+            .iter()
+            .map(|(_, latency)| *latency)
+            .collect::<Vec<_>>();
+        let latency = LatencySummary::from_latencies(&mut latencies);
+        if latency.count > 0 && fastest.is_none_or(|(_, p50)| latency.p50_us < p50) {
+            fastest = Some((api, latency.p50_us));
+        }
+        result.insert(
+            api.name().to_string(),
+            json!({
+                "num_requests": samples.len(),
+                "bytes": bytes,
+                "elapsed_us": elapsed_us as u64,
+                "mbps": bytes as f64 / 1024.0 / 1024.0 / (elapsed_us as f64 / 1_000_000.0),
+                "latency": latency.to_json(),
+            }),
+        );
+    }
+
+    result.insert(
+        "fastest_p50".to_string(),
+        json!(fastest.map(|(api, _)| api.name())),
+    );
+    result.insert("interrupted".to_string(), control.is_shutdown().into());
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
@@ -0,0 +1,230 @@
+//! Metadata latency: a `head` of every object under the location.
+//!
+//! Some engines issue a HEAD per file before planning any reads, so planning
+//! time grows with the object count at the store's metadata latency. The
+//! objects are found with [`inspect_location`], so a location naming a single
+//! object heads just that one, then headed `--parallel` at a time. A failed
+//! head is counted under `failed`, with the first error kept, and the run goes
+//! on; only heads that succeeded count towards the latency percentiles. With
+//! `--missing-objects skip` or `retry-list`, an object deleted since the
+//! listing is reported under `vanished_objects` rather than as a failure.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use object_store::{path::Path, ObjectStore};
+use serde_json::json;
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::missing::MissingTracker;
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, Histogram};
+
+/// What a run of heads found.
+#[derive(Debug, Default, PartialEq)]
+pub struct HeadSummary {
+    pub succeeded: usize,
+    pub failed: usize,
+}
+
+/// Heads every object under `location`, `parallel` at a time.
+pub async fn head_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel: usize,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<HeadSummary, Error> {
+    let listing_start = Instant::now();
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    let listing_us = listing_start.elapsed().as_micros();
+    let tracker = MissingTracker::new(
+        object_store.clone(),
+        &location,
+        &objects,
+        control.listing(),
+        &retry,
+    );
+    let tracker = &tracker;
+
+    let start = Instant::now();
+    let outcomes: Vec<Result<Duration, object_store::Error>> = futures::stream::iter(&objects)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            async move {
+                if !control.request_started().await {
+                    return None;
+                }
+                control.record("head", &meta.location, None);
+                let start = Instant::now();
+                let head = tracker
+                    .resolve(&meta.location, || {
+                        retry.run(|| object_store.head(&meta.location))
+                    })
+                    .await;
+                match head {
+                    Ok(Some(_)) => {
+                        control.request_finished(0);
+                        Some(Ok(start.elapsed()))
+                    }
+                    // Deleted since the listing; the tracker reports it.
+                    Ok(None) => {
+                        control.request_failed();
+                        None
+                    }
+                    Err(err) => {
+                        control.request_failed();
+                        Some(Err(err))
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .filter_map(futures::future::ready)
+        .collect()
+        .await;
+    let elapsed_us = start.elapsed().as_micros();
+    let paused_us = control.paused().as_micros();
+
+    let mut histogram = Histogram::default();
+    let mut summary = HeadSummary::default();
+    let mut first_error = None;
+    for outcome in &outcomes {
+        match outcome {
+            Ok(latency) => {
+                histogram.record(*latency);
+                summary.succeeded += 1;
+            }
+            Err(err) => {
+                summary.failed += 1;
+                first_error.get_or_insert_with(|| err.to_string());
+            }
+        }
+    }
+    let active_secs = active_us(elapsed_us, paused_us) as f64 / 1_000_000.0;
+    let mut result = fields(json!({
+        "mode": "head",
+        "num_objects": objects.len(),
+        "requests": outcomes.len(),
+        "succeeded": summary.succeeded,
+        "failed": summary.failed,
+        "first_error": first_error,
+        "parallel": parallel,
+        "listing_us": listing_us as u64,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "requests_per_sec": outcomes.len() as f64 / active_secs,
+        "latency": histogram.summary(),
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(tracker.json_field());
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::capture;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use crate::missing::MissingObjects;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn failed_heads_are_counted_not_fatal() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for i in 0..4 {
+            inner
+                .put(&Path::from(format!("data/{}", i)), Bytes::from(vec![0; 10]))
+                .await
+                .unwrap();
+        }
+        let faults = [Fault::NotFound("data/1".to_string())];
+        let store = FaultStore::new(inner.clone(), FaultConfig::new(&faults), 0);
+        let (outcome, results) = capture(head_bench(
+            Arc::new(store),
+            Path::from("data"),
+            2,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        let summary = outcome.unwrap();
+        assert_eq!(
+            summary,
+            HeadSummary {
+                succeeded: 3,
+                failed: 1
+            }
+        );
+
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["num_objects"], 4);
+        assert_eq!(result["latency"]["count"], 3);
+        assert!(result["first_error"].is_string());
+
+        // A location naming one object heads just that object.
+        let (outcome, _) = capture(head_bench(
+            inner,
+            Path::from("data/1"),
+            2,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        assert_eq!(
+            outcome.unwrap(),
+            HeadSummary {
+                succeeded: 1,
+                failed: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_objects_vanish_rather_than_fail() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for i in 0..4 {
+            inner
+                .put(&Path::from(format!("data/{}", i)), Bytes::from(vec![0; 10]))
+                .await
+                .unwrap();
+        }
+        // Listed, then gone by the time it is headed.
+        let faults = [Fault::NotFound("data/2".to_string())];
+        let store = FaultStore::new(inner, FaultConfig::new(&faults), 0);
+        let retry = RetryPolicy::new(0, None).with_missing_objects(MissingObjects::Skip);
+        let (outcome, results) = capture(head_bench(
+            Arc::new(store),
+            Path::from("data"),
+            2,
+            retry,
+            RunControl::new(),
+        ))
+        .await;
+        assert_eq!(
+            outcome.unwrap(),
+            HeadSummary {
+                succeeded: 3,
+                failed: 0
+            }
+        );
+
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["first_error"], serde_json::Value::Null);
+        assert_eq!(result["vanished_objects"]["policy"], "skip");
+        let vanished = result["vanished_objects"]["objects"].as_array().unwrap();
+        assert_eq!(vanished.len(), 1);
+        assert_eq!(vanished[0]["path"], "data/2");
+    }
+}
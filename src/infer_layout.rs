@@ -0,0 +1,242 @@
+//! Inferring a page layout for the columnar simulator from a real file.
+//!
+//! With `columnar --infer-layout`, the first object's footer is read before
+//! the run. If it is a Parquet file and the tool was built with the `parquet`
+//! feature, each row group becomes a group and each column's page size is
+//! the mean compressed size of its column chunks. Otherwise the number of
+//! columns from `--page-sizes` is kept and their size is picked from the
+//! object's size alone. Results record which under `inferred_layout`.
+
+use object_store::{ObjectMeta, ObjectStore};
+
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+
+/// Parquet files start and end with this magic number.
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// The trailer after a Parquet footer: its length and the magic number.
+const PARQUET_TRAILER_SIZE: usize = 8;
+
+/// Page size Parquet writers default to, which the heuristic won't exceed.
+const MAX_HEURISTIC_PAGE: usize = 1024 * 1024;
+
+/// Smallest page size the heuristic picks.
+const MIN_HEURISTIC_PAGE: usize = 8 * 1024;
+
+/// Groups the heuristic aims to split an object into.
+const HEURISTIC_GROUPS: usize = 16;
+
+/// Page sizes to simulate, and where they came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferredLayout {
+    pub page_sizes: Vec<usize>,
+    /// What the sizes were inferred from, as the JSON recorded in results
+    pub source: serde_json::Value,
+}
+
+/// Read the footer of `meta` and infer page sizes from it, falling back to
+/// `num_columns` heuristically sized columns.
+pub async fn infer(
+    object_store: &dyn ObjectStore,
+    meta: &ObjectMeta,
+    num_columns: usize,
+    retry: &RetryPolicy,
+) -> Result<InferredLayout, Error> {
+    let reason = match read_parquet_footer(object_store, meta, retry).await? {
+        Ok(footer) => match from_parquet_footer(&footer) {
+            Ok(layout) => return Ok(layout),
+            Err(reason) => reason,
+        },
+        Err(reason) => reason,
+    };
+    Ok(from_size(meta.size, num_columns, &reason))
+}
+
+/// The footer of `meta` if it ends like a Parquet file, or why it doesn't.
+async fn read_parquet_footer(
+    object_store: &dyn ObjectStore,
+    meta: &ObjectMeta,
+    retry: &RetryPolicy,
+) -> Result<Result<Vec<u8>, String>, Error> {
+    if meta.size < PARQUET_MAGIC.len() + PARQUET_TRAILER_SIZE {
+        return Ok(Err("too small to be a Parquet file".to_string()));
+    }
+    let trailer_start = meta.size - PARQUET_TRAILER_SIZE;
+    let trailer = retry
+        .run(|| object_store.get_range(&meta.location, trailer_start..meta.size))
+        .await?;
+    if &trailer[4..] != PARQUET_MAGIC {
+        return Ok(Err("no Parquet magic number at the end".to_string()));
+    }
+    let footer_len = u32::from_le_bytes(trailer[..4].try_into().unwrap()) as usize;
+    let Some(footer_start) = trailer_start
+        .checked_sub(footer_len)
+        .filter(|start| *start >= PARQUET_MAGIC.len())
+    else {
+        return Ok(Err(format!(
+            "Parquet footer of {} bytes doesn't fit in the object",
+            footer_len
+        )));
+    };
+    let footer = retry
+        .run(|| object_store.get_range(&meta.location, footer_start..trailer_start))
+        .await?;
+    Ok(Ok(footer.to_vec()))
+}
+
+#[cfg(feature = "parquet")]
+fn from_parquet_footer(footer: &[u8]) -> Result<InferredLayout, String> {
+    let metadata = parquet::file::footer::decode_metadata(footer)
+        .map_err(|err| format!("Parquet footer didn't parse: {}", err))?;
+    let row_groups = metadata.row_groups();
+    let Some(first) = row_groups.first() else {
+        return Err("Parquet file has no row groups".to_string());
+    };
+    let mut chunk_bytes = vec![0u64; first.num_columns()];
+    for row_group in row_groups {
+        for (total, column) in chunk_bytes.iter_mut().zip(row_group.columns()) {
+            *total += column.compressed_size().max(0) as u64;
+        }
+    }
+    let page_sizes = chunk_bytes
+        .iter()
+        .map(|total| (total / row_groups.len() as u64).max(1) as usize)
+        .collect();
+    let columns = first
+        .columns()
+        .iter()
+        .map(|column| column.column_path().string())
+        .collect::<Vec<_>>();
+    Ok(InferredLayout {
+        page_sizes,
+        source: serde_json::json!({
+            "source": "parquet_footer",
+            "row_groups": row_groups.len(),
+            "columns": columns,
+            "footer_bytes": footer.len(),
+        }),
+    })
+}
+
+#[cfg(not(feature = "parquet"))]
+fn from_parquet_footer(_footer: &[u8]) -> Result<InferredLayout, String> {
+    Err("Parquet footer found, but this build lacks the parquet feature".to_string())
+}
+
+/// `num_columns` equal pages sized for about [`HEURISTIC_GROUPS`] groups,
+/// within the range of page sizes Parquet writers use.
+fn from_size(object_size: usize, num_columns: usize, reason: &str) -> InferredLayout {
+    let num_columns = num_columns.max(1);
+    let page_size = (object_size / (num_columns * HEURISTIC_GROUPS))
+        .clamp(MIN_HEURISTIC_PAGE, MAX_HEURISTIC_PAGE)
+        .min(object_size / num_columns)
+        .max(1);
+    InferredLayout {
+        page_sizes: vec![page_size; num_columns],
+        source: serde_json::json!({
+            "source": "size_heuristic",
+            "reason": reason,
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, path::Path};
+
+    async fn infer_bytes(bytes: Vec<u8>) -> InferredLayout {
+        let store = InMemory::new();
+        let location = Path::from("data/file.bin");
+        store.put(&location, Bytes::from(bytes)).await.unwrap();
+        let meta = store.head(&location).await.unwrap();
+        infer(&store, &meta, 3, &RetryPolicy::new(0, None))
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn heuristic_pages_stay_in_range() {
+        let pages = |size| from_size(size, 3, "").page_sizes;
+        assert_eq!(pages(1 << 30), vec![MAX_HEURISTIC_PAGE; 3]);
+        assert_eq!(pages(48 * 64 * 1024), vec![64 * 1024; 3]);
+        assert_eq!(pages(100 * 1024), vec![MIN_HEURISTIC_PAGE; 3]);
+        // Even a tiny object fits one group.
+        assert_eq!(pages(30), vec![10; 3]);
+    }
+
+    #[tokio::test]
+    async fn other_files_fall_back_to_the_heuristic() {
+        let layout = infer_bytes(vec![7; 48 * 64 * 1024]).await;
+        assert_eq!(layout.page_sizes, vec![64 * 1024; 3]);
+        assert_eq!(layout.source["source"], "size_heuristic");
+        assert_eq!(
+            layout.source["reason"],
+            "no Parquet magic number at the end"
+        );
+
+        // A footer length running off the front of the object.
+        let mut bytes = b"PAR1".to_vec();
+        bytes.extend([0; 64]);
+        bytes.extend(1000u32.to_le_bytes());
+        bytes.extend(b"PAR1");
+        let layout = infer_bytes(bytes).await;
+        assert!(layout.source["reason"]
+            .as_str()
+            .unwrap()
+            .contains("doesn't fit"));
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn parquet_column_chunks_become_pages() {
+        use parquet::data_type::{ByteArray, ByteArrayType, Int64Type};
+        use parquet::file::properties::WriterProperties;
+        use parquet::file::writer::SerializedFileWriter;
+        use std::sync::Arc;
+
+        let schema = Arc::new(
+            parquet::schema::parser::parse_message_type(
+                "message test { required int64 id; required binary payload; }",
+            )
+            .unwrap(),
+        );
+        let properties = Arc::new(
+            WriterProperties::builder()
+                .set_dictionary_enabled(false)
+                .build(),
+        );
+        let mut file = Vec::new();
+        let mut writer = SerializedFileWriter::new(&mut file, schema, properties).unwrap();
+        for _ in 0..4 {
+            let mut row_group = writer.next_row_group().unwrap();
+            let mut ids = row_group.next_column().unwrap().unwrap();
+            ids.typed::<Int64Type>()
+                .write_batch(&(0..1000).collect::<Vec<_>>(), None, None)
+                .unwrap();
+            ids.close().unwrap();
+            let mut payloads = row_group.next_column().unwrap().unwrap();
+            payloads
+                .typed::<ByteArrayType>()
+                .write_batch(&vec![ByteArray::from(vec![1u8; 100]); 1000], None, None)
+                .unwrap();
+            payloads.close().unwrap();
+            row_group.close().unwrap();
+        }
+        writer.close().unwrap();
+
+        let layout = infer_bytes(file).await;
+        assert_eq!(layout.source["source"], "parquet_footer");
+        assert_eq!(layout.source["row_groups"], 4);
+        assert_eq!(
+            layout.source["columns"],
+            serde_json::json!(["id", "payload"])
+        );
+        assert_eq!(layout.page_sizes.len(), 2);
+        // 1000 eight byte ids, and 1000 length-prefixed 100 byte payloads.
+        assert!(layout.page_sizes[0] >= 8000);
+        assert!(layout.page_sizes[1] >= 104_000);
+    }
+}
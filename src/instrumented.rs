@@ -0,0 +1,338 @@
+//! Counting the calls a benchmark makes, with `--stats`.
+//!
+//! [`crate::counting`] sits beneath every other wrapper and reports what the
+//! backend bills for. [`InstrumentedStore`] instead wraps the store the
+//! benchmark is handed, above all the others, so it sees each call the tool
+//! itself makes: `get_range` and `get_ranges` apart from whole `get`s, and
+//! any stray `head` or `list`. Every result then carries an `ops` field with
+//! a count for each method, such as `{"get_range": 12, "head": 0, ...}`,
+//! beside the bytes read and written through them and
+//! `read_sizes`, a histogram of the lengths of ranged reads in power-of-two
+//! buckets, each labelled by its upper bound.
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::experiment::Fields;
+
+/// The methods counted, each a key of `ops`.
+const METHODS: [&str; 13] = [
+    "put",
+    "put_multipart",
+    "abort_multipart",
+    "get",
+    "get_opts",
+    "get_range",
+    "get_ranges",
+    "head",
+    "delete",
+    "list",
+    "list_with_delimiter",
+    "copy",
+    "copy_if_not_exists",
+];
+
+/// Calls made through an [`InstrumentedStore`], and the bytes they moved.
+#[derive(Debug, Default)]
+pub struct OpStats {
+    state: Mutex<OpState>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct OpState {
+    calls: BTreeMap<&'static str, u64>,
+    bytes_read: u64,
+    bytes_written: u64,
+    /// Ranged reads by the power of two their length rounds up to
+    read_sizes: BTreeMap<usize, u64>,
+}
+
+impl OpStats {
+    fn call(&self, method: &'static str) {
+        *self.state.lock().unwrap().calls.entry(method).or_default() += 1;
+    }
+
+    fn ranges(&self, ranges: &[Range<usize>]) {
+        let mut state = self.state.lock().unwrap();
+        for range in ranges {
+            *state
+                .read_sizes
+                .entry(range.len().max(1).next_power_of_two())
+                .or_default() += 1;
+        }
+    }
+
+    fn read(&self, bytes: usize) {
+        self.state.lock().unwrap().bytes_read += bytes as u64;
+    }
+
+    fn written(&self, bytes: usize) {
+        self.state.lock().unwrap().bytes_written += bytes as u64;
+    }
+
+    /// Calls of `method` so far.
+    pub fn calls(&self, method: &str) -> u64 {
+        let state = self.state.lock().unwrap();
+        state.calls.get(method).copied().unwrap_or(0)
+    }
+
+    /// The calls by method, every method present even if never called, beside
+    /// the bytes moved and the read size histogram.
+    pub fn to_json(&self) -> serde_json::Value {
+        let state = self.state.lock().unwrap().clone();
+        let mut ops: serde_json::Map<String, serde_json::Value> = METHODS
+            .iter()
+            .map(|&method| {
+                let calls = state.calls.get(method).copied().unwrap_or(0);
+                (method.to_string(), calls.into())
+            })
+            .collect();
+        ops.insert("bytes_read".to_string(), state.bytes_read.into());
+        ops.insert("bytes_written".to_string(), state.bytes_written.into());
+        ops.insert(
+            "read_sizes".to_string(),
+            state
+                .read_sizes
+                .iter()
+                .map(|(up_to, requests)| {
+                    serde_json::json!({
+                        "up_to_bytes": up_to,
+                        "requests": requests,
+                    })
+                })
+                .collect(),
+        );
+        ops.into()
+    }
+}
+
+/// Append the calls `stats` counted so far to the JSON object `result`.
+pub fn with_ops(result: &mut Fields, stats: &OpStats) {
+    result.insert("ops".to_string(), stats.to_json());
+}
+
+/// An [`ObjectStore`] that records each call made on it before passing it
+/// to `inner`.
+pub struct InstrumentedStore {
+    inner: Arc<dyn ObjectStore>,
+    stats: Arc<OpStats>,
+}
+
+impl InstrumentedStore {
+    pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+        Self {
+            inner,
+            stats: Arc::default(),
+        }
+    }
+
+    /// The calls recorded, which stay readable after the store is handed to
+    /// a benchmark.
+    pub fn stats(&self) -> Arc<OpStats> {
+        self.stats.clone()
+    }
+
+    fn read_stream(&self, result: GetResult) -> GetResult {
+        let stats = self.stats.clone();
+        let stream = result.into_stream().inspect(move |chunk| {
+            if let Ok(chunk) = chunk {
+                stats.read(chunk.len());
+            }
+        });
+        GetResult::Stream(stream.boxed())
+    }
+}
+
+/// Counts the bytes written to a multipart upload.
+struct InstrumentedWriter {
+    inner: Box<dyn AsyncWrite + Unpin + Send>,
+    stats: Arc<OpStats>,
+}
+
+impl AsyncWrite for InstrumentedWriter {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.stats.written(written);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Display for InstrumentedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "InstrumentedStore({})", self.inner)
+    }
+}
+
+impl Debug for InstrumentedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InstrumentedStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for InstrumentedStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.stats.call("put");
+        self.stats.written(bytes.len());
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.stats.call("put_multipart");
+        let (id, inner) = self.inner.put_multipart(location).await?;
+        let writer = InstrumentedWriter {
+            inner,
+            stats: self.stats.clone(),
+        };
+        Ok((id, Box::new(writer)))
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.stats.call("abort_multipart");
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get(&self, location: &Path) -> Result<GetResult> {
+        self.stats.call("get");
+        let result = self.inner.get(location).await?;
+        Ok(self.read_stream(result))
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.stats.call("get_opts");
+        if let Some(range) = &options.range {
+            self.stats.ranges(std::slice::from_ref(range));
+        }
+        let result = self.inner.get_opts(location, options).await?;
+        Ok(self.read_stream(result))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.stats.call("get_range");
+        self.stats.ranges(std::slice::from_ref(&range));
+        let bytes = self.inner.get_range(location, range).await?;
+        self.stats.read(bytes.len());
+        Ok(bytes)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.stats.call("get_ranges");
+        self.stats.ranges(ranges);
+        let bytes = self.inner.get_ranges(location, ranges).await?;
+        self.stats.read(bytes.iter().map(Bytes::len).sum());
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.stats.call("head");
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.stats.call("delete");
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.stats.call("list");
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.stats.call("list_with_delimiter");
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.stats.call("copy");
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.stats.call("copy_if_not_exists");
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+    use tokio::io::AsyncWriteExt;
+
+    #[tokio::test]
+    async fn records_calls_bytes_and_read_sizes() {
+        let store = InstrumentedStore::new(Arc::new(InMemory::new()));
+        let stats = store.stats();
+        let location = Path::from("data/a.bin");
+        store
+            .put(&location, Bytes::from(vec![0; 10_000]))
+            .await
+            .unwrap();
+        let (_, mut writer) = store.put_multipart(&Path::from("big.bin")).await.unwrap();
+        writer.write_all(&[0; 100]).await.unwrap();
+        writer.shutdown().await.unwrap();
+
+        store.get(&location).await.unwrap().bytes().await.unwrap();
+        store.get_range(&location, 0..4096).await.unwrap();
+        store
+            .get_ranges(&location, &[0..1000, 4096..8192])
+            .await
+            .unwrap();
+        store.head(&location).await.unwrap();
+
+        assert_eq!(stats.calls("get_range"), 1);
+        assert_eq!(stats.calls("get_ranges"), 1);
+        assert_eq!(stats.calls("head"), 1);
+        assert_eq!(stats.calls("list"), 0);
+        let json = stats.to_json();
+        assert_eq!(json["get"], 1);
+        assert_eq!(json["list"], 0);
+        assert_eq!(json["copy_if_not_exists"], 0);
+        assert_eq!(json["bytes_read"], 10_000 + 4096 + 1000 + 4096);
+        assert_eq!(json["bytes_written"], 10_000 + 100);
+        assert_eq!(
+            json["read_sizes"],
+            serde_json::json!([
+                {"up_to_bytes": 1024, "requests": 1},
+                {"up_to_bytes": 4096, "requests": 2},
+            ])
+        );
+    }
+}
@@ -0,0 +1,346 @@
+//! Repeating a benchmark until its throughput settles.
+//!
+//! With `--until-stable cv=0.05,window=5,max=30`, the benchmark is run again
+//! and again until the coefficient of variation (standard deviation over mean)
+//! of the reported `mbps` across the last `window` iterations is at most `cv`,
+//! or `max` iterations have run. Each iteration's result is emitted as usual,
+//! followed by a summary saying whether and when stability was reached.
+//!
+//! With `--min-runtime-secs N`, the benchmark is instead repeated until at
+//! least N seconds have passed, for example to run past a credential refresh.
+//! The summary then carries the run's error counts by kind and the timeline
+//! of auth errors since the start of the run.
+//!
+//! `download --iterations N --warmup M` runs M untimed iterations and then N
+//! measured ones, listing the objects only for the first. Each result carries
+//! `iteration`, saying whether it was a warmup, and the summary gives the
+//! mean, standard deviation, minimum and maximum of the measured `mbps`, or
+//! of `objects_per_sec` for `list`.
+
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectMeta};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
+use crate::retry::RetryPolicy;
+
+/// Listings kept for the later runs of a [`ListingOptions`](crate::ListingOptions):
+/// every location's while repeating with [`repeat`], and one location's while
+/// runs share it, as a sweep's do.
+#[derive(Debug, Default)]
+pub struct ListingReuse {
+    repeating: AtomicBool,
+    shared: Mutex<Option<Path>>,
+    /// The first listing kept
+    listing: Mutex<Option<(Path, Vec<ObjectMeta>)>>,
+}
+
+impl ListingReuse {
+    /// Whether listings of `location` are being kept for later runs.
+    fn reusing(&self, location: &Path) -> bool {
+        self.repeating.load(Ordering::SeqCst)
+            || self.shared.lock().unwrap().as_ref() == Some(location)
+    }
+
+    /// Keep every listing while `repeating`.
+    fn set_repeating(&self, repeating: bool) {
+        self.repeating.store(repeating, Ordering::SeqCst);
+    }
+
+    /// Forget the listing kept so far.
+    fn forget(&self) {
+        *self.listing.lock().unwrap() = None;
+    }
+
+    /// Share the first listing of `location` with later runs, until called
+    /// again with `None`.
+    pub fn share(&self, location: Option<&Path>) {
+        *self.listing.lock().unwrap() = None;
+        *self.shared.lock().unwrap() = location.cloned();
+    }
+
+    /// The objects an earlier run listed under `location`, if kept.
+    pub fn reused(&self, location: &Path) -> Option<Vec<ObjectMeta>> {
+        if !self.reusing(location) {
+            return None;
+        }
+        match &*self.listing.lock().unwrap() {
+            Some((listed, objects)) if listed == location => Some(objects.clone()),
+            _ => None,
+        }
+    }
+
+    /// Keep the listing of `location` for later runs, if reusing it.
+    pub fn remember(&self, location: &Path, objects: &[ObjectMeta]) {
+        if self.reusing(location) {
+            *self.listing.lock().unwrap() = Some((location.clone(), objects.to_vec()));
+        }
+    }
+}
+
+/// The iteration running, while repeating with [`repeat`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Iteration {
+    /// Counted separately for warmup and measured iterations
+    index: usize,
+    warmup: bool,
+}
+
+/// When to stop repeating.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StabilityCriterion {
+    pub cv: f64,
+    pub window: usize,
+    pub max: usize,
+}
+
+impl Default for StabilityCriterion {
+    fn default() -> Self {
+        Self {
+            cv: 0.05,
+            window: 5,
+            max: 30,
+        }
+    }
+}
+
+impl FromStr for StabilityCriterion {
+    type Err = String;
+
+    /// Parse `key=value` pairs separated by commas; missing keys keep their
+    /// defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut criterion = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", pair))?;
+            let invalid = |err: &dyn std::fmt::Display| format!("{}: {}", key, err);
+            match key.trim() {
+                "cv" => criterion.cv = value.parse().map_err(|err| invalid(&err))?,
+                "window" => criterion.window = value.parse().map_err(|err| invalid(&err))?,
+                "max" => criterion.max = value.parse().map_err(|err| invalid(&err))?,
+                _ => return Err(format!("unknown key {:?}; expected cv, window or max", key)),
+            }
+        }
+        if criterion.window < 2 {
+            return Err("window must be at least 2".to_string());
+        }
+        if criterion.max < criterion.window {
+            return Err(format!(
+                "max ({}) must be at least the window ({})",
+                criterion.max, criterion.window
+            ));
+        }
+        Ok(criterion)
+    }
+}
+
+/// Sample coefficient of variation of `values`.
+pub fn coefficient_of_variation(values: &[f64]) -> f64 {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    variance.sqrt() / mean
+}
+
+/// Sample mean and standard deviation of `values`.
+pub fn mean_and_stddev(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    if values.len() < 2 {
+        return (mean, 0.0);
+    }
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance.sqrt())
+}
+
+/// Run `iteration` `warmup` times untimed and then `iterations` times,
+/// emitting a summary of the result field `metric` across those measured.
+pub async fn repeat<F, Fut>(
+    iterations: usize,
+    warmup: usize,
+    metric: &str,
+    control: &RunControl,
+    mut iteration: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let reuse = &control.listing().reuse;
+    reuse.forget();
+    let mut throughputs = Vec::new();
+    let mut warmups_run = 0;
+    let plan = (0..warmup)
+        .map(|index| Iteration {
+            index,
+            warmup: true,
+        })
+        .chain((0..iterations).map(|index| Iteration {
+            index,
+            warmup: false,
+        }));
+    let running = &control.context().iteration;
+    for current in plan {
+        if control.is_shutdown() {
+            break;
+        }
+        control.reset_paused();
+        *running.lock().unwrap() = Some(current);
+        reuse.set_repeating(true);
+        iteration().await;
+        reuse.set_repeating(false);
+        *running.lock().unwrap() = None;
+        let result = control
+            .take_last_result()
+            .ok_or("the benchmark did not report a result")?;
+        if current.warmup {
+            warmups_run += 1;
+            continue;
+        }
+        let value = serde_json::from_str::<serde_json::Value>(&result)?
+            .get(metric)
+            .and_then(|v| v.as_f64())
+            .ok_or_else(|| format!("--iterations needs a benchmark that reports {}", metric))?;
+        throughputs.push(value);
+    }
+    reuse.forget();
+
+    let (mean, stddev) = mean_and_stddev(&throughputs);
+    let finite = |value: f64| value.is_finite().then_some(value);
+    let mut summary = serde_json::json!({
+        "mode": "iterations",
+        "warmup_iterations": warmups_run,
+        "iterations": throughputs.len(),
+        "interrupted": control.is_shutdown(),
+    });
+    for (name, value) in [
+        (metric.to_string(), serde_json::json!(throughputs)),
+        (format!("mean_{}", metric), serde_json::json!(finite(mean))),
+        (
+            format!("stddev_{}", metric),
+            serde_json::json!(finite(stddev)),
+        ),
+        (
+            format!("min_{}", metric),
+            serde_json::json!(throughputs.iter().copied().reduce(f64::min)),
+        ),
+        (
+            format!("max_{}", metric),
+            serde_json::json!(throughputs.iter().copied().reduce(f64::max)),
+        ),
+    ] {
+        summary[name] = value;
+    }
+    control.emit(summary);
+    Ok(())
+}
+
+/// Append the place of the running `iteration` to the JSON object `result`.
+pub(crate) fn with_iteration(result: &mut Fields, iteration: &Iteration) {
+    result.insert(
+        "iteration".to_string(),
+        serde_json::json!({"index": iteration.index, "warmup": iteration.warmup}),
+    );
+}
+
+/// Run `iteration` until `criterion` is met, then emit a summary.
+pub async fn until_stable<F, Fut>(
+    criterion: StabilityCriterion,
+    control: &RunControl,
+    mut iteration: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut throughputs = Vec::new();
+    let mut cv = None;
+    while throughputs.len() < criterion.max && !control.is_shutdown() {
+        control.reset_paused();
+        iteration().await;
+        let result = control
+            .take_last_result()
+            .ok_or("the benchmark did not report a result")?;
+        let mbps = serde_json::from_str::<serde_json::Value>(&result)?
+            .get("mbps")
+            .and_then(|v| v.as_f64())
+            .ok_or("--until-stable needs a benchmark that reports mbps")?;
+        throughputs.push(mbps);
+        if throughputs.len() >= criterion.window {
+            let latest =
+                coefficient_of_variation(&throughputs[throughputs.len() - criterion.window..]);
+            cv = Some(latest);
+            if latest <= criterion.cv {
+                break;
+            }
+        }
+    }
+    let stable = cv.is_some_and(|cv| cv <= criterion.cv);
+    let window = &throughputs[throughputs.len().saturating_sub(criterion.window)..];
+
+    control.emit(serde_json::json!({
+        "mode": "until_stable",
+        "target_cv": criterion.cv,
+        "window": criterion.window,
+        "max_iterations": criterion.max,
+        "iterations": throughputs.len(),
+        "stable": stable,
+        "final_cv": cv,
+        "window_mean_mbps": window.iter().sum::<f64>() / window.len().max(1) as f64,
+        "mbps": throughputs,
+        "interrupted": control.is_shutdown(),
+    }));
+    Ok(())
+}
+
+/// Run `iteration` until `min_runtime` has passed, then emit a summary.
+pub async fn for_at_least<F, Fut>(
+    min_runtime: Duration,
+    control: &RunControl,
+    retry: &RetryPolicy,
+    mut iteration: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let start = Instant::now();
+    let mut iterations = 0;
+    while !control.is_shutdown() {
+        control.reset_paused();
+        iteration().await;
+        iterations += 1;
+        if start.elapsed() >= min_runtime {
+            break;
+        }
+    }
+    let mut result = fields(serde_json::json!({
+        "mode": "min_runtime",
+        "min_runtime_secs": min_runtime.as_secs_f64(),
+        "iterations": iterations,
+        "elapsed_us": start.elapsed().as_micros() as u64,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spread_of_iterations() {
+        let (mean, stddev) = mean_and_stddev(&[90.0, 100.0, 110.0]);
+        assert_eq!(mean, 100.0);
+        assert_eq!(stddev, 10.0);
+        assert_eq!(mean_and_stddev(&[42.0]), (42.0, 0.0));
+    }
+}
@@ -0,0 +1,194 @@
+//! Benchmarks of object store throughput and latency, as a library.
+//!
+//! The `object-store-bench` binary is a command line over these modules, and
+//! a harness or integration test can call the benchmarks the same way:
+//! [`parallel_download_bench`], [`columnar_read_test`] and the upload helpers
+//! in [`upload`] each return their typed result, or an [`Error`] to match on.
+//! Download and columnar results come back [`Checked`], with any check made
+//! after the run that failed, and printing them is left to the caller. The
+//! other benchmarks report through [`control::RunControl::emit`] as the binary
+//! prints them; wrap a call in [`experiment::capture`] to collect them
+//! instead. [`ListingOptions`] says which of the listed objects a run reads,
+//! where the binary fills it from `--filter`, `--max-objects` and the like,
+//! and a [`context::RunContext`] what else its results report.
+
+use std::sync::Arc;
+
+use futures::TryStreamExt;
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+pub mod accounting;
+pub mod analyze;
+pub mod backoff;
+pub mod calibration;
+pub mod cleanup;
+pub mod columnar;
+pub mod connection;
+pub mod content_encoding;
+pub mod context;
+pub mod control;
+pub mod cost;
+pub mod counting;
+pub mod coverage;
+pub mod deadline;
+pub mod digest;
+pub mod direct_io;
+pub mod download;
+pub mod duration;
+pub mod error;
+pub mod experiment;
+pub mod fairness;
+pub mod fault;
+pub mod filter;
+pub mod get_apis;
+pub mod head;
+pub mod infer_layout;
+pub mod instrumented;
+pub mod iterate;
+pub mod list;
+pub mod listing_cache;
+pub mod manifest;
+pub mod merge;
+pub mod mirror;
+pub mod missing;
+pub mod naming;
+pub mod partitions;
+pub mod pattern;
+pub mod plan;
+pub mod pool;
+pub mod progress;
+pub mod query_sim;
+pub mod random_read;
+pub mod ranges_file;
+pub mod reassembly;
+pub mod recipe;
+pub mod report;
+pub mod request_trace;
+pub mod retry;
+pub mod sampling;
+pub mod schedule;
+pub mod scratch;
+pub mod scrub;
+pub mod selection;
+pub mod selftest;
+pub mod simulate;
+pub mod size_buckets;
+pub mod sparkline;
+pub mod stats;
+pub mod store_defaults;
+pub mod store_options;
+pub mod sweep;
+pub mod swr;
+pub mod tail;
+pub mod think_time;
+pub mod trace;
+pub mod ttfb;
+pub mod upload;
+pub mod user_defaults;
+pub mod wire;
+pub mod worker;
+
+pub use columnar::{columnar_read_test, ColumnarOptions};
+pub use download::{parallel_download_bench, DownloadOptions, ReadMode};
+pub use error::Error;
+pub use report::{Checked, ColumnarResult, DownloadResult};
+pub use upload::{bench_upload, upload_multiple, upload_test_data};
+
+use retry::RetryPolicy;
+
+/// How [`inspect_location`] narrows a listing to the objects this process
+/// benchmarks. The default keeps every listed object.
+#[derive(Debug, Clone, Default)]
+pub struct ListingOptions {
+    /// Keep only the objects uploaded by this `--run-id`
+    pub run_id: Option<String>,
+    /// Keep only objects matching one of these `--filter` globs, if any
+    pub filters: Vec<mirror::Glob>,
+    /// Sample the listing down to `--max-objects`
+    pub sampling: Option<Arc<sampling::Sampling>>,
+    /// Keep only this worker's share of a sharded run
+    pub worker: Option<worker::WorkerId>,
+    /// Reuse a fresh cached listing, and cache new ones
+    pub cache: Option<Arc<listing_cache::ListingCache>>,
+    /// Listings kept for later runs, while repeating or sweeping
+    pub reuse: Arc<iterate::ListingReuse>,
+}
+
+/// Inspects the given location and returns a list of all objects and their sizes.
+///
+/// If the location is an object itself, it will just return that object.
+///
+/// If the location is a common prefix, it will return all objects with that prefix.
+///
+/// The objects are then narrowed as `listing` says: to one run's uploads,
+/// to those matching its filters, to a sample, and to this worker's share.
+///
+/// With a listing cache, a fresh cached listing of the prefix is used instead of
+/// listing it, and a new listing is written to the cache.
+///
+/// With `--iterations`, iterations after the first reuse the first one's objects.
+pub async fn inspect_location(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    listing: &ListingOptions,
+    retry: &RetryPolicy,
+) -> Result<Vec<ObjectMeta>, Error> {
+    if let Some(objects) = listing.reuse.reused(location) {
+        return Ok(objects);
+    }
+    let cached = listing
+        .cache
+        .as_ref()
+        .and_then(|cache| cache.load(location));
+    let objects = match cached {
+        Some(objects) => narrow(objects, location, listing)?,
+        None => refresh_location(object_store, location, listing, retry).await?,
+    };
+    listing.reuse.remember(location, &objects);
+    Ok(objects)
+}
+
+/// Like [`inspect_location`], but always asks the store, updating the
+/// listing cache if there is one.
+pub async fn refresh_location(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    listing: &ListingOptions,
+    retry: &RetryPolicy,
+) -> Result<Vec<ObjectMeta>, Error> {
+    match retry.probe(|| object_store.head(location)).await {
+        Ok(metadata) => {
+            let objects = filter::apply(vec![metadata], location, &listing.filters)?;
+            Ok(worker::shard(objects, listing.worker)?)
+        }
+        Err(err @ object_store::Error::NotFound { .. }) => {
+            let objects: Vec<ObjectMeta> = retry
+                .run(|| async { object_store.list(Some(location)).await?.try_collect().await })
+                .await?;
+            if objects.is_empty() {
+                // Neither an object nor a prefix: report the original miss.
+                return Err(err.into());
+            }
+            if let Some(cache) = &listing.cache {
+                cache.save(location, &objects);
+            }
+            narrow(objects, location, listing)
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// The listed objects this process benchmarks: those of the run matching
+/// the filters, sampled, then this worker's share of them.
+fn narrow(
+    objects: Vec<ObjectMeta>,
+    location: &Path,
+    listing: &ListingOptions,
+) -> Result<Vec<ObjectMeta>, Error> {
+    let objects = naming::select_run(objects, listing.run_id.as_deref())?;
+    let mut objects = filter::apply(objects, location, &listing.filters)?;
+    if let Some(sampling) = &listing.sampling {
+        objects = sampling.apply(objects);
+    }
+    Ok(worker::shard(objects, listing.worker)?)
+}
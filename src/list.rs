@@ -0,0 +1,334 @@
+//! Listing benchmark, single-stream or sharded by sub-prefix.
+//!
+//! A single-stream listing pages through the whole prefix with one `list`. The
+//! sharded strategy first walks the prefix with `list_with_delimiter` down to
+//! `--shard-by-prefix` levels to discover sub-prefixes, then lists those
+//! concurrently. Discovery is part of the sharded strategy's elapsed time.
+//! The delimited strategy, with `--delimiter`, walks the whole prefix with
+//! `list_with_delimiter` alone, one request per prefix, to compare that code
+//! path with a flat `list`.
+//!
+//! The result's `objects_per_sec` is that of the strategy asked for, so
+//! `--iterations` summarizes it across runs.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+use serde_json::{json, Value};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::retry::RetryPolicy;
+
+/// Most differing paths listed in the output; the counts are always complete.
+const MAX_DIFF_PATHS: usize = 20;
+
+/// Parameters for [`list_bench`].
+#[derive(Debug, Clone)]
+pub struct ListOptions {
+    /// Depth of sub-prefixes to discover before listing them concurrently
+    pub shard_depth: Option<usize>,
+    /// Number of sub-prefixes listed concurrently
+    pub parallel: usize,
+    /// Walk the prefix with delimited listings instead of one flat listing
+    pub delimiter: bool,
+    /// Run the single-stream listing too and compare the two
+    pub compare: bool,
+}
+
+struct Listing {
+    paths: BTreeSet<String>,
+    elapsed_us: u128,
+    requests: usize,
+    /// Sub-prefixes listed concurrently, for the sharded strategy
+    num_prefixes: Option<usize>,
+    discovery_us: Option<u128>,
+}
+
+impl Listing {
+    fn to_json(&self, strategy: &str) -> Value {
+        let mut listing = fields(json!({
+            "strategy": strategy,
+            "num_objects": self.paths.len(),
+            "requests": self.requests,
+            "elapsed_us": self.elapsed_us,
+            "objects_per_sec": self.objects_per_sec(),
+        }));
+        if let (Some(num_prefixes), Some(discovery_us)) = (self.num_prefixes, self.discovery_us) {
+            listing.insert("num_prefixes".to_string(), num_prefixes.into());
+            listing.insert("discovery_us".to_string(), (discovery_us as u64).into());
+        }
+        listing.into()
+    }
+
+    fn objects_per_sec(&self) -> f64 {
+        self.paths.len() as f64 / (self.elapsed_us as f64 / 1_000_000.0)
+    }
+}
+
+/// Benchmarks listing every object under `location`.
+pub async fn list_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: ListOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let alternative = options.shard_depth.is_some() || options.delimiter;
+    let single = if !alternative || options.compare {
+        Some(list_single(object_store.as_ref(), &location, &retry, &control).await?)
+    } else {
+        None
+    };
+    let other = match options.shard_depth {
+        Some(depth) => Some((
+            "sharded",
+            list_sharded(
+                object_store.clone(),
+                &location,
+                depth,
+                options.parallel,
+                &retry,
+                &control,
+            )
+            .await?,
+        )),
+        None if options.delimiter => Some((
+            "delimited",
+            list_delimited(object_store.as_ref(), &location, &retry, &control).await?,
+        )),
+        None => None,
+    };
+    let objects_per_sec = match (&single, &other) {
+        (_, Some((_, listing))) | (Some(listing), None) => listing.objects_per_sec(),
+        (None, None) => unreachable!("at least one strategy always runs"),
+    };
+
+    let strategies = match (&single, &other) {
+        (Some(single), Some((name, other))) => {
+            let diff = |a: &Listing, b: &Listing| {
+                let paths = a.paths.difference(&b.paths).collect::<Vec<_>>();
+                (
+                    paths.len(),
+                    serde_json::json!(paths.into_iter().take(MAX_DIFF_PATHS).collect::<Vec<_>>()),
+                )
+            };
+            let (only_single, only_single_paths) = diff(single, other);
+            let (only_other, only_other_paths) = diff(other, single);
+            fields(json!({
+                "single": single.to_json("single"),
+                *name: other.to_json(name),
+                "speedup": single.elapsed_us as f64 / other.elapsed_us as f64,
+                "consistent": only_single == 0 && only_other == 0,
+                "only_in_single": only_single,
+                "only_in_single_paths": only_single_paths,
+                format!("only_in_{}", name): only_other,
+                format!("only_in_{}_paths", name): only_other_paths,
+            }))
+        }
+        (Some(listing), None) => fields(json!({ "single": listing.to_json("single") })),
+        (None, Some((name, listing))) => fields(json!({ *name: listing.to_json(name) })),
+        (None, None) => unreachable!("at least one strategy always runs"),
+    };
+
+    let mut result = fields(json!({
+        "mode": "list",
+        "shard_depth": options.shard_depth,
+        "delimiter": options.delimiter,
+        "parallel": options.parallel,
+        "objects_per_sec": objects_per_sec,
+    }));
+    result.extend(strategies);
+    result.insert("interrupted".to_string(), control.is_shutdown().into());
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+/// List every object under `prefix` with a single `list` call.
+async fn list_all(
+    object_store: &dyn ObjectStore,
+    prefix: &Path,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Vec<String>> {
+    if !control.request_started().await {
+        return Ok(Vec::new());
+    }
+    let result = retry
+        .run(|| async {
+            object_store
+                .list(Some(prefix))
+                .await?
+                .map_ok(|meta| meta.location.to_string())
+                .try_collect::<Vec<_>>()
+                .await
+        })
+        .await;
+    match &result {
+        Ok(_) => control.request_finished(0),
+        Err(_) => control.request_failed(),
+    }
+    result
+}
+
+async fn list_single(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Listing> {
+    let start = Instant::now();
+    let paths = list_all(object_store, location, retry, control).await?;
+    Ok(Listing {
+        paths: paths.into_iter().collect(),
+        elapsed_us: start.elapsed().as_micros(),
+        requests: 1,
+        num_prefixes: None,
+        discovery_us: None,
+    })
+}
+
+/// Walk every prefix under `location` with `list_with_delimiter`, breadth
+/// first, one request per prefix.
+async fn list_delimited(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Listing> {
+    let start = Instant::now();
+    let mut paths = BTreeSet::new();
+    let mut prefixes = std::collections::VecDeque::from([location.clone()]);
+    let mut requests = 0;
+    while let Some(prefix) = prefixes.pop_front() {
+        if !control.request_started().await {
+            break;
+        }
+        requests += 1;
+        let listing = match retry
+            .run(|| object_store.list_with_delimiter(Some(&prefix)))
+            .await
+        {
+            Ok(listing) => {
+                control.request_finished(0);
+                listing
+            }
+            Err(err) => {
+                control.request_failed();
+                return Err(err);
+            }
+        };
+        paths.extend(listing.objects.iter().map(|meta| meta.location.to_string()));
+        prefixes.extend(listing.common_prefixes);
+    }
+    Ok(Listing {
+        paths,
+        elapsed_us: start.elapsed().as_micros(),
+        requests,
+        num_prefixes: None,
+        discovery_us: None,
+    })
+}
+
+async fn list_sharded(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    depth: usize,
+    parallel: usize,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Listing> {
+    let start = Instant::now();
+
+    // Walk down `depth` levels. Objects sitting directly in a walked prefix are
+    // found by the walk itself; only the deepest prefixes need a full listing.
+    let mut paths = BTreeSet::new();
+    let mut prefixes = vec![location.clone()];
+    let mut requests = 0;
+    for _ in 0..depth {
+        let mut next = Vec::new();
+        for prefix in &prefixes {
+            if !control.request_started().await {
+                break;
+            }
+            requests += 1;
+            let result = retry
+                .run(|| object_store.list_with_delimiter(Some(prefix)))
+                .await;
+            let listing = match result {
+                Ok(listing) => {
+                    control.request_finished(0);
+                    listing
+                }
+                Err(err) => {
+                    control.request_failed();
+                    return Err(err);
+                }
+            };
+            paths.extend(listing.objects.iter().map(|meta| meta.location.to_string()));
+            next.extend(listing.common_prefixes);
+        }
+        prefixes = next;
+        if prefixes.is_empty() {
+            break;
+        }
+    }
+    let discovery_us = start.elapsed().as_micros();
+
+    let num_prefixes = prefixes.len();
+    requests += num_prefixes;
+    let listed = futures::stream::iter(prefixes)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|prefix| {
+            let object_store = object_store.clone();
+            async move { list_all(object_store.as_ref(), &prefix, retry, control).await }
+        })
+        .buffer_unordered(parallel)
+        .try_collect::<Vec<_>>()
+        .await?;
+    paths.extend(listed.into_iter().flatten());
+
+    Ok(Listing {
+        paths,
+        elapsed_us: start.elapsed().as_micros(),
+        requests,
+        num_prefixes: Some(num_prefixes),
+        discovery_us: Some(discovery_us),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn delimited_walk_finds_what_a_flat_listing_does() {
+        let store = InMemory::new();
+        for name in ["data/x", "data/a/y", "data/a/b/z", "data/c/w", "other/v"] {
+            store
+                .put(&Path::from(name), Bytes::from_static(b"hi"))
+                .await
+                .unwrap();
+        }
+        let location = Path::from("data");
+        let retry = RetryPolicy::new(0, None);
+        let control = RunControl::new();
+        let single = list_single(&store, &location, &retry, &control)
+            .await
+            .unwrap();
+        let delimited = list_delimited(&store, &location, &retry, &control)
+            .await
+            .unwrap();
+        assert_eq!(delimited.paths, single.paths);
+        assert_eq!(delimited.paths.len(), 4);
+        // One request for each of data, data/a, data/a/b and data/c.
+        assert_eq!(delimited.requests, 4);
+    }
+}
@@ -0,0 +1,299 @@
+//! Reusing a listing of the benchmarked location across invocations.
+//!
+//! Listing a prefix with hundreds of thousands of objects can take minutes.
+//! With `--listing-cache path`, the objects found under the location are
+//! written to `path`, and later runs against the same URI load them from
+//! there instead of listing, until the cache is older than
+//! `--listing-cache-ttl` seconds. Results say whether the cache was used and
+//! how old it was.
+//!
+//! A cached object may have been deleted since the cache was written. Reads
+//! go through [`StaleCheckStore`], which turns a `NotFound` for a cached path
+//! into an error naming the cache and how to refresh it.
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::experiment::Fields;
+
+/// A listing of one location of one store, kept in a file.
+#[derive(Debug)]
+pub struct ListingCache {
+    path: std::path::PathBuf,
+    ttl: Duration,
+    uri: String,
+    location: String,
+    /// Age of the cache when it was loaded, or `None` if the location was listed
+    used: Mutex<Option<Option<Duration>>>,
+    /// Paths loaded from the cache, which a `NotFound` would make stale
+    cached: Mutex<HashSet<String>>,
+}
+
+impl ListingCache {
+    /// Cache the objects under `location` of `uri` in `path` for `ttl`.
+    pub fn new(path: std::path::PathBuf, ttl: Duration, uri: &str, location: &Path) -> Self {
+        Self {
+            path,
+            ttl,
+            uri: uri.to_string(),
+            location: location.to_string(),
+            used: Mutex::new(None),
+            cached: Mutex::default(),
+        }
+    }
+
+    /// The cached objects under `location`, if the cache holds a fresh listing of it.
+    pub fn load(&self, location: &Path) -> Option<Vec<ObjectMeta>> {
+        if self.location != location.as_ref() {
+            return None;
+        }
+        let contents = std::fs::read_to_string(&self.path).ok()?;
+        let loaded = parse(self, &contents);
+        if let Err(reason) = &loaded {
+            eprintln!(
+                "not using listing cache {}: {}",
+                self.path.display(),
+                reason
+            );
+        }
+        let (objects, age) = loaded.ok()?;
+        *self.used.lock().unwrap() = Some(Some(age));
+        self.cached
+            .lock()
+            .unwrap()
+            .extend(objects.iter().map(|meta| meta.location.to_string()));
+        Some(objects)
+    }
+
+    /// Write `objects`, just found under `location`, to the cache.
+    pub fn save(&self, location: &Path, objects: &[ObjectMeta]) {
+        if self.location != location.as_ref() {
+            return;
+        }
+        *self.used.lock().unwrap() = Some(None);
+        let record = serde_json::json!({
+            "uri": self.uri,
+            "created_at": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            "objects": objects
+                .iter()
+                .map(|meta| serde_json::json!({
+                    "path": meta.location.to_string(),
+                    "size": meta.size,
+                    "e_tag": meta.e_tag,
+                    "last_modified": meta.last_modified.to_rfc3339_opts(SecondsFormat::Micros, true),
+                }))
+                .collect::<Vec<_>>(),
+        });
+        if let Err(err) = std::fs::write(&self.path, format!("{}\n", record)) {
+            eprintln!(
+                "failed to write listing cache {}: {}",
+                self.path.display(),
+                err
+            );
+        }
+    }
+}
+
+fn parse(
+    cache: &ListingCache,
+    contents: &str,
+) -> std::result::Result<(Vec<ObjectMeta>, Duration), String> {
+    let record: serde_json::Value =
+        serde_json::from_str(contents).map_err(|err| format!("invalid cache: {}", err))?;
+    let field = |name: &str| {
+        record
+            .get(name)
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("cache is missing {}", name))
+    };
+    if field("uri")? != cache.uri {
+        return Err(format!("it lists {}, not {}", field("uri")?, cache.uri));
+    }
+    let created_at = DateTime::parse_from_rfc3339(field("created_at")?)
+        .map_err(|err| format!("invalid created_at: {}", err))?;
+    let age = (Utc::now() - created_at.with_timezone(&Utc))
+        .to_std()
+        .unwrap_or_default();
+    if age > cache.ttl {
+        return Err(format!(
+            "it is {:.0}s old, more than the {}s allowed",
+            age.as_secs_f64(),
+            cache.ttl.as_secs()
+        ));
+    }
+    let objects = record
+        .get("objects")
+        .and_then(|v| v.as_array())
+        .ok_or("cache is missing objects")?
+        .iter()
+        .map(|object| {
+            let path = object.get("path").and_then(|v| v.as_str());
+            let size = object.get("size").and_then(|v| v.as_u64());
+            let last_modified = object
+                .get("last_modified")
+                .and_then(|v| v.as_str())
+                .and_then(|v| DateTime::parse_from_rfc3339(v).ok());
+            match (path, size, last_modified) {
+                (Some(path), Some(size), Some(last_modified)) => Ok(ObjectMeta {
+                    location: Path::from(path),
+                    last_modified: last_modified.with_timezone(&Utc),
+                    size: size as usize,
+                    e_tag: object
+                        .get("e_tag")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                }),
+                _ => Err(format!("invalid cached object {}", object)),
+            }
+        })
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+    if objects.is_empty() {
+        return Err("it lists no objects".to_string());
+    }
+    Ok((objects, age))
+}
+
+/// Append whether `cache` was used to the JSON object `result`.
+pub fn with_status(result: &mut Fields, cache: &ListingCache) {
+    let Some(used) = *cache.used.lock().unwrap() else {
+        return;
+    };
+    let status = serde_json::json!({
+        "path": cache.path.display().to_string(),
+        "used": used.is_some(),
+        "age_s": used.map(|age| age.as_secs_f64()),
+    });
+    result.insert("listing_cache".to_string(), status);
+}
+
+/// Explain a `NotFound` for an object that was loaded from `cache`.
+fn check_stale(
+    cache: &ListingCache,
+    location: &Path,
+    err: object_store::Error,
+) -> object_store::Error {
+    match err {
+        object_store::Error::NotFound { path, source }
+            if cache.cached.lock().unwrap().contains(location.as_ref()) =>
+        {
+            object_store::Error::NotFound {
+                source: format!(
+                    "stale listing cache: {} is listed in {} but no longer exists; delete the cache or lower --listing-cache-ttl to list again ({})",
+                    location,
+                    cache.path.display(),
+                    source
+                )
+                .into(),
+                path,
+            }
+        }
+        err => err,
+    }
+}
+
+/// An [`ObjectStore`] that reports reads of deleted cached objects as a stale cache.
+pub struct StaleCheckStore {
+    inner: Arc<dyn ObjectStore>,
+    cache: Arc<ListingCache>,
+}
+
+impl StaleCheckStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, cache: Arc<ListingCache>) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl Display for StaleCheckStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "StaleCheckStore({})", self.inner)
+    }
+}
+
+impl Debug for StaleCheckStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaleCheckStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for StaleCheckStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.inner
+            .get_opts(location, options)
+            .await
+            .map_err(|err| check_stale(&self.cache, location, err))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.inner
+            .get_range(location, range)
+            .await
+            .map_err(|err| check_stale(&self.cache, location, err))
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.inner
+            .get_ranges(location, ranges)
+            .await
+            .map_err(|err| check_stale(&self.cache, location, err))
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner
+            .head(location)
+            .await
+            .map_err(|err| check_stale(&self.cache, location, err))
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
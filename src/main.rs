@@ -1,111 +1,344 @@
 use std::sync::Arc;
 
-use clap::{Parser, Subcommand};
-use futures::TryStreamExt;
-use object_store::{parse_url, ObjectMeta};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand};
+use futures::FutureExt;
 use object_store::{path::Path, ObjectStore};
-use rand::{thread_rng, Rng, RngCore};
-use tokio::io::AsyncWriteExt;
 use tracing_chrome::{ChromeLayerBuilder, TraceStyle};
 use tracing_subscriber::prelude::*;
 
-mod columnar;
-mod download;
-
-/// Upload a test object of the given size
-///
-/// This will upload in batches of 10MB, allowing for objects larger than memory.
-///
-/// The data generated will be random bytes.
-async fn upload_test_data(
-    object_store: Arc<dyn ObjectStore>,
-    location: &Path,
-    size: usize,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let (_id, mut writer) = object_store.put_multipart(location).await?;
-
-    // Write 10 MB at a time
-    let mut written = 0;
-    let mut rng = rand::thread_rng();
-    let mut buffer = vec![0; 10 * 1024 * 1024];
-    while written < size {
-        let to_write = std::cmp::min(size - written, 10 * 1024 * 1024);
-        rng.fill_bytes(&mut buffer);
-        writer.write_all(&buffer[0..to_write]).await?;
-        written += to_write;
-    }
-    writer.flush().await?;
-    writer.shutdown().await?;
-
-    Ok(())
-}
-
-async fn upload_multiple(
-    object_store: Arc<dyn ObjectStore>,
-    location: &Path,
-    num_objects: usize,
-    size: usize,
-    random_prefixes: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let size_per_object = size / num_objects;
-    if size % num_objects != 0 {
-        panic!("size must be divisible by num_objects");
-    }
-
-    for i in 0..num_objects {
-        let mut location = location.parts().collect::<Vec<_>>();
-        if random_prefixes {
-            let prefix = thread_rng()
-                .sample_iter(rand::distributions::Alphanumeric)
-                .take(8)
-                .collect::<Vec<u8>>();
-            let prefix = String::from_utf8(prefix).unwrap();
-
-            location.push(prefix.into());
-        }
-        location.push(format!("object_{}.bin", i).into());
-        let location = Path::from_iter(location);
-        upload_test_data(object_store.clone(), &location, size_per_object).await?;
-    }
-
-    Ok(())
-}
-
-/// Inspects the given location and returns a list of all objects and their sizes.
-///
-/// If the location is an object itself, it will just return that object.
-///
-/// If the location is a common prefix, it will return all objects with that prefix.
-async fn inspect_location(
-    object_store: &dyn ObjectStore,
-    location: &Path,
-) -> Result<Vec<ObjectMeta>, Box<dyn std::error::Error>> {
-    match object_store.head(location).await {
-        Ok(metadata) => Ok(vec![metadata]),
-        Err(object_store::Error::NotFound { .. }) => Ok(object_store
-            .list(Some(location))
-            .await?
-            .try_collect()
-            .await?),
-        Err(err) => Err(err.into()),
-    }
-}
+use object_store_bench::context::RunContext;
+use object_store_bench::{
+    backoff, calibration, cleanup, columnar, connection, content_encoding, control, cost, counting,
+    coverage, deadline, digest, direct_io, download, duration, experiment, fairness, fault,
+    get_apis, head, instrumented, iterate, list, listing_cache, merge, mirror, missing, naming,
+    partitions, pattern, plan, pool, progress, query_sim, random_read, ranges_file, reassembly,
+    recipe, report, request_trace, retry, sampling, schedule, scrub, selection, selftest, simulate,
+    size_buckets, store_defaults, store_options, sweep, swr, tail, think_time, trace, ttfb, upload,
+    user_defaults, wire, worker, Checked, Error, ListingOptions,
+};
 
+use retry::RetryPolicy;
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     /// Optional name to operate on
+    ///
+    /// For the report command, this is a local results file or experiment directory.
     object_uri: String,
 
     /// Enable tracing for debugging
     #[arg(short, long, default_value = "false")]
     traced: bool,
 
+    /// Log requests to stderr: `-v` for failures and whole runs, `-vv` for
+    /// every request as it finishes. RUST_LOG overrides the filter
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Maximum number of times a single failed request is retried
+    #[arg(long, default_value = "0")]
+    max_retries: usize,
+
+    /// Longest wait before a request's first retry, in milliseconds. Each
+    /// later retry may wait twice as long, up to 10 seconds; the actual wait
+    /// is drawn uniformly below that
+    #[arg(long, default_value = "100")]
+    retry_delay_ms: u64,
+
+    /// Total number of retries allowed across all requests in the run.
+    /// Once exhausted, further failures are permanent. Unlimited by default.
+    #[arg(long, default_value = None)]
+    retry_budget: Option<usize>,
+
+    /// Cut the number of requests in flight when the store throttles, and
+    /// grow it back once it stops: AIMD over windows of the throttle rate.
+    /// Takes optional `threshold=0.05,window-ms=1000,decrease=0.5,min=1`
+    #[arg(long, num_args = 0..=1, default_missing_value = "")]
+    adaptive_backoff: Option<backoff::AimdConfig>,
+
+    /// What a `NotFound` for a listed object does partway through download,
+    /// columnar, scrub, head and random-read runs: end the run, skip the
+    /// object, or list the location again once and skip every object no
+    /// longer listed
+    #[arg(long, value_enum, default_value = "fail")]
+    missing_objects: missing::MissingObjects,
+
+    /// Let download, columnar and random-read runs go on past requests that
+    /// fail for good, leaving their bytes out and counting them by kind. The
+    /// run still exits with an error after reporting
+    #[arg(long, default_value = "false")]
+    continue_on_error: bool,
+
+    /// Fail download, columnar and scrub runs whose bytes received from some
+    /// object disagree with what the run planned to read from it
+    #[arg(long)]
+    strict_accounting: bool,
+
+    /// Object sizes, in bytes, at which download and scrub results split
+    /// their per-size-class statistics
+    #[arg(
+        long,
+        value_delimiter = ',',
+        default_values_t = size_buckets::DEFAULT_EDGES
+    )]
+    size_buckets: Vec<u64>,
+
+    /// Write results and traces into a new timestamped subdirectory of this
+    /// directory, and record the run in its index.jsonl
+    #[arg(long, default_value = None)]
+    experiment_dir: Option<std::path::PathBuf>,
+
+    /// Append each result, one JSON line, to this file instead of printing it
+    #[arg(long, default_value = None)]
+    output: Option<std::path::PathBuf>,
+
+    /// Listen for keypresses while a benchmark runs: [s]tatus, [p]ause,
+    /// [r]esume, [q]uit. Ignored unless stdin is a terminal.
+    #[arg(long, default_value = "false")]
+    interactive: bool,
+
+    /// After Ctrl-C, wait this long for requests in flight before reporting
+    /// without them
+    #[arg(long, default_value = "10")]
+    interrupt_timeout_secs: f64,
+
+    /// Write periodic machine-readable progress events to stderr
+    #[arg(long, value_enum, default_value = None)]
+    progress_format: Option<progress::ProgressFormat>,
+
+    /// Write progress events to this already-open file descriptor instead of stderr
+    #[arg(long, default_value = None)]
+    progress_fd: Option<i32>,
+
+    /// Draw a progress bar of bytes moved on stderr, when it is a terminal.
+    /// On by default there; `--progress=false` turns it off. Ignored with
+    /// `--progress-format`
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    progress: Option<bool>,
+
+    /// Milliseconds between progress events
+    #[arg(long, default_value = "1000")]
+    progress_interval_ms: u64,
+
+    /// Record every issued request, in issue order, to this JSONL file
+    #[arg(long, default_value = None)]
+    record_plan: Option<std::path::PathBuf>,
+
+    /// Write a sample of every read to this file: when it was issued, the
+    /// object and offset, the bytes received, its latency, outcome and
+    /// attempt. `report` reads it back
+    #[arg(long, default_value = None)]
+    request_trace: Option<std::path::PathBuf>,
+
+    /// Format of `--request-trace`: JSON lines, or Parquet row groups in
+    /// builds with the parquet feature
+    #[arg(long, value_enum, default_value = "jsonl")]
+    trace_format: request_trace::TraceFormat,
+
+    /// Inject a fault into reads, as `error-rate=P`, `range-error-rate=P`,
+    /// `latency-ms=N`, `truncate-rate=P` or `not-found=KEY`. May be repeated
+    #[arg(long = "fault")]
+    faults: Vec<fault::Fault>,
+
+    /// Fail this fraction of `get_range` calls with an injected error, the same
+    /// as `--fault range-error-rate=P`
+    #[arg(long, value_name = "PROBABILITY", value_parser = fault::parse_failure_rate)]
+    inject_failures: Option<fault::Fault>,
+
+    /// Seed for choosing which reads are faulted
+    #[arg(long, alias = "inject-failures-seed", default_value = "0")]
+    fault_seed: u64,
+
+    /// Identifier shared by every worker of one logical run, recorded in results.
+    /// Uploads embed it in object names, and reads keep only its objects
+    #[arg(long, default_value = None)]
+    run_id: Option<String>,
+
+    /// Run as worker k of n, taking every nth object starting at the kth
+    #[arg(long, default_value = None)]
+    worker_id: Option<worker::WorkerId>,
+
+    /// Only benchmark listed objects whose path below the location matches
+    /// this glob, such as `*.parquet`. May be repeated
+    #[arg(long = "filter")]
+    filters: Vec<mirror::Glob>,
+
+    /// Benchmark at most this many of the listed objects
+    #[arg(long, default_value = None)]
+    max_objects: Option<usize>,
+
+    /// Which objects `--max-objects` keeps
+    #[arg(long, value_enum, default_value = "first", requires = "max_objects")]
+    sample: sampling::SampleKind,
+
+    /// Seed for `--sample random`
+    #[arg(long, default_value = "0")]
+    sample_seed: u64,
+
+    /// Calibration record from the calibrate command; results then include
+    /// overhead-adjusted figures alongside the raw ones
+    #[arg(long, default_value = None)]
+    calibration: Option<std::path::PathBuf>,
+
+    /// Repeat the benchmark until the coefficient of variation of its
+    /// throughput over the last `window` runs is at most `cv`, or `max` runs
+    /// have happened, e.g. `cv=0.05,window=5,max=30`
+    #[arg(long, default_value = None)]
+    until_stable: Option<iterate::StabilityCriterion>,
+
+    /// Repeat the benchmark until at least this many seconds have passed, for
+    /// example to run past a credential refresh. The summary includes a
+    /// timeline of auth errors; pair with --max-retries to ride out blips
+    #[arg(long, default_value = None, conflicts_with = "until_stable")]
+    min_runtime_secs: Option<f64>,
+
+    /// Run the benchmark on a fixed schedule, starting every this many
+    /// seconds, in one process that keeps its connections between runs
+    #[arg(long, default_value = None, requires = "repeat_count", conflicts_with_all = ["until_stable", "min_runtime_secs"])]
+    repeat_every_secs: Option<f64>,
+
+    /// Number of runs to make on the --repeat-every-secs schedule
+    #[arg(long, default_value = None, requires = "repeat_every_secs")]
+    repeat_count: Option<usize>,
+
+    /// Build a new store client for every scheduled run instead of reusing
+    /// the warm one
+    #[arg(
+        long,
+        default_value = "false",
+        requires = "repeat_every_secs",
+        conflicts_with = "accept_encoding"
+    )]
+    fresh_client_each_run: bool,
+
+    /// Keep at most this many idle connections per host in the HTTP client's
+    /// pool. Only s3, gs and http(s) stores take it
+    #[arg(long, default_value = None)]
+    pool_max_idle_per_host: Option<usize>,
+
+    /// Speak only HTTP/2 to the store. Only s3, gs and http(s) stores take it
+    #[arg(long, default_value = "false")]
+    http2: bool,
+
+    /// Send this header, as name=value, with every request; may be repeated.
+    /// Only s3, gs and http(s) stores take it. Results list the names, never
+    /// the values
+    #[arg(long = "header", value_name = "NAME=VALUE", value_parser = pool::parse_header)]
+    headers: Vec<(String, String)>,
+
+    /// Pass this key=value to the store's builder, such as
+    /// `aws_endpoint=http://localhost:9000`; may be repeated. Only s3 and gs
+    /// stores take it. Results list the keys, never the values
+    #[arg(long = "store-option", value_name = "KEY=VALUE", value_parser = store_options::parse_option)]
+    store_options: Vec<(String, String)>,
+
+    /// TOML file of further store options, as top-level keys; a
+    /// `--store-option` beats the same key here
+    #[arg(long, default_value = None)]
+    store_config: Option<std::path::PathBuf>,
+
+    /// Run download once per pool size, each with a new client, and
+    /// summarize throughput and p99 for each. Takes optional comma-separated
+    /// sizes, 1 to 64 by default
+    #[arg(
+        long,
+        value_delimiter = ',',
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = pool::DEFAULT_SWEEP,
+        conflicts_with_all = ["pool_max_idle_per_host", "fresh_client_each_run", "accept_encoding", "until_stable", "min_runtime_secs", "repeat_every_secs"]
+    )]
+    pool_sweep: Option<Vec<usize>>,
+
+    /// Chart sweeps on stderr even when stdout isn't a terminal
+    #[arg(long, default_value = "false")]
+    sparkline: bool,
+
+    /// Load the prefix's object list from this file instead of listing it,
+    /// and write a new listing to it when it is missing or stale
+    #[arg(long, default_value = None)]
+    listing_cache: Option<std::path::PathBuf>,
+
+    /// Seconds a listing cache stays fresh
+    #[arg(long, default_value = "3600")]
+    listing_cache_ttl: u64,
+
+    /// Read local files with O_DIRECT, or evict them from the page cache after
+    /// each read where that isn't supported, so reruns measure the device
+    #[arg(long)]
+    direct_io: bool,
+
+    /// Record which byte ranges of each object were read, reporting each
+    /// object's coverage and a heat map of this many buckets per object
+    /// (`--coverage=256`; 1024 if no count is given)
+    #[arg(
+        long,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "1024",
+        value_parser = clap::value_parser!(u64).range(1..=65536)
+    )]
+    coverage: Option<u64>,
+
+    /// Count the store calls the benchmark makes by method, with the bytes
+    /// they moved and a histogram of ranged read sizes, under `ops`
+    #[arg(long, default_value = "false")]
+    stats: bool,
+
+    /// Delay each get, head and put by this many milliseconds, plus up to
+    /// JITTER_MS more drawn uniformly, as if the store were remote
+    #[arg(long, value_name = "MS[:JITTER_MS]")]
+    simulate_latency: Option<simulate::SimulatedLatency>,
+
+    /// Limit the bytes read and put to this many MB/s, shared by all requests
+    #[arg(long, value_name = "MBPS")]
+    simulate_bandwidth: Option<f64>,
+
+    /// File for the per-object heat maps; defaults to coverage.jsonl in the
+    /// experiment's run directory
+    #[arg(long, requires = "coverage")]
+    coverage_out: Option<std::path::PathBuf>,
+
+    /// Group reads and heads by the first N bytes of their key, reporting
+    /// the slowest prefixes and those whose p99 or error rate stands out
+    #[arg(long, default_value = None, value_parser = clap::value_parser!(u64).range(1..))]
+    partition_prefix_bytes: Option<u64>,
+
+    /// Before the benchmark, time resolving, connecting to and handshaking
+    /// with the store's endpoint, and a cold and a warm request, recording
+    /// each stage in the results
+    #[arg(long, default_value = "false")]
+    connection_breakdown: bool,
+
+    /// For http(s):// stores, the Accept-Encoding to read with; `compare`
+    /// runs the command once with identity and once with gzip. Results then
+    /// report the bytes received on the wire and after decoding
+    #[arg(long, value_enum, default_value = None)]
+    accept_encoding: Option<content_encoding::AcceptEncoding>,
+
+    /// TOML file of per-request and per-GB prices; results then include an
+    /// estimated cost alongside the request counts
+    #[arg(long, default_value = None)]
+    price_model: Option<std::path::PathBuf>,
+
+    /// Estimated request plus response header bytes per request, as `N` for
+    /// every request class or `get=N` for one. May be repeated. The default
+    /// depends on the store; results report payload plus this overhead as
+    /// `estimated_wire_bytes`
+    #[arg(long = "wire-overhead-bytes")]
+    wire_overhead: Vec<wire::WireOverhead>,
+
+    /// Leave the temporary objects benchmarks write, such as self-test's,
+    /// in place instead of deleting them afterwards
+    #[arg(long, default_value = "false")]
+    keep_scratch: bool,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
 
-#[derive(Subcommand)]
+#[derive(Subcommand, Clone)]
 enum Commands {
     /// Uploads test data to the given object store uri
     ///
@@ -114,6 +347,27 @@ enum Commands {
         /// Number of bytes to upload to the object. Defaults to 100MB.
         #[arg(short, long, default_value = "104857600")]
         size: usize,
+        /// Bytes handed to the multipart writer at a time. Default: 10 MiB
+        #[arg(long, default_value = "10485760")]
+        part_size: usize,
+        #[command(flatten)]
+        digest: DigestArgs,
+        #[command(flatten)]
+        windows: UploadWindowArgs,
+        /// Time only the transfer, from data generated up front, and report
+        /// the parts written instead of per-window throughput
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = ["digest", "part_size", "seed"]
+        )]
+        bench: bool,
+        /// Draw the object's bytes from this seed, so the same seed and size
+        /// upload the same data again
+        #[arg(long, default_value = None)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        pattern: PatternArgs,
     },
 
     /// Uploads multiple test objects
@@ -121,13 +375,37 @@ enum Commands {
         /// Number of objects to upload
         #[arg(short, long, default_value = "10")]
         num_objects: usize,
-        /// Total number of bytes to upload across all objects
-        /// Default: 10GB
+        /// Total number of bytes to upload across all objects, split as
+        /// evenly as possible. Default: 10GB
         #[arg(short, long, default_value = "10737418240")]
         size: usize,
+        /// Bytes handed to the multipart writer at a time. Default: 10 MiB
+        #[arg(long, default_value = "10485760")]
+        part_size: usize,
         /// Whether to use random prefixes
         #[arg(short, long, default_value = "false")]
         random_prefixes: bool,
+        /// Draw the random prefixes from this seed, so they can be reproduced
+        #[arg(long, default_value = None, requires = "random_prefixes")]
+        prefix_seed: Option<u64>,
+        /// Draw object i's bytes from this seed plus i, and the random
+        /// prefixes from this seed unless --prefix-seed is given, so the
+        /// upload can be regenerated byte for byte
+        #[arg(long, default_value = None)]
+        seed: Option<u64>,
+        #[command(flatten)]
+        pattern: PatternArgs,
+        /// Name objects object_{i}.bin instead of embedding the run id
+        #[arg(long, default_value = "false")]
+        flat_names: bool,
+        /// Write the run id, root prefix and the full list of uploaded names
+        /// to this file, for scrub and cleanup to work from
+        #[arg(long, default_value = None)]
+        manifest_out: Option<std::path::PathBuf>,
+        #[command(flatten)]
+        digest: DigestArgs,
+        #[command(flatten)]
+        windows: UploadWindowArgs,
     },
 
     /// Times how long it takes to download an object.
@@ -138,81 +416,2083 @@ enum Commands {
     ///
     ///
     Download {
-        #[arg(short, long, default_value = "10")]
-        parallel_downloads: usize,
+        /// Maximum number of requests in flight. The default is tuned for the
+        /// store; 10 is used for stores without tuned defaults. A
+        /// comma-separated list runs the download once per value
+        #[arg(short, long, default_value = "10", value_parser = concurrency_list)]
+        parallel_downloads: sweep::Values<usize>,
+        /// Size of each ranged request. The default is tuned for the store, or
+        /// splits each object evenly across the parallel downloads, in blocks
+        /// of at least 64 KiB; smaller objects are fetched whole. A
+        /// comma-separated list runs the download once per value
         #[arg(short, long, default_value = None)]
-        block_size: Option<usize>,
+        block_size: Option<sweep::Values<usize>>,
+        /// Read each object in ranged blocks, or stream it whole through one
+        /// get as simple copy tools do
+        #[arg(long = "mode", value_enum, default_value = "ranged")]
+        read_mode: download::ReadMode,
+        /// Keep reading for this long, such as `60s` or `5m`, cycling through
+        /// the blocks, and report throughput over that window only
+        #[arg(long, value_parser = duration::parse)]
+        duration: Option<std::time::Duration>,
+        /// Seconds to wait between the runs of a --parallel-downloads or
+        /// --block-size sweep
+        #[arg(long, default_value = "0")]
+        cooldown_secs: f64,
+        /// Stream each block and drain it at no more than this many MB/s,
+        /// simulating a slow consumer
+        #[arg(long, default_value = None, conflicts_with = "request_deadline_ms")]
+        consume_mbps: Option<f64>,
+        /// Instead of downloading whole objects, read the last N bytes of each
+        /// one, comparing head + absolute range against a suffix range
+        #[arg(
+            long,
+            default_value = None,
+            conflicts_with_all = ["request_deadline_ms", "think_time_ms"]
+        )]
+        suffix_bytes: Option<usize>,
+        /// Also report latency and throughput over consecutive windows of
+        /// this many seconds, to surface degradation during the run
+        #[arg(long, default_value = "10")]
+        window_secs: f64,
+        /// Also report the bytes and requests completed in each second of
+        /// the run, starting from its start, as a `timeline` array
+        #[arg(long, default_value = "false")]
+        timeline: bool,
+        /// Instead of downloading in blocks, fetch each object whole with get,
+        /// get_opts and get_range in turn and compare the three
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = ["suffix_bytes", "request_deadline_ms", "think_time_ms"]
+        )]
+        compare_get_apis: bool,
+        /// Hand blocks on strictly in offset order, as a sequential writer
+        /// would, and report how much reorder buffer that takes
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = ["suffix_bytes", "compare_get_apis", "consume_mbps", "request_deadline_ms", "think_time_ms"]
+        )]
+        reassemble: bool,
+        /// With --reassemble, cap the bytes in flight plus those waiting to be
+        /// handed on, delaying new requests until there is room
+        #[arg(long, default_value = None, requires = "reassemble")]
+        max_buffered_bytes: Option<usize>,
+        /// Instead of downloading, plan reading one synthetic object of this
+        /// many bytes and check the block and byte arithmetic, issuing no
+        /// requests
+        #[arg(
+            long,
+            default_value = None,
+            conflicts_with_all = [
+                "suffix_bytes",
+                "compare_get_apis",
+                "reassemble",
+                "consume_mbps",
+                "request_deadline_ms",
+                "think_time_ms"
+            ]
+        )]
+        huge_object: Option<u64>,
+        /// Instead of planning blocks, read exactly the ranges listed in this
+        /// file, one `path,offset,len` per line as CSV or JSON, in file order
+        #[arg(
+            long,
+            default_value = None,
+            conflicts_with_all = [
+                "suffix_bytes",
+                "compare_get_apis",
+                "reassemble",
+                "consume_mbps",
+                "huge_object",
+                "request_deadline_ms",
+                "think_time_ms"
+            ]
+        )]
+        ranges_file: Option<std::path::PathBuf>,
+        /// Check every block against the pattern `upload-data --pattern
+        /// offset` writes, and exit non-zero after reporting if any differ
+        #[arg(
+            long,
+            default_value = "false",
+            conflicts_with_all = [
+                "suffix_bytes",
+                "compare_get_apis",
+                "reassemble",
+                "consume_mbps",
+                "huge_object",
+                "ranges_file",
+                "request_deadline_ms"
+            ]
+        )]
+        verify: bool,
+        #[command(flatten)]
+        repeat: IterationArgs,
+        #[command(flatten)]
+        deadline: DeadlineArgs,
+        #[command(flatten)]
+        pacing: PacingArgs,
     },
 
-    Columnar {
-        /// Number of batches to read in parallel
-        #[arg(short, long, default_value = "10")]
+    /// Times reading the objects as a columnar format would, splitting each
+    /// into groups of pages of the given sizes and reading them page by page
+    Columnar(ColumnarArgs),
+
+    /// Times listing every object under the location
+    List {
+        /// Discover sub-prefixes this many levels deep with delimited listings,
+        /// then list them concurrently
+        #[arg(long, default_value = None)]
+        shard_by_prefix: Option<usize>,
+        /// Number of sub-prefixes to list concurrently
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Walk the prefix with delimited listings, one request per prefix,
+        /// instead of one flat listing
+        #[arg(long, default_value = "false", conflicts_with = "shard_by_prefix")]
+        delimiter: bool,
+        /// Also run the single-stream listing and compare the two
+        #[arg(long, default_value = "false")]
+        compare: bool,
+        #[command(flatten)]
+        repeat: IterationArgs,
+    },
+
+    /// Measures how a batch scan affects interactive reads on the same prefix.
+    ///
+    /// Class A issues random small reads at a fixed rate, first alone and then
+    /// while class B scans the objects in large blocks at full speed.
+    Fairness {
+        /// Size of each class A read
+        #[arg(long, default_value = "262144")]
+        read_size: usize,
+        /// Class A requests issued per second
+        #[arg(long, default_value = "20")]
+        target_qps: f64,
+        /// Size of each class B block read
+        #[arg(long, default_value = "16777216")]
+        block_size: usize,
+        /// Number of concurrent class B scanners
+        #[arg(short, long, default_value = "8", value_parser = concurrency)]
+        parallel: usize,
+        /// Seconds to run class A for, both alone and alongside class B
+        #[arg(long, default_value = "10")]
+        duration_secs: f64,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        #[command(flatten)]
+        deadline: DeadlineArgs,
+    },
+
+    /// Issues ranged reads at random offsets, as point lookups do
+    RandomRead {
+        /// Number of reads to issue
+        #[arg(short, long, default_value = "1000")]
+        num_requests: usize,
+        /// Bytes per read; objects smaller than this are read whole
+        #[arg(long, default_value = "65536")]
+        request_size: usize,
+        /// Number of reads in flight
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Seed for the objects and offsets read, so a run can be repeated
+        /// against another store; the same as --selection-seed. Random by
+        /// default
+        #[arg(long, conflicts_with = "selection_seed")]
+        seed: Option<u64>,
+        /// Keep reading for this long, such as `60s` or `5m`, instead of
+        /// issuing --num-requests reads
+        #[arg(long, value_parser = duration::parse, conflicts_with = "num_requests")]
+        duration: Option<std::time::Duration>,
+        /// Read as this recipe says, such as
+        /// `seed=42;reads=10000;size=256K;dist=zipf(1.1);objects=all`, in
+        /// place of the options that pick the reads
+        #[arg(
+            long,
+            conflicts_with_all = [
+                "num_requests",
+                "request_size",
+                "seed",
+                "duration",
+                "selection",
+                "zipf_exponent",
+                "recency_half_life_secs",
+                "weights_file",
+                "selection_seed",
+            ]
+        )]
+        workload_recipe: Option<recipe::Recipe>,
+        /// Print the first this many planned reads instead of issuing them
+        #[arg(long)]
+        print_workload: Option<usize>,
+        #[command(flatten)]
+        selection: SelectionArgs,
+        #[command(flatten)]
+        deadline: DeadlineArgs,
+    },
+
+    /// Times the first byte and the whole body of a streaming get per object
+    Ttfb {
+        /// Number of objects fetched in parallel
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Read only this many bytes from the start of each object, with a
+        /// ranged get_opts
+        #[arg(long, default_value = None)]
+        range_size: Option<usize>,
+    },
+
+    /// Reads every object in full, optionally verifying digests
+    Scrub {
+        /// Number of objects to read in parallel. The default is tuned for the store
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
         parallel_downloads: usize,
-        /// Comma-separated list of page sizes to use
-        #[arg(short, long, default_value = "65536,65536,65536")]
-        page_sizes: Option<String>,
+        /// Audit file written by an upload with --digest-out to verify against
+        #[arg(long, default_value = None)]
+        digests: Option<std::path::PathBuf>,
+        /// Read the objects named in this upload manifest instead of listing
+        #[arg(long, default_value = None)]
+        manifest: Option<std::path::PathBuf>,
+    },
+
+    /// Heads every object under the location, timing each request
+    Head {
+        /// Number of heads in flight
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+    },
+
+    /// Deletes test data under the location
+    Cleanup {
+        /// Number of deletes in flight
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Delete exactly the objects named in this upload manifest, without
+        /// listing, instead of everything under the location
+        #[arg(long, default_value = None)]
+        manifest: Option<std::path::PathBuf>,
+    },
+
+    /// Measures the tool's own per-request and per-byte overhead by running a
+    /// scaled-down copy of a download or columnar workload in memory
+    Calibrate {
+        /// Write the calibration record to this file, for use with --calibration
+        #[arg(long, default_value = None)]
+        out: Option<std::path::PathBuf>,
+        /// The workload to calibrate, with the parameters it will be run with
+        #[command(subcommand)]
+        workload: CalibrationWorkload,
+    },
+
+    /// Summarizes recorded results from a results file or experiment
+    /// directory, or the reads of a `--request-trace` in either format
+    Report,
+
+    /// Combines the results of the workers of one run, read from OBJECT_URI and FILES
+    Merge {
+        /// Further result files to merge
+        files: Vec<std::path::PathBuf>,
+        /// Run to merge, when the files hold several
+        #[arg(long, default_value = None)]
+        run_id: Option<String>,
+    },
+
+    /// Upgrades the results file or experiment directory at OBJECT_URI to
+    /// the current result schema
+    Migrate {
+        /// File to write the upgraded records to. Default: stdout
+        #[arg(long, default_value = None)]
+        out: Option<std::path::PathBuf>,
+    },
+
+    /// Replays a plan recorded with --record-plan against this location
+    Replay {
+        /// Plan file to replay
+        plan: std::path::PathBuf,
+        /// Override the concurrency recorded in the plan
+        #[arg(short, long, default_value = None, value_parser = concurrency)]
+        parallel_downloads: Option<usize>,
+    },
+
+    /// Runs a miniature version of every benchmark under the location and
+    /// checks what each reports, printing a pass/fail line per stage.
+    ///
+    /// Use `memory:///` or a scratch prefix; everything written is deleted
+    /// afterwards.
+    SelfTest,
+
+    /// Copies a local directory tree to the location, or the objects under
+    /// the location to a local directory, keeping relative paths
+    Mirror {
+        #[command(subcommand)]
+        direction: MirrorDirection,
+    },
+
+    /// Runs the reads of an analytical query over the objects under the
+    /// location: list them, read each footer, prune groups, then scan the
+    /// projected columns of the groups left, timing each stage
+    QuerySim {
+        /// Maximum number of requests in flight in each stage
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Comma-separated list of each column's page size
+        #[arg(long, default_value = "65536,65536,65536", value_parser = number_list)]
+        page_sizes: String,
+        /// Comma-separated indices of the columns the query reads. Default:
+        /// every column
+        #[arg(long, default_value = None, value_parser = number_list)]
+        columns: Option<String>,
+        /// Bytes at the end of each object read as its footer
+        #[arg(long, default_value = "65536")]
+        footer_size: usize,
+        /// Fraction of groups left after pruning
+        #[arg(long, default_value = "1.0")]
+        selectivity: f64,
+        /// Seed for choosing which groups are pruned
+        #[arg(long, default_value = "0")]
+        seed: u64,
+        /// Read pages of an object in one request when at most this many
+        /// bytes separate them
+        #[arg(long, default_value = "0")]
+        coalesce_gap: usize,
+        /// Simulate decoding scanned bytes at this many MB/s, holding each
+        /// read's slot until its bytes are decoded
+        #[arg(long, default_value = None)]
+        decode_mbps: Option<f64>,
+    },
+
+    /// Reads keys from a stale copy while revalidating them alongside, on
+    /// scratch objects
+    Swr {
+        /// Number of keys to write and read
+        #[arg(long, default_value = "100")]
+        num_keys: usize,
+        /// Size of each key's object
+        #[arg(long, default_value = "1048576")]
+        object_size: usize,
+        /// Bytes read from the start of a key on each access
+        #[arg(long, default_value = "65536")]
+        read_size: usize,
+        /// Number of accesses
+        #[arg(long, default_value = "1000")]
+        accesses: usize,
+        /// Number of accesses in flight
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel: usize,
+        /// Fraction of keys overwritten after their versions are recorded
+        #[arg(long, default_value = "0.1")]
+        mutate_fraction: f64,
+        /// Seed for choosing the mutated keys and the accesses
+        #[arg(long, default_value = "0")]
+        seed: u64,
     },
 }
 
-#[tokio::main]
-async fn main() {
-    let args: Args = Args::parse();
+#[derive(Subcommand, Clone)]
+enum MirrorDirection {
+    /// Uploads every file under DIR, keyed by its path relative to DIR
+    Push {
+        /// Local directory to upload
+        dir: std::path::PathBuf,
+        /// Number of files to upload in parallel
+        #[arg(long, default_value = "8", value_parser = concurrency)]
+        parallel_uploads: usize,
+        /// Files of at least this many bytes are uploaded as multipart uploads
+        #[arg(long, default_value = "10485760")]
+        multipart_threshold: usize,
+        #[command(flatten)]
+        include: MirrorInclude,
+    },
+    /// Downloads every object under the location into DIR
+    Pull {
+        /// Local directory to download into
+        dir: std::path::PathBuf,
+        /// Number of objects to download in parallel
+        #[arg(short, long, default_value = "8", value_parser = concurrency)]
+        parallel_downloads: usize,
+        #[command(flatten)]
+        include: MirrorInclude,
+    },
+}
 
-    let (object_store, location) = parse_url(&url::Url::parse(&args.object_uri).unwrap()).unwrap();
-    let object_store: Arc<_> = object_store.into();
+/// Path filters shared by both mirror directions
+#[derive(clap::Args, Clone)]
+struct MirrorInclude {
+    /// Only copy relative paths matching this glob. `*` and `?` stay within a
+    /// directory, `**` spans directories, and a pattern without `/` matches
+    /// file names. May be repeated
+    #[arg(long)]
+    include: Vec<mirror::Glob>,
+}
 
-    let _maybe_guard = if args.traced {
-        let builder = ChromeLayerBuilder::new().trace_style(TraceStyle::Async);
-        let (chrome_layer, guard) = builder.build();
-        tracing_subscriber::registry().with(chrome_layer).init();
-        Some(guard)
-    } else {
-        None
-    };
+/// Parameters of the columnar benchmark
+#[derive(clap::Args, Clone)]
+struct ColumnarArgs {
+    /// Number of batches to read in parallel. The default is tuned for the store
+    #[arg(short, long, default_value = "10", value_parser = concurrency)]
+    parallel_downloads: usize,
+    /// Comma-separated list of page sizes to use
+    #[arg(long, default_value = "65536,65536,65536", value_parser = number_list)]
+    page_sizes: Option<String>,
+    /// After the run, calibrate the store's latency and bandwidth and
+    /// report the predicted optimal page size and coalescing gap
+    #[arg(long, default_value = "false")]
+    analyze: bool,
+    /// Align the start of every page to a multiple of this many bytes
+    #[arg(long, default_value = "1")]
+    page_align: usize,
+    /// Leave this many unused bytes after every page
+    #[arg(long, default_value = "0")]
+    inter_page_gap: usize,
+    /// Write the page layout, with true page offsets, to this JSON file
+    #[arg(long, default_value = None)]
+    manifest_out: Option<std::path::PathBuf>,
+    /// Comma-separated indices of the columns to read, laid out among all
+    /// of them. Default: every column
+    #[arg(long, default_value = None, value_parser = number_list)]
+    columns: Option<String>,
+    /// Comma-separated column indices whose pages are issued first within
+    /// each group, in this order; other columns follow in natural order
+    #[arg(long, default_value = None, value_parser = number_list)]
+    column_priority: Option<String>,
+    /// Infer page sizes from the first object's footer: its column chunks if
+    /// it is a Parquet file, otherwise sizes picked from the object's size
+    /// for as many columns as --page-sizes lists
+    #[arg(long, default_value = "false")]
+    infer_layout: bool,
+    /// Read a group's pages at most this many bytes apart in one request,
+    /// so 0 merges adjacent pages. Default: one request per page
+    #[arg(long, default_value = None)]
+    coalesce_gap: Option<usize>,
+    /// Read each group's pages of an object with one get_ranges call instead
+    /// of a get_range per page
+    #[arg(long, default_value = "false")]
+    ranges_api: bool,
+    /// Treat the last N bytes of each object as its footer, laying pages out
+    /// before it, and read every footer before the pages
+    #[arg(long, default_value = None)]
+    footer_size: Option<usize>,
+    #[command(flatten)]
+    repeat: IterationArgs,
+    #[command(flatten)]
+    pacing: PacingArgs,
+}
+
+/// Repetition of a benchmark's timed section
+#[derive(clap::Args, Clone)]
+struct IterationArgs {
+    /// Run the benchmark this many times, listing the objects once, and
+    /// report the mean, standard deviation, minimum and maximum MB/s
+    #[arg(long, default_value = None, value_parser = clap::value_parser!(u64).range(1..))]
+    iterations: Option<u64>,
+    /// Iterations to run first, reported as warmups and left out of the
+    /// summary, to warm connections and caches
+    #[arg(long, default_value = "0", requires = "iterations")]
+    warmup: u64,
+}
+
+/// Dropping requests that run too long
+#[derive(clap::Args, Clone)]
+struct DeadlineArgs {
+    /// Drop any request, retries included, still running after this many
+    /// milliseconds, counting it as expired rather than failed
+    #[arg(long, default_value = None, value_parser = clap::value_parser!(u64).range(1..))]
+    request_deadline_ms: Option<u64>,
+    /// What to do with an expired request: give up on its bytes, or send it
+    /// once more under the same deadline
+    #[arg(
+        long,
+        value_enum,
+        default_value = "skip",
+        requires = "request_deadline_ms"
+    )]
+    on_expire: deadline::OnExpire,
+}
+
+impl DeadlineArgs {
+    fn spec(&self) -> Option<deadline::DeadlineSpec> {
+        self.request_deadline_ms.map(|ms| deadline::DeadlineSpec {
+            deadline: std::time::Duration::from_millis(ms),
+            on_expire: self.on_expire,
+        })
+    }
+}
+
+/// Waiting between dependent requests, as a reader decoding its data would
+#[derive(clap::Args, Clone)]
+struct PacingArgs {
+    /// After each block or group completes, wait this many milliseconds
+    /// before the slot issues its next request; `normal:MEAN,STD` draws each
+    /// wait from a normal distribution
+    #[arg(long, default_value = None, value_parser = think_time::ThinkTime::parse)]
+    think_time_ms: Option<think_time::ThinkTime>,
+    /// Seed for drawing think times
+    #[arg(long, default_value = "0", requires = "think_time_ms")]
+    think_time_seed: u64,
+    /// Run once without the think time and once with it, and compare the
+    /// two throughputs
+    #[arg(long, default_value = "false", requires = "think_time_ms")]
+    compare_paced: bool,
+}
+
+impl PacingArgs {
+    fn spec(&self) -> Option<think_time::Pacing> {
+        self.think_time_ms.map(|think_time| think_time::Pacing {
+            think_time,
+            seed: self.think_time_seed,
+        })
+    }
+
+    /// Run `run` with the think time, or compare it with and without.
+    async fn drive<F, Fut>(&self, control: &control::RunControl, mut run: F) -> Result<(), Error>
+    where
+        F: FnMut(Option<think_time::Pacing>) -> Fut,
+        Fut: std::future::Future<Output = Result<(), Error>>,
+    {
+        match (self.spec(), self.compare_paced) {
+            (Some(pacing), true) => Ok(think_time::compare(pacing, control, run).await?),
+            (pacing, _) => run(pacing).await,
+        }
+    }
+}
+
+/// Workloads the calibrate command can measure
+#[derive(Subcommand, Clone)]
+enum CalibrationWorkload {
+    /// The download benchmark
+    Download {
+        /// Maximum number of requests in flight. The default is tuned for the
+        /// store; 10 is used for stores without tuned defaults
+        #[arg(short, long, default_value = "10", value_parser = concurrency)]
+        parallel_downloads: usize,
+        /// Size of each ranged request. The default is tuned for the store, or
+        /// splits each object evenly across the parallel downloads, in blocks
+        /// of at least 64 KiB
+        #[arg(short, long, default_value = None)]
+        block_size: Option<usize>,
+    },
+    /// The columnar benchmark
+    Columnar(ColumnarArgs),
+}
+
+/// Content digest options shared by the upload commands
+#[derive(clap::Args, Clone)]
+struct DigestArgs {
+    /// Compute a digest of every uploaded object
+    #[arg(long, value_enum, default_value = None)]
+    digest: Option<digest::DigestAlgorithm>,
+    /// Append each object's path, digest, size and timestamp to this JSONL file
+    #[arg(long, default_value = None)]
+    digest_out: Option<std::path::PathBuf>,
+    /// Hash on the blocking thread pool, overlapping with the upload
+    #[arg(long, default_value = "false")]
+    digest_blocking: bool,
+}
+
+/// What the upload commands fill objects with
+#[derive(clap::Args, Clone)]
+struct PatternArgs {
+    /// What objects hold: random bytes, zeros, printable text, or each 8-byte
+    /// word its own offset, for download --verify to check
+    #[arg(long, value_enum, default_value = "random")]
+    pattern: pattern::Pattern,
+    /// Bits each byte of --pattern text carries, from 1 to 6
+    #[arg(long, default_value = "4")]
+    text_entropy_bits: u8,
+}
+
+impl PatternArgs {
+    fn contents(&self, seed: Option<u64>) -> Result<pattern::Contents, Error> {
+        pattern::Contents::new(self.pattern, seed, self.text_entropy_bits)
+            .map_err(|err| Error::Invalid(err.to_string()))
+    }
+}
+
+/// How the random-read workloads pick objects and offsets
+#[derive(clap::Args, Clone)]
+struct SelectionArgs {
+    /// Strategy for picking which object to read next
+    #[arg(long, value_enum, default_value = "uniform")]
+    selection: selection::SelectionKind,
+    /// Exponent of `--selection zipf`; larger is more skewed
+    #[arg(long, default_value = "1.0")]
+    zipf_exponent: f64,
+    /// Age at which `--selection recency` halves an object's weight
+    #[arg(long, default_value = "3600")]
+    recency_half_life_secs: f64,
+    /// `path weight` lines for `--selection weights`
+    #[arg(long, required_if_eq("selection", "weights"))]
+    weights_file: Option<std::path::PathBuf>,
+    /// Seed for the selection, so a run can be repeated. Random by default
+    #[arg(long)]
+    selection_seed: Option<u64>,
+}
+
+impl SelectionArgs {
+    fn spec(self) -> Result<selection::SelectionSpec, String> {
+        let strategy = match self.selection {
+            selection::SelectionKind::Uniform => selection::Strategy::Uniform,
+            selection::SelectionKind::Zipf => selection::Strategy::Zipf {
+                exponent: self.zipf_exponent,
+            },
+            selection::SelectionKind::RoundRobin => selection::Strategy::RoundRobin,
+            selection::SelectionKind::Recency => selection::Strategy::Recency {
+                half_life_secs: self.recency_half_life_secs,
+            },
+            selection::SelectionKind::Weights => selection::Strategy::Weights {
+                path: self
+                    .weights_file
+                    .ok_or("--selection weights needs --weights-file")?,
+            },
+        };
+        Ok(selection::SelectionSpec {
+            strategy,
+            seed: self.selection_seed.unwrap_or_else(rand::random),
+        })
+    }
+}
+
+/// Throughput sampling options shared by the upload commands
+#[derive(clap::Args, Clone)]
+struct UploadWindowArgs {
+    /// Report throughput over consecutive windows of this many seconds, to
+    /// surface slowdowns partway through the upload
+    #[arg(long, default_value = "10")]
+    window_secs: f64,
+    /// Flag windows whose throughput is below this fraction of the median
+    /// window's
+    #[arg(long, default_value = "0.5")]
+    slow_window_fraction: f64,
+}
+
+impl ColumnarArgs {
+    fn options(self) -> Result<columnar::ColumnarOptions, String> {
+        let page_sizes = numbers(self.page_sizes.as_deref().ok_or("--page-sizes is needed")?)?;
+        let indices = |list: Option<String>| {
+            list.as_deref()
+                .map(numbers)
+                .transpose()
+                .map(Option::unwrap_or_default)
+        };
+        let columns = indices(self.columns)?;
+        let column_priority = indices(self.column_priority)?;
+        Ok(columnar::ColumnarOptions {
+            parallel_downloads: self.parallel_downloads,
+            page_sizes,
+            analyze: self.analyze,
+            page_align: self.page_align,
+            inter_page_gap: self.inter_page_gap,
+            manifest_out: self.manifest_out,
+            columns,
+            column_priority,
+            infer_layout: self.infer_layout,
+            coalesce_gap: self.coalesce_gap,
+            ranges_api: self.ranges_api,
+            footer_size: self.footer_size,
+            pacing: self.pacing.spec(),
+        })
+    }
+}
+
+impl DigestArgs {
+    fn config(&self) -> std::io::Result<Option<digest::DigestConfig>> {
+        match self.digest {
+            Some(digest::DigestAlgorithm::Sha256) => Ok(Some(digest::DigestConfig::new(
+                self.digest_blocking,
+                self.digest_out.as_deref(),
+            )?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl UploadWindowArgs {
+    /// The result of an upload of `num_objects` objects.
+    fn result_json(
+        &self,
+        num_objects: usize,
+        part_size: usize,
+        contents: pattern::Contents,
+        samples: &upload::UploadSamples,
+        digest: Option<&digest::DigestConfig>,
+        retry: &RetryPolicy,
+    ) -> serde_json::Value {
+        let mut result = experiment::fields(serde_json::json!({
+            "mode": "upload",
+            "num_objects": num_objects,
+            "part_size": part_size,
+        }));
+        result.extend(contents.json_fields());
+        result.extend(samples.json_fields(
+            std::time::Duration::from_secs_f64(self.window_secs),
+            self.slow_window_fraction,
+        ));
+        result.extend(retry.json_fields());
+        result.extend(
+            digest
+                .into_iter()
+                .flat_map(digest::DigestConfig::json_fields),
+        );
+        result.into()
+    }
+}
+
+impl Commands {
+    fn name(&self) -> &'static str {
+        match self {
+            Commands::UploadData { .. } => "upload-data",
+            Commands::UploadMultiple { .. } => "upload-multiple",
+            Commands::Download { .. } => "download",
+            Commands::Columnar(_) => "columnar",
+            Commands::List { .. } => "list",
+            Commands::Fairness { .. } => "fairness",
+            Commands::RandomRead { .. } => "random-read",
+            Commands::Ttfb { .. } => "ttfb",
+            Commands::Scrub { .. } => "scrub",
+            Commands::Head { .. } => "head",
+            Commands::Cleanup { .. } => "cleanup",
+            Commands::Calibrate { .. } => "calibrate",
+            Commands::Report => "report",
+            Commands::Merge { .. } => "merge",
+            Commands::Migrate { .. } => "migrate",
+            Commands::Replay { .. } => "replay",
+            Commands::SelfTest => "self-test",
+            Commands::Mirror { .. } => "mirror",
+            Commands::QuerySim { .. } => "query-sim",
+            Commands::Swr { .. } => "swr",
+        }
+    }
+
+    /// Request concurrency, for commands that have one.
+    fn parallel_downloads(&self) -> Option<usize> {
+        match self {
+            Commands::Download {
+                parallel_downloads, ..
+            } => Some(parallel_downloads.first()),
+            Commands::Columnar(ColumnarArgs {
+                parallel_downloads, ..
+            })
+            | Commands::Scrub {
+                parallel_downloads, ..
+            } => Some(*parallel_downloads),
+            Commands::List { parallel, .. }
+            | Commands::Fairness { parallel, .. }
+            | Commands::RandomRead { parallel, .. }
+            | Commands::Ttfb { parallel, .. }
+            | Commands::Head { parallel }
+            | Commands::Cleanup { parallel, .. }
+            | Commands::QuerySim { parallel, .. }
+            | Commands::Swr { parallel, .. } => Some(*parallel),
+            Commands::Replay {
+                parallel_downloads, ..
+            } => *parallel_downloads,
+            Commands::Mirror {
+                direction:
+                    MirrorDirection::Push {
+                        parallel_uploads, ..
+                    },
+            } => Some(*parallel_uploads),
+            Commands::Mirror {
+                direction:
+                    MirrorDirection::Pull {
+                        parallel_downloads, ..
+                    },
+            } => Some(*parallel_downloads),
+            _ => None,
+        }
+    }
+
+    /// The settings a `download` given lists of concurrency values or block
+    /// sizes runs at, and the cooldown between them.
+    fn download_sweep(&self) -> Option<(Vec<sweep::Point>, f64)> {
+        match self {
+            Commands::Download {
+                parallel_downloads,
+                block_size,
+                cooldown_secs,
+                ..
+            } if parallel_downloads.is_sweep()
+                || block_size.as_ref().is_some_and(sweep::Values::is_sweep) =>
+            {
+                let points = sweep::points(parallel_downloads, block_size.as_ref());
+                Some((points, *cooldown_secs))
+            }
+            _ => None,
+        }
+    }
+
+    /// This `download` at one setting of its sweep.
+    fn at(mut self, parallel: usize, size: Option<usize>) -> Self {
+        if let Commands::Download {
+            parallel_downloads,
+            block_size,
+            ..
+        } = &mut self
+        {
+            *parallel_downloads = sweep::Values::one(parallel);
+            *block_size = size.map(sweep::Values::one);
+        }
+        self
+    }
+
+    /// How many times to repeat, for commands that take `--iterations`.
+    fn iterations(&self) -> Option<&IterationArgs> {
+        match self {
+            Commands::Download { repeat, .. }
+            | Commands::Columnar(ColumnarArgs { repeat, .. })
+            | Commands::List { repeat, .. } => repeat.iterations.is_some().then_some(repeat),
+            _ => None,
+        }
+    }
+
+    /// The result field `--iterations` summarizes.
+    fn iteration_metric(&self) -> &'static str {
+        match self {
+            Commands::List { .. } => "objects_per_sec",
+            _ => "mbps",
+        }
+    }
 
-    match args.command {
-        Some(Commands::UploadData { size }) => {
-            upload_test_data(object_store, &location, size)
-                .await
-                .unwrap();
+    /// Multipart write size, for the upload commands.
+    fn part_size(&self) -> Option<usize> {
+        match self {
+            Commands::UploadData { part_size, .. } | Commands::UploadMultiple { part_size, .. } => {
+                Some(*part_size)
+            }
+            _ => None,
         }
-        Some(Commands::UploadMultiple {
+    }
+}
+
+/// Parse a number of requests in flight, which has to be at least one.
+fn concurrency(arg: &str) -> Result<usize, String> {
+    match arg.trim().parse::<usize>() {
+        Ok(0) => Err("must be at least 1".to_string()),
+        Ok(value) => Ok(value),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// [`concurrency`], for a value or a list of values to sweep over.
+fn concurrency_list(arg: &str) -> Result<sweep::Values<usize>, String> {
+    let values: sweep::Values<usize> = arg.parse()?;
+    match values.all().contains(&0) {
+        true => Err("every value must be at least 1".to_string()),
+        false => Ok(values),
+    }
+}
+
+/// Check a comma-separated list of whole numbers, naming the entry that isn't one.
+fn number_list(list: &str) -> Result<String, String> {
+    numbers(list)?;
+    Ok(list.to_string())
+}
+
+/// The whole numbers of a comma-separated list.
+fn numbers(list: &str) -> Result<Vec<usize>, String> {
+    list.split(',')
+        .map(|entry| {
+            entry
+                .trim()
+                .parse::<usize>()
+                .map_err(|err| format!("{:?} is not a whole number: {}", entry.trim(), err))
+        })
+        .collect()
+}
+
+/// The value of `result`, or exit with `error: {context}: {err}` rather than
+/// panicking.
+fn or_exit<T, E: std::fmt::Display>(result: Result<T, E>, context: &str) -> T {
+    result.unwrap_or_else(|err| {
+        eprintln!("error: {}: {}", context, err);
+        control::restore_terminal();
+        std::process::exit(1);
+    })
+}
+
+/// Print a typed benchmark result, as [`control::RunControl::emit`] prints the rest.
+fn emit_serialized<T: serde::Serialize>(
+    control: &control::RunControl,
+    result: &T,
+) -> Result<(), Error> {
+    control.emit(serde_json::to_value(result).map_err(|err| Error::Other(err.to_string()))?);
+    Ok(())
+}
+
+/// Print a benchmark's result, then fail if a check made on it did.
+fn emit_checked<T: serde::Serialize>(
+    control: &control::RunControl,
+    checked: Checked<T>,
+) -> Result<T, Error> {
+    emit_serialized(control, &checked.result)?;
+    checked.into_result()
+}
+
+/// Exit on a store that can't be built for `uri`.
+fn unusable(uri: &str, err: object_store::Error) -> ! {
+    eprintln!("error: can't open object URI '{}': {}", uri, err);
+    std::process::exit(2);
+}
+
+/// Whether the argument `id` was left to its default on the command line.
+fn is_default(matches: &ArgMatches, id: &str) -> bool {
+    matches!(
+        matches.value_source(id),
+        None | Some(ValueSource::DefaultValue)
+    )
+}
+
+impl Commands {
+    /// Replace defaults the user didn't override with the tuned ones for the store.
+    /// Values from the user's defaults file or environment are kept.
+    fn apply_store_defaults(
+        &mut self,
+        matches: &ArgMatches,
+        defaults: &store_defaults::StoreDefaults,
+        layered: &user_defaults::Layered,
+    ) {
+        let Some((name, matches)) = matches.subcommand() else {
+            return;
+        };
+        let mut path = vec![name];
+        let tuned = |path: &[&str], matches: &ArgMatches, id: &str| {
+            is_default(matches, id) && !user_defaults::is_layered(layered, path, id)
+        };
+        if let Commands::Download {
+            parallel_downloads,
+            block_size,
+            ..
+        } = self
+        {
+            if tuned(&path, matches, "parallel_downloads") {
+                *parallel_downloads = sweep::Values::one(defaults.parallel_downloads);
+            }
+            if tuned(&path, matches, "block_size") {
+                *block_size = defaults.block_size.map(sweep::Values::one);
+            }
+            return;
+        }
+        let (parallel_downloads, block_size, matches) = match self {
+            Commands::Scrub {
+                parallel_downloads, ..
+            }
+            | Commands::Columnar(ColumnarArgs {
+                parallel_downloads, ..
+            }) => (Some(parallel_downloads), None, matches),
+            Commands::Calibrate { workload, .. } => {
+                let Some((name, matches)) = matches.subcommand() else {
+                    return;
+                };
+                path.push(name);
+                match workload {
+                    CalibrationWorkload::Download {
+                        parallel_downloads,
+                        block_size,
+                    } => (Some(parallel_downloads), Some(block_size), matches),
+                    CalibrationWorkload::Columnar(ColumnarArgs {
+                        parallel_downloads, ..
+                    }) => (Some(parallel_downloads), None, matches),
+                }
+            }
+            _ => (None, None, matches),
+        };
+        if let Some(parallel_downloads) = parallel_downloads {
+            if tuned(&path, matches, "parallel_downloads") {
+                *parallel_downloads = defaults.parallel_downloads;
+            }
+        }
+        if let Some(block_size) = block_size {
+            if tuned(&path, matches, "block_size") {
+                *block_size = defaults.block_size;
+            }
+        }
+    }
+}
+
+/// Run one benchmark command to completion.
+async fn run_command(
+    command: Commands,
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    retry: RetryPolicy,
+    control: control::RunControl,
+    keep_scratch: bool,
+) -> Result<(), Error> {
+    match command {
+        Commands::UploadData {
+            size,
+            part_size,
+            digest,
+            windows,
+            bench,
+            seed,
+            pattern,
+        } => {
+            let contents = pattern.contents(seed)?;
+            if bench {
+                let bench =
+                    upload::bench_upload(object_store.as_ref(), &location, size, contents, &retry)
+                        .await?;
+                control.emit(bench.to_json(&retry));
+                return Ok(());
+            }
+            let digest = digest.config()?;
+            let samples = upload::UploadSamples::start().reporting_to(&control, size);
+            upload::upload_test_data(
+                object_store,
+                &location,
+                size,
+                part_size,
+                contents,
+                &retry,
+                digest.as_ref(),
+                &samples,
+            )
+            .await?;
+            control.emit(windows.result_json(
+                1,
+                part_size,
+                contents,
+                &samples,
+                digest.as_ref(),
+                &retry,
+            ));
+        }
+        Commands::UploadMultiple {
             num_objects,
             size,
+            part_size,
             random_prefixes,
-        }) => {
-            upload_multiple(object_store, &location, num_objects, size, random_prefixes)
-                .await
-                .unwrap();
+            prefix_seed,
+            seed,
+            pattern,
+            flat_names,
+            manifest_out,
+            digest,
+            windows,
+        } => {
+            let contents = pattern.contents(seed)?;
+            let digest = digest.config()?;
+            let samples = upload::UploadSamples::start().reporting_to(&control, size);
+            let run_id = (!flat_names).then(|| {
+                control
+                    .listing()
+                    .run_id
+                    .clone()
+                    .unwrap_or_else(naming::generate_run_id)
+            });
+            if let Some(run_id) = &run_id {
+                eprintln!("uploading run {}", run_id);
+            }
+            let manifest = upload::upload_multiple(
+                object_store,
+                &location,
+                num_objects,
+                size,
+                part_size,
+                random_prefixes,
+                prefix_seed,
+                contents,
+                run_id.as_deref(),
+                manifest_out.as_deref(),
+                &retry,
+                digest.as_ref(),
+                &samples,
+            )
+            .await?;
+            eprintln!(
+                "uploaded {} objects under {}",
+                manifest.objects.len(),
+                manifest.root
+            );
+            control.emit(windows.result_json(
+                manifest.objects.len(),
+                part_size,
+                contents,
+                &samples,
+                digest.as_ref(),
+                &retry,
+            ));
         }
-        Some(Commands::Download {
-            parallel_downloads,
+        Commands::List {
+            shard_by_prefix,
+            parallel,
+            delimiter,
+            compare,
+            repeat: _,
+        } => {
+            list::list_bench(
+                object_store,
+                location,
+                list::ListOptions {
+                    shard_depth: shard_by_prefix,
+                    parallel,
+                    delimiter,
+                    compare,
+                },
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Fairness {
+            read_size,
+            target_qps,
             block_size,
-        }) => {
-            download::parallel_download_bench(
+            parallel,
+            duration_secs,
+            selection,
+            deadline,
+        } => {
+            fairness::fairness_bench(
+                object_store,
+                location,
+                fairness::FairnessOptions {
+                    read_size,
+                    target_qps,
+                    block_size,
+                    parallel,
+                    duration: std::time::Duration::from_secs_f64(duration_secs),
+                    selection: selection.spec()?,
+                    deadline: deadline.spec(),
+                },
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::RandomRead {
+            num_requests,
+            request_size,
+            parallel,
+            seed,
+            duration,
+            workload_recipe,
+            print_workload,
+            selection,
+            deadline,
+        } => {
+            let mut options = match workload_recipe {
+                Some(recipe) => recipe.options(parallel),
+                None => {
+                    let mut selection = selection.spec()?;
+                    if let Some(seed) = seed {
+                        selection.seed = seed;
+                    }
+                    random_read::RandomReadOptions {
+                        num_requests,
+                        request_size,
+                        parallel,
+                        selection,
+                        duration,
+                        deadline: None,
+                        recipe: None,
+                    }
+                }
+            };
+            options.deadline = deadline.spec();
+            match print_workload {
+                Some(count) => {
+                    random_read::print_workload(
+                        object_store,
+                        location,
+                        options,
+                        count,
+                        retry,
+                        control,
+                    )
+                    .await?
+                }
+                None => {
+                    random_read::random_read_bench(object_store, location, options, retry, control)
+                        .await?
+                }
+            }
+        }
+        Commands::Ttfb {
+            parallel,
+            range_size,
+        } => {
+            ttfb::ttfb_bench(object_store, location, parallel, range_size, retry, control).await?;
+        }
+        Commands::Scrub {
+            parallel_downloads,
+            digests,
+            manifest,
+        } => {
+            scrub::scrub(
                 object_store,
                 location,
                 parallel_downloads,
-                block_size,
+                digests,
+                manifest,
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Head { parallel } => {
+            head::head_bench(object_store, location, parallel, retry, control).await?;
+        }
+        Commands::Cleanup { parallel, manifest } => {
+            cleanup::cleanup(object_store, location, parallel, manifest, retry, control).await?;
+        }
+        Commands::Download {
+            parallel_downloads,
+            block_size,
+            ranges_file: Some(ranges_file),
+            ..
+        } => {
+            ranges_file::ranges_file_bench(
+                object_store,
+                location,
+                &ranges_file,
+                parallel_downloads.first(),
+                block_size.as_ref().map(sweep::Values::first),
+                retry,
+                control,
             )
-            .await
-            .unwrap();
+            .await?;
         }
-        Some(Commands::Columnar {
+        Commands::Download {
             parallel_downloads,
+            block_size,
+            huge_object: Some(object_size),
+            ..
+        } => {
+            download::check_huge_object(
+                object_size,
+                block_size.as_ref().map(sweep::Values::first),
+                parallel_downloads.first(),
+                &control,
+            )?;
+        }
+        Commands::Download {
+            parallel_downloads,
+            block_size,
+            reassemble: true,
+            max_buffered_bytes,
+            ..
+        } => {
+            reassembly::reassembly_bench(
+                object_store,
+                location,
+                parallel_downloads.first(),
+                block_size.as_ref().map(sweep::Values::first),
+                max_buffered_bytes,
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Download {
+            parallel_downloads,
+            compare_get_apis: true,
+            ..
+        } => {
+            get_apis::compare_get_apis(
+                object_store,
+                location,
+                parallel_downloads.first(),
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Download {
+            parallel_downloads,
+            suffix_bytes: Some(suffix_bytes),
+            ..
+        } => {
+            tail::suffix_read_bench(
+                object_store,
+                location,
+                suffix_bytes,
+                parallel_downloads.first(),
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Download {
+            parallel_downloads,
+            block_size,
+            consume_mbps,
+            suffix_bytes: None,
+            window_secs,
+            timeline,
+            compare_get_apis: false,
+            reassemble: false,
+            max_buffered_bytes: _,
+            huge_object: None,
+            ranges_file: None,
+            verify,
+            read_mode,
+            duration,
+            cooldown_secs: _,
+            repeat: _,
+            deadline,
+            pacing,
+        } => {
+            pacing
+                .drive(&control, |pacing| {
+                    download::parallel_download_bench(
+                        object_store.clone(),
+                        location.clone(),
+                        download::DownloadOptions {
+                            parallel_downloads: parallel_downloads.first(),
+                            block_size: block_size.as_ref().map(sweep::Values::first),
+                            read_mode,
+                            duration,
+                            consume_mbps,
+                            deadline: deadline.spec(),
+                            pacing,
+                            verify,
+                            window: std::time::Duration::from_secs_f64(window_secs),
+                            timeline,
+                        },
+                        retry.clone(),
+                        control.clone(),
+                    )
+                    .map(|result| {
+                        result
+                            .and_then(|checked| emit_checked(&control, checked))
+                            .map(drop)
+                    })
+                })
+                .await?;
+        }
+        Commands::Columnar(columnar_args) => {
+            let pacing = columnar_args.pacing.clone();
+            let options = columnar_args.options()?;
+            pacing
+                .drive(&control, |pacing| {
+                    columnar::columnar_read_test(
+                        object_store.clone(),
+                        location.clone(),
+                        columnar::ColumnarOptions {
+                            pacing,
+                            ..options.clone()
+                        },
+                        retry.clone(),
+                        control.clone(),
+                    )
+                    .map(|result| {
+                        result
+                            .and_then(|checked| emit_checked(&control, checked))
+                            .map(drop)
+                    })
+                })
+                .await?;
+        }
+        Commands::Calibrate { out, workload } => {
+            let workload = match workload {
+                CalibrationWorkload::Download {
+                    parallel_downloads,
+                    block_size,
+                } => calibration::Workload::Download {
+                    parallel_downloads,
+                    block_size,
+                },
+                CalibrationWorkload::Columnar(columnar_args) => {
+                    calibration::Workload::Columnar(columnar_args.options()?)
+                }
+            };
+            calibration::calibrate(
+                object_store,
+                location,
+                workload,
+                out.as_deref(),
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::Replay {
+            plan,
+            parallel_downloads,
+        } => {
+            plan::replay_plan(
+                object_store,
+                location,
+                &plan,
+                parallel_downloads,
+                retry,
+                control,
+            )
+            .await?;
+        }
+        Commands::SelfTest => {
+            selftest::self_test(object_store, location, retry, control, keep_scratch).await?;
+        }
+        Commands::Mirror { direction } => match direction {
+            MirrorDirection::Push {
+                dir,
+                parallel_uploads,
+                multipart_threshold,
+                include,
+            } => {
+                let options = mirror::MirrorOptions {
+                    parallel: parallel_uploads,
+                    include: include.include,
+                    multipart_threshold,
+                };
+                mirror::push(object_store, location, &dir, options, retry, control).await?;
+            }
+            MirrorDirection::Pull {
+                dir,
+                parallel_downloads,
+                include,
+            } => {
+                let options = mirror::MirrorOptions {
+                    parallel: parallel_downloads,
+                    include: include.include,
+                    multipart_threshold: usize::MAX,
+                };
+                mirror::pull(object_store, location, &dir, options, retry, control).await?;
+            }
+        },
+        Commands::QuerySim {
+            parallel,
             page_sizes,
-        }) => {
-            let page_sizes = page_sizes
-                .unwrap()
-                .split(',')
-                .map(|s| s.parse().unwrap())
-                .collect();
-            columnar::columnar_read_test(object_store, location, parallel_downloads, page_sizes)
-                .await
-                .unwrap();
-        }
-        None => {
-            println!("No command specified");
+            columns,
+            footer_size,
+            selectivity,
+            seed,
+            coalesce_gap,
+            decode_mbps,
+        } => {
+            let options = query_sim::QuerySimOptions {
+                parallel,
+                page_sizes: numbers(&page_sizes)?,
+                columns: columns
+                    .as_deref()
+                    .map(numbers)
+                    .transpose()?
+                    .unwrap_or_default(),
+                footer_size,
+                selectivity,
+                seed,
+                coalesce_gap,
+                decode_mbps,
+            };
+            query_sim::query_sim(object_store, location, options, retry, control).await?;
+        }
+        Commands::Swr {
+            num_keys,
+            object_size,
+            read_size,
+            accesses,
+            parallel,
+            mutate_fraction,
+            seed,
+        } => {
+            let options = swr::SwrOptions {
+                num_keys,
+                object_size,
+                read_size,
+                accesses,
+                parallel,
+                mutate_fraction,
+                seed,
+            };
+            let result = swr::swr_bench(
+                object_store,
+                location,
+                options,
+                retry,
+                control.clone(),
+                keep_scratch,
+            )
+            .await?;
+            emit_serialized(&control, &result)?;
+        }
+        Commands::Report | Commands::Merge { .. } | Commands::Migrate { .. } => {
+            unreachable!("handled before the store is created")
+        }
+    }
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() {
+    let (command, layered) =
+        user_defaults::layer_user_defaults(Args::command()).unwrap_or_else(|err| {
+            eprintln!("error: {}", err);
+            std::process::exit(2);
+        });
+    let matches = command.clone().get_matches();
+    let config = user_defaults::record(&command, &matches, &layered);
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    if args.command.is_none() {
+        eprintln!("{}", command.clone().render_help());
+        std::process::exit(2);
+    }
+
+    if let Some(Commands::Report) = args.command {
+        or_exit(
+            report::report(std::path::Path::new(&args.object_uri)),
+            "report",
+        );
+        return;
+    }
+    if let Some(Commands::Merge { files, run_id }) = &args.command {
+        let mut paths = vec![std::path::PathBuf::from(&args.object_uri)];
+        paths.extend(files.iter().cloned());
+        or_exit(
+            merge::merge(&paths, run_id.as_deref().or(args.run_id.as_deref())),
+            "merge",
+        );
+        return;
+    }
+    if let Some(Commands::Migrate { out }) = &args.command {
+        or_exit(
+            report::migrate_file(std::path::Path::new(&args.object_uri), out.as_deref()),
+            "migrate",
+        );
+        return;
+    }
+    let mut context = RunContext::default();
+    context.listing = ListingOptions {
+        run_id: args.run_id.clone(),
+        filters: args.filters.clone(),
+        worker: args.worker_id,
+        ..ListingOptions::default()
+    };
+    context.output = args.output.clone();
+    context.config = config;
+    context.strict_accounting = args.strict_accounting;
+    context.sparkline = args.sparkline;
+    if let Some(path) = &args.price_model {
+        context.price_model = Some(or_exit(cost::PriceModel::load(path), "--price-model"));
+    }
+    if let Some(path) = &args.calibration {
+        let calibration = or_exit(calibration::load(path), "--calibration");
+        calibration.check_workload(args.command.as_ref().map_or("none", Commands::name));
+        context.calibration = Some(calibration);
+    }
+    if args.run_id.is_some() || args.worker_id.is_some() {
+        context.worker = Some(worker::RunInfo::new(args.run_id.clone(), args.worker_id));
+    }
+    if let Some(max_objects) = args.max_objects {
+        context.listing.sampling = Some(Arc::new(sampling::Sampling::new(
+            max_objects,
+            args.sample,
+            args.sample_seed,
+        )));
+    }
+    context.experiment = args.experiment_dir.as_ref().map(|root| {
+        let command = args.command.as_ref().map_or("none", Commands::name);
+        or_exit(
+            experiment::Experiment::init(root, command),
+            "--experiment-dir",
+        )
+    });
+
+    let url = url::Url::parse(&args.object_uri).unwrap_or_else(|err| {
+        eprintln!("error: invalid object URI '{}': {}", args.object_uri, err);
+        std::process::exit(2);
+    });
+    let family = store_defaults::StoreFamily::detect(&url);
+    context.store = Some(family);
+    let mut options = match &args.store_config {
+        Some(path) => store_options::load(path).unwrap_or_else(|err| {
+            eprintln!("error: --store-config {}", err);
+            std::process::exit(2);
+        }),
+        None => Vec::new(),
+    };
+    // Later keys win in the builder, so the flags go last.
+    options.extend(args.store_options.iter().cloned());
+    if let Err(err) = store_options::check(&url, &options) {
+        eprintln!("error: --store-option: {}", err);
+        std::process::exit(2);
+    }
+    context.wire = wire::WireModel::for_store(&url, family, &args.wire_overhead);
+    if let Some(command) = &mut args.command {
+        command.apply_store_defaults(&matches, &family.defaults(), &layered);
+        if let Some(part_size) = command.part_size() {
+            family.check_part_size(part_size).unwrap_or_else(|err| {
+                eprintln!("error: {}", err);
+                std::process::exit(2);
+            });
+        }
+        if let Commands::List {
+            shard_by_prefix: None,
+            delimiter: false,
+            compare: true,
+            ..
+        } = command
+        {
+            eprintln!("error: --compare needs --shard-by-prefix or --delimiter");
+            std::process::exit(2);
+        }
+        let repeated = args.until_stable.is_some()
+            || args.min_runtime_secs.is_some()
+            || args.repeat_every_secs.is_some();
+        if args.pool_sweep.is_some()
+            && (!matches!(command, Commands::Download { .. }) || command.iterations().is_some())
+        {
+            eprintln!("error: --pool-sweep only runs download, without --iterations");
+            std::process::exit(2);
+        }
+        if command.iterations().is_some() && repeated {
+            eprintln!(
+                "error: --iterations can't be combined with --until-stable, --min-runtime-secs or --repeat-every-secs"
+            );
+            std::process::exit(2);
+        }
+        if let Commands::Calibrate {
+            workload: CalibrationWorkload::Columnar(ColumnarArgs { repeat, pacing, .. }),
+            ..
+        } = command
+        {
+            if repeat.iterations.is_some() {
+                eprintln!("error: calibrate doesn't take --iterations");
+                std::process::exit(2);
+            }
+            if pacing.compare_paced {
+                eprintln!("error: calibrate doesn't take --compare-paced");
+                std::process::exit(2);
+            }
+        }
+        let compare_paced = match command {
+            Commands::Download { pacing, .. } | Commands::Columnar(ColumnarArgs { pacing, .. }) => {
+                pacing.compare_paced
+            }
+            _ => false,
+        };
+        if compare_paced && args.pool_sweep.is_some() {
+            eprintln!("error: --compare-paced can't be combined with --pool-sweep");
+            std::process::exit(2);
+        }
+        let block_download = matches!(
+            command,
+            Commands::Download {
+                suffix_bytes: None,
+                compare_get_apis: false,
+                reassemble: false,
+                huge_object: None,
+                ranges_file: None,
+                ..
+            }
+        );
+        if let Commands::Download {
+            read_mode: download::ReadMode::Sequential,
+            ..
+        } = command
+        {
+            if !block_download {
+                eprintln!("error: --mode sequential only applies to a block download");
+                std::process::exit(2);
+            }
+        }
+        if let Commands::Download {
+            duration: Some(_), ..
+        } = command
+        {
+            if !block_download {
+                eprintln!("error: --duration only applies to a block download");
+                std::process::exit(2);
+            }
+        }
+        if command.download_sweep().is_some()
+            && (!block_download
+                || command.iterations().is_some()
+                || repeated
+                || args.pool_sweep.is_some()
+                || compare_paced)
+        {
+            eprintln!(
+                "error: lists of --parallel-downloads or --block-size only sweep a block download, without --iterations, --pool-sweep, --compare-paced or repeated runs"
+            );
+            std::process::exit(2);
+        }
+    }
+    let mut client = pool::ClientConfig {
+        pool_max_idle_per_host: args.pool_max_idle_per_host,
+        http2: args.http2,
+        headers: args.headers.clone(),
+    };
+    if client != pool::ClientConfig::default() && !pool::supported(&url) {
+        eprintln!("warning: ignoring --pool-max-idle-per-host, --http2 and --header: only s3, gs and http(s) stores take them");
+        client = pool::ClientConfig::default();
+    }
+    if args.pool_sweep.is_some() && !pool::supported(&url) {
+        eprintln!("error: --pool-sweep needs an s3, gs or http(s) store");
+        std::process::exit(2);
+    }
+    let (object_store, location) = store_options::parse_url(&url, &options)
+        .unwrap_or_else(|err| unusable(&args.object_uri, err));
+    let mut object_store: Arc<dyn ObjectStore> = if client == pool::ClientConfig::default() {
+        object_store.into()
+    } else {
+        pool::begin(&context, client.clone());
+        pool::build(&url, &client, &options)
+            .unwrap_or_else(|err| unusable(&args.object_uri, err))
+            .into()
+    };
+    if !client.headers.is_empty() {
+        let retry = RetryPolicy::new(args.max_retries, None);
+        if let Err(err) = pool::preflight(object_store.as_ref(), &location, &retry).await {
+            eprintln!(
+                "error: the store rejected a request sent with --header: {}",
+                err
+            );
+            std::process::exit(2);
+        }
+    }
+    let pool_sweep = args.pool_sweep.clone().map(|sizes| {
+        let sweep_url = url.clone();
+        let sweep_options = options.clone();
+        let slots = sizes.clone();
+        let (http2, headers) = (args.http2, args.headers.clone());
+        let make = move |slot: usize| {
+            let config = pool::ClientConfig {
+                pool_max_idle_per_host: Some(slots[slot]),
+                http2,
+                headers: headers.clone(),
+            };
+            pool::build(&sweep_url, &config, &sweep_options).map(Into::into)
+        };
+        let store = or_exit(
+            schedule::FreshClientStore::new(Box::new(make), sizes.len()),
+            "--pool-sweep",
+        );
+        let store = Arc::new(store);
+        object_store = store.clone();
+        (sizes, store)
+    });
+    let fresh_client = match (args.fresh_client_each_run, args.repeat_count) {
+        (true, Some(runs)) => {
+            let client_url = url.clone();
+            let client = client.clone();
+            let options = options.clone();
+            let make = move |_| pool::build(&client_url, &client, &options).map(Into::into);
+            let store = or_exit(
+                schedule::FreshClientStore::new(Box::new(make), runs),
+                "--fresh-client-each-run",
+            );
+            let store = Arc::new(store);
+            object_store = store.clone();
+            Some(store)
+        }
+        _ => None,
+    };
+    let encodings = match (args.accept_encoding, url.scheme(), family) {
+        (None, _, _) => vec![None],
+        (Some(encoding), "http" | "https", store_defaults::StoreFamily::Other) => {
+            let store = or_exit(
+                content_encoding::EncodingStore::http(&url),
+                "--accept-encoding",
+            );
+            context.encoding = Some(store.status());
+            object_store = Arc::new(store);
+            encoding.settings().into_iter().map(Some).collect()
+        }
+        (Some(_), _, _) => {
+            eprintln!("warning: ignoring --accept-encoding: only plain http(s) stores take it");
+            vec![None]
+        }
+    };
+    match (args.direct_io, family) {
+        (false, _) => {}
+        (true, store_defaults::StoreFamily::Local) => {
+            let store = direct_io::DirectIoStore::new(object_store);
+            context.direct_io = Some(store.status());
+            object_store = Arc::new(store);
+        }
+        (true, family) => {
+            context.direct_io = Some(direct_io::unsupported(&format!(
+                "{} stores have no page cache to bypass",
+                family.name()
+            )))
+        }
+    }
+    let simulation = simulate::Simulation {
+        latency: args.simulate_latency,
+        bandwidth_mbps: args.simulate_bandwidth,
+    };
+    if simulation.is_active() {
+        eprintln!(
+            "warning: simulating {}; results describe the simulation, not the store",
+            simulation.to_json()
+        );
+        context.simulation = Some(simulation);
+        object_store = Arc::new(simulate::SimulatedStore::new(object_store, simulation));
+    }
+    if let Some(buckets) = args.coverage {
+        let tracker = Arc::new(coverage::Coverage::new(
+            buckets as usize,
+            args.coverage_out.clone(),
+        ));
+        context.coverage = Some(tracker.clone());
+        object_store = Arc::new(coverage::CoverageStore::new(object_store, tracker));
+    }
+    let store = counting::CountingStore::new(object_store);
+    let request_counts = store.counts();
+    context.counts = Some(request_counts.clone());
+    let mut object_store: Arc<dyn ObjectStore> = Arc::new(store);
+    let faults: Vec<_> = args
+        .faults
+        .iter()
+        .chain(&args.inject_failures)
+        .cloned()
+        .collect();
+    let fault_counts = (!faults.is_empty()).then(|| {
+        let store = fault::FaultStore::new(
+            object_store.clone(),
+            fault::FaultConfig::new(&faults),
+            args.fault_seed,
+        );
+        let counts = store.counts();
+        object_store = Arc::new(store);
+        counts
+    });
+    if let Some(prefix_bytes) = args.partition_prefix_bytes {
+        let partitions = Arc::new(partitions::Partitions::new(prefix_bytes as usize));
+        context.partitions = Some(partitions.clone());
+        object_store = Arc::new(partitions::PartitionStore::new(object_store, partitions));
+    }
+    if let Some(path) = args.listing_cache.clone() {
+        let cache = Arc::new(listing_cache::ListingCache::new(
+            path,
+            std::time::Duration::from_secs(args.listing_cache_ttl),
+            &args.object_uri,
+            &location,
+        ));
+        object_store = Arc::new(listing_cache::StaleCheckStore::new(
+            object_store,
+            cache.clone(),
+        ));
+        context.listing.cache = Some(cache);
+    }
+    let request_trace = args.request_trace.as_ref().map(|path| {
+        Arc::new(or_exit(
+            request_trace::RequestTrace::create(path, args.trace_format),
+            "--request-trace",
+        ))
+    });
+    if let Some(trace) = &request_trace {
+        object_store = Arc::new(request_trace::RequestTraceStore::new(
+            object_store,
+            trace.clone(),
+        ));
+    }
+    if args.traced || args.verbose > 0 {
+        object_store = Arc::new(trace::TracingStore::new(object_store));
+    }
+    if args.stats {
+        let store = instrumented::InstrumentedStore::new(object_store);
+        context.ops = Some(store.stats());
+        object_store = Arc::new(store);
+    }
+    if args.connection_breakdown {
+        let breakdown = connection::probe(&url, object_store.as_ref(), &location).await;
+        if let Some(error) = &breakdown.error {
+            eprintln!("warning: connection probe stopped early: {}", error);
+        }
+        context.connection = Some(breakdown);
+    }
+    let backoff = args
+        .adaptive_backoff
+        .map(|config| Arc::new(backoff::AdaptiveBackoff::new(config)));
+    if let Err(err) = size_buckets::check_edges(&args.size_buckets) {
+        eprintln!("error: {}", err);
+        std::process::exit(2);
+    }
+    context.size_buckets = Some(args.size_buckets.clone());
+    let retry = RetryPolicy::new(args.max_retries, args.retry_budget)
+        .with_retry_delay(std::time::Duration::from_millis(args.retry_delay_ms))
+        .with_backoff(backoff)
+        .with_missing_objects(args.missing_objects)
+        .with_continue_on_error(args.continue_on_error);
+    let control = control::RunControl::with_context(context);
+    if let Some(plan_path) = &args.record_plan {
+        let parallel_downloads = args.command.as_ref().and_then(Commands::parallel_downloads);
+        let recorder = or_exit(
+            plan::PlanRecorder::create(plan_path, location.clone(), parallel_downloads),
+            "--record-plan",
+        );
+        control.set_plan_recorder(recorder);
+    }
+    control::spawn_interrupt_handler(
+        control.clone(),
+        std::time::Duration::from_secs_f64(args.interrupt_timeout_secs),
+    );
+    let _terminal_guard = if args.interactive {
+        control::spawn_key_listener(control.clone())
+    } else {
+        None
+    };
+
+    let (chrome_layer, _maybe_guard) = if args.traced {
+        let mut builder = ChromeLayerBuilder::new().trace_style(TraceStyle::Async);
+        if let Some(experiment) = &control.context().experiment {
+            builder = builder.file(experiment.trace_path());
+        }
+        let (chrome_layer, guard) = builder.build();
+        (Some(chrome_layer), Some(guard))
+    } else {
+        (None, None)
+    };
+    if args.traced || args.verbose > 0 {
+        tracing_subscriber::registry()
+            .with(chrome_layer)
+            .with(trace::layer(args.verbose))
+            .init();
+    }
+
+    let progress = match args.progress_format {
+        Some(_) => Some(progress::ProgressReporter::spawn(
+            control.clone(),
+            std::time::Duration::from_millis(args.progress_interval_ms),
+            progress::progress_writer(args.progress_fd),
+        )),
+        None if args.progress != Some(false) => {
+            progress::ProgressReporter::bar(control.clone(), std::time::Duration::from_millis(200))
+        }
+        None => None,
+    };
+
+    // A failed run ends the process, whichever driver is repeating it.
+    let run = |command: Commands| {
+        let context = command.name();
+        let request_trace = request_trace.clone();
+        run_command(
+            command,
+            object_store.clone(),
+            location.clone(),
+            retry.clone(),
+            control.clone(),
+            args.keep_scratch,
+        )
+        .map(move |result| {
+            // The process is about to exit, so close the trace while it can.
+            if let (Err(_), Some(trace)) = (&result, &request_trace) {
+                let _ = trace.finish();
+            }
+            or_exit(result, context)
+        })
+    };
+    for encoding in encodings {
+        if let (Some(encoding), Some(status)) = (encoding, &control.context().encoding) {
+            status.begin(encoding);
+        }
+        match (
+            args.command.clone(),
+            args.until_stable,
+            args.min_runtime_secs,
+        ) {
+            (Some(command), None, None) if args.repeat_every_secs.is_some() => {
+                schedule::on_schedule(
+                    std::time::Duration::from_secs_f64(args.repeat_every_secs.unwrap()),
+                    args.repeat_count.unwrap(),
+                    fresh_client.is_some(),
+                    &control,
+                    |index| {
+                        if let Some(store) = &fresh_client {
+                            or_exit(store.renew(index), "--fresh-client-each-run");
+                        }
+                        run(command.clone())
+                    },
+                )
+                .await;
+            }
+            (Some(command), Some(criterion), _) => {
+                let stable =
+                    iterate::until_stable(criterion, &control, || run(command.clone())).await;
+                or_exit(stable, "--until-stable");
+            }
+            (Some(command), None, Some(min_runtime_secs)) => {
+                iterate::for_at_least(
+                    std::time::Duration::from_secs_f64(min_runtime_secs),
+                    &control,
+                    &retry,
+                    || run(command.clone()),
+                )
+                .await;
+            }
+            (Some(command), None, None) if pool_sweep.is_some() => {
+                let (sizes, store) = pool_sweep.as_ref().unwrap();
+                let swept = pool::sweep(sizes, args.http2, &args.headers, store, &control, || {
+                    run(command.clone())
+                })
+                .await;
+                or_exit(swept, "--pool-sweep");
+            }
+            (Some(command), None, None) if command.download_sweep().is_some() => {
+                let (points, cooldown_secs) = command.download_sweep().unwrap();
+                let swept = sweep::download_sweep(
+                    &location,
+                    &points,
+                    std::time::Duration::from_secs_f64(cooldown_secs),
+                    &control,
+                    |parallel_downloads, block_size| {
+                        run(command.clone().at(parallel_downloads, block_size))
+                    },
+                )
+                .await;
+                or_exit(swept, "sweep");
+            }
+            (Some(command), None, None) if command.iterations().is_some() => {
+                let repeat = command.iterations().unwrap().clone();
+                let repeated = iterate::repeat(
+                    repeat.iterations.unwrap() as usize,
+                    repeat.warmup as usize,
+                    command.iteration_metric(),
+                    &control,
+                    || run(command.clone()),
+                )
+                .await;
+                or_exit(repeated, "--iterations");
+            }
+            (Some(command), None, None) => {
+                run(command).await;
+            }
+            (None, _, _) => unreachable!("checked before the store is created"),
+        }
+        if let Some(status) = &control.context().encoding {
+            status.check_ignored();
+        }
+    }
+
+    if let Some(counts) = fault_counts {
+        eprintln!("injected faults: {}", counts.to_json());
+    }
+    let totals = request_counts.totals();
+    eprintln!("requests sent: {}", totals.to_json());
+    if let Some(cost) = control
+        .context()
+        .price_model
+        .as_ref()
+        .map(|model| model.estimate(&totals))
+    {
+        eprintln!("estimated cost: {:.4} {}", cost.total(), cost.currency);
+    }
+    or_exit(control.flush_plan(), "--record-plan");
+    if let Some(trace) = &request_trace {
+        or_exit(trace.finish(), "--request-trace");
+    }
+    if let Some(progress) = progress {
+        progress.finish().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn self_test_covers_every_subcommand() {
+        for command in Args::command().get_subcommands() {
+            let name = command.get_name();
+            assert!(
+                selftest::STAGES.contains(&name) || selftest::UNSTAGED.contains(&name),
+                "self-test has no stage for {}",
+                name
+            );
         }
     }
 }
@@ -0,0 +1,143 @@
+//! Upload manifests: the complete list of objects one upload created.
+//!
+//! `upload-multiple --manifest-out` writes the run id, the root prefix the
+//! upload was pointed at, the seed of any random prefixes and the full path
+//! of every object. `scrub --manifest` and `cleanup --manifest` then work
+//! from that list without listing the store, so they touch exactly the
+//! objects the upload created.
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+use crate::error::Error;
+use crate::retry::RetryPolicy;
+
+/// What `upload-multiple` records about the objects it created.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadManifest {
+    pub run_id: Option<String>,
+    /// Location the upload was pointed at; every object is under it
+    pub root: Path,
+    /// Seed the random prefixes were drawn from, if they were seeded
+    pub prefix_seed: Option<u64>,
+    /// Seed the object contents were drawn from, object `i` from `seed + i`
+    pub seed: Option<u64>,
+    pub objects: Vec<Path>,
+}
+
+impl UploadManifest {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "run_id": self.run_id,
+            "root": self.root.as_ref(),
+            "prefix_seed": self.prefix_seed,
+            "seed": self.seed,
+            "objects": self.objects.iter().map(Path::as_ref).collect::<Vec<_>>(),
+        })
+    }
+
+    pub fn write(&self, path: &std::path::Path) -> std::io::Result<()> {
+        std::fs::write(path, format!("{}\n", self.to_json()))
+    }
+
+    /// Read a manifest written by [`UploadManifest::write`]. Manifests from
+    /// before the root was recorded are accepted, rooted at the store root.
+    pub fn load(path: &std::path::Path) -> Result<Self, Error> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let manifest: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|err| format!("{}: {}", path.display(), err))?;
+        let Some(objects) = manifest.get("objects").and_then(|v| v.as_array()) else {
+            return Err(format!("{}: expected a list of objects", path.display()).into());
+        };
+        let objects = objects
+            .iter()
+            .map(|object| match object.as_str() {
+                Some(object) => Path::parse(object).map_err(|err| err.to_string()),
+                None => Err(format!("{}: object names must be strings", path.display())),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let root = match manifest.get("root").and_then(|v| v.as_str()) {
+            Some(root) => Path::parse(root)?,
+            None => Path::default(),
+        };
+        Ok(Self {
+            run_id: manifest
+                .get("run_id")
+                .and_then(|v| v.as_str())
+                .map(str::to_string),
+            root,
+            prefix_seed: manifest.get("prefix_seed").and_then(|v| v.as_u64()),
+            seed: manifest.get("seed").and_then(|v| v.as_u64()),
+            objects,
+        })
+    }
+
+    /// The manifest's objects, refusing any outside `location` so a
+    /// manifest for one prefix can't be applied to another by mistake.
+    pub fn objects_under(&self, location: &Path) -> Result<&[Path], String> {
+        match self
+            .objects
+            .iter()
+            .find(|object| !object.prefix_matches(location))
+        {
+            Some(object) => Err(format!(
+                "manifest object {} is not under {}",
+                object, location
+            )),
+            None => Ok(&self.objects),
+        }
+    }
+
+    /// Metadata for every object in the manifest, found with one head
+    /// request each instead of a listing.
+    pub async fn inspect(
+        &self,
+        object_store: &dyn ObjectStore,
+        location: &Path,
+        parallel: usize,
+        retry: &RetryPolicy,
+    ) -> Result<Vec<ObjectMeta>, Error> {
+        let objects = futures::stream::iter(self.objects_under(location)?)
+            .map(|object| retry.run(move || object_store.head(object)))
+            .buffered(parallel)
+            .try_collect()
+            .await?;
+        Ok(objects)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(objects: &[&str]) -> UploadManifest {
+        UploadManifest {
+            run_id: Some("seed1".to_string()),
+            root: Path::from("data"),
+            prefix_seed: Some(7),
+            seed: Some(3),
+            objects: objects.iter().map(|o| Path::from(*o)).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let manifest = manifest(&["data/abc/object_seed1_0.bin", "data/def/object_seed1_1.bin"]);
+        let path = std::env::temp_dir().join(format!(
+            "object-store-bench-manifest-{}.json",
+            std::process::id()
+        ));
+        manifest.write(&path).unwrap();
+        let loaded = UploadManifest::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, manifest);
+    }
+
+    #[test]
+    fn refuses_objects_outside_the_location() {
+        let manifest = manifest(&["data/object_seed1_0.bin", "database/object_seed1_1.bin"]);
+        assert!(manifest.objects_under(&Path::from("data")).is_err());
+        assert_eq!(manifest.objects_under(&Path::default()).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,358 @@
+//! Combining the results of workers that shared one logical run.
+//!
+//! Records are matched by the `run_id` in their `metadata`. Counters are summed,
+//! the elapsed time becomes the wall-clock span from the earliest worker start
+//! to the latest finish, and latency histograms are merged bucket by bucket.
+//! Any other field is a run parameter, and a warning names each one whose
+//! value differs between workers.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde_json::Value;
+
+use crate::error::Error;
+use crate::report::load_records;
+use crate::stats::Histogram;
+
+/// Fields added up across workers.
+const SUMMED: &[&str] = &[
+    "num_objects",
+    "zero_byte_objects",
+    "num_requests",
+    "successful_requests",
+    "failed_requests",
+    "bytes",
+    "bytes_received",
+    "retries",
+    "retry_budget_used",
+    "stream_errors",
+    "stream_timeouts",
+    "auth_errors",
+];
+
+/// Per-worker measurements that are recomputed rather than compared.
+const MEASURED: &[&str] = &[
+    "metadata",
+    "elapsed_us",
+    "paused_us",
+    "mbps",
+    "interrupted",
+    "latency",
+    "latency_histogram",
+    "windows",
+    "window_summary",
+    "phases",
+    "steady_mbps",
+    "small_objects",
+    "reorder_buffer",
+    "consumer_stall_us",
+    "issue_blocked_us",
+    "error_kinds",
+    "request_counts",
+    "estimated_cost",
+    "auth_error_timeline",
+    "listing_cache",
+    "direct_io",
+    "retry_budget_exhausted_us",
+];
+
+/// Print one combined record for the run in `paths`.
+///
+/// * `run_id`: the run to merge; required when the files hold several runs
+pub fn merge(paths: &[std::path::PathBuf], run_id: Option<&str>) -> Result<(), Error> {
+    let mut records = Vec::new();
+    for path in paths {
+        for record in load_records(path)? {
+            records.push((path.display().to_string(), record));
+        }
+    }
+    let record_run_id = |record: &Value| {
+        record
+            .pointer("/metadata/run_id")
+            .and_then(|v| v.as_str())
+            .map(str::to_string)
+    };
+
+    let run_id = match run_id {
+        Some(run_id) => run_id.to_string(),
+        None => {
+            let run_ids = records
+                .iter()
+                .filter_map(|(_, record)| record_run_id(record))
+                .collect::<BTreeSet<_>>();
+            match run_ids.len() {
+                0 => return Err("no records carry a run id; run workers with --run-id".into()),
+                1 => run_ids.into_iter().next().unwrap(),
+                _ => {
+                    return Err(format!(
+                        "records come from several runs, pick one with --run-id: {}",
+                        run_ids.into_iter().collect::<Vec<_>>().join(", ")
+                    )
+                    .into())
+                }
+            }
+        }
+    };
+    let records = records
+        .into_iter()
+        .filter(|(_, record)| record_run_id(record).as_deref() == Some(run_id.as_str()))
+        .map(|(source, record)| {
+            let worker = record
+                .pointer("/metadata/worker_id")
+                .and_then(|v| v.as_str())
+                .map_or(source, str::to_string);
+            (worker, record)
+        })
+        .collect::<Vec<_>>();
+    if records.is_empty() {
+        return Err(format!("no records found for run {}", run_id).into());
+    }
+
+    for warning in worker_mismatches(&records)
+        .into_iter()
+        .chain(parameter_mismatches(&records))
+    {
+        eprintln!("warning: {}", warning);
+    }
+
+    println!("{}", combine(&run_id, &records)?);
+    Ok(())
+}
+
+/// One message per duplicated worker, and one naming any absent shards.
+fn worker_mismatches(records: &[(String, Value)]) -> Vec<String> {
+    let mut seen = BTreeMap::new();
+    let mut count = None;
+    for (worker, _) in records {
+        *seen.entry(worker.clone()).or_insert(0) += 1;
+        if let Ok(id) = worker.parse::<crate::worker::WorkerId>() {
+            count = Some(id.count);
+        }
+    }
+    let mut warnings = seen
+        .iter()
+        .filter(|(_, times)| **times > 1)
+        .map(|(worker, times)| format!("worker {} has {} records", worker, times))
+        .collect::<Vec<_>>();
+    if let Some(count) = count {
+        let missing = (0..count)
+            .map(|index| format!("{}/{}", index, count))
+            .filter(|worker| !seen.contains_key(worker))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            warnings.push(format!("no results from workers {}", missing.join(", ")));
+        }
+    }
+    warnings
+}
+
+/// One message per run parameter whose value differs between workers.
+fn parameter_mismatches(records: &[(String, Value)]) -> Vec<String> {
+    let fields = records
+        .iter()
+        .filter_map(|(_, record)| record.as_object())
+        .flat_map(|fields| fields.keys().cloned())
+        .filter(|field| !SUMMED.contains(&field.as_str()) && !MEASURED.contains(&field.as_str()))
+        .collect::<BTreeSet<_>>();
+    fields
+        .into_iter()
+        .filter_map(|field| {
+            let values = records
+                .iter()
+                .map(|(worker, record)| (worker, record.get(&field).unwrap_or(&Value::Null)))
+                .collect::<Vec<_>>();
+            if values.iter().all(|(_, value)| *value == values[0].1) {
+                return None;
+            }
+            let values = values
+                .iter()
+                .map(|(worker, value)| format!("{}={}", worker, value))
+                .collect::<Vec<_>>()
+                .join(", ");
+            Some(format!("{} differs between workers: {}", field, values))
+        })
+        .collect()
+}
+
+fn combine(run_id: &str, records: &[(String, Value)]) -> Result<Value, Error> {
+    let mut combined = records[0].1.as_object().cloned().unwrap_or_default();
+
+    for field in SUMMED {
+        let values = records
+            .iter()
+            .filter_map(|(_, record)| record.get(*field).and_then(|v| v.as_u64()))
+            .collect::<Vec<_>>();
+        if !values.is_empty() {
+            combined.insert(field.to_string(), values.iter().sum::<u64>().into());
+        }
+    }
+
+    let timestamp = |record: &Value, field: &str| -> Result<DateTime<Utc>, String> {
+        let value = record
+            .pointer(&format!("/metadata/{}", field))
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| format!("record is missing metadata.{}", field))?;
+        DateTime::parse_from_rfc3339(value)
+            .map(|t| t.with_timezone(&Utc))
+            .map_err(|err| format!("invalid metadata.{} {:?}: {}", field, value, err))
+    };
+    let mut started_at = None::<DateTime<Utc>>;
+    let mut finished_at = None::<DateTime<Utc>>;
+    for (_, record) in records {
+        let start = timestamp(record, "started_at")?;
+        let finish = timestamp(record, "finished_at")?;
+        started_at = Some(started_at.map_or(start, |t| t.min(start)));
+        finished_at = Some(finished_at.map_or(finish, |t| t.max(finish)));
+    }
+    let (started_at, finished_at) = (started_at.unwrap(), finished_at.unwrap());
+    let elapsed_us = (finished_at - started_at).num_microseconds().unwrap_or(0);
+    combined.insert("elapsed_us".to_string(), elapsed_us.into());
+    combined.remove("paused_us");
+    combined.remove("retry_budget_exhausted_us");
+    // Each worker's windows, phases and auth timeline start at its own start time,
+    // and small-object latencies have no histogram to merge.
+    combined.remove("windows");
+    combined.remove("window_summary");
+    combined.remove("phases");
+    combined.remove("steady_mbps");
+    combined.remove("small_objects");
+    // Each worker reassembles its own objects, so buffers don't combine.
+    combined.remove("reorder_buffer");
+    combined.remove("consumer_stall_us");
+    combined.remove("issue_blocked_us");
+    combined.remove("auth_error_timeline");
+
+    for field in ["error_kinds", "request_counts"] {
+        if let Some(counts) = sum_counts(records, field) {
+            combined.insert(field.to_string(), counts);
+        }
+    }
+    // Tiered egress pricing applies to the run's total, so workers' costs
+    // can't be added up.
+    combined.remove("estimated_cost");
+
+    let mbps = combined
+        .get("bytes")
+        .and_then(|v| v.as_u64())
+        .map(|bytes| bytes as f64 / 1024.0 / 1024.0 / (elapsed_us as f64 / 1_000_000.0));
+    combined.insert("mbps".to_string(), mbps.into());
+
+    let interrupted = records
+        .iter()
+        .any(|(_, record)| record.get("interrupted").and_then(|v| v.as_bool()) == Some(true));
+    combined.insert("interrupted".to_string(), interrupted.into());
+
+    let histograms = records
+        .iter()
+        .filter_map(|(_, record)| record.get("latency_histogram"))
+        .map(Histogram::from_json)
+        .collect::<Result<Vec<_>, _>>()?;
+    if !histograms.is_empty() {
+        let mut merged = Histogram::default();
+        for histogram in &histograms {
+            merged.merge(histogram);
+        }
+        combined.insert("latency".to_string(), merged.summary().to_json());
+        combined.insert("latency_histogram".to_string(), merged.to_json());
+    }
+
+    combined.insert(
+        "metadata".to_string(),
+        serde_json::json!({
+            "run_id": run_id,
+            "workers": records.iter().map(|(worker, _)| worker).collect::<Vec<_>>(),
+            "started_at": started_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            "finished_at": finished_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        }),
+    );
+    Ok(Value::Object(combined))
+}
+
+/// Add up the counters in the object `field` of each record.
+fn sum_counts(records: &[(String, Value)], field: &str) -> Option<Value> {
+    let mut totals = serde_json::Map::new();
+    for (_, record) in records {
+        for (key, count) in record
+            .get(field)
+            .and_then(|v| v.as_object())
+            .into_iter()
+            .flatten()
+        {
+            let total = totals.get(key).and_then(|v| v.as_u64()).unwrap_or(0);
+            totals.insert(key.clone(), (total + count.as_u64().unwrap_or(0)).into());
+        }
+    }
+    (!totals.is_empty()).then_some(Value::Object(totals))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record(worker: &str, block_size: u64, bytes: u64) -> (String, Value) {
+        let started_at = "2024-01-01T00:00:00Z";
+        let finished_at = match worker {
+            "0/2" => "2024-01-01T00:00:01Z",
+            _ => "2024-01-01T00:00:02Z",
+        };
+        let record = json!({
+            "mode": "download",
+            "block_size": block_size,
+            "num_requests": 4,
+            "successful_requests": 3,
+            "failed_requests": 1,
+            "bytes": bytes,
+            "elapsed_us": 1_000_000,
+            "mbps": 1.0,
+            "metadata": {
+                "run_id": "run",
+                "worker_id": worker,
+                "started_at": started_at,
+                "finished_at": finished_at,
+            },
+        });
+        (worker.to_string(), record)
+    }
+
+    #[test]
+    fn differing_parameters_are_named_with_each_workers_value() {
+        let records = vec![record("0/2", 1024, 10), record("1/2", 2048, 20)];
+        assert_eq!(
+            parameter_mismatches(&records),
+            vec!["block_size differs between workers: 0/2=1024, 1/2=2048"]
+        );
+        // Counters and measurements differ between workers by design.
+        let records = vec![record("0/2", 1024, 10), record("1/2", 1024, 20)];
+        assert!(parameter_mismatches(&records).is_empty());
+    }
+
+    #[test]
+    fn duplicate_and_absent_workers_are_reported() {
+        let records = vec![
+            record("0/3", 1024, 10),
+            record("0/3", 1024, 10),
+            record("2/3", 1024, 10),
+        ];
+        assert_eq!(
+            worker_mismatches(&records),
+            vec!["worker 0/3 has 2 records", "no results from workers 1/3"]
+        );
+        let records = vec![record("0/2", 1024, 10), record("1/2", 1024, 20)];
+        assert!(worker_mismatches(&records).is_empty());
+    }
+
+    #[test]
+    fn counters_are_summed_over_the_runs_span() {
+        let records = vec![record("0/2", 1024, 1 << 20), record("1/2", 1024, 1 << 20)];
+        let combined = combine("run", &records).unwrap();
+        assert_eq!(combined["num_requests"], 8);
+        assert_eq!(combined["successful_requests"], 6);
+        assert_eq!(combined["failed_requests"], 2);
+        assert_eq!(combined["bytes"], 2 << 20);
+        assert_eq!(combined["elapsed_us"], 2_000_000);
+        assert_eq!(combined["mbps"], 1.0);
+        assert_eq!(combined["metadata"]["workers"], json!(["0/2", "1/2"]));
+    }
+}
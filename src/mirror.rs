@@ -0,0 +1,679 @@
+//! Copying a local directory tree to the store and back, for migration
+//! rehearsals.
+//!
+//! `mirror push DIR` uploads every regular file under `DIR` to the location,
+//! keyed by its path relative to `DIR`. Files smaller than
+//! `--multipart-threshold` go up in one put, larger ones as a multipart
+//! upload streamed from disk. `mirror pull DIR` lists the location and
+//! streams each object into the file at its relative key under `DIR`,
+//! creating directories as needed. Both walk iteratively, so deep trees don't
+//! exhaust the stack, copy empty files as empty objects and back, and skip
+//! symbolic links.
+//!
+//! `--include` globs narrow either direction to matching relative paths.
+//! Results report files per second and MB/s overall, and split the files, the
+//! bytes and the time spent transferring them by extension and by size
+//! bucket.
+
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::retry::RetryPolicy;
+use crate::upload::multipart_error;
+
+/// Bytes read from disk per multipart write.
+const CHUNK_SIZE: usize = 10 * 1024 * 1024;
+
+/// Upper bounds of the size buckets, and their names. Larger files fall in
+/// the last bucket.
+const SIZE_BUCKETS: &[(u64, &str)] = &[
+    (0, "empty"),
+    (4 * 1024, "<=4KiB"),
+    (64 * 1024, "<=64KiB"),
+    (1024 * 1024, "<=1MiB"),
+    (16 * 1024 * 1024, "<=16MiB"),
+    (256 * 1024 * 1024, "<=256MiB"),
+    (u64::MAX, ">256MiB"),
+];
+
+/// An `--include` pattern. `*` and `?` match within one path segment and
+/// `**` across segments. A pattern without a `/` is matched against the
+/// file name alone, as in `*.parquet`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Glob {
+    pattern: String,
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("empty pattern".to_string());
+        }
+        Ok(Self {
+            pattern: s.trim_start_matches('/').to_string(),
+        })
+    }
+}
+
+impl Glob {
+    /// Whether the relative path `path`, with `/` separators, matches.
+    pub fn matches(&self, path: &str) -> bool {
+        let subject = match self.pattern.contains('/') {
+            true => path,
+            false => path.rsplit('/').next().unwrap_or(path),
+        };
+        glob_match(self.pattern.as_bytes(), subject.as_bytes())
+    }
+}
+
+fn glob_match(pattern: &[u8], subject: &[u8]) -> bool {
+    match pattern {
+        [] => subject.is_empty(),
+        [b'*', b'*', rest @ ..] => match rest.strip_prefix(b"/") {
+            // `**/` matches any number of whole directories, including none.
+            Some(rest) => {
+                glob_match(rest, subject)
+                    || subject
+                        .iter()
+                        .enumerate()
+                        .any(|(i, c)| *c == b'/' && glob_match(rest, &subject[i + 1..]))
+            }
+            None => (0..=subject.len()).any(|i| glob_match(rest, &subject[i..])),
+        },
+        [b'*', rest @ ..] => {
+            let segment = subject
+                .iter()
+                .position(|c| *c == b'/')
+                .unwrap_or(subject.len());
+            (0..=segment).any(|i| glob_match(rest, &subject[i..]))
+        }
+        [b'?', rest @ ..] => match subject {
+            [c, tail @ ..] if *c != b'/' => glob_match(rest, tail),
+            _ => false,
+        },
+        [c, rest @ ..] => match subject {
+            [s, tail @ ..] if s == c => glob_match(rest, tail),
+            _ => false,
+        },
+    }
+}
+
+/// Settings shared by both directions.
+#[derive(Debug, Clone)]
+pub struct MirrorOptions {
+    /// Files in flight at once
+    pub parallel: usize,
+    /// Only copy relative paths matching one of these, or everything if empty
+    pub include: Vec<Glob>,
+    /// Files at least this large are pushed as multipart uploads
+    pub multipart_threshold: usize,
+}
+
+impl MirrorOptions {
+    fn includes(&self, relative: &str) -> bool {
+        self.include.is_empty() || self.include.iter().any(|glob| glob.matches(relative))
+    }
+}
+
+/// One file to copy, by its path relative to the mirrored roots.
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    relative: String,
+    size: u64,
+}
+
+/// One copied file.
+#[derive(Debug, Clone)]
+struct Copied {
+    entry: Entry,
+    multipart: bool,
+    latency: Duration,
+}
+
+/// Files, bytes and time spent in one slice of the breakdown.
+#[derive(Debug, Default, Clone, Copy)]
+struct Slice {
+    files: usize,
+    bytes: u64,
+    busy: Duration,
+}
+
+impl Slice {
+    fn add(&mut self, copied: &Copied) {
+        self.files += 1;
+        self.bytes += copied.entry.size;
+        self.busy += copied.latency;
+    }
+
+    fn to_json(self, total_busy: Duration) -> serde_json::Value {
+        serde_json::json!({
+            "files": self.files,
+            "bytes": self.bytes,
+            "busy_us": self.busy.as_micros() as u64,
+            "mean_latency_us": (self.busy.as_micros() as u64).checked_div(self.files as u64).unwrap_or(0),
+            "busy_share": match total_busy.is_zero() {
+                true => 0.0,
+                false => self.busy.as_secs_f64() / total_busy.as_secs_f64(),
+            },
+        })
+    }
+}
+
+/// Extension of a relative path, lowercased, or `""` for none.
+fn extension(relative: &str) -> String {
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => String::new(),
+    }
+}
+
+fn size_bucket(size: u64) -> usize {
+    SIZE_BUCKETS
+        .iter()
+        .position(|(max, _)| size <= *max)
+        .unwrap()
+}
+
+/// Every regular file under `root` matching `options`, sorted by relative
+/// path. Directories are walked with an explicit stack.
+fn walk(root: &std::path::Path, options: &MirrorOptions) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut stack = vec![std::path::PathBuf::new()];
+    while let Some(dir) = stack.pop() {
+        let listing = std::fs::read_dir(root.join(&dir))
+            .map_err(|err| format!("failed to read {}: {}", root.join(&dir).display(), err))?;
+        for item in listing {
+            let item = item
+                .map_err(|err| format!("failed to read {}: {}", root.join(&dir).display(), err))?;
+            let relative = dir.join(item.file_name());
+            let file_type = item.file_type().map_err(|err| {
+                format!("failed to stat {}: {}", root.join(&relative).display(), err)
+            })?;
+            if file_type.is_dir() {
+                stack.push(relative);
+            } else if file_type.is_file() {
+                let relative = relative_key(&relative)?;
+                if options.includes(&relative) {
+                    let size = item
+                        .metadata()
+                        .map_err(|err| format!("failed to stat {}: {}", relative, err))?
+                        .len();
+                    entries.push(Entry { relative, size });
+                }
+            }
+        }
+    }
+    entries.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok(entries)
+}
+
+/// `relative` with `/` separators, for use in keys and globs.
+fn relative_key(relative: &std::path::Path) -> Result<String, String> {
+    let parts = relative
+        .components()
+        .map(|part| {
+            part.as_os_str()
+                .to_str()
+                .ok_or_else(|| format!("{} is not valid UTF-8", relative.display()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(parts.join("/"))
+}
+
+fn object_key(location: &Path, relative: &str) -> Path {
+    Path::from_iter(location.parts().chain(Path::from(relative).parts()))
+}
+
+/// Upload `entries` from under `dir` to `location`.
+async fn push_files(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    dir: &std::path::Path,
+    entries: &[Entry],
+    options: &MirrorOptions,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Vec<Copied>> {
+    futures::stream::iter(entries)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|entry| {
+            let object_store = object_store.clone();
+            async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                let key = object_key(location, &entry.relative);
+                let file = dir.join(&entry.relative);
+                let multipart = entry.size >= options.multipart_threshold as u64 && entry.size > 0;
+                control.record(if multipart { "put_multipart" } else { "put" }, &key, None);
+                let start = Instant::now();
+                let result = retry
+                    .run(|| push_file(object_store.as_ref(), &key, &file, multipart))
+                    .await;
+                match &result {
+                    Ok(()) => control.request_finished(entry.size as usize),
+                    Err(_) => control.request_failed(),
+                }
+                result.map(|()| {
+                    Some(Copied {
+                        entry: entry.clone(),
+                        multipart,
+                        latency: start.elapsed(),
+                    })
+                })
+            }
+        })
+        .buffer_unordered(options.parallel)
+        .try_filter_map(|copied| futures::future::ready(Ok(copied)))
+        .try_collect()
+        .await
+}
+
+async fn push_file(
+    object_store: &dyn ObjectStore,
+    key: &Path,
+    file: &std::path::Path,
+    multipart: bool,
+) -> object_store::Result<()> {
+    if !multipart {
+        let data = tokio::fs::read(file).await.map_err(local_error)?;
+        return object_store.put(key, Bytes::from(data)).await;
+    }
+    let mut reader = tokio::fs::File::open(file).await.map_err(local_error)?;
+    let (_id, mut writer) = object_store.put_multipart(key).await?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = reader.read(&mut buffer).await.map_err(local_error)?;
+        if read == 0 {
+            break;
+        }
+        writer
+            .write_all(&buffer[..read])
+            .await
+            .map_err(multipart_error)?;
+    }
+    writer.flush().await.map_err(multipart_error)?;
+    writer.shutdown().await.map_err(multipart_error)
+}
+
+/// Download `entries` under `location` into `dir`.
+async fn pull_files(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    dir: &std::path::Path,
+    entries: &[Entry],
+    options: &MirrorOptions,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Vec<Copied>> {
+    futures::stream::iter(entries)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|entry| {
+            let object_store = object_store.clone();
+            async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                let key = object_key(location, &entry.relative);
+                let file = dir.join(&entry.relative);
+                control.record("get", &key, None);
+                let start = Instant::now();
+                let result = retry
+                    .run(|| pull_file(object_store.as_ref(), &key, &file))
+                    .await;
+                match &result {
+                    Ok(bytes) => control.request_finished(*bytes as usize),
+                    Err(_) => control.request_failed(),
+                }
+                result.map(|size| {
+                    Some(Copied {
+                        entry: Entry {
+                            relative: entry.relative.clone(),
+                            size,
+                        },
+                        multipart: false,
+                        latency: start.elapsed(),
+                    })
+                })
+            }
+        })
+        .buffer_unordered(options.parallel)
+        .try_filter_map(|copied| futures::future::ready(Ok(copied)))
+        .try_collect()
+        .await
+}
+
+/// Stream the object at `key` into `file`, returning the bytes written.
+async fn pull_file(
+    object_store: &dyn ObjectStore,
+    key: &Path,
+    file: &std::path::Path,
+) -> object_store::Result<u64> {
+    if let Some(parent) = file.parent() {
+        tokio::fs::create_dir_all(parent)
+            .await
+            .map_err(local_error)?;
+    }
+    let mut stream = object_store.get(key).await?.into_stream();
+    let mut writer = tokio::fs::File::create(file).await.map_err(local_error)?;
+    let mut written = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        writer.write_all(&chunk).await.map_err(local_error)?;
+        written += chunk.len() as u64;
+    }
+    writer.flush().await.map_err(local_error)?;
+    Ok(written)
+}
+
+fn local_error(source: std::io::Error) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "mirror",
+        source: Box::new(source),
+    }
+}
+
+/// The objects under `location` matching `options`, by key relative to it.
+async fn list_entries(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    options: &MirrorOptions,
+    retry: &RetryPolicy,
+) -> Result<Vec<Entry>, Error> {
+    let objects: Vec<object_store::ObjectMeta> = retry
+        .run(|| async { object_store.list(Some(location)).await?.try_collect().await })
+        .await?;
+    let mut entries = Vec::with_capacity(objects.len());
+    for meta in objects {
+        let Some(parts) = meta.location.prefix_match(location) else {
+            continue;
+        };
+        let parts = parts
+            .map(|part| part.as_ref().to_string())
+            .collect::<Vec<_>>();
+        if parts.is_empty() {
+            continue;
+        }
+        if parts.iter().any(|part| part == "." || part == "..") {
+            return Err(format!("refusing to pull {} outside the directory", meta.location).into());
+        }
+        let relative = parts.join("/");
+        if options.includes(&relative) {
+            entries.push(Entry {
+                relative,
+                size: meta.size as u64,
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.relative.cmp(&b.relative));
+    Ok(entries)
+}
+
+/// The result of mirroring `entries`, of which `copied` were transferred.
+#[allow(clippy::too_many_arguments)]
+fn summarize(
+    mode: &str,
+    location: &Path,
+    dir: &std::path::Path,
+    entries: &[Entry],
+    copied: &[Copied],
+    options: &MirrorOptions,
+    elapsed: Duration,
+    control: &RunControl,
+    retry: &RetryPolicy,
+) -> serde_json::Value {
+    let bytes = copied.iter().map(|c| c.entry.size).sum::<u64>();
+    let busy = copied.iter().map(|c| c.latency).sum::<Duration>();
+    let mut by_extension = BTreeMap::<String, Slice>::new();
+    let mut by_size = vec![Slice::default(); SIZE_BUCKETS.len()];
+    for c in copied {
+        by_extension
+            .entry(extension(&c.entry.relative))
+            .or_default()
+            .add(c);
+        by_size[size_bucket(c.entry.size)].add(c);
+    }
+    let secs = elapsed.as_secs_f64().max(1e-9);
+    let mut record = fields(serde_json::json!({
+        "mode": mode,
+        "root": location.to_string(),
+        "local_dir": dir.display().to_string(),
+        "include": options.include.iter().map(|glob| glob.pattern.clone()).collect::<Vec<_>>(),
+        "parallel": options.parallel,
+        "num_files": entries.len(),
+        "files": copied.len(),
+        "empty_files": copied.iter().filter(|c| c.entry.size == 0).count(),
+        "multipart_files": copied.iter().filter(|c| c.multipart).count(),
+        "bytes": bytes,
+        "elapsed_us": elapsed.as_micros() as u64,
+        "files_per_sec": copied.len() as f64 / secs,
+        "mbps": bytes as f64 / 1024.0 / 1024.0 / secs,
+        "by_extension": by_extension
+            .into_iter()
+            .map(|(ext, slice)| (ext, slice.to_json(busy)))
+            .collect::<serde_json::Map<_, _>>(),
+        "by_size": SIZE_BUCKETS
+            .iter()
+            .zip(&by_size)
+            .filter(|(_, slice)| slice.files > 0)
+            .map(|((_, name), slice)| {
+                let mut json = slice.to_json(busy);
+                json["bucket"] = serde_json::json!(name);
+                json
+            })
+            .collect::<Vec<_>>(),
+        "interrupted": control.is_shutdown(),
+    }));
+    record.extend(retry.json_fields());
+    record.into()
+}
+
+/// Upload the tree under `dir` to `location`.
+pub async fn push(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    dir: &std::path::Path,
+    options: MirrorOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let entries = walk(dir, &options)?;
+    control.set_bytes_total(entries.iter().map(|e| e.size).sum());
+    let start = Instant::now();
+    let copied = push_files(
+        object_store,
+        &location,
+        dir,
+        &entries,
+        &options,
+        &retry,
+        &control,
+    )
+    .await?;
+    let elapsed = start.elapsed();
+    control.emit(summarize(
+        "mirror_push",
+        &location,
+        dir,
+        &entries,
+        &copied,
+        &options,
+        elapsed,
+        &control,
+        &retry,
+    ));
+    Ok(())
+}
+
+/// Download every object under `location` into the tree under `dir`.
+pub async fn pull(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    dir: &std::path::Path,
+    options: MirrorOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let entries = list_entries(object_store.as_ref(), &location, &options, &retry).await?;
+    control.set_bytes_total(entries.iter().map(|e| e.size).sum());
+    let start = Instant::now();
+    let copied = pull_files(
+        object_store,
+        &location,
+        dir,
+        &entries,
+        &options,
+        &retry,
+        &control,
+    )
+    .await?;
+    let elapsed = start.elapsed();
+    control.emit(summarize(
+        "mirror_pull",
+        &location,
+        dir,
+        &entries,
+        &copied,
+        &options,
+        elapsed,
+        &control,
+        &retry,
+    ));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::capture;
+    use object_store::memory::InMemory;
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "object-store-bench-mirror-{}-{}",
+            name,
+            crate::naming::generate_run_id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn options(include: &[&str]) -> MirrorOptions {
+        MirrorOptions {
+            parallel: 4,
+            include: include.iter().map(|g| g.parse().unwrap()).collect(),
+            multipart_threshold: 1024,
+        }
+    }
+
+    #[test]
+    fn globs_match_segments_and_names() {
+        let glob = |p: &str| p.parse::<Glob>().unwrap();
+        assert!(glob("*.parquet").matches("a/b/part-0.parquet"));
+        assert!(!glob("*.parquet").matches("a/b/part-0.json"));
+        assert!(glob("a/*/x.bin").matches("a/b/x.bin"));
+        assert!(!glob("a/*/x.bin").matches("a/b/c/x.bin"));
+        assert!(glob("a/**/x.bin").matches("a/b/c/x.bin"));
+        assert!(glob("a/**/x.bin").matches("a/x.bin"));
+        assert!(glob("**/data/*").matches("deep/er/data/f"));
+        assert!(glob("f?le").matches("dir/file"));
+        assert!(!glob("f?le").matches("dir/fle"));
+    }
+
+    #[test]
+    fn buckets_and_extensions() {
+        assert_eq!(SIZE_BUCKETS[size_bucket(0)].1, "empty");
+        assert_eq!(SIZE_BUCKETS[size_bucket(4096)].1, "<=4KiB");
+        assert_eq!(SIZE_BUCKETS[size_bucket(4097)].1, "<=64KiB");
+        assert_eq!(SIZE_BUCKETS[size_bucket(u64::MAX)].1, ">256MiB");
+        assert_eq!(extension("a.b/c.PARQUET"), "parquet");
+        assert_eq!(extension("a.b/Makefile"), "");
+        assert_eq!(extension("dir/.hidden"), "");
+    }
+
+    #[tokio::test]
+    async fn push_then_pull_round_trips_a_deep_tree() {
+        let source = temp_dir("source");
+        let mut deep = source.clone();
+        for _ in 0..200 {
+            deep.push("d");
+        }
+        std::fs::create_dir_all(&deep).unwrap();
+        std::fs::write(deep.join("leaf.txt"), b"deep").unwrap();
+        std::fs::write(source.join("empty.bin"), b"").unwrap();
+        std::fs::write(source.join("big.bin"), vec![3u8; 5000]).unwrap();
+        std::fs::create_dir_all(source.join("skipped")).unwrap();
+        std::fs::write(source.join("skipped").join("notes.md"), b"no").unwrap();
+
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let root = Path::from("mirror");
+        let (outcome, results) = capture(push(
+            store.clone(),
+            root.clone(),
+            &source,
+            options(&["*.bin", "*.txt"]),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let pushed: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(pushed["files"], 3);
+        assert_eq!(pushed["empty_files"], 1);
+        assert_eq!(pushed["multipart_files"], 1);
+        assert_eq!(pushed["bytes"], 5004);
+        assert_eq!(pushed["by_extension"]["bin"]["files"], 2);
+        assert_eq!(pushed["by_size"][0]["bucket"], "empty");
+        let leaf = format!("mirror/{}leaf.txt", "d/".repeat(200));
+        assert_eq!(
+            store
+                .get(&Path::from(leaf))
+                .await
+                .unwrap()
+                .bytes()
+                .await
+                .unwrap(),
+            Bytes::from_static(b"deep")
+        );
+
+        let target = temp_dir("target");
+        let (outcome, results) = capture(pull(
+            store,
+            root,
+            &target,
+            options(&[]),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let pulled: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(pulled["files"], 3);
+        assert_eq!(std::fs::read(target.join("empty.bin")).unwrap(), b"");
+        assert_eq!(
+            std::fs::read(target.join("big.bin")).unwrap(),
+            vec![3u8; 5000]
+        );
+        assert_eq!(
+            std::fs::read(target.join("d/".repeat(200)).join("leaf.txt")).unwrap(),
+            b"deep"
+        );
+        assert!(!target.join("skipped").exists());
+
+        std::fs::remove_dir_all(source).unwrap();
+        std::fs::remove_dir_all(target).unwrap();
+    }
+}
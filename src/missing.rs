@@ -0,0 +1,269 @@
+//! Objects deleted while a benchmark is reading them.
+//!
+//! Lifecycle rules and other background jobs may delete objects partway
+//! through a long run. `--missing-objects` picks what a `NotFound` for a
+//! listed object does:
+//!
+//! * `fail` ends the run with the error, as any other failure would
+//! * `skip` records the object as vanished and skips its remaining requests,
+//!   so its unread bytes drop out of the totals
+//! * `retry-list` lists the location again, once per run, and skips every
+//!   planned object the new listing lacks. An object still listed is tried
+//!   once more, as it may have been replaced rather than deleted.
+//!
+//! Results report the vanished objects under `vanished_objects`, each with
+//! the time since the start of the run it was first found missing.
+
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use tokio::sync::OnceCell;
+
+use crate::experiment::{fields, Fields};
+use crate::retry::RetryPolicy;
+use crate::ListingOptions;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum MissingObjects {
+    Skip,
+    #[default]
+    Fail,
+    RetryList,
+}
+
+impl MissingObjects {
+    pub fn name(self) -> &'static str {
+        match self {
+            MissingObjects::Skip => "skip",
+            MissingObjects::Fail => "fail",
+            MissingObjects::RetryList => "retry-list",
+        }
+    }
+}
+
+/// Errors that may be the store's `NotFound`.
+pub trait MaybeNotFound {
+    fn is_not_found(&self) -> bool;
+}
+
+impl MaybeNotFound for object_store::Error {
+    fn is_not_found(&self) -> bool {
+        matches!(self, object_store::Error::NotFound { .. })
+    }
+}
+
+impl MaybeNotFound for crate::Error {
+    fn is_not_found(&self) -> bool {
+        matches!(self, crate::Error::Store(err) if err.is_not_found())
+    }
+}
+
+/// The objects of one run found missing, and the policy for the next.
+pub struct MissingTracker {
+    policy: MissingObjects,
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    listing: ListingOptions,
+    retry: RetryPolicy,
+    start: Instant,
+    /// Objects the run set out to read
+    planned: HashSet<Path>,
+    vanished: Mutex<BTreeMap<Path, Duration>>,
+    /// Paths found by listing again, or `None` if that failed
+    relisted: OnceCell<Option<HashSet<Path>>>,
+}
+
+impl MissingTracker {
+    /// Track `objects` listed under `location` as `listing` says for a run
+    /// starting now, with the policy `retry` carries.
+    pub fn new(
+        object_store: Arc<dyn ObjectStore>,
+        location: &Path,
+        objects: &[ObjectMeta],
+        listing: &ListingOptions,
+        retry: &RetryPolicy,
+    ) -> Self {
+        Self {
+            policy: retry.missing_objects,
+            object_store,
+            location: location.clone(),
+            listing: listing.clone(),
+            retry: retry.clone(),
+            start: Instant::now(),
+            planned: objects.iter().map(|meta| meta.location.clone()).collect(),
+            vanished: Mutex::default(),
+            relisted: OnceCell::new(),
+        }
+    }
+
+    /// Whether `location` was found missing, so its requests are skipped.
+    pub fn is_vanished(&self, location: &Path) -> bool {
+        self.vanished.lock().unwrap().contains_key(location)
+    }
+
+    fn vanish(&self, location: &Path) {
+        let at = self.start.elapsed();
+        self.vanished
+            .lock()
+            .unwrap()
+            .entry(location.clone())
+            .or_insert(at);
+    }
+
+    /// Run `request` for `location`, applying the policy to a `NotFound`.
+    /// `None` means the object vanished and the request was given up.
+    pub async fn resolve<T, E, F, Fut>(
+        &self,
+        location: &Path,
+        mut request: F,
+    ) -> Result<Option<T>, E>
+    where
+        E: MaybeNotFound,
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        match request().await {
+            Err(err) if err.is_not_found() && self.policy != MissingObjects::Fail => {}
+            result => return result.map(Some),
+        }
+        if self.policy == MissingObjects::RetryList && self.still_listed(location).await {
+            match request().await {
+                Err(err) if err.is_not_found() => {}
+                result => return result.map(Some),
+            }
+        }
+        self.vanish(location);
+        Ok(None)
+    }
+
+    /// Whether listing the location again, which happens at most once,
+    /// still finds `location`. Planned objects it lacks are marked vanished.
+    async fn still_listed(&self, location: &Path) -> bool {
+        let relisted = self
+            .relisted
+            .get_or_init(|| async {
+                eprintln!("{} is missing; listing {} again", location, self.location);
+                match crate::refresh_location(
+                    self.object_store.as_ref(),
+                    &self.location,
+                    &self.listing,
+                    &self.retry,
+                )
+                .await
+                {
+                    Ok(objects) => {
+                        let listed = objects
+                            .into_iter()
+                            .map(|meta| meta.location)
+                            .collect::<HashSet<_>>();
+                        for gone in self.planned.difference(&listed) {
+                            self.vanish(gone);
+                        }
+                        Some(listed)
+                    }
+                    Err(err) => {
+                        eprintln!("warning: listing {} again failed: {}", self.location, err);
+                        None
+                    }
+                }
+            })
+            .await;
+        relisted
+            .as_ref()
+            .is_some_and(|listed| listed.contains(location))
+    }
+
+    /// The policy and vanished objects, as the `vanished_objects` field.
+    pub fn json_field(&self) -> Fields {
+        let vanished = self
+            .vanished
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(path, at)| {
+                serde_json::json!({
+                    "path": path.to_string(),
+                    "detected_at_us": at.as_micros() as u64,
+                })
+            })
+            .collect::<Vec<_>>();
+        fields(serde_json::json!({
+            "vanished_objects": {
+                "policy": self.policy.name(),
+                "relisted": self.relisted.initialized(),
+                "objects": vanished,
+            },
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    async fn tracker(policy: MissingObjects) -> (Arc<dyn ObjectStore>, MissingTracker) {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let mut objects = Vec::new();
+        for name in ["data/a", "data/b", "data/c"] {
+            let path = Path::from(name);
+            store.put(&path, Bytes::from_static(b"x")).await.unwrap();
+            objects.push(store.head(&path).await.unwrap());
+        }
+        let retry = RetryPolicy::new(0, None).with_missing_objects(policy);
+        let tracker = MissingTracker::new(
+            store.clone(),
+            &Path::from("data"),
+            &objects,
+            &ListingOptions::default(),
+            &retry,
+        );
+        (store, tracker)
+    }
+
+    #[tokio::test]
+    async fn policies_treat_not_found_differently() {
+        for policy in [MissingObjects::Fail, MissingObjects::Skip] {
+            let (store, tracker) = tracker(policy).await;
+            let a = Path::from("data/a");
+            store.delete(&a).await.unwrap();
+            let result = tracker.resolve(&a, || store.get_range(&a, 0..1)).await;
+            match policy {
+                MissingObjects::Fail => {
+                    assert!(matches!(result, Err(object_store::Error::NotFound { .. })))
+                }
+                _ => assert!(result.unwrap().is_none()),
+            }
+            assert_eq!(tracker.is_vanished(&a), policy == MissingObjects::Skip);
+        }
+    }
+
+    #[tokio::test]
+    async fn relisting_drops_every_deleted_object() {
+        let (store, tracker) = tracker(MissingObjects::RetryList).await;
+        let (a, b, c) = (
+            Path::from("data/a"),
+            Path::from("data/b"),
+            Path::from("data/c"),
+        );
+        store.delete(&a).await.unwrap();
+        store.delete(&b).await.unwrap();
+        assert!(tracker
+            .resolve(&a, || store.get_range(&a, 0..1))
+            .await
+            .unwrap()
+            .is_none());
+        // Found missing by the listing, before any request to it.
+        assert!(tracker.is_vanished(&b));
+        assert!(!tracker.is_vanished(&c));
+
+        let json = tracker.json_field();
+        assert_eq!(json["vanished_objects"]["policy"], "retry-list");
+        assert_eq!(json["vanished_objects"]["relisted"], true);
+        assert_eq!(json["vanished_objects"]["objects"][1]["path"], "data/b");
+    }
+}
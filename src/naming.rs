@@ -0,0 +1,193 @@
+//! Names of uploaded test objects.
+//!
+//! Objects uploaded by `upload-multiple` are named `object_{run_id}_{i}.bin`,
+//! so two seeding jobs sharing a prefix never overwrite each other. The run id
+//! is `--run-id` when given and a fresh ULID otherwise. `--flat-names` restores
+//! the older `object_{i}.bin`. Reads with `--run-id` keep only that run's
+//! objects when the location holds objects from several runs.
+
+use object_store::{path::Path, ObjectMeta};
+use rand::Rng;
+
+/// Longest key accepted by any supported backend, in bytes.
+const MAX_KEY_BYTES: usize = 1024;
+/// Longest run id allowed in an object name.
+const MAX_RUN_ID_LEN: usize = 64;
+
+/// Crockford's base32 alphabet, as used by ULIDs.
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// A new ULID: 48 bits of milliseconds since the epoch then 80 random bits,
+/// in 26 characters that sort by creation time.
+pub fn generate_run_id() -> String {
+    let millis = chrono::Utc::now().timestamp_millis() as u128;
+    let random = rand::thread_rng().gen::<u128>() & ((1 << 80) - 1);
+    let value = (millis << 80) | random;
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+/// Check that `run_id` can be embedded in an object name on every backend.
+pub fn validate_run_id(run_id: &str) -> Result<(), String> {
+    if run_id.is_empty() || run_id.len() > MAX_RUN_ID_LEN {
+        return Err(format!(
+            "run id must be 1 to {} characters, got {:?}",
+            MAX_RUN_ID_LEN, run_id
+        ));
+    }
+    if let Some(c) = run_id
+        .chars()
+        .find(|c| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')))
+    {
+        return Err(format!(
+            "run id may only contain letters, digits, '-', '.' and '_', got {:?} in {:?}",
+            c, run_id
+        ));
+    }
+    Ok(())
+}
+
+/// Name of the `i`th object of a run, or the flat name without a run id.
+pub fn object_name(run_id: Option<&str>, i: usize) -> String {
+    match run_id {
+        Some(run_id) => format!("object_{}_{}.bin", run_id, i),
+        None => format!("object_{}.bin", i),
+    }
+}
+
+/// Check that `location` is short enough for every backend.
+pub fn check_key(location: &Path) -> Result<(), String> {
+    let len = location.as_ref().len();
+    if len > MAX_KEY_BYTES {
+        return Err(format!(
+            "object key is {} bytes, more than the {} allowed: {}",
+            len, MAX_KEY_BYTES, location
+        ));
+    }
+    Ok(())
+}
+
+/// The run id embedded in an object name, if it has one.
+pub fn run_id_of(location: &Path) -> Option<&str> {
+    let name = location.filename()?;
+    let (run_id, i) = name
+        .strip_prefix("object_")?
+        .strip_suffix(".bin")?
+        .rsplit_once('_')?;
+    if run_id.is_empty() || i.is_empty() || !i.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some(run_id)
+}
+
+/// Keep only the objects uploaded by `run_id`.
+///
+/// Locations holding no run-named objects are returned unchanged, so a run id
+/// used only to tag results doesn't hide data seeded with flat names.
+pub fn select_run(
+    objects: Vec<ObjectMeta>,
+    run_id: Option<&str>,
+) -> Result<Vec<ObjectMeta>, String> {
+    let Some(run_id) = run_id else {
+        return Ok(objects);
+    };
+    let runs = objects
+        .iter()
+        .filter_map(|meta| run_id_of(&meta.location))
+        .collect::<std::collections::BTreeSet<_>>();
+    if runs.is_empty() {
+        return Ok(objects);
+    }
+    if !runs.contains(run_id) {
+        return Err(format!(
+            "no objects from run {} here; found runs {}",
+            run_id,
+            runs.into_iter().collect::<Vec<_>>().join(", ")
+        ));
+    }
+    Ok(objects
+        .into_iter()
+        .filter(|meta| run_id_of(&meta.location) == Some(run_id))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(location: &str) -> ObjectMeta {
+        ObjectMeta {
+            location: Path::from(location),
+            last_modified: chrono::Utc::now(),
+            size: 1,
+            e_tag: None,
+        }
+    }
+
+    #[test]
+    fn generated_run_ids_are_valid_and_distinct() {
+        let a = generate_run_id();
+        let b = generate_run_id();
+        assert_eq!(a.len(), 26);
+        assert_ne!(a, b);
+        validate_run_id(&a).unwrap();
+        assert!(a.bytes().all(|c| CROCKFORD.contains(&c)));
+    }
+
+    #[test]
+    fn run_ids_are_restricted() {
+        validate_run_id("nightly-2024.01_a").unwrap();
+        assert!(validate_run_id("").is_err());
+        assert!(validate_run_id("a/b").is_err());
+        assert!(validate_run_id("a b").is_err());
+        assert!(validate_run_id("ü").is_err());
+        assert!(validate_run_id(&"a".repeat(MAX_RUN_ID_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn names_round_trip() {
+        let run_id = generate_run_id();
+        let name = Path::from("data").child(object_name(Some(&run_id), 12));
+        assert_eq!(run_id_of(&name), Some(run_id.as_str()));
+        assert_eq!(
+            run_id_of(&Path::from("data/object_run_with_underscores_3.bin")),
+            Some("run_with_underscores")
+        );
+
+        let flat = Path::from("data").child(object_name(None, 12));
+        assert_eq!(flat.filename(), Some("object_12.bin"));
+        assert_eq!(run_id_of(&flat), None);
+        assert_eq!(run_id_of(&Path::from("data/object_x_y.bin")), None);
+        assert_eq!(run_id_of(&Path::from("data/_SUCCESS")), None);
+    }
+
+    #[test]
+    fn names_survive_path_encoding() {
+        // Names that Path would percent-encode would not match what's listed.
+        let name = object_name(Some(&"Az09-._".repeat(10)[..MAX_RUN_ID_LEN]), usize::MAX);
+        let location = Path::from("prefix").child(name.as_str());
+        assert_eq!(location.filename(), Some(name.as_str()));
+        assert_eq!(Path::parse(location.as_ref()).unwrap(), location);
+        check_key(&location).unwrap();
+        assert!(check_key(&Path::from("a".repeat(MAX_KEY_BYTES + 1))).is_err());
+    }
+
+    #[test]
+    fn selects_one_run() {
+        let objects = vec![
+            meta("data/object_A_0.bin"),
+            meta("data/object_A_1.bin"),
+            meta("data/object_B_0.bin"),
+            meta("data/_SUCCESS"),
+        ];
+        let selected = select_run(objects.clone(), Some("A")).unwrap();
+        assert_eq!(selected.len(), 2);
+        assert!(select_run(objects.clone(), Some("C")).is_err());
+        assert_eq!(select_run(objects, None).unwrap().len(), 4);
+
+        let flat = vec![meta("data/object_0.bin"), meta("data/object_1.bin")];
+        assert_eq!(select_run(flat, Some("A")).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,341 @@
+//! Grouping requests by key prefix, to spot a slow or failing partition.
+//!
+//! S3 partitions a bucket by key range, so requests that are slow together
+//! often share the start of their keys. With `--partition-prefix-bytes N`,
+//! [`PartitionStore`] tags each read and head with the first N bytes of its
+//! key and records its latency, or its error, under that prefix. Every result
+//! then has a `partitions` field listing the slowest prefixes and, as
+//! `suspect_prefixes`, those whose p99 latency or error rate is an outlier: a
+//! z-score above [`SUSPECT_Z`] against the other prefixes. Only prefixes with
+//! at least [`MIN_REQUESTS`] requests are compared, and since one outlier
+//! among few groups can't score that high, several prefixes are needed.
+//!
+//! Latency is measured from the request to its response, so for streamed
+//! gets it excludes the body. `NotFound` responses aren't counted. This is only a hint: the store's real
+//! partition boundaries aren't visible to clients.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::experiment::Fields;
+use crate::stats::Histogram;
+
+/// The z-score above which a prefix is reported as suspect.
+pub const SUSPECT_Z: f64 = 2.0;
+
+/// Requests a prefix needs before it is compared with the others.
+pub const MIN_REQUESTS: u64 = 5;
+
+/// Prefixes listed under `slowest`.
+const MAX_LISTED: usize = 10;
+
+#[derive(Debug, Clone, Default)]
+struct PrefixStats {
+    requests: u64,
+    errors: u64,
+    histogram: Histogram,
+}
+
+/// What one prefix's requests measured.
+#[derive(Debug, Clone, PartialEq)]
+struct PrefixSummary {
+    prefix: String,
+    requests: u64,
+    errors: u64,
+    error_rate: f64,
+    p50_us: u128,
+    p99_us: u128,
+}
+
+impl PrefixSummary {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "prefix": self.prefix,
+            "requests": self.requests,
+            "errors": self.errors,
+            "error_rate": self.error_rate,
+            "p50_us": self.p50_us,
+            "p99_us": self.p99_us,
+        })
+    }
+}
+
+/// Requests of a run, grouped by the first `prefix_bytes` bytes of their key.
+#[derive(Debug)]
+pub struct Partitions {
+    prefix_bytes: usize,
+    prefixes: Mutex<HashMap<String, PrefixStats>>,
+}
+
+impl Partitions {
+    pub fn new(prefix_bytes: usize) -> Self {
+        Self {
+            prefix_bytes,
+            prefixes: Mutex::default(),
+        }
+    }
+
+    /// The first `prefix_bytes` bytes of `location`. Paths are
+    /// percent-encoded, so any byte ends a character.
+    fn prefix<'a>(&self, location: &'a Path) -> &'a str {
+        let key = location.as_ref();
+        &key[..self.prefix_bytes.min(key.len())]
+    }
+
+    /// Count a request to `location` that took `latency`, or failed.
+    pub fn record(&self, location: &Path, outcome: std::result::Result<Duration, ()>) {
+        let prefix = self.prefix(location);
+        let mut prefixes = self.prefixes.lock().unwrap();
+        let stats = match prefixes.get_mut(prefix) {
+            Some(stats) => stats,
+            None => prefixes.entry(prefix.to_string()).or_default(),
+        };
+        stats.requests += 1;
+        match outcome {
+            Ok(latency) => stats.histogram.record(latency),
+            Err(()) => stats.errors += 1,
+        }
+    }
+
+    fn summaries(&self) -> Vec<PrefixSummary> {
+        let prefixes = self.prefixes.lock().unwrap();
+        let mut summaries = prefixes
+            .iter()
+            .map(|(prefix, stats)| PrefixSummary {
+                prefix: prefix.clone(),
+                requests: stats.requests,
+                errors: stats.errors,
+                error_rate: stats.errors as f64 / stats.requests as f64,
+                p50_us: stats.histogram.percentile_us(0.50),
+                p99_us: stats.histogram.percentile_us(0.99),
+            })
+            .collect::<Vec<_>>();
+        summaries.sort_by(|a, b| {
+            b.p99_us
+                .cmp(&a.p99_us)
+                .then_with(|| a.prefix.cmp(&b.prefix))
+        });
+        summaries
+    }
+
+    /// The prefixes as a JSON object, with the outliers among them.
+    pub fn summary(&self) -> serde_json::Value {
+        let summaries = self.summaries();
+        let compared = summaries
+            .iter()
+            .filter(|summary| summary.requests >= MIN_REQUESTS)
+            .collect::<Vec<_>>();
+        let p99_z = z_scores(&compared, |summary| summary.p99_us as f64);
+        let error_rate_z = z_scores(&compared, |summary| summary.error_rate);
+        let suspects = compared
+            .iter()
+            .zip(p99_z.iter().zip(&error_rate_z))
+            .filter(|(_, (p99_z, error_rate_z))| {
+                p99_z.is_some_and(|z| z > SUSPECT_Z) || error_rate_z.is_some_and(|z| z > SUSPECT_Z)
+            })
+            .map(|(summary, (p99_z, error_rate_z))| {
+                let mut json = summary.to_json();
+                json["p99_z"] = serde_json::json!(p99_z);
+                json["error_rate_z"] = serde_json::json!(error_rate_z);
+                json
+            })
+            .collect::<Vec<_>>();
+        serde_json::json!({
+            "prefix_bytes": self.prefix_bytes,
+            "prefixes": summaries.len(),
+            "compared": compared.len(),
+            "slowest": summaries
+                .iter()
+                .take(MAX_LISTED)
+                .map(PrefixSummary::to_json)
+                .collect::<Vec<_>>(),
+            "suspect_prefixes": suspects,
+        })
+    }
+}
+
+/// Each summary's z-score for `metric` against all of them, or `None` when
+/// they are too few or all the same.
+fn z_scores(
+    summaries: &[&PrefixSummary],
+    metric: impl Fn(&PrefixSummary) -> f64,
+) -> Vec<Option<f64>> {
+    let values = summaries
+        .iter()
+        .map(|summary| metric(summary))
+        .collect::<Vec<_>>();
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let std_dev = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n).sqrt();
+    values
+        .iter()
+        .map(|value| (values.len() >= 3 && std_dev > 0.0).then(|| (value - mean) / std_dev))
+        .collect()
+}
+
+/// Append the requests `partitions` timed so far, grouped by prefix, to the
+/// JSON object `result`.
+pub fn with_partitions(result: &mut Fields, partitions: &Partitions) {
+    result.insert("partitions".to_string(), partitions.summary());
+}
+
+/// An [`ObjectStore`] that times the reads and heads sent to `inner` by the
+/// prefix of their key.
+pub struct PartitionStore {
+    inner: Arc<dyn ObjectStore>,
+    partitions: Arc<Partitions>,
+}
+
+impl PartitionStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, partitions: Arc<Partitions>) -> Self {
+        Self { inner, partitions }
+    }
+
+    fn timed<T>(&self, location: &Path, start: Instant, result: Result<T>) -> Result<T> {
+        let outcome = match &result {
+            Ok(_) => Ok(start.elapsed()),
+            // A miss says nothing about the partition, and probing whether a
+            // location is an object or a prefix makes one.
+            Err(object_store::Error::NotFound { .. }) => return result,
+            Err(_) => Err(()),
+        };
+        self.partitions.record(location, outcome);
+        result
+    }
+}
+
+impl Display for PartitionStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PartitionStore({})", self.inner)
+    }
+}
+
+impl Debug for PartitionStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("PartitionStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for PartitionStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let start = Instant::now();
+        let result = self.inner.get_opts(location, options).await;
+        self.timed(location, start, result)
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let start = Instant::now();
+        let result = self.inner.get_range(location, range).await;
+        self.timed(location, start, result)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let start = Instant::now();
+        let result = self.inner.get_ranges(location, ranges).await;
+        self.timed(location, start, result)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let start = Instant::now();
+        let result = self.inner.head(location).await;
+        self.timed(location, start, result)
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slow_and_failing_prefixes_are_suspect() {
+        let partitions = Partitions::new(3);
+        for prefix in ["a", "b", "c", "d", "e", "f", "g"] {
+            for i in 0..10 {
+                let location = Path::from(format!("{}{}/{}", prefix, prefix, i));
+                let latency = match prefix {
+                    "b" => Duration::from_millis(500),
+                    _ => Duration::from_millis(10),
+                };
+                let failed = prefix == "e" && i < 5;
+                partitions.record(&location, if failed { Err(()) } else { Ok(latency) });
+            }
+        }
+        // Too few requests to compare.
+        partitions.record(&Path::from("zz/0"), Ok(Duration::from_secs(5)));
+
+        let summary = partitions.summary();
+        assert_eq!(summary["prefixes"], 8);
+        assert_eq!(summary["compared"], 7);
+        assert_eq!(summary["slowest"][0]["prefix"], "zz/");
+        assert_eq!(summary["slowest"][1]["prefix"], "bb/");
+        let suspects = summary["suspect_prefixes"].as_array().unwrap();
+        let mut names = suspects
+            .iter()
+            .map(|suspect| suspect["prefix"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, ["bb/", "ee/"]);
+        let ee = suspects.iter().find(|s| s["prefix"] == "ee/").unwrap();
+        assert_eq!(ee["errors"], 5);
+        assert_eq!(ee["error_rate"], 0.5);
+    }
+
+    #[test]
+    fn prefixes_are_the_first_bytes_of_the_key() {
+        let partitions = Partitions::new(3);
+        assert_eq!(partitions.prefix(&Path::from("é/x")), "%C3");
+        assert_eq!(partitions.prefix(&Path::from("a")), "a");
+    }
+}
@@ -0,0 +1,407 @@
+//! What uploaded test objects contain, and checking downloads against it.
+//!
+//! By default objects are random bytes, drawn from `--seed` when one is
+//! given, which no store or proxy can compress in flight. `--pattern zeros`
+//! and `--pattern text` write data that compresses: all zero bytes, or
+//! printable ASCII drawn from an alphabet of `2^--text-entropy-bits`
+//! characters, so each byte carries that many bits and compresses to roughly
+//! that fraction of eight. Every pattern is generated one part at a time, so
+//! objects larger than memory still upload.
+//!
+//! `--pattern offset` instead writes each aligned 8-byte word as its own
+//! absolute offset in the object, little-endian, so any byte's expected value
+//! follows from where it sits. `download --verify` checks every block it reads
+//! against that pattern, which catches a store returning the wrong range, or a
+//! short one, without keeping a copy of the data.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use object_store::path::Path;
+use rand::rngs::StdRng;
+use rand::{RngCore, SeedableRng};
+
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
+
+/// Mismatched blocks listed in full; the rest are only counted.
+const MAX_LISTED: usize = 20;
+
+/// Characters of `--pattern text`, of which the first `2^entropy_bits` are
+/// used.
+const ALPHABET: &[u8; 64] = b"etaoinsrhldcumfpgwybvkxjqz ETAOINSRHLDCUMFPGWYBVKXJQZ0123456789\n";
+
+/// Bits per byte `--pattern text` can be given.
+pub const TEXT_ENTROPY_BITS: std::ops::RangeInclusive<u8> = 1..=6;
+
+/// How test objects are filled, as chosen on the command line.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Pattern {
+    #[default]
+    Random,
+    Zeros,
+    Text,
+    Offset,
+}
+
+/// The contents of one object, or of each object of an upload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contents {
+    /// Random bytes, drawn from the seed when there is one
+    Random { seed: Option<u64> },
+    /// Zero bytes
+    Zeros,
+    /// Random characters from the first `2^entropy_bits` of the alphabet
+    Text { entropy_bits: u8, seed: Option<u64> },
+    /// Each 8-byte word holds its own offset
+    Offset,
+}
+
+impl Contents {
+    /// The contents `pattern` names. Only random patterns take a seed, and
+    /// `text_entropy_bits` only applies to text.
+    pub fn new(pattern: Pattern, seed: Option<u64>, text_entropy_bits: u8) -> Result<Self, Error> {
+        if seed.is_some() && matches!(pattern, Pattern::Zeros | Pattern::Offset) {
+            return Err(format!("--pattern {} can't be seeded", pattern.name()).into());
+        }
+        if !TEXT_ENTROPY_BITS.contains(&text_entropy_bits) {
+            return Err(format!(
+                "--text-entropy-bits must be between {} and {}",
+                TEXT_ENTROPY_BITS.start(),
+                TEXT_ENTROPY_BITS.end()
+            )
+            .into());
+        }
+        Ok(match pattern {
+            Pattern::Random => Contents::Random { seed },
+            Pattern::Zeros => Contents::Zeros,
+            Pattern::Text => Contents::Text {
+                entropy_bits: text_entropy_bits,
+                seed,
+            },
+            Pattern::Offset => Contents::Offset,
+        })
+    }
+
+    /// The contents of object `i` of an upload: seeded objects each get
+    /// their own seed, `seed + i`.
+    pub fn for_object(self, i: usize) -> Self {
+        let next = |seed: Option<u64>| seed.map(|seed| seed.wrapping_add(i as u64));
+        match self {
+            Contents::Random { seed } => Contents::Random { seed: next(seed) },
+            Contents::Text { entropy_bits, seed } => Contents::Text {
+                entropy_bits,
+                seed: next(seed),
+            },
+            Contents::Zeros | Contents::Offset => self,
+        }
+    }
+
+    pub fn seed(self) -> Option<u64> {
+        match self {
+            Contents::Random { seed } | Contents::Text { seed, .. } => seed,
+            Contents::Zeros | Contents::Offset => None,
+        }
+    }
+
+    pub fn pattern(self) -> Pattern {
+        match self {
+            Contents::Random { .. } => Pattern::Random,
+            Contents::Zeros => Pattern::Zeros,
+            Contents::Text { .. } => Pattern::Text,
+            Contents::Offset => Pattern::Offset,
+        }
+    }
+
+    /// The `pattern` field of an upload's result, with the text entropy.
+    pub fn json_fields(self) -> Fields {
+        match self {
+            Contents::Text { entropy_bits, .. } => fields(serde_json::json!({
+                "pattern": self.pattern().name(),
+                "text_entropy_bits": entropy_bits,
+            })),
+            _ => fields(serde_json::json!({ "pattern": self.pattern().name() })),
+        }
+    }
+
+    /// A filler producing an object's bytes from its start.
+    pub fn filler(self) -> Filler {
+        let rng = |seed: Option<u64>| {
+            Box::new(match seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            })
+        };
+        match self {
+            Contents::Random { seed } => Filler::Random(rng(seed)),
+            Contents::Zeros => Filler::Zeros,
+            Contents::Text { entropy_bits, seed } => Filler::Text(rng(seed), entropy_bits),
+            Contents::Offset => Filler::Offset,
+        }
+    }
+}
+
+impl Pattern {
+    /// The pattern's name, as given to `--pattern`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pattern::Random => "random",
+            Pattern::Zeros => "zeros",
+            Pattern::Text => "text",
+            Pattern::Offset => "offset",
+        }
+    }
+}
+
+/// Produces an object's bytes in order.
+pub enum Filler {
+    Random(Box<StdRng>),
+    Zeros,
+    Text(Box<StdRng>, u8),
+    Offset,
+}
+
+impl Filler {
+    /// Fill `buffer` with the bytes at `offset` onwards.
+    pub fn fill(&mut self, offset: u64, buffer: &mut [u8]) {
+        match self {
+            Filler::Random(rng) => rng.fill_bytes(buffer),
+            Filler::Zeros => buffer.fill(0),
+            Filler::Text(rng, entropy_bits) => {
+                // The top bits of each random byte pick its character.
+                rng.fill_bytes(buffer);
+                for byte in buffer.iter_mut() {
+                    *byte = ALPHABET[(*byte >> (8 - *entropy_bits)) as usize];
+                }
+            }
+            Filler::Offset => {
+                for (i, byte) in buffer.iter_mut().enumerate() {
+                    *byte = expected_byte(offset + i as u64);
+                }
+            }
+        }
+    }
+}
+
+/// The byte at `offset` of an object written with [`Pattern::Offset`].
+fn expected_byte(offset: u64) -> u8 {
+    (offset & !7).to_le_bytes()[(offset & 7) as usize]
+}
+
+/// The first offset at which `data`, read from `range` of an object written
+/// with [`Pattern::Offset`], differs from the pattern. Data shorter than the
+/// range differs where it ends.
+pub fn first_mismatch(range: std::ops::Range<u64>, data: &[u8]) -> Option<u64> {
+    let mismatch = data
+        .iter()
+        .zip(range.clone())
+        .position(|(byte, offset)| *byte != expected_byte(offset))
+        .map(|i| range.start + i as u64);
+    let expected_len = range.end - range.start;
+    let short = data.len() as u64 != expected_len;
+    mismatch.or_else(|| short.then(|| range.start + expected_len.min(data.len() as u64)))
+}
+
+/// A block that didn't hold the offset pattern.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct Mismatch {
+    pub object: String,
+    pub range: std::ops::Range<u64>,
+    pub first_differing_offset: u64,
+}
+
+/// Checks of the blocks a download read, collected as they complete.
+#[derive(Debug, Default)]
+pub struct Verification {
+    state: Mutex<VerificationState>,
+}
+
+#[derive(Debug, Default)]
+struct VerificationState {
+    blocks: usize,
+    bytes: u64,
+    mismatched: usize,
+    listed: Vec<Mismatch>,
+    elapsed: Duration,
+}
+
+impl Verification {
+    /// Check `data`, read from `range` of `location`, against the pattern.
+    pub fn check(&self, location: &Path, range: std::ops::Range<u64>, data: &[u8]) {
+        let start = Instant::now();
+        let mismatch = first_mismatch(range.clone(), data);
+        let elapsed = start.elapsed();
+        let mut state = self.state.lock().unwrap();
+        state.blocks += 1;
+        state.bytes += data.len() as u64;
+        state.elapsed += elapsed;
+        if let Some(first_differing_offset) = mismatch {
+            state.mismatched += 1;
+            if state.listed.len() < MAX_LISTED {
+                state.listed.push(Mismatch {
+                    object: location.to_string(),
+                    range,
+                    first_differing_offset,
+                });
+            }
+        }
+    }
+
+    /// The `verify` field of a result.
+    pub fn json_field(&self) -> Fields {
+        let state = self.state.lock().unwrap();
+        let mut listed = state.listed.clone();
+        listed.sort_by(|a, b| (&a.object, a.range.start).cmp(&(&b.object, b.range.start)));
+        fields(serde_json::json!({
+            "verify": {
+                "blocks_checked": state.blocks,
+                "bytes_checked": state.bytes,
+                "mismatched_blocks": state.mismatched,
+                "mismatches": listed,
+                "verify_elapsed_us": state.elapsed.as_micros() as u64,
+            },
+        }))
+    }
+
+    /// An error if any block mismatched, for after the result is printed.
+    pub fn enforce(&self) -> Result<(), crate::Error> {
+        let state = self.state.lock().unwrap();
+        match state.mismatched {
+            0 => Ok(()),
+            mismatched => Err(crate::Error::Verification(format!(
+                "{} of {} blocks didn't match the offset pattern",
+                mismatched, state.blocks
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::RunControl;
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::retry::RetryPolicy;
+    use crate::upload::{upload_test_data, UploadSamples};
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, ObjectStore};
+    use std::sync::Arc;
+
+    #[test]
+    fn words_hold_their_offsets() {
+        let mut object = vec![0; 24];
+        Contents::Offset.filler().fill(0, &mut object);
+        assert_eq!(&object[8..16], &8u64.to_le_bytes());
+        assert_eq!(first_mismatch(0..24, &object), None);
+
+        // Filling from an unaligned offset continues the same pattern.
+        let mut tail = vec![0; 13];
+        Contents::Offset.filler().fill(11, &mut tail);
+        assert_eq!(tail, object[11..24]);
+        assert_eq!(first_mismatch(11..24, &tail), None);
+
+        // A range read from elsewhere in the object is caught at its start.
+        assert_eq!(first_mismatch(8..24, &object[..16]), Some(8));
+        tail[5] ^= 1;
+        assert_eq!(first_mismatch(11..24, &tail), Some(16));
+        // So is a short read.
+        assert_eq!(first_mismatch(0..24, &object[..20]), Some(20));
+    }
+
+    #[test]
+    fn seeded_objects_each_get_their_own_seed() {
+        let contents = Contents::new(Pattern::Random, Some(5), 4).unwrap();
+        assert_eq!(contents.for_object(2), Contents::Random { seed: Some(7) });
+        let fill = |contents: Contents| {
+            let mut buffer = vec![0; 64];
+            contents.filler().fill(0, &mut buffer);
+            buffer
+        };
+        assert_eq!(fill(contents), fill(contents));
+        assert_ne!(fill(contents), fill(contents.for_object(1)));
+        assert_eq!(
+            Contents::new(Pattern::Offset, None, 4).unwrap().seed(),
+            None
+        );
+        assert!(Contents::new(Pattern::Offset, Some(5), 4).is_err());
+    }
+
+    #[test]
+    fn text_draws_from_an_alphabet_of_the_entropy() {
+        let text = Contents::new(Pattern::Text, Some(1), 2).unwrap();
+        assert_eq!(text.for_object(1).seed(), Some(2));
+        let mut buffer = vec![0; 4096];
+        text.filler().fill(0, &mut buffer);
+        let mut seen = buffer.clone();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, b"aeot");
+        assert!(Contents::new(Pattern::Text, None, 7).is_err());
+
+        let mut zeros = vec![1; 100];
+        Contents::Zeros.filler().fill(0, &mut zeros);
+        assert!(zeros.iter().all(|&byte| byte == 0));
+        assert_eq!(
+            serde_json::Value::from(text.json_fields()),
+            serde_json::json!({"pattern": "text", "text_entropy_bits": 2})
+        );
+    }
+
+    #[tokio::test]
+    async fn downloads_report_blocks_that_break_the_pattern() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for name in ["a", "b"] {
+            upload_test_data(
+                object_store.clone(),
+                &Path::from(format!("data/{}", name)),
+                10_000,
+                10_000,
+                Contents::Offset,
+                &RetryPolicy::new(0, None),
+                None,
+                &UploadSamples::start(),
+            )
+            .await
+            .unwrap();
+        }
+        let download = || {
+            parallel_download_bench(
+                object_store.clone(),
+                Path::from("data"),
+                DownloadOptions {
+                    parallel_downloads: 4,
+                    block_size: Some(1024),
+                    verify: true,
+                    ..DownloadOptions::default()
+                },
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            )
+        };
+        let result = download().await.unwrap().into_result().unwrap();
+        let result = serde_json::to_value(result).unwrap();
+        assert_eq!(result["verify"]["blocks_checked"], 20);
+        assert_eq!(result["verify"]["bytes_checked"], 20_000);
+        assert_eq!(result["verify"]["mismatched_blocks"], 0);
+
+        // Shift the second object by one word, as a store serving the wrong
+        // offsets would.
+        let mut shifted = vec![0; 10_008];
+        Contents::Offset.filler().fill(0, &mut shifted);
+        object_store
+            .put(&Path::from("data/b"), Bytes::from(shifted[8..].to_vec()))
+            .await
+            .unwrap();
+        let checked = download().await.unwrap();
+        // Timing is still reported.
+        let result = serde_json::to_value(&checked.result).unwrap();
+        let err = checked.into_result().unwrap_err();
+        assert!(err.to_string().contains("10 of 20 blocks"));
+        assert!(result["mbps"].as_f64().is_some());
+        assert_eq!(result["verify"]["mismatched_blocks"], 10);
+        let first = &result["verify"]["mismatches"][0];
+        assert_eq!(first["object"], "data/b");
+        assert_eq!(first["range"], serde_json::json!({"start": 0, "end": 1024}));
+        assert_eq!(first["first_differing_offset"], 0);
+    }
+}
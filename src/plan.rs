@@ -0,0 +1,418 @@
+//! Recording and replaying the exact sequence of requests a run issued.
+//!
+//! `--record-plan plan.jsonl` writes a header line with the run's concurrency,
+//! followed by one line per issued request in issue order. Paths are stored
+//! relative to the benchmarked location so a plan recorded against one bucket
+//! can be replayed with `replay` against another holding the same dataset.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, GetOptions, ObjectMeta, ObjectStore};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps};
+
+/// Writes issued requests to a plan file.
+#[derive(Debug)]
+pub struct PlanRecorder {
+    base: Path,
+    next: AtomicU64,
+    writer: Mutex<BufWriter<File>>,
+}
+
+impl PlanRecorder {
+    pub fn create(
+        path: &std::path::Path,
+        base: Path,
+        parallel_downloads: Option<usize>,
+    ) -> std::io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let header = serde_json::json!({
+            "op": "header",
+            "parallel_downloads": parallel_downloads,
+        });
+        writeln!(writer, "{}", header)?;
+        Ok(Self {
+            base,
+            next: AtomicU64::new(0),
+            writer: Mutex::new(writer),
+        })
+    }
+
+    /// Record one request at the moment it is issued.
+    pub fn record(&self, op: &str, location: &Path, range: Option<&Range<usize>>) {
+        let relative = match location.prefix_match(&self.base) {
+            Some(parts) => Path::from_iter(parts).to_string(),
+            None => location.to_string(),
+        };
+        let mut writer = self.writer.lock().unwrap();
+        // Take the sequence number under the lock so file order is issue order.
+        let seq = self.next.fetch_add(1, Ordering::SeqCst);
+        let line = serde_json::json!({
+            "seq": seq,
+            "op": op,
+            "path": relative,
+            "range": range.map(|r| [r.start, r.end]),
+        });
+        if let Err(err) = writeln!(writer, "{}", line) {
+            eprintln!("failed to record plan entry: {}", err);
+        }
+    }
+
+    pub fn flush(&self) -> std::io::Result<()> {
+        self.writer.lock().unwrap().flush()
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PlannedRequest {
+    Head { path: String },
+    Get { path: String },
+    GetOpts { path: String, range: Range<usize> },
+    GetRange { path: String, range: Range<usize> },
+}
+
+impl PlannedRequest {
+    fn path(&self) -> &str {
+        match self {
+            PlannedRequest::Head { path }
+            | PlannedRequest::Get { path }
+            | PlannedRequest::GetOpts { path, .. }
+            | PlannedRequest::GetRange { path, .. } => path,
+        }
+    }
+}
+
+struct Plan {
+    parallel_downloads: Option<usize>,
+    requests: Vec<PlannedRequest>,
+}
+
+fn load_plan(path: &std::path::Path) -> Result<Plan, Error> {
+    let reader = BufReader::new(
+        File::open(path).map_err(|err| format!("failed to open {}: {}", path.display(), err))?,
+    );
+    let mut plan = Plan {
+        parallel_downloads: None,
+        requests: Vec::new(),
+    };
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry: serde_json::Value = serde_json::from_str(&line)
+            .map_err(|err| format!("{}:{}: {}", path.display(), i + 1, err))?;
+        let field = |name: &str| {
+            entry
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("{}:{}: missing {}", path.display(), i + 1, name))
+        };
+        let op = field("op")?;
+        match op.as_str() {
+            Some("header") => {
+                plan.parallel_downloads = entry
+                    .get("parallel_downloads")
+                    .and_then(|v| v.as_u64())
+                    .map(|v| v as usize);
+            }
+            Some("head") => plan.requests.push(PlannedRequest::Head {
+                path: field("path")?.as_str().unwrap_or_default().to_string(),
+            }),
+            Some("get") => plan.requests.push(PlannedRequest::Get {
+                path: field("path")?.as_str().unwrap_or_default().to_string(),
+            }),
+            Some(op @ ("get_opts" | "get_range")) => {
+                let range: [usize; 2] = serde_json::from_value(field("range")?)?;
+                let path = field("path")?.as_str().unwrap_or_default().to_string();
+                let range = range[0]..range[1];
+                plan.requests.push(match op {
+                    "get_opts" => PlannedRequest::GetOpts { path, range },
+                    _ => PlannedRequest::GetRange { path, range },
+                });
+            }
+            _ => return Err(format!("{}:{}: unknown op {}", path.display(), i + 1, op).into()),
+        }
+    }
+    Ok(plan)
+}
+
+/// Head each of `paths`, relative to `base`, returning the found objects by
+/// path and the paths that are missing.
+pub async fn head_referenced(
+    object_store: &dyn ObjectStore,
+    base: &Path,
+    paths: &BTreeSet<String>,
+    retry: &RetryPolicy,
+) -> object_store::Result<(HashMap<String, ObjectMeta>, Vec<String>)> {
+    let mut found = HashMap::new();
+    let mut missing = Vec::new();
+    for path in paths {
+        let location = resolve(base, path);
+        match retry.run(|| object_store.head(&location)).await {
+            Ok(meta) => {
+                found.insert(path.clone(), meta);
+            }
+            Err(object_store::Error::NotFound { .. }) => missing.push(path.clone()),
+            Err(err) => return Err(err),
+        }
+    }
+    Ok((found, missing))
+}
+
+pub fn resolve(base: &Path, relative: &str) -> Path {
+    let relative = Path::from(relative);
+    let mut parts = base.parts().collect::<Vec<_>>();
+    parts.extend(relative.parts());
+    Path::from_iter(parts)
+}
+
+/// Replays a recorded plan against `base`, in recorded issue order.
+///
+/// Every object the plan references is checked with a `head` first; if any are
+/// missing the replay fails before issuing the plan, listing them all.
+pub async fn replay_plan(
+    object_store: Arc<dyn ObjectStore>,
+    base: Path,
+    plan_path: &std::path::Path,
+    parallel_downloads: Option<usize>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let plan = load_plan(plan_path)?;
+    let parallel_downloads = parallel_downloads.or(plan.parallel_downloads).unwrap_or(10);
+
+    let paths = plan
+        .requests
+        .iter()
+        .map(|request| request.path().to_string())
+        .collect::<BTreeSet<_>>();
+    let (_, missing) = head_referenced(object_store.as_ref(), &base, &paths, &retry).await?;
+    if !missing.is_empty() {
+        return Err(format!(
+            "target {} is missing {} of {} objects referenced by the plan:\n  - {}",
+            base,
+            missing.len(),
+            paths.len(),
+            missing.join("\n  - ")
+        )
+        .into());
+    }
+
+    let start = Instant::now();
+    let counts = futures::stream::iter(plan.requests.iter().cloned())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|request| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            let location = resolve(&base, request.path());
+            tokio::task::spawn(async move {
+                if !control.request_started().await {
+                    return Ok(0);
+                }
+                let result = match &request {
+                    PlannedRequest::Head { .. } => {
+                        retry.run(|| object_store.head(&location)).await.map(|_| 0)
+                    }
+                    PlannedRequest::Get { .. } => retry
+                        .run(|| async { object_store.get(&location).await?.bytes().await })
+                        .await
+                        .map(|bytes| bytes.len()),
+                    PlannedRequest::GetOpts { range, .. } => retry
+                        .run(|| async {
+                            let options = GetOptions {
+                                range: Some(range.clone()),
+                                ..Default::default()
+                            };
+                            object_store
+                                .get_opts(&location, options)
+                                .await?
+                                .bytes()
+                                .await
+                        })
+                        .await
+                        .map(|bytes| bytes.len()),
+                    PlannedRequest::GetRange { range, .. } => retry
+                        .run(|| object_store.get_range(&location, range.clone()))
+                        .await
+                        .map(|bytes| bytes.len()),
+                };
+                match &result {
+                    Ok(len) => control.request_finished(*len),
+                    Err(_) => control.request_failed(),
+                }
+                result
+            })
+        })
+        .buffered(parallel_downloads)
+        .map(|joined| joined.map_err(|source| object_store::Error::JoinError { source })?)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let elapsed_us = start.elapsed().as_micros();
+    let paused_us = control.paused().as_micros();
+
+    let total_size: usize = counts.iter().sum();
+    let mbps = mbps(total_size as u64, active_us(elapsed_us, paused_us));
+
+    let mut result = fields(serde_json::json!({
+        "mode": "replay",
+        "plan": plan_path.display().to_string(),
+        "num_objects": paths.len(),
+        "num_requests": counts.len(),
+        "parallel_downloads": parallel_downloads,
+        "bytes": total_size,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "mbps": mbps,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::experiment::capture;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    async fn dataset(base: &str, names: &[&str]) -> Arc<dyn ObjectStore> {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for name in names {
+            object_store
+                .put(
+                    &Path::from(format!("{}/{}", base, name)),
+                    Bytes::from(vec![7; 4096]),
+                )
+                .await
+                .unwrap();
+        }
+        object_store
+    }
+
+    fn plan_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "object-store-bench-plan-{}-{}.jsonl",
+            name,
+            std::process::id()
+        ))
+    }
+
+    #[tokio::test]
+    async fn a_recorded_plan_replays_against_another_location() {
+        let path = plan_path("replay");
+        let source = dataset("recorded", &["a", "b"]).await;
+        let control = RunControl::new();
+        control.set_plan_recorder(
+            PlanRecorder::create(&path, Path::from("recorded"), Some(2)).unwrap(),
+        );
+        parallel_download_bench(
+            source,
+            Path::from("recorded"),
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(1024),
+                ..DownloadOptions::default()
+            },
+            RetryPolicy::new(0, None),
+            control.clone(),
+        )
+        .await
+        .unwrap()
+        .into_result()
+        .unwrap();
+        control.flush_plan().unwrap();
+
+        let plan = load_plan(&path).unwrap();
+        assert_eq!(plan.parallel_downloads, Some(2));
+        assert_eq!(plan.requests.len(), 8);
+        // Paths are kept relative, so the plan isn't tied to its bucket.
+        let paths = plan
+            .requests
+            .iter()
+            .map(PlannedRequest::path)
+            .collect::<BTreeSet<_>>();
+        assert_eq!(paths, BTreeSet::from(["a", "b"]));
+
+        let target = dataset("copy", &["a", "b"]).await;
+        let (outcome, results) = capture(replay_plan(
+            target,
+            Path::from("copy"),
+            &path,
+            None,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["mode"], "replay");
+        assert_eq!(result["num_objects"], 2);
+        assert_eq!(result["num_requests"], 8);
+        assert_eq!(result["parallel_downloads"], 2);
+        assert_eq!(result["bytes"], 2 * 4096);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn replay_lists_every_missing_object_before_issuing_anything() {
+        let path = plan_path("missing");
+        std::fs::write(
+            &path,
+            concat!(
+                "{\"op\":\"header\",\"parallel_downloads\":1}\n",
+                "{\"seq\":0,\"op\":\"get\",\"path\":\"a\",\"range\":null}\n",
+                "{\"seq\":1,\"op\":\"get_range\",\"path\":\"b\",\"range\":[0,10]}\n",
+                "{\"seq\":2,\"op\":\"get_range\",\"path\":\"c\",\"range\":[0,10]}\n",
+            ),
+        )
+        .unwrap();
+        let target = dataset("copy", &["a"]).await;
+        let control = RunControl::new();
+        let (outcome, results) = capture(replay_plan(
+            target,
+            Path::from("copy"),
+            &path,
+            None,
+            RetryPolicy::new(0, None),
+            control.clone(),
+        ))
+        .await;
+        let err = outcome.unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "target copy is missing 2 of 3 objects referenced by the plan:\n  - b\n  - c"
+        );
+        assert!(results.is_empty());
+        assert_eq!(control.snapshot().bytes, 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn plans_with_unknown_ops_are_rejected_by_line() {
+        let path = plan_path("unknown");
+        std::fs::write(
+            &path,
+            "{\"op\":\"header\"}\n{\"op\":\"put\",\"path\":\"a\"}\n",
+        )
+        .unwrap();
+        let err = load_plan(&path).err().unwrap().to_string();
+        assert!(err.ends_with(":2: unknown op \"put\""), "{}", err);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
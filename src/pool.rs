@@ -0,0 +1,422 @@
+//! HTTP client settings, and sweeping the connection pool size.
+//!
+//! Against S3, GCS and HTTP stores the concurrency that matters is that of
+//! the client's connections, not of the benchmark's futures. With
+//! `--pool-max-idle-per-host N` or `--http2`, the store is built with its
+//! scheme's builder and those `ClientOptions` rather than with `parse_url`,
+//! and results record the settings under `client`.
+//!
+//! `--header name=value`, repeated, sends the headers with every request,
+//! such as the `x-amz-server-side-encryption-customer-*` keys an SSE-C bucket
+//! requires. Before the workload starts, a preflight lists the location and
+//! heads the first object found, so a store that rejects the headers fails
+//! the run up front. Results list the header names under `client.headers`;
+//! their values are never recorded.
+//!
+//! `--pool-sweep` runs `download` once per pool size, each with a new client,
+//! and ends with a summary of throughput and p99 per size. The number of
+//! connections used isn't visible through object_store, so it is
+//! inferred: an HTTP/1.1 request holds a connection to itself, so a run
+//! whose peak concurrency was C opened at least C, of which at most the pool
+//! size were kept idle for reuse. Once C exceeds the cap, connections are
+//! closed as requests finish and opened again for the next ones. Over HTTP/2
+//! every request shares one connection. The summary is also charted, as in
+//! [`crate::sparkline`].
+
+use std::future::Future;
+
+use futures::StreamExt;
+use http::{HeaderMap, HeaderName, HeaderValue};
+use object_store::aws::AmazonS3Builder;
+use object_store::gcp::GoogleCloudStorageBuilder;
+use object_store::http::HttpBuilder;
+use object_store::path::Path;
+use object_store::{ClientOptions, ObjectStore};
+use url::Url;
+
+use crate::context::RunContext;
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::Fields;
+use crate::retry::RetryPolicy;
+use crate::schedule::FreshClientStore;
+use crate::sparkline::{Sweep, SweepPoint};
+use crate::store_options::{self, Builder};
+
+/// Pool sizes swept by a bare `--pool-sweep`.
+pub const DEFAULT_SWEEP: &str = "1,2,4,8,16,32,64";
+
+/// The `ClientOptions` a store is built with.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientConfig {
+    pub pool_max_idle_per_host: Option<usize>,
+    pub http2: bool,
+    /// Sent with every request, as `(name, value)`
+    pub headers: Vec<(String, String)>,
+}
+
+impl ClientConfig {
+    fn options(&self, url: &Url) -> ClientOptions {
+        let mut options = ClientOptions::new().with_allow_http(url.scheme() == "http");
+        if let Some(max) = self.pool_max_idle_per_host {
+            options = options.with_pool_max_idle_per_host(max);
+        }
+        if self.http2 {
+            options = options.with_http2_only();
+        }
+        if !self.headers.is_empty() {
+            let mut headers = HeaderMap::new();
+            for (name, value) in &self.headers {
+                // Both were checked by `parse_header`.
+                let mut value = HeaderValue::from_str(value).unwrap();
+                value.set_sensitive(true);
+                headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value);
+            }
+            options = options.with_default_headers(headers);
+        }
+        options
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        let mut config = serde_json::json!({
+            "pool_max_idle_per_host": self.pool_max_idle_per_host,
+            "http2": self.http2,
+        });
+        if !self.headers.is_empty() {
+            let names = self
+                .headers
+                .iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            config["headers"] = serde_json::json!(names);
+        }
+        config
+    }
+}
+
+/// Parse a `--header name=value` argument.
+pub fn parse_header(arg: &str) -> Result<(String, String), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected name=value, got {:?}", arg))?;
+    let name = HeaderName::from_bytes(name.trim().as_bytes())
+        .map_err(|_| format!("{:?} is not a valid header name", name))?;
+    // The value may be a key, so it is left out of the error.
+    HeaderValue::from_str(value).map_err(|_| format!("the value of {} is not valid", name))?;
+    Ok((name.as_str().to_string(), value.to_string()))
+}
+
+/// Whether stores for `url` are built with client options.
+pub fn supported(url: &Url) -> bool {
+    matches!(url.scheme(), "s3" | "s3a" | "gs" | "http" | "https")
+}
+
+/// The store for `url`, built with `config` when it sets anything, and with
+/// the store `options`.
+pub fn build(
+    url: &Url,
+    config: &ClientConfig,
+    store_options: &[(String, String)],
+) -> object_store::Result<Box<dyn ObjectStore>> {
+    if *config == ClientConfig::default() {
+        return store_options::parse_url(url, store_options).map(|(store, _)| store);
+    }
+    let options = config.options(url);
+    let gcs_bucket = match store_options {
+        [] => None,
+        _ => store_options::gcs_bucket(url)?,
+    };
+    Ok(match (store_options::builder(url), url.scheme()) {
+        (Some(Builder::S3), _) => {
+            let builder = match store_options::global_s3_bucket(url) {
+                Some(bucket) => AmazonS3Builder::new().with_bucket_name(bucket),
+                None => AmazonS3Builder::new().with_url(url.as_str()),
+            };
+            let builder = builder.with_client_options(options);
+            Box::new(store_options::s3(builder, store_options)?.build()?)
+        }
+        (Some(Builder::Gcs), scheme) if scheme == "gs" || gcs_bucket.is_some() => {
+            let builder = match gcs_bucket {
+                Some((bucket, _)) => GoogleCloudStorageBuilder::new().with_bucket_name(bucket),
+                None => GoogleCloudStorageBuilder::new().with_url(url.as_str()),
+            };
+            let builder = builder.with_client_options(options);
+            Box::new(store_options::gcs(builder, store_options)?.build()?)
+        }
+        (_, "http" | "https") => Box::new(
+            HttpBuilder::new()
+                .with_url(&url[..url::Position::BeforePath])
+                .with_client_options(options)
+                .build()?,
+        ),
+        (_, scheme) => {
+            return Err(object_store::Error::Generic {
+                store: "client",
+                source: format!("{} stores take no client options", scheme).into(),
+            })
+        }
+    })
+}
+
+/// Check that `object_store` accepts the configured headers: list `location`
+/// and head the first object under it, if there is one.
+pub async fn preflight(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    retry: &RetryPolicy,
+) -> object_store::Result<()> {
+    let first = retry
+        .run(|| async {
+            object_store
+                .list(Some(location))
+                .await?
+                .next()
+                .await
+                .transpose()
+        })
+        .await?;
+    if let Some(meta) = first {
+        retry.run(|| object_store.head(&meta.location)).await?;
+    }
+    Ok(())
+}
+
+/// Record `config` as the settings of the runs of `context` that follow.
+pub fn begin(context: &RunContext, config: ClientConfig) {
+    *context.client.lock().unwrap() = Some(config);
+}
+
+/// Append the running client's settings `config` to the JSON object `result`.
+pub fn with_client(result: &mut Fields, config: &ClientConfig) {
+    result.insert("client".to_string(), config.to_json());
+}
+
+/// Connections a run with `peak_in_flight` requests at once plausibly used
+/// under `config`, as a JSON object.
+fn connections(peak_in_flight: u64, config: &ClientConfig) -> serde_json::Value {
+    if config.http2 {
+        return serde_json::json!({"concurrent": 1, "kept_idle": 1, "reconnecting": false});
+    }
+    let cap = config.pool_max_idle_per_host.unwrap_or(usize::MAX) as u64;
+    serde_json::json!({
+        "concurrent": peak_in_flight,
+        "kept_idle": peak_in_flight.min(cap),
+        "reconnecting": peak_in_flight > cap,
+    })
+}
+
+/// Run `run` once with a new client for each of `pool_sizes`, built into the
+/// slots of `store`, then emit a summary of the runs.
+pub async fn sweep<F, Fut>(
+    pool_sizes: &[usize],
+    http2: bool,
+    headers: &[(String, String)],
+    store: &FreshClientStore,
+    control: &RunControl,
+    mut run: F,
+) -> Result<(), Error>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let mut settings = Vec::new();
+    for (slot, pool_size) in pool_sizes.iter().enumerate() {
+        if control.is_shutdown() {
+            break;
+        }
+        let config = ClientConfig {
+            pool_max_idle_per_host: Some(*pool_size),
+            http2,
+            headers: headers.to_vec(),
+        };
+        store.renew(slot)?;
+        begin(control.context(), config.clone());
+        control.reset_paused();
+        control.take_peak_in_flight();
+        run().await;
+        let result = control
+            .take_last_result()
+            .ok_or("the benchmark did not report a result")?;
+        let result: serde_json::Value = serde_json::from_str(&result)?;
+        let peak_in_flight = control.take_peak_in_flight();
+        settings.push(serde_json::json!({
+            "pool_max_idle_per_host": pool_size,
+            "mbps": result["mbps"],
+            "p99_us": result["latency"]["p99_us"],
+            "peak_in_flight": peak_in_flight,
+            "connections": connections(peak_in_flight, &config),
+        }));
+    }
+    *control.context().client.lock().unwrap() = None;
+    let chart = Sweep {
+        parameter: "pool_max_idle_per_host".to_string(),
+        points: settings
+            .iter()
+            .map(|setting| SweepPoint {
+                value: setting["pool_max_idle_per_host"].to_string(),
+                mbps: setting["mbps"].as_f64(),
+                p99_us: setting["p99_us"].as_f64(),
+            })
+            .collect(),
+    };
+    let best = chart
+        .best()
+        .map(|i| settings[i]["pool_max_idle_per_host"].clone());
+    control.emit(serde_json::json!({
+        "mode": "pool_sweep",
+        "http2": http2,
+        "settings": settings,
+        "best_pool_max_idle_per_host": best,
+        "interrupted": control.is_shutdown(),
+    }));
+    crate::sparkline::show(&chart, control.context().sparkline);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::experiment::capture;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use crate::report::Checked;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn connections_past_the_cap_are_reopened() {
+        let capped = ClientConfig {
+            pool_max_idle_per_host: Some(4),
+            ..ClientConfig::default()
+        };
+        assert_eq!(
+            connections(10, &capped),
+            serde_json::json!({"concurrent": 10, "kept_idle": 4, "reconnecting": true})
+        );
+        assert_eq!(connections(3, &capped)["reconnecting"], false);
+        let multiplexed = ClientConfig {
+            http2: true,
+            ..capped
+        };
+        assert_eq!(connections(10, &multiplexed)["concurrent"], 1);
+    }
+
+    #[test]
+    fn only_remote_stores_take_client_options() {
+        let config = ClientConfig {
+            pool_max_idle_per_host: Some(2),
+            http2: true,
+            headers: vec![
+                parse_header("x-amz-server-side-encryption-customer-key=c2VjcmV0").unwrap(),
+            ],
+        };
+        let url = Url::parse("https://example.com/data").unwrap();
+        assert!(supported(&url));
+        assert!(build(&url, &config, &[]).is_ok());
+        let url = Url::parse("file:///tmp/data").unwrap();
+        assert!(!supported(&url));
+        assert!(build(&url, &config, &[]).is_err());
+        assert!(build(&url, &ClientConfig::default(), &[]).is_ok());
+    }
+
+    #[test]
+    fn header_values_are_left_out_of_results() {
+        assert_eq!(
+            parse_header("X-Custom = a=b").unwrap(),
+            ("x-custom".to_string(), " a=b".to_string())
+        );
+        assert!(parse_header("x-custom").is_err());
+        assert!(parse_header("bad name=1").is_err());
+        let err = parse_header("x-key=sec\nret").unwrap_err();
+        assert!(!err.contains("sec"), "{}", err);
+
+        let config = ClientConfig {
+            headers: vec![("x-key".to_string(), "secret".to_string())],
+            ..ClientConfig::default()
+        };
+        let json = config.to_json();
+        assert_eq!(json["headers"], serde_json::json!(["x-key"]));
+        assert!(!json.to_string().contains("secret"));
+        assert!(ClientConfig::default().to_json().get("headers").is_none());
+    }
+
+    #[tokio::test]
+    async fn preflight_heads_the_first_object() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let retry = RetryPolicy::new(0, None);
+        // An empty location is only listed.
+        preflight(inner.as_ref(), &Path::from("data"), &retry)
+            .await
+            .unwrap();
+        inner
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 16]))
+            .await
+            .unwrap();
+        let faults = [Fault::NotFound("data/a".to_string())];
+        let store = FaultStore::new(inner.clone(), FaultConfig::new(&faults), 0);
+        assert!(preflight(&store, &Path::from("data"), &retry)
+            .await
+            .is_err());
+        preflight(inner.as_ref(), &Path::from("data"), &retry)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn sweeps_each_pool_size_with_its_own_client() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        inner
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 1 << 16]))
+            .await
+            .unwrap();
+        let built = Arc::new(Mutex::new(Vec::new()));
+        let make = {
+            let (inner, built) = (inner.clone(), built.clone());
+            move |slot| {
+                built.lock().unwrap().push(slot);
+                Ok(inner.clone())
+            }
+        };
+        let store = Arc::new(FreshClientStore::new(Box::new(make), 3).unwrap());
+        let control = RunControl::new();
+        let run = || {
+            let (store, control) = (store.clone(), control.clone());
+            async move {
+                let result = parallel_download_bench(
+                    store,
+                    Path::from("data"),
+                    DownloadOptions {
+                        parallel_downloads: 4,
+                        block_size: Some(1 << 12),
+                        ..DownloadOptions::default()
+                    },
+                    RetryPolicy::new(0, None),
+                    control.clone(),
+                )
+                .await
+                .and_then(Checked::into_result)
+                .unwrap();
+                // The driver reads the result each run emits, as the binary does.
+                control.emit(serde_json::to_value(result).unwrap());
+            }
+        };
+        let (outcome, results) =
+            capture(sweep(&[1, 2, 8], false, &[], store.as_ref(), &control, run)).await;
+        outcome.unwrap();
+
+        assert_eq!(*built.lock().unwrap(), [0, 1, 2]);
+        assert_eq!(results.len(), 4);
+        let first: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(first["client"]["pool_max_idle_per_host"], 1);
+        let summary: serde_json::Value = serde_json::from_str(&results[3]).unwrap();
+        assert_eq!(summary["mode"], "pool_sweep");
+        let settings = summary["settings"].as_array().unwrap();
+        assert_eq!(settings.len(), 3);
+        assert_eq!(settings[2]["pool_max_idle_per_host"], 8);
+        assert_eq!(settings[0]["peak_in_flight"], 4);
+        assert_eq!(settings[0]["connections"]["reconnecting"], true);
+        assert_eq!(settings[2]["connections"]["reconnecting"], false);
+    }
+}
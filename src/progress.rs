@@ -0,0 +1,234 @@
+//! Machine-readable progress events for orchestration.
+//!
+//! With `--progress-format jsonl`, a background task writes one JSON object
+//! per line: a `start` event, a `progress` event every interval, and a
+//! `finish` event once the benchmark returns. Each event is formatted in full
+//! and written with a single call, so lines never interleave partially. The
+//! events are read from the run's atomic counters; nothing here takes a lock
+//! the request path waits on.
+//!
+//! For people watching a terminal, [`ProgressReporter::bar`] instead redraws
+//! one line on stderr: a bar of the bytes done out of those planned, the
+//! MB/s over the last couple of seconds and the time left at that rate. It
+//! is only drawn when stderr is a terminal, so piped and CI runs are
+//! unaffected, and stdout is never touched.
+
+use std::collections::VecDeque;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+use crate::control::RunControl;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum ProgressFormat {
+    Jsonl,
+}
+
+/// Width of the progress bar, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Redraws between which the bar's rate is measured.
+const RATE_TICKS: usize = 10;
+
+/// Whether a progress bar is drawn on the current line of stderr.
+static BAR_DRAWN: Mutex<bool> = Mutex::new(false);
+
+/// Handle to the background progress task.
+pub struct ProgressReporter {
+    stop: Arc<Notify>,
+    handle: JoinHandle<()>,
+}
+
+impl ProgressReporter {
+    /// Start emitting events for `control` to `writer` every `interval`.
+    pub fn spawn(
+        control: RunControl,
+        interval: Duration,
+        mut writer: Box<dyn Write + Send>,
+    ) -> Self {
+        let stop = Arc::new(Notify::new());
+        let stopped = stop.clone();
+        let handle = tokio::spawn(async move {
+            write_event(&mut writer, "start", &control);
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately; skip it so the first
+            // progress event comes one interval after start.
+            ticker.tick().await;
+            loop {
+                tokio::select! {
+                    _ = ticker.tick() => write_event(&mut writer, "progress", &control),
+                    _ = stopped.notified() => break,
+                }
+            }
+            write_event(&mut writer, "finish", &control);
+        });
+        Self { stop, handle }
+    }
+
+    /// Start redrawing a progress bar for `control` on stderr every
+    /// `interval`, or return `None` if stderr isn't a terminal.
+    pub fn bar(control: RunControl, interval: Duration) -> Option<Self> {
+        if !std::io::stderr().is_terminal() {
+            return None;
+        }
+        let stop = Arc::new(Notify::new());
+        let stopped = stop.clone();
+        let handle = tokio::spawn(async move {
+            let mut recent = VecDeque::new();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                let stop = tokio::select! {
+                    _ = ticker.tick() => false,
+                    _ = stopped.notified() => true,
+                };
+                let snapshot = control.snapshot();
+                recent.push_back((snapshot.elapsed, snapshot.bytes));
+                if recent.len() > RATE_TICKS {
+                    recent.pop_front();
+                }
+                let line = bar_line(snapshot.bytes, control.bytes_total(), rate(&recent));
+                let mut drawn = BAR_DRAWN.lock().unwrap();
+                // Pad over whatever a longer previous line left behind.
+                eprint!("\r{:<80}", line);
+                *drawn = !stop;
+                if stop {
+                    eprintln!();
+                    break;
+                }
+            }
+        });
+        Some(Self { stop, handle })
+    }
+
+    /// Emit the `finish` event and wait for the task to exit.
+    pub async fn finish(self) {
+        self.stop.notify_one();
+        let _ = self.handle.await;
+    }
+}
+
+/// Run `print` with any progress bar cleared from the terminal first, so a
+/// result printed to the same terminal starts on a line of its own. The bar
+/// is redrawn below it on its next tick.
+pub fn above_bar<T>(print: impl FnOnce() -> T) -> T {
+    let mut drawn = BAR_DRAWN.lock().unwrap();
+    if *drawn {
+        eprint!("\r{:<80}\r", "");
+        *drawn = false;
+    }
+    print()
+}
+
+/// Bytes per second between the oldest and newest of `recent` samples.
+fn rate(recent: &VecDeque<(Duration, u64)>) -> Option<f64> {
+    let (first, last) = (recent.front()?, recent.back()?);
+    let secs = (last.0 - first.0).as_secs_f64();
+    (secs > 0.0).then(|| (last.1 - first.1) as f64 / secs)
+}
+
+/// One line of the progress bar, as `[=====>    ]  50%  5.0 / 10.0 MiB
+/// 2.5 MB/s  ETA 2s`. Without a planned total the bar, percentage and ETA
+/// are left out.
+fn bar_line(done: u64, total: Option<u64>, bytes_per_sec: Option<f64>) -> String {
+    const MIB: f64 = 1024.0 * 1024.0;
+    let mbps = bytes_per_sec.map_or("-".to_string(), |rate| format!("{:.1}", rate / MIB));
+    let Some(total) = total.filter(|&total| total > 0) else {
+        return format!("{:.1} MiB  {} MB/s", done as f64 / MIB, mbps);
+    };
+    let fraction = (done as f64 / total as f64).min(1.0);
+    let filled = (fraction * BAR_WIDTH as f64) as usize;
+    let bar = match filled {
+        BAR_WIDTH => "=".repeat(BAR_WIDTH),
+        _ => format!(
+            "{}>{}",
+            "=".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled - 1)
+        ),
+    };
+    let eta = match bytes_per_sec {
+        Some(rate) if rate > 0.0 => {
+            format!(
+                "{}s",
+                (total.saturating_sub(done) as f64 / rate).ceil() as u64
+            )
+        }
+        _ => "-".to_string(),
+    };
+    format!(
+        "[{}] {:>3}%  {:.1} / {:.1} MiB  {} MB/s  ETA {}",
+        bar,
+        (fraction * 100.0) as u64,
+        done as f64 / MIB,
+        total as f64 / MIB,
+        mbps,
+        eta
+    )
+}
+
+fn write_event(writer: &mut Box<dyn Write + Send>, event: &str, control: &RunControl) {
+    let snapshot = control.snapshot();
+    let line = format!(
+        "{}\n",
+        serde_json::json!({
+            "event": event,
+            "elapsed_ms": snapshot.elapsed.as_millis() as u64,
+            "bytes_done": snapshot.bytes,
+            "bytes_total": control.bytes_total(),
+            "requests_done": snapshot.requests,
+            "errors": snapshot.errors,
+        })
+    );
+    // Progress is best effort; a closed pipe must not affect the benchmark.
+    let _ = writer.write_all(line.as_bytes());
+    let _ = writer.flush();
+}
+
+/// Where progress events go: stderr, or an already-open file descriptor.
+pub fn progress_writer(fd: Option<i32>) -> Box<dyn Write + Send> {
+    match fd {
+        #[cfg(unix)]
+        Some(fd) => {
+            use std::os::unix::io::FromRawFd;
+            // Safety: the caller passed us this descriptor to write to and we
+            // are its only user for the life of the process.
+            Box::new(unsafe { std::fs::File::from_raw_fd(fd) })
+        }
+        #[cfg(not(unix))]
+        Some(_) => {
+            eprintln!("--progress-fd is only supported on unix; using stderr");
+            Box::new(std::io::stderr())
+        }
+        None => Box::new(std::io::stderr()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MIB: u64 = 1 << 20;
+
+    #[test]
+    fn bars_show_the_share_done_rate_and_time_left() {
+        assert_eq!(
+            bar_line(5 * MIB, Some(10 * MIB), Some(2.5 * MIB as f64)),
+            format!(
+                "[{}>{}]  50%  5.0 / 10.0 MiB  2.5 MB/s  ETA 2s",
+                "=".repeat(15),
+                " ".repeat(14)
+            )
+        );
+        assert!(bar_line(10 * MIB, Some(10 * MIB), None)
+            .starts_with(&format!("[{}] 100%", "=".repeat(BAR_WIDTH))));
+        assert_eq!(bar_line(3 * MIB, None, None), "3.0 MiB  - MB/s");
+        let recent = VecDeque::from([
+            (Duration::from_secs(1), MIB),
+            (Duration::from_secs(3), 5 * MIB),
+        ]);
+        assert_eq!(rate(&recent), Some(2.0 * MIB as f64));
+    }
+}
@@ -0,0 +1,470 @@
+//! The read sequence of an analytical query, timed stage by stage.
+//!
+//! `query-sim` lists the location, reads the footer at the end of every
+//! file, prunes groups by a seeded selectivity, then scans the projected
+//! columns of the groups that survive. Each file is laid out as the columnar
+//! benchmark lays it out, with the footer taking its last `--footer-size`
+//! bytes. Reads of one file's surviving pages are coalesced when the gap
+//! between them is at most `--coalesce-gap`, and at most `--parallel`
+//! requests are in flight at once in every stage. With `--decode-mbps`, each
+//! scanned read holds its slot for as long as decoding its bytes at that rate
+//! would take, as a reader that decodes before issuing more would.
+//!
+//! The result breaks the run down into `list`, `metadata`, `prune` and `scan`
+//! stages, each with its elapsed time, requests and bytes, and gives the
+//! end-to-end time to scan the table.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::columnar::Layout;
+use crate::control::RunControl;
+use crate::download::to_usize_range;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::retry::RetryPolicy;
+use crate::stats::{mbps, LatencySummary};
+
+/// Parameters for [`query_sim`].
+#[derive(Debug, Clone)]
+pub struct QuerySimOptions {
+    /// Requests in flight at once, in each stage
+    pub parallel: usize,
+    pub page_sizes: Vec<usize>,
+    /// Columns the query reads; all of them when empty
+    pub columns: Vec<usize>,
+    /// Bytes at the end of each file holding its footer
+    pub footer_size: usize,
+    /// Fraction of groups that survive pruning
+    pub selectivity: f64,
+    pub seed: u64,
+    /// Largest gap between two pages of a file read in one request
+    pub coalesce_gap: usize,
+    /// Rate at which scanned bytes are decoded, if decoding is simulated
+    pub decode_mbps: Option<f64>,
+}
+
+/// One stage's share of the run.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Stage {
+    pub elapsed: Duration,
+    pub requests: usize,
+    pub bytes: u64,
+}
+
+impl Stage {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "elapsed_us": self.elapsed.as_micros() as u64,
+            "requests": self.requests,
+            "bytes": self.bytes,
+        })
+    }
+}
+
+/// The groups of one file that survived pruning, and the reads scanning
+/// their projected pages.
+#[derive(Debug, Clone, PartialEq)]
+struct FileScan {
+    location: Path,
+    groups_total: usize,
+    groups_kept: usize,
+    /// Bytes of the projected pages in the kept groups
+    page_bytes: u64,
+    reads: Vec<Range<u64>>,
+}
+
+/// Which groups of a file survive pruning, drawn from `rng`, and the
+/// coalesced reads of their `columns`.
+fn plan_scan(
+    location: Path,
+    layout: &Layout,
+    columns: &[usize],
+    selectivity: f64,
+    coalesce_gap: usize,
+    rng: &mut StdRng,
+) -> FileScan {
+    let kept = (0..layout.num_groups)
+        .filter(|_| rng.gen_bool(selectivity))
+        .collect::<Vec<_>>();
+    let mut pages = kept
+        .iter()
+        .flat_map(|&group_i| {
+            columns
+                .iter()
+                .map(move |&column_i| layout.page(column_i, group_i))
+        })
+        .collect::<Vec<_>>();
+    pages.sort_by_key(|page| page.start);
+    let page_bytes = pages.iter().map(|page| page.end - page.start).sum();
+    FileScan {
+        location,
+        groups_total: layout.num_groups,
+        groups_kept: kept.len(),
+        page_bytes,
+        reads: coalesce(pages, coalesce_gap as u64),
+    }
+}
+
+/// Merge sorted ranges separated by at most `gap` bytes.
+pub fn coalesce(ranges: Vec<Range<u64>>, gap: u64) -> Vec<Range<u64>> {
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end.saturating_add(gap) => {
+                last.end = last.end.max(range.end);
+            }
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Issue one ranged read, counting it against `control`.
+pub async fn read_range(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    range: Range<usize>,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Option<(usize, Duration)>> {
+    if !control.request_started().await {
+        return Ok(None);
+    }
+    control.record("get_range", location, Some(&range));
+    let issued = Instant::now();
+    let result = retry
+        .run(|| object_store.get_range(location, range.clone()))
+        .await
+        .map(|bytes| bytes.len());
+    match &result {
+        Ok(len) => control.request_finished(*len),
+        Err(_) => control.request_failed(),
+    }
+    result.map(|len| Some((len, issued.elapsed())))
+}
+
+pub async fn query_sim(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: QuerySimOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let result = simulate(object_store, location, options, retry, control.clone()).await?;
+    control.emit(result);
+    Ok(())
+}
+
+/// Run the query, returning its result.
+async fn simulate(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: QuerySimOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<serde_json::Value, Error> {
+    let QuerySimOptions {
+        parallel,
+        page_sizes,
+        columns,
+        footer_size,
+        selectivity,
+        seed,
+        coalesce_gap,
+        decode_mbps,
+    } = options;
+    if !(0.0..=1.0).contains(&selectivity) {
+        return Err(format!("selectivity {} is not between 0 and 1", selectivity).into());
+    }
+    let columns = match columns.is_empty() {
+        true => (0..page_sizes.len()).collect(),
+        false => columns,
+    };
+    if let Some(column_i) = columns
+        .iter()
+        .find(|&&column_i| column_i >= page_sizes.len())
+    {
+        return Err(format!(
+            "columns lists column {} but there are only {} columns",
+            column_i,
+            page_sizes.len()
+        )
+        .into());
+    }
+
+    let run_start = Instant::now();
+    if !control.request_started().await {
+        return Err("interrupted before listing".into());
+    }
+    control.record("list", &location, None);
+    let listed = retry
+        .run(|| async {
+            object_store
+                .list(Some(&location))
+                .await?
+                .try_collect::<Vec<ObjectMeta>>()
+                .await
+        })
+        .await;
+    match &listed {
+        Ok(_) => control.request_finished(0),
+        Err(_) => control.request_failed(),
+    }
+    let listed = listed?;
+    let list = Stage {
+        elapsed: run_start.elapsed(),
+        requests: 1,
+        bytes: 0,
+    };
+
+    // Zero-byte markers such as `_SUCCESS` are not part of the table.
+    let (empty, mut files): (Vec<_>, Vec<_>) = listed.into_iter().partition(|o| o.size == 0);
+    if files.is_empty() {
+        return Err(format!("no non-empty objects under {}", location).into());
+    }
+    files.sort_by(|a, b| a.location.cmp(&b.location));
+    let layouts = files
+        .iter()
+        .map(|meta| {
+            let data_size = meta.size.saturating_sub(footer_size) as u64;
+            let layout = Layout::plan(&page_sizes, data_size, 1, 0);
+            match layout.num_groups {
+                0 => Err(format!(
+                    "{} is {} bytes, too small for a {} byte footer and one group of pages {:?}",
+                    meta.location, meta.size, footer_size, page_sizes
+                )),
+                _ => Ok(layout),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Footers
+    let metadata_start = Instant::now();
+    let footers = futures::stream::iter(&files)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let range = meta.size.saturating_sub(footer_size)..meta.size;
+            read_range(
+                object_store.as_ref(),
+                &meta.location,
+                range,
+                &retry,
+                &control,
+            )
+        })
+        .buffer_unordered(parallel)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let footers = footers.into_iter().flatten().collect::<Vec<_>>();
+    let metadata = Stage {
+        elapsed: metadata_start.elapsed(),
+        requests: footers.len(),
+        bytes: footers.iter().map(|(len, _)| *len as u64).sum(),
+    };
+
+    // Pruning
+    let prune_start = Instant::now();
+    let mut rng = StdRng::seed_from_u64(seed);
+    let plans = files
+        .iter()
+        .zip(&layouts)
+        .map(|(meta, layout)| {
+            plan_scan(
+                meta.location.clone(),
+                layout,
+                &columns,
+                selectivity,
+                coalesce_gap,
+                &mut rng,
+            )
+        })
+        .collect::<Vec<_>>();
+    let prune = Stage {
+        elapsed: prune_start.elapsed(),
+        ..Stage::default()
+    };
+    let groups_total = plans.iter().map(|plan| plan.groups_total).sum::<usize>();
+    let groups_kept = plans.iter().map(|plan| plan.groups_kept).sum::<usize>();
+    let page_bytes = plans.iter().map(|plan| plan.page_bytes).sum::<u64>();
+    control.set_bytes_total(
+        plans
+            .iter()
+            .flat_map(|plan| &plan.reads)
+            .map(|read| read.end - read.start)
+            .sum(),
+    );
+
+    // Scan
+    let scan_start = Instant::now();
+    let decode_us = std::sync::atomic::AtomicU64::new(0);
+    let reads = plans.iter().flat_map(|plan| {
+        plan.reads
+            .iter()
+            // The reads lie within the file, whose size is a usize.
+            .map(move |read| (&plan.location, to_usize_range(read.clone()).unwrap()))
+    });
+    let scanned = futures::stream::iter(reads)
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|(location, range)| {
+            let object_store = object_store.clone();
+            let (retry, control, decode_us) = (&retry, &control, &decode_us);
+            async move {
+                let read =
+                    read_range(object_store.as_ref(), location, range, retry, control).await?;
+                if let (Some((len, _)), Some(decode_mbps)) = (read, decode_mbps) {
+                    let decode =
+                        Duration::from_secs_f64(len as f64 / (decode_mbps * 1024.0 * 1024.0));
+                    tokio::time::sleep(decode).await;
+                    decode_us.fetch_add(
+                        decode.as_micros() as u64,
+                        std::sync::atomic::Ordering::Relaxed,
+                    );
+                }
+                Ok::<_, object_store::Error>(read)
+            }
+        })
+        .buffer_unordered(parallel)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let mut scan_latencies = scanned
+        .iter()
+        .flatten()
+        .map(|(_, latency)| *latency)
+        .collect::<Vec<_>>();
+    let scan = Stage {
+        elapsed: scan_start.elapsed(),
+        requests: scan_latencies.len(),
+        bytes: scanned.iter().flatten().map(|(len, _)| *len as u64).sum(),
+    };
+    let elapsed = run_start.elapsed().saturating_sub(control.paused());
+    let mut footer_latencies = footers
+        .iter()
+        .map(|(_, latency)| *latency)
+        .collect::<Vec<_>>();
+
+    let mut result = fields(serde_json::json!({
+        "mode": "query_sim",
+        "num_files": files.len(),
+        "zero_byte_objects": empty.len(),
+        "page_sizes": page_sizes,
+        "columns": columns,
+        "footer_size": footer_size,
+        "selectivity": selectivity,
+        "seed": seed,
+        "coalesce_gap": coalesce_gap,
+        "decode_mbps": decode_mbps,
+        "parallel": parallel,
+        "groups_total": groups_total,
+        "groups_kept": groups_kept,
+        "page_bytes": page_bytes,
+        // Bytes between projected pages fetched because reads coalesced
+        "wasted_bytes": scan.bytes.saturating_sub(page_bytes),
+        "decode_us": decode_us.into_inner(),
+        "stages": {
+            "list": list.to_json(),
+            "metadata": metadata.to_json(),
+            "prune": prune.to_json(),
+            "scan": scan.to_json(),
+        },
+        "footer_latency": LatencySummary::from_latencies(&mut footer_latencies).to_json(),
+        "scan_latency": LatencySummary::from_latencies(&mut scan_latencies).to_json(),
+        "elapsed_us": elapsed.as_micros() as u64,
+        "paused_us": control.paused().as_micros() as u64,
+        "mbps": mbps(scan.bytes, elapsed.as_micros()),
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    Ok(result.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn nearby_pages_coalesce() {
+        assert_eq!(
+            coalesce(vec![0..10, 10..20, 25..30, 100..110], 5),
+            vec![0..30, 100..110]
+        );
+        assert_eq!(coalesce(vec![0..10, 11..20], 0), vec![0..10, 11..20]);
+        assert_eq!(coalesce(Vec::new(), 5), Vec::<Range<u64>>::new());
+    }
+
+    #[test]
+    fn pruning_is_seeded() {
+        let layout = Layout::plan(&[100, 200, 300], 600 * 50, 1, 0);
+        let plan = |seed, selectivity| {
+            plan_scan(
+                Path::from("f"),
+                &layout,
+                &[1],
+                selectivity,
+                0,
+                &mut StdRng::seed_from_u64(seed),
+            )
+        };
+        assert_eq!(plan(7, 0.3), plan(7, 0.3));
+        assert_eq!(plan(7, 1.0).groups_kept, 50);
+        assert_eq!(plan(7, 0.0).reads, Vec::<Range<u64>>::new());
+        let kept = plan(7, 0.3);
+        assert!(kept.groups_kept > 0 && kept.groups_kept < 50);
+        // Columns 2 and 0 separate every column 1 page, so nothing coalesces.
+        assert_eq!(kept.reads.len(), kept.groups_kept);
+        assert_eq!(kept.page_bytes, 200 * kept.groups_kept as u64);
+    }
+
+    #[tokio::test]
+    async fn stages_add_up() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for name in ["t/a.bin", "t/b.bin"] {
+            store
+                .put(&Path::from(name), Bytes::from(vec![1; 10 * 300 + 64]))
+                .await
+                .unwrap();
+        }
+        store
+            .put(&Path::from("t/_SUCCESS"), Bytes::new())
+            .await
+            .unwrap();
+        let options = QuerySimOptions {
+            parallel: 4,
+            page_sizes: vec![100, 200],
+            columns: vec![1],
+            footer_size: 64,
+            selectivity: 1.0,
+            seed: 1,
+            coalesce_gap: 100,
+            decode_mbps: None,
+        };
+        let result = simulate(
+            store,
+            Path::from("t"),
+            options,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result["num_files"], 2);
+        assert_eq!(result["zero_byte_objects"], 1);
+        assert_eq!(result["groups_total"], 20);
+        assert_eq!(result["stages"]["list"]["requests"], 1);
+        assert_eq!(result["stages"]["metadata"]["requests"], 2);
+        assert_eq!(result["stages"]["metadata"]["bytes"], 128);
+        // Every group's column 1 page, 100 bytes apart, in one read per file.
+        assert_eq!(result["stages"]["scan"]["requests"], 2);
+        assert_eq!(result["page_bytes"], 2 * 10 * 200);
+        assert_eq!(result["wasted_bytes"], 2 * 9 * 100);
+        assert_eq!(result["max_retries"], 0);
+    }
+}
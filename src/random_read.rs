@@ -0,0 +1,637 @@
+//! Point lookups: ranged reads at random offsets.
+//!
+//! `random-read` issues `--num-requests` reads of `--request-size` bytes,
+//! each from an object picked from those under the location by the
+//! `--selection` strategy, uniformly by default, at an offset that keeps the
+//! read within the object, see [`crate::selection`]. Objects smaller than the
+//! request size are read whole. Every read is planned up front from `--seed`,
+//! so two stores given the same seed and objects serve the same sequence of
+//! ranges whatever the concurrency; the spec and its seed are recorded in the
+//! results under `selection`. `--workload-recipe` gives all of that in one
+//! string instead, see [`crate::recipe`]. Reads are issued in plan order,
+//! `--parallel` at a time. With `--duration`, reads are drawn from the same
+//! sequence for as long as the run lasts instead, see [`crate::duration`].
+//! With `--request-deadline-ms`, a read still running at the deadline is
+//! dropped and counted as expired, see [`crate::deadline`]; expired reads
+//! count towards neither the latency nor the throughput.
+//!
+//! A failed read ends the run unless `--continue-on-error`, or retries, let
+//! the run go on without it, as in `download`; the run then fails once the
+//! results are reported. `--missing-objects` decides what a read of an
+//! object deleted since the listing does, see [`crate::missing`].
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectMeta, ObjectStore};
+
+use crate::control::RunControl;
+use crate::deadline::{DeadlineSpec, Deadlines, Fetched};
+use crate::duration::MeasurementWindow;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::missing::MissingTracker;
+use crate::recipe::Recipe;
+use crate::retry::RetryPolicy;
+use crate::selection::SelectionSpec;
+use crate::stats::{active_us, mbps, Histogram};
+
+/// Parameters for [`random_read_bench`].
+#[derive(Debug, Clone)]
+pub struct RandomReadOptions {
+    pub num_requests: usize,
+    pub request_size: usize,
+    pub parallel: usize,
+    /// Which objects are read, and where, drawn from the spec's seed
+    pub selection: SelectionSpec,
+    /// Read for this long, ignoring `num_requests`
+    pub duration: Option<Duration>,
+    /// Drop reads still running at the deadline and count them as expired
+    pub deadline: Option<DeadlineSpec>,
+    /// The workload recipe the run follows, which also picks the objects
+    /// read and is echoed in the results, see [`crate::recipe`]
+    pub recipe: Option<Recipe>,
+}
+
+/// A read of a run, as the index of its object and the range read.
+pub type PlannedRead = (usize, Range<usize>);
+
+/// The reads of a run: `num_requests` ranges of `request_size` bytes over
+/// `objects`, picked as `selection` says.
+pub fn plan(
+    objects: &[ObjectMeta],
+    num_requests: usize,
+    request_size: usize,
+    selection: &SelectionSpec,
+) -> Result<Vec<PlannedRead>, Error> {
+    Ok(reads(objects, request_size, selection)?
+        .take(num_requests)
+        .collect())
+}
+
+/// Endless reads of `request_size` bytes over `objects`, the first of which
+/// are those [`plan`] returns for the same spec.
+pub fn reads<'a>(
+    objects: &'a [ObjectMeta],
+    request_size: usize,
+    selection: &SelectionSpec,
+) -> Result<impl Iterator<Item = PlannedRead> + Send + 'a, Error> {
+    let mut selector = selection.selector(objects)?;
+    Ok(std::iter::repeat_with(move || {
+        let object_i = selector.next_object();
+        let size = objects[object_i].size;
+        let offset = selector.next_offset(size, request_size);
+        (object_i, offset..offset + request_size.min(size))
+    }))
+}
+
+/// Issue one read, under `deadlines` if there are any, counting it against
+/// `control`. `None` means the run stopped before it was issued.
+async fn read(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    range: Range<usize>,
+    deadlines: Option<&Deadlines>,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> object_store::Result<Option<(Fetched, Duration)>> {
+    if !control.request_started().await {
+        return Ok(None);
+    }
+    control.record("get_range", location, Some(&range));
+    let issued = Instant::now();
+    let result = match deadlines {
+        Some(deadlines) => {
+            deadlines
+                .fetch(object_store, location, Some(range), retry)
+                .await
+        }
+        None => retry
+            .run(|| object_store.get_range(location, range.clone()))
+            .await
+            .map(|bytes| Fetched::Met(bytes.len())),
+    };
+    match &result {
+        Ok(Fetched::Met(len)) => control.request_finished(*len),
+        Ok(Fetched::Expired) => control.request_expired(),
+        Err(_) => control.request_failed(),
+    }
+    result.map(|fetched| Some((fetched, issued.elapsed())))
+}
+
+/// The objects under `location` a run of `options` reads.
+async fn planned_objects(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    options: &RandomReadOptions,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> Result<Vec<ObjectMeta>, Error> {
+    if options.request_size == 0 {
+        return Err("--request-size must be at least one byte".into());
+    }
+    let objects = inspect_location(object_store, location, control.listing(), retry).await?;
+    let objects = match &options.recipe {
+        Some(recipe) => recipe.objects(objects),
+        // Zero-byte markers such as `_SUCCESS` have nothing to read.
+        None => objects.into_iter().filter(|meta| meta.size > 0).collect(),
+    };
+    if objects.is_empty() {
+        return Err(format!("every object under {} is empty", location).into());
+    }
+    Ok(objects)
+}
+
+/// Print the first `count` reads a run of `options` would issue, one per
+/// line, without issuing any.
+pub async fn print_workload(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: RandomReadOptions,
+    count: usize,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        planned_objects(object_store.as_ref(), &location, &options, &retry, &control).await?;
+    let count = match options.duration {
+        Some(_) => count,
+        None => count.min(options.num_requests),
+    };
+    println!("read\tobject\toffset\tlen");
+    for (read_i, (object_i, range)) in reads(&objects, options.request_size, &options.selection)?
+        .take(count)
+        .enumerate()
+    {
+        println!(
+            "{}\t{}\t{}\t{}",
+            read_i,
+            objects[object_i].location,
+            range.start,
+            range.len()
+        );
+    }
+    Ok(())
+}
+
+pub async fn random_read_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: RandomReadOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        planned_objects(object_store.as_ref(), &location, &options, &retry, &control).await?;
+    let RandomReadOptions {
+        num_requests,
+        request_size,
+        parallel,
+        selection,
+        duration,
+        deadline,
+        recipe,
+    } = options;
+    let measurement = duration.map(|requested| MeasurementWindow { requested });
+    let reads: Box<dyn Iterator<Item = PlannedRead> + Send + '_> = match measurement {
+        Some(_) => Box::new(reads(&objects, request_size, &selection)?),
+        None => {
+            let reads = plan(&objects, num_requests, request_size, &selection)?;
+            control.set_bytes_total(reads.iter().map(|(_, range)| range.len() as u64).sum());
+            Box::new(reads.into_iter())
+        }
+    };
+
+    let deadlines = deadline.map(Deadlines::new);
+    let deadlines = deadlines.as_ref();
+    let tracker = MissingTracker::new(
+        object_store.clone(),
+        &location,
+        &objects,
+        control.listing(),
+        &retry,
+    );
+    let tracker = &tracker;
+    let start = Instant::now();
+    let completed = futures::stream::iter(reads)
+        .take_while(|_| {
+            let open = measurement
+                .is_none_or(|measurement| measurement.is_open(start.elapsed(), control.paused()));
+            futures::future::ready(open && !control.is_shutdown())
+        })
+        .map(|(object_i, range)| {
+            let (object_store, retry, control) = (&object_store, &retry, &control);
+            let location = &objects[object_i].location;
+            async move {
+                if tracker.is_vanished(location) {
+                    return Ok(None);
+                }
+                let read = tracker
+                    .resolve(location, || {
+                        read(
+                            object_store.as_ref(),
+                            location,
+                            range.clone(),
+                            deadlines,
+                            retry,
+                            control,
+                        )
+                    })
+                    .await;
+                let read = match read {
+                    Ok(read) => read.flatten(),
+                    Err(err) if retry.absorb(&err) => None,
+                    Err(err) => return Err(err),
+                };
+                Ok::<_, object_store::Error>(
+                    read.map(|(fetched, latency)| (fetched, latency, start.elapsed())),
+                )
+            }
+        })
+        .buffered(parallel)
+        .try_collect::<Vec<_>>()
+        .await?;
+    let elapsed = start.elapsed();
+    let paused = control.paused();
+    let elapsed = measurement.map_or(elapsed, |measurement| {
+        measurement.measured(elapsed, paused, control.is_shutdown())
+    });
+    let elapsed_us = elapsed.as_micros();
+    let paused_us = paused.as_micros();
+
+    // Reads completing after a duration-bounded run's window count toward
+    // latency but not throughput.
+    let mut histogram = Histogram::default();
+    let (mut bytes, mut requests) = (0, 0);
+    let (mut late_requests, mut late_bytes) = (0, 0);
+    for (fetched, latency, completed) in completed.iter().flatten() {
+        // Expired reads have no latency to report.
+        let Fetched::Met(len) = fetched else {
+            continue;
+        };
+        histogram.record(*latency);
+        if measurement.is_some_and(|measurement| measurement.is_late(*completed, paused)) {
+            late_requests += 1;
+            late_bytes += *len as u64;
+        } else {
+            requests += 1;
+            bytes += *len as u64;
+        }
+    }
+    let active_secs = active_us(elapsed_us, paused_us) as f64 / 1_000_000.0;
+    let mut result = fields(serde_json::json!({
+        "mode": "random_read",
+        "num_objects": objects.len(),
+        // A duration-bounded run reports the reads it issued.
+        "num_requests": match measurement {
+            Some(_) => requests + late_requests,
+            None => num_requests,
+        },
+        "requests": requests,
+        "request_size": request_size,
+        "parallel": parallel,
+        "seed": selection.seed,
+        "selection": selection.to_json(),
+        "bytes": bytes,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "requests_per_sec": requests as f64 / active_secs,
+        "mbps": mbps(bytes, active_us(elapsed_us, paused_us)),
+        "latency": histogram.summary(),
+        "interrupted": control.is_shutdown(),
+    }));
+    if let Some(recipe) = recipe {
+        result.insert("workload_recipe".to_string(), recipe.to_string().into());
+    }
+    if let Some(deadlines) = deadlines {
+        result.insert(
+            "deadline".to_string(),
+            serde_json::json!(deadlines.report(active_us(elapsed_us, paused_us))),
+        );
+    }
+    if let Some(measurement) = measurement {
+        result.insert(
+            "duration".to_string(),
+            serde_json::json!(measurement.report(elapsed, late_requests, late_bytes)),
+        );
+    }
+    result.extend(tracker.json_field());
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    retry.enforce()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deadline::OnExpire;
+    use crate::experiment::capture;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use crate::missing::MissingObjects;
+    use crate::selection::Strategy;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+    use serde_json::json;
+
+    fn objects(sizes: &[usize]) -> Vec<ObjectMeta> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| ObjectMeta {
+                location: Path::from(format!("data/{}", i)),
+                last_modified: chrono::DateTime::default(),
+                size,
+                e_tag: None,
+            })
+            .collect()
+    }
+
+    fn uniform(seed: u64) -> SelectionSpec {
+        SelectionSpec {
+            strategy: Strategy::Uniform,
+            seed,
+        }
+    }
+
+    #[test]
+    fn plans_repeat_with_the_seed_and_stay_in_bounds() {
+        let sizes = [1000, 50, 4096];
+        let first = plan(&objects(&sizes), 200, 100, &uniform(3)).unwrap();
+        assert_eq!(
+            first,
+            plan(&objects(&sizes), 200, 100, &uniform(3)).unwrap()
+        );
+        assert_ne!(
+            first,
+            plan(&objects(&sizes), 200, 100, &uniform(4)).unwrap()
+        );
+        for (object_i, range) in &first {
+            assert!(range.end <= sizes[*object_i]);
+            assert_eq!(range.len(), 100.min(sizes[*object_i]));
+        }
+        // Every object is picked.
+        for object_i in 0..sizes.len() {
+            assert!(first.iter().any(|(i, _)| *i == object_i));
+        }
+    }
+
+    #[test]
+    fn plans_follow_the_selection_strategy() {
+        let round_robin = SelectionSpec {
+            strategy: Strategy::RoundRobin,
+            seed: 0,
+        };
+        assert_eq!(
+            plan(&objects(&[300, 300]), 5, 100, &round_robin).unwrap(),
+            [
+                (0, 0..100),
+                (1, 0..100),
+                (0, 100..200),
+                (1, 100..200),
+                (0, 200..300)
+            ]
+        );
+
+        // A steep Zipf reads the first object far more often than the last.
+        let zipf = SelectionSpec {
+            strategy: Strategy::Zipf { exponent: 2.0 },
+            seed: 5,
+        };
+        let reads = plan(&objects(&[1000; 4]), 400, 100, &zipf).unwrap();
+        let count = |object_i| reads.iter().filter(|(i, _)| *i == object_i).count();
+        assert!(count(0) > 4 * count(3));
+    }
+
+    #[tokio::test]
+    async fn reads_the_requested_number_of_ranges() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for (name, size) in [("a", 1000), ("b", 10), ("empty", 0)] {
+            object_store
+                .put(
+                    &Path::from(format!("data/{}", name)),
+                    Bytes::from(vec![1; size]),
+                )
+                .await
+                .unwrap();
+        }
+        let (outcome, results) = capture(random_read_bench(
+            object_store,
+            Path::from("data"),
+            RandomReadOptions {
+                num_requests: 20,
+                request_size: 100,
+                parallel: 4,
+                selection: uniform(1),
+                duration: None,
+                deadline: None,
+                recipe: None,
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["num_objects"], 2);
+        assert_eq!(
+            result["selection"],
+            json!({"strategy": "uniform", "seed": 1})
+        );
+        assert_eq!(result["requests"], 20);
+        assert_eq!(result["latency"]["count"], 20);
+        let expected: usize = plan(&objects(&[1000, 10]), 20, 100, &uniform(1))
+            .unwrap()
+            .iter()
+            .map(|(_, range)| range.len())
+            .sum();
+        assert_eq!(result["bytes"], expected);
+    }
+
+    #[tokio::test]
+    async fn reads_for_the_requested_duration() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a"), Bytes::from(vec![1; 1000]))
+            .await
+            .unwrap();
+        let (outcome, results) = capture(random_read_bench(
+            object_store,
+            Path::from("data"),
+            RandomReadOptions {
+                num_requests: 1,
+                request_size: 100,
+                parallel: 2,
+                selection: uniform(1),
+                duration: Some(Duration::from_millis(50)),
+                deadline: None,
+                recipe: None,
+            },
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        let requests = result["requests"].as_u64().unwrap();
+        assert!(requests > 1);
+        assert_eq!(result["bytes"], requests * 100);
+        assert_eq!(result["elapsed_us"], 50_000);
+        let late = result["duration"]["late_requests"].as_u64().unwrap();
+        assert_eq!(result["num_requests"], requests + late);
+    }
+
+    #[tokio::test]
+    async fn failed_and_missing_reads_follow_the_retry_policy() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for name in ["a", "b"] {
+            inner
+                .put(
+                    &Path::from(format!("data/{}", name)),
+                    Bytes::from(vec![1; 1000]),
+                )
+                .await
+                .unwrap();
+        }
+        let faults = [Fault::NotFound("data/b".to_string())];
+        let store: Arc<dyn ObjectStore> =
+            Arc::new(FaultStore::new(inner, FaultConfig::new(&faults), 0));
+        let options = RandomReadOptions {
+            num_requests: 20,
+            request_size: 100,
+            parallel: 1,
+            selection: uniform(1),
+            duration: None,
+            deadline: None,
+            recipe: None,
+        };
+        let reads_of_a = plan(&objects(&[1000, 1000]), 20, 100, &uniform(1))
+            .unwrap()
+            .iter()
+            .filter(|(object_i, _)| *object_i == 0)
+            .count();
+        let run = |retry| {
+            capture(random_read_bench(
+                store.clone(),
+                Path::from("data"),
+                options.clone(),
+                retry,
+                RunControl::new(),
+            ))
+        };
+
+        // By default the first read of the missing object ends the run.
+        let (outcome, results) = run(RetryPolicy::new(0, None)).await;
+        assert!(matches!(outcome, Err(Error::Store(_))));
+        assert!(results.is_empty());
+
+        // Skipped, it vanishes and the other object's reads go on.
+        let retry = RetryPolicy::new(0, None).with_missing_objects(MissingObjects::Skip);
+        let (outcome, results) = run(retry).await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["requests"], reads_of_a);
+        assert_eq!(result["vanished_objects"]["objects"][0]["path"], "data/b");
+        assert_eq!(result["failed_requests"], 0);
+
+        // Under --continue-on-error every read of it fails, and the run
+        // fails once they are reported.
+        let retry = RetryPolicy::new(0, None).with_continue_on_error(true);
+        let (outcome, results) = run(retry).await;
+        let failed = 20 - reads_of_a;
+        assert!(matches!(outcome, Err(Error::FailedRequests { failed: f }) if f == failed));
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["requests"], reads_of_a);
+        assert_eq!(result["failed_requests"], failed);
+    }
+
+    #[tokio::test]
+    async fn recipes_read_the_ranges_they_plan() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for (name, size) in [("c", 5000), ("a", 3000), ("b", 4000), ("_SUCCESS", 0)] {
+            inner
+                .put(
+                    &Path::from(format!("data/{}", name)),
+                    Bytes::from(vec![1; size]),
+                )
+                .await
+                .unwrap();
+        }
+        let store = crate::counting::CountingStore::new(inner.clone()).recording_ranges();
+        let log = store.ranges().unwrap();
+        let recipe: Recipe = "seed=9;reads=30;size=1K;dist=zipf(1.2);objects=2"
+            .parse()
+            .unwrap();
+        let (outcome, results) = capture(random_read_bench(
+            Arc::new(store),
+            Path::from("data"),
+            recipe.options(1),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+        ))
+        .await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(
+            result["workload_recipe"],
+            "seed=9;reads=30;size=1K;dist=zipf(1.2);objects=2"
+        );
+        assert_eq!(result["num_objects"], 2);
+
+        let listed = inner
+            .list(Some(&Path::from("data")))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        let objects = recipe.objects(listed.clone());
+        let planned = recipe
+            .plan(listed)
+            .unwrap()
+            .into_iter()
+            .map(|(object_i, range)| (objects[object_i].location.clone(), range))
+            .collect::<Vec<_>>();
+        assert_eq!(*log.lock().unwrap(), planned);
+    }
+
+    #[tokio::test]
+    async fn reads_past_the_deadline_expire() {
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        inner
+            .put(&Path::from("data/a"), Bytes::from(vec![1; 1000]))
+            .await
+            .unwrap();
+        let faults = [Fault::LatencyMs(200)];
+        let store = FaultStore::new(inner, FaultConfig::new(&faults), 0);
+        let control = RunControl::new();
+        let (outcome, results) = capture(random_read_bench(
+            Arc::new(store),
+            Path::from("data"),
+            RandomReadOptions {
+                num_requests: 3,
+                request_size: 100,
+                parallel: 3,
+                selection: uniform(1),
+                duration: None,
+                deadline: Some(DeadlineSpec {
+                    deadline: Duration::from_millis(20),
+                    on_expire: OnExpire::Skip,
+                }),
+                recipe: None,
+            },
+            RetryPolicy::new(0, None),
+            control.clone(),
+        ))
+        .await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["deadline"]["expired"], 3);
+        assert_eq!(result["deadline"]["met"], 0);
+        assert_eq!(result["requests"], 0);
+        assert_eq!(result["latency"]["count"], 0);
+        assert_eq!(control.snapshot().errors, 0);
+    }
+}
@@ -0,0 +1,323 @@
+//! Reading exactly the ranges listed in a file, with `download --ranges-file`.
+//!
+//! An engine that logs the `(path, offset, length)` reads it issued for a
+//! query can have them replayed through this tool against other stores and
+//! client settings. The file holds one read per line, either as CSV
+//! (`path,offset,len`, with an optional header) or as JSON objects with
+//! `path`, `offset` and `len` fields. Paths are relative to the benchmarked
+//! location, as in [`crate::plan`].
+//!
+//! Every referenced object is checked with a `head` first, and the run fails
+//! before reading anything if any are missing or a range runs past the end of
+//! its object. The reads are then issued in file order, `--parallel-downloads`
+//! at a time, block planning bypassed. Results compare the requests and bytes
+//! with what `download`'s block planner would have read for the same objects,
+//! under `block_planner`.
+
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+
+use crate::control::RunControl;
+use crate::download::{block_size_for, BlockPlan};
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::plan::{head_referenced, resolve};
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps, Histogram};
+
+/// One read listed in a ranges file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedRange {
+    /// Path relative to the benchmarked location
+    pub path: String,
+    pub range: Range<usize>,
+}
+
+/// Parse a ranges file's lines, as CSV or JSON objects.
+pub fn parse(reader: impl BufRead) -> Result<Vec<ListedRange>, String> {
+    let mut ranges = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line.map_err(|err| err.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let listed = match line.starts_with('{') {
+            true => parse_json(line),
+            false => match parse_csv(line) {
+                // A CSV header names the fields rather than giving numbers.
+                Err(_) if ranges.is_empty() && line.starts_with("path,") => continue,
+                listed => listed,
+            },
+        };
+        ranges.push(listed.map_err(|err| format!("line {}: {}", i + 1, err))?);
+    }
+    Ok(ranges)
+}
+
+fn parse_csv(line: &str) -> Result<ListedRange, String> {
+    // The path may itself hold commas, so split the numbers off the end.
+    let mut fields = line.rsplitn(3, ',');
+    let (len, offset, path) = match (fields.next(), fields.next(), fields.next()) {
+        (Some(len), Some(offset), Some(path)) => (len, offset, path),
+        _ => return Err(format!("expected path,offset,len, got {:?}", line)),
+    };
+    let number = |field: &str| {
+        field
+            .trim()
+            .parse::<usize>()
+            .map_err(|err| format!("{:?}: {}", field, err))
+    };
+    listed(path.trim(), number(offset)?, number(len)?)
+}
+
+fn parse_json(line: &str) -> Result<ListedRange, String> {
+    let entry: serde_json::Value = serde_json::from_str(line).map_err(|err| err.to_string())?;
+    let number = |name: &str| {
+        entry
+            .get(name)
+            .and_then(serde_json::Value::as_u64)
+            .map(|n| n as usize)
+            .ok_or_else(|| format!("missing or non-numeric {}", name))
+    };
+    let path = entry
+        .get("path")
+        .and_then(serde_json::Value::as_str)
+        .ok_or("missing path")?;
+    listed(path, number("offset")?, number("len")?)
+}
+
+fn listed(path: &str, offset: usize, len: usize) -> Result<ListedRange, String> {
+    let end = offset
+        .checked_add(len)
+        .ok_or_else(|| format!("range at {} of {} bytes overflows", offset, len))?;
+    if len == 0 {
+        return Err("range is empty".to_string());
+    }
+    Ok(ListedRange {
+        path: path.to_string(),
+        range: offset..end,
+    })
+}
+
+/// Reads the ranges listed in `ranges_file` from under `base`, in file order.
+///
+/// * `parallel_downloads`: maximum number of reads in flight
+/// * `block_size`: block size of the planner the reads are compared with
+pub async fn ranges_file_bench(
+    object_store: Arc<dyn ObjectStore>,
+    base: Path,
+    ranges_file: &std::path::Path,
+    parallel_downloads: usize,
+    block_size: Option<usize>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let file = std::fs::File::open(ranges_file)
+        .map_err(|err| format!("failed to open {}: {}", ranges_file.display(), err))?;
+    let ranges =
+        parse(BufReader::new(file)).map_err(|err| format!("{}: {}", ranges_file.display(), err))?;
+    if ranges.is_empty() {
+        return Err(format!("{} lists no ranges", ranges_file.display()).into());
+    }
+
+    let paths = ranges
+        .iter()
+        .map(|listed| listed.path.clone())
+        .collect::<BTreeSet<_>>();
+    let (objects, missing) = head_referenced(object_store.as_ref(), &base, &paths, &retry).await?;
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} is missing {} of {} objects referenced by {}:\n  - {}",
+            base,
+            missing.len(),
+            paths.len(),
+            ranges_file.display(),
+            missing.join("\n  - ")
+        )
+        .into());
+    }
+    if let Some(listed) = ranges
+        .iter()
+        .find(|listed| listed.range.end > objects[&listed.path].size)
+    {
+        return Err(format!(
+            "{} lists {:?} of {}, which is only {} bytes",
+            ranges_file.display(),
+            listed.range,
+            listed.path,
+            objects[&listed.path].size
+        )
+        .into());
+    }
+    let total = ranges.iter().map(|listed| listed.range.len() as u64).sum();
+    control.set_bytes_total(total);
+
+    let start = Instant::now();
+    let reads: Vec<Option<(usize, Duration)>> = futures::stream::iter(ranges.iter().cloned())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|listed| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            let location = resolve(&base, &listed.path);
+            tokio::task::spawn(async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                control.record("get_range", &location, Some(&listed.range));
+                let start = Instant::now();
+                let result = retry
+                    .run(|| object_store.get_range(&location, listed.range.clone()))
+                    .await;
+                match &result {
+                    Ok(bytes) => control.request_finished(bytes.len()),
+                    Err(_) => control.request_failed(),
+                }
+                result.map(|bytes| Some((bytes.len(), start.elapsed())))
+            })
+        })
+        .buffer_unordered(parallel_downloads)
+        .map(|joined| joined.map_err(|source| object_store::Error::JoinError { source })?)
+        .try_collect()
+        .await?;
+    let elapsed_us = start.elapsed().as_micros();
+    let paused_us = control.paused().as_micros();
+
+    let mut histogram = Histogram::default();
+    let mut bytes = 0;
+    for (len, latency) in reads.iter().flatten() {
+        bytes += *len as u64;
+        histogram.record(*latency);
+    }
+    let num_requests = histogram.count();
+
+    // What `download` would have read: every object whole, in blocks split
+    // from the largest object unless a block size was given.
+    let largest = objects.values().map(|meta| meta.size).max().unwrap_or(0);
+    let block_size = block_size_for(largest, parallel_downloads, block_size);
+    let planned_requests: u64 = objects
+        .values()
+        .map(|meta| BlockPlan::new(meta.size as u64, block_size as u64).num_blocks())
+        .sum();
+    let planned_bytes: u64 = objects.values().map(|meta| meta.size as u64).sum();
+    let listed_bytes = total;
+
+    let mut result = fields(serde_json::json!({
+        "mode": "ranges_file",
+        "ranges_file": ranges_file.display().to_string(),
+        "num_objects": objects.len(),
+        "num_ranges": ranges.len(),
+        "num_requests": num_requests,
+        "parallel_downloads": parallel_downloads,
+        "bytes": bytes,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "mbps": mbps(bytes, active_us(elapsed_us, paused_us)),
+        "latency": histogram.summary(),
+        "block_planner": {
+            "block_size": block_size,
+            "num_requests": planned_requests,
+            "bytes": planned_bytes,
+            "request_ratio": ranges.len() as f64 / planned_requests.max(1) as f64,
+            "byte_ratio": listed_bytes as f64 / planned_bytes.max(1) as f64,
+        },
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::capture;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    fn listed(path: &str, range: Range<usize>) -> ListedRange {
+        ListedRange {
+            path: path.to_string(),
+            range,
+        }
+    }
+
+    #[test]
+    fn parses_csv_and_json_lines() {
+        let file = "path,offset,len\n\
+                    a/x.bin,0,100\n\
+                    \n\
+                    {\"path\": \"b.bin\", \"offset\": 10, \"len\": 5}\n\
+                    odd,name.bin, 7 ,3\n";
+        assert_eq!(
+            parse(file.as_bytes()).unwrap(),
+            [
+                listed("a/x.bin", 0..100),
+                listed("b.bin", 10..15),
+                listed("odd,name.bin", 7..10),
+            ]
+        );
+        let err = parse("a.bin,0,100\nb.bin,x,1\n".as_bytes()).unwrap_err();
+        assert!(err.starts_with("line 2:"), "{}", err);
+        assert!(parse("{\"path\": \"a\", \"offset\": 1}".as_bytes()).is_err());
+        assert!(parse("a,0,0".as_bytes()).is_err());
+    }
+
+    #[tokio::test]
+    async fn reads_the_listed_ranges_and_checks_objects_first() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a.bin"), Bytes::from(vec![1; 1000]))
+            .await
+            .unwrap();
+        let dir =
+            std::env::temp_dir().join(format!("object-store-bench-ranges-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let run = |contents: &str| {
+            let path = dir.join("ranges.csv");
+            std::fs::write(&path, contents).unwrap();
+            let object_store = object_store.clone();
+            async move {
+                capture(ranges_file_bench(
+                    object_store,
+                    Path::from("data"),
+                    &path,
+                    2,
+                    Some(100),
+                    RetryPolicy::new(0, None),
+                    RunControl::new(),
+                ))
+                .await
+            }
+        };
+
+        let (outcome, results) = run("a.bin,0,10\na.bin,500,10\na.bin,990,10\n").await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["num_requests"], 3);
+        assert_eq!(result["bytes"], 30);
+        assert_eq!(result["block_planner"]["num_requests"], 10);
+        assert_eq!(result["block_planner"]["bytes"], 1000);
+        assert_eq!(result["block_planner"]["byte_ratio"], 0.03);
+
+        let (outcome, results) = run("a.bin,0,10\ngone.bin,0,10\n").await;
+        let err = outcome.unwrap_err().to_string();
+        assert!(
+            err.contains("missing 1 of 2") && err.contains("gone.bin"),
+            "{}",
+            err
+        );
+        assert!(results.is_empty());
+
+        let (outcome, _) = run("a.bin,995,10\n").await;
+        assert!(outcome.unwrap_err().to_string().contains("only 1000 bytes"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,208 @@
+//! Downloads whose bytes must be handed on in order.
+//!
+//! A consumer that writes blocks to a file sequentially can only take the
+//! block at the next offset; blocks that complete early wait in a reorder
+//! buffer until the gap before them fills. This benchmark issues ranged reads
+//! in offset order, object after object, releases bytes strictly in that
+//! order, and reports how large the reorder buffer grew and how long the
+//! consumer waited on a missing block while later ones sat buffered.
+//!
+//! With `--max-buffered-bytes`, a request is only issued once the bytes in
+//! flight plus those buffered leave room for it, and the time spent waiting
+//! for room is reported as head-of-line blocking on issue.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::FuturesUnordered;
+use futures::StreamExt;
+use object_store::{path::Path, ObjectStore};
+
+use crate::control::RunControl;
+use crate::download::block_size_for;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps, LatencySummary};
+
+/// Time-weighted occupancy of the reorder buffer.
+#[derive(Debug, Default)]
+struct Occupancy {
+    bytes: usize,
+    blocks: usize,
+    peak_bytes: usize,
+    peak_blocks: usize,
+    /// Sum of buffered bytes times microseconds spent at that level
+    byte_us: u128,
+    changed: Option<Instant>,
+}
+
+impl Occupancy {
+    /// Account for the time at the current level, then move to a new one.
+    fn set(&mut self, now: Instant, bytes: usize, blocks: usize) {
+        if let Some(changed) = self.changed {
+            self.byte_us += self.bytes as u128 * (now - changed).as_micros();
+        }
+        self.changed = Some(now);
+        self.bytes = bytes;
+        self.blocks = blocks;
+        self.peak_bytes = self.peak_bytes.max(bytes);
+        self.peak_blocks = self.peak_blocks.max(blocks);
+    }
+}
+
+/// Benchmarks downloading every object under `location` in blocks, handing
+/// bytes on in offset order
+///
+/// * `parallel_downloads`: maximum number of requests in flight
+/// * `block_size`: size of each ranged request; defaults to splitting the
+///   largest object evenly across the parallel downloads, see
+///   [`block_size_for`]
+/// * `max_buffered_bytes`: cap on bytes in flight plus bytes waiting in the
+///   reorder buffer
+pub async fn reassembly_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel_downloads: usize,
+    block_size: Option<usize>,
+    max_buffered_bytes: Option<usize>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    let objects = objects
+        .into_iter()
+        .filter(|o| o.size > 0)
+        .collect::<Vec<_>>();
+    let Some(largest) = objects.iter().map(|o| o.size).max() else {
+        return Err(format!("every object under {} is empty", location).into());
+    };
+    let block_size = block_size_for(largest, parallel_downloads, block_size);
+    let blocks = objects
+        .iter()
+        .flat_map(|meta| {
+            (0..meta.size.div_ceil(block_size)).map(move |i| {
+                let range = i * block_size..((i + 1) * block_size).min(meta.size);
+                (meta.location.clone(), range)
+            })
+        })
+        .collect::<Vec<_>>();
+    control.set_bytes_total(objects.iter().map(|o| o.size as u64).sum());
+
+    let start = Instant::now();
+    let mut pending = blocks.iter().enumerate().peekable();
+    let mut in_flight = FuturesUnordered::new();
+    let mut in_flight_bytes = 0;
+    let mut buffer = BTreeMap::new();
+    let mut occupancy = Occupancy::default();
+    let mut next_seq = 0;
+    let mut released = 0;
+    let mut latencies = Vec::with_capacity(blocks.len());
+    let mut stalled_since = None;
+    let mut stall = Duration::ZERO;
+    let mut blocked_since = None;
+    let mut issue_blocked = Duration::ZERO;
+
+    loop {
+        while in_flight.len() < parallel_downloads && !control.is_shutdown() {
+            let Some((_, (_, range))) = pending.peek() else {
+                break;
+            };
+            // The block at the head of the line is always allowed, so a
+            // budget smaller than one block still makes progress.
+            let held = in_flight_bytes + occupancy.bytes;
+            if max_buffered_bytes.is_some_and(|max| held > 0 && held + range.len() > max) {
+                blocked_since.get_or_insert_with(Instant::now);
+                break;
+            }
+            if let Some(since) = blocked_since.take() {
+                issue_blocked += since.elapsed();
+            }
+            if !control.request_started().await {
+                break;
+            }
+            let (seq, (location, range)) = pending.next().unwrap();
+            control.record("get_range", location, Some(range));
+            in_flight_bytes += range.len();
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let location = location.clone();
+            let range = range.clone();
+            in_flight.push(tokio::task::spawn(async move {
+                let issued = Instant::now();
+                let result = retry
+                    .run(|| object_store.get_range(&location, range.clone()))
+                    .await;
+                (seq, range.len(), result, issued.elapsed())
+            }));
+        }
+
+        let Some(completed) = in_flight.next().await else {
+            break;
+        };
+        let (seq, len, result, latency) = completed?;
+        in_flight_bytes -= len;
+        let bytes = match result {
+            Ok(bytes) => {
+                control.request_finished(bytes.len());
+                bytes
+            }
+            Err(err) => {
+                control.request_failed();
+                return Err(err.into());
+            }
+        };
+        latencies.push(latency);
+        buffer.insert(seq, bytes);
+        // Hand on every block that is now contiguous with what came before.
+        while let Some(bytes) = buffer.remove(&next_seq) {
+            released += bytes.len();
+            next_seq += 1;
+        }
+        let now = Instant::now();
+        let buffered = buffer.values().map(|b| b.len()).sum();
+        occupancy.set(now, buffered, buffer.len());
+        match (buffer.is_empty(), stalled_since) {
+            (false, None) => stalled_since = Some(now),
+            (true, Some(since)) => {
+                stall += now - since;
+                stalled_since = None;
+            }
+            _ => {}
+        }
+    }
+    let elapsed = start.elapsed();
+    occupancy.set(Instant::now(), 0, 0);
+
+    let elapsed_us = elapsed.as_micros();
+    let paused_us = control.paused().as_micros();
+    let mbps = mbps(released as u64, active_us(elapsed_us, paused_us));
+    let mut result = fields(serde_json::json!({
+        "mode": "reassemble",
+        "num_objects": objects.len(),
+        "num_blocks": blocks.len(),
+        "block_size": block_size,
+        "parallel_downloads": parallel_downloads,
+        "max_buffered_bytes": max_buffered_bytes,
+        "num_requests": latencies.len(),
+        "bytes": released,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "mbps": mbps,
+        "latency": LatencySummary::from_latencies(&mut latencies).to_json(),
+        "reorder_buffer": {
+            "peak_bytes": occupancy.peak_bytes,
+            "mean_bytes": occupancy.byte_us as f64 / elapsed_us.max(1) as f64,
+            "peak_blocks": occupancy.peak_blocks,
+        },
+        "consumer_stall_us": stall.as_micros() as u64,
+        "issue_blocked_us": issue_blocked.as_micros() as u64,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
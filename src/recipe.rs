@@ -0,0 +1,321 @@
+//! Workload recipes: one short string that fixes a random-read sequence.
+//!
+//! `--workload-recipe` takes `key=value` pairs separated by semicolons, such
+//! as `seed=42;reads=10000;size=256K;dist=zipf(1.1);objects=all`:
+//!
+//! * `seed`: seeds the objects and offsets read; the only key without a
+//!   default, as a recipe without one wouldn't repeat
+//! * `reads`: the number of reads, 1000 by default
+//! * `size`: bytes per read, with an optional binary `K`, `M` or `G` suffix;
+//!   64K by default
+//! * `dist`: the selection strategy, `uniform`, `zipf(EXPONENT)`,
+//!   `round-robin` or `recency(HALF_LIFE_SECS)`; uniform by default
+//! * `objects`: `all`, or the first N non-empty objects by path
+//!
+//! Given the same objects, a recipe plans the same reads in every run and in
+//! every mode that accepts one, whatever the concurrency or the order the
+//! store lists them in. Results echo the recipe normalized, with every key
+//! in the order above and every default spelled out, under
+//! `workload_recipe`, so it can be handed on as is. `--print-workload N`
+//! prints a run's first N planned reads instead of issuing them.
+
+use std::fmt;
+use std::str::FromStr;
+
+use object_store::ObjectMeta;
+
+use crate::error::Error;
+use crate::random_read::{plan, PlannedRead, RandomReadOptions};
+use crate::selection::{SelectionSpec, Strategy};
+
+/// A parsed `--workload-recipe`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Recipe {
+    pub seed: u64,
+    pub reads: usize,
+    pub size: usize,
+    /// The selection strategy; never [`Strategy::Weights`], which needs a file
+    pub dist: Strategy,
+    /// Read only the first this many objects by path; all of them when `None`
+    pub objects: Option<usize>,
+}
+
+impl Recipe {
+    /// Which objects are read, and where.
+    pub fn selection(&self) -> SelectionSpec {
+        SelectionSpec {
+            strategy: self.dist.clone(),
+            seed: self.seed,
+        }
+    }
+
+    /// The objects the recipe reads out of those listed, in path order.
+    /// Zero-byte markers such as `_SUCCESS` have nothing to read, so they
+    /// never count towards `objects`.
+    pub fn objects(&self, mut objects: Vec<ObjectMeta>) -> Vec<ObjectMeta> {
+        objects.retain(|meta| meta.size > 0);
+        objects.sort_by(|a, b| a.location.cmp(&b.location));
+        if let Some(count) = self.objects {
+            objects.truncate(count);
+        }
+        objects
+    }
+
+    /// The reads of the recipe over the `objects` it picks, as indexes into
+    /// [`Recipe::objects`].
+    pub fn plan(&self, objects: Vec<ObjectMeta>) -> Result<Vec<PlannedRead>, Error> {
+        plan(
+            &self.objects(objects),
+            self.reads,
+            self.size,
+            &self.selection(),
+        )
+    }
+
+    /// `random-read` options running the recipe, `parallel` reads at a time.
+    pub fn options(&self, parallel: usize) -> RandomReadOptions {
+        RandomReadOptions {
+            num_requests: self.reads,
+            request_size: self.size,
+            parallel,
+            selection: self.selection(),
+            duration: None,
+            deadline: None,
+            recipe: Some(self.clone()),
+        }
+    }
+}
+
+impl FromStr for Recipe {
+    type Err = String;
+
+    /// Parse `key=value` pairs separated by semicolons; missing keys other
+    /// than `seed` keep their defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut seed = None;
+        let mut recipe = Recipe {
+            seed: 0,
+            reads: 1000,
+            size: 64 * 1024,
+            dist: Strategy::Uniform,
+            objects: None,
+        };
+        let mut seen = Vec::new();
+        for pair in s.split(';').filter(|pair| !pair.trim().is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected key=value, got {:?}", pair))?;
+            let (key, value) = (key.trim(), value.trim());
+            if seen.contains(&key) {
+                return Err(format!("{} is given more than once", key));
+            }
+            seen.push(key);
+            let invalid = |err: &dyn fmt::Display| format!("{}: {}", key, err);
+            match key {
+                "seed" => seed = Some(value.parse().map_err(|err| invalid(&err))?),
+                "reads" => recipe.reads = value.parse().map_err(|err| invalid(&err))?,
+                "size" => recipe.size = parse_size(value).map_err(|err| invalid(&err))?,
+                "dist" => recipe.dist = parse_dist(value).map_err(|err| invalid(&err))?,
+                "objects" => {
+                    recipe.objects = match value {
+                        "all" => None,
+                        count => Some(count.parse().map_err(|err| invalid(&err))?),
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "unknown key {:?}; expected seed, reads, size, dist or objects",
+                        key
+                    ))
+                }
+            }
+        }
+        recipe.seed = seed.ok_or("a recipe needs a seed, such as seed=42")?;
+        if recipe.reads == 0 || recipe.size == 0 || recipe.objects == Some(0) {
+            return Err("reads, size and objects must be positive".to_string());
+        }
+        Ok(recipe)
+    }
+}
+
+/// The normalized recipe: every key, in order, with sizes in their largest
+/// exact unit.
+impl fmt::Display for Recipe {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "seed={};reads={};size={};dist=",
+            self.seed,
+            self.reads,
+            format_size(self.size)
+        )?;
+        match &self.dist {
+            Strategy::Uniform => write!(f, "uniform")?,
+            Strategy::Zipf { exponent } => write!(f, "zipf({})", exponent)?,
+            Strategy::RoundRobin => write!(f, "round-robin")?,
+            Strategy::Recency { half_life_secs } => write!(f, "recency({})", half_life_secs)?,
+            Strategy::Weights { path } => write!(f, "weights({})", path.display())?,
+        }
+        match self.objects {
+            Some(count) => write!(f, ";objects={}", count),
+            None => write!(f, ";objects=all"),
+        }
+    }
+}
+
+const UNITS: [(&str, usize); 3] = [("G", 1 << 30), ("M", 1 << 20), ("K", 1 << 10)];
+
+/// Bytes, with an optional binary `K`, `M` or `G` suffix.
+fn parse_size(value: &str) -> Result<usize, String> {
+    let upper = value.to_ascii_uppercase();
+    let (digits, unit) = UNITS
+        .iter()
+        .find_map(|(suffix, unit)| Some((upper.strip_suffix(suffix)?, *unit)))
+        .unwrap_or((&upper, 1));
+    let count: usize = digits
+        .parse()
+        .map_err(|err| format!("{:?}: {}", value, err))?;
+    count
+        .checked_mul(unit)
+        .ok_or_else(|| format!("{:?} is too large", value))
+}
+
+fn format_size(size: usize) -> String {
+    UNITS
+        .iter()
+        .find(|(_, unit)| size.is_multiple_of(*unit))
+        .map_or(size.to_string(), |(suffix, unit)| {
+            format!("{}{}", size / unit, suffix)
+        })
+}
+
+/// `name` or `name(parameter)`.
+fn parse_dist(value: &str) -> Result<Strategy, String> {
+    let (name, parameter) = match value.strip_suffix(')') {
+        Some(call) => call
+            .split_once('(')
+            .map(|(name, parameter)| (name, Some(parameter)))
+            .ok_or_else(|| format!("unbalanced parentheses in {:?}", value))?,
+        None => (value, None),
+    };
+    let number = |default: Option<f64>| match parameter {
+        Some(parameter) => parameter
+            .parse::<f64>()
+            .ok()
+            .filter(|number| *number > 0.0 && number.is_finite())
+            .ok_or_else(|| format!("{} takes a positive number, not {:?}", name, parameter)),
+        None => default.ok_or_else(|| format!("{} needs a parameter, such as {}(1)", name, name)),
+    };
+    let strategy = match name {
+        "uniform" => Strategy::Uniform,
+        "round-robin" => Strategy::RoundRobin,
+        "zipf" => Strategy::Zipf {
+            exponent: number(Some(1.0))?,
+        },
+        "recency" => Strategy::Recency {
+            half_life_secs: number(None)?,
+        },
+        _ => {
+            return Err(format!(
+                "unknown distribution {:?}; expected uniform, zipf, round-robin or recency",
+                name
+            ))
+        }
+    };
+    if parameter.is_some() && matches!(strategy, Strategy::Uniform | Strategy::RoundRobin) {
+        return Err(format!("{} takes no parameter", name));
+    }
+    Ok(strategy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::path::Path;
+
+    fn objects(names: &[&str]) -> Vec<ObjectMeta> {
+        names
+            .iter()
+            .map(|name| ObjectMeta {
+                location: Path::from(*name),
+                last_modified: chrono::DateTime::default(),
+                size: 1 << 20,
+                e_tag: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn recipes_normalize() {
+        let recipe: Recipe = "seed=42;reads=10000;size=256K;dist=zipf(1.1);objects=all"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            recipe,
+            Recipe {
+                seed: 42,
+                reads: 10000,
+                size: 256 * 1024,
+                dist: Strategy::Zipf { exponent: 1.1 },
+                objects: None,
+            }
+        );
+        assert_eq!(
+            recipe.to_string(),
+            "seed=42;reads=10000;size=256K;dist=zipf(1.1);objects=all"
+        );
+
+        // Keys in any order, defaults spelled out, sizes in their largest unit.
+        let recipe: Recipe = " objects=2; size=2048k ;seed=7;".parse().unwrap();
+        assert_eq!(
+            recipe.to_string(),
+            "seed=7;reads=1000;size=2M;dist=uniform;objects=2"
+        );
+        let recipe: Recipe = "seed=7;size=1000;dist=recency(60)".parse().unwrap();
+        assert_eq!(
+            recipe.to_string(),
+            "seed=7;reads=1000;size=1000;dist=recency(60);objects=all"
+        );
+        // Normalizing is idempotent.
+        assert_eq!(recipe.to_string().parse::<Recipe>().unwrap(), recipe);
+    }
+
+    #[test]
+    fn bad_recipes_are_rejected() {
+        for (recipe, error) in [
+            ("reads=10", "a recipe needs a seed"),
+            ("seed=1;seed=2", "seed is given more than once"),
+            ("seed=1;speed=2", "unknown key"),
+            ("seed=1;size=0", "must be positive"),
+            ("seed=1;size=12Q", "size:"),
+            ("seed=1;dist=zipf(0)", "zipf takes a positive number"),
+            ("seed=1;dist=recency", "recency needs a parameter"),
+            ("seed=1;dist=uniform(2)", "uniform takes no parameter"),
+            ("seed=1;dist=pareto", "unknown distribution"),
+            ("seed=1;objects=some", "objects:"),
+        ] {
+            let err = recipe.parse::<Recipe>().unwrap_err();
+            assert!(err.contains(error), "{}: {}", recipe, err);
+        }
+    }
+
+    #[test]
+    fn plans_ignore_the_listing_order() {
+        let recipe: Recipe = "seed=3;reads=50;size=4K;dist=zipf(1.5);objects=2"
+            .parse()
+            .unwrap();
+        let listed = objects(&["data/c", "data/a", "data/b"]);
+        let reversed = listed.iter().rev().cloned().collect();
+        let planned = recipe.plan(listed).unwrap();
+        assert_eq!(planned, recipe.plan(reversed).unwrap());
+        assert_eq!(planned.len(), 50);
+        // Only data/a and data/b are read.
+        assert!(planned.iter().all(|(object_i, range)| {
+            *object_i < 2 && range.len() == 4096 && range.end <= 1 << 20
+        }));
+        let other: Recipe = "seed=4;reads=50;size=4K;dist=zipf(1.5);objects=2"
+            .parse()
+            .unwrap();
+        assert_ne!(planned, other.plan(objects(&["data/a", "data/b"])).unwrap());
+    }
+}
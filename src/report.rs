@@ -0,0 +1,611 @@
+//! Summaries over previously recorded results, and the schema they follow.
+//!
+//! The input is either a newline-delimited results file, or an experiment
+//! directory, in which case its `index.jsonl` is used. `report` also takes a
+//! `--request-trace` file, in either format, and summarizes its reads.
+//!
+//! Every result carries the [`SCHEMA_VERSION`] it was written with as
+//! `schema_version`, and a `mode` naming the benchmark. Within a version
+//! fields are only ever added, so a query written against a version keeps
+//! working on later results of it. Removing, renaming or retyping a field
+//! bumps the version, with a migration upgrading older records; records are
+//! upgraded as they are loaded, and `migrate` rewrites a file of them. The
+//! structs below are the results serialized with serde. A test runs each
+//! mode with its optional parts switched on and checks the fields of the
+//! results against the snapshot in `schema/`, failing if a field changes
+//! without a bump; the snapshot's header says which fields it leaves out.
+
+use std::path::Path;
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::error::Error;
+use crate::experiment::{prepend, Fields, INDEX_FILE};
+use crate::stats::LatencySummary;
+
+/// The version of the result schema, recorded in every result.
+pub const SCHEMA_VERSION: u64 = 1;
+
+/// `MIGRATIONS[v]` upgrades a record of version `v` to version `v + 1`.
+/// Records written before versioning are version 0.
+const MIGRATIONS: [fn(&mut Map<String, Value>); SCHEMA_VERSION as usize] = [from_unversioned];
+
+/// Version 1 named the mode of every result. Downloads and columnar reads
+/// had no `mode`, and runs before pausing, interrupting and zero-byte objects
+/// were handled report none of them.
+fn from_unversioned(record: &mut Map<String, Value>) {
+    if record.contains_key("mode") {
+        return;
+    }
+    let mode = if record.contains_key("num_groups") {
+        "columnar"
+    } else if record.contains_key("num_blocks") {
+        "download"
+    } else {
+        return;
+    };
+    record.insert("mode".to_string(), mode.into());
+    for (field, default) in [
+        ("zero_byte_objects", Value::from(0)),
+        ("paused_us", Value::from(0)),
+        ("interrupted", Value::from(false)),
+    ] {
+        record.entry(field).or_insert(default);
+    }
+}
+
+/// Upgrade `record` to [`SCHEMA_VERSION`].
+pub fn migrate(record: Value) -> Result<Value, String> {
+    let Value::Object(mut record) = record else {
+        return Err("a record must be a JSON object".to_string());
+    };
+    let version = match record.get("schema_version") {
+        None => 0,
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| format!("invalid schema_version {}", version))?,
+    };
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "schema version {} is newer than version {}, the latest this build reads",
+            version, SCHEMA_VERSION
+        ));
+    }
+    for migration in &MIGRATIONS[version as usize..] {
+        migration(&mut record);
+    }
+    record.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(Value::Object(record))
+}
+
+/// Record the schema version at the start of `result`.
+pub fn with_schema_version(result: &mut Fields) {
+    prepend(result, "schema_version", SCHEMA_VERSION.into());
+}
+
+/// Upgrade the records of a results file or experiment directory to the
+/// current schema, writing them to `out`, or stdout.
+pub fn migrate_file(path: &Path, out: Option<&Path>) -> Result<(), Error> {
+    let (file, lines) = read_lines(path)?;
+    let mut upgraded = 0;
+    let mut migrated = String::new();
+    for (line_no, line) in &lines {
+        let record: Value = serde_json::from_str(line)
+            .map_err(|err| format!("{}:{}: invalid record: {}", file.display(), line_no, err))?;
+        let old = record.get("schema_version").cloned();
+        let record =
+            migrate(record).map_err(|err| format!("{}:{}: {}", file.display(), line_no, err))?;
+        if old != record.get("schema_version").cloned() {
+            upgraded += 1;
+        }
+        migrated.push_str(&record.to_string());
+        migrated.push('\n');
+    }
+    match out {
+        Some(out) => std::fs::write(out, migrated)
+            .map_err(|err| format!("failed to write {}: {}", out.display(), err))?,
+        None => print!("{}", migrated),
+    }
+    eprintln!(
+        "upgraded {} of {} records to schema version {}",
+        upgraded,
+        lines.len(),
+        SCHEMA_VERSION
+    );
+    Ok(())
+}
+
+/// Print one row per recorded run with its headline metrics.
+pub fn report(path: &Path) -> Result<(), Error> {
+    if let Some(format) = crate::request_trace::detect(path)? {
+        return crate::request_trace::report(path, format);
+    }
+    let records = load_records(path)?;
+
+    println!("run\tcommand\telapsed_us\tmbps");
+    for (i, record) in records.iter().enumerate() {
+        let run = match record.get("run_id").and_then(|v| v.as_str()) {
+            Some(run_id) => run_id.to_string(),
+            None => i.to_string(),
+        };
+        let command = record
+            .get("command")
+            .or_else(|| record.get("mode"))
+            .and_then(|v| v.as_str())
+            .unwrap_or("-");
+        println!(
+            "{}\t{}\t{}\t{}",
+            run,
+            command,
+            record.get("elapsed_us").unwrap_or(&serde_json::Value::Null),
+            record.get("mbps").unwrap_or(&serde_json::Value::Null),
+        );
+    }
+    Ok(())
+}
+
+/// Load records from a results file or an experiment directory's index,
+/// upgraded to the current schema.
+pub fn load_records(path: &Path) -> Result<Vec<Value>, Error> {
+    let (file, lines) = read_lines(path)?;
+    lines
+        .iter()
+        .map(|(line_no, line)| {
+            let record = serde_json::from_str(line).map_err(|err| {
+                format!("{}:{}: invalid record: {}", file.display(), line_no, err)
+            })?;
+            Ok(
+                migrate(record)
+                    .map_err(|err| format!("{}:{}: {}", file.display(), line_no, err))?,
+            )
+        })
+        .collect()
+}
+
+/// Non-blank lines of a file, with their line numbers.
+type NumberedLines = Vec<(usize, String)>;
+
+/// The file holding the records at `path`, and its lines.
+fn read_lines(path: &Path) -> Result<(std::path::PathBuf, NumberedLines), Error> {
+    let file = if path.is_dir() {
+        path.join(INDEX_FILE)
+    } else {
+        path.to_path_buf()
+    };
+    let contents = std::fs::read_to_string(&file)
+        .map_err(|err| format!("failed to read {}: {}", file.display(), err))?;
+    let lines = contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| (i + 1, line.to_string()))
+        .collect();
+    Ok((file, lines))
+}
+
+/// A benchmark's result, and the first check made on it after the run that
+/// failed.
+///
+/// A run whose bytes don't add up under `--strict-accounting`, whose requests
+/// failed and were left out, or whose data failed `--verify` is still worth
+/// reporting, so it returns its result with the failure rather than instead
+/// of it.
+#[derive(Debug)]
+pub struct Checked<T> {
+    pub result: T,
+    pub failure: Option<Error>,
+}
+
+impl<T> Checked<T> {
+    /// The result, unless a check failed.
+    pub fn into_result(self) -> Result<T, Error> {
+        match self.failure {
+            Some(err) => Err(err),
+            None => Ok(self.result),
+        }
+    }
+}
+
+/// What a download run returns.
+#[derive(Debug, Serialize)]
+pub struct DownloadResult {
+    pub mode: &'static str,
+    pub num_objects: usize,
+    pub zero_byte_objects: usize,
+    /// Blocks of the largest object read in blocks
+    pub num_blocks: u64,
+    pub block_size: usize,
+    pub parallel_downloads: usize,
+    /// Requests measured, including those the run went on without
+    pub num_requests: usize,
+    /// Requests that completed without error; with `failed_requests`, they
+    /// make up `num_requests`, and `bytes` and `mbps` count only theirs
+    pub successful_requests: usize,
+    pub bytes: u64,
+    pub elapsed_us: u128,
+    pub paused_us: u128,
+    pub aggregation_us: u128,
+    pub mbps: f64,
+    /// `ranged` or `sequential`, see [`crate::download::ReadMode`]
+    pub read_mode: &'static str,
+    pub interrupted: bool,
+    #[serde(flatten)]
+    pub streaming: Option<Streaming>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deadline: Option<DeadlineReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<DurationReport>,
+    /// Latency, window, phase and per-object fields, then those of the
+    /// accounting, missing objects and retries
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// How a download streamed to a slow consumer, with `--consume-mbps`.
+#[derive(Debug, Serialize)]
+pub struct Streaming {
+    pub consume_mbps: f64,
+    pub bytes_received: u64,
+    pub stream_errors: usize,
+    pub stream_timeouts: usize,
+}
+
+/// How requests fared against `--request-deadline-ms`.
+#[derive(Debug, Serialize)]
+pub struct DeadlineReport {
+    pub deadline_ms: u64,
+    pub on_expire: &'static str,
+    pub requests: usize,
+    pub met: usize,
+    pub expired: usize,
+    pub expiry_rate: f64,
+    pub reissued: usize,
+    pub reissues_met: usize,
+    pub goodput_bytes: u64,
+    pub goodput_mbps: f64,
+    pub wasted_bytes: u64,
+}
+
+/// The measurement window of a `--duration` run, see [`crate::duration`].
+#[derive(Debug, Serialize)]
+pub struct DurationReport {
+    pub requested_secs: f64,
+    pub measured_us: u128,
+    /// Requests that completed after the window closed
+    pub late_requests: usize,
+    pub late_bytes: u64,
+}
+
+/// What a columnar run returns.
+#[derive(Debug, Serialize)]
+pub struct ColumnarResult {
+    pub mode: &'static str,
+    /// `get_range` or `get_ranges`
+    pub api: &'static str,
+    pub num_objects: usize,
+    pub zero_byte_objects: usize,
+    /// Groups in the largest object; the layout fields describe it too
+    pub num_groups: usize,
+    pub page_sizes: Vec<usize>,
+    pub page_align: usize,
+    pub inter_page_gap: usize,
+    pub padding_bytes: u64,
+    pub space_overhead: f64,
+    pub parallel_downloads: usize,
+    /// The columns read
+    pub columns: Vec<usize>,
+    pub column_priority: Vec<usize>,
+    pub time_to_available: Vec<ColumnReady>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub coalesce_gap: Option<usize>,
+    /// Requests measured, including those the run went on without
+    pub num_requests: usize,
+    /// Requests that completed without error; with `failed_requests`, they
+    /// make up `num_requests`, and `bytes` and `mbps` count only theirs
+    pub successful_requests: usize,
+    pub bytes: u64,
+    /// Bytes of the pages read, without the gaps coalesced reads spanned
+    pub bytes_needed: u64,
+    pub read_amplification: f64,
+    pub elapsed_us: u128,
+    pub paused_us: u128,
+    pub mbps: f64,
+    pub interrupted: bool,
+    /// Fields of the accounting, missing objects and retries, then any
+    /// analysis and inferred layout
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// How soon one column's pages were available after their group was issued.
+#[derive(Debug, Serialize)]
+pub struct ColumnReady {
+    pub column: usize,
+    pub page_size: usize,
+    pub latency: LatencySummary,
+}
+
+/// What a `swr` run returns.
+#[derive(Debug, Serialize)]
+pub struct SwrResult {
+    pub mode: &'static str,
+    pub run_id: String,
+    pub num_keys: usize,
+    pub object_size: usize,
+    pub read_size: usize,
+    pub parallel: usize,
+    pub seed: u64,
+    pub mutate_fraction: f64,
+    pub mutated_keys: usize,
+    pub validators: ValidatorCounts,
+    pub accesses: usize,
+    pub read_latency: LatencySummary,
+    pub revalidate_latency: LatencySummary,
+    pub read_errors: usize,
+    pub revalidate_errors: usize,
+    pub mismatches: usize,
+    pub mismatch_rate: f64,
+    pub misclassified: usize,
+    pub extra_requests: usize,
+    pub extra_bytes: usize,
+    pub scratch: serde_json::Value,
+    pub interrupted: bool,
+    #[serde(flatten)]
+    pub fields: serde_json::Map<String, serde_json::Value>,
+}
+
+/// How many keys of a `swr` run were versioned by ETag, and how many by
+/// modification time.
+#[derive(Debug, Serialize)]
+pub struct ValidatorCounts {
+    pub etag: usize,
+    pub last_modified: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::columnar::{columnar_read_test, ColumnarOptions};
+    use crate::control::RunControl;
+    use crate::deadline::{DeadlineSpec, OnExpire};
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::experiment::{fields, Fields};
+    use crate::retry::RetryPolicy;
+    use crate::swr::{swr_bench, SwrOptions};
+    use crate::think_time::{Pacing, ThinkTime};
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+    use object_store::path::Path;
+    use object_store::ObjectStore;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    /// A populated result of each mode, from runs against an in-memory store
+    /// with the optional parts of their results switched on. Each mode may
+    /// take several runs, where its options exclude one another.
+    async fn examples() -> Vec<Value> {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let objects = [
+            ("data/a", 1 << 17),
+            ("data/small", 1000),
+            ("data/empty", 0),
+            ("columnar/a", 1 << 17),
+        ];
+        for (name, size) in objects {
+            store
+                .put(&Path::from(name), Bytes::from(vec![7; size]))
+                .await
+                .unwrap();
+        }
+        let location = Path::from("data");
+        let retry = || RetryPolicy::new(0, Some(10));
+        let pacing = Some(Pacing {
+            think_time: ThinkTime::Fixed { ms: 1.0 },
+            seed: 0,
+        });
+        let download = |options| {
+            parallel_download_bench(
+                store.clone(),
+                location.clone(),
+                options,
+                retry(),
+                RunControl::new(),
+            )
+        };
+        let downloads = [
+            DownloadOptions {
+                parallel_downloads: 2,
+                block_size: Some(1 << 14),
+                pacing,
+                verify: true,
+                window: Duration::from_micros(100),
+                timeline: true,
+                ..DownloadOptions::default()
+            },
+            DownloadOptions {
+                block_size: Some(1 << 14),
+                deadline: Some(DeadlineSpec {
+                    deadline: Duration::from_secs(10),
+                    on_expire: OnExpire::Skip,
+                }),
+                ..DownloadOptions::default()
+            },
+            DownloadOptions {
+                block_size: Some(1 << 14),
+                consume_mbps: Some(1000.0),
+                duration: Some(Duration::from_millis(20)),
+                ..DownloadOptions::default()
+            },
+        ];
+        let mut examples = Vec::new();
+        for options in downloads {
+            let result = download(options).await.unwrap().result;
+            examples.push(serde_json::to_value(result).unwrap());
+        }
+        let columnar = columnar_read_test(
+            store.clone(),
+            Path::from("columnar"),
+            ColumnarOptions {
+                parallel_downloads: 2,
+                page_sizes: vec![100, 200, 300],
+                analyze: true,
+                page_align: 1,
+                inter_page_gap: 0,
+                manifest_out: None,
+                columns: Vec::new(),
+                column_priority: vec![2],
+                infer_layout: true,
+                coalesce_gap: Some(0),
+                ranges_api: false,
+                footer_size: Some(100),
+                pacing,
+            },
+            retry(),
+            RunControl::new(),
+        )
+        .await
+        .unwrap()
+        .result;
+        examples.push(serde_json::to_value(columnar).unwrap());
+        let swr = swr_bench(
+            store,
+            Path::from("scratch"),
+            SwrOptions {
+                num_keys: 4,
+                object_size: 1024,
+                read_size: 256,
+                accesses: 20,
+                parallel: 2,
+                mutate_fraction: 0.5,
+                seed: 0,
+            },
+            retry(),
+            RunControl::new(),
+            false,
+        )
+        .await
+        .unwrap();
+        examples.push(serde_json::to_value(swr).unwrap());
+        examples
+    }
+
+    /// A `mode.field type` line for every field of `value`, descending into
+    /// objects and the first element of arrays. A null says nothing of a
+    /// field's type, which another example has to give.
+    fn describe(prefix: &str, value: &Value, lines: &mut Vec<String>) {
+        let kind = match value {
+            Value::Null => return,
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+        };
+        lines.push(format!("{} {}", prefix, kind));
+        match value {
+            Value::Object(fields) => {
+                for (name, value) in fields {
+                    describe(&format!("{}.{}", prefix, name), value, lines);
+                }
+            }
+            Value::Array(items) => {
+                if let Some(item) = items.first() {
+                    describe(&format!("{}[]", prefix), item, lines);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    async fn snapshot() -> Vec<String> {
+        let mut lines = Vec::new();
+        for example in examples().await {
+            let mut example = fields(example);
+            with_schema_version(&mut example);
+            let example = Value::from(example);
+            let mode = example["mode"].as_str().unwrap().to_string();
+            let mut described = Vec::new();
+            describe(&mode, &example, &mut described);
+            for line in described {
+                if !lines.contains(&line) {
+                    lines.push(line);
+                }
+            }
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn schema_snapshot_is_current() {
+        let path = format!(
+            "{}/schema/v{}.txt",
+            env!("CARGO_MANIFEST_DIR"),
+            SCHEMA_VERSION
+        );
+        let recorded = std::fs::read_to_string(&path)
+            .unwrap_or_else(|err| panic!("failed to read {}: {}", path, err));
+        let recorded = recorded
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        let current = snapshot().await;
+        let changed = recorded
+            .iter()
+            .filter(|line| !current.contains(line))
+            .collect::<Vec<_>>();
+        assert!(
+            changed.is_empty(),
+            "fields removed, renamed or retyped: {:?}. Bump SCHEMA_VERSION, add a \
+             migration, and record the new fields in schema/v{}.txt",
+            changed,
+            SCHEMA_VERSION + 1
+        );
+        let added = current
+            .iter()
+            .filter(|line| !recorded.contains(line))
+            .collect::<Vec<_>>();
+        assert!(
+            added.is_empty(),
+            "fields added: {:?}. Append them to {}",
+            added,
+            path
+        );
+    }
+
+    #[tokio::test]
+    async fn unversioned_records_are_upgraded() {
+        // A download result from before versioning, as in `results.ndjson`.
+        let old: Value = serde_json::from_str(
+            r#"{"num_blocks": 6, "block_size": 214748364, "parallel_downloads": 5, "elapsed_us": 108537, "mbps": 9434.5}"#,
+        )
+        .unwrap();
+        let upgraded = migrate(old).unwrap();
+        assert_eq!(upgraded["schema_version"], SCHEMA_VERSION);
+        assert_eq!(upgraded["mode"], "download");
+        assert_eq!(upgraded["interrupted"], false);
+        assert_eq!(upgraded["mbps"], 9434.5);
+        // Upgrading is idempotent, and leaves current records alone.
+        assert_eq!(migrate(upgraded.clone()).unwrap(), upgraded);
+        let mut versioned = examples().await.remove(3);
+        versioned["schema_version"] = SCHEMA_VERSION.into();
+        assert_eq!(migrate(versioned.clone()).unwrap(), versioned);
+
+        let newer = serde_json::json!({"schema_version": SCHEMA_VERSION + 1});
+        assert!(migrate(newer).unwrap_err().contains("newer"));
+    }
+
+    #[test]
+    fn emitted_results_lead_with_the_version() {
+        let mut result = fields(serde_json::json!({"mode": "list"}));
+        with_schema_version(&mut result);
+        assert_eq!(
+            Value::from(result).to_string(),
+            "{\"schema_version\":1,\"mode\":\"list\"}"
+        );
+        let mut empty = Fields::new();
+        with_schema_version(&mut empty);
+        assert_eq!(Value::from(empty), serde_json::json!({"schema_version": 1}));
+    }
+}
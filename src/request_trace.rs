@@ -0,0 +1,805 @@
+//! One sample per read, with `--request-trace`.
+//!
+//! Results summarize a run; a trace keeps every read so the tail can be
+//! picked apart afterwards. `--request-trace PATH` wraps the store in a
+//! [`RequestTraceStore`], which records each `get`, `get_opts`, `get_range`
+//! and `get_ranges` with:
+//!
+//! * `timestamp_us`: when it was issued, in microseconds since the Unix epoch
+//! * `location`, `offset`: the object and where the read starts; a
+//!   `get_ranges` call is one sample at its first range
+//! * `len`: bytes received, 0 for a read that failed
+//! * `latency_us`: until the last byte, or the failure
+//! * `outcome`: `ok`, `not_found` or `error`
+//! * `attempt`: 1, or how many times in a row the same read had been issued,
+//!   so retries show up as their own samples
+//!
+//! `--trace-format jsonl` writes one JSON object per sample. At millions of
+//! requests that gets large and slow to scan, so builds with the `parquet`
+//! feature also take `--trace-format parquet`, which batches samples into
+//! Arrow arrays and writes them as Snappy-compressed row groups. Either way
+//! samples are written out every [`BATCH_ROWS`] samples or
+//! [`FLUSH_INTERVAL`], whichever comes first, so a run holds at most one
+//! batch in memory and a crash loses at most the last one; a Parquet file
+//! also gets its footer when the run ends or fails. `report` reads either
+//! format, telling them apart by their contents.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::{Stream, StreamExt};
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWrite;
+
+use crate::error::Error;
+use crate::missing::MaybeNotFound;
+use crate::stats::LatencySummary;
+
+/// Samples written out at once, and in each Parquet row group.
+pub const BATCH_ROWS: usize = 64 * 1024;
+/// Longest a sample waits to be written out.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The first bytes of every Parquet file.
+const PARQUET_MAGIC: &[u8; 4] = b"PAR1";
+
+/// How `--request-trace` is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TraceFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Parquet row groups, in builds with the `parquet` feature
+    Parquet,
+}
+
+/// How a read ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    Ok,
+    NotFound,
+    Error,
+}
+
+impl Outcome {
+    fn of<T>(result: &Result<T>) -> Self {
+        match result {
+            Ok(_) => Outcome::Ok,
+            Err(err) if err.is_not_found() => Outcome::NotFound,
+            Err(_) => Outcome::Error,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Ok => "ok",
+            Outcome::NotFound => "not_found",
+            Outcome::Error => "error",
+        }
+    }
+
+    #[cfg(feature = "parquet")]
+    fn parse(name: &str) -> Option<Self> {
+        [Outcome::Ok, Outcome::NotFound, Outcome::Error]
+            .into_iter()
+            .find(|outcome| outcome.name() == name)
+    }
+}
+
+/// One read.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    pub timestamp_us: u64,
+    pub location: String,
+    pub offset: u64,
+    pub len: u64,
+    pub latency_us: u64,
+    pub outcome: Outcome,
+    pub attempt: u32,
+}
+
+enum Sink {
+    Jsonl(BufWriter<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(Box<parquet_format::Writer>),
+    Finished,
+}
+
+impl Sink {
+    fn write(&mut self, samples: &[Sample]) -> std::result::Result<(), Error> {
+        match self {
+            Sink::Jsonl(writer) => {
+                for sample in samples {
+                    serde_json::to_writer(&mut *writer, sample)?;
+                    writeln!(writer)?;
+                }
+                writer.flush()?;
+            }
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.write(samples)?,
+            Sink::Finished => {}
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> std::result::Result<(), Error> {
+        match std::mem::replace(self, Sink::Finished) {
+            Sink::Jsonl(mut writer) => writer.flush()?,
+            #[cfg(feature = "parquet")]
+            Sink::Parquet(writer) => writer.finish()?,
+            Sink::Finished => {}
+        }
+        Ok(())
+    }
+}
+
+struct Pending {
+    sink: Sink,
+    batch: Vec<Sample>,
+    flushed: Instant,
+    /// Why writing failed, once it has; later samples are dropped
+    failed: Option<String>,
+}
+
+/// Where a run's samples go, and those not written out yet.
+pub struct RequestTrace {
+    path: std::path::PathBuf,
+    pending: Mutex<Pending>,
+    /// Reads that failed and haven't succeeded since, with how many times
+    /// they were issued
+    attempts: Mutex<HashMap<(Path, u64), u32>>,
+}
+
+impl RequestTrace {
+    pub fn create(path: &std::path::Path, format: TraceFormat) -> std::result::Result<Self, Error> {
+        let file = File::create(path)
+            .map_err(|err| format!("failed to create {}: {}", path.display(), err))?;
+        let sink = match format {
+            TraceFormat::Jsonl => Sink::Jsonl(BufWriter::new(file)),
+            #[cfg(feature = "parquet")]
+            TraceFormat::Parquet => Sink::Parquet(Box::new(parquet_format::Writer::new(file)?)),
+            #[cfg(not(feature = "parquet"))]
+            TraceFormat::Parquet => {
+                drop(file);
+                let _ = std::fs::remove_file(path);
+                return Err("--trace-format parquet needs a build with the parquet feature".into());
+            }
+        };
+        Ok(Self {
+            path: path.to_path_buf(),
+            pending: Mutex::new(Pending {
+                sink,
+                batch: Vec::with_capacity(BATCH_ROWS),
+                flushed: Instant::now(),
+                failed: None,
+            }),
+            attempts: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The attempt a read of `location` at `offset` issued now is.
+    fn attempt(&self, location: &Path, offset: u64) -> u32 {
+        let attempts = self.attempts.lock().unwrap();
+        attempts
+            .get(&(location.clone(), offset))
+            .map_or(1, |issued| issued + 1)
+    }
+
+    fn record(&self, location: &Path, sample: Sample) {
+        let key = (location.clone(), sample.offset);
+        {
+            let mut attempts = self.attempts.lock().unwrap();
+            match sample.outcome {
+                Outcome::Ok => attempts.remove(&key),
+                Outcome::NotFound | Outcome::Error => attempts.insert(key, sample.attempt),
+            };
+        }
+        let mut pending = self.pending.lock().unwrap();
+        if pending.failed.is_some() {
+            return;
+        }
+        pending.batch.push(sample);
+        if pending.batch.len() >= BATCH_ROWS || pending.flushed.elapsed() >= FLUSH_INTERVAL {
+            pending.flush(&self.path);
+        }
+    }
+
+    /// Write out the samples still pending and close the file.
+    pub fn finish(&self) -> std::result::Result<(), Error> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.flush(&self.path);
+        if let Some(err) = pending.failed.take() {
+            return Err(err.into());
+        }
+        pending
+            .sink
+            .finish()
+            .map_err(|err| format!("failed to finish {}: {}", self.path.display(), err).into())
+    }
+}
+
+impl Pending {
+    fn flush(&mut self, path: &std::path::Path) {
+        if let Err(err) = self.sink.write(&self.batch) {
+            let err = format!("failed to write {}: {}", path.display(), err);
+            eprintln!("warning: {}; dropping later samples", err);
+            self.failed = Some(err);
+        }
+        self.batch.clear();
+        self.flushed = Instant::now();
+    }
+}
+
+/// Microseconds since the Unix epoch.
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64)
+}
+
+/// A read being timed.
+struct Issued {
+    trace: Arc<RequestTrace>,
+    location: Path,
+    offset: u64,
+    attempt: u32,
+    timestamp_us: u64,
+    start: Instant,
+}
+
+impl Issued {
+    fn new(trace: &Arc<RequestTrace>, location: &Path, offset: u64) -> Self {
+        Self {
+            trace: trace.clone(),
+            location: location.clone(),
+            offset,
+            attempt: trace.attempt(location, offset),
+            timestamp_us: now_us(),
+            start: Instant::now(),
+        }
+    }
+
+    fn finished(&self, len: usize, outcome: Outcome) {
+        self.trace.record(
+            &self.location,
+            Sample {
+                timestamp_us: self.timestamp_us,
+                location: self.location.to_string(),
+                offset: self.offset,
+                len: len as u64,
+                latency_us: self.start.elapsed().as_micros() as u64,
+                outcome,
+                attempt: self.attempt,
+            },
+        );
+    }
+
+    /// Record `result`, with the bytes `len` says it returned.
+    fn result<T>(&self, result: Result<T>, len: impl FnOnce(&T) -> usize) -> Result<T> {
+        let received = result.as_ref().map_or(0, len);
+        self.finished(received, Outcome::of(&result));
+        result
+    }
+}
+
+/// The body of a `get_opts`, whose read ends with it: when the stream does,
+/// fails or is dropped.
+struct Body {
+    inner: BoxStream<'static, Result<Bytes>>,
+    issued: Option<Issued>,
+    received: usize,
+    outcome: Outcome,
+}
+
+impl Body {
+    fn finish(&mut self) {
+        if let Some(issued) = self.issued.take() {
+            issued.finished(self.received, self.outcome);
+        }
+    }
+}
+
+impl Stream for Body {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => self.received += chunk.len(),
+            Poll::Ready(Some(Err(_))) => {
+                self.outcome = Outcome::Error;
+                self.finish();
+            }
+            Poll::Ready(None) => self.finish(),
+            Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// An [`ObjectStore`] recording a [`Sample`] of every read of `inner`.
+pub struct RequestTraceStore {
+    inner: Arc<dyn ObjectStore>,
+    trace: Arc<RequestTrace>,
+}
+
+impl RequestTraceStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, trace: Arc<RequestTrace>) -> Self {
+        Self { inner, trace }
+    }
+}
+
+impl Display for RequestTraceStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RequestTraceStore({})", self.inner)
+    }
+}
+
+impl Debug for RequestTraceStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RequestTraceStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for RequestTraceStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        let offset = options.range.as_ref().map_or(0, |range| range.start as u64);
+        let issued = Issued::new(&self.trace, location, offset);
+        match self.inner.get_opts(location, options).await {
+            Ok(result) => Ok(GetResult::Stream(
+                Body {
+                    inner: result.into_stream(),
+                    issued: Some(issued),
+                    received: 0,
+                    outcome: Outcome::Ok,
+                }
+                .boxed(),
+            )),
+            Err(err) => issued.result(Err(err), |_| 0),
+        }
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let issued = Issued::new(&self.trace, location, range.start as u64);
+        let result = self.inner.get_range(location, range).await;
+        issued.result(result, Bytes::len)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let offset = ranges.first().map_or(0, |range| range.start as u64);
+        let issued = Issued::new(&self.trace, location, offset);
+        let result = self.inner.get_ranges(location, ranges).await;
+        issued.result(result, |parts| parts.iter().map(Bytes::len).sum())
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+/// The format of the trace at `path`, or `None` if it holds something else,
+/// such as results.
+pub fn detect(path: &std::path::Path) -> std::result::Result<Option<TraceFormat>, Error> {
+    if path.is_dir() {
+        return Ok(None);
+    }
+    let mut file =
+        File::open(path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut magic = [0; 4];
+    if file.read_exact(&mut magic).is_ok() && &magic == PARQUET_MAGIC {
+        return Ok(Some(TraceFormat::Parquet));
+    }
+    let first = BufReader::new(File::open(path)?)
+        .lines()
+        .map_while(std::io::Result::ok)
+        .find(|line| !line.trim().is_empty());
+    let is_sample = first.is_some_and(|line| serde_json::from_str::<Sample>(&line).is_ok());
+    Ok(is_sample.then_some(TraceFormat::Jsonl))
+}
+
+/// Every sample of the trace at `path`, written in `format`.
+pub fn load(
+    path: &std::path::Path,
+    format: TraceFormat,
+) -> std::result::Result<Vec<Sample>, Error> {
+    match format {
+        TraceFormat::Jsonl => {
+            let reader = BufReader::new(
+                File::open(path)
+                    .map_err(|err| format!("failed to read {}: {}", path.display(), err))?,
+            );
+            let mut samples = Vec::new();
+            for (i, line) in reader.lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let sample = serde_json::from_str(&line).map_err(|err| {
+                    format!("{}:{}: invalid sample: {}", path.display(), i + 1, err)
+                })?;
+                samples.push(sample);
+            }
+            Ok(samples)
+        }
+        #[cfg(feature = "parquet")]
+        TraceFormat::Parquet => parquet_format::read(path),
+        #[cfg(not(feature = "parquet"))]
+        TraceFormat::Parquet => Err(format!(
+            "{} is a Parquet trace, but this build lacks the parquet feature",
+            path.display()
+        )
+        .into()),
+    }
+}
+
+/// Print one row per outcome of the trace at `path`, and one for all reads.
+pub fn report(path: &std::path::Path, format: TraceFormat) -> std::result::Result<(), Error> {
+    let samples = load(path, format)?;
+    println!("outcome\trequests\tretried\tbytes\tp50_us\tp99_us\tmax_us");
+    let rows = [Outcome::Ok, Outcome::NotFound, Outcome::Error]
+        .into_iter()
+        .map(|outcome| (outcome.name(), Some(outcome)))
+        .chain([("all", None)]);
+    for (name, outcome) in rows {
+        let samples = samples
+            .iter()
+            .filter(|sample| outcome.is_none_or(|outcome| sample.outcome == outcome))
+            .collect::<Vec<_>>();
+        if samples.is_empty() && outcome.is_some() {
+            continue;
+        }
+        let mut latencies = samples
+            .iter()
+            .map(|sample| Duration::from_micros(sample.latency_us))
+            .collect::<Vec<_>>();
+        let latency = LatencySummary::from_latencies(&mut latencies);
+        println!(
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            name,
+            samples.len(),
+            samples.iter().filter(|sample| sample.attempt > 1).count(),
+            samples.iter().map(|sample| sample.len).sum::<u64>(),
+            latency.p50_us,
+            latency.p99_us,
+            latency.max_us,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_format {
+    //! Samples as Arrow record batches in Parquet row groups.
+
+    use std::fs::File;
+    use std::sync::Arc;
+
+    use arrow_array::cast::AsArray;
+    use arrow_array::types::{UInt32Type, UInt64Type};
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, UInt32Array, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use parquet::arrow::ArrowWriter;
+    use parquet::basic::Compression;
+    use parquet::file::properties::WriterProperties;
+
+    use super::{Outcome, Sample, BATCH_ROWS};
+    use crate::error::Error;
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("timestamp_us", DataType::UInt64, false),
+            Field::new("location", DataType::Utf8, false),
+            Field::new("offset", DataType::UInt64, false),
+            Field::new("len", DataType::UInt64, false),
+            Field::new("latency_us", DataType::UInt64, false),
+            Field::new("outcome", DataType::Utf8, false),
+            Field::new("attempt", DataType::UInt32, false),
+        ]))
+    }
+
+    fn invalid(err: impl std::fmt::Display) -> Error {
+        Error::Invalid(format!("Parquet trace: {}", err))
+    }
+
+    pub struct Writer {
+        schema: SchemaRef,
+        writer: ArrowWriter<File>,
+    }
+
+    impl Writer {
+        pub fn new(file: File) -> Result<Self, Error> {
+            let properties = WriterProperties::builder()
+                .set_compression(Compression::SNAPPY)
+                .set_max_row_group_size(BATCH_ROWS)
+                .build();
+            let schema = schema();
+            let writer =
+                ArrowWriter::try_new(file, schema.clone(), Some(properties)).map_err(invalid)?;
+            Ok(Self { schema, writer })
+        }
+
+        /// Write `samples` as a row group of their own.
+        pub fn write(&mut self, samples: &[Sample]) -> Result<(), Error> {
+            if samples.is_empty() {
+                return Ok(());
+            }
+            let u64s = |field: fn(&Sample) -> u64| -> ArrayRef {
+                Arc::new(samples.iter().map(field).collect::<UInt64Array>())
+            };
+            let columns: Vec<ArrayRef> = vec![
+                u64s(|sample| sample.timestamp_us),
+                Arc::new(
+                    samples
+                        .iter()
+                        .map(|sample| Some(sample.location.as_str()))
+                        .collect::<StringArray>(),
+                ),
+                u64s(|sample| sample.offset),
+                u64s(|sample| sample.len),
+                u64s(|sample| sample.latency_us),
+                Arc::new(
+                    samples
+                        .iter()
+                        .map(|sample| Some(sample.outcome.name()))
+                        .collect::<StringArray>(),
+                ),
+                Arc::new(
+                    samples
+                        .iter()
+                        .map(|sample| sample.attempt)
+                        .collect::<UInt32Array>(),
+                ),
+            ];
+            let batch = RecordBatch::try_new(self.schema.clone(), columns).map_err(invalid)?;
+            self.writer.write(&batch).map_err(invalid)?;
+            self.writer.flush().map_err(invalid)
+        }
+
+        /// Write the footer.
+        pub fn finish(self) -> Result<(), Error> {
+            self.writer.close().map(drop).map_err(invalid)
+        }
+    }
+
+    pub fn read(path: &std::path::Path) -> Result<Vec<Sample>, Error> {
+        let file = File::open(path)
+            .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+        let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+            .and_then(|builder| builder.build())
+            .map_err(invalid)?;
+        let mut samples = Vec::new();
+        for batch in reader {
+            let batch = batch.map_err(invalid)?;
+            let column = |name: &str| {
+                batch
+                    .column_by_name(name)
+                    .ok_or_else(|| invalid(format!("no {} column", name)))
+            };
+            let u64s = |name: &str| -> Result<_, Error> {
+                column(name)?
+                    .as_primitive_opt::<UInt64Type>()
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("{} is not a UInt64 column", name)))
+            };
+            let strings = |name: &str| -> Result<_, Error> {
+                column(name)?
+                    .as_string_opt::<i32>()
+                    .cloned()
+                    .ok_or_else(|| invalid(format!("{} is not a string column", name)))
+            };
+            let (timestamps, offsets, lens, latencies) = (
+                u64s("timestamp_us")?,
+                u64s("offset")?,
+                u64s("len")?,
+                u64s("latency_us")?,
+            );
+            let (locations, outcomes) = (strings("location")?, strings("outcome")?);
+            let attempts = column("attempt")?
+                .as_primitive_opt::<UInt32Type>()
+                .cloned()
+                .ok_or_else(|| invalid("attempt is not a UInt32 column"))?;
+            for row in 0..batch.num_rows() {
+                let outcome = outcomes.value(row);
+                samples.push(Sample {
+                    timestamp_us: timestamps.value(row),
+                    location: locations.value(row).to_string(),
+                    offset: offsets.value(row),
+                    len: lens.value(row),
+                    latency_us: latencies.value(row),
+                    outcome: Outcome::parse(outcome)
+                        .ok_or_else(|| invalid(format!("unknown outcome {:?}", outcome)))?,
+                    attempt: attempts.value(row),
+                });
+            }
+        }
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use crate::retry::RetryPolicy;
+    use object_store::memory::InMemory;
+
+    fn trace_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!(
+            "object-store-bench-request-trace-{}-{}",
+            name,
+            std::process::id()
+        ))
+    }
+
+    /// Read through a trace in `format`, with some reads failing and retried.
+    async fn traced_reads(path: &std::path::Path, format: TraceFormat) -> Vec<Sample> {
+        let memory: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let location = Path::from("data/a");
+        memory
+            .put(&location, Bytes::from(vec![1; 4096]))
+            .await
+            .unwrap();
+        let faults = FaultConfig::new(&[Fault::ErrorRate(0.3)]);
+        let faulty = Arc::new(FaultStore::new(memory, faults, 5));
+        let trace = Arc::new(RequestTrace::create(path, format).unwrap());
+        let store = RequestTraceStore::new(faulty, trace.clone());
+        let retry = RetryPolicy::new(20, None).with_retry_delay(Duration::ZERO);
+        for i in 0..40 {
+            let range = i * 100..i * 100 + 50;
+            retry
+                .run(|| store.get_range(&location, range.clone()))
+                .await
+                .unwrap();
+        }
+        let whole = retry
+            .run(|| async { store.get(&location).await?.bytes().await })
+            .await
+            .unwrap();
+        assert_eq!(whole.len(), 4096);
+        assert!(store.get(&Path::from("data/b")).await.is_err());
+        trace.finish().unwrap();
+        assert_eq!(detect(path).unwrap(), Some(format));
+        load(path, format).unwrap()
+    }
+
+    fn check(samples: &[Sample]) {
+        let ok = samples
+            .iter()
+            .filter(|sample| sample.outcome == Outcome::Ok)
+            .collect::<Vec<_>>();
+        assert_eq!(ok.len(), 41);
+        assert!(ok[..40].iter().all(|sample| sample.len == 50));
+        assert_eq!(ok[40].len, 4096);
+        let (missing, samples) = samples.split_last().unwrap();
+        let failed = samples
+            .iter()
+            .filter(|sample| sample.outcome == Outcome::Error)
+            .count();
+        assert!(failed > 0);
+        // Every failure was retried, and each retry counted as an attempt.
+        let retried = samples.iter().filter(|sample| sample.attempt > 1).count();
+        assert_eq!(retried, failed);
+        assert_eq!(missing.location, "data/b");
+        assert!(missing.outcome != Outcome::Ok);
+        assert!(samples
+            .windows(2)
+            .all(|pair| pair[0].timestamp_us <= pair[1].timestamp_us));
+    }
+
+    #[tokio::test]
+    async fn jsonl_traces_round_trip() {
+        let path = trace_path("jsonl");
+        let samples = traced_reads(&path, TraceFormat::Jsonl).await;
+        check(&samples);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "parquet")]
+    #[tokio::test]
+    async fn parquet_traces_round_trip() {
+        let jsonl = trace_path("same-jsonl");
+        let parquet = trace_path("parquet");
+        let samples = traced_reads(&parquet, TraceFormat::Parquet).await;
+        check(&samples);
+        // The same reads, with the same faults, trace the same way in JSONL.
+        let same = traced_reads(&jsonl, TraceFormat::Jsonl).await;
+        let without_timing = |samples: &[Sample]| {
+            samples
+                .iter()
+                .map(|sample| {
+                    (
+                        sample.location.clone(),
+                        sample.offset,
+                        sample.len,
+                        sample.outcome,
+                        sample.attempt,
+                    )
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(without_timing(&samples), without_timing(&same));
+        std::fs::remove_file(&jsonl).unwrap();
+        std::fs::remove_file(&parquet).unwrap();
+    }
+
+    #[cfg(not(feature = "parquet"))]
+    #[test]
+    fn parquet_traces_need_the_feature() {
+        let path = trace_path("no-parquet");
+        let err = RequestTrace::create(&path, TraceFormat::Parquet)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("parquet feature"));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn results_are_not_traces() {
+        let path = trace_path("results");
+        std::fs::write(&path, "{\"mode\":\"download\",\"bytes\":10}\n").unwrap();
+        assert_eq!(detect(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
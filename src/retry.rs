@@ -0,0 +1,520 @@
+//! Retry handling shared by all benchmark paths.
+//!
+//! Each request may be retried up to `--max-retries` times. On top of that, an
+//! optional `--retry-budget` bounds the total number of retries across the
+//! whole run, so a degraded backend shows up as failures rather than as a
+//! slow-but-successful benchmark.
+//!
+//! Before each retry the request waits with exponential backoff and full
+//! jitter: a random delay up to `--retry-delay-ms` doubled for every earlier
+//! retry, capped at [`MAX_RETRY_DELAY`]. Benchmarks time a request from its
+//! first attempt to its last, so the waits show up in tail latencies as a
+//! client would see them.
+//!
+//! A benchmark that reads many objects can go on past a request that failed
+//! for good, leaving its bytes out, when [`RetryPolicy::absorb`] allows it:
+//! with `--continue-on-error`, or with retries enabled and a transient error.
+//! Such requests are counted in `failed_requests` and by kind in
+//! `failed_request_kinds`, and the run fails after reporting.
+//!
+//! Every failed attempt, retried or not, is counted by kind. Credential and
+//! permission errors additionally go on a timeline relative to the start of
+//! the run, so an expiring token shows up as a burst of `auth` errors at the
+//! refresh boundary even when retries papered over it.
+//!
+//! With `--adaptive-backoff`, every attempt waits for a slot under the run's
+//! [`AdaptiveBackoff`] limit, and is reported to it marked by whether the
+//! store throttled it.
+//!
+//! The policy also carries `--missing-objects`, which benchmarks apply to a
+//! `NotFound` for an object they listed; see [`crate::missing`].
+
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use rand::Rng;
+
+use crate::backoff::AdaptiveBackoff;
+use crate::experiment::{fields, Fields};
+use crate::missing::MissingObjects;
+
+/// Most auth errors kept on the timeline; the count is always complete.
+const MAX_AUTH_TIMELINE: usize = 100;
+
+/// Longest wait before any one retry.
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Total number of retries allowed across every request in a run.
+#[derive(Debug)]
+pub struct RetryBudget {
+    limit: usize,
+    used: AtomicUsize,
+    start: Instant,
+    /// Microseconds since `start` at which the budget ran out, or `u64::MAX`.
+    exhausted_at_us: AtomicU64,
+}
+
+impl RetryBudget {
+    pub fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            start: Instant::now(),
+            exhausted_at_us: AtomicU64::new(u64::MAX),
+        }
+    }
+
+    /// Try to take one retry from the budget. Returns false once it is exhausted.
+    fn try_acquire(&self) -> bool {
+        let acquired = self
+            .used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                (used < self.limit).then_some(used + 1)
+            })
+            .is_ok();
+        if !acquired {
+            let now = self.start.elapsed().as_micros() as u64;
+            if self
+                .exhausted_at_us
+                .compare_exchange(u64::MAX, now, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                eprintln!(
+                    "retry budget of {} exhausted after {:.3}s; further failures are permanent",
+                    self.limit,
+                    now as f64 / 1_000_000.0
+                );
+            }
+        }
+        acquired
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Time since the start of the run at which the budget ran out, if it did.
+    pub fn exhausted_at_us(&self) -> Option<u64> {
+        match self.exhausted_at_us.load(Ordering::SeqCst) {
+            u64::MAX => None,
+            us => Some(us),
+        }
+    }
+}
+
+/// Failed attempts seen by a policy, by kind.
+#[derive(Debug)]
+struct ErrorLog {
+    start: Instant,
+    kinds: Mutex<BTreeMap<&'static str, usize>>,
+    /// Attempts failed by a [`crate::fault::FaultStore`] rather than the store
+    injected: AtomicUsize,
+    /// Microseconds since `start` of each auth error, and whether it was retried
+    auth: Mutex<Vec<(u128, bool)>>,
+}
+
+impl Default for ErrorLog {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            kinds: Mutex::default(),
+            injected: AtomicUsize::new(0),
+            auth: Mutex::default(),
+        }
+    }
+}
+
+impl ErrorLog {
+    fn record(&self, err: &object_store::Error, retried: bool) {
+        let kind = error_kind(err);
+        *self.kinds.lock().unwrap().entry(kind).or_default() += 1;
+        if crate::fault::is_injected(err) {
+            self.injected.fetch_add(1, Ordering::SeqCst);
+        }
+        if kind == "auth" {
+            self.auth
+                .lock()
+                .unwrap()
+                .push((self.start.elapsed().as_micros(), retried));
+        }
+    }
+}
+
+/// Per-request retry limit plus the optional run-wide budget.
+#[derive(Debug, Clone, Default)]
+pub struct RetryPolicy {
+    pub max_retries: usize,
+    pub budget: Option<Arc<RetryBudget>>,
+    /// Longest wait before the first retry, doubling for each one after it
+    pub retry_delay: Duration,
+    retries: Arc<AtomicUsize>,
+    /// Requests the run went on without, by kind
+    failed: Arc<Mutex<BTreeMap<&'static str, usize>>>,
+    errors: Arc<ErrorLog>,
+    backoff: Option<Arc<AdaptiveBackoff>>,
+    /// What a `NotFound` for a listed object does
+    pub missing_objects: MissingObjects,
+    /// Go on past any failed request rather than only transient ones
+    pub continue_on_error: bool,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: usize, budget: Option<usize>) -> Self {
+        Self {
+            max_retries,
+            budget: budget.map(|limit| Arc::new(RetryBudget::new(limit))),
+            retry_delay: Duration::ZERO,
+            retries: Arc::new(AtomicUsize::new(0)),
+            failed: Arc::default(),
+            errors: Arc::default(),
+            backoff: None,
+            missing_objects: MissingObjects::Fail,
+            continue_on_error: false,
+        }
+    }
+
+    /// Hold attempts to the limit `backoff` sets, and report their outcomes to it.
+    pub fn with_backoff(mut self, backoff: Option<Arc<AdaptiveBackoff>>) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Wait up to `retry_delay`, doubling for each retry, before retrying.
+    pub fn with_retry_delay(mut self, retry_delay: Duration) -> Self {
+        self.retry_delay = retry_delay;
+        self
+    }
+
+    /// Go on past requests that fail for any reason, see [`Self::absorb`].
+    pub fn with_continue_on_error(mut self, continue_on_error: bool) -> Self {
+        self.continue_on_error = continue_on_error;
+        self
+    }
+
+    /// Apply `missing_objects` to objects found deleted partway through a run.
+    pub fn with_missing_objects(mut self, missing_objects: MissingObjects) -> Self {
+        self.missing_objects = missing_objects;
+        self
+    }
+
+    /// Total retries issued so far under this policy.
+    pub fn retries(&self) -> usize {
+        self.retries.load(Ordering::SeqCst)
+    }
+
+    /// Requests the run went on without so far.
+    pub fn failed_requests(&self) -> usize {
+        self.failed.lock().unwrap().values().sum()
+    }
+
+    /// Whether the run can go on without a request that failed with `err`,
+    /// counting it if so. With `continue_on_error` any error is absorbed;
+    /// otherwise only a transient one, and only once retries have been tried.
+    pub fn absorb(&self, err: &(dyn std::error::Error + 'static)) -> bool {
+        let store_error = err
+            .downcast_ref::<object_store::Error>()
+            .or_else(|| match err.downcast_ref::<crate::Error>() {
+                Some(crate::Error::Store(err)) => Some(err),
+                _ => None,
+            });
+        let transient = store_error.is_some_and(is_retryable);
+        if !(self.continue_on_error || (self.max_retries > 0 && transient)) {
+            return false;
+        }
+        let kind = store_error.map_or("other", error_kind);
+        *self.failed.lock().unwrap().entry(kind).or_default() += 1;
+        true
+    }
+
+    /// After reporting, fail a run that went on without some requests.
+    pub fn enforce(&self) -> Result<(), crate::Error> {
+        match self.failed_requests() {
+            0 => Ok(()),
+            failed => Err(crate::Error::FailedRequests { failed }),
+        }
+    }
+
+    /// The wait before retry number `retry`, counting from one: uniform up
+    /// to `retry_delay * 2^(retry - 1)`, capped at [`MAX_RETRY_DELAY`].
+    pub fn delay(&self, retry: usize) -> Duration {
+        let ceiling = self
+            .retry_delay
+            .saturating_mul(1 << (retry - 1).min(31))
+            .min(MAX_RETRY_DELAY);
+        if ceiling.is_zero() {
+            return Duration::ZERO;
+        }
+        ceiling.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// Run `f`, retrying transient failures while both the per-request limit
+    /// and the shared budget allow it.
+    pub async fn run<T, F, Fut>(&self, f: F) -> object_store::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = object_store::Result<T>>,
+    {
+        self.attempt(f, true).await
+    }
+
+    /// Like [`Self::run`], for a request whose `NotFound` is an answer rather
+    /// than an error, such as a `head` checking whether a location is an
+    /// object. Such a miss is not counted in `error_kinds`.
+    pub async fn probe<T, F, Fut>(&self, f: F) -> object_store::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = object_store::Result<T>>,
+    {
+        self.attempt(f, false).await
+    }
+
+    async fn attempt<T, F, Fut>(&self, mut f: F, log_not_found: bool) -> object_store::Result<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = object_store::Result<T>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let result = match &self.backoff {
+                Some(backoff) => {
+                    let _slot = backoff.acquire().await;
+                    let result = f().await;
+                    backoff.observe(matches!(&result, Err(err) if is_throttle(err)));
+                    result
+                }
+                None => f().await,
+            };
+            match result {
+                Ok(value) => return Ok(value),
+                Err(err) if attempt < self.max_retries && is_retryable(&err) => {
+                    if let Some(budget) = &self.budget {
+                        if !budget.try_acquire() {
+                            self.errors.record(&err, false);
+                            return Err(err);
+                        }
+                    }
+                    self.errors.record(&err, true);
+                    attempt += 1;
+                    self.retries.fetch_add(1, Ordering::SeqCst);
+                    let delay = self.delay(attempt);
+                    if !delay.is_zero() {
+                        tokio::time::sleep(delay).await;
+                    }
+                }
+                Err(err) => {
+                    if log_not_found || !matches!(err, object_store::Error::NotFound { .. }) {
+                        self.errors.record(&err, false);
+                    }
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    /// Fields describing retry usage.
+    pub fn json_fields(&self) -> Fields {
+        let budget = self.budget.as_ref();
+        let kinds = self.errors.kinds.lock().unwrap().clone();
+        let injected = self.errors.injected.load(Ordering::SeqCst);
+        let real = kinds.values().sum::<usize>() - injected;
+        let auth = self.errors.auth.lock().unwrap();
+        let timeline = auth
+            .iter()
+            .take(MAX_AUTH_TIMELINE)
+            .map(|(at_us, retried)| serde_json::json!({"at_us": at_us, "retried": retried}))
+            .collect::<Vec<_>>();
+        let mut retry = fields(serde_json::json!({
+            "max_retries": self.max_retries,
+            "retry_delay_ms": self.retry_delay.as_millis() as u64,
+            "retries": self.retries(),
+            "failed_requests": self.failed_requests(),
+            "failed_request_kinds": *self.failed.lock().unwrap(),
+            "retry_budget": budget.map(|budget| budget.limit()),
+            "retry_budget_used": budget.map(|budget| budget.used()),
+            "retry_budget_exhausted_us": budget.and_then(|budget| budget.exhausted_at_us()),
+            "error_kinds": kinds,
+            "injected_errors": injected,
+            "real_errors": real,
+            "auth_errors": auth.len(),
+            "auth_error_timeline": timeline,
+        }));
+        if let Some(backoff) = &self.backoff {
+            retry.insert("adaptive_backoff".to_string(), backoff.to_json());
+        }
+        retry
+    }
+}
+
+/// Errors that describe the request rather than the backend's health are not retried.
+pub fn is_retryable(err: &object_store::Error) -> bool {
+    !matches!(
+        err,
+        object_store::Error::NotFound { .. }
+            | object_store::Error::InvalidPath { .. }
+            | object_store::Error::NotSupported { .. }
+            | object_store::Error::AlreadyExists { .. }
+            | object_store::Error::Precondition { .. }
+            | object_store::Error::NotModified { .. }
+            | object_store::Error::NotImplemented
+            | object_store::Error::UnknownConfigurationKey { .. }
+    )
+}
+
+/// Whether the error, or anything in its source chain, looks like a timeout.
+pub fn is_timeout(err: &object_store::Error) -> bool {
+    mentions_any(err, &["timed out", "timeout"])
+}
+
+/// Whether the error looks like a rejected, expired or missing credential.
+///
+/// object_store reports these as generic errors, so this goes by the
+/// messages the HTTP client and the backends use.
+pub fn is_auth(err: &object_store::Error) -> bool {
+    mentions_any(
+        err,
+        &[
+            "unauthorized",
+            "forbidden",
+            "access denied",
+            "accessdenied",
+            "expiredtoken",
+            "token has expired",
+            "invalidaccesskeyid",
+            "invalidtoken",
+            "signaturedoesnotmatch",
+            "credential",
+            "permission denied",
+        ],
+    )
+}
+
+/// Whether the error looks like the store asking for fewer requests:
+/// S3's `SlowDown`, a 503 or a 429.
+pub fn is_throttle(err: &object_store::Error) -> bool {
+    mentions_any(
+        err,
+        &[
+            "slowdown",
+            "slow down",
+            "503 service unavailable",
+            "429 too many requests",
+            "too many requests",
+            "reduce your request rate",
+            "rate exceeded",
+            "throttl",
+            "rate limit",
+        ],
+    )
+}
+
+/// Kind of a failed request, as counted in `error_kinds`.
+pub fn error_kind(err: &object_store::Error) -> &'static str {
+    match err {
+        object_store::Error::NotFound { .. } => "not_found",
+        object_store::Error::Precondition { .. } | object_store::Error::NotModified { .. } => {
+            "precondition"
+        }
+        err if is_throttle(err) => "throttle",
+        err if is_auth(err) => "auth",
+        err if is_timeout(err) => "timeout",
+        _ => "other",
+    }
+}
+
+/// Whether any message in the error's source chain contains one of `needles`.
+fn mentions_any(err: &object_store::Error, needles: &[&str]) -> bool {
+    let mut source: Option<&dyn std::error::Error> = Some(err);
+    while let Some(err) = source {
+        let message = err.to_string().to_lowercase();
+        if needles.iter().any(|needle| message.contains(needle)) {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transient() -> object_store::Error {
+        object_store::Error::Generic {
+            store: "test",
+            source: "503 Service Unavailable".into(),
+        }
+    }
+
+    #[test]
+    fn retry_delays_double_up_to_the_cap() {
+        let retry = RetryPolicy::new(20, None).with_retry_delay(Duration::from_millis(100));
+        for (n, ceiling_ms) in [(1, 100), (2, 200), (4, 800), (20, 10_000)] {
+            let delays = (0..50).map(|_| retry.delay(n)).collect::<Vec<_>>();
+            assert!(delays
+                .iter()
+                .all(|d| *d <= Duration::from_millis(ceiling_ms)));
+            // Jitter spreads the waits rather than sending every retry at once.
+            assert!(delays.iter().any(|d| *d != delays[0]));
+        }
+        assert_eq!(RetryPolicy::new(3, None).delay(3), Duration::ZERO);
+    }
+
+    #[test]
+    fn absorbs_transient_errors_once_retries_are_enabled() {
+        let not_found = object_store::Error::NotFound {
+            path: "a".to_string(),
+            source: "gone".into(),
+        };
+        let retry = RetryPolicy::new(0, None);
+        assert!(!retry.absorb(&transient()));
+        let retry = RetryPolicy::new(2, None);
+        assert!(retry.absorb(&transient()));
+        assert!(!retry.absorb(&not_found));
+        // A store error the library has already wrapped is still transient.
+        assert!(retry.absorb(&crate::Error::Store(transient())));
+        assert_eq!(retry.failed_requests(), 2);
+
+        // With continue_on_error every failure is absorbed, by kind.
+        let retry = RetryPolicy::new(0, None).with_continue_on_error(true);
+        assert!(retry.absorb(&transient()));
+        assert!(retry.absorb(&not_found));
+        let join_error: Box<dyn std::error::Error> = "task panicked".into();
+        assert!(retry.absorb(join_error.as_ref()));
+        assert_eq!(retry.failed_requests(), 3);
+        assert_eq!(
+            retry.json_fields()["failed_request_kinds"],
+            serde_json::json!({"not_found": 1, "other": 1, "throttle": 1})
+        );
+        assert!(retry.enforce().is_err());
+        assert!(RetryPolicy::new(0, None).enforce().is_ok());
+    }
+
+    #[tokio::test]
+    async fn retries_stop_once_the_budget_is_spent() {
+        // Five retries each, but three for the whole run.
+        let retry = RetryPolicy::new(5, Some(3));
+        let attempts = AtomicUsize::new(0);
+        let failing = || async {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            Err::<(), _>(transient())
+        };
+        assert!(retry.run(failing).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 4);
+        // With the budget spent, the next request fails on its first attempt.
+        assert!(retry.run(failing).await.is_err());
+        assert_eq!(attempts.load(Ordering::SeqCst), 5);
+        assert_eq!(retry.retries(), 3);
+        let fields = retry.json_fields();
+        assert_eq!(fields["retry_budget"], 3);
+        assert_eq!(fields["retry_budget_used"], 3);
+        assert!(fields["retry_budget_exhausted_us"].is_u64());
+        assert!(RetryPolicy::new(5, None).json_fields()["retry_budget_exhausted_us"].is_null());
+    }
+}
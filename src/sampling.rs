@@ -0,0 +1,160 @@
+//! Benchmarking a sample of a large listing, with `--max-objects`.
+//!
+//! A prefix of 50,000 objects takes far longer to read than a representative
+//! few hundred. `--max-objects N` keeps at most `N` of the listed objects, the
+//! first `N` in listing order or, with `--sample random`, `N` drawn uniformly
+//! by `--sample-seed` and kept in listing order. The sample is taken after
+//! `--filter` and before a sharded run splits the objects between workers, so
+//! every worker of a run agrees on it. Results record under `sample` how many
+//! objects were listed and how many were sampled.
+
+use std::sync::Mutex;
+
+use object_store::ObjectMeta;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+
+use crate::experiment::Fields;
+
+/// Which of the listed objects `--max-objects` keeps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SampleKind {
+    /// The first objects in listing order
+    #[default]
+    First,
+    /// Objects drawn uniformly at random
+    Random,
+}
+
+impl SampleKind {
+    fn name(self) -> &'static str {
+        match self {
+            SampleKind::First => "first",
+            SampleKind::Random => "random",
+        }
+    }
+}
+
+/// How listings are sampled down to `--max-objects`.
+#[derive(Debug)]
+pub struct Sampling {
+    max_objects: usize,
+    kind: SampleKind,
+    seed: u64,
+    /// Objects listed and sampled, once a listing has been sampled
+    counts: Mutex<Option<(usize, usize)>>,
+}
+
+impl Sampling {
+    pub fn new(max_objects: usize, kind: SampleKind, seed: u64) -> Self {
+        Self {
+            max_objects,
+            kind,
+            seed,
+            counts: Mutex::new(None),
+        }
+    }
+
+    /// Keep at most `max_objects` of `objects`.
+    pub fn apply(&self, objects: Vec<ObjectMeta>) -> Vec<ObjectMeta> {
+        let listed = objects.len();
+        let objects = sample(objects, self.max_objects, self.kind, self.seed);
+        *self.counts.lock().unwrap() = Some((listed, objects.len()));
+        objects
+    }
+}
+
+fn sample(
+    mut objects: Vec<ObjectMeta>,
+    max_objects: usize,
+    kind: SampleKind,
+    seed: u64,
+) -> Vec<ObjectMeta> {
+    if objects.len() <= max_objects {
+        return objects;
+    }
+    match kind {
+        SampleKind::First => {
+            objects.truncate(max_objects);
+            objects
+        }
+        SampleKind::Random => {
+            // Listing order can differ between stores, so draw from a sorted
+            // copy for a seed to pick the same objects everywhere.
+            objects.sort_by(|a, b| a.location.cmp(&b.location));
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut picked =
+                rand::seq::index::sample(&mut rng, objects.len(), max_objects).into_vec();
+            picked.sort_unstable();
+            let mut picked = picked.into_iter().peekable();
+            objects
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| picked.next_if_eq(i).is_some())
+                .map(|(_, meta)| meta)
+                .collect()
+        }
+    }
+}
+
+/// Append how many objects `sampling` listed and sampled to the JSON object
+/// `result`.
+pub fn with_sample(result: &mut Fields, sampling: &Sampling) {
+    let Some((listed, sampled)) = *sampling.counts.lock().unwrap() else {
+        return;
+    };
+    let sample = serde_json::json!({
+        "max_objects": sampling.max_objects,
+        "kind": sampling.kind.name(),
+        "seed": sampling.seed,
+        "listed_objects": listed,
+        "sampled_objects": sampled,
+    });
+    result.insert("sample".to_string(), sample);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::path::Path;
+
+    fn objects(n: usize) -> Vec<ObjectMeta> {
+        (0..n)
+            .map(|i| ObjectMeta {
+                location: Path::from(format!("data/{:02}", i)),
+                last_modified: chrono::Utc::now(),
+                size: 1,
+                e_tag: None,
+            })
+            .collect()
+    }
+
+    fn names(objects: Vec<ObjectMeta>) -> Vec<String> {
+        objects
+            .into_iter()
+            .map(|meta| meta.location.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn first_keeps_a_prefix_of_the_listing() {
+        assert_eq!(
+            names(sample(objects(10), 3, SampleKind::First, 0)),
+            ["data/00", "data/01", "data/02"]
+        );
+        assert_eq!(sample(objects(2), 3, SampleKind::First, 0).len(), 2);
+    }
+
+    #[test]
+    fn random_samples_repeat_with_the_seed_and_keep_listing_order() {
+        let first = names(sample(objects(50), 10, SampleKind::Random, 7));
+        assert_eq!(first.len(), 10);
+        let mut sorted = first.clone();
+        sorted.sort();
+        assert_eq!(first, sorted);
+        let mut reversed = objects(50);
+        reversed.reverse();
+        assert_eq!(first, names(sample(reversed, 10, SampleKind::Random, 7)));
+        assert_ne!(first, names(sample(objects(50), 10, SampleKind::Random, 8)));
+    }
+}
@@ -0,0 +1,304 @@
+//! Repeating a benchmark on a fixed schedule in one long-lived process.
+//!
+//! With `--repeat-every-secs N --repeat-count M`, the benchmark runs M times,
+//! each run starting N seconds after the previous one was scheduled to, so
+//! lateness doesn't accumulate the way it does under cron. Keeping the process
+//! alive also keeps the client's connections warm between runs, unless
+//! `--fresh-client-each-run` asks for a new client every time.
+//!
+//! Each run's result is emitted as it finishes, with its place in the
+//! schedule under `schedule`, and a summary follows the last one. A run that
+//! lasts past the next start is reported as an overrun and the starts it
+//! covered are skipped rather than queued. Ctrl-C between runs ends the
+//! schedule at once; during a run it stops new requests, so that run's result
+//! is still reported. A second Ctrl-C exits immediately.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+
+use crate::control::RunControl;
+use crate::experiment::Fields;
+
+/// Where one run fell in the schedule.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct ScheduledRun {
+    run: usize,
+    /// Offset of the scheduled start from the first run's
+    scheduled: Duration,
+    /// How long after its scheduled start the run began
+    start_lag: Duration,
+    fresh_client: bool,
+}
+
+/// A run that was still going when the next one should have started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Overrun {
+    run: usize,
+    /// How far past the next scheduled start it finished
+    overran_by: Duration,
+    /// Scheduled starts it covered, which were dropped
+    skipped: u32,
+}
+
+/// The first scheduled start at or after `elapsed` since the first run's,
+/// with starts every `every`, as an index into the schedule.
+fn next_slot(elapsed: Duration, every: Duration) -> u32 {
+    elapsed.as_micros().div_ceil(every.as_micros().max(1)) as u32
+}
+
+/// Run `iteration` `count` times, starting every `every`, then emit a
+/// summary. `iteration` is passed the index of the run.
+pub async fn on_schedule<F, Fut>(
+    every: Duration,
+    count: usize,
+    fresh_client: bool,
+    control: &RunControl,
+    mut iteration: F,
+) where
+    F: FnMut(usize) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let start = Instant::now();
+    let mut slot: u32 = 0;
+    let mut runs = Vec::new();
+    let mut overruns = Vec::new();
+    while runs.len() < count && !control.is_shutdown() {
+        let scheduled = every * slot;
+        let waited = tokio::select! {
+            _ = tokio::time::sleep_until((start + scheduled).into()) => true,
+            _ = control.stopped() => false,
+        };
+        if !waited {
+            break;
+        }
+        let run = ScheduledRun {
+            run: runs.len(),
+            scheduled,
+            start_lag: start.elapsed().saturating_sub(scheduled),
+            fresh_client,
+        };
+        *control.context().scheduled.lock().unwrap() = Some(run);
+        control.reset_paused();
+        iteration(run.run).await;
+        runs.push(run);
+
+        let finished = start.elapsed();
+        let next = next_slot(finished, every).max(slot + 1);
+        if next > slot + 1 {
+            let overrun = Overrun {
+                run: run.run,
+                overran_by: finished - every * (slot + 1),
+                skipped: next - slot - 1,
+            };
+            eprintln!(
+                "warning: run {} overran its {:?} interval by {:?}; skipping {} scheduled start(s)",
+                overrun.run, every, overrun.overran_by, overrun.skipped
+            );
+            overruns.push(overrun);
+        }
+        slot = next;
+    }
+    *control.context().scheduled.lock().unwrap() = None;
+
+    let overruns_json = overruns
+        .iter()
+        .map(|o| {
+            serde_json::json!({
+                "run": o.run,
+                "overran_by_us": o.overran_by.as_micros() as u64,
+                "skipped_starts": o.skipped,
+            })
+        })
+        .collect::<Vec<_>>();
+    control.emit(serde_json::json!({
+        "mode": "schedule",
+        "every_secs": every.as_secs_f64(),
+        "repeat_count": count,
+        "runs": runs.len(),
+        "fresh_client_each_run": fresh_client,
+        "max_start_lag_us": runs.iter().map(|r| r.start_lag.as_micros() as u64).max().unwrap_or(0),
+        "overruns": overruns_json,
+        "skipped_starts": overruns.iter().map(|o| o.skipped).sum::<u32>(),
+        "elapsed_us": start.elapsed().as_micros() as u64,
+        "interrupted": control.is_shutdown(),
+    }));
+}
+
+/// Append the place of `run` in the schedule to the JSON object `result`.
+pub(crate) fn with_run(result: &mut Fields, run: &ScheduledRun) {
+    result.insert(
+        "schedule".to_string(),
+        serde_json::json!({
+            "run": run.run,
+            "scheduled_s": run.scheduled.as_secs_f64(),
+            "start_lag_us": run.start_lag.as_micros() as u64,
+            "fresh_client": run.fresh_client,
+        }),
+    );
+}
+
+/// Builds the client for the slot it is given.
+pub type MakeClient = Box<dyn Fn(usize) -> Result<Arc<dyn ObjectStore>> + Send + Sync>;
+
+/// An [`ObjectStore`] that can swap its client for a newly built one, so
+/// each run starts without pooled connections.
+///
+/// Listings borrow the client they came from, so replaced clients are kept
+/// until the store is dropped rather than freed mid-listing. There is a slot
+/// for each run, filled as the run starts.
+pub struct FreshClientStore {
+    make: MakeClient,
+    clients: Box<[OnceLock<Arc<dyn ObjectStore>>]>,
+    current: AtomicUsize,
+}
+
+impl FreshClientStore {
+    /// A store building its clients with `make`, with room for `runs` of them.
+    pub fn new(make: MakeClient, runs: usize) -> Result<Self> {
+        let clients = (0..runs.max(1))
+            .map(|_| OnceLock::new())
+            .collect::<Box<[_]>>();
+        let _ = clients[0].set(make(0)?);
+        Ok(Self {
+            make,
+            clients,
+            current: AtomicUsize::new(0),
+        })
+    }
+
+    /// Use a new client for run `run` and everything after it.
+    pub fn renew(&self, run: usize) -> Result<()> {
+        let Some(slot) = self.clients.get(run) else {
+            return Err(object_store::Error::Generic {
+                store: "fresh_client",
+                source: format!("no client slot for run {}", run).into(),
+            });
+        };
+        if slot.get().is_none() {
+            let _ = slot.set((self.make)(run)?);
+        }
+        self.current.store(run, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn client(&self) -> &Arc<dyn ObjectStore> {
+        self.clients[self.current.load(Ordering::SeqCst)]
+            .get()
+            .expect("the current slot is always filled")
+    }
+}
+
+impl Display for FreshClientStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "FreshClientStore({})", self.client())
+    }
+}
+
+impl Debug for FreshClientStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FreshClientStore")
+            .field("client", self.client())
+            .field("current", &self.current)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for FreshClientStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.client().put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.client().put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.client().abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.client().get_opts(location, options).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.client().get_range(location, range).await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.client().get_ranges(location, ranges).await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.client().head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.client().delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.client().list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.client().list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.client().copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.client().copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[test]
+    fn starts_stay_on_the_grid() {
+        let every = Duration::from_secs(600);
+        assert_eq!(next_slot(Duration::ZERO, every), 0);
+        // A run finishing late in its interval still starts the next on time.
+        assert_eq!(next_slot(Duration::from_secs(599), every), 1);
+        assert_eq!(next_slot(Duration::from_secs(600), every), 1);
+        // An overrun lands on the next grid point, not 600s after it ended.
+        assert_eq!(next_slot(Duration::from_secs(1201), every), 3);
+    }
+
+    #[tokio::test]
+    async fn renewing_swaps_the_client() {
+        let store = FreshClientStore::new(Box::new(|_| Ok(Arc::new(InMemory::new()))), 2).unwrap();
+        store
+            .put(&Path::from("a"), Bytes::from_static(b"a"))
+            .await
+            .unwrap();
+        store.renew(0).unwrap();
+        assert!(store.head(&Path::from("a")).await.is_ok());
+        store.renew(1).unwrap();
+        assert!(store.head(&Path::from("a")).await.is_err());
+        assert!(store.renew(2).is_err());
+    }
+}
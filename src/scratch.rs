@@ -0,0 +1,255 @@
+//! Temporary objects written by benchmarks, and removing them afterwards.
+//!
+//! A benchmark that needs objects of its own works in a [`ScratchArea`]: a
+//! prefix under the location named for the benchmark and a fresh run id, so
+//! concurrent runs never share objects. The area hands out paths under its
+//! prefix and remembers them. [`ScratchArea::run`] deletes every remembered
+//! path, and anything else listed under the prefix, once the benchmark
+//! finishes, whether it succeeded, failed or was interrupted with Ctrl-C.
+//! `--keep-scratch` leaves the objects in place for inspection.
+
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+
+use crate::control::RunControl;
+use crate::naming::generate_run_id;
+use crate::retry::RetryPolicy;
+
+/// Deletes running at once while cleaning up.
+const PARALLEL_DELETES: usize = 16;
+
+/// What cleaning up a scratch area did.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ScratchSummary {
+    pub deleted: usize,
+    /// Objects that could not be deleted, and are left behind
+    pub failed: usize,
+    /// Whether `--keep-scratch` left everything in place
+    pub kept: bool,
+}
+
+impl ScratchSummary {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "deleted": self.deleted,
+            "failed": self.failed,
+            "kept": self.kept,
+        })
+    }
+}
+
+/// A prefix of temporary objects belonging to one run.
+pub struct ScratchArea {
+    object_store: Arc<dyn ObjectStore>,
+    root: Path,
+    run_id: String,
+    keep: bool,
+    created: Mutex<BTreeSet<Path>>,
+}
+
+impl ScratchArea {
+    /// A new area at `<location>/<name>-<run id>`. With `keep`, its objects
+    /// are not deleted.
+    pub fn new(
+        object_store: Arc<dyn ObjectStore>,
+        location: &Path,
+        name: &str,
+        keep: bool,
+    ) -> Self {
+        let run_id = generate_run_id();
+        let root = Path::from_iter(
+            location
+                .parts()
+                .chain(std::iter::once(format!("{}-{}", name, run_id).into())),
+        );
+        Self {
+            object_store,
+            root,
+            run_id,
+            keep,
+            created: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// A path `name` under the area, remembered for deletion. `name` may
+    /// contain `/` to nest it.
+    pub fn path(&self, name: &str) -> Path {
+        let path = Path::from_iter(self.root.parts().chain(Path::from(name).parts()));
+        self.created.lock().unwrap().insert(path.clone());
+        path
+    }
+
+    /// Run `work`, then delete the area's objects, even if `work` failed.
+    ///
+    /// Ctrl-C during `work` shuts `control` down, abandons `work` and returns
+    /// `None` once the area is cleaned up; a second Ctrl-C exits without
+    /// cleaning up.
+    pub async fn run<F, T>(
+        &self,
+        control: &RunControl,
+        retry: &RetryPolicy,
+        work: F,
+    ) -> (Option<T>, ScratchSummary)
+    where
+        F: Future<Output = T>,
+    {
+        let outcome = tokio::select! {
+            outcome = work => Some(outcome),
+            Ok(()) = tokio::signal::ctrl_c() => {
+                eprintln!(
+                    "interrupted: deleting scratch objects under {}; press Ctrl-C again to leave them",
+                    self.root
+                );
+                control.shutdown();
+                None
+            }
+        };
+        let summary = tokio::select! {
+            summary = self.clean_up(retry) => summary,
+            Ok(()) = tokio::signal::ctrl_c() => crate::control::exit_interrupted(),
+        };
+        (outcome, summary)
+    }
+
+    /// Delete every path handed out and everything listed under the root.
+    /// Failures are counted and reported, not returned.
+    pub async fn clean_up(&self, retry: &RetryPolicy) -> ScratchSummary {
+        if self.keep {
+            eprintln!("keeping scratch objects under {}", self.root);
+            return ScratchSummary {
+                kept: true,
+                ..ScratchSummary::default()
+            };
+        }
+        let mut objects = self.created.lock().unwrap().clone();
+        match retry
+            .run(|| async {
+                self.object_store
+                    .list(Some(&self.root))
+                    .await?
+                    .map_ok(|meta| meta.location)
+                    .try_collect::<Vec<_>>()
+                    .await
+            })
+            .await
+        {
+            Ok(listed) => objects.extend(listed),
+            Err(err) => eprintln!(
+                "warning: listing scratch prefix {} failed: {}",
+                self.root, err
+            ),
+        }
+
+        let outcomes = futures::stream::iter(objects)
+            .map(|object| async move {
+                match retry.run(|| self.object_store.delete(&object)).await {
+                    // Paths handed out but never written are already gone.
+                    Ok(()) | Err(object_store::Error::NotFound { .. }) => true,
+                    Err(err) => {
+                        eprintln!(
+                            "warning: deleting scratch object {} failed: {}",
+                            object, err
+                        );
+                        false
+                    }
+                }
+            })
+            .buffer_unordered(PARALLEL_DELETES)
+            .collect::<Vec<_>>()
+            .await;
+        let deleted = outcomes.iter().filter(|deleted| **deleted).count();
+        ScratchSummary {
+            deleted,
+            failed: outcomes.len() - deleted,
+            kept: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    async fn listed(store: &dyn ObjectStore, prefix: &Path) -> Vec<Path> {
+        store
+            .list(Some(prefix))
+            .await
+            .unwrap()
+            .map_ok(|meta| meta.location)
+            .try_collect()
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_work_is_cleaned_up() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let bystander = Path::from("base/other.bin");
+        store
+            .put(&bystander, Bytes::from_static(b"keep"))
+            .await
+            .unwrap();
+        let area = ScratchArea::new(store.clone(), &Path::from("base"), "churn", false);
+        assert!(area.root().as_ref().starts_with("base/churn-"));
+
+        let (outcome, summary) = area
+            .run(&RunControl::new(), &RetryPolicy::new(0, None), async {
+                store
+                    .put(&area.path("a.bin"), Bytes::from_static(b"a"))
+                    .await?;
+                // Written under the prefix without asking the area.
+                store
+                    .put(
+                        &area.root().child("untracked.bin"),
+                        Bytes::from_static(b"b"),
+                    )
+                    .await?;
+                let _never_written = area.path("nested/c.bin");
+                Err::<(), _>(object_store::Error::NotImplemented)
+            })
+            .await;
+
+        assert!(outcome.unwrap().is_err());
+        assert_eq!(
+            summary,
+            ScratchSummary {
+                deleted: 3,
+                failed: 0,
+                kept: false
+            }
+        );
+        assert!(listed(store.as_ref(), area.root()).await.is_empty());
+        assert_eq!(
+            listed(store.as_ref(), &Path::from("base")).await,
+            vec![bystander]
+        );
+    }
+
+    #[tokio::test]
+    async fn kept_areas_are_left_alone() {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let area = ScratchArea::new(store.clone(), &Path::from("base"), "churn", true);
+        let path = area.path("a.bin");
+        let (_, summary) = area
+            .run(&RunControl::new(), &RetryPolicy::new(0, None), async {
+                store.put(&path, Bytes::from_static(b"a")).await.unwrap();
+            })
+            .await;
+        assert!(summary.kept);
+        assert_eq!(listed(store.as_ref(), area.root()).await, vec![path]);
+    }
+}
@@ -0,0 +1,206 @@
+//! Full sequential read of every object, optionally verifying digests.
+//!
+//! Each object is fetched with a plain `get` and its body stream drained. With
+//! `--digests`, the SHA-256 of each body is compared against the audit file
+//! written at upload time. With `--manifest`, the objects of one upload are
+//! read from its manifest instead of listing the location. Reads are also
+//! reported by object size class, under `by_size_bucket`.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+use sha2::{Digest, Sha256};
+
+use crate::accounting::Accounting;
+use crate::control::RunControl;
+use crate::digest::{hex, load_digests, ExpectedDigest};
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::manifest::UploadManifest;
+use crate::missing::MissingTracker;
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps};
+
+/// Scrubs every object under `location`.
+///
+/// * `parallel_downloads`: maximum number of objects read concurrently
+/// * `digests`: audit file to verify object contents against
+/// * `manifest`: upload manifest naming the objects to read
+pub async fn scrub(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel_downloads: usize,
+    digests: Option<std::path::PathBuf>,
+    manifest: Option<std::path::PathBuf>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects = match &manifest {
+        Some(path) => {
+            UploadManifest::load(path)?
+                .inspect(object_store.as_ref(), &location, parallel_downloads, &retry)
+                .await?
+        }
+        None => {
+            inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?
+        }
+    };
+    let expected = match &digests {
+        Some(path) => Some(load_digests(path)?),
+        None => None,
+    };
+    control.set_bytes_total(objects.iter().map(|o| o.size as u64).sum());
+    let tracker = MissingTracker::new(
+        object_store.clone(),
+        &location,
+        &objects,
+        control.listing(),
+        &retry,
+    );
+    let tracker = &tracker;
+
+    let start = Instant::now();
+    let scanned = futures::stream::iter(objects.iter())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            let location = meta.location.clone();
+            let size = meta.size;
+            let verify = expected.is_some();
+            async move {
+                if tracker.is_vanished(&location) || !control.request_started().await {
+                    return Ok(None);
+                }
+                control.record("get", &location, None);
+                let start = Instant::now();
+                let result = tracker
+                    .resolve(&location, || {
+                        read_object(object_store.as_ref(), &location, verify, &retry)
+                    })
+                    .await;
+                match &result {
+                    Ok(Some((bytes, _))) => control.request_finished(*bytes),
+                    Ok(None) | Err(_) => control.request_failed(),
+                }
+                result.map(|read| {
+                    read.map(|(bytes, sha256)| ((location, bytes, sha256), (size, start.elapsed())))
+                })
+            }
+        })
+        .buffer_unordered(parallel_downloads)
+        .try_filter_map(|scanned| futures::future::ready(Ok(scanned)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    let (scanned, timings): (Vec<_>, Vec<_>) = scanned.into_iter().unzip();
+    let elapsed_us = start.elapsed().as_micros();
+    let paused_us = control.paused().as_micros();
+
+    let total_size: usize = scanned.iter().map(|(_, bytes, _)| bytes).sum();
+    let accounting = Accounting::whole(&objects);
+    for (location, bytes, _) in &scanned {
+        accounting.received(location, *bytes);
+    }
+    let accounting = accounting.check(control.is_shutdown(), |path| tracker.is_vanished(path));
+    let mut by_size = control.context().size_buckets();
+    for meta in &objects {
+        by_size.add_object(meta.size);
+    }
+    for ((_, bytes, _), (size, latency)) in scanned.iter().zip(&timings) {
+        by_size.record(*size, *latency, *bytes);
+    }
+    let mbps = mbps(total_size as u64, active_us(elapsed_us, paused_us));
+
+    let mut result = fields(serde_json::json!({
+        "mode": "scrub",
+        "num_objects": scanned.len(),
+        "bytes": total_size,
+        "parallel_downloads": parallel_downloads,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "mbps": mbps,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(by_size.json_field());
+    result.extend(accounting.json_field());
+    result.extend(tracker.json_field());
+    result.extend(retry.json_fields());
+    if let Some(expected) = &expected {
+        result.insert(
+            "verification".to_string(),
+            verify(&location, &scanned, expected),
+        );
+    }
+    control.emit(result.into());
+    accounting.enforce(control.context().strict_accounting)
+}
+
+/// Stream a whole object, returning its length and, if asked, its SHA-256.
+async fn read_object(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    digest: bool,
+    retry: &RetryPolicy,
+) -> object_store::Result<(usize, Option<String>)> {
+    let mut stream = retry
+        .run(|| object_store.get(location))
+        .await?
+        .into_stream();
+    let mut hasher = digest.then(Sha256::new);
+    let mut bytes = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        bytes += chunk.len();
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&chunk);
+        }
+    }
+    Ok((bytes, hasher.map(|hasher| hex(&hasher.finalize()))))
+}
+
+/// Compare scanned objects against the audit file, as a JSON object. Audit
+/// entries under `location` that weren't scanned are reported as missing.
+fn verify(
+    location: &Path,
+    scanned: &[(Path, usize, Option<String>)],
+    expected: &HashMap<String, ExpectedDigest>,
+) -> serde_json::Value {
+    let mut verified = 0;
+    let mut mismatches = Vec::new();
+    let mut unlisted = Vec::new();
+    let mut seen = HashSet::new();
+    for (location, bytes, sha256) in scanned {
+        let path = location.to_string();
+        match expected.get(&path) {
+            Some(digest) if Some(&digest.sha256) == sha256.as_ref() && digest.size == *bytes => {
+                verified += 1
+            }
+            Some(digest) => mismatches.push(serde_json::json!({
+                "path": path,
+                "expected_sha256": digest.sha256,
+                "actual_sha256": sha256,
+                "expected_size": digest.size,
+                "actual_size": bytes,
+            })),
+            None => unlisted.push(path.clone()),
+        }
+        seen.insert(path);
+    }
+    let mut missing = expected
+        .keys()
+        .filter(|path| !seen.contains(*path) && Path::from(path.as_str()).prefix_matches(location))
+        .cloned()
+        .collect::<Vec<_>>();
+    missing.sort();
+    serde_json::json!({
+        "verified": verified,
+        "mismatches": mismatches,
+        "not_in_digests": unlisted,
+        "missing_objects": missing,
+    })
+}
@@ -0,0 +1,365 @@
+//! Which object, and where in it, a random-read workload hits next.
+//!
+//! `--selection` picks a strategy:
+//!
+//! * `uniform`: every object equally often
+//! * `zipf`: the object of rank `k` in listing order with weight
+//!   `1 / k^--zipf-exponent`, so a few objects are hot
+//! * `round-robin`: each object in turn, stepping through it one read at a
+//!   time
+//! * `recency`: weighted towards recently written objects, halving an
+//!   object's weight for every `--recency-half-life-secs` it is older than
+//!   the newest, by the modification times of the listing
+//! * `weights`: the weights of `--weights-file`, one `path weight` pair per
+//!   line; objects the file doesn't name are never read
+//!
+//! Every strategy draws from `--selection-seed`, so a seeded run reads the
+//! same sequence again, and results record the spec under `selection`.
+
+use std::path::PathBuf;
+
+use object_store::ObjectMeta;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::error::Error;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum SelectionKind {
+    #[default]
+    Uniform,
+    Zipf,
+    RoundRobin,
+    Recency,
+    Weights,
+}
+
+/// A strategy and its parameters.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Strategy {
+    Uniform,
+    Zipf { exponent: f64 },
+    RoundRobin,
+    Recency { half_life_secs: f64 },
+    Weights { path: PathBuf },
+}
+
+/// How a workload picks what to read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectionSpec {
+    pub strategy: Strategy,
+    pub seed: u64,
+}
+
+impl SelectionSpec {
+    /// A selector over `objects`, reading the weights file if there is one.
+    pub fn selector(&self, objects: &[ObjectMeta]) -> Result<Box<dyn Selector>, Error> {
+        if objects.is_empty() {
+            return Err("no objects to select from".into());
+        }
+        let rng = StdRng::seed_from_u64(self.seed);
+        Ok(match &self.strategy {
+            Strategy::Uniform => Box::new(Uniform {
+                num_objects: objects.len(),
+                rng,
+            }),
+            Strategy::Zipf { exponent } => Box::new(Weighted::new(
+                (1..=objects.len()).map(|rank| (rank as f64).powf(-exponent)),
+                rng,
+            )?),
+            Strategy::RoundRobin => Box::new(RoundRobin {
+                num_objects: objects.len(),
+                next: 0,
+            }),
+            Strategy::Recency { half_life_secs } => {
+                let newest = objects.iter().map(|meta| meta.last_modified).max().unwrap();
+                Box::new(Weighted::new(
+                    objects.iter().map(|meta| {
+                        let age = (newest - meta.last_modified).num_milliseconds() as f64 / 1000.0;
+                        0.5f64.powf(age / half_life_secs)
+                    }),
+                    rng,
+                )?)
+            }
+            Strategy::Weights { path } => {
+                let weights = load_weights(path, objects)?;
+                Box::new(Weighted::new(weights, rng)?)
+            }
+        })
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut spec = match &self.strategy {
+            Strategy::Uniform => serde_json::json!({"strategy": "uniform"}),
+            Strategy::Zipf { exponent } => {
+                serde_json::json!({"strategy": "zipf", "exponent": exponent})
+            }
+            Strategy::RoundRobin => serde_json::json!({"strategy": "round-robin"}),
+            Strategy::Recency { half_life_secs } => {
+                serde_json::json!({"strategy": "recency", "half_life_secs": half_life_secs})
+            }
+            Strategy::Weights { path } => {
+                serde_json::json!({"strategy": "weights", "weights_file": path.display().to_string()})
+            }
+        };
+        spec["seed"] = self.seed.into();
+        spec
+    }
+}
+
+/// Picks the object, and the offset within it, of each read.
+pub trait Selector: Send {
+    /// Index of the next object to read.
+    fn next_object(&mut self) -> usize;
+
+    /// Offset of a `read_size` read within an object of `size` bytes.
+    fn next_offset(&mut self, size: usize, read_size: usize) -> usize;
+}
+
+/// Each object equally often, at uniformly random offsets.
+struct Uniform {
+    num_objects: usize,
+    rng: StdRng,
+}
+
+impl Selector for Uniform {
+    fn next_object(&mut self) -> usize {
+        self.rng.gen_range(0..self.num_objects)
+    }
+
+    fn next_offset(&mut self, size: usize, read_size: usize) -> usize {
+        random_offset(&mut self.rng, size, read_size)
+    }
+}
+
+/// Objects in proportion to fixed weights, at uniformly random offsets.
+struct Weighted {
+    /// Running total of the weights, ending with their sum
+    cumulative: Vec<f64>,
+    rng: StdRng,
+}
+
+impl Weighted {
+    fn new(weights: impl IntoIterator<Item = f64>, rng: StdRng) -> Result<Self, Error> {
+        let mut total = 0.0;
+        let mut cumulative = Vec::new();
+        for weight in weights {
+            if !(weight >= 0.0 && weight.is_finite()) {
+                return Err(format!("object weights must be non-negative, not {}", weight).into());
+            }
+            total += weight;
+            cumulative.push(total);
+        }
+        if total <= 0.0 {
+            return Err("every object has weight zero".into());
+        }
+        Ok(Self { cumulative, rng })
+    }
+}
+
+impl Selector for Weighted {
+    fn next_object(&mut self) -> usize {
+        let target = self.rng.gen::<f64>() * self.cumulative.last().unwrap();
+        // Objects of weight zero share a running total with the object
+        // before them, and are never the first above the target.
+        self.cumulative
+            .partition_point(|total| *total <= target)
+            .min(self.cumulative.len() - 1)
+    }
+
+    fn next_offset(&mut self, size: usize, read_size: usize) -> usize {
+        random_offset(&mut self.rng, size, read_size)
+    }
+}
+
+/// Each object in turn, reading consecutive slots of each pass.
+struct RoundRobin {
+    num_objects: usize,
+    next: usize,
+}
+
+impl Selector for RoundRobin {
+    fn next_object(&mut self) -> usize {
+        let object = self.next % self.num_objects;
+        self.next += 1;
+        object
+    }
+
+    fn next_offset(&mut self, size: usize, read_size: usize) -> usize {
+        if size == 0 {
+            return 0;
+        }
+        let read_size = read_size.clamp(1, size);
+        let slots = (size - read_size) / read_size + 1;
+        // `next` has already moved past the object just picked.
+        let pass = (self.next - 1) / self.num_objects;
+        (pass % slots) * read_size
+    }
+}
+
+fn random_offset(rng: &mut StdRng, size: usize, read_size: usize) -> usize {
+    rng.gen_range(0..=size - read_size.min(size))
+}
+
+/// The weight of each of `objects` from a file of `path weight` lines.
+/// Blank lines and lines starting with `#` are skipped.
+fn load_weights(path: &std::path::Path, objects: &[ObjectMeta]) -> Result<Vec<f64>, Error> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+    let mut weights = std::collections::HashMap::new();
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parsed = line
+            .rsplit_once(char::is_whitespace)
+            .and_then(|(object, weight)| Some((object.trim(), weight.parse::<f64>().ok()?)));
+        let Some((object, weight)) = parsed else {
+            return Err(format!(
+                "{}:{}: expected `path weight`, not {:?}",
+                path.display(),
+                line_no + 1,
+                line
+            )
+            .into());
+        };
+        weights.insert(object.to_string(), weight);
+    }
+    let unlisted = weights
+        .keys()
+        .filter(|object| !objects.iter().any(|meta| meta.location.as_ref() == *object))
+        .count();
+    if unlisted > 0 {
+        eprintln!(
+            "warning: {} objects in {} are not under the location",
+            unlisted,
+            path.display()
+        );
+    }
+    Ok(objects
+        .iter()
+        .map(|meta| weights.get(meta.location.as_ref()).copied().unwrap_or(0.0))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+    use object_store::path::Path;
+
+    const DRAWS: usize = 100_000;
+
+    fn objects(ages_secs: &[i64]) -> Vec<ObjectMeta> {
+        let newest = Utc.timestamp_opt(1_700_000_000, 0).unwrap();
+        ages_secs
+            .iter()
+            .enumerate()
+            .map(|(i, age)| ObjectMeta {
+                location: Path::from(format!("data/{}", i)),
+                last_modified: newest - Duration::seconds(*age),
+                size: 1 << 20,
+                e_tag: None,
+            })
+            .collect()
+    }
+
+    /// Share of `DRAWS` draws that picked each object.
+    fn realized(strategy: Strategy, objects: &[ObjectMeta]) -> Vec<f64> {
+        let spec = SelectionSpec { strategy, seed: 7 };
+        let mut selector = spec.selector(objects).unwrap();
+        let mut counts = vec![0; objects.len()];
+        for _ in 0..DRAWS {
+            counts[selector.next_object()] += 1;
+        }
+        counts.iter().map(|n| *n as f64 / DRAWS as f64).collect()
+    }
+
+    fn assert_close(realized: &[f64], weights: &[f64]) {
+        let total = weights.iter().sum::<f64>();
+        for (i, (realized, weight)) in realized.iter().zip(weights).enumerate() {
+            let expected = weight / total;
+            assert!(
+                (realized - expected).abs() < 0.01,
+                "object {}: drew {:.4}, expected {:.4}",
+                i,
+                realized,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn realized_distributions_match_their_weights() {
+        let four = objects(&[0, 0, 0, 0]);
+        assert_close(&realized(Strategy::Uniform, &four), &[1.0; 4]);
+        assert_close(
+            &realized(Strategy::Zipf { exponent: 1.0 }, &four),
+            &[1.0, 1.0 / 2.0, 1.0 / 3.0, 1.0 / 4.0],
+        );
+        assert_close(&realized(Strategy::RoundRobin, &four), &[1.0; 4]);
+        // One and two half lives older than the newest object.
+        assert_close(
+            &realized(
+                Strategy::Recency {
+                    half_life_secs: 60.0,
+                },
+                &objects(&[0, 60, 120]),
+            ),
+            &[4.0, 2.0, 1.0],
+        );
+    }
+
+    #[test]
+    fn weights_file_names_objects_to_read() {
+        let path = std::env::temp_dir().join(format!(
+            "object-store-bench-weights-{}.txt",
+            std::process::id()
+        ));
+        std::fs::write(&path, "# hot object\ndata/2 3\ndata/0 1\nelsewhere/x 5\n").unwrap();
+        let realized = realized(
+            Strategy::Weights { path: path.clone() },
+            &objects(&[0, 0, 0]),
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_close(&realized, &[1.0, 0.0, 3.0]);
+        assert_eq!(realized[1], 0.0);
+    }
+
+    #[test]
+    fn seeded_selectors_repeat_and_offsets_fit() {
+        let objects = objects(&[0, 0, 0]);
+        let spec = SelectionSpec {
+            strategy: Strategy::Zipf { exponent: 1.2 },
+            seed: 3,
+        };
+        let draw = |spec: &SelectionSpec| {
+            let mut selector = spec.selector(&objects).unwrap();
+            (0..50)
+                .map(|_| (selector.next_object(), selector.next_offset(1000, 100)))
+                .collect::<Vec<_>>()
+        };
+        let first = draw(&spec);
+        assert_eq!(first, draw(&spec));
+        assert!(first.iter().all(|(_, offset)| *offset <= 900));
+
+        let mut round_robin = SelectionSpec {
+            strategy: Strategy::RoundRobin,
+            seed: 0,
+        }
+        .selector(&objects)
+        .unwrap();
+        let picks = (0..7)
+            .map(|_| {
+                let object = round_robin.next_object();
+                (object, round_robin.next_offset(250, 100))
+            })
+            .collect::<Vec<_>>();
+        // Two slots of 100 bytes fit, so the third pass starts over.
+        assert_eq!(
+            picks,
+            [(0, 0), (1, 0), (2, 0), (0, 100), (1, 100), (2, 100), (0, 0)]
+        );
+    }
+}
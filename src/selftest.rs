@@ -0,0 +1,895 @@
+//! End-to-end check of the tool itself.
+//!
+//! [`self_test`] runs a miniature version of each benchmark against a
+//! scratch prefix, usually on `memory://`, one stage per entry of
+//! [`STAGES`]: it uploads one object and a small run of several, downloads
+//! them in blocks, checks ranged reads byte for byte, scrubs everything
+//! against the digests recorded at upload, reads the single object as
+//! columnar pages with a manifest, lists, compares the get calls, reads
+//! suffixes and reassembles in order. It then heads the several objects,
+//! times their first bytes, reads them at random from a workload recipe,
+//! queries them as a table, runs both fairness phases, calibrates, replays a
+//! recorded plan, revalidates stale reads, mirrors a local tree there and
+//! back, and deletes the mirrored copy with `cleanup`. Every subcommand has
+//! a stage but those in [`UNSTAGED`]. Its objects live in a
+//! [`ScratchArea`], which deletes them afterwards even if a stage failed, and a last `scratch` stage checks that nothing is left. Each stage
+//! checks the counts and byte totals its benchmark reported, and a failing
+//! stage names the field with the expected and actual values.
+//!
+//! The same run is a test, so `cargo test` exercises every benchmark.
+
+use std::future::Future;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::TryStreamExt;
+use object_store::{path::Path, ObjectStore};
+use serde_json::Value;
+
+use crate::calibration::{calibrate, Workload};
+use crate::cleanup::cleanup;
+use crate::columnar::{columnar_read_test, ColumnarOptions};
+use crate::control::RunControl;
+use crate::digest::DigestConfig;
+use crate::download::{parallel_download_bench, DownloadOptions};
+use crate::error::Error;
+use crate::experiment::capture;
+use crate::fairness::{fairness_bench, FairnessOptions};
+use crate::get_apis::compare_get_apis;
+use crate::head::head_bench;
+use crate::list::{list_bench, ListOptions};
+use crate::mirror::{pull, push, MirrorOptions};
+use crate::pattern::Contents;
+use crate::plan::{replay_plan, PlanRecorder};
+use crate::query_sim::{query_sim, QuerySimOptions};
+use crate::random_read::random_read_bench;
+use crate::reassembly::reassembly_bench;
+use crate::recipe::Recipe;
+use crate::report::Checked;
+use crate::retry::RetryPolicy;
+use crate::scratch::{ScratchArea, ScratchSummary};
+use crate::scrub::scrub;
+use crate::selection::{SelectionSpec, Strategy};
+use crate::store_defaults::PART_SIZE;
+use crate::swr::{swr_bench, SwrOptions};
+use crate::tail::suffix_read_bench;
+use crate::ttfb::ttfb_bench;
+use crate::upload::{upload_multiple, upload_test_data, UploadSamples};
+
+/// Size of the single object, deliberately not a multiple of any block or page.
+const SINGLE_SIZE: usize = (1 << 20) + 4099;
+const NUM_OBJECTS: usize = 4;
+const OBJECT_SIZE: usize = 1 << 20;
+const BLOCK_SIZE: usize = 1 << 18;
+const PARALLEL: usize = 4;
+const SUFFIX_BYTES: usize = 1000;
+const PAGE_SIZES: [usize; 3] = [4096, 16384, 65536];
+/// Sizes of the files mirrored, one of them empty and one pushed in parts.
+const MIRRORED_SIZES: [usize; 3] = [0, 5000, PART_SIZE + 1];
+
+/// The stages run inside the scratch area, in order, named after the
+/// subcommand or download mode each exercises.
+pub const STAGES: &[&str] = &[
+    "upload-data",
+    "upload-multiple",
+    "download",
+    "ranged-reads",
+    "scrub",
+    "columnar",
+    "list",
+    "get-apis",
+    "suffix",
+    "reassemble",
+    "head",
+    "ttfb",
+    "random-read",
+    "query-sim",
+    "fairness",
+    "calibrate",
+    "replay",
+    "swr",
+    "mirror",
+    "cleanup",
+];
+
+/// Subcommands without a stage: they read result files rather than a store,
+/// or, for `self-test`, are the run itself.
+pub const UNSTAGED: &[&str] = &["report", "merge", "migrate", "self-test"];
+
+type StageResult = Result<(), String>;
+
+/// Where one self-test run keeps its objects and local files.
+struct Scratch {
+    object_store: Arc<dyn ObjectStore>,
+    area: ScratchArea,
+    root: Path,
+    single: Path,
+    multi: Path,
+    run_id: String,
+    dir: std::path::PathBuf,
+    retry: RetryPolicy,
+    control: RunControl,
+    keep_scratch: bool,
+}
+
+impl Scratch {
+    fn digests(&self) -> std::path::PathBuf {
+        self.dir.join("digests.jsonl")
+    }
+
+    fn upload_manifest(&self) -> std::path::PathBuf {
+        self.dir.join("upload-manifest.json")
+    }
+
+    fn columnar_manifest(&self) -> std::path::PathBuf {
+        self.dir.join("columnar-manifest.json")
+    }
+
+    fn plan(&self) -> std::path::PathBuf {
+        self.dir.join("plan.jsonl")
+    }
+
+    fn mirrored(&self) -> Path {
+        self.root.child("mirror")
+    }
+
+    async fn list(&self, prefix: &Path) -> Result<Vec<object_store::ObjectMeta>, String> {
+        self.object_store
+            .list(Some(prefix))
+            .await
+            .map_err(|err| format!("listing {} failed: {}", prefix, err))?
+            .try_collect()
+            .await
+            .map_err(|err| format!("listing {} failed: {}", prefix, err))
+    }
+}
+
+/// Run a benchmark that returns its result, and return that result as JSON.
+async fn returned<F, T>(bench: F) -> Result<Value, String>
+where
+    F: Future<Output = Result<Checked<T>, Error>>,
+    T: serde::Serialize,
+{
+    let result = bench
+        .await
+        .and_then(Checked::into_result)
+        .map_err(|err| format!("benchmark failed: {}", err))?;
+    serde_json::to_value(result).map_err(|err| format!("invalid result: {}", err))
+}
+
+/// Run `bench` and return the one result it reported, parsed.
+async fn result_of<F, T, E>(bench: F) -> Result<Value, String>
+where
+    F: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let (outcome, results) = capture(bench).await;
+    outcome.map_err(|err| format!("benchmark failed: {}", err))?;
+    let [result] = results.as_slice() else {
+        return Err(format!(
+            "expected one result, got {}: {:?}",
+            results.len(),
+            results
+        ));
+    };
+    serde_json::from_str(result).map_err(|err| format!("invalid result {}: {}", result, err))
+}
+
+/// Check that the field at `pointer` in `result` is `expected`.
+fn expect(result: &Value, pointer: &str, expected: impl Into<Value>) -> StageResult {
+    let expected = expected.into();
+    match result.pointer(pointer) {
+        Some(actual) if *actual == expected => Ok(()),
+        actual => Err(format!(
+            "{}: expected {}, got {}",
+            pointer,
+            expected,
+            actual.unwrap_or(&Value::Null)
+        )),
+    }
+}
+
+/// Runs every stage against a fresh prefix under `location`, printing a
+/// pass/fail line per stage to stderr and a summary result. With
+/// `keep_scratch`, the objects written are left in place.
+pub async fn self_test(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    retry: RetryPolicy,
+    control: RunControl,
+    keep_scratch: bool,
+) -> Result<(), Error> {
+    let area = ScratchArea::new(object_store.clone(), &location, "self-test", keep_scratch);
+    let run_id = area.run_id().to_string();
+    let dir = std::env::temp_dir().join(format!("object-store-bench-self-test-{}", run_id));
+    std::fs::create_dir_all(&dir)?;
+    let scratch = Scratch {
+        object_store,
+        single: area.path("single.bin"),
+        multi: area.root().child("multi"),
+        root: area.root().clone(),
+        area,
+        run_id,
+        dir,
+        retry,
+        control,
+        keep_scratch,
+    };
+
+    let (stages, summary) = scratch
+        .area
+        .run(&scratch.control, &scratch.retry, async {
+            let mut stages: Vec<(&str, StageResult)> = Vec::new();
+            for name in STAGES {
+                stages.push((name, run_stage(&scratch, name).await));
+            }
+            stages
+        })
+        .await;
+    let mut stages = stages.ok_or("self-test interrupted")?;
+    stages.push(("scratch", scratch_left(&scratch, &summary).await));
+    let _ = std::fs::remove_dir_all(&scratch.dir);
+
+    let width = stages.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+    for (name, result) in &stages {
+        match result {
+            Ok(()) => eprintln!("PASS  {:width$}", name),
+            Err(err) => eprintln!("FAIL  {:width$}  {}", name, err),
+        }
+    }
+    let failed = stages.iter().filter(|(_, result)| result.is_err()).count();
+    scratch.control.emit(serde_json::json!({
+        "mode": "self_test",
+        "run_id": scratch.run_id,
+        "scratch": summary.to_json(),
+        "passed": stages.len() - failed,
+        "failed": failed,
+        "stages": stages
+            .iter()
+            .map(|(name, result)| serde_json::json!({
+                "stage": name,
+                "passed": result.is_ok(),
+                "error": result.as_ref().err(),
+            }))
+            .collect::<Vec<_>>(),
+    }));
+    if failed > 0 {
+        return Err(format!("self-test failed {} of {} stages", failed, stages.len()).into());
+    }
+    Ok(())
+}
+
+async fn run_stage(scratch: &Scratch, name: &str) -> StageResult {
+    match name {
+        "upload-data" => upload_single(scratch).await,
+        "upload-multiple" => upload_several(scratch).await,
+        "download" => download(scratch).await,
+        "ranged-reads" => ranged_reads(scratch).await,
+        "scrub" => scrub_digests(scratch).await,
+        "columnar" => columnar(scratch).await,
+        "list" => list(scratch).await,
+        "get-apis" => get_apis(scratch).await,
+        "suffix" => suffix(scratch).await,
+        "reassemble" => reassemble(scratch).await,
+        "head" => head(scratch).await,
+        "ttfb" => ttfb(scratch).await,
+        "random-read" => random_read(scratch).await,
+        "query-sim" => query(scratch).await,
+        "fairness" => fairness(scratch).await,
+        "calibrate" => calibration(scratch).await,
+        "replay" => replay(scratch).await,
+        "swr" => swr(scratch).await,
+        "mirror" => mirror(scratch).await,
+        "cleanup" => cleanup_mirrored(scratch).await,
+        _ => Err(format!("unknown stage {}", name)),
+    }
+}
+
+async fn upload_single(scratch: &Scratch) -> StageResult {
+    let digest = DigestConfig::new(false, Some(&scratch.digests())).map_err(|e| e.to_string())?;
+    let samples = UploadSamples::start();
+    upload_test_data(
+        scratch.object_store.clone(),
+        &scratch.single,
+        SINGLE_SIZE,
+        PART_SIZE,
+        Contents::Random { seed: None },
+        &scratch.retry,
+        Some(&digest),
+        &samples,
+    )
+    .await
+    .map_err(|err| format!("upload failed: {}", err))?;
+    let result = Value::from(samples.json_fields(Duration::from_secs(1), 0.5));
+    expect(&result, "/bytes", SINGLE_SIZE)?;
+    let meta = scratch
+        .object_store
+        .head(&scratch.single)
+        .await
+        .map_err(|err| format!("head failed: {}", err))?;
+    if meta.size != SINGLE_SIZE {
+        return Err(format!("size: expected {}, got {}", SINGLE_SIZE, meta.size));
+    }
+    Ok(())
+}
+
+async fn upload_several(scratch: &Scratch) -> StageResult {
+    let digest = DigestConfig::new(false, Some(&scratch.digests())).map_err(|e| e.to_string())?;
+    upload_multiple(
+        scratch.object_store.clone(),
+        &scratch.multi,
+        NUM_OBJECTS,
+        NUM_OBJECTS * OBJECT_SIZE,
+        PART_SIZE,
+        false,
+        None,
+        Contents::Random { seed: None },
+        Some(&scratch.run_id),
+        Some(&scratch.upload_manifest()),
+        &scratch.retry,
+        Some(&digest),
+        &UploadSamples::start(),
+    )
+    .await
+    .map_err(|err| format!("upload failed: {}", err))?;
+
+    let manifest = std::fs::read_to_string(scratch.upload_manifest())
+        .map_err(|err| format!("reading the manifest failed: {}", err))?;
+    let manifest: Value =
+        serde_json::from_str(&manifest).map_err(|err| format!("invalid manifest: {}", err))?;
+    expect(&manifest, "/run_id", scratch.run_id.as_str())?;
+    let names = manifest["objects"].as_array().map_or(0, Vec::len);
+    let listed = scratch.list(&scratch.multi).await?;
+    if names != NUM_OBJECTS || listed.len() != NUM_OBJECTS {
+        return Err(format!(
+            "objects: expected {}, got {} in the manifest and {} listed",
+            NUM_OBJECTS,
+            names,
+            listed.len()
+        ));
+    }
+    match listed.iter().find(|meta| meta.size != OBJECT_SIZE) {
+        Some(meta) => Err(format!(
+            "{} size: expected {}, got {}",
+            meta.location, OBJECT_SIZE, meta.size
+        )),
+        None => Ok(()),
+    }
+}
+
+async fn download(scratch: &Scratch) -> StageResult {
+    let result = returned(parallel_download_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        DownloadOptions {
+            parallel_downloads: PARALLEL,
+            block_size: Some(BLOCK_SIZE),
+            ..DownloadOptions::default()
+        },
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(&result, "/block_size", BLOCK_SIZE)?;
+    expect(
+        &result,
+        "/num_requests",
+        NUM_OBJECTS * OBJECT_SIZE / BLOCK_SIZE,
+    )?;
+    expect(&result, "/bytes", NUM_OBJECTS * OBJECT_SIZE)?;
+    expect(&result, "/interrupted", false)
+}
+
+async fn ranged_reads(scratch: &Scratch) -> StageResult {
+    let store = scratch.object_store.as_ref();
+    let whole = store
+        .get(&scratch.single)
+        .await
+        .map_err(|err| format!("get failed: {}", err))?
+        .bytes()
+        .await
+        .map_err(|err| format!("get failed: {}", err))?;
+    if whole.len() != SINGLE_SIZE {
+        return Err(format!(
+            "get length: expected {}, got {}",
+            SINGLE_SIZE,
+            whole.len()
+        ));
+    }
+    let ranges: [Range<usize>; 4] = [0..1, 4095..4097, 12345..400_000, OBJECT_SIZE..SINGLE_SIZE];
+    for range in &ranges {
+        let bytes = store
+            .get_range(&scratch.single, range.clone())
+            .await
+            .map_err(|err| format!("get_range {:?} failed: {}", range, err))?;
+        if bytes != whole[range.clone()] {
+            return Err(format!(
+                "get_range {:?}: {} bytes differ from the same range of get",
+                range,
+                bytes.len()
+            ));
+        }
+    }
+    let all = store
+        .get_ranges(&scratch.single, &ranges)
+        .await
+        .map_err(|err| format!("get_ranges failed: {}", err))?;
+    for (range, bytes) in ranges.iter().zip(&all) {
+        if *bytes != whole[range.clone()] {
+            return Err(format!(
+                "get_ranges {:?}: bytes differ from the same range of get",
+                range
+            ));
+        }
+    }
+    Ok(())
+}
+
+async fn scrub_digests(scratch: &Scratch) -> StageResult {
+    let result = result_of(scrub(
+        scratch.object_store.clone(),
+        scratch.root.clone(),
+        PARALLEL,
+        Some(scratch.digests()),
+        None,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS + 1)?;
+    expect(&result, "/bytes", NUM_OBJECTS * OBJECT_SIZE + SINGLE_SIZE)?;
+    expect(&result, "/verification/verified", NUM_OBJECTS + 1)?;
+    expect(&result, "/verification/mismatches", Vec::<Value>::new())?;
+    expect(
+        &result,
+        "/verification/missing_objects",
+        Vec::<Value>::new(),
+    )?;
+    expect(&result, "/verification/not_in_digests", Vec::<Value>::new())
+}
+
+async fn columnar(scratch: &Scratch) -> StageResult {
+    let options = ColumnarOptions {
+        parallel_downloads: PARALLEL,
+        page_sizes: PAGE_SIZES.to_vec(),
+        manifest_out: Some(scratch.columnar_manifest()),
+        ..ColumnarOptions::default()
+    };
+    let result = returned(columnar_read_test(
+        scratch.object_store.clone(),
+        scratch.single.clone(),
+        options,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    let manifest = std::fs::read_to_string(scratch.columnar_manifest())
+        .map_err(|err| format!("reading the manifest failed: {}", err))?;
+    let manifest: Value =
+        serde_json::from_str(&manifest).map_err(|err| format!("invalid manifest: {}", err))?;
+    let groups = SINGLE_SIZE / (4096 + 16384 + 65536);
+    expect(&manifest, "/num_groups", groups)?;
+    expect(&result, "/num_objects", 1)?;
+    expect(&result, "/num_groups", groups)?;
+    expect(&result, "/bytes", groups * (4096 + 16384 + 65536))?;
+    expect(&result, "/interrupted", false)
+}
+
+async fn list(scratch: &Scratch) -> StageResult {
+    let options = ListOptions {
+        shard_depth: None,
+        parallel: 1,
+        delimiter: false,
+        compare: false,
+    };
+    let result = result_of(list_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        options,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/single/num_objects", NUM_OBJECTS)
+}
+
+async fn get_apis(scratch: &Scratch) -> StageResult {
+    let result = result_of(compare_get_apis(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        PARALLEL,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    for api in ["get", "get_opts", "get_range"] {
+        expect(&result, &format!("/{}/num_requests", api), NUM_OBJECTS)?;
+        expect(
+            &result,
+            &format!("/{}/bytes", api),
+            NUM_OBJECTS * OBJECT_SIZE,
+        )?;
+    }
+    Ok(())
+}
+
+async fn suffix(scratch: &Scratch) -> StageResult {
+    let result = result_of(suffix_read_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        SUFFIX_BYTES,
+        PARALLEL,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(&result, "/head_then_range/latency/count", NUM_OBJECTS)
+}
+
+async fn reassemble(scratch: &Scratch) -> StageResult {
+    let result = result_of(reassembly_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        PARALLEL,
+        Some(BLOCK_SIZE),
+        Some(2 * BLOCK_SIZE),
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(
+        &result,
+        "/num_blocks",
+        NUM_OBJECTS * OBJECT_SIZE / BLOCK_SIZE,
+    )?;
+    expect(&result, "/bytes", NUM_OBJECTS * OBJECT_SIZE)?;
+    let peak = result
+        .pointer("/reorder_buffer/peak_bytes")
+        .and_then(Value::as_u64)
+        .unwrap_or(u64::MAX);
+    if peak > 2 * BLOCK_SIZE as u64 {
+        return Err(format!(
+            "/reorder_buffer/peak_bytes: expected at most {}, got {}",
+            2 * BLOCK_SIZE,
+            peak
+        ));
+    }
+    Ok(())
+}
+
+async fn head(scratch: &Scratch) -> StageResult {
+    let result = result_of(head_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        PARALLEL,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(&result, "/succeeded", NUM_OBJECTS)?;
+    expect(&result, "/failed", 0)?;
+    expect(&result, "/latency/count", NUM_OBJECTS)
+}
+
+async fn ttfb(scratch: &Scratch) -> StageResult {
+    let result = result_of(ttfb_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        PARALLEL,
+        Some(SUFFIX_BYTES),
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_requests", NUM_OBJECTS)?;
+    expect(&result, "/bytes", NUM_OBJECTS * SUFFIX_BYTES)?;
+    expect(&result, "/ttfb/count", NUM_OBJECTS)
+}
+
+async fn random_read(scratch: &Scratch) -> StageResult {
+    let recipe: Recipe = "seed=7;reads=64;size=4K;dist=zipf(1.1)".parse()?;
+    let result = result_of(random_read_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        recipe.options(PARALLEL),
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(&result, "/requests", 64)?;
+    expect(&result, "/bytes", 64 * 4096)?;
+    expect(&result, "/workload_recipe", recipe.to_string())
+}
+
+async fn query(scratch: &Scratch) -> StageResult {
+    let options = QuerySimOptions {
+        parallel: PARALLEL,
+        page_sizes: PAGE_SIZES.to_vec(),
+        columns: vec![1],
+        footer_size: 4096,
+        selectivity: 1.0,
+        seed: 7,
+        coalesce_gap: 0,
+        decode_mbps: None,
+    };
+    let result = result_of(query_sim(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        options,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    // Every group survives, and only the middle column is scanned, one
+    // request a page as no two of its pages touch.
+    let groups = NUM_OBJECTS * ((OBJECT_SIZE - 4096) / PAGE_SIZES.iter().sum::<usize>());
+    expect(&result, "/num_files", NUM_OBJECTS)?;
+    expect(&result, "/stages/metadata/requests", NUM_OBJECTS)?;
+    expect(&result, "/groups_total", groups)?;
+    expect(&result, "/groups_kept", groups)?;
+    expect(&result, "/page_bytes", groups * PAGE_SIZES[1])?;
+    expect(&result, "/stages/scan/requests", groups)
+}
+
+async fn fairness(scratch: &Scratch) -> StageResult {
+    let options = FairnessOptions {
+        read_size: 4096,
+        target_qps: 200.0,
+        block_size: BLOCK_SIZE,
+        parallel: 2,
+        duration: Duration::from_millis(100),
+        selection: SelectionSpec {
+            strategy: Strategy::Uniform,
+            seed: 7,
+        },
+        deadline: None,
+    };
+    let result = result_of(fairness_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        options,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(&result, "/class_a/baseline/errors", 0)?;
+    expect(&result, "/class_a/contended/errors", 0)?;
+    expect(&result, "/class_b/errors", 0)
+}
+
+async fn calibration(scratch: &Scratch) -> StageResult {
+    let workload = Workload::Download {
+        parallel_downloads: PARALLEL,
+        block_size: Some(BLOCK_SIZE),
+    };
+    let result = result_of(calibrate(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        workload,
+        None,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/workload", "download")?;
+    expect(&result, "/num_objects", NUM_OBJECTS)
+}
+
+async fn replay(scratch: &Scratch) -> StageResult {
+    // A run of its own, so only its requests are recorded.
+    let recording = RunControl::new();
+    let recorder = PlanRecorder::create(&scratch.plan(), scratch.multi.clone(), Some(PARALLEL))
+        .map_err(|err| format!("creating the plan failed: {}", err))?;
+    recording.set_plan_recorder(recorder);
+    returned(parallel_download_bench(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        DownloadOptions {
+            parallel_downloads: PARALLEL,
+            block_size: Some(BLOCK_SIZE),
+            ..DownloadOptions::default()
+        },
+        scratch.retry.clone(),
+        recording.clone(),
+    ))
+    .await?;
+    recording
+        .flush_plan()
+        .map_err(|err| format!("writing the plan failed: {}", err))?;
+
+    let result = result_of(replay_plan(
+        scratch.object_store.clone(),
+        scratch.multi.clone(),
+        &scratch.plan(),
+        None,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", NUM_OBJECTS)?;
+    expect(
+        &result,
+        "/num_requests",
+        NUM_OBJECTS * OBJECT_SIZE / BLOCK_SIZE,
+    )?;
+    expect(&result, "/parallel_downloads", PARALLEL)?;
+    expect(&result, "/bytes", NUM_OBJECTS * OBJECT_SIZE)
+}
+
+async fn swr(scratch: &Scratch) -> StageResult {
+    let options = SwrOptions {
+        num_keys: 4,
+        object_size: 8192,
+        read_size: 1024,
+        accesses: 32,
+        parallel: PARALLEL,
+        mutate_fraction: 0.5,
+        seed: 7,
+    };
+    let result = swr_bench(
+        scratch.object_store.clone(),
+        scratch.root.child("swr"),
+        options,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+        scratch.keep_scratch,
+    )
+    .await
+    .map_err(|err| format!("benchmark failed: {}", err))?;
+    let result = serde_json::to_value(result).map_err(|err| format!("invalid result: {}", err))?;
+    expect(&result, "/mutated_keys", 2)?;
+    expect(&result, "/accesses", 32)?;
+    expect(&result, "/read_errors", 0)?;
+    expect(&result, "/revalidate_errors", 0)?;
+    expect(&result, "/misclassified", 0)
+}
+
+async fn mirror(scratch: &Scratch) -> StageResult {
+    let local = scratch.dir.join("mirror");
+    let pulled = scratch.dir.join("pulled");
+    std::fs::create_dir_all(local.join("nested"))
+        .map_err(|err| format!("creating {} failed: {}", local.display(), err))?;
+    let names = ["empty.bin", "small.bin", "nested/large.bin"];
+    for (name, size) in names.iter().zip(MIRRORED_SIZES) {
+        let contents = (0..size).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(local.join(name), contents)
+            .map_err(|err| format!("writing {} failed: {}", name, err))?;
+    }
+    let options = |multipart_threshold| MirrorOptions {
+        parallel: PARALLEL,
+        include: Vec::new(),
+        multipart_threshold,
+    };
+    let bytes: usize = MIRRORED_SIZES.iter().sum();
+
+    let pushed = result_of(push(
+        scratch.object_store.clone(),
+        scratch.mirrored(),
+        &local,
+        options(PART_SIZE),
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&pushed, "/files", names.len())?;
+    expect(&pushed, "/empty_files", 1)?;
+    expect(&pushed, "/multipart_files", 1)?;
+    expect(&pushed, "/bytes", bytes)?;
+
+    let pulled_result = result_of(pull(
+        scratch.object_store.clone(),
+        scratch.mirrored(),
+        &pulled,
+        options(usize::MAX),
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&pulled_result, "/files", names.len())?;
+    expect(&pulled_result, "/bytes", bytes)?;
+    for name in names {
+        let read = |dir: &std::path::Path| {
+            std::fs::read(dir.join(name))
+                .map_err(|err| format!("reading {} failed: {}", dir.join(name).display(), err))
+        };
+        if read(&local)? != read(&pulled)? {
+            return Err(format!("{}: pulled bytes differ from those pushed", name));
+        }
+    }
+    Ok(())
+}
+
+async fn cleanup_mirrored(scratch: &Scratch) -> StageResult {
+    let result = result_of(cleanup(
+        scratch.object_store.clone(),
+        scratch.mirrored(),
+        PARALLEL,
+        None,
+        scratch.retry.clone(),
+        scratch.control.clone(),
+    ))
+    .await?;
+    expect(&result, "/num_objects", MIRRORED_SIZES.len())?;
+    expect(&result, "/deleted", MIRRORED_SIZES.len())?;
+    let left = scratch.list(&scratch.mirrored()).await?;
+    if !left.is_empty() {
+        return Err(format!("objects left: expected 0, got {}", left.len()));
+    }
+    Ok(())
+}
+
+async fn scratch_left(scratch: &Scratch, summary: &ScratchSummary) -> StageResult {
+    if summary.kept {
+        return Ok(());
+    }
+    if summary.failed > 0 {
+        return Err(format!(
+            "deletes failed: expected 0, got {}",
+            summary.failed
+        ));
+    }
+    let left = scratch.list(&scratch.root).await?;
+    if !left.is_empty() {
+        return Err(format!("objects left: expected 0, got {}", left.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fault::{Fault, FaultConfig, FaultStore};
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn every_stage_passes_against_memory() {
+        let store = Arc::new(InMemory::new());
+        self_test(
+            store.clone(),
+            Path::from("scratch"),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+            false,
+        )
+        .await
+        .unwrap();
+        let left = store
+            .list(None)
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(left.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_runs_leave_no_scratch_objects() {
+        let memory = Arc::new(InMemory::new());
+        // Every read fails, so most stages do, after the uploads succeeded.
+        let faults = FaultConfig::new(&[Fault::ErrorRate(1.0)]);
+        let store = Arc::new(FaultStore::new(memory.clone(), faults, 7));
+        let err = self_test(
+            store,
+            Path::from("scratch"),
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+            false,
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("self-test failed"));
+        let left = memory
+            .list(Some(&Path::from("scratch")))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(left.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,338 @@
+//! A remote store's latency and bandwidth, simulated over a local one.
+//!
+//! Against `file://` or `memory://` every request returns at once, which
+//! hides what concurrency buys. `--simulate-latency 20:5` makes each `get`,
+//! `get_range`, `get_ranges`, `head` and `put` wait 20 ms plus a uniform jitter
+//! of up to 5 ms before it is passed on, and `--simulate-bandwidth 100` limits
+//! the bytes read and put to 100 MB/s, shared by every request in flight as
+//! one link would be. Under pure latency, doubling `--parallel-downloads`
+//! should halve a download's wall time. While simulating, every result says
+//! so under `simulated`, and a warning on stderr says the numbers are not the
+//! store's.
+//!
+//! Unlike `--fault latency-ms`, which only slows reads to exercise retries,
+//! this models the store as a whole; multipart uploads, listings and deletes
+//! still pass straight through.
+
+use std::fmt::{Debug, Display, Formatter};
+use std::ops::Range;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use rand::Rng;
+use tokio::io::AsyncWrite;
+use tokio::time::Instant;
+
+use crate::experiment::Fields;
+
+/// `--simulate-latency MS[:JITTER_MS]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SimulatedLatency {
+    pub base: Duration,
+    pub jitter: Duration,
+}
+
+impl FromStr for SimulatedLatency {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let ms = |field: &str| {
+            let ms: f64 = field
+                .trim()
+                .parse()
+                .map_err(|err| format!("{:?}: {}", field, err))?;
+            if !ms.is_finite() || ms < 0.0 {
+                return Err(format!("{:?} is not a non-negative number", field));
+            }
+            Ok(Duration::from_secs_f64(ms / 1000.0))
+        };
+        let (base, jitter) = match s.split_once(':') {
+            Some((base, jitter)) => (ms(base)?, ms(jitter)?),
+            None => (ms(s)?, Duration::ZERO),
+        };
+        Ok(SimulatedLatency { base, jitter })
+    }
+}
+
+impl SimulatedLatency {
+    /// The wait before one request.
+    pub fn sample(&self) -> Duration {
+        match self.jitter.is_zero() {
+            true => self.base,
+            false => self.base + self.jitter.mul_f64(rand::thread_rng().gen::<f64>()),
+        }
+    }
+}
+
+/// What `--simulate-latency` and `--simulate-bandwidth` asked for.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Simulation {
+    pub latency: Option<SimulatedLatency>,
+    /// MB/s shared by all transfers
+    pub bandwidth_mbps: Option<f64>,
+}
+
+impl Simulation {
+    pub fn is_active(&self) -> bool {
+        self.latency.is_some() || self.bandwidth_mbps.is_some()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "latency_ms": self.latency.map(|latency| latency.base.as_secs_f64() * 1000.0),
+            "jitter_ms": self.latency.map(|latency| latency.jitter.as_secs_f64() * 1000.0),
+            "bandwidth_mbps": self.bandwidth_mbps,
+        })
+    }
+}
+
+/// Append the latency and bandwidth `simulation` adds to the JSON object
+/// `result`.
+pub fn with_simulation(result: &mut Fields, simulation: &Simulation) {
+    result.insert("simulated".to_string(), simulation.to_json());
+}
+
+/// One link's worth of bandwidth, handed out to transfers in the order they
+/// ask for it.
+#[derive(Debug)]
+struct Link {
+    bytes_per_sec: f64,
+    /// When the bytes already promised will have gone through
+    free_at: Mutex<Instant>,
+}
+
+impl Link {
+    /// Wait until `bytes` more have had their turn on the link.
+    async fn transfer(&self, bytes: usize) {
+        let done = {
+            let mut free_at = self.free_at.lock().unwrap();
+            let start = (*free_at).max(Instant::now());
+            *free_at = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_sec);
+            *free_at
+        };
+        tokio::time::sleep_until(done).await;
+    }
+}
+
+/// An [`ObjectStore`] that delays requests to `inner` as a remote store would.
+pub struct SimulatedStore {
+    inner: Arc<dyn ObjectStore>,
+    latency: Option<SimulatedLatency>,
+    link: Option<Arc<Link>>,
+}
+
+impl SimulatedStore {
+    pub fn new(inner: Arc<dyn ObjectStore>, simulation: Simulation) -> Self {
+        Self {
+            inner,
+            latency: simulation.latency,
+            link: simulation.bandwidth_mbps.map(|mbps| {
+                Arc::new(Link {
+                    bytes_per_sec: mbps * 1024.0 * 1024.0,
+                    free_at: Mutex::new(Instant::now()),
+                })
+            }),
+        }
+    }
+
+    async fn wait(&self) {
+        if let Some(latency) = &self.latency {
+            tokio::time::sleep(latency.sample()).await;
+        }
+    }
+
+    async fn transfer(&self, bytes: usize) {
+        if let Some(link) = &self.link {
+            link.transfer(bytes).await;
+        }
+    }
+}
+
+impl Display for SimulatedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SimulatedStore({})", self.inner)
+    }
+}
+
+impl Debug for SimulatedStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SimulatedStore")
+            .field("inner", &self.inner)
+            .field("latency", &self.latency)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for SimulatedStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.wait().await;
+        self.transfer(bytes.len()).await;
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        self.wait().await;
+        let result = self.inner.get_opts(location, options).await?;
+        let Some(link) = self.link.clone() else {
+            return Ok(result);
+        };
+        // Each chunk waits its turn on the link as it streams.
+        let stream = result.into_stream().then(move |chunk| {
+            let link = link.clone();
+            async move {
+                if let Ok(chunk) = &chunk {
+                    link.transfer(chunk.len()).await;
+                }
+                chunk
+            }
+        });
+        Ok(GetResult::Stream(stream.boxed()))
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        self.wait().await;
+        let bytes = self.inner.get_range(location, range).await?;
+        self.transfer(bytes.len()).await;
+        Ok(bytes)
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        self.wait().await;
+        let bytes = self.inner.get_ranges(location, ranges).await?;
+        self.transfer(bytes.iter().map(Bytes::len).sum()).await;
+        Ok(bytes)
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        self.wait().await;
+        self.inner.head(location).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        self.inner.list(prefix).await
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        self.inner.list_with_delimiter(prefix).await
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control::RunControl;
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::report::Checked;
+    use crate::retry::RetryPolicy;
+    use object_store::memory::InMemory;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_latency_with_optional_jitter() {
+        assert_eq!(
+            "20".parse(),
+            Ok(SimulatedLatency {
+                base: ms(20),
+                jitter: Duration::ZERO
+            })
+        );
+        let latency: SimulatedLatency = "20:5".parse().unwrap();
+        assert_eq!(latency.jitter, ms(5));
+        assert!((0..100).all(|_| (ms(20)..=ms(25)).contains(&latency.sample())));
+        assert!("-1".parse::<SimulatedLatency>().is_err());
+        assert!("20:x".parse::<SimulatedLatency>().is_err());
+    }
+
+    #[tokio::test]
+    async fn bandwidth_is_shared_by_concurrent_reads() {
+        let memory = Arc::new(InMemory::new());
+        let location = Path::from("data/a");
+        memory
+            .put(&location, Bytes::from(vec![0; 1 << 20]))
+            .await
+            .unwrap();
+        let simulation = Simulation {
+            latency: None,
+            bandwidth_mbps: Some(10.0),
+        };
+        let store = SimulatedStore::new(memory, simulation);
+        let start = Instant::now();
+        // Two reads of 512 KiB at 10 MB/s take 100 ms together.
+        let reads = (0..2).map(|i| store.get_range(&location, i << 19..(i + 1) << 19));
+        futures::future::try_join_all(reads).await.unwrap();
+        assert!(start.elapsed() >= ms(95), "{:?}", start.elapsed());
+    }
+
+    #[tokio::test]
+    async fn concurrency_hides_simulated_latency() {
+        let memory: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        memory
+            .put(&Path::from("data/a"), Bytes::from(vec![0; 8 << 10]))
+            .await
+            .unwrap();
+        let simulation = Simulation {
+            latency: Some("40".parse().unwrap()),
+            bandwidth_mbps: None,
+        };
+        let object_store: Arc<dyn ObjectStore> = Arc::new(SimulatedStore::new(memory, simulation));
+        let mut elapsed = Vec::new();
+        for parallel in [1, 4] {
+            let result = parallel_download_bench(
+                object_store.clone(),
+                Path::from("data"),
+                DownloadOptions {
+                    parallel_downloads: parallel,
+                    block_size: Some(1 << 10),
+                    ..DownloadOptions::default()
+                },
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            )
+            .await
+            .and_then(Checked::into_result)
+            .unwrap();
+            elapsed.push(result.elapsed_us);
+        }
+        // Eight blocks take eight latencies one at a time, two at four.
+        assert!(elapsed[0] >= 320_000, "{:?}", elapsed);
+        assert!(elapsed[0] > 2 * elapsed[1], "{:?}", elapsed);
+    }
+}
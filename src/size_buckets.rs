@@ -0,0 +1,144 @@
+//! Per-size-class statistics for locations holding objects of mixed sizes.
+//!
+//! Pooled latency over a prefix of 4 KiB and 64 MiB objects describes
+//! neither. `download` and `scrub` also report each request under the size
+//! class of the object it read, as `by_size_bucket`. The class edges are set
+//! with `--size-buckets`, defaulting to 1 MiB, 16 MiB, 128 MiB and 1 GiB; an
+//! object of exactly an edge's size falls in the class above it. Each class
+//! gives its object and request counts, bytes, latency percentiles from a
+//! [`Histogram`], and `request_mbps`: its bytes over the summed time its
+//! requests were in flight, so the rate of a single stream rather than of the
+//! run.
+
+use std::time::Duration;
+
+use crate::experiment::{fields, Fields};
+use crate::stats::{mbps, Histogram};
+
+pub const DEFAULT_EDGES: [u64; 4] = [1 << 20, 16 << 20, 128 << 20, 1 << 30];
+
+/// Check that `edges` can bucket objects in place of [`DEFAULT_EDGES`].
+pub fn check_edges(edges: &[u64]) -> Result<(), String> {
+    if edges.is_empty() || edges[0] == 0 || edges.windows(2).any(|w| w[0] >= w[1]) {
+        return Err(format!(
+            "size bucket edges must be positive and increasing, got {:?}",
+            edges
+        ));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Default)]
+struct SizeBucket {
+    objects: usize,
+    requests: usize,
+    bytes: u64,
+    busy_us: u128,
+    histogram: Histogram,
+}
+
+/// Objects and requests grouped by object size.
+#[derive(Debug, Clone)]
+pub struct SizeBuckets {
+    edges: Vec<u64>,
+    buckets: Vec<SizeBucket>,
+}
+
+impl Default for SizeBuckets {
+    fn default() -> Self {
+        Self::with_edges(&DEFAULT_EDGES)
+    }
+}
+
+impl SizeBuckets {
+    pub fn with_edges(edges: &[u64]) -> Self {
+        Self {
+            edges: edges.to_vec(),
+            buckets: vec![SizeBucket::default(); edges.len() + 1],
+        }
+    }
+
+    fn bucket(&mut self, object_size: usize) -> &mut SizeBucket {
+        let i = self
+            .edges
+            .partition_point(|edge| *edge <= object_size as u64);
+        &mut self.buckets[i]
+    }
+
+    /// Count an object of `size` bytes read by the run.
+    pub fn add_object(&mut self, size: usize) {
+        self.bucket(size).objects += 1;
+    }
+
+    /// Count a request that read `bytes` of an object of `object_size` bytes.
+    pub fn record(&mut self, object_size: usize, latency: Duration, bytes: usize) {
+        let bucket = self.bucket(object_size);
+        bucket.requests += 1;
+        bucket.bytes += bytes as u64;
+        bucket.busy_us += latency.as_micros();
+        bucket.histogram.record(latency);
+    }
+
+    /// The classes as the field `"by_size_bucket": [...]`.
+    pub fn json_field(&self) -> Fields {
+        let buckets = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(i, bucket)| {
+                serde_json::json!({
+                    "min_size": if i == 0 { 0 } else { self.edges[i - 1] },
+                    "max_size": self.edges.get(i),
+                    "objects": bucket.objects,
+                    "requests": bucket.requests,
+                    "bytes": bucket.bytes,
+                    "request_mbps": (bucket.busy_us > 0).then(|| mbps(bucket.bytes, bucket.busy_us)),
+                    "latency": bucket.histogram.summary(),
+                })
+            })
+            .collect::<Vec<_>>();
+        fields(serde_json::json!({ "by_size_bucket": buckets }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests_fall_in_their_object_size_class() {
+        let mut buckets = SizeBuckets::with_edges(&DEFAULT_EDGES);
+        for size in [4096, 1 << 20, 64 << 20, 2 << 30] {
+            buckets.add_object(size);
+        }
+        buckets.record(4096, Duration::from_millis(100), 4096);
+        buckets.record(64 << 20, Duration::from_millis(100), 8 << 20);
+        buckets.record(64 << 20, Duration::from_millis(300), 8 << 20);
+
+        let json = buckets.json_field();
+        let buckets = json["by_size_bucket"].as_array().unwrap();
+        assert_eq!(buckets.len(), 5);
+        let objects = buckets
+            .iter()
+            .map(|b| b["objects"].clone())
+            .collect::<Vec<_>>();
+        // 1 MiB is an edge, so it falls in the class above.
+        assert_eq!(objects, [1, 1, 1, 0, 1]);
+        assert_eq!(buckets[0]["max_size"], 1 << 20);
+        assert_eq!(buckets[4]["max_size"], serde_json::Value::Null);
+        assert_eq!(buckets[2]["min_size"], 16 << 20);
+        assert_eq!(buckets[2]["requests"], 2);
+        // 16 MiB took 0.4s of request time.
+        assert_eq!(buckets[2]["request_mbps"], 40.0);
+        assert_eq!(buckets[1]["request_mbps"], serde_json::Value::Null);
+        assert_eq!(buckets[0]["latency"]["count"], 1);
+    }
+
+    #[test]
+    fn edges_must_increase() {
+        assert!(check_edges(&[]).is_err());
+        assert!(check_edges(&[0, 10]).is_err());
+        assert!(check_edges(&[10, 10]).is_err());
+        assert!(check_edges(&[10, 20]).is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+//! A terminal chart of a sweep, printed to stderr after its results.
+//!
+//! A sweep's summary lists one point per parameter value, which is hard to
+//! read as a curve in JSON. When stdout is a terminal, or with `--sparkline`,
+//! sweeps also print each value with a block-character sparkline of its
+//! throughput and p99 latency beneath, marking the value with the best
+//! throughput. Otherwise nothing is printed, so logs and pipes see only the
+//! results.
+
+use std::io::IsTerminal;
+
+const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// One parameter value of a sweep and what the run at it measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SweepPoint {
+    pub value: String,
+    pub mbps: Option<f64>,
+    pub p99_us: Option<f64>,
+}
+
+/// The runs of a sweep over `parameter`, in the order they ran.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep {
+    pub parameter: String,
+    pub points: Vec<SweepPoint>,
+}
+
+impl Sweep {
+    /// Index of the point with the highest throughput, the first on ties.
+    pub fn best(&self) -> Option<usize> {
+        self.points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, point)| Some((i, point.mbps?)))
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))
+            .map(|(i, _)| i)
+    }
+
+    /// The chart, one line per row, each ending in a newline.
+    pub fn render(&self) -> String {
+        let width = self
+            .points
+            .iter()
+            .map(|point| point.value.chars().count())
+            .max()
+            .unwrap_or(0)
+            + 1;
+        let label_width = self.parameter.chars().count().max("p99_us".len()) + 2;
+        let row = |label: &str, cells: Vec<String>, suffix: String| {
+            let cells = cells
+                .iter()
+                .map(|cell| format!("{:<width$}", cell, width = width))
+                .collect::<String>();
+            let line = format!("{:<label_width$}{}{}", label, cells, suffix);
+            format!("{}\n", line.trim_end())
+        };
+        let values = self
+            .points
+            .iter()
+            .map(|point| point.value.clone())
+            .collect();
+        let mbps = self
+            .points
+            .iter()
+            .map(|point| point.mbps)
+            .collect::<Vec<_>>();
+        let p99 = self
+            .points
+            .iter()
+            .map(|point| point.p99_us)
+            .collect::<Vec<_>>();
+        let mut chart = row(&self.parameter, values, String::new());
+        chart += &row("mbps", spark(&mbps), range(&mbps, 1));
+        chart += &row("p99_us", spark(&p99), range(&p99, 0));
+        if let Some(best) = self.best() {
+            let mut marks = vec![String::new(); self.points.len()];
+            marks[best] = "^".to_string();
+            chart += &row("best", marks, String::new());
+        }
+        chart
+    }
+}
+
+/// A block per value, scaled between the smallest and largest; missing
+/// values are left blank.
+fn spark(values: &[Option<f64>]) -> Vec<String> {
+    let present = values.iter().flatten();
+    let min = present.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = present.copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| match value {
+            Some(value) if max > min => {
+                let level = (value - min) / (max - min) * (BLOCKS.len() - 1) as f64;
+                BLOCKS[level.round() as usize].to_string()
+            }
+            Some(_) => BLOCKS[BLOCKS.len() / 2].to_string(),
+            None => String::new(),
+        })
+        .collect()
+}
+
+/// The smallest and largest of `values`, for the end of a row.
+fn range(values: &[Option<f64>], decimals: usize) -> String {
+    let present = values.iter().flatten();
+    let min = present.clone().copied().fold(f64::INFINITY, f64::min);
+    let max = present.copied().fold(f64::NEG_INFINITY, f64::max);
+    if min > max {
+        return String::new();
+    }
+    format!(" {:.*} to {:.*}", decimals, min, decimals, max)
+}
+
+/// Print the chart of `sweep` to stderr, if stdout is a terminal or charts
+/// were `forced`.
+pub fn show(sweep: &Sweep, forced: bool) {
+    if forced || std::io::stdout().is_terminal() {
+        eprint!("{}", sweep.render());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(value: &str, mbps: Option<f64>, p99_us: Option<f64>) -> SweepPoint {
+        SweepPoint {
+            value: value.to_string(),
+            mbps,
+            p99_us,
+        }
+    }
+
+    #[test]
+    fn renders_a_sweep() {
+        let sweep = Sweep {
+            parameter: "pool_max_idle_per_host".to_string(),
+            points: vec![
+                point("1", Some(10.0), Some(9000.0)),
+                point("4", Some(40.0), Some(3000.0)),
+                point("16", Some(80.0), Some(1000.0)),
+                point("64", Some(70.0), Some(2000.0)),
+            ],
+        };
+        assert_eq!(sweep.best(), Some(2));
+        assert_eq!(
+            sweep.render(),
+            "pool_max_idle_per_host  1  4  16 64\n\
+             mbps                    ▁  ▄  █  ▇   10.0 to 80.0\n\
+             p99_us                  █  ▃  ▁  ▂   1000 to 9000\n\
+             best                          ^\n"
+        );
+    }
+
+    #[test]
+    fn missing_and_flat_values() {
+        let sweep = Sweep {
+            parameter: "n".to_string(),
+            points: vec![
+                point("1", Some(5.0), None),
+                point("2", None, None),
+                point("3", Some(5.0), None),
+            ],
+        };
+        assert_eq!(sweep.best(), Some(0));
+        assert_eq!(
+            sweep.render(),
+            "n       1 2 3\n\
+             mbps    ▅   ▅  5.0 to 5.0\n\
+             p99_us\n\
+             best    ^\n"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Summary statistics over request samples.
+
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::control::PhaseBoundaries;
+use crate::experiment::{fields, Fields};
+
+/// Throughput in MiB/s of `bytes` moved in `elapsed_us` microseconds.
+///
+/// Each operand is converted to floating point once, so a total past 2^53
+/// bytes loses only its lowest bits instead of overflowing an intermediate
+/// product.
+pub fn mbps(bytes: u64, elapsed_us: u128) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0) / (elapsed_us as f64 / 1_000_000.0)
+}
+
+/// The microseconds of `elapsed_us` a run spent measuring, without the
+/// `paused_us` it spent paused. A pause still in progress counts toward
+/// `paused_us` and can outlast the elapsed time taken before it, so this
+/// stops at zero.
+pub fn active_us(elapsed_us: u128, paused_us: u128) -> u128 {
+    elapsed_us.saturating_sub(paused_us)
+}
+
+/// Latency distribution over a set of requests, in microseconds.
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct LatencySummary {
+    pub count: usize,
+    pub min_us: u128,
+    pub p50_us: u128,
+    pub p90_us: u128,
+    pub p99_us: u128,
+    pub max_us: u128,
+}
+
+impl LatencySummary {
+    /// Summarize `latencies`, which are sorted in place.
+    pub fn from_latencies(latencies: &mut [Duration]) -> Self {
+        if latencies.is_empty() {
+            return Self::default();
+        }
+        latencies.sort();
+        Self {
+            count: latencies.len(),
+            min_us: latencies[0].as_micros(),
+            p50_us: percentile(latencies, 0.50).as_micros(),
+            p90_us: percentile(latencies, 0.90).as_micros(),
+            p99_us: percentile(latencies, 0.99).as_micros(),
+            max_us: latencies[latencies.len() - 1].as_micros(),
+        }
+    }
+
+    pub fn to_json(self) -> Value {
+        json!({
+            "count": self.count,
+            "min_us": self.min_us as u64,
+            "p50_us": self.p50_us as u64,
+            "p90_us": self.p90_us as u64,
+            "p99_us": self.p99_us as u64,
+            "max_us": self.max_us as u64,
+        })
+    }
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice.
+pub fn percentile(sorted: &[Duration], q: f64) -> Duration {
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Log-bucketed latency histogram that can be merged across runs.
+///
+/// Bucket `i` holds latencies in `[2^(i/4), 2^((i+1)/4))` microseconds, so
+/// histograms from different workers line up bucket for bucket and merge by
+/// adding counts. Percentiles read from it are accurate to within a bucket.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Histogram {
+    counts: std::collections::BTreeMap<u32, u64>,
+}
+
+const BUCKETS_PER_OCTAVE: f64 = 4.0;
+
+impl Histogram {
+    pub fn record(&mut self, latency: Duration) {
+        let us = latency.as_micros().max(1) as f64;
+        let bucket = (us.log2() * BUCKETS_PER_OCTAVE).floor() as u32;
+        *self.counts.entry(bucket).or_default() += 1;
+    }
+
+    pub fn merge(&mut self, other: &Histogram) {
+        for (bucket, count) in &other.counts {
+            *self.counts.entry(*bucket).or_default() += count;
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.values().sum()
+    }
+
+    /// Upper bound of the bucket holding the nearest-rank `q` percentile.
+    pub fn percentile_us(&self, q: f64) -> u128 {
+        let count = self.count();
+        if count == 0 {
+            return 0;
+        }
+        let rank = ((q * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, bucket_count) in &self.counts {
+            seen += bucket_count;
+            if seen >= rank {
+                return bucket_upper_us(*bucket);
+            }
+        }
+        unreachable!("rank is at most the total count")
+    }
+
+    /// Approximate summary, with each statistic rounded up to its bucket.
+    pub fn summary(&self) -> LatencySummary {
+        LatencySummary {
+            count: self.count() as usize,
+            min_us: self.percentile_us(0.0),
+            p50_us: self.percentile_us(0.50),
+            p90_us: self.percentile_us(0.90),
+            p99_us: self.percentile_us(0.99),
+            max_us: self.percentile_us(1.0),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        json!({
+            "buckets_per_octave": BUCKETS_PER_OCTAVE,
+            "counts": self.counts.iter().map(|(b, c)| [*b as u64, *c]).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Parse a histogram previously written by [`Histogram::to_json`].
+    pub fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        if value.get("buckets_per_octave").and_then(|v| v.as_f64()) != Some(BUCKETS_PER_OCTAVE) {
+            return Err("histogram has a different bucket layout".to_string());
+        }
+        let pairs: Vec<[u64; 2]> = value
+            .get("counts")
+            .cloned()
+            .map(serde_json::from_value)
+            .transpose()
+            .map_err(|err| format!("invalid histogram counts: {}", err))?
+            .unwrap_or_default();
+        let mut histogram = Self::default();
+        for [bucket, count] in pairs {
+            *histogram.counts.entry(bucket as u32).or_default() += count;
+        }
+        Ok(histogram)
+    }
+}
+
+fn bucket_upper_us(bucket: u32) -> u128 {
+    2f64.powf((bucket + 1) as f64 / BUCKETS_PER_OCTAVE).ceil() as u128
+}
+
+/// One completed request, placed in time for [`windowed`].
+#[derive(Debug, Clone, Copy)]
+pub struct TimedSample {
+    /// When the request completed, relative to the start of the run
+    pub completed: Duration,
+    pub latency: Duration,
+    pub bytes: usize,
+    pub error: bool,
+}
+
+/// Per-window latency and throughput over a run, as the fields
+/// `"windows": [...], "window_summary": {...}`.
+///
+/// Samples are grouped into consecutive `window`-long intervals by completion
+/// time, the last one cut short at `elapsed`. The summary names the window
+/// with the highest p99 and the least-squares slope of p99 against each
+/// window's midpoint, so a run that degrades steadily shows a positive slope.
+pub fn windowed(samples: &[TimedSample], window: Duration, elapsed: Duration) -> Fields {
+    let window_us = window.as_micros().max(1);
+    let num_windows = elapsed.as_micros().div_ceil(window_us).max(1) as usize;
+    let mut latencies = vec![Vec::new(); num_windows];
+    let mut bytes = vec![0; num_windows];
+    let mut errors = vec![0; num_windows];
+    for sample in samples {
+        let i = ((sample.completed.as_micros() / window_us) as usize).min(num_windows - 1);
+        latencies[i].push(sample.latency);
+        bytes[i] += sample.bytes;
+        errors[i] += sample.error as usize;
+    }
+
+    let mut windows = Vec::with_capacity(num_windows);
+    let mut points = Vec::new();
+    let mut worst: Option<(usize, u128)> = None;
+    for (i, latencies) in latencies.iter_mut().enumerate() {
+        let start_us = i as u128 * window_us;
+        let end_us = (start_us + window_us).min(elapsed.as_micros().max(start_us + 1));
+        let summary = LatencySummary::from_latencies(latencies);
+        if summary.count > 0 {
+            let midpoint_s = (start_us + end_us) as f64 / 2.0 / 1_000_000.0;
+            points.push((midpoint_s, summary.p99_us as f64));
+            if worst.is_none_or(|(_, p99)| summary.p99_us > p99) {
+                worst = Some((i, summary.p99_us));
+            }
+        }
+        windows.push(json!({
+            "start_s": start_us as f64 / 1_000_000.0,
+            "end_s": end_us as f64 / 1_000_000.0,
+            "requests": summary.count,
+            "errors": errors[i],
+            "p50_us": summary.p50_us as u64,
+            "p99_us": summary.p99_us as u64,
+            "mbps": bytes[i] as f64 / 1024.0 / 1024.0 / ((end_us - start_us) as f64 / 1_000_000.0),
+        }));
+    }
+
+    fields(json!({
+        "windows": windows,
+        "window_summary": {
+            "window_secs": window.as_secs_f64(),
+            "worst_window": worst.map(|(i, _)| i),
+            "worst_p99_us": worst.map(|(_, p99)| p99 as u64),
+            "p99_slope_us_per_s": slope(&points),
+        },
+    }))
+}
+
+/// Per-window throughput of acknowledged bytes, as the fields
+/// `"windows": [...], "window_summary": {...}`.
+///
+/// Samples are grouped by completion time as in [`windowed`]. A window whose
+/// throughput is below `slow_fraction` of the median window's is flagged as
+/// slow, and the summary lists the slow windows and names the worst one, so
+/// an upload that degrades partway through stands out from its average.
+pub fn throughput_windows(
+    samples: &[TimedSample],
+    window: Duration,
+    elapsed: Duration,
+    slow_fraction: f64,
+) -> Fields {
+    let window_us = window.as_micros().max(1);
+    let num_windows = elapsed.as_micros().div_ceil(window_us).max(1) as usize;
+    let mut writes = vec![0; num_windows];
+    let mut bytes = vec![0; num_windows];
+    for sample in samples {
+        let i = ((sample.completed.as_micros() / window_us) as usize).min(num_windows - 1);
+        writes[i] += 1;
+        bytes[i] += sample.bytes;
+    }
+    let spans = (0..num_windows)
+        .map(|i| {
+            let start_us = i as u128 * window_us;
+            let end_us = (start_us + window_us).min(elapsed.as_micros().max(start_us + 1));
+            (start_us, end_us)
+        })
+        .collect::<Vec<_>>();
+    let mbps = spans
+        .iter()
+        .zip(&bytes)
+        .map(|((start_us, end_us), bytes)| {
+            *bytes as f64 / 1024.0 / 1024.0 / ((end_us - start_us) as f64 / 1_000_000.0)
+        })
+        .collect::<Vec<_>>();
+
+    let mut sorted = mbps.clone();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    let threshold = median * slow_fraction;
+    let slow = mbps
+        .iter()
+        .map(|mbps| *mbps < threshold)
+        .collect::<Vec<_>>();
+    let worst = (0..num_windows)
+        .min_by(|a, b| mbps[*a].total_cmp(&mbps[*b]))
+        .unwrap_or(0);
+
+    let windows = (0..num_windows)
+        .map(|i| {
+            json!({
+                "start_s": spans[i].0 as f64 / 1_000_000.0,
+                "end_s": spans[i].1 as f64 / 1_000_000.0,
+                "writes": writes[i],
+                "bytes": bytes[i],
+                "mbps": mbps[i],
+                "slow": slow[i],
+            })
+        })
+        .collect::<Vec<_>>();
+    let slow_windows = (0..num_windows).filter(|i| slow[*i]).collect::<Vec<_>>();
+    fields(json!({
+        "windows": windows,
+        "window_summary": {
+            "window_secs": window.as_secs_f64(),
+            "median_mbps": median,
+            "slow_fraction": slow_fraction,
+            "slow_threshold_mbps": threshold,
+            "slow_windows": slow_windows,
+            "worst_window": worst,
+            "worst_mbps": mbps[worst],
+        },
+    }))
+}
+
+/// Bytes and requests completed in each second of a run, as the field
+/// `"timeline": [...]`.
+///
+/// Bucket `i` holds the samples completing in `[i, i + 1)` seconds after the
+/// start, so boundaries don't depend on the window length or on when the run
+/// ended. Every second up to `elapsed` and the last completion has a bucket,
+/// empty or not, so the series plots without gaps.
+pub fn timeline(samples: &[TimedSample], elapsed: Duration) -> Fields {
+    let last = samples
+        .iter()
+        .map(|sample| sample.completed)
+        .max()
+        .unwrap_or_default();
+    let num_buckets =
+        (elapsed.as_micros().div_ceil(1_000_000) as usize).max(last.as_secs() as usize + 1);
+    let mut requests = vec![0; num_buckets];
+    let mut bytes = vec![0; num_buckets];
+    for sample in samples {
+        let i = sample.completed.as_secs() as usize;
+        requests[i] += 1;
+        bytes[i] += sample.bytes;
+    }
+    let buckets = (0..num_buckets)
+        .map(|i| json!({"start_s": i, "requests": requests[i], "bytes": bytes[i]}))
+        .collect::<Vec<_>>();
+    fields(json!({ "timeline": buckets }))
+}
+
+/// Throughput in each phase of a run, as the fields
+/// `"phases": {...}, "steady_mbps": ...`.
+///
+/// Each request's bytes count toward the phase in which it completed. The
+/// steady-state figure leaves out the slow start and the stragglers at the
+/// end, so it is the one to compare across configurations. Time spent paused
+/// is not excluded.
+pub fn phases(
+    samples: &[TimedSample],
+    boundaries: Option<PhaseBoundaries>,
+    elapsed: Duration,
+) -> Fields {
+    let Some(boundaries) = boundaries else {
+        return fields(json!({"phases": null, "steady_mbps": null}));
+    };
+    let ramp_end = boundaries.ramped_up.unwrap_or(boundaries.last_issued);
+    let spans = [
+        Some((boundaries.first_issued, ramp_end)),
+        boundaries
+            .ramped_up
+            .map(|ramped_up| (ramped_up, boundaries.last_issued)),
+        Some((boundaries.last_issued, elapsed.max(boundaries.last_issued))),
+    ];
+    let mut requests = [0; 3];
+    let mut bytes = [0; 3];
+    for sample in samples {
+        let phase = if sample.completed < ramp_end {
+            0
+        } else if sample.completed < boundaries.last_issued {
+            1
+        } else {
+            2
+        };
+        requests[phase] += 1;
+        bytes[phase] += sample.bytes;
+    }
+
+    let mbps = |phase: usize| {
+        let (start, end) = spans[phase]?;
+        let secs = end.saturating_sub(start).as_secs_f64();
+        (secs > 0.0).then(|| bytes[phase] as f64 / 1024.0 / 1024.0 / secs)
+    };
+    let phase = |phase: usize| {
+        spans[phase].map(|(start, end)| {
+            json!({
+                "start_s": start.as_secs_f64(),
+                "end_s": end.as_secs_f64(),
+                "requests": requests[phase],
+                "bytes": bytes[phase],
+                "mbps": mbps(phase),
+            })
+        })
+    };
+    fields(json!({
+        "phases": {"ramp_up": phase(0), "steady": phase(1), "drain": phase(2)},
+        "steady_mbps": mbps(1),
+    }))
+}
+
+/// Least-squares slope of `y` against `x`, if there are two distinct `x`.
+fn slope(points: &[(f64, f64)]) -> Option<f64> {
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let sxx = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+    let sxy = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    (points.len() >= 2 && sxx > 0.0).then(|| sxy / sxx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(completed_ms: u64, bytes: usize) -> TimedSample {
+        TimedSample {
+            completed: Duration::from_millis(completed_ms),
+            latency: Duration::from_millis(1),
+            bytes,
+            error: false,
+        }
+    }
+
+    #[test]
+    fn timeline_buckets_every_second_including_empty_ones() {
+        let samples = [sample(100, 10), sample(999, 5), sample(2500, 7)];
+        let timeline = super::timeline(&samples, Duration::from_millis(3200));
+        assert_eq!(
+            timeline["timeline"],
+            serde_json::json!([
+                {"start_s": 0, "requests": 2, "bytes": 15},
+                {"start_s": 1, "requests": 0, "bytes": 0},
+                {"start_s": 2, "requests": 1, "bytes": 7},
+                {"start_s": 3, "requests": 0, "bytes": 0},
+            ])
+        );
+        let empty = super::timeline(&[], Duration::ZERO);
+        assert_eq!(
+            empty["timeline"],
+            serde_json::json!([{"start_s": 0, "requests": 0, "bytes": 0}])
+        );
+    }
+
+    #[test]
+    fn a_pause_in_progress_leaves_no_active_time() {
+        assert_eq!(active_us(1_000, 250), 750);
+        assert_eq!(active_us(1_000, 1_500), 0);
+    }
+}
@@ -0,0 +1,244 @@
+//! Default tuning for each kind of backend.
+//!
+//! Ten parallel requests is far too few to saturate S3 and more than a local
+//! disk needs, so the default concurrency and block size depend on the store
+//! the URI points at. Flags given explicitly always win. The detected family
+//! and the defaults that applied are recorded in every result under `store`.
+//!
+//! Tuning a new backend means adding it to [`StoreFamily`] and [`DEFAULTS`].
+
+use url::Url;
+
+use crate::experiment::Fields;
+
+/// Kinds of backend that get their own defaults.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreFamily {
+    Local,
+    Memory,
+    S3,
+    Gcs,
+    Azure,
+    /// Anything else, which keeps the tool's historical defaults
+    Other,
+}
+
+/// Defaults for the flags that depend on the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StoreDefaults {
+    pub parallel_downloads: usize,
+    /// `None` splits each object evenly across the parallel downloads
+    pub block_size: Option<usize>,
+    /// Multipart upload part size. object_store fixes this at 10 MiB in the
+    /// version this tool builds against, so it is reported but not applied.
+    pub part_size: usize,
+}
+
+/// Smallest part S3 and GCS accept in a multipart upload, other than the last.
+pub const MIN_S3_PART_SIZE: usize = 5 * MIB;
+
+const MIB: usize = 1024 * 1024;
+pub const PART_SIZE: usize = 10 * MIB;
+
+const DEFAULTS: &[(StoreFamily, StoreDefaults)] = &[
+    (
+        StoreFamily::Local,
+        StoreDefaults {
+            parallel_downloads: 4,
+            block_size: None,
+            part_size: PART_SIZE,
+        },
+    ),
+    (
+        StoreFamily::Memory,
+        StoreDefaults {
+            parallel_downloads: 4,
+            block_size: None,
+            part_size: PART_SIZE,
+        },
+    ),
+    (
+        StoreFamily::S3,
+        StoreDefaults {
+            parallel_downloads: 64,
+            block_size: Some(8 * MIB),
+            part_size: PART_SIZE,
+        },
+    ),
+    (
+        StoreFamily::Gcs,
+        StoreDefaults {
+            parallel_downloads: 32,
+            block_size: Some(16 * MIB),
+            part_size: PART_SIZE,
+        },
+    ),
+    (
+        StoreFamily::Azure,
+        StoreDefaults {
+            parallel_downloads: 32,
+            block_size: Some(8 * MIB),
+            part_size: PART_SIZE,
+        },
+    ),
+    (
+        StoreFamily::Other,
+        StoreDefaults {
+            parallel_downloads: 10,
+            block_size: None,
+            part_size: PART_SIZE,
+        },
+    ),
+];
+
+impl StoreFamily {
+    /// The family a URI points at, from its scheme and, for HTTPS, its host.
+    pub fn detect(url: &Url) -> Self {
+        match url.scheme() {
+            "file" => StoreFamily::Local,
+            "memory" => StoreFamily::Memory,
+            "s3" | "s3a" => StoreFamily::S3,
+            "gs" => StoreFamily::Gcs,
+            "az" | "adl" | "azure" | "abfs" | "abfss" => StoreFamily::Azure,
+            "http" | "https" => match url.host_str().unwrap_or_default() {
+                "storage.googleapis.com" => StoreFamily::Gcs,
+                host if host.ends_with(".amazonaws.com") => StoreFamily::S3,
+                host if host.ends_with(".blob.core.windows.net")
+                    || host.ends_with(".dfs.core.windows.net") =>
+                {
+                    StoreFamily::Azure
+                }
+                _ => StoreFamily::Other,
+            },
+            _ => StoreFamily::Other,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            StoreFamily::Local => "local",
+            StoreFamily::Memory => "memory",
+            StoreFamily::S3 => "s3",
+            StoreFamily::Gcs => "gcs",
+            StoreFamily::Azure => "azure",
+            StoreFamily::Other => "other",
+        }
+    }
+
+    /// Check `part_size` against the smallest part the family's multipart
+    /// uploads accept.
+    pub fn check_part_size(self, part_size: usize) -> Result<(), String> {
+        match self {
+            StoreFamily::S3 | StoreFamily::Gcs if part_size < MIN_S3_PART_SIZE => Err(format!(
+                "--part-size {} is below the {} byte minimum part size for {} multipart uploads",
+                part_size,
+                MIN_S3_PART_SIZE,
+                self.name()
+            )),
+            _ if part_size == 0 => Err("--part-size must be at least 1 byte".to_string()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn defaults(self) -> StoreDefaults {
+        DEFAULTS
+            .iter()
+            .find(|(family, _)| *family == self)
+            .map(|(_, defaults)| *defaults)
+            .expect("every family has defaults")
+    }
+}
+
+/// Append the detected store `family` and its defaults to the JSON object
+/// `result`.
+pub fn with_store(result: &mut Fields, family: StoreFamily) {
+    let defaults = family.defaults();
+    let store = serde_json::json!({
+        "family": family.name(),
+        "defaults": {
+            "parallel_downloads": defaults.parallel_downloads,
+            "block_size": defaults.block_size,
+            "part_size": defaults.part_size,
+        },
+    });
+    result.insert("store".to_string(), store);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(uri: &str) -> StoreFamily {
+        StoreFamily::detect(&Url::parse(uri).unwrap())
+    }
+
+    #[test]
+    fn detects_families() {
+        assert_eq!(detect("file:///tmp/data"), StoreFamily::Local);
+        assert_eq!(detect("memory:///data"), StoreFamily::Memory);
+        assert_eq!(detect("s3://bucket/data"), StoreFamily::S3);
+        assert_eq!(detect("s3a://bucket/data"), StoreFamily::S3);
+        assert_eq!(
+            detect("https://bucket.s3.us-east-1.amazonaws.com/data"),
+            StoreFamily::S3
+        );
+        assert_eq!(detect("gs://bucket/data"), StoreFamily::Gcs);
+        assert_eq!(
+            detect("https://storage.googleapis.com/bucket/data"),
+            StoreFamily::Gcs
+        );
+        assert_eq!(detect("az://container/data"), StoreFamily::Azure);
+        assert_eq!(
+            detect("https://account.blob.core.windows.net/container"),
+            StoreFamily::Azure
+        );
+        assert_eq!(detect("https://example.com/data"), StoreFamily::Other);
+        assert_eq!(detect("ftp://example.com/data"), StoreFamily::Other);
+    }
+
+    #[test]
+    fn s3_compatible_stores_need_5mib_parts() {
+        assert!(StoreFamily::S3.check_part_size(MIN_S3_PART_SIZE).is_ok());
+        assert!(StoreFamily::S3
+            .check_part_size(MIN_S3_PART_SIZE - 1)
+            .unwrap_err()
+            .contains("minimum part size for s3"));
+        assert!(StoreFamily::Gcs.check_part_size(1024).is_err());
+        assert!(StoreFamily::Local.check_part_size(1024).is_ok());
+        assert!(StoreFamily::Local.check_part_size(0).is_err());
+    }
+
+    #[test]
+    fn every_family_has_one_set_of_defaults() {
+        let families = [
+            StoreFamily::Local,
+            StoreFamily::Memory,
+            StoreFamily::S3,
+            StoreFamily::Gcs,
+            StoreFamily::Azure,
+            StoreFamily::Other,
+        ];
+        for family in families {
+            let entries = DEFAULTS.iter().filter(|(f, _)| *f == family).count();
+            assert_eq!(entries, 1, "{:?}", family);
+            assert!(family.defaults().parallel_downloads > 0);
+        }
+        assert_eq!(DEFAULTS.len(), families.len());
+    }
+
+    #[test]
+    fn unknown_stores_keep_the_old_defaults() {
+        let defaults = StoreFamily::Other.defaults();
+        assert_eq!(defaults.parallel_downloads, 10);
+        assert_eq!(defaults.block_size, None);
+    }
+
+    #[test]
+    fn remote_stores_read_more_in_parallel_than_local_ones() {
+        let local = StoreFamily::Local.defaults().parallel_downloads;
+        for family in [StoreFamily::S3, StoreFamily::Gcs, StoreFamily::Azure] {
+            assert!(family.defaults().parallel_downloads > local);
+            assert!(family.defaults().block_size.is_some());
+        }
+    }
+}
@@ -0,0 +1,256 @@
+//! Builder settings for the store, with `--store-option` and `--store-config`.
+//!
+//! Pointing at a MinIO endpoint or a bucket outside the default region
+//! otherwise means exporting the right environment variables first.
+//! `--store-option aws_endpoint=http://localhost:9000`, repeated, passes each
+//! key to the store's builder, as do the top-level keys of a `--store-config`
+//! TOML file:
+//!
+//! ```toml
+//! aws_endpoint = "http://localhost:9000"
+//! aws_allow_http = true
+//! aws_region = "eu-west-1"
+//! ```
+//!
+//! A key on the command line beats the same key in the file. Every key is
+//! checked against the builder for the store's URL before anything runs,
+//! and one it doesn't know fails with the builder's own error, where
+//! `parse_url_opts` would drop it. Only S3 and GCS stores have builders that
+//! take options; as in `parse_url`, that covers `https://` URLs on an
+//! `amazonaws.com` or `r2.cloudflarestorage.com` host as well as `s3://`.
+//! `https://storage.googleapis.com/bucket/...` is read over plain HTTP
+//! unless options are given, when it is built as a GCS store like
+//! `gs://bucket/...`. Results list the keys under `config`, never their
+//! values, which may be credentials.
+
+use std::str::FromStr;
+
+use object_store::aws::{AmazonS3Builder, AmazonS3ConfigKey};
+use object_store::gcp::{GoogleCloudStorageBuilder, GoogleConfigKey};
+use object_store::path::Path;
+use object_store::ObjectStore;
+use url::Url;
+
+/// Parse a `--store-option key=value` argument.
+pub fn parse_option(arg: &str) -> Result<(String, String), String> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got {:?}", arg))?;
+    let key = key.trim();
+    if key.is_empty() {
+        return Err(format!("expected key=value, got {:?}", arg));
+    }
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// The options in the `--store-config` file at `path`.
+pub fn load(path: &std::path::Path) -> Result<Vec<(String, String)>, String> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("reading {}: {}", path.display(), err))?;
+    parse_config(&contents).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+fn parse_config(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let table = contents
+        .parse::<toml::Table>()
+        .map_err(|err| err.message().to_string())?;
+    table
+        .into_iter()
+        .map(|(key, value)| match value {
+            toml::Value::String(value) => Ok((key, value)),
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                Ok((key, value.to_string()))
+            }
+            _ => Err(format!("{}: expected a string, number or boolean", key)),
+        })
+        .collect()
+}
+
+/// The builders that take store options.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builder {
+    S3,
+    Gcs,
+}
+
+/// The builder for `url`, told by its scheme or, for `https`, its host.
+pub fn builder(url: &Url) -> Option<Builder> {
+    match (url.scheme(), url.host_str().unwrap_or_default()) {
+        ("s3" | "s3a", _) => Some(Builder::S3),
+        ("gs", _) => Some(Builder::Gcs),
+        ("https", host)
+            if host.ends_with("amazonaws.com") || host.ends_with("r2.cloudflarestorage.com") =>
+        {
+            Some(Builder::S3)
+        }
+        ("https", "storage.googleapis.com") => Some(Builder::Gcs),
+        _ => None,
+    }
+}
+
+/// The bucket and object path of a `https://storage.googleapis.com/bucket/...`
+/// URL, which `parse_url` only knows how to read over plain HTTP.
+pub fn gcs_bucket(url: &Url) -> object_store::Result<Option<(String, Path)>> {
+    if url.scheme() != "https" || builder(url) != Some(Builder::Gcs) {
+        return Ok(None);
+    }
+    let path = url.path().trim_start_matches('/');
+    let (bucket, rest) = path.split_once('/').unwrap_or((path, ""));
+    if bucket.is_empty() {
+        return Err(object_store::Error::Generic {
+            store: "GCS",
+            source: format!("{} names no bucket", url).into(),
+        });
+    }
+    Ok(Some((bucket.to_string(), Path::from_url_path(rest)?)))
+}
+
+/// The bucket of a `https://bucket.s3.amazonaws.com/...` URL, the global
+/// endpoint `parse_url` doesn't recognise without a region in the host.
+pub fn global_s3_bucket(url: &Url) -> Option<&str> {
+    let host = url.host_str().filter(|_| url.scheme() == "https")?;
+    host.strip_suffix(".s3.amazonaws.com")
+        .filter(|bucket| !bucket.is_empty() && !bucket.contains('.'))
+}
+
+/// Check `options` against the builder for `url`.
+pub fn check(url: &Url, options: &[(String, String)]) -> Result<(), String> {
+    let Some((key, _)) = options.first() else {
+        return Ok(());
+    };
+    let unknown = |err: object_store::Error| err.to_string();
+    match builder(url) {
+        Some(Builder::S3) => options
+            .iter()
+            .try_for_each(|(key, _)| AmazonS3ConfigKey::from_str(key).map(drop))
+            .map_err(unknown),
+        Some(Builder::Gcs) => {
+            gcs_bucket(url).map_err(unknown)?;
+            options
+                .iter()
+                .try_for_each(|(key, _)| GoogleConfigKey::from_str(key).map(drop))
+                .map_err(unknown)
+        }
+        None => Err(format!(
+            "{} stores take no store options, but {:?} was given",
+            url.scheme(),
+            key
+        )),
+    }
+}
+
+/// `object_store::parse_url`, with the store `options`, already [`check`]ed.
+pub fn parse_url(
+    url: &Url,
+    options: &[(String, String)],
+) -> object_store::Result<(Box<dyn ObjectStore>, Path)> {
+    if let Some(bucket) = global_s3_bucket(url) {
+        let builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+        let store = s3(builder, options)?.build()?;
+        return Ok((Box::new(store), Path::from_url_path(url.path())?));
+    }
+    if !options.is_empty() {
+        if let Some((bucket, path)) = gcs_bucket(url)? {
+            let builder = GoogleCloudStorageBuilder::from_env().with_bucket_name(bucket);
+            let store = gcs(builder, options)?.build()?;
+            return Ok((Box::new(store), path));
+        }
+    }
+    object_store::parse_url_opts(url, options.iter().cloned())
+}
+
+/// Apply the store `options` to an S3 `builder`.
+pub fn s3(
+    builder: AmazonS3Builder,
+    options: &[(String, String)],
+) -> object_store::Result<AmazonS3Builder> {
+    options.iter().try_fold(builder, |builder, (key, value)| {
+        Ok(builder.with_config(key.parse()?, value))
+    })
+}
+
+/// Apply the store `options` to a GCS `builder`.
+pub fn gcs(
+    builder: GoogleCloudStorageBuilder,
+    options: &[(String, String)],
+) -> object_store::Result<GoogleCloudStorageBuilder> {
+    options.iter().try_fold(builder, |builder, (key, value)| {
+        Ok(builder.with_config(key.parse()?, value))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(pairs: &[&str]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|pair| parse_option(pair).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn keys_are_checked_against_the_stores_builder() {
+        let s3 = Url::parse("s3://bucket/data").unwrap();
+        let known = options(&["aws_endpoint=http://localhost:9000", "aws_allow_http=true"]);
+        assert_eq!(check(&s3, &known), Ok(()));
+        let err = check(&s3, &options(&["aws_endpiont=http://localhost:9000"])).unwrap_err();
+        assert!(err.contains("aws_endpiont"), "{}", err);
+        let gs = Url::parse("gs://bucket/data").unwrap();
+        assert!(check(&gs, &known).is_err());
+        let file = Url::parse("file:///tmp/data").unwrap();
+        assert!(check(&file, &known).is_err());
+        assert_eq!(check(&file, &[]), Ok(()));
+        assert!(parse_option("aws_region").is_err());
+    }
+
+    #[test]
+    fn https_urls_are_told_apart_by_host() {
+        let region = options(&["aws_region=eu-west-1"]);
+        for uri in [
+            "https://mybucket.s3.amazonaws.com/data",
+            "https://s3.eu-west-1.amazonaws.com/mybucket/data",
+            "https://account.r2.cloudflarestorage.com/mybucket/data",
+        ] {
+            let url = Url::parse(uri).unwrap();
+            assert_eq!(builder(&url), Some(Builder::S3), "{}", uri);
+            assert_eq!(check(&url, &region), Ok(()), "{}", uri);
+        }
+
+        let gcs = Url::parse("https://storage.googleapis.com/mybucket/data/part-0").unwrap();
+        assert_eq!(builder(&gcs), Some(Builder::Gcs));
+        assert_eq!(
+            check(&gcs, &options(&["google_service_account=/tmp/key.json"])),
+            Ok(())
+        );
+        assert!(check(&gcs, &region).is_err());
+        assert_eq!(
+            gcs_bucket(&gcs).unwrap(),
+            Some(("mybucket".to_string(), Path::from("data/part-0")))
+        );
+        let bare = Url::parse("https://storage.googleapis.com/").unwrap();
+        assert!(check(&bare, &options(&["google_service_account=/tmp/key.json"])).is_err());
+
+        let global = Url::parse("https://mybucket.s3.amazonaws.com/data").unwrap();
+        assert_eq!(global_s3_bucket(&global), Some("mybucket"));
+        let regional = Url::parse("https://mybucket.s3.eu-west-1.amazonaws.com/data").unwrap();
+        assert_eq!(global_s3_bucket(&regional), None);
+
+        let other = Url::parse("https://example.com/data").unwrap();
+        assert_eq!(builder(&other), None);
+        assert!(check(&other, &region).is_err());
+    }
+
+    #[test]
+    fn config_files_hold_scalar_options() {
+        let parsed =
+            parse_config("aws_endpoint = \"http://localhost:9000\"\naws_allow_http = true\n")
+                .unwrap();
+        assert_eq!(
+            parsed,
+            options(&["aws_allow_http=true", "aws_endpoint=http://localhost:9000"])
+        );
+        assert!(parse_config("aws_region = [\"a\"]").is_err());
+    }
+}
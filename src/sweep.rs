@@ -0,0 +1,259 @@
+//! Sweeping `download` across concurrency values and block sizes.
+//!
+//! `--parallel-downloads` and `--block-size` each take a comma-separated list,
+//! such as `--parallel-downloads 1,2,4,8,16,32`. The download then runs once
+//! for every combination, concurrency outermost, emitting each run's result as
+//! usual with the `parallel_downloads` and `block_size` it used. The objects
+//! are listed only for the first run, and `--cooldown-secs` waits between runs
+//! so one run's tail requests don't land in the next. A `download_sweep`
+//! summary of each setting's throughput and p99 follows, charted on stderr as
+//! described in [`crate::sparkline`].
+
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+use std::time::Duration;
+
+use object_store::path::Path;
+
+use crate::control::RunControl;
+use crate::error::Error;
+
+use crate::sparkline::{Sweep, SweepPoint};
+
+/// One value, or a comma-separated list of values to sweep over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Values<T>(Vec<T>);
+
+impl<T: Copy> Values<T> {
+    pub fn one(value: T) -> Self {
+        Values(vec![value])
+    }
+
+    /// The first value, the only one unless sweeping.
+    pub fn first(&self) -> T {
+        self.0[0]
+    }
+
+    pub fn all(&self) -> &[T] {
+        &self.0
+    }
+
+    pub fn is_sweep(&self) -> bool {
+        self.0.len() > 1
+    }
+}
+
+impl<T> FromStr for Values<T>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    type Err = String;
+
+    fn from_str(arg: &str) -> Result<Self, Self::Err> {
+        arg.split(',')
+            .map(|value| {
+                value
+                    .trim()
+                    .parse()
+                    .map_err(|err| format!("{:?}: {}", value, err))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(Values)
+    }
+}
+
+/// One setting of a sweep, as its concurrency and block size.
+pub type Point = (usize, Option<usize>);
+
+/// Every combination of `parallel_downloads` and `block_sizes`, concurrency
+/// outermost.
+pub fn points(
+    parallel_downloads: &Values<usize>,
+    block_sizes: Option<&Values<usize>>,
+) -> Vec<Point> {
+    let block_sizes = match block_sizes {
+        Some(block_sizes) => block_sizes.all().iter().copied().map(Some).collect(),
+        None => vec![None],
+    };
+    parallel_downloads
+        .all()
+        .iter()
+        .flat_map(|&parallel| {
+            block_sizes
+                .iter()
+                .map(move |&block_size| (parallel, block_size))
+        })
+        .collect()
+}
+
+/// Run `run` once for each of `points`, sharing one listing of `location`
+/// and waiting `cooldown` between runs, then emit a summary of the runs.
+pub async fn download_sweep<F, Fut>(
+    location: &Path,
+    points: &[Point],
+    cooldown: Duration,
+    control: &RunControl,
+    mut run: F,
+) -> Result<(), Error>
+where
+    F: FnMut(usize, Option<usize>) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let sweeps_block_size = points.iter().any(|point| point.1 != points[0].1);
+    let sweeps_parallel = points.iter().any(|point| point.0 != points[0].0);
+    control.listing().reuse.share(Some(location));
+    let mut settings = Vec::new();
+    for (i, &(parallel_downloads, block_size)) in points.iter().enumerate() {
+        if control.is_shutdown() {
+            break;
+        }
+        if i > 0 && !cooldown.is_zero() {
+            tokio::time::sleep(cooldown).await;
+        }
+        control.reset_paused();
+        run(parallel_downloads, block_size).await;
+        let result = control
+            .take_last_result()
+            .ok_or("the benchmark did not report a result")?;
+        let result: serde_json::Value = serde_json::from_str(&result)?;
+        settings.push(serde_json::json!({
+            "parallel_downloads": parallel_downloads,
+            "block_size": result["block_size"],
+            "mbps": result["mbps"],
+            "p99_us": result["latency"]["p99_us"],
+        }));
+    }
+    control.listing().reuse.share(None);
+
+    let (parameter, label): (&str, fn(&serde_json::Value) -> String) =
+        match (sweeps_parallel, sweeps_block_size) {
+            (_, false) => ("parallel_downloads", |setting| {
+                setting["parallel_downloads"].to_string()
+            }),
+            (false, true) => ("block_size", |setting| setting["block_size"].to_string()),
+            (true, true) => ("parallel_downloads/block_size", |setting| {
+                format!(
+                    "{}/{}",
+                    setting["parallel_downloads"], setting["block_size"]
+                )
+            }),
+        };
+    let chart = Sweep {
+        parameter: parameter.to_string(),
+        points: settings
+            .iter()
+            .map(|setting| SweepPoint {
+                value: label(setting),
+                mbps: setting["mbps"].as_f64(),
+                p99_us: setting["p99_us"].as_f64(),
+            })
+            .collect(),
+    };
+    let best = chart.best().map(|i| {
+        serde_json::json!({
+            "parallel_downloads": settings[i]["parallel_downloads"],
+            "block_size": settings[i]["block_size"],
+        })
+    });
+    control.emit(serde_json::json!({
+        "mode": "download_sweep",
+        "cooldown_secs": cooldown.as_secs_f64(),
+        "settings": settings,
+        "best": best,
+        "interrupted": control.is_shutdown(),
+    }));
+    crate::sparkline::show(&chart, control.context().sparkline);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::counting::{CountingStore, Operation};
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::experiment::capture;
+    use crate::report::Checked;
+    use crate::retry::RetryPolicy;
+    use bytes::Bytes;
+    use object_store::{memory::InMemory, ObjectStore};
+    use std::sync::Arc;
+
+    #[test]
+    fn parses_lists_and_crosses_them() {
+        let parallel: Values<usize> = "1, 4".parse().unwrap();
+        let block_sizes: Values<usize> = "1024,2048".parse().unwrap();
+        assert!(parallel.is_sweep());
+        assert!(!"8".parse::<Values<usize>>().unwrap().is_sweep());
+        assert!("1,x".parse::<Values<usize>>().is_err());
+        assert_eq!(
+            points(&parallel, Some(&block_sizes)),
+            [
+                (1, Some(1024)),
+                (1, Some(2048)),
+                (4, Some(1024)),
+                (4, Some(2048))
+            ]
+        );
+        assert_eq!(points(&parallel, None), [(1, None), (4, None)]);
+    }
+
+    #[tokio::test]
+    async fn runs_each_setting_and_summarizes_them() {
+        let location = Path::from("sweep");
+        let counting = CountingStore::new(Arc::new(InMemory::new()));
+        let counts = counting.counts();
+        let object_store: Arc<dyn ObjectStore> = Arc::new(counting);
+        object_store
+            .put(&Path::from("sweep/a"), Bytes::from(vec![1; 1 << 16]))
+            .await
+            .unwrap();
+        let control = RunControl::new();
+        let run = |parallel_downloads, block_size| {
+            let object_store = object_store.clone();
+            let (location, control) = (location.clone(), control.clone());
+            async move {
+                let result = parallel_download_bench(
+                    object_store,
+                    location,
+                    DownloadOptions {
+                        parallel_downloads,
+                        block_size,
+                        ..DownloadOptions::default()
+                    },
+                    RetryPolicy::new(0, None),
+                    control.clone(),
+                )
+                .await
+                .and_then(Checked::into_result)
+                .unwrap();
+                // The driver reads the result each run emits, as the binary does.
+                control.emit(serde_json::to_value(result).unwrap());
+            }
+        };
+        let points = points(&"1,2,4".parse().unwrap(), Some(&Values::one(4096)));
+        let (outcome, results) = capture(download_sweep(
+            &location,
+            &points,
+            Duration::ZERO,
+            &control,
+            run,
+        ))
+        .await;
+        outcome.unwrap();
+        // Only the first run listed the objects.
+        assert_eq!(counts.totals().requests(Operation::List), 1);
+        assert_eq!(results.len(), 4);
+        for (result, parallel) in results.iter().zip([1, 2, 4]) {
+            let result: serde_json::Value = serde_json::from_str(result).unwrap();
+            assert_eq!(result["parallel_downloads"], parallel);
+            assert_eq!(result["num_requests"], 16);
+        }
+        let summary: serde_json::Value = serde_json::from_str(&results[3]).unwrap();
+        assert_eq!(summary["mode"], "download_sweep");
+        assert_eq!(summary["settings"].as_array().unwrap().len(), 3);
+        assert_eq!(summary["settings"][2]["block_size"], 4096);
+        assert!(summary["best"]["parallel_downloads"].is_u64());
+    }
+}
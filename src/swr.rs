@@ -0,0 +1,473 @@
+//! Stale-while-revalidate reads: serving a cached copy while checking it.
+//!
+//! A cache in front of a store may answer from its copy at once and check the
+//! copy is still current alongside. `swr` models that with `--num-keys`
+//! scratch objects. A first pass records each key's version: its ETag, or its
+//! modification time where the store reports no ETag. A `--mutate-fraction`
+//! of the keys is then overwritten. Each of `--accesses` accesses reads the
+//! first `--read-size` bytes of a key, as serving the stale copy would, and
+//! concurrently revalidates it: the same range read conditionally on the
+//! recorded version, with `if_none_match` for an ETag and `if_modified_since`
+//! otherwise. A `NotModified` answer means the copy was current; a body means
+//! the key changed and the cache served stale bytes.
+//!
+//! `--seed` picks both the mutated keys and the accesses, so a run repeats
+//! the same mismatches. Results report the latency of each path, the share of
+//! revalidations that found the key changed, and the requests and bytes
+//! revalidation added. As the mutated keys are known, `misclassified` counts
+//! revalidations that disagree with them: a store that ignores conditional
+//! reads, or a mutation within the one-second resolution some stores give
+//! `If-Modified-Since`.
+
+use std::collections::HashSet;
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, GetOptions, ObjectMeta, ObjectStore};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, RngCore, SeedableRng};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::report::{SwrResult, ValidatorCounts};
+use crate::retry::RetryPolicy;
+use crate::scratch::ScratchArea;
+use crate::stats::LatencySummary;
+
+/// Parameters for [`swr_bench`].
+#[derive(Debug, Clone)]
+pub struct SwrOptions {
+    /// Number of keys written and read
+    pub num_keys: usize,
+    /// Size of each key's object
+    pub object_size: usize,
+    /// Bytes read from the start of a key on each access
+    pub read_size: usize,
+    /// Number of accesses
+    pub accesses: usize,
+    /// Accesses in flight at once
+    pub parallel: usize,
+    /// Share of the keys overwritten after their versions are recorded
+    pub mutate_fraction: f64,
+    /// Seed for the object contents, the mutated keys and the accesses
+    pub seed: u64,
+}
+
+/// The version of a key recorded by the first pass.
+#[derive(Debug, Clone, PartialEq)]
+enum Validator {
+    ETag(String),
+    LastModified(DateTime<Utc>),
+}
+
+impl Validator {
+    fn of(meta: &ObjectMeta) -> Self {
+        match &meta.e_tag {
+            Some(e_tag) => Validator::ETag(e_tag.clone()),
+            None => Validator::LastModified(meta.last_modified),
+        }
+    }
+
+    /// Options reading `range` only if the key has changed since.
+    fn options(&self, range: Range<usize>) -> GetOptions {
+        let mut options = GetOptions {
+            range: Some(range),
+            ..Default::default()
+        };
+        match self {
+            Validator::ETag(e_tag) => options.if_none_match = Some(e_tag.clone()),
+            Validator::LastModified(at) => options.if_modified_since = Some(*at),
+        }
+        options
+    }
+}
+
+/// What revalidating a key found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Revalidation {
+    Current,
+    /// Changed, with the bytes of the fresh copy
+    Changed(usize),
+}
+
+/// One access's two requests, or `None` for each that failed.
+struct Access {
+    key: usize,
+    read: Option<Duration>,
+    revalidation: Option<(Duration, Revalidation)>,
+}
+
+/// The keys, their recorded versions and the keys changed since.
+struct Keys {
+    paths: Vec<Path>,
+    validators: Vec<Validator>,
+    mutated: HashSet<usize>,
+}
+
+/// Benchmarks stale reads revalidated alongside, on scratch objects under
+/// `location`, returning the result to report.
+pub async fn swr_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    options: SwrOptions,
+    retry: RetryPolicy,
+    control: RunControl,
+    keep_scratch: bool,
+) -> Result<SwrResult, Error> {
+    if options.num_keys == 0 {
+        return Err("swr needs at least one key".into());
+    }
+    if !(0.0..=1.0).contains(&options.mutate_fraction) {
+        return Err(format!(
+            "--mutate-fraction must be between 0 and 1, not {}",
+            options.mutate_fraction
+        )
+        .into());
+    }
+    let area = ScratchArea::new(object_store.clone(), &location, "swr", keep_scratch);
+    let (outcome, scratch) = area
+        .run(&control, &retry, async {
+            let mut rng = StdRng::seed_from_u64(options.seed);
+            let keys = prepare(&object_store, &area, &options, &retry, &mut rng).await?;
+            let picks = (0..options.accesses)
+                .map(|_| rng.gen_range(0..options.num_keys))
+                .collect::<Vec<_>>();
+            let accesses =
+                access_all(&object_store, &keys, &picks, &options, &retry, &control).await;
+            Ok::<_, object_store::Error>((keys, accesses))
+        })
+        .await;
+    let (keys, accesses) = outcome.ok_or("swr interrupted")??;
+
+    let mut read_latencies = Vec::new();
+    let mut revalidate_latencies = Vec::new();
+    let (mut read_errors, mut revalidate_errors) = (0, 0);
+    let (mut mismatches, mut misclassified, mut extra_bytes) = (0, 0, 0);
+    for access in &accesses {
+        match access.read {
+            Some(latency) => read_latencies.push(latency),
+            None => read_errors += 1,
+        }
+        let Some((latency, revalidation)) = access.revalidation else {
+            revalidate_errors += 1;
+            continue;
+        };
+        revalidate_latencies.push(latency);
+        let changed = match revalidation {
+            Revalidation::Current => false,
+            Revalidation::Changed(bytes) => {
+                mismatches += 1;
+                extra_bytes += bytes;
+                true
+            }
+        };
+        if changed != keys.mutated.contains(&access.key) {
+            misclassified += 1;
+        }
+    }
+    let etags = keys
+        .validators
+        .iter()
+        .filter(|validator| matches!(validator, Validator::ETag(_)))
+        .count();
+    let revalidations = revalidate_latencies.len();
+    Ok(SwrResult {
+        mode: "swr",
+        run_id: area.run_id().to_string(),
+        num_keys: options.num_keys,
+        object_size: options.object_size,
+        read_size: options.read_size,
+        parallel: options.parallel,
+        seed: options.seed,
+        mutate_fraction: options.mutate_fraction,
+        mutated_keys: keys.mutated.len(),
+        validators: ValidatorCounts {
+            etag: etags,
+            last_modified: keys.validators.len() - etags,
+        },
+        accesses: accesses.len(),
+        read_latency: LatencySummary::from_latencies(&mut read_latencies),
+        revalidate_latency: LatencySummary::from_latencies(&mut revalidate_latencies),
+        read_errors,
+        revalidate_errors,
+        mismatches,
+        mismatch_rate: mismatches as f64 / revalidations.max(1) as f64,
+        misclassified,
+        extra_requests: revalidations + revalidate_errors,
+        extra_bytes,
+        scratch: scratch.to_json(),
+        interrupted: control.is_shutdown(),
+        fields: retry.json_fields(),
+    })
+}
+
+/// Write the keys, record their versions, then overwrite the mutated ones.
+async fn prepare(
+    object_store: &Arc<dyn ObjectStore>,
+    area: &ScratchArea,
+    options: &SwrOptions,
+    retry: &RetryPolicy,
+    rng: &mut StdRng,
+) -> object_store::Result<Keys> {
+    let paths = (0..options.num_keys)
+        .map(|key| area.path(&format!("key-{:06}", key)))
+        .collect::<Vec<_>>();
+    let contents = random_contents(rng, options.num_keys, options.object_size);
+    put_all(object_store, &paths, contents, options.parallel, retry).await?;
+
+    let validators = futures::stream::iter(&paths)
+        .map(|path| async move {
+            retry
+                .run(|| object_store.head(path))
+                .await
+                .map(|meta| Validator::of(&meta))
+        })
+        .buffered(options.parallel.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    let mut order = (0..options.num_keys).collect::<Vec<_>>();
+    order.shuffle(rng);
+    order.truncate((options.mutate_fraction * options.num_keys as f64).round() as usize);
+    order.sort_unstable();
+    let contents = random_contents(rng, order.len(), options.object_size);
+    let mutated_paths = order
+        .iter()
+        .map(|key| paths[*key].clone())
+        .collect::<Vec<_>>();
+    put_all(
+        object_store,
+        &mutated_paths,
+        contents,
+        options.parallel,
+        retry,
+    )
+    .await?;
+
+    Ok(Keys {
+        paths,
+        validators,
+        mutated: order.into_iter().collect(),
+    })
+}
+
+fn random_contents(rng: &mut StdRng, count: usize, size: usize) -> Vec<Bytes> {
+    (0..count)
+        .map(|_| {
+            let mut data = vec![0; size];
+            rng.fill_bytes(&mut data);
+            Bytes::from(data)
+        })
+        .collect()
+}
+
+async fn put_all(
+    object_store: &Arc<dyn ObjectStore>,
+    paths: &[Path],
+    contents: Vec<Bytes>,
+    parallel: usize,
+    retry: &RetryPolicy,
+) -> object_store::Result<()> {
+    futures::stream::iter(paths.iter().zip(contents))
+        .map(|(path, data)| async move { retry.run(|| object_store.put(path, data.clone())).await })
+        .buffer_unordered(parallel.max(1))
+        .try_collect::<Vec<_>>()
+        .await?;
+    Ok(())
+}
+
+/// Run the access to each of `picks`, stopping early on shutdown.
+async fn access_all(
+    object_store: &Arc<dyn ObjectStore>,
+    keys: &Keys,
+    picks: &[usize],
+    options: &SwrOptions,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> Vec<Access> {
+    let range = 0..options.read_size.min(options.object_size);
+    futures::stream::iter(picks)
+        .map(|key| {
+            let range = range.clone();
+            async move {
+                let path = &keys.paths[*key];
+                let (read, revalidation) = tokio::join!(
+                    stale_read(object_store, path, range.clone(), retry, control),
+                    revalidate(
+                        object_store,
+                        path,
+                        &keys.validators[*key],
+                        range,
+                        retry,
+                        control
+                    ),
+                );
+                Some(Access {
+                    key: *key,
+                    read: read?,
+                    revalidation: revalidation?,
+                })
+            }
+        })
+        .buffer_unordered(options.parallel.max(1))
+        .filter_map(|access| async move { access })
+        .collect()
+        .await
+}
+
+/// The read served from the stale copy. `None` if the run is shutting down,
+/// `Some(None)` if the read failed.
+async fn stale_read(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &Path,
+    range: Range<usize>,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> Option<Option<Duration>> {
+    if !control.request_started().await {
+        return None;
+    }
+    control.record("get_range", path, Some(&range));
+    let start = Instant::now();
+    match retry
+        .run(|| object_store.get_range(path, range.clone()))
+        .await
+    {
+        Ok(bytes) => {
+            control.request_finished(bytes.len());
+            Some(Some(start.elapsed()))
+        }
+        Err(_) => {
+            control.request_failed();
+            Some(None)
+        }
+    }
+}
+
+/// The conditional read checking the copy against `validator`.
+///
+/// `NotModified` is mapped to [`Revalidation::Current`] inside the retried
+/// request, so it is neither retried nor counted as an error.
+async fn revalidate(
+    object_store: &Arc<dyn ObjectStore>,
+    path: &Path,
+    validator: &Validator,
+    range: Range<usize>,
+    retry: &RetryPolicy,
+    control: &RunControl,
+) -> Option<Option<(Duration, Revalidation)>> {
+    if !control.request_started().await {
+        return None;
+    }
+    control.record("get_opts", path, Some(&range));
+    let start = Instant::now();
+    let result = retry
+        .run(|| async {
+            match object_store
+                .get_opts(path, validator.options(range.clone()))
+                .await
+            {
+                Ok(result) => Ok(Revalidation::Changed(result.bytes().await?.len())),
+                Err(object_store::Error::NotModified { .. }) => Ok(Revalidation::Current),
+                Err(err) => Err(err),
+            }
+        })
+        .await;
+    match result {
+        Ok(revalidation) => {
+            control.request_finished(match revalidation {
+                Revalidation::Current => 0,
+                Revalidation::Changed(bytes) => bytes,
+            });
+            Some(Some((start.elapsed(), revalidation)))
+        }
+        Err(_) => {
+            control.request_failed();
+            Some(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    fn options(mutate_fraction: f64, seed: u64) -> SwrOptions {
+        SwrOptions {
+            num_keys: 20,
+            object_size: 4096,
+            read_size: 1024,
+            accesses: 200,
+            parallel: 4,
+            mutate_fraction,
+            seed,
+        }
+    }
+
+    async fn run(options: SwrOptions) -> serde_json::Value {
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let result = swr_bench(
+            store.clone(),
+            Path::from("base"),
+            options,
+            RetryPolicy::new(0, None),
+            RunControl::new(),
+            false,
+        )
+        .await
+        .unwrap();
+        // The scratch keys are cleaned up afterwards.
+        assert!(store.list(None).await.unwrap().next().await.is_none());
+        serde_json::to_value(result).unwrap()
+    }
+
+    #[tokio::test]
+    async fn mutated_keys_drive_the_mismatch_rate() {
+        let result = run(options(0.25, 11)).await;
+        assert_eq!(result["mode"], "swr");
+        assert_eq!(result["mutated_keys"], 5);
+        // The in-memory store reports no ETags, so modification times are
+        // compared.
+        assert_eq!(result["validators"]["last_modified"], 20);
+        assert_eq!(result["misclassified"], 0);
+        assert_eq!(result["extra_requests"], 200);
+        let mismatches = result["mismatches"].as_u64().unwrap();
+        assert!(mismatches > 0 && mismatches < 200);
+        // The in-memory store ignores the range of `get_opts`, so each
+        // changed key returns its whole object.
+        assert_eq!(result["extra_bytes"], mismatches * 4096);
+
+        // The same seed repeats the same mismatches.
+        assert_eq!(run(options(0.25, 11)).await["mismatches"], mismatches);
+        assert_eq!(run(options(0.0, 11)).await["mismatches"], 0);
+        assert_eq!(run(options(1.0, 11)).await["mismatch_rate"], 1.0);
+    }
+
+    #[test]
+    fn validators_prefer_etags() {
+        let meta = ObjectMeta {
+            location: Path::from("key"),
+            last_modified: Utc::now(),
+            size: 10,
+            e_tag: Some("\"abc\"".to_string()),
+        };
+        let options = Validator::of(&meta).options(0..5);
+        assert_eq!(options.if_none_match.as_deref(), Some("\"abc\""));
+        assert!(options.if_modified_since.is_none());
+        assert_eq!(options.range, Some(0..5));
+
+        let options = Validator::of(&ObjectMeta {
+            e_tag: None,
+            ..meta
+        })
+        .options(0..5);
+        assert!(options.if_none_match.is_none());
+        assert!(options.if_modified_since.is_some());
+    }
+}
@@ -0,0 +1,139 @@
+//! Reads of the last N bytes of each object, like a footer read.
+//!
+//! A reader that doesn't know an object's size can either `head` it and then
+//! request an absolute range, or ask for a suffix range directly and skip the
+//! `head`. This benchmark times both so the round trip the suffix form saves
+//! can be measured.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, ObjectStore};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::retry::RetryPolicy;
+use crate::stats::LatencySummary;
+
+/// Benchmarks reading the last `suffix_bytes` of every object under `location`
+///
+/// * `parallel_downloads`: maximum number of objects read concurrently
+pub async fn suffix_read_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    suffix_bytes: usize,
+    parallel_downloads: usize,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+
+    // Absolute range, preceded by the head a size-unaware reader needs.
+    let mut head_then_range = futures::stream::iter(objects.iter())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let control = control.clone();
+            let location = meta.location.clone();
+            async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                let start = Instant::now();
+                let result = async {
+                    control.record("head", &location, None);
+                    let size = retry.run(|| object_store.head(&location)).await?.size;
+                    let range = size.saturating_sub(suffix_bytes)..size;
+                    control.record("get_range", &location, Some(&range));
+                    retry
+                        .run(|| object_store.get_range(&location, range.clone()))
+                        .await
+                }
+                .await;
+                match result {
+                    Ok(bytes) => {
+                        control.request_finished(bytes.len());
+                        Ok(Some(start.elapsed()))
+                    }
+                    Err(err) => {
+                        control.request_failed();
+                        Err(err)
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel_downloads)
+        .try_filter_map(|latency| futures::future::ready(Ok(latency)))
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    // Suffix range with no head. Any store rejecting it marks the path unsupported.
+    let suffix = futures::stream::iter(objects.iter())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let object_store = object_store.clone();
+            let retry = retry.clone();
+            let location = meta.location.clone();
+            async move {
+                let start = Instant::now();
+                retry
+                    .run(|| get_suffix(object_store.as_ref(), &location, suffix_bytes))
+                    .await?;
+                Ok::<_, object_store::Error>(start.elapsed())
+            }
+        })
+        .buffer_unordered(parallel_downloads)
+        .try_collect::<Vec<Duration>>()
+        .await;
+    let suffix = match suffix {
+        Ok(mut latencies) => serde_json::json!({
+            "supported": true,
+            "latency": LatencySummary::from_latencies(&mut latencies).to_json(),
+        }),
+        Err(object_store::Error::NotSupported { source }) => serde_json::json!({
+            "supported": false,
+            "reason": source.to_string(),
+        }),
+        Err(err) => return Err(err.into()),
+    };
+
+    let mut result = fields(serde_json::json!({
+        "mode": "suffix",
+        "num_objects": objects.len(),
+        "suffix_bytes": suffix_bytes,
+        "parallel_downloads": parallel_downloads,
+        "head_then_range": {
+            "latency": LatencySummary::from_latencies(&mut head_then_range).to_json(),
+        },
+        "suffix": suffix,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+/// Request the last `suffix_bytes` of an object without knowing its size.
+///
+/// The object_store release this tool builds against can only express
+/// absolute ranges (suffix ranges arrived with `GetRange::Suffix` in 0.10), so
+/// this reports the path as unsupported rather than quietly issuing a `head`.
+async fn get_suffix(
+    _object_store: &dyn ObjectStore,
+    _location: &Path,
+    suffix_bytes: usize,
+) -> object_store::Result<Bytes> {
+    Err(object_store::Error::NotSupported {
+        source: format!(
+            "suffix range of {} bytes: this object_store version only supports absolute ranges",
+            suffix_bytes
+        )
+        .into(),
+    })
+}
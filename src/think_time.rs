@@ -0,0 +1,306 @@
+//! Think time between dependent requests, with `--think-time-ms`.
+//!
+//! A real reader decodes and computes between reads, so a store sees bursts
+//! of requests separated by idle gaps rather than a flat-out stream. With a
+//! think time, a slot of `download` or `columnar` waits after each block or
+//! group completes before it issues its next request. The wait is either
+//! fixed (`--think-time-ms 20`) or drawn from a normal distribution clamped at
+//! zero (`--think-time-ms normal:20,5`), seeded by `--think-time-seed` so runs
+//! repeat. Results then report under `pacing` the think time, the total time
+//! spent waiting, and the duty cycle: the fraction of the run during which at
+//! least one request was outstanding.
+//!
+//! `--compare-paced` runs the benchmark once without the think time and once
+//! with it, then emits a `paced_comparison` of the two throughputs, so a
+//! store that penalizes intermittent traffic, say by closing idle
+//! connections, shows up as more than the duty cycle explains.
+
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
+
+/// How long a slot waits after each completed block or group.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThinkTime {
+    Fixed { ms: f64 },
+    Normal { mean_ms: f64, std_dev_ms: f64 },
+}
+
+impl ThinkTime {
+    /// Parse `MS` or `normal:MEAN,STD`, all in milliseconds.
+    pub fn parse(arg: &str) -> Result<ThinkTime, String> {
+        let number = |field: &str| {
+            let ms = field
+                .trim()
+                .parse::<f64>()
+                .map_err(|err| format!("{:?}: {}", field, err))?;
+            if !ms.is_finite() || ms < 0.0 {
+                return Err(format!("{:?} is not a non-negative number", field));
+            }
+            Ok(ms)
+        };
+        match arg.strip_prefix("normal:") {
+            Some(params) => {
+                let (mean, std_dev) = params
+                    .split_once(',')
+                    .ok_or_else(|| format!("expected normal:MEAN,STD, got {:?}", arg))?;
+                Ok(ThinkTime::Normal {
+                    mean_ms: number(mean)?,
+                    std_dev_ms: number(std_dev)?,
+                })
+            }
+            None => Ok(ThinkTime::Fixed { ms: number(arg)? }),
+        }
+    }
+}
+
+impl fmt::Display for ThinkTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThinkTime::Fixed { ms } => write!(f, "{}", ms),
+            ThinkTime::Normal {
+                mean_ms,
+                std_dev_ms,
+            } => write!(f, "normal:{},{}", mean_ms, std_dev_ms),
+        }
+    }
+}
+
+/// A think time and the seed its waits are drawn with.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pacing {
+    pub think_time: ThinkTime,
+    pub seed: u64,
+}
+
+/// Draws and waits out think times for one run.
+pub struct Pacer {
+    pacing: Pacing,
+    rng: Mutex<StdRng>,
+    waited_us: AtomicU64,
+}
+
+impl Pacer {
+    pub fn new(pacing: Pacing) -> Self {
+        Pacer {
+            pacing,
+            rng: Mutex::new(StdRng::seed_from_u64(pacing.seed)),
+            waited_us: AtomicU64::new(0),
+        }
+    }
+
+    /// The next think time.
+    pub fn sample(&self) -> Duration {
+        let ms = match self.pacing.think_time {
+            ThinkTime::Fixed { ms } => ms,
+            ThinkTime::Normal {
+                mean_ms,
+                std_dev_ms,
+            } => {
+                // Box-Muller, clamped at zero.
+                let mut rng = self.rng.lock().unwrap();
+                let (u1, u2) = (1.0 - rng.gen::<f64>(), rng.gen::<f64>());
+                let z = (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos();
+                (mean_ms + std_dev_ms * z).max(0.0)
+            }
+        };
+        Duration::from_secs_f64(ms / 1000.0)
+    }
+
+    /// Wait out the next think time.
+    pub async fn think(&self) {
+        let wait = self.sample();
+        self.waited_us
+            .fetch_add(wait.as_micros() as u64, Ordering::SeqCst);
+        tokio::time::sleep(wait).await;
+    }
+
+    /// The `pacing` field of a run whose requests were outstanding over
+    /// `requests`, as `(issued, completed)` since its start, out of `elapsed`.
+    pub fn json_field(&self, requests: Vec<(Duration, Duration)>, elapsed: Duration) -> Fields {
+        fields(serde_json::json!({
+            "pacing": {
+                "think_time_ms": self.pacing.think_time.to_string(),
+                "seed": self.pacing.seed,
+                "waited_us": self.waited_us.load(Ordering::SeqCst),
+                "duty_cycle": duty_cycle(requests, elapsed),
+            },
+        }))
+    }
+}
+
+/// The fraction of `elapsed` covered by at least one of `requests`.
+pub fn duty_cycle(mut requests: Vec<(Duration, Duration)>, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+    requests.sort();
+    let mut busy = Duration::ZERO;
+    let mut current: Option<(Duration, Duration)> = None;
+    for (issued, completed) in requests {
+        current = match current {
+            Some((start, end)) if issued <= end => Some((start, end.max(completed))),
+            Some((start, end)) => {
+                busy += end - start;
+                Some((issued, completed))
+            }
+            None => Some((issued, completed)),
+        };
+    }
+    if let Some((start, end)) = current {
+        busy += end - start;
+    }
+    (busy.as_secs_f64() / elapsed.as_secs_f64()).min(1.0)
+}
+
+/// Run `run` without pacing and then with `pacing`, then emit a comparison
+/// of the two runs' throughput.
+pub async fn compare<F, Fut, E>(
+    pacing: Pacing,
+    control: &RunControl,
+    mut run: F,
+) -> Result<(), Error>
+where
+    F: FnMut(Option<Pacing>) -> Fut,
+    Fut: Future<Output = Result<(), E>>,
+    E: Into<Error>,
+{
+    let mut mbps = Vec::new();
+    let mut duty_cycle = None;
+    for pacing in [None, Some(pacing)] {
+        run(pacing).await.map_err(Into::into)?;
+        let result = control
+            .take_last_result()
+            .ok_or("the benchmark did not report a result")?;
+        let result: serde_json::Value = serde_json::from_str(&result)?;
+        mbps.push(result["mbps"].as_f64());
+        duty_cycle = result["pacing"]["duty_cycle"].as_f64();
+    }
+    let ratio = match (mbps[0], mbps[1]) {
+        (Some(unpaced), Some(paced)) if unpaced > 0.0 => Some(paced / unpaced),
+        _ => None,
+    };
+    control.emit(serde_json::json!({
+        "mode": "paced_comparison",
+        "think_time_ms": pacing.think_time.to_string(),
+        "seed": pacing.seed,
+        "unpaced_mbps": mbps[0],
+        "paced_mbps": mbps[1],
+        "paced_ratio": ratio,
+        "duty_cycle": duty_cycle,
+    }));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::download::{parallel_download_bench, DownloadOptions};
+    use crate::experiment::capture;
+    use crate::report::Checked;
+    use crate::retry::RetryPolicy;
+    use bytes::Bytes;
+    use futures::FutureExt;
+    use object_store::{memory::InMemory, path::Path, ObjectStore};
+    use std::sync::Arc;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn parses_fixed_and_normal_think_times() {
+        assert_eq!(ThinkTime::parse("20"), Ok(ThinkTime::Fixed { ms: 20.0 }));
+        let normal = ThinkTime::parse("normal:20, 5").unwrap();
+        assert_eq!(
+            normal,
+            ThinkTime::Normal {
+                mean_ms: 20.0,
+                std_dev_ms: 5.0
+            }
+        );
+        assert_eq!(normal.to_string(), "normal:20,5");
+        assert!(ThinkTime::parse("-1").is_err());
+        assert!(ThinkTime::parse("normal:20").is_err());
+        assert!(ThinkTime::parse("uniform:1,2").is_err());
+    }
+
+    #[test]
+    fn normal_think_times_repeat_with_the_seed() {
+        let pacing = Pacing {
+            think_time: ThinkTime::parse("normal:10,10").unwrap(),
+            seed: 7,
+        };
+        let draws = |pacer: Pacer| (0..100).map(|_| pacer.sample()).collect::<Vec<_>>();
+        let first = draws(Pacer::new(pacing));
+        assert_eq!(first, draws(Pacer::new(pacing)));
+        assert_ne!(first, draws(Pacer::new(Pacing { seed: 8, ..pacing })));
+        let mean = first.iter().sum::<Duration>().as_secs_f64() / 100.0;
+        assert!((0.007..0.015).contains(&mean), "{}", mean);
+    }
+
+    #[test]
+    fn duty_cycle_counts_overlapping_requests_once() {
+        let requests = vec![(ms(0), ms(20)), (ms(10), ms(30)), (ms(60), ms(80))];
+        assert_eq!(duty_cycle(requests, ms(100)), 0.5);
+        assert_eq!(duty_cycle(Vec::new(), ms(100)), 0.0);
+        assert_eq!(duty_cycle(Vec::new(), Duration::ZERO), 0.0);
+    }
+
+    #[tokio::test]
+    async fn compares_paced_and_unpaced_downloads() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        object_store
+            .put(&Path::from("data/a"), Bytes::from(vec![7; 1 << 16]))
+            .await
+            .unwrap();
+        let pacing = Pacing {
+            think_time: ThinkTime::Fixed { ms: 5.0 },
+            seed: 0,
+        };
+        let control = RunControl::new();
+        let run = |pacing| {
+            parallel_download_bench(
+                object_store.clone(),
+                Path::from("data"),
+                DownloadOptions {
+                    parallel_downloads: 2,
+                    block_size: Some(1 << 12),
+                    pacing,
+                    ..DownloadOptions::default()
+                },
+                RetryPolicy::new(0, None),
+                control.clone(),
+            )
+            .map(|result| {
+                // The comparison reads the result each run emits, as the
+                // binary does.
+                result
+                    .and_then(Checked::into_result)
+                    .map(|result| control.emit(serde_json::to_value(result).unwrap()))
+            })
+        };
+        let (outcome, results) = capture(compare(pacing, &control, run)).await;
+        outcome.unwrap();
+        assert_eq!(results.len(), 3);
+        let unpaced: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert!(unpaced.get("pacing").is_none());
+        let paced: serde_json::Value = serde_json::from_str(&results[1]).unwrap();
+        // Each of the 16 blocks is followed by a think time.
+        assert_eq!(paced["pacing"]["waited_us"], 16 * 5000);
+        assert!(paced["pacing"]["duty_cycle"].as_f64().unwrap() < 1.0);
+        let comparison: serde_json::Value = serde_json::from_str(&results[2]).unwrap();
+        assert_eq!(comparison["mode"], "paced_comparison");
+        assert_eq!(comparison["paced_mbps"], paced["mbps"]);
+        assert!(comparison["paced_ratio"].as_f64().unwrap() > 0.0);
+    }
+}
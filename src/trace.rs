@@ -0,0 +1,368 @@
+//! Logging requests as they run, for `-v`.
+//!
+//! `-v` logs to stderr, so the results on stdout are unchanged. It and
+//! `--traced` wrap the store in a [`TracingStore`], which opens a `request`
+//! span for every `get_range`, `get_ranges`, `get_opts`, `head` and `list`,
+//! recording the location, byte range, bytes or objects returned and
+//! duration. Multipart uploads open one for each part they write. With `-v` only the events of
+//! whole runs and failed requests are printed, save for `NotFound`s; `-vv`
+//! also prints each request as it finishes, so stragglers show up while the
+//! run is still going.
+//! `RUST_LOG` replaces the default filter with `target=level` directives, for
+//! instance `RUST_LOG=object_store_bench=debug,object_store=debug`.
+//!
+//! Without `-v` no subscriber logs anything.
+
+use std::any::Any;
+use std::fmt::{Debug, Display, Formatter};
+use std::future::Future;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Instant;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use object_store::path::Path;
+use object_store::{
+    GetOptions, GetResult, ListResult, MultipartId, ObjectMeta, ObjectStore, Result,
+};
+use tokio::io::AsyncWrite;
+use tracing::field::{display, Empty};
+use tracing::{Instrument, Level, Span};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::missing::MaybeNotFound;
+
+/// The filter for `verbose` uses of `-v`: `RUST_LOG` if it is set and
+/// parses, otherwise this crate's events at info, or debug from `-vv`.
+fn filter(verbose: u8) -> Targets {
+    if let Ok(directives) = std::env::var("RUST_LOG") {
+        match directives.parse() {
+            Ok(targets) => return targets,
+            Err(err) => eprintln!("warning: ignoring RUST_LOG={:?}: {}", directives, err),
+        }
+    }
+    let level = if verbose >= 2 {
+        Level::DEBUG
+    } else {
+        Level::INFO
+    };
+    Targets::new().with_target(env!("CARGO_CRATE_NAME"), level)
+}
+
+/// A layer logging to stderr for `verbose` uses of `-v`, or `None` without it.
+pub fn layer<S>(verbose: u8) -> Option<impl Layer<S>>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+{
+    let ansi = std::io::stderr().is_terminal();
+    (verbose > 0).then(|| layer_to(std::io::stderr, ansi, filter(verbose)))
+}
+
+fn layer_to<S, W>(writer: W, ansi: bool, filter: Targets) -> impl Layer<S>
+where
+    S: tracing::Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi)
+        .with_filter(filter)
+}
+
+/// A span for one request of `op` to `location`.
+pub fn request_span(op: &'static str, location: &Path, range: Option<&Range<usize>>) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        op,
+        location = %location,
+        range = Empty,
+        bytes = Empty,
+        objects = Empty,
+        duration_us = Empty,
+    );
+    if let Some(range) = range {
+        span.record(
+            "range",
+            display(format_args!("{}..{}", range.start, range.end)),
+        );
+    }
+    span
+}
+
+/// A span for writing the part at `range` of a multipart upload to `location`.
+pub fn part_span(location: &Path, range: Range<usize>) -> Span {
+    request_span("put_part", location, Some(&range))
+}
+
+/// Run `request` in `span`, recording its duration and the bytes `bytes`
+/// says it returned, then log it.
+pub async fn traced<T, E: Display + 'static>(
+    span: Span,
+    request: impl Future<Output = std::result::Result<T, E>>,
+    bytes: impl FnOnce(&T) -> Option<usize>,
+) -> std::result::Result<T, E> {
+    let start = Instant::now();
+    let result = request.instrument(span.clone()).await;
+    match &result {
+        Ok(value) => {
+            if let Some(bytes) = bytes(value) {
+                span.record("bytes", bytes);
+            }
+            finished(&span, start);
+        }
+        Err(err) => failed(&span, start, err),
+    }
+    result
+}
+
+fn finished(span: &Span, start: Instant) {
+    span.record("duration_us", start.elapsed().as_micros() as u64);
+    tracing::debug!(parent: span, "finished");
+}
+
+/// Log a failed request. A `NotFound` is often expected, as when checking
+/// whether the location is a single object, so it is logged only from `-vv`.
+fn failed<E: Display + 'static>(span: &Span, start: Instant, err: &E) {
+    span.record("duration_us", start.elapsed().as_micros() as u64);
+    let not_found = (err as &dyn Any)
+        .downcast_ref::<object_store::Error>()
+        .is_some_and(MaybeNotFound::is_not_found);
+    if not_found {
+        tracing::debug!(parent: span, error = %err, "failed");
+    } else {
+        tracing::info!(parent: span, error = %err, "failed");
+    }
+}
+
+/// An [`ObjectStore`] that runs each read and listing of `inner` in a
+/// [`request_span`].
+pub struct TracingStore {
+    inner: Arc<dyn ObjectStore>,
+}
+
+impl TracingStore {
+    pub fn new(inner: Arc<dyn ObjectStore>) -> Self {
+        Self { inner }
+    }
+}
+
+/// Trace a listing until its stream ends, recording the objects it returned.
+fn traced_list(
+    span: Span,
+    start: Instant,
+    stream: BoxStream<'_, Result<ObjectMeta>>,
+) -> BoxStream<'_, Result<ObjectMeta>> {
+    let objects = Arc::new(AtomicUsize::new(0));
+    let counted = objects.clone();
+    let mut pending = Some(span);
+    stream
+        .inspect(move |_| {
+            counted.fetch_add(1, Ordering::Relaxed);
+        })
+        .chain(futures::stream::poll_fn(move |_| {
+            if let Some(span) = pending.take() {
+                span.record("objects", objects.load(Ordering::Relaxed));
+                finished(&span, start);
+            }
+            Poll::Ready(None)
+        }))
+        .boxed()
+}
+
+impl Display for TracingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TracingStore({})", self.inner)
+    }
+}
+
+impl Debug for TracingStore {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TracingStore")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ObjectStore for TracingStore {
+    async fn put(&self, location: &Path, bytes: Bytes) -> Result<()> {
+        self.inner.put(location, bytes).await
+    }
+
+    async fn put_multipart(
+        &self,
+        location: &Path,
+    ) -> Result<(MultipartId, Box<dyn AsyncWrite + Unpin + Send>)> {
+        self.inner.put_multipart(location).await
+    }
+
+    async fn abort_multipart(&self, location: &Path, multipart_id: &MultipartId) -> Result<()> {
+        self.inner.abort_multipart(location, multipart_id).await
+    }
+
+    async fn get_opts(&self, location: &Path, options: GetOptions) -> Result<GetResult> {
+        // Only the time to the response is traced, not reading its body.
+        let span = request_span("get_opts", location, options.range.as_ref());
+        traced(span, self.inner.get_opts(location, options), |_| None).await
+    }
+
+    async fn get_range(&self, location: &Path, range: Range<usize>) -> Result<Bytes> {
+        let span = request_span("get_range", location, Some(&range));
+        traced(span, self.inner.get_range(location, range), |bytes| {
+            Some(bytes.len())
+        })
+        .await
+    }
+
+    async fn get_ranges(&self, location: &Path, ranges: &[Range<usize>]) -> Result<Vec<Bytes>> {
+        let covering = ranges.iter().map(|range| range.start).min().unwrap_or(0)
+            ..ranges.iter().map(|range| range.end).max().unwrap_or(0);
+        let span = request_span("get_ranges", location, Some(&covering));
+        traced(span, self.inner.get_ranges(location, ranges), |parts| {
+            Some(parts.iter().map(Bytes::len).sum())
+        })
+        .await
+    }
+
+    async fn head(&self, location: &Path) -> Result<ObjectMeta> {
+        let span = request_span("head", location, None);
+        traced(span, self.inner.head(location), |_| None).await
+    }
+
+    async fn delete(&self, location: &Path) -> Result<()> {
+        self.inner.delete(location).await
+    }
+
+    // async_trait names the `&self` lifetime, which trips this lint on `'_`.
+    #[allow(mismatched_lifetime_syntaxes)]
+    async fn list(&self, prefix: Option<&Path>) -> Result<BoxStream<'_, Result<ObjectMeta>>> {
+        let span = request_span("list", prefix.unwrap_or(&Path::default()), None);
+        let start = Instant::now();
+        match self.inner.list(prefix).instrument(span.clone()).await {
+            Ok(stream) => Ok(traced_list(span, start, stream)),
+            Err(err) => {
+                failed(&span, start, &err);
+                Err(err)
+            }
+        }
+    }
+
+    async fn list_with_delimiter(&self, prefix: Option<&Path>) -> Result<ListResult> {
+        let span = request_span(
+            "list_with_delimiter",
+            prefix.unwrap_or(&Path::default()),
+            None,
+        );
+        let start = Instant::now();
+        let result = self
+            .inner
+            .list_with_delimiter(prefix)
+            .instrument(span.clone())
+            .await;
+        match &result {
+            Ok(listing) => {
+                span.record("objects", listing.objects.len());
+                finished(&span, start);
+            }
+            Err(err) => failed(&span, start, err),
+        }
+        result
+    }
+
+    async fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn copy_if_not_exists(&self, from: &Path, to: &Path) -> Result<()> {
+        self.inner.copy_if_not_exists(from, to).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+    use object_store::memory::InMemory;
+    use std::sync::Mutex;
+    use tracing_subscriber::prelude::*;
+
+    /// Log lines written by a layer, shared with the test.
+    #[derive(Clone, Default)]
+    struct Lines(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'w> MakeWriter<'w> for Lines {
+        type Writer = Lines;
+
+        fn make_writer(&'w self) -> Lines {
+            self.clone()
+        }
+    }
+
+    /// What a store logs for one ranged read and one listing at `level`.
+    async fn log_requests(level: Level) -> String {
+        let lines = Lines::default();
+        let filter = Targets::new().with_target(env!("CARGO_CRATE_NAME"), level);
+        let subscriber =
+            tracing_subscriber::registry().with(layer_to(lines.clone(), false, filter).boxed());
+        let _default = tracing::subscriber::set_default(subscriber);
+
+        let inner: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let location = Path::from("data/a");
+        inner
+            .put(&location, Bytes::from_static(b"0123456789"))
+            .await
+            .unwrap();
+        let store = TracingStore::new(inner);
+        store.get_range(&location, 2..6).await.unwrap();
+        let listed = store
+            .list(Some(&Path::from("data")))
+            .await
+            .unwrap()
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        assert_eq!(listed.len(), 1);
+        assert!(store.get_range(&Path::from("data/b"), 0..1).await.is_err());
+        assert!(store.get_range(&location, 5..20).await.is_err());
+        let logged = lines.0.lock().unwrap().clone();
+        String::from_utf8(logged).unwrap()
+    }
+
+    #[tokio::test]
+    async fn requests_are_logged_as_they_finish() {
+        let logged = log_requests(Level::DEBUG).await;
+        let lines = logged.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 4, "{}", logged);
+        assert!(lines[0].contains("op=\"get_range\" location=data/a range=2..6 bytes=4"));
+        assert!(lines[0].contains("duration_us="));
+        assert!(lines[0].ends_with("finished"));
+        assert!(lines[1].contains("op=\"list\" location=data objects=1"));
+        assert!(lines[2].contains("location=data/b") && lines[2].contains("failed"));
+        assert!(lines[3].contains("range=5..20") && lines[3].contains("failed"));
+
+        // Without -vv only the failure other than a missing object is logged.
+        let logged = log_requests(Level::INFO).await;
+        assert_eq!(logged.lines().count(), 1, "{}", logged);
+        assert!(logged.contains("range=5..20"));
+    }
+}
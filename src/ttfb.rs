@@ -0,0 +1,235 @@
+//! Time to first byte, with `ttfb`.
+//!
+//! An interactive reader waits on the first bytes of a response more than on
+//! the transfer as a whole, and `download` only sees whole blocks. `ttfb`
+//! issues one streaming `get` per object under the location, `--parallel` at
+//! a time, and times both the first non-empty chunk of the body and the end of
+//! the body. With `--range-size N` each request reads only the first N bytes,
+//! through `get_opts` with a range. Results give every object's `ttfb_us` and
+//! `elapsed_us`, with percentiles of each across objects. Only the request
+//! that returned the response is retried; a body that fails part way fails the
+//! run.
+
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{StreamExt, TryStreamExt};
+use object_store::{path::Path, GetOptions, ObjectMeta, ObjectStore};
+
+use crate::control::RunControl;
+use crate::error::Error;
+use crate::experiment::fields;
+use crate::inspect_location;
+use crate::retry::RetryPolicy;
+use crate::stats::{active_us, mbps, LatencySummary};
+
+/// When one object's first and last bytes arrived, from issuing its request.
+#[derive(Debug, Clone)]
+struct ObjectTiming {
+    location: Path,
+    bytes: usize,
+    ttfb: Duration,
+    elapsed: Duration,
+}
+
+/// Fetch `range` of `meta`, or all of it, timing the first chunk and the end
+/// of the body.
+async fn time_object(
+    object_store: &dyn ObjectStore,
+    meta: &ObjectMeta,
+    range: Option<Range<usize>>,
+    retry: &RetryPolicy,
+) -> object_store::Result<ObjectTiming> {
+    let start = Instant::now();
+    let result = retry
+        .run(|| {
+            let options = GetOptions {
+                range: range.clone(),
+                ..Default::default()
+            };
+            object_store.get_opts(&meta.location, options)
+        })
+        .await?;
+    // The local and in-memory stores ignore `GetOptions::range` in this
+    // version of object_store, so stop once the range has been drained.
+    let expected = range.map_or(meta.size, |range| range.len());
+    let mut stream = result.into_stream();
+    let mut ttfb = None;
+    let mut bytes = 0;
+    while bytes < expected {
+        let Some(chunk) = stream.next().await else {
+            break;
+        };
+        let chunk = chunk?;
+        if !chunk.is_empty() {
+            ttfb.get_or_insert_with(|| start.elapsed());
+        }
+        bytes += chunk.len().min(expected - bytes);
+    }
+    let elapsed = start.elapsed();
+    Ok(ObjectTiming {
+        location: meta.location.clone(),
+        bytes,
+        ttfb: ttfb.unwrap_or(elapsed),
+        elapsed,
+    })
+}
+
+/// Times the first byte and the whole body of every object under `location`
+///
+/// * `parallel`: maximum number of objects fetched concurrently
+/// * `range_size`: when set, read only this many bytes from each object's
+///   start
+pub async fn ttfb_bench(
+    object_store: Arc<dyn ObjectStore>,
+    location: Path,
+    parallel: usize,
+    range_size: Option<usize>,
+    retry: RetryPolicy,
+    control: RunControl,
+) -> Result<(), Error> {
+    if range_size == Some(0) {
+        return Err("--range-size must be at least one byte".into());
+    }
+    let objects =
+        inspect_location(object_store.as_ref(), &location, control.listing(), &retry).await?;
+    // Zero-byte markers such as `_SUCCESS` have no first byte.
+    let objects = objects
+        .into_iter()
+        .filter(|meta| meta.size > 0)
+        .collect::<Vec<_>>();
+    if objects.is_empty() {
+        return Err(format!("every object under {} is empty", location).into());
+    }
+    let range = |meta: &ObjectMeta| range_size.map(|size| 0..size.min(meta.size));
+    control.set_bytes_total(
+        objects
+            .iter()
+            .map(|meta| range(meta).map_or(meta.size, |range| range.len()) as u64)
+            .sum(),
+    );
+
+    let start = Instant::now();
+    let mut timings = futures::stream::iter(objects.iter())
+        .take_while(|_| futures::future::ready(!control.is_shutdown()))
+        .map(|meta| {
+            let (object_store, retry, control) = (&object_store, &retry, &control);
+            let range = range(meta);
+            async move {
+                if !control.request_started().await {
+                    return Ok(None);
+                }
+                match &range {
+                    Some(range) => control.record("get_opts", &meta.location, Some(range)),
+                    None => control.record("get", &meta.location, None),
+                }
+                match time_object(object_store.as_ref(), meta, range, retry).await {
+                    Ok(timing) => {
+                        control.request_finished(timing.bytes);
+                        Ok(Some(timing))
+                    }
+                    Err(err) => {
+                        control.request_failed();
+                        Err(err)
+                    }
+                }
+            }
+        })
+        .buffer_unordered(parallel)
+        .try_filter_map(|timing| futures::future::ready(Ok(timing)))
+        .try_collect::<Vec<_>>()
+        .await?;
+    let elapsed_us = start.elapsed().as_micros();
+    let paused_us = control.paused().as_micros();
+
+    timings.sort_by(|a, b| a.location.cmp(&b.location));
+    let bytes: u64 = timings.iter().map(|timing| timing.bytes as u64).sum();
+    let mut ttfbs = timings.iter().map(|timing| timing.ttfb).collect::<Vec<_>>();
+    let mut elapsed = timings
+        .iter()
+        .map(|timing| timing.elapsed)
+        .collect::<Vec<_>>();
+    let per_object = timings
+        .iter()
+        .map(|timing| {
+            serde_json::json!({
+                "path": timing.location.to_string(),
+                "bytes": timing.bytes,
+                "ttfb_us": timing.ttfb.as_micros() as u64,
+                "elapsed_us": timing.elapsed.as_micros() as u64,
+            })
+        })
+        .collect::<Vec<_>>();
+    let mut result = fields(serde_json::json!({
+        "mode": "ttfb",
+        "num_objects": objects.len(),
+        "num_requests": timings.len(),
+        "parallel": parallel,
+        "range_size": range_size,
+        "bytes": bytes,
+        "elapsed_us": elapsed_us as u64,
+        "paused_us": paused_us as u64,
+        "mbps": mbps(bytes, active_us(elapsed_us, paused_us)),
+        "ttfb": LatencySummary::from_latencies(&mut ttfbs).to_json(),
+        "full": LatencySummary::from_latencies(&mut elapsed).to_json(),
+        "objects": per_object,
+        "interrupted": control.is_shutdown(),
+    }));
+    result.extend(retry.json_fields());
+    control.emit(result.into());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::experiment::capture;
+    use bytes::Bytes;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn times_the_first_byte_and_the_whole_body() {
+        let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        for (name, size) in [("a", 1000), ("b", 3000), ("empty", 0)] {
+            object_store
+                .put(
+                    &Path::from(format!("data/{}", name)),
+                    Bytes::from(vec![1; size]),
+                )
+                .await
+                .unwrap();
+        }
+        let run = |range_size| {
+            capture(ttfb_bench(
+                object_store.clone(),
+                Path::from("data"),
+                2,
+                range_size,
+                RetryPolicy::new(0, None),
+                RunControl::new(),
+            ))
+        };
+
+        let (outcome, results) = run(None).await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["num_objects"], 2);
+        assert_eq!(result["bytes"], 4000);
+        assert_eq!(result["ttfb"]["count"], 2);
+        assert_eq!(result["full"]["count"], 2);
+        let objects = result["objects"].as_array().unwrap();
+        assert_eq!(objects[1]["path"], "data/b");
+        assert_eq!(objects[1]["bytes"], 3000);
+        for object in objects {
+            assert!(object["ttfb_us"].as_u64().unwrap() <= object["elapsed_us"].as_u64().unwrap());
+        }
+
+        // A range reads only the start of each object.
+        let (outcome, results) = run(Some(2000)).await;
+        outcome.unwrap();
+        let result: serde_json::Value = serde_json::from_str(&results[0]).unwrap();
+        assert_eq!(result["range_size"], 2000);
+        assert_eq!(result["bytes"], 3000);
+    }
+}
@@ -0,0 +1,471 @@
+//! Generating and uploading test data.
+//!
+//! Every completed `write_all` is recorded with the bytes it handed to the
+//! store, so the upload result can report throughput per window and flag the
+//! windows that fell well below the rest.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use object_store::{path::Path, ObjectStore};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::io::AsyncWriteExt;
+
+use crate::control::RunControl;
+use crate::digest::DigestConfig;
+use crate::error::Error;
+use crate::experiment::{fields, Fields};
+use crate::manifest::UploadManifest;
+use crate::naming::{check_key, object_name, validate_run_id};
+use crate::pattern::Contents;
+use crate::retry::RetryPolicy;
+use crate::stats::{mbps, throughput_windows, TimedSample};
+use crate::store_defaults::PART_SIZE;
+use crate::trace;
+
+/// Bytes acknowledged by the store over an upload, one sample per completed
+/// write.
+#[derive(Debug)]
+pub struct UploadSamples {
+    start: Instant,
+    samples: Mutex<Vec<TimedSample>>,
+    /// Counts each write too, for progress reporting
+    control: Option<RunControl>,
+}
+
+impl UploadSamples {
+    /// Start timing an upload now.
+    pub fn start() -> Self {
+        Self {
+            start: Instant::now(),
+            samples: Mutex::new(Vec::new()),
+            control: None,
+        }
+    }
+
+    /// Also count each write in `control`, out of `total` bytes, so progress
+    /// can be reported while the upload goes on.
+    pub fn reporting_to(mut self, control: &RunControl, total: usize) -> Self {
+        control.set_bytes_total(total as u64);
+        self.control = Some(control.clone());
+        self
+    }
+
+    fn acknowledged(&self, issued: Instant, bytes: usize) {
+        let now = Instant::now();
+        self.samples.lock().unwrap().push(TimedSample {
+            completed: now - self.start,
+            latency: now - issued,
+            bytes,
+            error: false,
+        });
+        if let Some(control) = &self.control {
+            control.transferred(bytes);
+        }
+    }
+
+    /// The upload's overall and per-window throughput, as JSON fields.
+    ///
+    /// * `window`: length of the intervals throughput is reported over
+    /// * `slow_fraction`: windows below this fraction of the median window's
+    ///   throughput are flagged as slow
+    pub fn json_fields(&self, window: Duration, slow_fraction: f64) -> Fields {
+        let elapsed = self.start.elapsed();
+        let samples = self.samples.lock().unwrap();
+        let bytes = samples.iter().map(|s| s.bytes).sum::<usize>();
+        let mut result = fields(serde_json::json!({
+            "num_writes": samples.len(),
+            "bytes": bytes,
+            "elapsed_us": elapsed.as_micros() as u64,
+            "mbps": bytes as f64 / 1024.0 / 1024.0 / elapsed.as_secs_f64(),
+        }));
+        result.extend(throughput_windows(&samples, window, elapsed, slow_fraction));
+        result
+    }
+}
+
+/// Upload a test object of the given size
+///
+/// This will upload in batches of `part_size` bytes, allowing for objects
+/// larger than memory.
+///
+/// The data generated is given by `contents`, see [`crate::pattern`]. A
+/// failed upload is restarted from the beginning according to the retry
+/// policy; seeded or patterned contents are the same on every attempt.
+///
+/// With `digest`, the SHA-256 of the uploaded data is computed as it is
+/// generated and recorded in the audit file. Each write is recorded in
+/// `samples`, including those of attempts that were later restarted.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_test_data(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    size: usize,
+    part_size: usize,
+    contents: Contents,
+    retry: &RetryPolicy,
+    digest: Option<&DigestConfig>,
+    samples: &UploadSamples,
+) -> Result<(), Error> {
+    let sha256 = retry
+        .run(|| {
+            write_test_object(
+                object_store.as_ref(),
+                location,
+                size,
+                part_size,
+                contents,
+                digest,
+                samples,
+            )
+        })
+        .await?;
+    if let (Some(digest), Some(sha256)) = (digest, sha256) {
+        digest.record(location, &sha256, size)?;
+    }
+    Ok(())
+}
+
+/// Writes one test object, returning its digest if one was requested.
+async fn write_test_object(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    size: usize,
+    part_size: usize,
+    contents: Contents,
+    digest: Option<&DigestConfig>,
+    samples: &UploadSamples,
+) -> object_store::Result<Option<String>> {
+    let (_id, mut writer) = object_store.put_multipart(location).await?;
+    let mut hasher = digest.map(DigestConfig::hasher);
+
+    // Write one part at a time
+    let mut written = 0;
+    let mut filler = contents.filler();
+    let mut buffer = vec![0; part_size.min(size)];
+    while written < size {
+        let to_write = std::cmp::min(size - written, buffer.len());
+        filler.fill(written as u64, &mut buffer[..to_write]);
+        if let Some(hasher) = &mut hasher {
+            hasher.update(&buffer[0..to_write]).await;
+        }
+        let issued = Instant::now();
+        trace::traced(
+            trace::part_span(location, written..written + to_write),
+            writer.write_all(&buffer[0..to_write]),
+            |_| Some(to_write),
+        )
+        .await
+        .map_err(multipart_error)?;
+        samples.acknowledged(issued, to_write);
+        written += to_write;
+    }
+    writer.flush().await.map_err(multipart_error)?;
+    writer.shutdown().await.map_err(multipart_error)?;
+
+    Ok(match hasher {
+        Some(hasher) => Some(hasher.finish().await),
+        None => None,
+    })
+}
+
+/// The timing of one `upload-data --bench` upload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadBench {
+    pub size: usize,
+    pub part_size: usize,
+    /// Parts the multipart writer sent, the last of which may be short
+    pub num_parts: usize,
+    /// Time spent transferring, from starting the upload to completing it
+    pub elapsed: Duration,
+    /// What the repeated part holds
+    pub contents: Contents,
+}
+
+impl UploadBench {
+    pub fn to_json(&self, retry: &RetryPolicy) -> serde_json::Value {
+        let elapsed_us = self.elapsed.as_micros();
+        let mut result = fields(serde_json::json!({
+            "mode": "upload_bench",
+            "size": self.size,
+            "part_size": self.part_size,
+            "num_parts": self.num_parts,
+            "elapsed_us": elapsed_us as u64,
+            "mbps": mbps(self.size as u64, elapsed_us),
+        }));
+        result.extend(self.contents.json_fields());
+        result.extend(retry.json_fields());
+        result.into()
+    }
+}
+
+/// Upload a test object of the given size, timing only the transfer.
+///
+/// One part's worth of `contents` is generated before the clock starts and
+/// written repeatedly, so neither data generation nor setup is counted. A
+/// failed attempt is restarted according to the retry policy, and only the
+/// attempt that succeeded is timed.
+pub async fn bench_upload(
+    object_store: &dyn ObjectStore,
+    location: &Path,
+    size: usize,
+    contents: Contents,
+    retry: &RetryPolicy,
+) -> Result<UploadBench, Error> {
+    if contents == Contents::Offset {
+        return Err("--bench repeats one part, so it can't write --pattern offset".into());
+    }
+    let mut buffer = vec![0; PART_SIZE.min(size)];
+    contents.filler().fill(0, &mut buffer);
+    let buffer = &buffer;
+    let elapsed = retry
+        .run(|| async move {
+            let start = Instant::now();
+            let (_id, mut writer) = object_store.put_multipart(location).await?;
+            let mut written = 0;
+            while written < size {
+                let to_write = std::cmp::min(size - written, buffer.len());
+                trace::traced(
+                    trace::part_span(location, written..written + to_write),
+                    writer.write_all(&buffer[..to_write]),
+                    |_| Some(to_write),
+                )
+                .await
+                .map_err(multipart_error)?;
+                written += to_write;
+            }
+            writer.shutdown().await.map_err(multipart_error)?;
+            Ok(start.elapsed())
+        })
+        .await?;
+    Ok(UploadBench {
+        size,
+        part_size: PART_SIZE,
+        num_parts: size.div_ceil(PART_SIZE).max(1),
+        elapsed,
+        contents,
+    })
+}
+
+pub fn multipart_error(source: std::io::Error) -> object_store::Error {
+    object_store::Error::Generic {
+        store: "multipart",
+        source: Box::new(source),
+    }
+}
+
+/// `size` bytes split over `num_objects` as evenly as possible: the first
+/// `size % num_objects` objects are one byte larger than the rest.
+pub fn object_sizes(size: usize, num_objects: usize) -> Vec<usize> {
+    let (base, remainder) = (size / num_objects, size % num_objects);
+    (0..num_objects)
+        .map(|i| base + (i < remainder) as usize)
+        .collect()
+}
+
+/// The sizes [`object_sizes`] splits `size` into, as `3 objects of 143 bytes
+/// and 4 of 142 bytes`.
+fn describe_sizes(size: usize, num_objects: usize) -> String {
+    let (base, remainder) = (size / num_objects, size % num_objects);
+    match remainder {
+        0 => format!("{} objects of {} bytes", num_objects, base),
+        _ => format!(
+            "{} objects of {} bytes and {} of {} bytes",
+            remainder,
+            base + 1,
+            num_objects - remainder,
+            base
+        ),
+    }
+}
+
+/// Upload `num_objects` test objects totalling `size` bytes under `location`,
+/// sized as [`object_sizes`] splits it.
+///
+/// Objects are named for `run_id` as described in [`crate::naming`], or with
+/// flat names when it is `None`. Random prefixes are drawn from `prefix_seed`
+/// when it is given, so the same seed yields the same prefixes. Object `i`
+/// holds `contents.for_object(i)`; seeded contents also seed the prefixes
+/// unless `prefix_seed` is given, so the whole upload can be regenerated byte
+/// for byte. With
+/// `manifest_out`, an [`UploadManifest`] of every object's full path is
+/// written there. Returns the manifest.
+#[allow(clippy::too_many_arguments)]
+pub async fn upload_multiple(
+    object_store: Arc<dyn ObjectStore>,
+    location: &Path,
+    num_objects: usize,
+    size: usize,
+    part_size: usize,
+    random_prefixes: bool,
+    prefix_seed: Option<u64>,
+    contents: Contents,
+    run_id: Option<&str>,
+    manifest_out: Option<&std::path::Path>,
+    retry: &RetryPolicy,
+    digest: Option<&DigestConfig>,
+    samples: &UploadSamples,
+) -> Result<UploadManifest, Error> {
+    if num_objects == 0 {
+        return Err("--num-objects must be at least one".into());
+    }
+    let sizes = object_sizes(size, num_objects);
+    eprintln!("uploading {}", describe_sizes(size, num_objects));
+
+    if let Some(run_id) = run_id {
+        validate_run_id(run_id)?;
+    }
+
+    let seed = contents.seed();
+    let prefix_seed = prefix_seed.or(seed);
+    let mut rng = match prefix_seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_entropy(),
+    };
+    let mut names = Vec::with_capacity(num_objects);
+    for (i, size) in sizes.into_iter().enumerate() {
+        let mut object = location.parts().collect::<Vec<_>>();
+        if random_prefixes {
+            let prefix = (&mut rng)
+                .sample_iter(rand::distributions::Alphanumeric)
+                .take(8)
+                .collect::<Vec<u8>>();
+            let prefix = String::from_utf8(prefix).unwrap();
+
+            object.push(prefix.into());
+        }
+        object.push(object_name(run_id, i).into());
+        let object = Path::from_iter(object);
+        check_key(&object)?;
+        upload_test_data(
+            object_store.clone(),
+            &object,
+            size,
+            part_size,
+            contents.for_object(i),
+            retry,
+            digest,
+            samples,
+        )
+        .await?;
+        names.push(object);
+    }
+
+    let manifest = UploadManifest {
+        run_id: run_id.map(str::to_string),
+        root: location.clone(),
+        prefix_seed: prefix_seed.filter(|_| random_prefixes),
+        seed,
+        objects: names,
+    };
+    if let Some(manifest_out) = manifest_out {
+        manifest.write(manifest_out)?;
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::memory::InMemory;
+
+    #[tokio::test]
+    async fn bench_counts_parts_and_times_the_upload() {
+        let store = InMemory::new();
+        let location = Path::from("bench/object.bin");
+        let size = 2 * PART_SIZE + 5;
+        let bench = bench_upload(
+            &store,
+            &location,
+            size,
+            Contents::Random { seed: None },
+            &RetryPolicy::new(0, None),
+        )
+        .await
+        .unwrap();
+        assert_eq!(bench.size, size);
+        assert_eq!(bench.part_size, PART_SIZE);
+        assert_eq!(bench.num_parts, 3);
+        assert!(bench.elapsed > Duration::ZERO);
+        assert_eq!(store.head(&location).await.unwrap().size, size);
+
+        let json = bench.to_json(&RetryPolicy::new(0, None));
+        assert_eq!(json["mode"], "upload_bench");
+        assert_eq!(json["num_parts"], 3);
+        assert!(json["mbps"].as_f64().unwrap() > 0.0);
+    }
+
+    #[tokio::test]
+    async fn seeded_uploads_are_reproducible() {
+        let upload = |seed| async move {
+            let contents = Contents::Random { seed };
+            let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+            let manifest = upload_multiple(
+                store.clone(),
+                &Path::from("data"),
+                2,
+                2 * 1000,
+                300,
+                true,
+                None,
+                contents,
+                None,
+                None,
+                &RetryPolicy::new(0, None),
+                None,
+                &UploadSamples::start(),
+            )
+            .await
+            .unwrap();
+            let mut objects = Vec::new();
+            for object in &manifest.objects {
+                let bytes = store.get(object).await.unwrap().bytes().await.unwrap();
+                objects.push((object.clone(), bytes));
+            }
+            (manifest, objects)
+        };
+        let (manifest, first) = upload(Some(9)).await;
+        assert_eq!(manifest.seed, Some(9));
+        assert_eq!(manifest.prefix_seed, Some(9));
+        assert_eq!(first, upload(Some(9)).await.1);
+        // Each object has its own bytes.
+        assert_ne!(first[0].1, first[1].1);
+        assert_ne!(first, upload(Some(10)).await.1);
+        assert_ne!(first, upload(None).await.1);
+    }
+
+    #[tokio::test]
+    async fn uneven_sizes_are_spread_over_the_first_objects() {
+        assert_eq!(object_sizes(1000, 7), [143, 143, 143, 143, 143, 143, 142]);
+        assert_eq!(object_sizes(10, 5), [2; 5]);
+        assert_eq!(
+            describe_sizes(1000, 7),
+            "6 objects of 143 bytes and 1 of 142 bytes"
+        );
+
+        let store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+        let manifest = upload_multiple(
+            store.clone(),
+            &Path::from("data"),
+            3,
+            1001,
+            300,
+            false,
+            None,
+            Contents::Zeros,
+            None,
+            None,
+            &RetryPolicy::new(0, None),
+            None,
+            &UploadSamples::start(),
+        )
+        .await
+        .unwrap();
+        let mut sizes = Vec::new();
+        for object in &manifest.objects {
+            sizes.push(store.head(object).await.unwrap().size);
+        }
+        assert_eq!(sizes, [334, 334, 333]);
+    }
+}
@@ -0,0 +1,479 @@
+//! Defaults for command-line flags from a shared file and the environment.
+//!
+//! Teams share their standard settings in `~/.config/object-store-bench/defaults.toml`
+//! (under `$XDG_CONFIG_HOME` when that is set) rather than copying long
+//! command lines around. Top-level keys are global flags and tables hold the
+//! flags of a subcommand, both by their long names:
+//!
+//! ```toml
+//! max-retries = 3
+//! fault = ["latency-ms=20"]
+//!
+//! [download]
+//! parallel-downloads = 32
+//!
+//! [calibrate.columnar]
+//! page-sizes = "8192,8192"
+//! ```
+//!
+//! Any flag can also be given as `OSB_<LONG_NAME>`, as in
+//! `OSB_PARALLEL_DOWNLOADS=32`, for whichever subcommand has it. A flag on
+//! the command line beats the file, which beats the environment, which
+//! beats the store's tuned defaults. The layering happens once, on the clap
+//! command before it parses, so no benchmark handles it itself. Results then
+//! carry `config`, every flag that wasn't left to its default with whether it
+//! came from the command line, the file or the environment.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+use clap::parser::ValueSource;
+use clap::{Arg, ArgAction, ArgMatches, Command};
+
+use crate::experiment::Fields;
+
+/// Prefix of the environment variables holding flag defaults.
+const ENV_PREFIX: &str = "OSB_";
+
+/// Flags taking `name=value` whose values may be secrets, so `config` keeps
+/// only the names.
+const REDACTED: [&str; 2] = ["header", "store-option"];
+
+/// Where a default came from.
+#[derive(Debug, Clone, PartialEq)]
+enum Origin {
+    File,
+    Env,
+}
+
+/// Defaults layered onto each flag, keyed by subcommand path and flag id.
+#[derive(Debug, Default)]
+pub struct Layered {
+    origins: BTreeMap<(Vec<String>, String), Origin>,
+}
+
+/// The defaults file in the user's config directory, if there is one.
+pub fn defaults_path() -> Option<std::path::PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .map(std::path::PathBuf::from)
+        .or_else(|| {
+            std::env::var_os("HOME").map(|home| std::path::Path::new(&home).join(".config"))
+        })?;
+    Some(config.join("object-store-bench").join("defaults.toml"))
+}
+
+/// Layer the user's defaults file and `OSB_*` variables onto `command`.
+pub fn layer_user_defaults(command: Command) -> Result<(Command, Layered), String> {
+    let file = match defaults_path() {
+        Some(path) if path.exists() => {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+            Some((path, contents))
+        }
+        _ => None,
+    };
+    let file = file
+        .as_ref()
+        .map(|(path, contents)| (path.as_path(), contents.as_str()));
+    layer(command, file, std::env::vars_os())
+}
+
+/// Make the values in the TOML `file` and in `env` the defaults of
+/// `command`'s flags. Bad values name the file or variable and the key.
+pub fn layer(
+    command: Command,
+    file: Option<(&std::path::Path, &str)>,
+    env: impl IntoIterator<Item = (OsString, OsString)>,
+) -> Result<(Command, Layered), String> {
+    let table = match file {
+        Some((path, contents)) => Some(
+            contents
+                .parse::<toml::Table>()
+                .map_err(|err| format!("{}: invalid TOML: {}", path.display(), err))?,
+        ),
+        None => None,
+    };
+    let env = env
+        .into_iter()
+        .filter_map(|(key, value)| {
+            let key = key.into_string().ok()?;
+            key.starts_with(ENV_PREFIX).then_some((key, value))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let mut layered = Layered::default();
+    let file_path = file.map(|(path, _)| path.display().to_string());
+    let command = layer_command(
+        command,
+        &mut Vec::new(),
+        table.as_ref(),
+        file_path.as_deref().unwrap_or_default(),
+        &env,
+        &mut layered,
+    )?;
+    Ok((command, layered))
+}
+
+fn layer_command(
+    mut command: Command,
+    path: &mut Vec<String>,
+    table: Option<&toml::Table>,
+    file: &str,
+    env: &BTreeMap<String, OsString>,
+    layered: &mut Layered,
+) -> Result<Command, String> {
+    let section = match path.is_empty() {
+        true => String::new(),
+        false => format!("[{}] ", path.join(".")),
+    };
+    if let Some(table) = table {
+        for (key, value) in table {
+            let is_flag = command
+                .get_arguments()
+                .any(|arg| arg.get_long() == Some(key.as_str()) && is_layerable(arg));
+            let is_subcommand = command.find_subcommand(key).is_some();
+            match (is_flag, is_subcommand, value) {
+                (true, _, _) | (false, true, toml::Value::Table(_)) => {}
+                (false, true, _) => {
+                    return Err(format!(
+                        "{}: {}{}: expected a table of {} flags",
+                        file, section, key, key
+                    ))
+                }
+                (false, false, _) => {
+                    return Err(format!(
+                        "{}: {}unknown key {:?}; expected a flag or subcommand of {}",
+                        file,
+                        section,
+                        key,
+                        match path.is_empty() {
+                            true => command.get_name().to_string(),
+                            false => path.join(" "),
+                        }
+                    ))
+                }
+            }
+        }
+    }
+
+    let args = command
+        .get_arguments()
+        .filter(|arg| is_layerable(arg))
+        .cloned()
+        .collect::<Vec<_>>();
+    for arg in args {
+        let long = arg.get_long().unwrap();
+        let (values, origin) = match table.and_then(|table| table.get(long)) {
+            Some(value) => {
+                let where_ = format!("{}: {}{}", file, section, long);
+                (file_values(&arg, value, &where_)?, Origin::File)
+            }
+            None => {
+                let var = format!("{}{}", ENV_PREFIX, long.to_uppercase().replace('-', "_"));
+                match env.get(&var) {
+                    Some(value) => {
+                        let value = value
+                            .to_str()
+                            .ok_or_else(|| format!("{}: not valid UTF-8", var))?;
+                        (env_values(&arg, value, &var)?, Origin::Env)
+                    }
+                    None => continue,
+                }
+            }
+        };
+        layered
+            .origins
+            .insert((path.clone(), arg.get_id().to_string()), origin);
+        command = command.mut_arg(arg.get_id().clone(), |arg| match values.as_slice() {
+            [] => arg,
+            [value] => arg.default_value(value.clone()),
+            values => arg.default_values(values.to_vec()),
+        });
+    }
+
+    let subcommands = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_string())
+        .collect::<Vec<_>>();
+    for name in subcommands {
+        let sub = command.find_subcommand(&name).unwrap().clone();
+        let sub_table = match table.and_then(|table| table.get(&name)) {
+            Some(toml::Value::Table(sub_table)) => Some(sub_table),
+            _ => None,
+        };
+        path.push(name.clone());
+        let sub = layer_command(sub, path, sub_table, file, env, layered)?;
+        path.pop();
+        command = command.mut_subcommand(name, |_| sub);
+    }
+    Ok(command)
+}
+
+/// Flags, as opposed to positionals and `--help`.
+fn is_layerable(arg: &Arg) -> bool {
+    !arg.is_positional()
+        && arg.get_long().is_some()
+        && !matches!(arg.get_action(), ArgAction::Help | ArgAction::Version)
+}
+
+/// The defaults a file value stands for, checked against the flag's parser.
+fn file_values(arg: &Arg, value: &toml::Value, where_: &str) -> Result<Vec<String>, String> {
+    let scalar = |value: &toml::Value| match value {
+        toml::Value::String(s) => Ok(s.clone()),
+        toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+            Ok(value.to_string())
+        }
+        _ => Err(format!("{}: expected a string, number or boolean", where_)),
+    };
+    let values = match value {
+        toml::Value::Array(values) if matches!(arg.get_action(), ArgAction::Append) => {
+            values.iter().map(scalar).collect::<Result<Vec<_>, _>>()?
+        }
+        toml::Value::Array(_) => return Err(format!("{}: takes a single value", where_)),
+        value => vec![scalar(value)?],
+    };
+    check(arg, values, where_)
+}
+
+/// The defaults an environment variable stands for; repeatable flags take a
+/// comma-separated list.
+fn env_values(arg: &Arg, value: &str, var: &str) -> Result<Vec<String>, String> {
+    let values = match arg.get_action() {
+        ArgAction::Append => value.split(',').map(str::to_string).collect(),
+        _ => vec![value.to_string()],
+    };
+    check(arg, values, var)
+}
+
+/// Parse `values` as `arg` would on the command line.
+fn check(arg: &Arg, values: Vec<String>, where_: &str) -> Result<Vec<String>, String> {
+    if matches!(arg.get_action(), ArgAction::SetTrue | ArgAction::SetFalse) {
+        return match values.as_slice() {
+            [value] if matches!(value.as_str(), "true" | "false") => Ok(values),
+            _ => Err(format!(
+                "{}: expected true or false, got {:?}",
+                where_,
+                values.join(",")
+            )),
+        };
+    }
+    let probe = Command::new("defaults").no_binary_name(true).arg(
+        Arg::new("value")
+            .long("value")
+            .value_parser(arg.get_value_parser().clone())
+            .action(ArgAction::Append)
+            .allow_hyphen_values(true),
+    );
+    let argv = values.iter().flat_map(|value| ["--value", value.as_str()]);
+    match probe.try_get_matches_from(argv) {
+        Ok(_) => Ok(values),
+        Err(err) => {
+            let rendered = err.render().to_string();
+            let message = rendered
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .trim_start_matches("error: ")
+                .replace(" for '--value <value>'", "");
+            Err(format!("{}: {}", where_, message))
+        }
+    }
+}
+
+/// The flags that weren't left to their defaults, from `matches` of
+/// `command`, if there are any.
+pub fn record(
+    command: &Command,
+    matches: &ArgMatches,
+    layered: &Layered,
+) -> Option<serde_json::Value> {
+    let mut config = serde_json::Map::new();
+    collect(command, matches, &mut Vec::new(), layered, &mut config);
+    (!config.is_empty()).then_some(serde_json::Value::Object(config))
+}
+
+fn collect(
+    command: &Command,
+    matches: &ArgMatches,
+    path: &mut Vec<String>,
+    layered: &Layered,
+    config: &mut serde_json::Map<String, serde_json::Value>,
+) {
+    for arg in command.get_arguments().filter(|arg| is_layerable(arg)) {
+        let id = arg.get_id().as_str();
+        let source = match (
+            matches.value_source(id),
+            layered.origins.get(&(path.clone(), id.to_string())),
+        ) {
+            (Some(ValueSource::CommandLine), _) => "flag",
+            (Some(ValueSource::DefaultValue), Some(Origin::File)) => "file",
+            (Some(ValueSource::DefaultValue), Some(Origin::Env)) => "env",
+            _ => continue,
+        };
+        let redacted = arg.get_long().is_some_and(|long| REDACTED.contains(&long));
+        let values = matches
+            .get_raw(id)
+            .into_iter()
+            .flatten()
+            .map(|value| value.to_string_lossy().into_owned())
+            .map(|value| match (redacted, value.split_once('=')) {
+                (true, Some((name, _))) => format!("{}=<redacted>", name),
+                (true, None) => "<redacted>".to_string(),
+                (false, _) => value,
+            })
+            .map(serde_json::Value::String)
+            .collect::<Vec<_>>();
+        let value = match arg.get_action() {
+            ArgAction::Append => serde_json::Value::Array(values),
+            _ => values.into_iter().next().unwrap_or(serde_json::Value::Null),
+        };
+        let key = path
+            .iter()
+            .map(String::as_str)
+            .chain([arg.get_long().unwrap()])
+            .collect::<Vec<_>>()
+            .join(".");
+        config.insert(key, serde_json::json!({"value": value, "source": source}));
+    }
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        if let Some(sub) = command.find_subcommand(name) {
+            path.push(name.to_string());
+            collect(sub, sub_matches, path, layered, config);
+            path.pop();
+        }
+    }
+}
+
+/// Whether the flag `id` of the subcommand at `path` has a layered default
+/// that parsing kept, so the store's tuned defaults shouldn't replace it.
+pub fn is_layered(layered: &Layered, path: &[&str], id: &str) -> bool {
+    layered
+        .origins
+        .contains_key(&(path.iter().map(|s| s.to_string()).collect(), id.to_string()))
+}
+
+/// Append the effective `config` to the JSON object `result`.
+pub fn with_config(result: &mut Fields, config: &serde_json::Value) {
+    result.insert("config".to_string(), config.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Parser, Subcommand};
+
+    #[derive(Parser, Debug)]
+    struct Cli {
+        #[arg(long, default_value = "0")]
+        max_retries: usize,
+        #[arg(long)]
+        fault: Vec<String>,
+        #[arg(long)]
+        header: Vec<String>,
+        #[arg(long, default_value = "false")]
+        interactive: bool,
+        uri: String,
+        #[command(subcommand)]
+        command: Option<Sub>,
+    }
+
+    #[derive(Subcommand, Debug)]
+    enum Sub {
+        Download {
+            #[arg(short, long, default_value = "10")]
+            parallel_downloads: usize,
+        },
+    }
+
+    fn parse(
+        file: Option<&str>,
+        env: &[(&str, &str)],
+        argv: &[&str],
+    ) -> Result<(Cli, serde_json::Map<String, serde_json::Value>), String> {
+        let env = env
+            .iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect::<Vec<_>>();
+        let file = file.map(|contents| (std::path::Path::new("defaults.toml"), contents));
+        let (command, layered) = layer(<Cli as clap::CommandFactory>::command(), file, env)?;
+        let matches = command
+            .clone()
+            .try_get_matches_from(argv)
+            .map_err(|err| err.to_string())?;
+        let mut config = serde_json::Map::new();
+        collect(&command, &matches, &mut Vec::new(), &layered, &mut config);
+        let cli = <Cli as clap::FromArgMatches>::from_arg_matches(&matches).unwrap();
+        Ok((cli, config))
+    }
+
+    #[test]
+    fn flags_beat_the_file_which_beats_the_environment() {
+        let file = "max-retries = 3\nfault = [\"latency-ms=20\", \"error-rate=0.1\"]\n[download]\nparallel-downloads = 32\n";
+        let env = [("OSB_MAX_RETRIES", "7"), ("OSB_INTERACTIVE", "true")];
+        let (cli, config) = parse(Some(file), &env, &["bench", "memory:///", "download"]).unwrap();
+        assert_eq!(cli.max_retries, 3);
+        assert_eq!(cli.fault, vec!["latency-ms=20", "error-rate=0.1"]);
+        assert!(cli.interactive);
+        let Some(Sub::Download { parallel_downloads }) = cli.command else {
+            panic!("expected download");
+        };
+        assert_eq!(parallel_downloads, 32);
+        assert_eq!(config["max-retries"]["source"], "file");
+        assert_eq!(config["interactive"]["source"], "env");
+        assert_eq!(config["download.parallel-downloads"]["value"], "32");
+
+        let argv = [
+            "bench",
+            "--max-retries",
+            "1",
+            "--header",
+            "x-key=secret",
+            "memory:///",
+            "download",
+            "-p",
+            "4",
+        ];
+        let (cli, config) = parse(Some(file), &env, &argv).unwrap();
+        assert_eq!(cli.max_retries, 1);
+        assert_eq!(config["max-retries"]["source"], "flag");
+        assert_eq!(config["download.parallel-downloads"]["source"], "flag");
+        assert!(!config.contains_key("uri"));
+        assert_eq!(cli.header, ["x-key=secret"]);
+        assert_eq!(config["header"]["value"][0], "x-key=<redacted>");
+    }
+
+    #[test]
+    fn bad_defaults_name_the_file_and_key() {
+        let err = parse(
+            Some("[download]\nparallel-downloads = \"many\"\n"),
+            &[],
+            &["b", "u"],
+        )
+        .unwrap_err();
+        assert!(
+            err.starts_with("defaults.toml: [download] parallel-downloads:"),
+            "{}",
+            err
+        );
+        assert!(err.contains("many"), "{}", err);
+
+        let err = parse(Some("max-retires = 3\n"), &[], &["b", "u"]).unwrap_err();
+        assert!(
+            err.contains("defaults.toml: unknown key \"max-retires\""),
+            "{}",
+            err
+        );
+
+        let err = parse(Some("interactive = \"yes\"\n"), &[], &["b", "u"]).unwrap_err();
+        assert!(
+            err.contains("defaults.toml: interactive: expected true or false"),
+            "{}",
+            err
+        );
+
+        let err = parse(Some("max-retries = ["), &[], &["b", "u"]).unwrap_err();
+        assert!(err.starts_with("defaults.toml: invalid TOML"), "{}", err);
+
+        let err = parse(None, &[("OSB_MAX_RETRIES", "-1")], &["b", "u"]).unwrap_err();
+        assert!(err.starts_with("OSB_MAX_RETRIES:"), "{}", err);
+    }
+}
@@ -0,0 +1,234 @@
+//! Estimating the bytes a run put on the wire, not just the payload.
+//!
+//! Throughput counts payload bytes only, but every request also carries a
+//! request line, headers and, for cloud stores, a signature or token, and
+//! every response a status line and headers. With 4 KiB pages that overhead
+//! is a large share of the traffic. [`WireModel`] estimates it from the
+//! request counts: a fixed number of header bytes per request of each class,
+//! depending on the backend, plus the framing of TLS records around
+//! everything on encrypted connections.
+//!
+//! `--wire-overhead-bytes` overrides the per-request estimates, as `N` for
+//! every class or `get=N` for one. Results then carry `wire` alongside
+//! `request_counts`. Bodies the tool doesn't count as payload, such as
+//! listing responses, are left out.
+
+use std::str::FromStr;
+
+use url::Url;
+
+use crate::counting::{Operation, RequestTotals};
+use crate::store_defaults::StoreFamily;
+
+/// Largest plaintext a TLS record carries.
+const TLS_RECORD_SIZE: u64 = 16 * 1024;
+
+/// Bytes TLS adds to each record: a 5 byte header, plus the AEAD tag and
+/// content type of TLS 1.3.
+const TLS_RECORD_OVERHEAD: u64 = 22;
+
+/// Request plus response header bytes per request, in [`Operation::ALL`]
+/// order: get, head, put, list, copy, delete. These are typical sizes seen
+/// from each service's own clients, not measurements of this run.
+const OVERHEADS: &[(StoreFamily, [u64; Operation::ALL.len()])] = &[
+    // SigV4 authorization, content hashes and x-amz-* response headers.
+    (StoreFamily::S3, [1000, 900, 1100, 1000, 1100, 800]),
+    // A bearer token and a long list of x-goog-* response headers.
+    (StoreFamily::Gcs, [1100, 1000, 1100, 1000, 1100, 800]),
+    // SharedKey or SAS authorization and x-ms-* headers on both sides.
+    (StoreFamily::Azure, [1000, 950, 1100, 1000, 1100, 800]),
+    (StoreFamily::Other, [500, 450, 550, 500, 550, 400]),
+];
+
+/// One `--wire-overhead-bytes` override.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WireOverhead {
+    /// The request class it applies to, or `None` for every class
+    pub op: Option<Operation>,
+    pub bytes: u64,
+}
+
+impl FromStr for WireOverhead {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (op, bytes) = match s.split_once('=') {
+            Some((name, bytes)) => {
+                let op = Operation::from_name(name).ok_or_else(|| {
+                    format!(
+                        "unknown request class {:?}; expected one of {}",
+                        name,
+                        Operation::ALL.map(Operation::name).join(", ")
+                    )
+                })?;
+                (Some(op), bytes)
+            }
+            None => (None, s),
+        };
+        let bytes = bytes
+            .parse()
+            .map_err(|err| format!("{:?} is not a byte count: {}", bytes, err))?;
+        Ok(Self { op, bytes })
+    }
+}
+
+/// Header bytes per request of each class, and whether connections use TLS.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WireModel {
+    per_request: [u64; Operation::ALL.len()],
+    tls: bool,
+}
+
+/// What a run's requests are estimated to have sent and received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WireEstimate {
+    /// Bytes read plus bytes written, as counted
+    pub payload_bytes: u64,
+    pub header_bytes: u64,
+    pub tls_bytes: u64,
+    per_request: [u64; Operation::ALL.len()],
+}
+
+impl WireEstimate {
+    pub fn wire_bytes(&self) -> u64 {
+        self.payload_bytes + self.overhead_bytes()
+    }
+
+    pub fn overhead_bytes(&self) -> u64 {
+        self.header_bytes + self.tls_bytes
+    }
+
+    /// Share of the wire bytes that isn't payload, as a percentage.
+    pub fn overhead_pct(&self) -> f64 {
+        match self.wire_bytes() {
+            0 => 0.0,
+            wire => self.overhead_bytes() as f64 / wire as f64 * 100.0,
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let per_request = Operation::ALL
+            .iter()
+            .map(|op| (op.name().to_string(), self.per_request[*op as usize].into()))
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::json!({
+            "estimated_wire_bytes": self.wire_bytes(),
+            "payload_bytes": self.payload_bytes,
+            "overhead_bytes": self.overhead_bytes(),
+            "overhead_pct": self.overhead_pct(),
+            "header_bytes": self.header_bytes,
+            "tls_bytes": self.tls_bytes,
+            "overhead_per_request": per_request,
+        })
+    }
+}
+
+impl WireModel {
+    /// The built-in estimates for the store `url` points at, with
+    /// `overrides` applied in order. Local and in-memory stores have no wire,
+    /// so they get a model only when overridden.
+    pub fn for_store(url: &Url, family: StoreFamily, overrides: &[WireOverhead]) -> Option<Self> {
+        let defaults = OVERHEADS
+            .iter()
+            .find(|(f, _)| *f == family)
+            .map(|(_, per_request)| *per_request);
+        if defaults.is_none() && overrides.is_empty() {
+            return None;
+        }
+        let mut model = Self {
+            per_request: defaults.unwrap_or_default(),
+            tls: url.scheme() != "http" && defaults.is_some(),
+        };
+        for WireOverhead { op, bytes } in overrides {
+            match op {
+                Some(op) => model.per_request[*op as usize] = *bytes,
+                None => model.per_request = [*bytes; Operation::ALL.len()],
+            }
+        }
+        Some(model)
+    }
+
+    pub fn estimate(&self, totals: &RequestTotals) -> WireEstimate {
+        let payload_bytes = totals.bytes_read + totals.bytes_written;
+        let header_bytes = Operation::ALL
+            .iter()
+            .map(|op| totals.requests(*op) * self.per_request[*op as usize])
+            .sum::<u64>();
+        let tls_bytes = match self.tls {
+            true => (payload_bytes + header_bytes).div_ceil(TLS_RECORD_SIZE) * TLS_RECORD_OVERHEAD,
+            false => 0,
+        };
+        WireEstimate {
+            payload_bytes,
+            header_bytes,
+            tls_bytes,
+            per_request: self.per_request,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model(uri: &str, overrides: &[&str]) -> Option<WireModel> {
+        let url = Url::parse(uri).unwrap();
+        let overrides = overrides
+            .iter()
+            .map(|o| o.parse().unwrap())
+            .collect::<Vec<_>>();
+        WireModel::for_store(&url, StoreFamily::detect(&url), &overrides)
+    }
+
+    #[test]
+    fn small_pages_pay_a_third_in_overhead() {
+        let model = model("s3://bucket/data", &["get=2048"]).unwrap();
+        // 1000 reads of 4 KiB pages: a third of the traffic is headers.
+        let totals = RequestTotals::new(&[(Operation::Get, 1000)], 1000 * 4096, 0);
+        let estimate = model.estimate(&totals);
+        assert_eq!(estimate.header_bytes, 1000 * 2048);
+        let records = (1000 * (4096 + 2048) as u64).div_ceil(TLS_RECORD_SIZE);
+        assert_eq!(estimate.tls_bytes, records * TLS_RECORD_OVERHEAD);
+        assert_eq!(
+            estimate.wire_bytes(),
+            1000 * (4096 + 2048) + estimate.tls_bytes
+        );
+        assert!((33.0..35.0).contains(&estimate.overhead_pct()));
+
+        // Large reads barely notice it.
+        let totals = RequestTotals::new(&[(Operation::Get, 10)], 10 * (64 << 20), 0);
+        assert!(model.estimate(&totals).overhead_pct() < 0.2);
+    }
+
+    #[test]
+    fn overrides_apply_in_order() {
+        let model = model("https://example.com/data", &["300", "put=900"]).unwrap();
+        assert_eq!(model.per_request, [300, 300, 900, 300, 300, 300]);
+        assert!(model.tls);
+        assert!(!self::model("http://example.com/data", &[]).unwrap().tls);
+    }
+
+    #[test]
+    fn local_stores_have_no_wire_unless_asked() {
+        assert_eq!(model("file:///tmp/data", &[]), None);
+        assert_eq!(model("memory:///", &[]), None);
+        let model = model("memory:///", &["get=100"]).unwrap();
+        assert!(!model.tls);
+        let totals = RequestTotals::new(&[(Operation::Get, 2), (Operation::Head, 1)], 50, 0);
+        assert_eq!(model.estimate(&totals).wire_bytes(), 250);
+    }
+
+    #[test]
+    fn rejects_invalid_overrides() {
+        assert!("gets=10".parse::<WireOverhead>().is_err());
+        assert!("get=-1".parse::<WireOverhead>().is_err());
+        assert!("lots".parse::<WireOverhead>().is_err());
+        assert_eq!(
+            "delete=12".parse::<WireOverhead>().unwrap(),
+            WireOverhead {
+                op: Some(Operation::Delete),
+                bytes: 12
+            }
+        );
+    }
+}
@@ -0,0 +1,101 @@
+//! Splitting one logical run across several worker processes.
+//!
+//! Each worker is started with the same `--run-id` and its own
+//! `--worker-id k/n`. The objects under the benchmarked location are sorted by
+//! path and worker `k` takes every `n`th one starting at `k`. Every result a
+//! worker emits carries a `metadata` object with the run id, worker id and
+//! wall-clock start and finish times, which `merge` uses to combine them.
+
+use std::str::FromStr;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use object_store::ObjectMeta;
+
+use crate::experiment::{prepend, Fields};
+
+/// Position of this worker among the workers sharing a run, written `k/n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WorkerId {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl FromStr for WorkerId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = || {
+            let (index, count) = s.split_once('/')?;
+            Some((index.parse().ok()?, count.parse().ok()?))
+        };
+        match parse() {
+            Some((index, count)) if index < count => Ok(WorkerId { index, count }),
+            Some(_) => Err(format!(
+                "worker index must be less than the count, got {}",
+                s
+            )),
+            None => Err(format!("expected k/n, got {:?}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for WorkerId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// This process's place in a sharded run, reported as `metadata`.
+#[derive(Debug)]
+pub struct RunInfo {
+    run_id: Option<String>,
+    worker: Option<WorkerId>,
+    started_at: DateTime<Utc>,
+}
+
+impl RunInfo {
+    /// A run starting now.
+    pub fn new(run_id: Option<String>, worker: Option<WorkerId>) -> Self {
+        Self {
+            run_id,
+            worker,
+            started_at: Utc::now(),
+        }
+    }
+}
+
+/// Keep only `worker`'s share of `objects`, or all of them outside a sharded run.
+pub fn shard(
+    mut objects: Vec<ObjectMeta>,
+    worker: Option<WorkerId>,
+) -> Result<Vec<ObjectMeta>, String> {
+    let Some(worker) = worker else {
+        return Ok(objects);
+    };
+    let total = objects.len();
+    objects.sort_by(|a, b| a.location.cmp(&b.location));
+    let objects = objects
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| i % worker.count == worker.index)
+        .map(|(_, meta)| meta)
+        .collect::<Vec<_>>();
+    if objects.is_empty() {
+        return Err(format!(
+            "worker {} has no objects: the location holds only {}",
+            worker, total
+        ));
+    }
+    Ok(objects)
+}
+
+/// Prefix the JSON object `result` with the metadata of `run`.
+pub fn with_metadata(result: &mut Fields, run: &RunInfo) {
+    let metadata = serde_json::json!({
+        "run_id": run.run_id,
+        "worker_id": run.worker.map(|worker| worker.to_string()),
+        "started_at": run.started_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+        "finished_at": Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+    });
+    prepend(result, "metadata", metadata);
+}
@@ -0,0 +1,181 @@
+//! The benchmarks called as a library, against an in-memory store.
+
+use std::sync::Arc;
+
+use bytes::Bytes;
+use object_store::{memory::InMemory, path::Path, ObjectStore};
+use object_store_bench::control::RunControl;
+use object_store_bench::counting::{CountingStore, Operation, RangeLog, RequestCounts};
+use object_store_bench::experiment::capture;
+use object_store_bench::pattern::Contents;
+use object_store_bench::retry::RetryPolicy;
+use object_store_bench::upload::UploadSamples;
+use object_store_bench::{
+    columnar_read_test, parallel_download_bench, upload_test_data, Checked, ColumnarOptions,
+    DownloadOptions, DownloadResult, Error,
+};
+
+/// An in-memory store holding `objects` of offset-patterned data, behind a
+/// counting store that logs every range read.
+async fn seeded_store(
+    objects: &[(&str, usize)],
+) -> (Arc<dyn ObjectStore>, Arc<RequestCounts>, RangeLog) {
+    let memory: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    for (name, size) in objects {
+        upload_test_data(
+            memory.clone(),
+            &Path::from(*name),
+            *size,
+            1 << 20,
+            Contents::Offset,
+            &RetryPolicy::new(0, None),
+            None,
+            &UploadSamples::start(),
+        )
+        .await
+        .unwrap();
+    }
+    let counting = CountingStore::new(memory).recording_ranges();
+    let (counts, ranges) = (counting.counts(), counting.ranges().unwrap());
+    (Arc::new(counting), counts, ranges)
+}
+
+/// The ranges read from `name`, in order of their start.
+fn ranges_of(log: &RangeLog, name: &str) -> Vec<std::ops::Range<usize>> {
+    let mut ranges = log
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(location, _)| location.as_ref() == name)
+        .map(|(_, range)| range.clone())
+        .collect::<Vec<_>>();
+    ranges.sort_by_key(|range| range.start);
+    ranges
+}
+
+async fn download(
+    object_store: Arc<dyn ObjectStore>,
+    location: &str,
+) -> (Result<DownloadResult, Error>, Vec<String>) {
+    let (result, emitted) = capture(parallel_download_bench(
+        object_store,
+        Path::from(location),
+        DownloadOptions {
+            parallel_downloads: 4,
+            block_size: Some(1 << 12),
+            ..DownloadOptions::default()
+        },
+        RetryPolicy::new(0, None),
+        RunControl::new(),
+    ))
+    .await;
+    (result.and_then(Checked::into_result), emitted)
+}
+
+#[tokio::test]
+async fn downloads_return_their_result() {
+    let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    for name in ["data/a", "data/b"] {
+        object_store
+            .put(&Path::from(name), Bytes::from(vec![1; 10_000]))
+            .await
+            .unwrap();
+    }
+    let (result, emitted) = download(object_store, "data").await;
+    let result = result.unwrap();
+    assert_eq!(result.num_objects, 2);
+    assert_eq!(result.bytes, 20_000);
+    // Three blocks of each object, the last of them short.
+    assert_eq!(result.num_requests, 6);
+    assert!(!result.interrupted);
+    // Printing the result is left to the caller.
+    assert!(emitted.is_empty());
+    assert_eq!(serde_json::to_value(&result).unwrap()["bytes"], 20_000);
+}
+
+#[tokio::test]
+async fn failures_can_be_matched_on() {
+    let object_store: Arc<dyn ObjectStore> = Arc::new(InMemory::new());
+    let (result, emitted) = download(object_store, "nothing/here").await;
+    assert!(matches!(
+        result,
+        Err(Error::Store(object_store::Error::NotFound { .. }))
+    ));
+    assert!(emitted.is_empty());
+}
+
+#[tokio::test]
+async fn downloads_read_every_byte_once_in_bounds() {
+    let sizes = [("data/a", 100_000), ("data/b", 40_000)];
+    let (object_store, counts, log) = seeded_store(&sizes).await;
+    let result = parallel_download_bench(
+        object_store,
+        Path::from("data"),
+        DownloadOptions {
+            parallel_downloads: 4,
+            block_size: Some(16_384),
+            verify: true,
+            ..DownloadOptions::default()
+        },
+        RetryPolicy::new(0, None),
+        RunControl::new(),
+    )
+    .await
+    .and_then(Checked::into_result)
+    .unwrap();
+    // Seven blocks of the first object and three of the second.
+    assert_eq!(result.num_requests, 10);
+    assert_eq!(result.bytes, 140_000);
+    let totals = counts.totals();
+    assert_eq!(totals.requests(Operation::Get), 10);
+    assert_eq!(totals.bytes_read, 140_000);
+    for (name, size) in sizes {
+        let ranges = ranges_of(&log, name);
+        // Blocks are contiguous from the start and end at the object's end.
+        let mut end = 0;
+        for range in ranges {
+            assert_eq!(range.start, end, "{} {:?}", name, range);
+            assert!(range.len() <= 16_384);
+            end = range.end;
+        }
+        assert_eq!(end, size);
+    }
+}
+
+#[tokio::test]
+async fn columnar_reads_fetch_aligned_pages_in_bounds() {
+    let (object_store, counts, log) = seeded_store(&[("table/a", 50_000)]).await;
+    let options = ColumnarOptions {
+        parallel_downloads: 2,
+        page_sizes: vec![1000, 3000, 500],
+        page_align: 4096,
+        ..ColumnarOptions::default()
+    };
+    let result = columnar_read_test(
+        object_store,
+        Path::from("table"),
+        options,
+        RetryPolicy::new(0, None),
+        RunControl::new(),
+    )
+    .await
+    .and_then(Checked::into_result)
+    .unwrap();
+    // Each group spans three aligned pages, 12 KiB. A fifth group's first
+    // page would start at 49152 and end past the object.
+    assert_eq!(result.num_groups, 4);
+    assert_eq!(result.num_requests, 12);
+    assert_eq!(result.bytes, 4 * 4500);
+    // The last page ends at 45056 + 500, after 18000 bytes of pages.
+    assert_eq!(result.padding_bytes, 45_556 - 18_000);
+    let totals = counts.totals();
+    assert_eq!(totals.requests(Operation::Get), 12);
+    assert_eq!(totals.bytes_read, 18_000);
+    let ranges = ranges_of(&log, "table/a");
+    assert_eq!(ranges.len(), 12);
+    for (i, range) in ranges.iter().enumerate() {
+        assert_eq!(range.start, i * 4096, "{:?}", range);
+        assert_eq!(range.len(), [1000, 3000, 500][i % 3]);
+        assert!(range.end <= 50_000);
+    }
+}